@@ -0,0 +1,122 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Merging many iterators, e.g., in a deep compaction or a scan over dozens of L0 SSTs. Run with
+//! `cargo bench --bench merge_iterator`.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use mini_lsm_starter::iterators::StorageIterator;
+use mini_lsm_starter::iterators::merge_iterator::MergeIterator;
+use mini_lsm_starter::key::{KeySlice, TS_DEFAULT};
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const NUM_ITERS: usize = 256;
+/// About the number of L0 SSTs when the writes outpace the compactions.
+const NUM_L0_ITERS: usize = 32;
+const KEYS_PER_ITER: usize = 1000;
+
+struct VecIterator {
+    keys: Vec<Vec<u8>>,
+    idx: usize,
+}
+
+impl StorageIterator for VecIterator {
+    type KeyType<'a> = KeySlice<'a>;
+
+    fn value(&self) -> &[u8] {
+        &self.keys[self.idx]
+    }
+
+    fn key(&self) -> KeySlice<'_> {
+        KeySlice::from_slice(&self.keys[self.idx], TS_DEFAULT)
+    }
+
+    fn is_valid(&self) -> bool {
+        self.idx < self.keys.len()
+    }
+
+    fn next(&mut self) -> anyhow::Result<()> {
+        self.idx += 1;
+        Ok(())
+    }
+}
+
+#[allow(clippy::vec_box)] // `MergeIterator::create` takes boxed iterators
+fn generate_iters(num_iters: usize) -> Vec<Box<VecIterator>> {
+    (0..num_iters)
+        .map(|i| {
+            let keys = (0..KEYS_PER_ITER)
+                .map(|k| format!("key_{:08}", k * num_iters + i).into_bytes())
+                .collect();
+            Box::new(VecIterator { keys, idx: 0 })
+        })
+        .collect()
+}
+
+#[allow(clippy::vec_box)]
+fn measure(
+    name: &str,
+    num_iters: usize,
+    create: impl FnOnce(Vec<Box<VecIterator>>) -> MergeIterator<VecIterator>,
+) {
+    let iters = generate_iters(num_iters);
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    let mut iter = create(iters);
+    let create_allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+    let create_time = start.elapsed();
+    let mut cnt = 0;
+    while iter.is_valid() {
+        cnt += 1;
+        iter.next().unwrap();
+    }
+    assert_eq!(cnt, num_iters * KEYS_PER_ITER);
+    println!(
+        "{name}: create {create_allocations} allocations in {create_time:?}, scan {cnt} keys in {:?}",
+        start.elapsed()
+    );
+}
+
+fn main() {
+    measure("create (sized)", NUM_ITERS, MergeIterator::create);
+    measure("create_with_capacity(0)", NUM_ITERS, |iters| {
+        MergeIterator::create_with_capacity(iters, 0)
+    });
+    measure("create (L0)", NUM_L0_ITERS, MergeIterator::create);
+}
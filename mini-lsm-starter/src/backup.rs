@@ -0,0 +1,301 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::{BTreeSet, HashSet};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::lsm_storage::MiniLsm;
+
+/// Incremental backups of a storage, kept in a directory of their own:
+///
+/// * `shared/` holds the SSTs and value log files, each copied once and shared by all backups
+///   that contain it. As the same SST id always has the same content, a file is named after its
+///   original path and size, which tells apart the versions of a value log still being appended.
+/// * `private/<id>/` holds the files of backup `id` that change over time, i.e., the manifest and
+///   the WALs.
+/// * `meta/<id>` lists the files of backup `id`. It is written last, so a backup exists once its
+///   meta file does, and the files of an interrupted backup are removed by `garbage_collect`.
+pub struct BackupEngine {
+    dir: PathBuf,
+}
+
+/// A backup in a `BackupEngine`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupInfo {
+    pub id: u64,
+    /// Seconds since the Unix epoch when the backup was created.
+    pub timestamp: u64,
+    /// Total size in bytes of the files of the backup, including the shared ones.
+    pub size: u64,
+    pub num_files: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BackupMeta {
+    info: BackupInfo,
+    /// Paths of the files of the backup relative to the storage directory, and their names in
+    /// `shared/`.
+    shared_files: Vec<(String, String)>,
+    /// Paths of the files of the backup relative to the storage directory, also their paths in
+    /// `private/<id>/`.
+    private_files: Vec<String>,
+}
+
+/// Write `data` to `path` through a temporary file, so that `path` is either complete or missing.
+fn write_atomic(path: &Path, data: &[u8]) -> Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    std::fs::write(&tmp_path, data)?;
+    File::open(&tmp_path)?.sync_all()?;
+    std::fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+/// Copy `src` to `dst` through a temporary file, see `write_atomic`.
+fn copy_atomic(src: &Path, dst: &Path) -> Result<()> {
+    let tmp_path = dst.with_extension("tmp");
+    std::fs::copy(src, &tmp_path)
+        .with_context(|| format!("failed to copy {} to {}", src.display(), dst.display()))?;
+    File::open(&tmp_path)?.sync_all()?;
+    std::fs::rename(&tmp_path, dst)?;
+    Ok(())
+}
+
+fn sync_dir(path: &Path) -> Result<()> {
+    File::open(path)?.sync_all()?;
+    Ok(())
+}
+
+/// Paths of the files in `dir` and its subdirectories, i.e., the ones of the column families,
+/// relative to `dir`.
+fn list_files(dir: &Path) -> Result<Vec<String>> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().into_string().unwrap();
+        if entry.file_type()?.is_dir() {
+            files.extend(
+                list_files(&entry.path())?
+                    .into_iter()
+                    .map(|file| format!("{}/{}", name, file)),
+            );
+        } else {
+            files.push(name);
+        }
+    }
+    Ok(files)
+}
+
+impl BackupEngine {
+    /// Open the backups in `dir`, creating it if it does not exist.
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        for sub_dir in ["shared", "private", "meta"] {
+            std::fs::create_dir_all(dir.join(sub_dir))
+                .with_context(|| format!("failed to create backup directory {}", dir.display()))?;
+        }
+        Ok(Self { dir })
+    }
+
+    fn meta_path(&self, backup_id: u64) -> PathBuf {
+        self.dir.join("meta").join(backup_id.to_string())
+    }
+
+    fn private_dir(&self, backup_id: u64) -> PathBuf {
+        self.dir.join("private").join(backup_id.to_string())
+    }
+
+    fn read_meta(&self, backup_id: u64) -> Result<BackupMeta> {
+        let data = std::fs::read(self.meta_path(backup_id))
+            .with_context(|| format!("backup {} not found", backup_id))?;
+        serde_json::from_slice(&data)
+            .map_err(|e| Error::corruption(format!("backup {} meta: {}", backup_id, e)).into())
+    }
+
+    fn backup_ids(&self) -> Result<Vec<u64>> {
+        let mut ids = Vec::new();
+        for entry in std::fs::read_dir(self.dir.join("meta"))? {
+            if let Some(id) = entry?
+                .file_name()
+                .to_str()
+                .and_then(|name| name.parse::<u64>().ok())
+            {
+                ids.push(id);
+            }
+        }
+        ids.sort();
+        Ok(ids)
+    }
+
+    /// The backups, from the earliest to the latest.
+    pub fn list_backups(&self) -> Result<Vec<BackupInfo>> {
+        self.backup_ids()?
+            .into_iter()
+            .map(|id| Ok(self.read_meta(id)?.info))
+            .collect()
+    }
+
+    /// Back up `storage` without stopping its writes, copying only the SSTs and value log files
+    /// that no backup has yet. Returns the id of the new backup.
+    pub fn create_backup(&self, storage: &MiniLsm) -> Result<u64> {
+        let backup_id = self.backup_ids()?.last().map_or(1, |id| id + 1);
+        // a checkpoint in the storage directory takes hard links, so the files cannot be removed
+        // by the storage while they are copied
+        let staging = storage.inner.path.join(format!("backup-{}.tmp", backup_id));
+        if staging.exists() {
+            std::fs::remove_dir_all(&staging)?;
+        }
+        storage.create_checkpoint(&staging)?;
+        let result = self.copy_backup(backup_id, &staging);
+        std::fs::remove_dir_all(&staging)?;
+        result?;
+        Ok(backup_id)
+    }
+
+    fn copy_backup(&self, backup_id: u64, staging: &Path) -> Result<()> {
+        let private_dir = self.private_dir(backup_id);
+        if private_dir.exists() {
+            // left by an interrupted backup
+            std::fs::remove_dir_all(&private_dir)?;
+        }
+        std::fs::create_dir(&private_dir)?;
+
+        let mut meta = BackupMeta {
+            info: BackupInfo {
+                id: backup_id,
+                timestamp: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+                size: 0,
+                num_files: 0,
+            },
+            shared_files: Vec::new(),
+            private_files: Vec::new(),
+        };
+        for file in list_files(staging)? {
+            let path = staging.join(&file);
+            let size = std::fs::metadata(&path)?.len();
+            meta.info.size += size;
+            meta.info.num_files += 1;
+            match path.extension().and_then(|ext| ext.to_str()) {
+                Some(ext @ ("sst" | "vlog")) => {
+                    let stem = file.strip_suffix(ext).unwrap().trim_end_matches('.');
+                    let shared_name = format!("{}_{}.{}", stem.replace('/', "_"), size, ext);
+                    let shared_path = self.dir.join("shared").join(&shared_name);
+                    if !shared_path.exists() {
+                        copy_atomic(&path, &shared_path)?;
+                    }
+                    meta.shared_files.push((file, shared_name));
+                }
+                _ => {
+                    let private_path = private_dir.join(&file);
+                    if let Some(parent) = private_path.parent() {
+                        std::fs::create_dir_all(parent)?;
+                    }
+                    copy_atomic(&path, &private_path)?;
+                    meta.private_files.push(file);
+                }
+            }
+        }
+        sync_dir(&self.dir.join("shared"))?;
+        sync_dir(&private_dir)?;
+        write_atomic(&self.meta_path(backup_id), &serde_json::to_vec(&meta)?)?;
+        sync_dir(&self.dir.join("meta"))
+    }
+
+    /// Restore backup `backup_id` to `target_dir`, which must not exist or be empty, as a storage
+    /// that can be opened with `MiniLsm::open`.
+    pub fn restore(&self, backup_id: u64, target_dir: impl AsRef<Path>) -> Result<()> {
+        let target_dir = target_dir.as_ref();
+        let meta = self.read_meta(backup_id)?;
+        if target_dir.exists() && std::fs::read_dir(target_dir)?.next().is_some() {
+            bail!("restore target {} is not empty", target_dir.display());
+        }
+        std::fs::create_dir_all(target_dir)?;
+        let private_dir = self.private_dir(backup_id);
+        let files = meta
+            .shared_files
+            .iter()
+            .map(|(file, shared_name)| (file, self.dir.join("shared").join(shared_name)))
+            .chain(
+                meta.private_files
+                    .iter()
+                    .map(|file| (file, private_dir.join(file))),
+            );
+        let mut dirs = BTreeSet::from([target_dir.to_path_buf()]);
+        for (file, src) in files {
+            let dst = target_dir.join(file);
+            let dir = dst.parent().unwrap();
+            if dirs.insert(dir.to_path_buf()) {
+                std::fs::create_dir(dir)?;
+            }
+            copy_atomic(&src, &dst)?;
+        }
+        for dir in dirs.iter().rev() {
+            sync_dir(dir)?;
+        }
+        Ok(())
+    }
+
+    /// Delete backup `backup_id` and the shared files no other backup needs.
+    pub fn delete_backup(&self, backup_id: u64) -> Result<()> {
+        std::fs::remove_file(self.meta_path(backup_id))
+            .with_context(|| format!("backup {} not found", backup_id))?;
+        sync_dir(&self.dir.join("meta"))?;
+        self.garbage_collect()
+    }
+
+    /// Delete all but the latest `num_backups_to_keep` backups.
+    pub fn purge_old_backups(&self, num_backups_to_keep: usize) -> Result<()> {
+        let ids = self.backup_ids()?;
+        let num_to_delete = ids.len().saturating_sub(num_backups_to_keep);
+        for id in &ids[..num_to_delete] {
+            std::fs::remove_file(self.meta_path(*id))?;
+        }
+        sync_dir(&self.dir.join("meta"))?;
+        self.garbage_collect()
+    }
+
+    /// Remove the files that no backup refers to, i.e., the ones of deleted or interrupted
+    /// backups.
+    pub fn garbage_collect(&self) -> Result<()> {
+        let ids = self.backup_ids()?;
+        let mut shared_files = HashSet::new();
+        for &id in &ids {
+            shared_files.extend(
+                self.read_meta(id)?
+                    .shared_files
+                    .into_iter()
+                    .map(|(_, shared_name)| shared_name),
+            );
+        }
+        for entry in std::fs::read_dir(self.dir.join("shared"))? {
+            let entry = entry?;
+            if !shared_files.contains(entry.file_name().to_str().unwrap_or_default()) {
+                std::fs::remove_file(entry.path())?;
+            }
+        }
+        for entry in std::fs::read_dir(self.dir.join("private"))? {
+            let entry = entry?;
+            let id = entry.file_name().to_str().and_then(|id| id.parse().ok());
+            if !id.is_some_and(|id| ids.contains(&id)) {
+                std::fs::remove_dir_all(entry.path())?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,303 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! An interactive shell over an on-disk store. Unlike `mini-lsm-cli`, which is shared with the
+//! reference solutions, it uses the whole API of the starter.
+
+mod wrapper;
+
+use std::ops::Bound;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::Result;
+use bytes::Bytes;
+use clap::{Parser, ValueEnum};
+use rustyline::DefaultEditor;
+use rustyline::error::ReadlineError;
+use wrapper::mini_lsm_wrapper;
+
+use mini_lsm_wrapper::compact::{
+    CompactionOptions, LeveledCompactionOptions, SimpleLeveledCompactionOptions,
+    TieredCompactionOptions,
+};
+use mini_lsm_wrapper::iterators::StorageIterator;
+use mini_lsm_wrapper::lsm_storage::{LsmStorageOptions, MiniLsm};
+
+const HELP: &str = "\
+put <key> <value>        write a key
+get <key>                read a key
+del <key>                delete a key
+scan [<begin> <end>]     list the keys from begin to end, both inclusive
+flush                    flush the mem-tables to SSTs
+compact [<begin> <end>]  compact the SSTs overlapping the range, or all of them
+stats                    print the structure, the approximate size and the metrics
+format <escaped|hex>     change how keys and values are printed
+help                     print this message
+quit                     close the store and exit";
+
+#[derive(Debug, Clone, ValueEnum)]
+enum CompactionStrategy {
+    Simple,
+    Leveled,
+    Tiered,
+    None,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum OutputFormat {
+    /// Bytes as escaped strings, e.g. `b"key\x00"`.
+    Escaped,
+    /// Bytes in lowercase hex.
+    Hex,
+}
+
+impl OutputFormat {
+    fn format(self, data: &[u8]) -> String {
+        match self {
+            OutputFormat::Escaped => format!("{:?}", Bytes::copy_from_slice(data)),
+            OutputFormat::Hex => data.iter().map(|byte| format!("{:02x}", byte)).collect(),
+        }
+    }
+}
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "An interactive shell for mini-lsm", long_about = None)]
+struct Args {
+    #[arg(long, default_value = "lsm.db")]
+    path: PathBuf,
+    #[arg(long, default_value = "leveled")]
+    compaction: CompactionStrategy,
+    #[arg(long)]
+    enable_wal: bool,
+    /// How keys and values are printed, can be changed with the `format` command.
+    #[arg(long, default_value = "escaped")]
+    format: OutputFormat,
+}
+
+#[derive(Debug)]
+enum Command {
+    Put { key: String, value: String },
+    Get { key: String },
+    Del { key: String },
+    Scan { range: Option<(String, String)> },
+    Flush,
+    Compact { range: Option<(String, String)> },
+    Stats,
+    Format { format: OutputFormat },
+    Help,
+    Quit,
+}
+
+impl Command {
+    fn parse(input: &str) -> Result<Self> {
+        let args = input.split_whitespace().collect::<Vec<_>>();
+        let range = |args: &[&str]| match args {
+            [] => Ok(None),
+            [begin, end] => Ok(Some((begin.to_string(), end.to_string()))),
+            _ => Err(anyhow::anyhow!("expect no range or <begin> <end>")),
+        };
+        let command = match args.as_slice() {
+            ["put", key, value] => Command::Put {
+                key: key.to_string(),
+                value: value.to_string(),
+            },
+            ["get", key] => Command::Get {
+                key: key.to_string(),
+            },
+            ["del", key] => Command::Del {
+                key: key.to_string(),
+            },
+            ["scan", rest @ ..] => Command::Scan {
+                range: range(rest)?,
+            },
+            ["flush"] => Command::Flush,
+            ["compact", rest @ ..] => Command::Compact {
+                range: range(rest)?,
+            },
+            ["stats"] => Command::Stats,
+            ["format", format] => Command::Format {
+                format: OutputFormat::from_str(format, true)
+                    .map_err(|e| anyhow::anyhow!("{}", e))?,
+            },
+            ["help"] => Command::Help,
+            ["quit" | "exit"] => Command::Quit,
+            _ => anyhow::bail!("invalid command, try `help`"),
+        };
+        Ok(command)
+    }
+}
+
+struct Shell {
+    lsm: Arc<MiniLsm>,
+    format: OutputFormat,
+}
+
+impl Shell {
+    /// Run a command, returns false once the shell should exit.
+    fn handle(&mut self, command: Command) -> Result<bool> {
+        match command {
+            Command::Put { key, value } => {
+                self.lsm.put(key.as_bytes(), value.as_bytes())?;
+                println!("ok");
+            }
+            Command::Get { key } => match self.lsm.get(key.as_bytes())? {
+                Some(value) => println!("{}", self.format.format(&value)),
+                None => println!("(not found)"),
+            },
+            Command::Del { key } => {
+                self.lsm.delete(key.as_bytes())?;
+                println!("ok");
+            }
+            Command::Scan { range } => {
+                let mut iter = match &range {
+                    Some((begin, end)) => self.lsm.scan(
+                        Bound::Included(begin.as_bytes()),
+                        Bound::Included(end.as_bytes()),
+                    )?,
+                    None => self.lsm.scan(Bound::Unbounded, Bound::Unbounded)?,
+                };
+                let mut cnt = 0;
+                while iter.is_valid() {
+                    println!(
+                        "{}={}",
+                        self.format.format(iter.key()),
+                        self.format.format(iter.value())
+                    );
+                    iter.next()?;
+                    cnt += 1;
+                }
+                println!("({} keys)", cnt);
+            }
+            Command::Flush => {
+                self.lsm.force_flush()?;
+                println!("ok");
+            }
+            Command::Compact { range } => {
+                match &range {
+                    Some((begin, end)) => self
+                        .lsm
+                        .compact_range(Some(begin.as_bytes()), Some(end.as_bytes()))?,
+                    None => self.lsm.force_full_compaction()?,
+                }
+                println!("ok");
+            }
+            Command::Stats => self.stats()?,
+            Command::Format { format } => {
+                self.format = format;
+                println!("ok");
+            }
+            Command::Help => println!("{}", HELP),
+            Command::Quit => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    fn stats(&self) -> Result<()> {
+        self.lsm.dump_structure();
+        println!(
+            "approximate size: {} bytes",
+            self.lsm
+                .approximate_size(Bound::Unbounded, Bound::Unbounded)?
+        );
+        println!(
+            "approximate entries: {}",
+            self.lsm
+                .approximate_num_keys(Bound::Unbounded, Bound::Unbounded)?
+        );
+        let metrics = self.lsm.metrics().snapshot();
+        println!(
+            "block cache: {} hits, {} misses, {:.1}% hit rate",
+            metrics.block_cache.hits,
+            metrics.block_cache.misses,
+            metrics.block_cache_hit_rate() * 100.0
+        );
+        println!(
+            "flushed: {} bytes, compacted: {} bytes, write amplification: {:.2}",
+            metrics.bytes_flushed,
+            metrics.bytes_compacted,
+            metrics.write_amplification()
+        );
+        for (name, latency) in [
+            ("get", &metrics.get_latency),
+            ("put", &metrics.put_latency),
+            ("scan", &metrics.scan_latency),
+        ] {
+            println!(
+                "{}: {} calls, mean {:.1}us",
+                name,
+                latency.count,
+                latency.mean_micros()
+            );
+        }
+        Ok(())
+    }
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let mut options = LsmStorageOptions::default_for_week2_test(match args.compaction {
+        CompactionStrategy::None => CompactionOptions::NoCompaction,
+        CompactionStrategy::Simple => CompactionOptions::Simple(SimpleLeveledCompactionOptions {
+            size_ratio_percent: 200,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 4,
+        }),
+        CompactionStrategy::Tiered => CompactionOptions::Tiered(TieredCompactionOptions {
+            num_tiers: 3,
+            max_size_amplification_percent: 200,
+            size_ratio: 1,
+            min_merge_width: 2,
+            max_merge_width: None,
+        }),
+        CompactionStrategy::Leveled => CompactionOptions::Leveled(LeveledCompactionOptions {
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 4,
+            base_level_size_mb: 128,
+            level_size_multiplier: 2,
+        }),
+    });
+    options.block_size = 4096;
+    options.target_sst_size = 2 << 20; // 2MB
+    options.num_memtable_limit = 3;
+    options.enable_wal = args.enable_wal;
+    let mut shell = Shell {
+        lsm: MiniLsm::open(&args.path, options)?,
+        format: args.format,
+    };
+
+    println!(
+        "lsm-cli on {}, type `help` for the commands",
+        args.path.display()
+    );
+    let mut editor = DefaultEditor::new()?;
+    loop {
+        let line = match editor.readline("lsm-cli> ") {
+            Ok(line) => line,
+            Err(ReadlineError::Eof | ReadlineError::Interrupted) => break,
+            Err(e) => return Err(e.into()),
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        editor.add_history_entry(line.as_str())?;
+        // a typo or a failed command does not end the session
+        match Command::parse(&line).and_then(|command| shell.handle(command)) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(e) => println!("error: {}", e),
+        }
+    }
+    shell.lsm.close()
+}
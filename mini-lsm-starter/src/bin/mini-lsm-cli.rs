@@ -328,41 +328,35 @@ impl ReplBuilder {
 
 fn main() -> Result<()> {
     let args = Args::parse();
-    let lsm = MiniLsm::open(
-        args.path,
-        LsmStorageOptions {
-            block_size: 4096,
-            target_sst_size: 2 << 20, // 2MB
-            num_memtable_limit: 3,
-            compaction_options: match args.compaction {
-                CompactionStrategy::None => CompactionOptions::NoCompaction,
-                CompactionStrategy::Simple => {
-                    CompactionOptions::Simple(SimpleLeveledCompactionOptions {
-                        size_ratio_percent: 200,
-                        level0_file_num_compaction_trigger: 2,
-                        max_levels: 4,
-                    })
-                }
-                CompactionStrategy::Tiered => CompactionOptions::Tiered(TieredCompactionOptions {
-                    num_tiers: 3,
-                    max_size_amplification_percent: 200,
-                    size_ratio: 1,
-                    min_merge_width: 2,
-                    max_merge_width: None,
-                }),
-                CompactionStrategy::Leveled => {
-                    CompactionOptions::Leveled(LeveledCompactionOptions {
-                        level0_file_num_compaction_trigger: 2,
-                        max_levels: 4,
-                        base_level_size_mb: 128,
-                        level_size_multiplier: 2,
-                    })
-                }
-            },
-            enable_wal: args.enable_wal,
-            serializable: args.serializable,
-        },
-    )?;
+    // the options the variants of the storage engine do not share are left at their defaults
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.block_size = 4096;
+    options.target_sst_size = 2 << 20; // 2MB
+    options.num_memtable_limit = 3;
+    options.compaction_options = match args.compaction {
+        CompactionStrategy::None => CompactionOptions::NoCompaction,
+        CompactionStrategy::Simple => CompactionOptions::Simple(SimpleLeveledCompactionOptions {
+            size_ratio_percent: 200,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 4,
+        }),
+        CompactionStrategy::Tiered => CompactionOptions::Tiered(TieredCompactionOptions {
+            num_tiers: 3,
+            max_size_amplification_percent: 200,
+            size_ratio: 1,
+            min_merge_width: 2,
+            max_merge_width: None,
+        }),
+        CompactionStrategy::Leveled => CompactionOptions::Leveled(LeveledCompactionOptions {
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 4,
+            base_level_size_mb: 128,
+            level_size_multiplier: 2,
+        }),
+    };
+    options.enable_wal = args.enable_wal;
+    options.serializable = args.serializable;
+    let lsm = MiniLsm::open(args.path, options)?;
 
     let repl = ReplBuilder::new()
         .app_name("mini-lsm-cli")
@@ -0,0 +1,223 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+mod wrapper;
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::Result;
+use bytes::Bytes;
+use clap::{Parser, Subcommand};
+use wrapper::mini_lsm_wrapper;
+
+use mini_lsm_wrapper::iterators::StorageIterator;
+use mini_lsm_wrapper::key::{KeySlice, TS_RANGE_BEGIN};
+use mini_lsm_wrapper::table::{FileObject, Footer, SsTable, SsTableIterator};
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Inspect a single SST file", long_about = None)]
+struct Args {
+    /// Path of the SST file.
+    path: PathBuf,
+    /// Print keys and values in hex instead of escaped strings.
+    #[arg(long)]
+    hex: bool,
+    #[command(subcommand)]
+    command: DumpCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum DumpCommand {
+    /// Print the footer and the format version.
+    Footer,
+    /// Print the meta of every data block.
+    Meta,
+    /// Print the statistics of the bloom filter.
+    Bloom,
+    /// Print the table properties.
+    Properties,
+    /// Print the key-value pairs, optionally limited to the user keys from `--from` to `--to`,
+    /// both inclusive.
+    Scan {
+        #[arg(long)]
+        from: Option<String>,
+        #[arg(long)]
+        to: Option<String>,
+    },
+    /// Verify the checksums of all blocks.
+    Verify,
+}
+
+struct Dumper {
+    path: PathBuf,
+    sst: Arc<SsTable>,
+    hex: bool,
+}
+
+impl Dumper {
+    fn format_bytes(&self, data: &[u8]) -> String {
+        if self.hex {
+            data.iter().map(|byte| format!("{:02x}", byte)).collect()
+        } else {
+            format!("{:?}", Bytes::copy_from_slice(data))
+        }
+    }
+
+    fn format_key(&self, key: KeySlice) -> String {
+        format!("{}@{}", self.format_bytes(key.key_ref()), key.ts())
+    }
+
+    fn footer(&self) -> Result<()> {
+        let file = FileObject::open(&self.path)?;
+        let tail_len = file.size().min(Footer::SIZE as u64);
+        println!("file size: {}", file.size());
+        match Footer::decode(&file.read(file.size() - tail_len, tail_len)?)? {
+            Some(footer) => {
+                println!("format version: {}", footer.version);
+                println!("meta offset: {}", footer.meta_offset);
+                println!("range tombstone offset: {}", footer.range_tombstone_offset);
+                println!("bloom offset: {}", footer.bloom_offset);
+                match footer.properties_offset {
+                    Some(offset) => println!("properties offset: {}", offset),
+                    None => println!("properties offset: none"),
+                }
+            }
+            None => println!("format version: {} (no footer)", self.sst.format_version()),
+        }
+        Ok(())
+    }
+
+    fn meta(&self) -> Result<()> {
+        println!(
+            "{} blocks, partitioned index: {}",
+            self.sst.num_of_blocks(),
+            self.sst.has_partitioned_index()
+        );
+        for block_idx in 0..self.sst.num_of_blocks() {
+            let meta = self.sst.block_meta_at(block_idx)?;
+            println!(
+                "block {}: offset={} len={} padded_len={} first_key={} last_key={}",
+                block_idx,
+                meta.offset,
+                meta.len,
+                meta.padded_len,
+                self.format_key(meta.first_key.as_key_slice()),
+                self.format_key(meta.last_key.as_key_slice())
+            );
+        }
+        for tombstone in self.sst.range_tombstones() {
+            println!(
+                "range tombstone: [{}, {})@{}",
+                self.format_bytes(&tombstone.start),
+                self.format_bytes(&tombstone.end),
+                tombstone.ts
+            );
+        }
+        Ok(())
+    }
+
+    fn bloom(&self) -> Result<()> {
+        let stats = self.sst.stats()?;
+        println!("bits: {}", stats.bloom_bits);
+        println!("entries: {}", stats.num_entries);
+        if stats.num_entries > 0 {
+            println!(
+                "bits per key: {:.2}",
+                stats.bloom_bits as f64 / stats.num_entries as f64
+            );
+        }
+        match stats.bloom_estimated_fpr {
+            Some(fpr) => println!("estimated false positive rate: {:.4}%", fpr * 100.0),
+            None => println!("no bloom filter"),
+        }
+        Ok(())
+    }
+
+    fn properties(&self) -> Result<()> {
+        let Some(properties) = self.sst.properties() else {
+            println!(
+                "no properties in format version {}",
+                self.sst.format_version()
+            );
+            return Ok(());
+        };
+        println!("num entries: {}", properties.num_entries);
+        println!("num deletions: {}", properties.num_deletions);
+        println!("num range tombstones: {}", properties.num_range_tombstones);
+        println!("raw key size: {}", properties.raw_key_size);
+        println!("raw value size: {}", properties.raw_value_size);
+        println!("data size: {}", properties.data_size);
+        println!("compression ratio: {:.2}", properties.compression_ratio());
+        for (name, value) in &properties.user_properties {
+            println!("{}: {}", name, self.format_bytes(value));
+        }
+        Ok(())
+    }
+
+    fn scan(&self, from: Option<&str>, to: Option<&str>) -> Result<()> {
+        let mut iter = match from {
+            Some(from) => SsTableIterator::create_and_seek_to_key(
+                self.sst.clone(),
+                KeySlice::from_slice(from.as_bytes(), TS_RANGE_BEGIN),
+            )?,
+            None => SsTableIterator::create_and_seek_to_first(self.sst.clone())?,
+        };
+        let mut cnt = 0;
+        while iter.is_valid() {
+            if to.is_some_and(|to| iter.key().key_ref() > to.as_bytes()) {
+                break;
+            }
+            println!(
+                "{}={}",
+                self.format_key(iter.key()),
+                self.format_bytes(iter.value())
+            );
+            iter.next()?;
+            cnt += 1;
+        }
+        println!();
+        println!("{} entries scanned", cnt);
+        Ok(())
+    }
+
+    fn verify(&self) -> Result<()> {
+        self.sst.verify()?;
+        println!(
+            "{}: {} blocks verified",
+            self.path.display(),
+            self.sst.num_of_blocks()
+        );
+        Ok(())
+    }
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    // the footer, the meta and the properties are checked when opening
+    let sst = SsTable::open(0, None, FileObject::open(&args.path)?)?;
+    let dumper = Dumper {
+        path: args.path,
+        sst: Arc::new(sst),
+        hex: args.hex,
+    };
+    match args.command {
+        DumpCommand::Footer => dumper.footer(),
+        DumpCommand::Meta => dumper.meta(),
+        DumpCommand::Bloom => dumper.bloom(),
+        DumpCommand::Properties => dumper.properties(),
+        DumpCommand::Scan { from, to } => dumper.scan(from.as_deref(), to.as_deref()),
+        DumpCommand::Verify => dumper.verify(),
+    }
+}
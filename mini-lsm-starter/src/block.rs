@@ -18,13 +18,21 @@
 mod builder;
 mod iterator;
 
+use anyhow::{Result, bail};
 pub use builder::BlockBuilder;
+pub(crate) use builder::{MAX_VALUE_LEN, RESTART_INTERVAL, SIZEOF_U16, SIZEOF_U64, VALUE_REF_LEN};
 use bytes::{Buf, BufMut, Bytes};
-pub use iterator::BlockIterator;
+pub use iterator::{BlockIterator, BlockRefIterator};
+
+use crate::error::Error;
 
 /// A block is the smallest unit of read and caching in LSM tree. It is a collection of sorted key-value pairs.
+///
+/// Each entry is encoded as `overlap (u16) | rest_len (u16) | rest | ts (u64) | value_len (u16) |
+/// value`, where the user key is the first `overlap` bytes of the previous user key followed by
+/// `rest`. Restart points, every `RESTART_INTERVAL` entries, store their user keys in full.
 pub struct Block {
-    pub(crate) data: Vec<u8>,
+    pub(crate) data: Bytes,
     pub(crate) offsets: Vec<u16>,
 }
 
@@ -34,7 +42,7 @@ impl Block {
     pub fn encode(&self) -> Bytes {
         // unimplemented!()
         // let num_of_elements = self.offsets.len();
-        let mut data = self.data.clone();
+        let mut data = self.data.to_vec();
 
         for &off in &self.offsets {
             data.put_u16(off);
@@ -50,8 +58,7 @@ impl Block {
 
         let offsets_ptr = num_elements_ptr - num_elements * 2;
 
-        // block.data use u8 as data type, can be converted by to_vec() directly.
-        let kvdata = data[0..offsets_ptr].to_vec();
+        let kvdata = Bytes::copy_from_slice(&data[0..offsets_ptr]);
 
         // offsets needs transversal
         let mut offsets = Vec::with_capacity(num_elements);
@@ -61,7 +68,103 @@ impl Block {
         }
         Self {
             data: kvdata,
-            offsets: offsets,
+            offsets,
+        }
+    }
+
+    /// Decode from the data layout without copying: the data section of the returned block is a
+    /// slice of `data`, sharing the same underlying buffer.
+    pub fn decode_shared(data: Bytes) -> Result<Self> {
+        if data.len() < 2 {
+            bail!(Error::corruption(format!(
+                "block too short: {} bytes",
+                data.len()
+            )));
+        }
+        let num_elements_ptr = data.len() - 2;
+        let num_elements = (&data[num_elements_ptr..]).get_u16() as usize;
+        let Some(offsets_ptr) = num_elements_ptr.checked_sub(num_elements * 2) else {
+            bail!(Error::corruption(format!(
+                "block too short for {} entries",
+                num_elements
+            )));
+        };
+        let mut offsets = Vec::with_capacity(num_elements);
+        let mut offsets_data = &data[offsets_ptr..num_elements_ptr];
+        while offsets_data.has_remaining() {
+            let offset = offsets_data.get_u16();
+            if offset as usize >= offsets_ptr {
+                bail!(Error::corruption(format!(
+                    "block entry offset {} out of range",
+                    offset
+                )));
+            }
+            offsets.push(offset);
+        }
+        let block = Self {
+            data: data.slice(0..offsets_ptr),
+            offsets,
+        };
+        block.validate()?;
+        Ok(block)
+    }
+
+    /// Check that every entry of the block lies within its data section, and that the compressed
+    /// keys can be restored.
+    pub(crate) fn validate(&self) -> Result<()> {
+        let mut prev_key_len = 0;
+        for (idx, &offset) in self.offsets.iter().enumerate() {
+            let Some(mut entry) = self.data.get(offset as usize..) else {
+                bail!(Error::corruption(format!(
+                    "block entry {} offset {} out of range",
+                    idx, offset
+                )));
+            };
+            // a key shares a prefix with the previous key, except at restart points
+            let overlap = entry.try_get_u16()? as usize;
+            if overlap > prev_key_len || (idx.is_multiple_of(RESTART_INTERVAL) && overlap != 0) {
+                bail!(Error::corruption(format!(
+                    "block entry {} has invalid key overlap {}",
+                    idx, overlap
+                )));
+            }
+            prev_key_len = overlap + (&entry[..]).try_get_u16()? as usize;
+            for field in 0..2 {
+                let len = entry.try_get_u16()?;
+                if field == 0 {
+                    // skip the key and the timestamp after it
+                    if entry.remaining() < len as usize + SIZEOF_U64 {
+                        bail!(Error::corruption(format!("block entry {} truncated", idx)));
+                    }
+                    entry.advance(len as usize + SIZEOF_U64);
+                    continue;
+                }
+                if len == VALUE_REF_LEN {
+                    // a back-reference needs a previous entry to refer to
+                    if idx == 0 {
+                        bail!(Error::corruption(
+                            "first block entry refers to a previous value"
+                        ));
+                    }
+                    continue;
+                }
+                if entry.remaining() < len as usize {
+                    bail!(Error::corruption(format!("block entry {} truncated", idx)));
+                }
+                entry.advance(len as usize);
+            }
         }
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for Block {
+    /// Print the structure of the block without the raw entries.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Block")
+            .field("num_of_elements", &self.offsets.len())
+            .field("data_len", &self.data.len())
+            .field("offsets", &self.offsets)
+            .finish()
     }
 }
@@ -17,14 +17,29 @@
 
 use std::sync::atomic::fence;
 
-use bytes::BufMut;
+use bytes::{Buf, BufMut};
 
 use crate::{
     block,
     key::{KeySlice, KeyVec},
 };
 
-use super::Block;
+use super::{Block, BlockRefIterator};
+
+pub(crate) const SIZEOF_U16: usize = std::mem::size_of::<u16>();
+pub(crate) const SIZEOF_U64: usize = std::mem::size_of::<u64>();
+
+/// Every `RESTART_INTERVAL`-th entry of a block, starting from the first one, is a restart point
+/// that stores its key in full. Other entries only store the part of the key that differs from the
+/// previous key.
+pub(crate) const RESTART_INTERVAL: usize = 16;
+
+/// Value length marking an entry that stores no value bytes and instead has the same value as the
+/// entry before it in the block. Values must be shorter than this.
+pub(crate) const VALUE_REF_LEN: u16 = u16::MAX;
+
+/// The longest value a block can hold, as the next length is taken by `VALUE_REF_LEN`.
+pub(crate) const MAX_VALUE_LEN: usize = VALUE_REF_LEN as usize - 1;
 
 /// Builds a block.
 pub struct BlockBuilder {
@@ -36,6 +51,12 @@ pub struct BlockBuilder {
     block_size: usize,
     /// The first key in the block
     first_key: KeyVec,
+    /// The last key in the block, which the next key is compressed against
+    last_key: KeyVec,
+    /// Store a back-reference instead of a value equal to the previous entry's value
+    dedup_values: bool,
+    /// The range of the last stored value in `data`
+    last_value: (usize, usize),
 }
 
 impl BlockBuilder {
@@ -47,9 +68,19 @@ impl BlockBuilder {
             data: Vec::with_capacity(block_size),
             block_size,
             first_key: KeyVec::new(),
+            last_key: KeyVec::new(),
+            dedup_values: false,
+            last_value: (0, 0),
         }
     }
 
+    /// Store a non-empty value that byte-equals the value of the previous entry as a
+    /// back-reference to it, which is resolved when the block is read. Off by default.
+    pub fn with_value_dedup(mut self, enabled: bool) -> Self {
+        self.dedup_values = enabled;
+        self
+    }
+
     /// Adds a key-value pair to the block. Returns false when the block is full.
     /// You may find the `bytes::BufMut` trait useful for manipulating binary data.
     #[must_use]
@@ -57,26 +88,102 @@ impl BlockBuilder {
         // unimplemented!()
         // encode key and value to data.
         // use u8 as unit
-        let entry_size = key.len() + value.len() + 4;
-        let total_size =
-            self.data.len() + self.offsets.len() * 2 + entry_size + self.first_key.len();
-
-        if !self.data.is_empty() && total_size > self.block_size {
+        // overlap + rest_len + rest of the key + ts + value_len + value, plus one more offset in
+        // the footer
+        let value_size = if self.repeats_last_value(value) {
+            0
+        } else {
+            value.len()
+        };
+        let rest_size = key.key_len() - self.key_overlap(key);
+        let entry_size = rest_size + SIZEOF_U64 + value_size + SIZEOF_U16 * 3 + SIZEOF_U16;
+        if !self.is_empty() && self.estimated_size() + entry_size > self.block_size {
             return false;
         }
-        self.offsets.push(self.data.len() as u16);
+        self.append(key, value);
+        true
+    }
 
-        self.data.put_u16(key.len() as u16);
-        self.data.put(key.raw_ref());
+    /// Creates a block builder holding all entries of `block`, so that more entries can be
+    /// appended to it. The entries are kept even if they exceed `block_size`, and are copied as is,
+    /// including compressed keys and back-referenced values.
+    pub fn from_block(block: &Block, block_size: usize) -> Self {
+        let mut builder = Self::new(block_size);
+        for (idx, &offset) in block.offsets.iter().enumerate() {
+            let start = offset as usize;
+            let end = block
+                .offsets
+                .get(idx + 1)
+                .map_or(block.data.len(), |&next| next as usize);
+            let entry = &block.data[start..end];
+            let rest_len = (&entry[SIZEOF_U16..]).get_u16() as usize;
+            let value_start = SIZEOF_U16 * 3 + rest_len + SIZEOF_U64;
+            let value_len = (&entry[value_start - SIZEOF_U16..]).get_u16();
+
+            let new_offset = builder.data.len();
+            builder.offsets.push(new_offset as u16);
+            builder.data.put(entry);
+            if value_len != VALUE_REF_LEN {
+                let value_start = new_offset + value_start;
+                builder.last_value = (value_start, value_start + value_len as usize);
+            }
+        }
+        if !block.offsets.is_empty() {
+            let mut iter = BlockRefIterator::create_keys_only_and_seek_to_first(block);
+            builder.first_key = iter.key().to_key_vec();
+            iter.seek_to_last();
+            builder.last_key = iter.key().to_key_vec();
+        }
+        builder
+    }
 
-        self.data.put_u16(value.len() as u16);
-        self.data.put(value);
+    /// Length of the prefix the user key of `key` shares with the last user key, 0 if `key` starts
+    /// a restart point.
+    fn key_overlap(&self, key: KeySlice) -> usize {
+        if self.offsets.len().is_multiple_of(RESTART_INTERVAL) {
+            return 0;
+        }
+        self.last_key
+            .key_ref()
+            .iter()
+            .zip(key.key_ref())
+            .take_while(|(a, b)| a == b)
+            .count()
+    }
+
+    /// Whether `value` would be stored as a back-reference to the previous entry's value.
+    fn repeats_last_value(&self, value: &[u8]) -> bool {
+        self.dedup_values
+            && !value.is_empty()
+            && !self.is_empty()
+            && self.data[self.last_value.0..self.last_value.1] == *value
+    }
+
+    /// Appends an entry without checking the block size.
+    fn append(&mut self, key: KeySlice, value: &[u8]) {
+        assert!(value.len() <= MAX_VALUE_LEN, "value too large");
+        let repeated = self.repeats_last_value(value);
+        let overlap = self.key_overlap(key);
+        self.offsets.push(self.data.len() as u16);
+
+        self.data.put_u16(overlap as u16);
+        self.data.put_u16((key.key_len() - overlap) as u16);
+        self.data.put(&key.key_ref()[overlap..]);
+        self.data.put_u64(key.ts());
+
+        if repeated {
+            self.data.put_u16(VALUE_REF_LEN);
+        } else {
+            self.data.put_u16(value.len() as u16);
+            let value_start = self.data.len();
+            self.data.put(value);
+            self.last_value = (value_start, self.data.len());
+        }
 
         if self.first_key.is_empty() {
             self.first_key = key.to_key_vec();
         }
-
-        true
+        self.last_key.set_from_slice(key);
     }
 
     /// Check if there is no key-value pair in the block.
@@ -87,7 +194,7 @@ impl BlockBuilder {
     /// Finalize the block.
     pub fn build(self) -> Block {
         Block {
-            data: self.data,
+            data: self.data.into(),
             offsets: self.offsets,
         }
     }
@@ -95,4 +202,9 @@ impl BlockBuilder {
     pub fn get_size(&self) -> usize {
         self.data.len()
     }
+
+    /// The size of the block once encoded: the entries, the offsets, and the number of entries.
+    pub fn estimated_size(&self) -> usize {
+        self.data.len() + self.offsets.len() * SIZEOF_U16 + SIZEOF_U16
+    }
 }
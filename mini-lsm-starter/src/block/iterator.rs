@@ -15,18 +15,20 @@
 // #![allow(unused_variables)] // TODO(you): remove this lint after implementing this mod
 // #![allow(dead_code)] // TODO(you): remove this lint after implementing this mod
 
+use std::ops::Deref;
 use std::sync::Arc;
 
-use crate::key::{Key, KeySlice, KeyVec};
+use crate::key::{BytewiseComparator, Key, KeyBytes, KeyComparator, KeySlice, KeyVec};
 
-use bytes::Buf;
+use bytes::{Buf, Bytes};
 
-use super::Block;
+use super::{Block, RESTART_INTERVAL, VALUE_REF_LEN};
 
-/// Iterates on a block.
-pub struct BlockIterator {
-    /// The internal `Block`, wrapped by an `Arc`
-    block: Arc<Block>,
+/// Iterates on a block. The block is held by an `Arc` by default; use `BlockRefIterator` to
+/// iterate a borrowed block instead.
+pub struct BlockIterator<B: Deref<Target = Block> = Arc<Block>> {
+    /// The internal `Block`, wrapped by an `Arc` or borrowed
+    block: B,
     /// The current key, empty represents the iterator is invalid
     key: KeyVec,
     /// the current value range in the block.data, corresponds to the current key
@@ -35,30 +37,70 @@ pub struct BlockIterator {
     idx: usize,
     /// The first key in the block
     first_key: KeyVec,
+    /// Skip resolving `value_range` when seeking, `value` must not be called in this mode
+    keys_only: bool,
 }
 
-impl BlockIterator {
-    fn new(block: Arc<Block>) -> Self {
+/// Iterates on a borrowed block, avoiding the `Arc` for blocks that are not shared.
+pub type BlockRefIterator<'a> = BlockIterator<&'a Block>;
+
+impl<B: Deref<Target = Block>> BlockIterator<B> {
+    fn new(block: B) -> Self {
         Self {
             block,
             key: KeyVec::new(),
             value_range: (0, 0),
             idx: 0,
             first_key: KeyVec::new(),
+            keys_only: false,
         }
     }
 
     /// Creates a block iterator and seek to the first entry.
-    pub fn create_and_seek_to_first(block: Arc<Block>) -> Self {
+    pub fn create_and_seek_to_first(block: B) -> Self {
+        let mut iter = Self::new(block);
+        iter.seek_to_first();
+        iter
+    }
+
+    /// Creates a block iterator that only yields keys and seek to the first entry.
+    pub fn create_keys_only_and_seek_to_first(block: B) -> Self {
         let mut iter = Self::new(block);
+        iter.keys_only = true;
         iter.seek_to_first();
         iter
     }
 
+    /// Creates a block iterator that only yields keys and seek to the first key that >= `key`.
+    pub fn create_keys_only_and_seek_to_key(block: B, key: KeySlice) -> Self {
+        Self::create_keys_only_and_seek_to_key_by(block, key, &BytewiseComparator)
+    }
+
+    /// `create_keys_only_and_seek_to_key` in a block ordered by `comparator`.
+    pub fn create_keys_only_and_seek_to_key_by(
+        block: B,
+        key: KeySlice,
+        comparator: &dyn KeyComparator,
+    ) -> Self {
+        let mut iter = Self::new(block);
+        iter.keys_only = true;
+        iter.seek_to_key_by(key, comparator);
+        iter
+    }
+
     /// Creates a block iterator and seek to the first key that >= `key`.
-    pub fn create_and_seek_to_key(block: Arc<Block>, key: KeySlice) -> Self {
+    pub fn create_and_seek_to_key(block: B, key: KeySlice) -> Self {
+        Self::create_and_seek_to_key_by(block, key, &BytewiseComparator)
+    }
+
+    /// `create_and_seek_to_key` in a block ordered by `comparator`.
+    pub fn create_and_seek_to_key_by(
+        block: B,
+        key: KeySlice,
+        comparator: &dyn KeyComparator,
+    ) -> Self {
         let mut iter = Self::new(block);
-        iter.seek_to_key(key);
+        iter.seek_to_key_by(key, comparator);
         iter
     }
 
@@ -67,11 +109,33 @@ impl BlockIterator {
         self.key.as_key_slice()
     }
 
-    /// Returns the value of the current entry.
+    /// Returns the value of the current entry. A value stored as a back-reference to the previous
+    /// entry's value is resolved to that value.
     pub fn value(&self) -> &[u8] {
+        debug_assert!(!self.keys_only, "value is not resolved in keys-only mode");
         &self.block.data[self.value_range.0..self.value_range.1]
     }
 
+    /// Returns the key of the current entry. A key stored in full, e.g., at a restart point, is a
+    /// slice of the block data sharing its buffer; a prefix-compressed key is copied.
+    pub fn key_bytes(&self) -> KeyBytes {
+        let (overlap, rest, ts, _) = Self::raw_entry(&self.block, self.idx);
+        if overlap == 0 {
+            KeyBytes::from_bytes_with_ts(self.block.data.slice_ref(rest), ts)
+        } else {
+            KeyBytes::from_bytes_with_ts(Bytes::copy_from_slice(self.key.key_ref()), ts)
+        }
+    }
+
+    /// Returns the value of the current entry as a slice of the block data, sharing its buffer
+    /// instead of copying.
+    pub fn value_bytes(&self) -> Bytes {
+        debug_assert!(!self.keys_only, "value is not resolved in keys-only mode");
+        self.block
+            .data
+            .slice(self.value_range.0..self.value_range.1)
+    }
+
     /// Returns true if the iterator is valid.
     /// Note: You may want to make use of `key`
     pub fn is_valid(&self) -> bool {
@@ -84,51 +148,175 @@ impl BlockIterator {
         self.seek_to_index(0);
     }
 
-    fn seek_to_index_util(block: &Block, index: usize) -> (&[u8], (usize, usize)) {
+    /// Returns the length of the prefix shared with the previous user key, the rest of the user
+    /// key, the timestamp, and the remaining data starting from the value length of the entry at
+    /// `index`.
+    fn raw_entry(block: &Block, index: usize) -> (usize, &[u8], u64, &[u8]) {
         let offset = block.offsets[index] as usize;
         let mut data_ptr = &block.data[offset..];
+        let overlap = data_ptr.get_u16() as usize;
+        let rest_len = data_ptr.get_u16() as usize;
+        let (rest, mut data_ptr) = data_ptr.split_at(rest_len);
+        let ts = data_ptr.get_u64();
+        (overlap, rest, ts, data_ptr)
+    }
+
+    /// Restores the full key at `index` into `key`, decoding forward from the closest restart
+    /// point before it.
+    fn decode_key_at(block: &Block, index: usize, key: &mut KeyVec) {
+        key.clear();
+        for idx in index - index % RESTART_INTERVAL..=index {
+            let (overlap, rest, ts, _) = Self::raw_entry(block, idx);
+            key.truncate(overlap);
+            key.append(rest);
+            key.set_ts(ts);
+        }
+    }
+
+    /// Returns the number of leading restart points whose keys satisfy `pred`, like
+    /// `slice::partition_point`. The keys of restart points are stored in full.
+    fn restart_partition_point(block: &Block, pred: impl Fn(KeySlice) -> bool) -> usize {
+        let (mut lo, mut hi) = (0, block.offsets.len().div_ceil(RESTART_INTERVAL));
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            let (_, key, ts, _) = Self::raw_entry(block, mid * RESTART_INTERVAL);
+            if pred(KeySlice::from_slice(key, ts)) {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+        lo
+    }
 
-        // Parse key length and content
-        let key_len = data_ptr.get_u16() as usize;
-        let key_content = &data_ptr[..key_len];
-        data_ptr.advance(key_len);
+    /// Returns the value range in block.data of the entry at `index`, `None` if the value is a
+    /// back-reference to the previous entry.
+    fn value_range_at(block: &Block, index: usize) -> Option<(usize, usize)> {
+        let (_, _, _, mut data_ptr) = Self::raw_entry(block, index);
 
         // Parse value length and compute its range in block.data
-        let value_len = data_ptr.get_u16() as usize;
+        let value_len = data_ptr.get_u16();
+        if value_len == VALUE_REF_LEN {
+            return None;
+        }
         let value_start = block.data.len() - data_ptr.len();
-        let value_end = value_start + value_len;
+        Some((value_start, value_start + value_len as usize))
+    }
 
-        (key_content, (value_start, value_end))
+    /// Find the value of the entry at `index`, whose value is a back-reference, by walking back to
+    /// the closest entry that stores its value.
+    fn resolve_value_ref(block: &Block, index: usize) -> (usize, usize) {
+        (0..index)
+            .rev()
+            .find_map(|idx| Self::value_range_at(block, idx))
+            .expect("the first entry of a block stores its value")
     }
+
     pub fn seek_to_index(&mut self, index: usize) {
         if index >= self.block.offsets.len() {
             self.key.clear();
             return;
         }
-
-        let (key_content, (value_start, value_end)) = Self::seek_to_index_util(&self.block, index);
-        self.key.clear();
-        self.key.append(key_content);
-        self.value_range = (value_start, value_end);
+        // moving forward, the key is compressed against the key we are at, and a back-reference
+        // resolves to the value we are at
+        let forward = self.is_valid() && index == self.idx + 1;
+        if forward {
+            let (overlap, rest, ts, _) = Self::raw_entry(&self.block, index);
+            self.key.truncate(overlap);
+            self.key.append(rest);
+            self.key.set_ts(ts);
+        } else {
+            Self::decode_key_at(&self.block, index, &mut self.key);
+        }
         self.idx = index;
+        if self.keys_only {
+            return;
+        }
+        self.value_range = match Self::value_range_at(&self.block, index) {
+            Some(range) => range,
+            None if forward => self.value_range,
+            None => Self::resolve_value_ref(&self.block, index),
+        };
+    }
+
+    /// Index of the current entry in the block.
+    pub(crate) fn index(&self) -> usize {
+        self.idx
+    }
+
+    /// Number of entries from the current one to the end of the block, 0 if the iterator is invalid.
+    pub(crate) fn remaining(&self) -> usize {
+        if self.is_valid() {
+            self.block.offsets.len() - self.idx
+        } else {
+            0
+        }
     }
+
     /// Move to the next key in the block.
     pub fn next(&mut self) {
         self.seek_to_index(self.idx + 1);
     }
 
+    /// Move to the previous key in the block, the iterator becomes invalid if the current key is
+    /// the first one.
+    pub fn prev(&mut self) {
+        if self.idx == 0 {
+            self.key.clear();
+        } else {
+            self.seek_to_index(self.idx - 1);
+        }
+    }
+
+    /// Seeks to the last key in the block.
+    pub fn seek_to_last(&mut self) {
+        match self.block.offsets.len() {
+            0 => self.key.clear(),
+            len => self.seek_to_index(len - 1),
+        }
+    }
+
+    /// Seek to the last key that <= `key`, the iterator becomes invalid if there is none.
+    pub fn seek_for_prev(&mut self, key: KeySlice) {
+        self.seek_for_prev_by(key, &BytewiseComparator);
+    }
+
+    /// `seek_for_prev` in a block ordered by `comparator`.
+    pub fn seek_for_prev_by(&mut self, key: KeySlice, comparator: &dyn KeyComparator) {
+        let restart =
+            Self::restart_partition_point(&self.block, |k| comparator.compare_keys(k, key).is_le());
+        if restart == 0 {
+            self.key.clear();
+            return;
+        }
+        // the key of the restart point is <= `key`, move forward while the next key is too
+        self.seek_to_index((restart - 1) * RESTART_INTERVAL);
+        let mut last = self.idx;
+        loop {
+            self.next();
+            if !self.is_valid() || comparator.compare_keys(self.key(), key).is_gt() {
+                break;
+            }
+            last = self.idx;
+        }
+        self.seek_to_index(last);
+    }
+
     /// Seek to the first key that >= `key`.
     /// Note: You should assume the key-value pairs in the block are sorted when being added by
     /// callers.
     pub fn seek_to_key(&mut self, key: KeySlice) {
-        // 寻找第一个满足 k >= key 的索引
-        let index = self.block.offsets.partition_point(|&offset| {
-            let mut data_ptr = &self.block.data[offset as usize..];
-            let key_len = data_ptr.get_u16() as usize;
-            let k = &data_ptr[..key_len];
-            KeySlice::from_slice(k) < key
-        });
-
-        self.seek_to_index(index);
+        self.seek_to_key_by(key, &BytewiseComparator);
+    }
+
+    /// `seek_to_key` in a block ordered by `comparator`.
+    pub fn seek_to_key_by(&mut self, key: KeySlice, comparator: &dyn KeyComparator) {
+        // binary search the restart points, then scan forward from the last one that is < `key`
+        let restart =
+            Self::restart_partition_point(&self.block, |k| comparator.compare_keys(k, key).is_lt());
+        self.seek_to_index(restart.saturating_sub(1) * RESTART_INTERVAL);
+        while self.is_valid() && comparator.compare_keys(self.key(), key).is_lt() {
+            self.next();
+        }
     }
 }
@@ -0,0 +1,332 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::Arc;
+
+use anyhow::Result;
+use parking_lot::Mutex;
+
+use crate::block::Block;
+
+/// `(namespace, sst_id, block_idx)`
+type CacheKey = (usize, usize, usize);
+
+/// Which block to evict when the cache is full.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BlockCachePolicy {
+    /// Evict the least recently used block.
+    #[default]
+    Lru,
+    /// Evict the least frequently used block, and the least recently used one among those.
+    Lfu,
+    /// Sweep the blocks in insertion order, giving the ones used since the last sweep a second
+    /// chance.
+    Clock,
+}
+
+/// Counters of the lookups into the block cache.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+}
+
+struct CacheEntry {
+    block: Arc<Block>,
+    /// Number of lookups that hit this entry, plus the one that inserted it.
+    frequency: u64,
+    /// Tick of the last lookup that hit this entry, unique among the entries.
+    last_used: u64,
+    /// Whether the entry has been used since the clock hand last passed it.
+    referenced: bool,
+}
+
+struct CacheInner {
+    capacity: usize,
+    policy: BlockCachePolicy,
+    entries: HashMap<CacheKey, CacheEntry>,
+    /// Eviction order for LRU and LFU: the entry to evict first comes first.
+    order: BTreeMap<(u64, u64), CacheKey>,
+    /// The clock for `BlockCachePolicy::Clock`, with the hand at the front.
+    clock: VecDeque<CacheKey>,
+    tick: u64,
+    /// Keyed by `(namespace, sst_id)`.
+    stats: HashMap<(usize, usize), CacheStats>,
+    /// Keyed by namespace, kept when the stats of an SST are removed.
+    total_stats: HashMap<usize, CacheStats>,
+}
+
+impl CacheInner {
+    fn rank(&self, entry: &CacheEntry) -> (u64, u64) {
+        match self.policy {
+            BlockCachePolicy::Lfu => (entry.frequency, entry.last_used),
+            BlockCachePolicy::Lru | BlockCachePolicy::Clock => (entry.last_used, 0),
+        }
+    }
+
+    /// Update the stats of the SST of `key` and the total ones of its namespace.
+    fn count(&mut self, (namespace, sst_id, _): CacheKey, update: fn(&mut CacheStats)) {
+        update(self.stats.entry((namespace, sst_id)).or_default());
+        update(self.total_stats.entry(namespace).or_default());
+    }
+
+    fn get(&mut self, key: CacheKey) -> Option<Arc<Block>> {
+        self.tick += 1;
+        let tick = self.tick;
+        let Some(mut entry) = self.entries.remove(&key) else {
+            self.count(key, |stats| stats.misses += 1);
+            return None;
+        };
+        self.count(key, |stats| stats.hits += 1);
+        if self.policy != BlockCachePolicy::Clock {
+            self.order.remove(&self.rank(&entry));
+        }
+        entry.frequency += 1;
+        entry.last_used = tick;
+        entry.referenced = true;
+        if self.policy != BlockCachePolicy::Clock {
+            self.order.insert(self.rank(&entry), key);
+        }
+        let block = entry.block.clone();
+        self.entries.insert(key, entry);
+        Some(block)
+    }
+
+    fn insert(&mut self, key: CacheKey, block: Arc<Block>) {
+        self.remove(key);
+        if self.capacity == 0 {
+            return;
+        }
+        while self.entries.len() >= self.capacity {
+            self.evict();
+        }
+        self.tick += 1;
+        let entry = CacheEntry {
+            block,
+            frequency: 1,
+            last_used: self.tick,
+            referenced: false,
+        };
+        match self.policy {
+            BlockCachePolicy::Clock => self.clock.push_back(key),
+            BlockCachePolicy::Lru | BlockCachePolicy::Lfu => {
+                self.order.insert(self.rank(&entry), key);
+            }
+        }
+        self.entries.insert(key, entry);
+    }
+
+    fn evict(&mut self) {
+        let victim = match self.policy {
+            BlockCachePolicy::Clock => loop {
+                let key = self.clock.pop_front().expect("the clock holds every entry");
+                let entry = self.entries.get_mut(&key).unwrap();
+                if !entry.referenced {
+                    break key;
+                }
+                entry.referenced = false;
+                self.clock.push_back(key);
+            },
+            BlockCachePolicy::Lru | BlockCachePolicy::Lfu => {
+                self.order
+                    .pop_first()
+                    .expect("the order holds every entry")
+                    .1
+            }
+        };
+        self.entries.remove(&victim);
+        self.count(victim, |stats| stats.evictions += 1);
+    }
+
+    fn remove(&mut self, key: CacheKey) {
+        if let Some(entry) = self.entries.remove(&key) {
+            match self.policy {
+                BlockCachePolicy::Clock => self.clock.retain(|k| *k != key),
+                BlockCachePolicy::Lru | BlockCachePolicy::Lfu => {
+                    self.order.remove(&self.rank(&entry));
+                }
+            }
+        }
+    }
+
+    fn remove_if(&mut self, predicate: impl Fn(&CacheKey) -> bool) {
+        let keys = self
+            .entries
+            .keys()
+            .filter(|key| predicate(key))
+            .copied()
+            .collect::<Vec<_>>();
+        for key in keys {
+            self.remove(key);
+        }
+    }
+}
+
+/// A block cache shared by the SSTs of a storage engine, keyed by `(sst_id, block_idx)`.
+///
+/// Multiple storage engines in the same process can share one cache by using handles with
+/// different namespaces (see `with_namespace`), so that their SST ids do not collide.
+#[derive(Clone)]
+pub struct BlockCache {
+    inner: Arc<Mutex<CacheInner>>,
+    namespace: usize,
+}
+
+impl BlockCache {
+    /// Create an LRU block cache that holds at most `capacity` blocks.
+    pub fn new(capacity: u64) -> Self {
+        Self::with_policy(capacity, BlockCachePolicy::default())
+    }
+
+    /// Create a block cache that holds at most `capacity` blocks and evicts by `policy`.
+    pub fn with_policy(capacity: u64, policy: BlockCachePolicy) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(CacheInner {
+                capacity: capacity as usize,
+                policy,
+                entries: HashMap::new(),
+                order: BTreeMap::new(),
+                clock: VecDeque::new(),
+                tick: 0,
+                stats: HashMap::new(),
+                total_stats: HashMap::new(),
+            })),
+            namespace: 0,
+        }
+    }
+
+    /// Create a handle to the same underlying cache whose keys live in `namespace`.
+    pub fn with_namespace(&self, namespace: usize) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            namespace,
+        }
+    }
+
+    pub fn namespace(&self) -> usize {
+        self.namespace
+    }
+
+    pub fn policy(&self) -> BlockCachePolicy {
+        self.inner.lock().policy
+    }
+
+    /// Get the block from the cache, or load it with `init` and insert it into the cache. The
+    /// cache is not locked while loading, so concurrent misses on the same block may load it more
+    /// than once.
+    pub fn try_get_with<F>(
+        &self,
+        (sst_id, block_idx): (usize, usize),
+        init: F,
+    ) -> Result<Arc<Block>>
+    where
+        F: FnOnce() -> Result<Arc<Block>>,
+    {
+        let key = (self.namespace, sst_id, block_idx);
+        if let Some(block) = self.inner.lock().get(key) {
+            return Ok(block);
+        }
+        let block = init()?;
+        let mut inner = self.inner.lock();
+        if !inner.entries.contains_key(&key) {
+            inner.insert(key, block.clone());
+        }
+        Ok(block)
+    }
+
+    /// Get the block from the cache if it is there. Unlike `try_get_with`, a miss is not counted,
+    /// as the caller is expected to load the block with `try_get_with` afterwards.
+    pub fn get_if_present(&self, (sst_id, block_idx): (usize, usize)) -> Option<Arc<Block>> {
+        let key = (self.namespace, sst_id, block_idx);
+        let mut inner = self.inner.lock();
+        if inner.entries.contains_key(&key) {
+            inner.get(key)
+        } else {
+            None
+        }
+    }
+
+    /// Put a block into the cache, replacing the cached one if any.
+    pub fn insert(&self, (sst_id, block_idx): (usize, usize), block: Arc<Block>) {
+        self.inner
+            .lock()
+            .insert((self.namespace, sst_id, block_idx), block);
+    }
+
+    /// Drop a single cached block.
+    pub fn invalidate_block(&self, sst_id: usize, block_idx: usize) {
+        self.inner
+            .lock()
+            .remove((self.namespace, sst_id, block_idx));
+    }
+
+    /// Check if the block of `sst_id` at `block_idx` is in the cache.
+    pub fn contains_block(&self, sst_id: usize, block_idx: usize) -> bool {
+        self.inner
+            .lock()
+            .entries
+            .contains_key(&(self.namespace, sst_id, block_idx))
+    }
+
+    /// Number of cached blocks in the namespace of this handle.
+    pub fn entry_count(&self) -> usize {
+        let namespace = self.namespace;
+        self.inner
+            .lock()
+            .entries
+            .keys()
+            .filter(|(ns, _, _)| *ns == namespace)
+            .count()
+    }
+
+    /// Drop all cached blocks of an SST, e.g., after the SST is removed by compaction. The stats
+    /// of the SST are dropped as well.
+    pub fn invalidate_sst(&self, sst_id: usize) {
+        let namespace = self.namespace;
+        let mut inner = self.inner.lock();
+        inner.remove_if(|&(ns, id, _)| ns == namespace && id == sst_id);
+        inner.stats.remove(&(namespace, sst_id));
+    }
+
+    /// Drop all cached blocks in the namespace of this handle.
+    pub fn clear(&self) {
+        let namespace = self.namespace;
+        self.inner.lock().remove_if(|&(ns, _, _)| ns == namespace);
+    }
+
+    /// The hit, miss and eviction counters of each SST in the namespace of this handle.
+    pub fn cache_stats(&self) -> HashMap<usize, CacheStats> {
+        let namespace = self.namespace;
+        self.inner
+            .lock()
+            .stats
+            .iter()
+            .filter(|((ns, _), _)| *ns == namespace)
+            .map(|(&(_, sst_id), stats)| (sst_id, *stats))
+            .collect()
+    }
+
+    /// The counters of all lookups in the namespace of this handle, including the ones of the
+    /// SSTs removed since.
+    pub fn total_stats(&self) -> CacheStats {
+        self.inner
+            .lock()
+            .total_stats
+            .get(&self.namespace)
+            .copied()
+            .unwrap_or_default()
+    }
+}
@@ -0,0 +1,124 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fs::File;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+use crate::lsm_storage::LsmStorageInner;
+use crate::manifest::{Manifest, ManifestRecord};
+
+/// Hard-link `src` to `dst`, or copy it if they are on different file systems.
+fn link_or_copy(src: &Path, dst: &Path) -> Result<()> {
+    if std::fs::hard_link(src, dst).is_err() {
+        copy(src, dst)?;
+    }
+    Ok(())
+}
+
+fn copy(src: &Path, dst: &Path) -> Result<()> {
+    std::fs::copy(src, dst)
+        .with_context(|| format!("failed to copy {} to {}", src.display(), dst.display()))?;
+    File::open(dst)?.sync_all()?;
+    Ok(())
+}
+
+impl LsmStorageInner {
+    /// Create a checkpoint of the storage in `dir`, which must not exist yet: a copy of the
+    /// storage that can be opened on its own, taken without stopping the writes.
+    ///
+    /// The memtables are flushed first. The SSTs and the sealed value log files are hard-linked
+    /// into `dir`, as they are never modified, while the files still being appended to, i.e., the
+    /// WALs of the memtables frozen or written since and the head of the value log, are copied.
+    /// The checkpoint gets a manifest of its own describing the state at the time of the copy. The
+    /// column families are checkpointed before the WALs are copied, which may log writes to them
+    /// made after their checkpoints.
+    pub fn create_checkpoint(&self, dir: impl AsRef<Path>) -> Result<()> {
+        let dir = dir.as_ref();
+        self.flush()?;
+        if let Some(parent) = dir.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::create_dir(dir)
+            .with_context(|| format!("failed to create checkpoint {}", dir.display()))?;
+        let column_families = self.column_families.read().clone();
+        for cf in column_families.values() {
+            cf.inner
+                .create_checkpoint(dir.join(cf.inner.path.file_name().unwrap()))?;
+        }
+
+        // the garbage collection may not remove the value log files, and the SSTs may not be
+        // removed by a compaction while linking them
+        let _gc_lock = self.value_log_gc_lock.lock();
+        let state_lock = self.state_lock.lock();
+        let snapshot = self.state.read().clone();
+        for &sst_id in snapshot
+            .l0_sstables
+            .iter()
+            .chain(snapshot.levels.iter().flat_map(|(_, ssts)| ssts))
+        {
+            link_or_copy(
+                &self.path_of_sst(sst_id),
+                &Self::path_of_sst_static(dir, sst_id),
+            )?;
+        }
+
+        let mut memtables = Vec::new();
+        if self.options.enable_wal {
+            for memtable in std::iter::once(&snapshot.memtable).chain(&snapshot.imm_memtables) {
+                // the buffered records must be in the file before copying it
+                memtable.sync_wal()?;
+                copy(
+                    &self.path_of_wal(memtable.id()),
+                    &Self::path_of_wal_static(dir, memtable.id()),
+                )?;
+                memtables.push(memtable.id());
+            }
+        }
+
+        // the values are appended before the WAL records pointing to them, so the copies of the
+        // value log hold every value the copies of the WALs point to
+        let head_id = self
+            .value_log_head
+            .lock()
+            .as_ref()
+            .map(|head| head.file.id());
+        for file in self.value_logs.files() {
+            let src = self.path_of_vlog(file.id());
+            let dst = dir.join(src.file_name().unwrap());
+            if Some(file.id()) == head_id {
+                copy(&src, &dst)?;
+            } else {
+                link_or_copy(&src, &dst)?;
+            }
+        }
+
+        let manifest = Manifest::create(dir.join("MANIFEST"))?;
+        manifest.add_record_when_init(ManifestRecord::Snapshot {
+            memtables,
+            l0_sstables: snapshot.l0_sstables.clone(),
+            levels: snapshot.levels.clone(),
+            next_sst_id: self.next_sst_id(),
+            column_families: column_families
+                .iter()
+                .map(|(name, cf)| (cf.id, name.clone()))
+                .collect(),
+            comparator: Some(self.options.comparator.name().to_string()),
+        })?;
+        drop(state_lock);
+        File::open(dir)?.sync_all()?;
+        Ok(())
+    }
+}
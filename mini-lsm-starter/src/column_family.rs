@@ -0,0 +1,293 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{Result, bail};
+use bytes::Bytes;
+
+use crate::lsm_iterator::{FusedIterator, LsmIterator};
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions, WriteBatchRecord, batch_data};
+use crate::manifest::ManifestRecord;
+use crate::ttl;
+use crate::wal::ColumnFamilyEntries;
+
+/// The name of the column family of the storage itself, which always exists.
+pub const DEFAULT_COLUMN_FAMILY: &str = "default";
+
+/// A named keyspace of a storage with a memtable, SSTs and options of its own. It is stored as a
+/// storage of its own in the directory `cf-<id>`, whose manifest lists its SSTs, while its writes
+/// are logged to the WAL of the storage and its blocks are kept in the block cache of the storage.
+///
+/// A WAL of the storage logs the writes to the column families while its memtable is the current
+/// one, so the column families are flushed before the WAL is removed.
+pub(crate) struct ColumnFamily {
+    pub(crate) id: usize,
+    pub(crate) inner: Arc<LsmStorageInner>,
+}
+
+impl ColumnFamily {
+    fn path(storage_path: &Path, id: usize) -> PathBuf {
+        storage_path.join(format!("cf-{}", id))
+    }
+}
+
+impl LsmStorageInner {
+    /// The ids and names of the column families after applying the manifest `records`.
+    pub(crate) fn column_families_in(records: &[ManifestRecord]) -> BTreeMap<usize, String> {
+        let mut column_families = BTreeMap::new();
+        for record in records {
+            match record {
+                ManifestRecord::CreateColumnFamily { id, name } => {
+                    column_families.insert(*id, name.clone());
+                }
+                ManifestRecord::DropColumnFamily(id) => {
+                    column_families.remove(id);
+                }
+                ManifestRecord::Snapshot {
+                    column_families: snapshot,
+                    ..
+                } => column_families = snapshot.iter().cloned().collect(),
+                _ => {}
+            }
+        }
+        column_families
+    }
+
+    /// The ids and names of the column families, to be recorded in a manifest snapshot.
+    pub(crate) fn column_family_ids(&self) -> Vec<(usize, String)> {
+        self.column_families
+            .read()
+            .iter()
+            .map(|(name, cf)| (cf.id, name.clone()))
+            .collect()
+    }
+
+    /// The column families other than the default one.
+    pub(crate) fn column_families(&self) -> Vec<Arc<ColumnFamily>> {
+        self.column_families.read().values().cloned().collect()
+    }
+
+    /// The names of the column families, starting with the default one.
+    pub fn column_family_names(&self) -> Vec<String> {
+        std::iter::once(DEFAULT_COLUMN_FAMILY.to_string())
+            .chain(self.column_families.read().keys().cloned())
+            .collect()
+    }
+
+    pub(crate) fn column_family(&self, name: &str) -> Result<Arc<ColumnFamily>> {
+        match self.column_families.read().get(name) {
+            Some(cf) => Ok(cf.clone()),
+            None => bail!("column family {} not found", name),
+        }
+    }
+
+    fn open_column_family(
+        &self,
+        id: usize,
+        mut options: LsmStorageOptions,
+    ) -> Result<ColumnFamily> {
+        // the writes are logged to the WAL of the storage
+        options.enable_wal = false;
+        // the writes to the column families are not replicated
+        options.enable_replication_log = false;
+        options.column_family_options.clear();
+        let path = ColumnFamily::path(&self.path, id);
+        // the namespaces of other storages sharing the block cache are expected to be small
+        let block_cache = Arc::new(
+            self.block_cache
+                .with_namespace(self.block_cache.namespace() + (id << (usize::BITS / 2))),
+        );
+        let inner = if self.read_only {
+            Self::open_readonly_with_block_cache(path, options, block_cache)?
+        } else {
+            Self::open_with_block_cache(path, options, block_cache)?
+        };
+        Ok(ColumnFamily {
+            id,
+            inner: Arc::new(inner),
+        })
+    }
+
+    /// Open the `column_families` of the storage with their options in `column_family_options`,
+    /// or the options of the storage, and replay their `entries` recovered from the WALs. The
+    /// column families with recovered entries are flushed, so that the WALs can be removed.
+    pub(crate) fn open_column_families(
+        &self,
+        column_families: BTreeMap<usize, String>,
+        entries: ColumnFamilyEntries,
+    ) -> Result<()> {
+        let mut opened = HashMap::new();
+        for (id, name) in column_families {
+            let options = self
+                .options
+                .column_family_options
+                .get(&name)
+                .cloned()
+                .unwrap_or_else(|| self.options.as_ref().clone());
+            opened.insert(id, (name, self.open_column_family(id, options)?));
+        }
+        let mut replayed = BTreeSet::new();
+        for (id, key, value) in &entries {
+            // the writes to a dropped column family are ignored
+            let Some((_, cf)) = opened.get(id) else {
+                continue;
+            };
+            cf.inner
+                .state
+                .read()
+                .memtable
+                .put(key.as_key_slice(), value)?;
+            let mvcc = cf.inner.mvcc();
+            mvcc.update_commit_ts(mvcc.latest_commit_ts().max(key.ts()));
+            replayed.insert(*id);
+        }
+        for id in replayed {
+            opened[&id].1.inner.flush()?;
+        }
+        *self.column_families.write() = opened
+            .into_values()
+            .map(|(name, cf)| (name, Arc::new(cf)))
+            .collect();
+        Ok(())
+    }
+
+    /// Create a column family with `options`, of which `enable_wal` is ignored as the writes are
+    /// logged to the WAL of the storage. When the storage is reopened, the column family uses its
+    /// options in `column_family_options`, or the options of the storage.
+    pub fn create_cf(&self, name: &str, options: LsmStorageOptions) -> Result<()> {
+        self.check_writable()?;
+        let state_lock = self.state_lock.lock();
+        if name == DEFAULT_COLUMN_FAMILY || self.column_families.read().contains_key(name) {
+            bail!("column family {} already exists", name);
+        }
+        let id = self.next_sst_id();
+        let path = ColumnFamily::path(&self.path, id);
+        if path.exists() {
+            // left by a creation interrupted before it was logged to the manifest
+            std::fs::remove_dir_all(&path)?;
+        }
+        let cf = self.open_column_family(id, options)?;
+        self.sync_dir()?;
+        // in the state before logging it, see `add_manifest_record`
+        self.column_families
+            .write()
+            .insert(name.to_string(), Arc::new(cf));
+        self.add_manifest_record(
+            &state_lock,
+            ManifestRecord::CreateColumnFamily {
+                id,
+                name: name.to_string(),
+            },
+        )
+    }
+
+    /// Drop a column family and remove its data. The reads and writes of the column family that
+    /// are still running may fail.
+    pub fn drop_cf(&self, name: &str) -> Result<()> {
+        self.check_writable()?;
+        if name == DEFAULT_COLUMN_FAMILY {
+            bail!("the default column family cannot be dropped");
+        }
+        if self.is_index_column_family(name) {
+            bail!("column family {} holds an index, see `drop_index`", name);
+        }
+        let state_lock = self.state_lock.lock();
+        let Some(cf) = self.column_families.write().remove(name) else {
+            bail!("column family {} not found", name);
+        };
+        self.add_manifest_record(&state_lock, ManifestRecord::DropColumnFamily(cf.id))?;
+        drop(state_lock);
+        for id in cf.inner.state.read().sstables.keys() {
+            cf.inner.block_cache.invalidate_sst(*id);
+        }
+        std::fs::remove_dir_all(&cf.inner.path)?;
+        self.sync_dir()
+    }
+
+    pub fn put_cf(&self, cf: &str, key: &[u8], value: &[u8]) -> Result<()> {
+        self.write_batch_cf(cf, &[WriteBatchRecord::Put(key, value)])
+    }
+
+    pub fn delete_cf(&self, cf: &str, key: &[u8]) -> Result<()> {
+        self.write_batch_cf(cf, &[WriteBatchRecord::Del(key)])
+    }
+
+    pub fn merge_cf(&self, cf: &str, key: &[u8], operand: &[u8]) -> Result<()> {
+        self.write_batch_cf(cf, &[WriteBatchRecord::Merge(key, operand)])
+    }
+
+    /// Write a batch of data into the column family `cf`, see `write_batch`. The batch is logged
+    /// to the WAL of the storage as one record and applied to the memtable of the column family.
+    pub fn write_batch_cf<T: AsRef<[u8]>>(
+        &self,
+        cf: &str,
+        batch: &[WriteBatchRecord<T>],
+    ) -> Result<()> {
+        if cf == DEFAULT_COLUMN_FAMILY {
+            return self.write_batch(batch);
+        }
+        self.check_writable()?;
+        let cf = self.column_family(cf)?;
+        let inner = &cf.inner;
+        let expire_at = inner.options.default_ttl.map(ttl::expire_at);
+        inner.check_batch_len(batch, expire_at)?;
+        let (memtable, cf_memtable, separated) = {
+            let _write_lock = inner.mvcc().write_lock.lock();
+            let ts = inner.mvcc().latest_commit_ts() + 1;
+            let (values, separated) = inner.encode_values(batch, ts, expire_at)?;
+            let data = batch_data(batch, &values, ts);
+            // the memtable of the storage is not frozen until the batch is in the memtable of the
+            // column family, so the column families flushed after freezing it hold the batch
+            let state = self.state.read();
+            state.memtable.log_column_family_batch(cf.id, &data)?;
+            let cf_state = inner.state.read();
+            cf_state.memtable.put_batch(&data)?;
+            inner.mvcc().update_commit_ts(ts);
+            (state.memtable.clone(), cf_state.memtable.clone(), separated)
+        };
+        self.sync_write(&memtable, separated.then_some(inner.as_ref()))?;
+        inner.try_freeze(&cf_memtable)
+    }
+
+    pub fn get_cf(&self, cf: &str, key: &[u8]) -> Result<Option<Bytes>> {
+        if cf == DEFAULT_COLUMN_FAMILY {
+            return self.get(key);
+        }
+        self.column_family(cf)?.inner.get(key)
+    }
+
+    pub fn scan_cf(
+        &self,
+        cf: &str,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<FusedIterator<LsmIterator>> {
+        if cf == DEFAULT_COLUMN_FAMILY {
+            return self.scan(lower, upper);
+        }
+        self.column_family(cf)?.inner.scan(lower, upper)
+    }
+
+    /// Flush the memtables of all column families other than the default one.
+    pub(crate) fn flush_column_families(&self) -> Result<()> {
+        for cf in self.column_families() {
+            cf.inner.flush()?;
+        }
+        Ok(())
+    }
+}
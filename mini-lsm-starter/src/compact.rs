@@ -17,21 +17,35 @@
 
 mod leveled;
 mod simple_leveled;
+mod subcompaction;
 mod tiered;
 
+use std::ops::Bound;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::Duration;
 
 use anyhow::Result;
+use bytes::Bytes;
 pub use leveled::{LeveledCompactionController, LeveledCompactionOptions, LeveledCompactionTask};
+use parking_lot::Mutex;
 use serde::{Deserialize, Serialize};
 pub use simple_leveled::{
     SimpleLeveledCompactionController, SimpleLeveledCompactionOptions, SimpleLeveledCompactionTask,
 };
+pub use subcompaction::{BoundedIterator, Subcompaction};
 pub use tiered::{TieredCompactionController, TieredCompactionOptions, TieredCompactionTask};
 
-use crate::lsm_storage::{LsmStorageInner, LsmStorageState};
-use crate::table::SsTable;
+use crate::iterators::StorageIterator;
+use crate::iterators::concat_iterator::SstConcatIterator;
+use crate::iterators::merge_iterator::MergeIterator;
+use crate::key::KeyBytes;
+use crate::lsm_storage::{CompactionFilter, LsmStorageInner, LsmStorageState};
+use crate::manifest::ManifestRecord;
+use crate::range_tombstone::RangeTombstone;
+use crate::rate_limiter::IoPriority;
+use crate::table::{SsTable, SstSplitter};
+use crate::vlog::ValueLogSnapshot;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum CompactionTask {
@@ -53,6 +67,70 @@ impl CompactionTask {
             CompactionTask::Tiered(task) => task.bottom_tier_included,
         }
     }
+
+    /// The first user keys of the SSTs of the level below the output level, where the output SSTs
+    /// preferably end, see `SstSplitter`. Empty if the output is not a level above another.
+    fn grandparent_boundaries(&self, snapshot: &LsmStorageState) -> Vec<Bytes> {
+        let lower_level = match self {
+            CompactionTask::Leveled(task) => task.lower_level,
+            CompactionTask::Simple(task) => task.lower_level,
+            _ => return Vec::new(),
+        };
+        // the levels are numbered from 1, so this is the level below
+        let Some((_, grandparent)) = snapshot.levels.get(lower_level) else {
+            return Vec::new();
+        };
+        grandparent
+            .iter()
+            .map(|id| &snapshot.sstables[id])
+            // an SST may hold range tombstones only
+            .filter(|sst| sst.num_of_blocks() > 0)
+            .map(|sst| Bytes::copy_from_slice(sst.first_key().key_ref()))
+            .collect()
+    }
+
+    /// The sorted runs the task reads, from latest to earliest.
+    fn input_runs(&self, snapshot: &LsmStorageState) -> Vec<Vec<Arc<SsTable>>> {
+        let ssts = |ids: &[usize]| {
+            ids.iter()
+                .map(|id| snapshot.sstables[id].clone())
+                .collect::<Vec<_>>()
+        };
+        // each L0 SST is a sorted run of its own
+        let l0_runs = |ids: &[usize]| ids.iter().map(|id| ssts(&[*id])).collect::<Vec<_>>();
+        match self {
+            CompactionTask::ForceFullCompaction {
+                l0_sstables,
+                l1_sstables,
+            } => {
+                let mut runs = l0_runs(l0_sstables);
+                runs.push(ssts(l1_sstables));
+                runs
+            }
+            CompactionTask::Leveled(LeveledCompactionTask {
+                upper_level,
+                upper_level_sst_ids,
+                lower_level_sst_ids,
+                ..
+            })
+            | CompactionTask::Simple(SimpleLeveledCompactionTask {
+                upper_level,
+                upper_level_sst_ids,
+                lower_level_sst_ids,
+                ..
+            }) => {
+                let mut runs = match upper_level {
+                    Some(_) => vec![ssts(upper_level_sst_ids)],
+                    None => l0_runs(upper_level_sst_ids),
+                };
+                runs.push(ssts(lower_level_sst_ids));
+                runs
+            }
+            CompactionTask::Tiered(TieredCompactionTask { tiers, .. }) => {
+                tiers.iter().map(|(_, ids)| ssts(ids)).collect()
+            }
+        }
+    }
 }
 
 pub(crate) enum CompactionController {
@@ -63,6 +141,21 @@ pub(crate) enum CompactionController {
 }
 
 impl CompactionController {
+    pub fn new(options: &CompactionOptions) -> Self {
+        match options {
+            CompactionOptions::Leveled(options) => {
+                CompactionController::Leveled(LeveledCompactionController::new(options.clone()))
+            }
+            CompactionOptions::Tiered(options) => {
+                CompactionController::Tiered(TieredCompactionController::new(options.clone()))
+            }
+            CompactionOptions::Simple(options) => CompactionController::Simple(
+                SimpleLeveledCompactionController::new(options.clone()),
+            ),
+            CompactionOptions::NoCompaction => CompactionController::NoCompaction,
+        }
+    }
+
     pub fn generate_compaction_task(&self, snapshot: &LsmStorageState) -> Option<CompactionTask> {
         match self {
             CompactionController::Leveled(ctrl) => ctrl
@@ -78,6 +171,95 @@ impl CompactionController {
         }
     }
 
+    /// Generates a task compacting the SSTs of `level`, or L0 if `None`, that overlap the user key
+    /// range `lower..=upper` into the level below. Tiered compaction merges all tiers at L0
+    /// instead, and without a compaction strategy L0 and L1 are compacted into L1. Returns `None`
+    /// if there is nothing to compact.
+    pub fn generate_range_compaction_task(
+        &self,
+        snapshot: &LsmStorageState,
+        level: Option<usize>,
+        lower: Option<&[u8]>,
+        upper: Option<&[u8]>,
+    ) -> Option<CompactionTask> {
+        let overlapping = |ids: &[usize]| {
+            ids.iter()
+                .copied()
+                .filter(|id| overlaps_range(&snapshot.sstables[id], lower, upper))
+                .collect::<Vec<_>>()
+        };
+        let level_ssts = match level {
+            None => &snapshot.l0_sstables,
+            Some(level) => &snapshot.levels.get(level - 1)?.1,
+        };
+        match self {
+            CompactionController::Leveled(ctrl) => {
+                let sst_ids = overlapping(level_ssts);
+                if sst_ids.is_empty() {
+                    return None;
+                }
+                ctrl.generate_manual_compaction_task(snapshot, level, sst_ids)
+                    .map(CompactionTask::Leveled)
+            }
+            CompactionController::Simple(ctrl) => {
+                if overlapping(level_ssts).is_empty() {
+                    return None;
+                }
+                ctrl.generate_manual_compaction_task(snapshot, level)
+                    .map(CompactionTask::Simple)
+            }
+            CompactionController::Tiered(ctrl) => {
+                let overlaps = snapshot
+                    .levels
+                    .iter()
+                    .any(|(_, ids)| !overlapping(ids).is_empty());
+                if level.is_some() || !overlaps {
+                    return None;
+                }
+                ctrl.generate_manual_compaction_task(snapshot)
+                    .map(CompactionTask::Tiered)
+            }
+            CompactionController::NoCompaction => {
+                if level.is_some() {
+                    return None;
+                }
+                let l0_sstables = overlapping(&snapshot.l0_sstables);
+                let l1 = &snapshot.levels[0].1;
+                let picked = l1
+                    .iter()
+                    .map(|id| {
+                        let sst = &snapshot.sstables[id];
+                        overlaps_range(sst, lower, upper)
+                            || l0_sstables.iter().any(|l0_id| {
+                                let l0_sst = &snapshot.sstables[l0_id];
+                                l0_sst.num_of_blocks() > 0
+                                    && overlaps_range(
+                                        sst,
+                                        Some(l0_sst.first_key().key_ref()),
+                                        Some(l0_sst.last_key().key_ref()),
+                                    )
+                            })
+                    })
+                    .collect::<Vec<_>>();
+                // the SSTs in between are picked as well, as the output covers the whole span
+                let l1_sstables = match (
+                    picked.iter().position(|&p| p),
+                    picked.iter().rposition(|&p| p),
+                ) {
+                    (Some(first), Some(last)) => l1[first..=last].to_vec(),
+                    _ => Vec::new(),
+                };
+                if l0_sstables.is_empty() && l1_sstables.is_empty() {
+                    return None;
+                }
+                Some(CompactionTask::ForceFullCompaction {
+                    l0_sstables,
+                    l1_sstables,
+                })
+            }
+        }
+    }
+
     pub fn apply_compaction_result(
         &self,
         snapshot: &LsmStorageState,
@@ -95,6 +277,31 @@ impl CompactionController {
             (CompactionController::Tiered(ctrl), CompactionTask::Tiered(task)) => {
                 ctrl.apply_compaction_result(snapshot, task, output)
             }
+            (
+                CompactionController::NoCompaction,
+                CompactionTask::ForceFullCompaction {
+                    l0_sstables,
+                    l1_sstables,
+                },
+            ) => {
+                let mut snapshot = snapshot.clone();
+                snapshot.l0_sstables.retain(|id| !l0_sstables.contains(id));
+                // SSTs may have been ingested into L1 during the compaction
+                let l1 = &mut snapshot.levels[0].1;
+                l1.retain(|id| !l1_sstables.contains(id));
+                l1.extend_from_slice(output);
+                if !in_recovery {
+                    l1.sort_by(|a, b| {
+                        let (a, b) = (&snapshot.sstables[a], &snapshot.sstables[b]);
+                        a.comparator().compare_keys(
+                            a.first_key().as_key_slice(),
+                            b.first_key().as_key_slice(),
+                        )
+                    });
+                }
+                let files_to_remove = l0_sstables.iter().chain(l1_sstables).copied().collect();
+                (snapshot, files_to_remove)
+            }
             _ => unreachable!(),
         }
     }
@@ -122,45 +329,376 @@ pub enum CompactionOptions {
     NoCompaction,
 }
 
+/// Whether the keys or the range tombstones of `sst` may fall in the user key range
+/// `lower..=upper`, where `None` is unbounded.
+fn overlaps_range(sst: &SsTable, lower: Option<&[u8]>, upper: Option<&[u8]>) -> bool {
+    let comparator = sst.comparator();
+    let below_upper = |key: &[u8]| upper.is_none_or(|upper| comparator.compare(key, upper).is_le());
+    let above_lower = |key: &[u8]| lower.is_none_or(|lower| comparator.compare(key, lower).is_ge());
+    // range tombstones are only written with the bytewise order
+    (sst.num_of_blocks() > 0
+        && below_upper(sst.first_key().key_ref())
+        && above_lower(sst.last_key().key_ref()))
+        || sst.range_tombstones().iter().any(|tombstone| {
+            below_upper(&tombstone.start) && lower.is_none_or(|lower| &tombstone.end[..] > lower)
+        })
+}
+
+/// Pick the inputs of a compaction from level n into level n+1 starting from `level_n[chosen]`.
+///
+/// Returns the indices of the picked SSTs in `level_n` and `level_n1`. The picked set is expanded
+/// until it is closed: every SST in either level that overlaps the key range of the picked SSTs is
+/// picked as well, so the output of the compaction does not overlap any SST left behind.
+pub fn pick_overlapping(
+    level_n: &[Arc<SsTable>],
+    level_n1: &[Arc<SsTable>],
+    chosen: usize,
+) -> (Vec<usize>, Vec<usize>) {
+    let comparator = level_n[chosen].comparator();
+    let cmp =
+        |a: &KeyBytes, b: &KeyBytes| comparator.compare_keys(a.as_key_slice(), b.as_key_slice());
+    let mut first_key = level_n[chosen].first_key().clone();
+    let mut last_key = level_n[chosen].last_key().clone();
+    let mut picked_n = vec![false; level_n.len()];
+    let mut picked_n1 = vec![false; level_n1.len()];
+    picked_n[chosen] = true;
+    loop {
+        let mut expanded = false;
+        for (level, picked) in [(level_n1, &mut picked_n1), (level_n, &mut picked_n)] {
+            for (idx, sst) in level.iter().enumerate() {
+                if picked[idx]
+                    || cmp(sst.first_key(), &last_key).is_gt()
+                    || cmp(sst.last_key(), &first_key).is_lt()
+                {
+                    continue;
+                }
+                picked[idx] = true;
+                expanded = true;
+                if cmp(sst.first_key(), &first_key).is_lt() {
+                    first_key = sst.first_key().clone();
+                }
+                if cmp(sst.last_key(), &last_key).is_gt() {
+                    last_key = sst.last_key().clone();
+                }
+            }
+        }
+        if !expanded {
+            break;
+        }
+    }
+    let indices = |picked: Vec<bool>| {
+        picked
+            .into_iter()
+            .enumerate()
+            .filter_map(|(idx, picked)| picked.then_some(idx))
+            .collect()
+    };
+    (indices(picked_n), indices(picked_n1))
+}
+
 impl LsmStorageInner {
-    fn compact(&self, _task: &CompactionTask) -> Result<Vec<Arc<SsTable>>> {
-        unimplemented!()
+    /// Merge the inputs of `task` into new SSTs. The key range is split into up to
+    /// `max_subcompactions` subcompactions, run on up to `compaction_threads` threads, each
+    /// writing SSTs of its own, and the output is returned in key order.
+    fn compact(&self, task: &CompactionTask) -> Result<Vec<Arc<SsTable>>> {
+        // the value logs first, see `ValueLogs::snapshot`
+        let value_logs = self.value_logs.snapshot();
+        let snapshot = self.state.read().clone();
+        let runs = task.input_runs(&snapshot);
+        let inputs = runs.iter().flatten().cloned().collect::<Vec<_>>();
+        let range_tombstones = inputs
+            .iter()
+            .flat_map(|sst| sst.range_tombstones().iter().cloned())
+            .collect::<Vec<_>>();
+        // a leveled compaction may leave SSTs of the bottom level out, which the range tombstones
+        // may still cover
+        let partial = matches!(task, CompactionTask::Leveled(_));
+        let keep_tombstones =
+            !task.compact_to_bottom_level() || (partial && !range_tombstones.is_empty());
+        // the split points are ordered bytewise
+        let max_subcompactions = if self.options.comparator.is_bytewise() {
+            self.options.max_subcompactions
+        } else {
+            1
+        };
+        let subcompactions = Subcompaction::split(&inputs, max_subcompactions)?;
+        let watermark = self.mvcc().watermark();
+        let compaction_filters = self.compaction_filters();
+        let splitter = SstSplitter::new(self.options.target_sst_size).with_boundaries(
+            task.grandparent_boundaries(&snapshot),
+            self.options.comparator.clone(),
+        );
+
+        let next = AtomicUsize::new(0);
+        let outputs = subcompactions
+            .iter()
+            .map(|_| Mutex::new((Vec::new(), Ok(()))))
+            .collect::<Vec<_>>();
+        let threads = self
+            .options
+            .compaction_threads
+            .clamp(1, subcompactions.len());
+        std::thread::scope(|scope| {
+            for _ in 0..threads {
+                scope.spawn(|| {
+                    loop {
+                        let idx = next.fetch_add(1, Ordering::SeqCst);
+                        let Some(subcompaction) = subcompactions.get(idx) else {
+                            return;
+                        };
+                        let mut output = outputs[idx].lock();
+                        let (ssts, result) = &mut *output;
+                        *result = self.run_subcompaction(
+                            &runs,
+                            subcompaction,
+                            keep_tombstones,
+                            watermark,
+                            &range_tombstones,
+                            &compaction_filters,
+                            &splitter,
+                            &value_logs,
+                            ssts,
+                        );
+                        if result.is_err() {
+                            // skip the remaining subcompactions
+                            next.store(subcompactions.len(), Ordering::SeqCst);
+                        }
+                    }
+                });
+            }
+        });
+
+        let mut ssts = Vec::new();
+        let mut error = None;
+        for output in outputs {
+            let (output, result) = output.into_inner();
+            ssts.extend(output);
+            if let Err(e) = result {
+                error.get_or_insert(e);
+            }
+        }
+        if let Some(e) = error {
+            for sst in ssts {
+                std::fs::remove_file(self.path_of_sst(sst.sst_id())).ok();
+            }
+            return Err(e);
+        }
+        let bytes_written = ssts.iter().map(|sst| sst.file_size()).sum::<u64>();
+        self.metrics
+            .bytes_compacted
+            .fetch_add(bytes_written, Ordering::Relaxed);
+        Ok(ssts)
+    }
+
+    /// Merge the entries of `runs` in the key range of `subcompaction` into SSTs ended by
+    /// `splitter`, pushed to `output` as they are written. The range tombstones in the key range go
+    /// to the last SST.
+    #[allow(clippy::too_many_arguments)]
+    fn run_subcompaction(
+        &self,
+        runs: &[Vec<Arc<SsTable>>],
+        subcompaction: &Subcompaction,
+        keep_tombstones: bool,
+        watermark: u64,
+        range_tombstones: &[RangeTombstone],
+        compaction_filters: &[CompactionFilter],
+        splitter: &SstSplitter,
+        value_logs: &ValueLogSnapshot,
+        output: &mut Vec<Arc<SsTable>>,
+    ) -> Result<()> {
+        let lower = subcompaction
+            .lower
+            .as_deref()
+            .map_or(Bound::Unbounded, Bound::Included);
+        let mut iters = Vec::with_capacity(runs.len());
+        for run in runs {
+            // an SST may hold range tombstones only
+            let ssts = run
+                .iter()
+                .filter(|sst| sst.num_of_blocks() > 0)
+                .cloned()
+                .collect();
+            // the inputs are read sequentially, so they are read ahead like scans
+            iters.push(Box::new(
+                SstConcatIterator::create_and_seek_to_range(ssts, lower)?
+                    .with_readahead(self.options.readahead_bytes),
+            ));
+        }
+        let mut iter = BoundedIterator::new(
+            MergeIterator::create(iters).with_comparator(self.options.comparator.clone()),
+            subcompaction.upper.clone(),
+        );
+        let range_tombstones = range_tombstones
+            .iter()
+            .filter_map(|tombstone| subcompaction.clip(tombstone))
+            .collect::<Vec<_>>();
+        loop {
+            let mut builder = self.new_sst_builder();
+            if let Some(operator) = &self.options.merge_operator {
+                builder = builder.with_merge_operator(operator.clone(), value_logs.clone());
+            }
+            builder.add_from_iter_until(
+                &mut iter,
+                keep_tombstones,
+                watermark,
+                &range_tombstones,
+                compaction_filters,
+                splitter,
+            )?;
+            let done = !iter.is_valid();
+            if !builder.is_empty() {
+                let sst_id = self.next_sst_id();
+                output.push(Arc::new(self.build_sst(
+                    builder,
+                    sst_id,
+                    IoPriority::Low,
+                )?));
+            }
+            if done {
+                return Ok(());
+            }
+        }
     }
 
+    /// Compact the SSTs that overlap the user key range `lower..=upper`, where `None` is
+    /// unbounded, level by level down to the bottom level, and wait for it to finish. Tiered
+    /// compaction merges all tiers instead. The memtables are not flushed.
+    pub fn compact_range(&self, lower: Option<&[u8]>, upper: Option<&[u8]>) -> Result<()> {
+        self.check_writable()?;
+        let _compaction_lock = self.compaction_lock.lock();
+        let num_levels = self.state.read().levels.len();
+        for level in std::iter::once(None).chain((1..num_levels).map(Some)) {
+            let task = {
+                let snapshot = self.state.read();
+                self.compaction_controller
+                    .generate_range_compaction_task(&snapshot, level, lower, upper)
+            };
+            if let Some(task) = task {
+                self.run_compaction_task(task)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Compact all SSTs down to the bottom level and wait for it to finish, see `compact_range`.
     pub fn force_full_compaction(&self) -> Result<()> {
-        unimplemented!()
+        self.compact_range(None, None)
+    }
+
+    /// Compact each SST above the bottom level whose fraction of deletes exceeds `threshold`, see
+    /// `TableProperties::deleted_fraction`, down to the bottom level together with the SSTs
+    /// overlapping it, see `compact_range`, so that the space of the tombstones and of the
+    /// versions they delete is reclaimed. Returns the number of SSTs selected.
+    pub fn gc_compact(&self, threshold: f64) -> Result<usize> {
+        let candidates = {
+            let snapshot = self.state.read();
+            let bottom_level = snapshot.levels.last().map(|(_, ssts)| ssts.as_slice());
+            let mut candidates = snapshot
+                .sstables
+                .values()
+                .filter(|sst| !bottom_level.unwrap_or_default().contains(&sst.sst_id()))
+                .filter(|sst| {
+                    sst.properties()
+                        .is_some_and(|properties| properties.deleted_fraction() > threshold)
+                })
+                .map(|sst| {
+                    (
+                        sst.sst_id(),
+                        sst.first_key().clone(),
+                        sst.last_key().clone(),
+                    )
+                })
+                .collect::<Vec<_>>();
+            candidates.sort_by(|(_, a, _), (_, b, _)| a.cmp(b));
+            candidates
+        };
+        for (sst_id, first_key, last_key) in &candidates {
+            // an earlier candidate may have compacted this one already
+            if !self.state.read().sstables.contains_key(sst_id) {
+                continue;
+            }
+            self.compact_range(Some(first_key.key_ref()), Some(last_key.key_ref()))?;
+        }
+        Ok(candidates.len())
     }
 
     fn trigger_compaction(&self) -> Result<()> {
-        unimplemented!()
+        // the column families are compacted by the compaction thread of the storage
+        for cf in self.column_families() {
+            cf.inner.trigger_compaction()?;
+        }
+        if let CompactionOptions::NoCompaction = self.options.compaction_options {
+            return Ok(());
+        }
+        let _compaction_lock = self.compaction_lock.lock();
+        let snapshot = self.state.read().clone();
+        let Some(task) = self
+            .compaction_controller
+            .generate_compaction_task(&snapshot)
+        else {
+            return Ok(());
+        };
+        self.run_compaction_task(task)
+    }
+
+    /// Run `task` and install its output: the new SSTs replace the inputs in a single update of
+    /// the state, which is then recorded in the manifest, and the inputs are removed afterwards.
+    fn run_compaction_task(&self, task: CompactionTask) -> Result<()> {
+        let ssts = self.compact(&task)?;
+        let output = ssts.iter().map(|sst| sst.sst_id()).collect::<Vec<_>>();
+        let removed = {
+            let state_lock = self.state_lock.lock();
+            let mut snapshot = self.state.read().as_ref().clone();
+            for sst in ssts {
+                let existing = snapshot.sstables.insert(sst.sst_id(), sst);
+                assert!(existing.is_none());
+            }
+            let (mut snapshot, files_to_remove) = self
+                .compaction_controller
+                .apply_compaction_result(&snapshot, &task, &output, false);
+            let mut removed = Vec::with_capacity(files_to_remove.len());
+            for sst_id in files_to_remove {
+                let sst = snapshot.sstables.remove(&sst_id);
+                removed.push(sst.unwrap_or_else(|| panic!("cannot remove {}.sst", sst_id)));
+            }
+            *self.state.write() = Arc::new(snapshot);
+            self.sync_dir()?;
+            self.add_manifest_record(&state_lock, ManifestRecord::Compaction(task, output))?;
+            removed
+        };
+        for sst in removed {
+            self.block_cache.invalidate_sst(sst.sst_id());
+            std::fs::remove_file(self.path_of_sst(sst.sst_id()))?;
+        }
+        Ok(())
     }
 
     pub(crate) fn spawn_compaction_thread(
         self: &Arc<Self>,
         rx: crossbeam_channel::Receiver<()>,
     ) -> Result<Option<std::thread::JoinHandle<()>>> {
-        if let CompactionOptions::Leveled(_)
-        | CompactionOptions::Simple(_)
-        | CompactionOptions::Tiered(_) = self.options.compaction_options
-        {
-            let this = self.clone();
-            let handle = std::thread::spawn(move || {
-                let ticker = crossbeam_channel::tick(Duration::from_millis(50));
-                loop {
-                    crossbeam_channel::select! {
-                        recv(ticker) -> _ => if let Err(e) = this.trigger_compaction() {
-                            eprintln!("compaction failed: {}", e);
-                        },
-                        recv(rx) -> _ => return
-                    }
+        // also started without a compaction strategy, for the column families created later
+        let this = self.clone();
+        let handle = std::thread::spawn(move || {
+            let ticker = crossbeam_channel::tick(Duration::from_millis(50));
+            loop {
+                crossbeam_channel::select! {
+                    recv(ticker) -> _ => if let Err(e) = this.trigger_compaction() {
+                        eprintln!("compaction failed: {}", e);
+                    },
+                    recv(rx) -> _ => return
                 }
-            });
-            return Ok(Some(handle));
-        }
-        Ok(None)
+            }
+        });
+        Ok(Some(handle))
     }
 
+    /// Flush the immutable memtables, oldest first, while there are at least `num_memtable_limit`
+    /// of them.
     fn trigger_flush(&self) -> Result<()> {
+        while self.state.read().imm_memtables.len() >= self.options.num_memtable_limit {
+            self.force_flush_next_imm_memtable()?;
+        }
         Ok(())
     }
 
@@ -12,9 +12,20 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::HashSet;
+
 use serde::{Deserialize, Serialize};
 
 use crate::lsm_storage::LsmStorageState;
+use crate::table::{SsTable, TableProperties};
+
+/// Weight of the fraction of deletes among the entries of a level in its score, so that a level
+/// dense in tombstones is compacted towards the bottom level, where they are dropped, before it
+/// grows over its target size.
+const DELETION_WEIGHT: f64 = 2.0;
+/// Weight of the age of an SST when picking the SST of a level to compact: the oldest SST of the
+/// level gets this much on top of its fraction of deletes, the newest one nothing.
+const AGE_WEIGHT: f64 = 0.5;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct LeveledCompactionTask {
@@ -43,22 +54,237 @@ impl LeveledCompactionController {
         Self { options }
     }
 
+    /// Find the SSTs in `in_level` whose key ranges overlap the key range covered by `sst_ids`.
     fn find_overlapping_ssts(
         &self,
         _snapshot: &LsmStorageState,
         _sst_ids: &[usize],
         _in_level: usize,
     ) -> Vec<usize> {
-        unimplemented!()
+        let comparator = _snapshot.sstables[&_sst_ids[0]].comparator();
+        let first_key = _sst_ids
+            .iter()
+            .map(|id| _snapshot.sstables[id].first_key().as_key_slice())
+            .min_by(|a, b| comparator.compare_keys(*a, *b))
+            .unwrap();
+        let last_key = _sst_ids
+            .iter()
+            .map(|id| _snapshot.sstables[id].last_key().as_key_slice())
+            .max_by(|a, b| comparator.compare_keys(*a, *b))
+            .unwrap();
+        _snapshot.levels[_in_level - 1]
+            .1
+            .iter()
+            .filter(|id| {
+                let sst = &_snapshot.sstables[*id];
+                comparator
+                    .compare_keys(sst.first_key().as_key_slice(), last_key)
+                    .is_le()
+                    && comparator
+                        .compare_keys(sst.last_key().as_key_slice(), first_key)
+                        .is_ge()
+            })
+            .copied()
+            .collect()
+    }
+
+    /// The target size in bytes of each level, from L1 to L_max, and the base level that L0 is
+    /// compacted into.
+    ///
+    /// The bottom level targets its actual size, but at least `base_level_size_mb`. Each upper
+    /// level targets `1 / level_size_multiplier` of the level below it, as long as the level below
+    /// is larger than `base_level_size_mb`; the levels above are empty, with a target of 0. The
+    /// base level is the uppermost level with a non-zero target.
+    fn target_level_sizes(&self, real_level_sizes: &[u64]) -> (Vec<u64>, usize) {
+        let max_levels = self.options.max_levels;
+        let base_level_size = self.options.base_level_size_mb as u64 * 1024 * 1024;
+        let mut target_level_sizes = vec![0; max_levels];
+        target_level_sizes[max_levels - 1] = real_level_sizes[max_levels - 1].max(base_level_size);
+        let mut base_level = max_levels;
+        for level in (0..max_levels - 1).rev() {
+            let next_level_size = target_level_sizes[level + 1];
+            if next_level_size > base_level_size {
+                target_level_sizes[level] =
+                    next_level_size / self.options.level_size_multiplier as u64;
+            }
+            if target_level_sizes[level] > 0 {
+                base_level = level + 1;
+            }
+        }
+        (target_level_sizes, base_level)
+    }
+
+    fn real_level_sizes(snapshot: &LsmStorageState) -> Vec<u64> {
+        snapshot
+            .levels
+            .iter()
+            .map(|(_, ssts)| {
+                ssts.iter()
+                    .map(|id| snapshot.sstables[id].table_size())
+                    .sum::<u64>()
+            })
+            .collect()
+    }
+
+    /// Generates a task compacting `sst_ids` of `upper_level`, or L0 if `None`, into the level
+    /// below it, which is the base level for L0. Returns `None` for the bottom level.
+    pub fn generate_manual_compaction_task(
+        &self,
+        snapshot: &LsmStorageState,
+        upper_level: Option<usize>,
+        sst_ids: Vec<usize>,
+    ) -> Option<LeveledCompactionTask> {
+        let max_levels = self.options.max_levels;
+        let lower_level = match upper_level {
+            None => self.target_level_sizes(&Self::real_level_sizes(snapshot)).1,
+            Some(level) if level < max_levels => level + 1,
+            Some(_) => return None,
+        };
+        Some(LeveledCompactionTask {
+            upper_level,
+            lower_level_sst_ids: self.find_overlapping_ssts(snapshot, &sst_ids, lower_level),
+            upper_level_sst_ids: sst_ids,
+            lower_level,
+            is_lower_level_bottom_level: lower_level == max_levels,
+        })
+    }
+
+    /// The deletes, i.e., tombstones and range tombstones, and the entries of the SST, from its
+    /// properties. An SST without properties counts as having no deletes.
+    fn deletes_and_entries(sst: &SsTable) -> (u64, u64) {
+        sst.properties().map_or((0, 0), |properties| {
+            (
+                properties.num_deletions + properties.num_range_tombstones,
+                properties.num_entries + properties.num_range_tombstones,
+            )
+        })
     }
 
+    /// The fraction of deletes among the entries of `ssts`, 0 without entries.
+    fn deletion_density<'a>(ssts: impl Iterator<Item = &'a SsTable>) -> f64 {
+        let (deletes, entries) = ssts
+            .map(Self::deletes_and_entries)
+            .fold((0, 0), |(d, e), (deletes, entries)| {
+                (d + deletes, e + entries)
+            });
+        if entries == 0 {
+            return 0.0;
+        }
+        deletes as f64 / entries as f64
+    }
+
+    /// The compaction score of L0 and each level, indexed by level. L0 scores its number of SSTs
+    /// over `level0_file_num_compaction_trigger`, and is due for compaction at 1. A level scores its
+    /// size over its target size, plus `DELETION_WEIGHT` times its fraction of deletes, and is due
+    /// above 1. The bottom level and the levels above the base level score 0.
+    pub fn level_scores(&self, snapshot: &LsmStorageState) -> Vec<f64> {
+        let max_levels = self.options.max_levels;
+        let real_level_sizes = Self::real_level_sizes(snapshot);
+        let (target_level_sizes, _) = self.target_level_sizes(&real_level_sizes);
+        let mut scores = vec![
+            snapshot.l0_sstables.len() as f64
+                / self.options.level0_file_num_compaction_trigger.max(1) as f64,
+        ];
+        for level in 0..max_levels {
+            // the bottom level cannot be compacted further
+            if level == max_levels - 1 || target_level_sizes[level] == 0 {
+                scores.push(0.0);
+                continue;
+            }
+            let density = Self::deletion_density(
+                snapshot.levels[level]
+                    .1
+                    .iter()
+                    .map(|id| snapshot.sstables[id].as_ref()),
+            );
+            scores.push(
+                real_level_sizes[level] as f64 / target_level_sizes[level] as f64
+                    + DELETION_WEIGHT * density,
+            );
+        }
+        scores
+    }
+
+    /// The SST of `level` to compact: the one with the largest fraction of deletes, plus
+    /// `AGE_WEIGHT` scaled by its age among the SSTs of the level.
+    fn pick_sst(snapshot: &LsmStorageState, level: usize) -> Option<usize> {
+        let mut ssts = snapshot.levels[level - 1].1.clone();
+        // SST ids grow over time, the smallest one is the oldest SST in the level
+        ssts.sort_unstable();
+        let oldest_rank = ssts.len().saturating_sub(1).max(1) as f64;
+        ssts.iter()
+            .enumerate()
+            .map(|(rank, &id)| {
+                let age = 1.0 - rank as f64 / oldest_rank;
+                let density = snapshot.sstables[&id]
+                    .properties()
+                    .map_or(0.0, TableProperties::deleted_fraction);
+                (id, density + AGE_WEIGHT * age)
+            })
+            // the oldest of equally scored SSTs
+            .rev()
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(id, _)| id)
+    }
+
+    /// Generates a compaction task.
+    ///
+    /// The due level with the highest score, see `level_scores`, is compacted into the level
+    /// below it: all of L0 into the base level, or the SST picked by `pick_sst` of another level.
+    /// Returns `None` if no level is due.
     pub fn generate_compaction_task(
         &self,
         _snapshot: &LsmStorageState,
     ) -> Option<LeveledCompactionTask> {
-        unimplemented!()
+        let max_levels = self.options.max_levels;
+        let base_level = self
+            .target_level_sizes(&Self::real_level_sizes(_snapshot))
+            .1;
+        let (level, _) = self
+            .level_scores(_snapshot)
+            .into_iter()
+            .enumerate()
+            .filter(|&(level, score)| {
+                if level == 0 {
+                    score >= 1.0
+                } else {
+                    score > 1.0
+                }
+            })
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))?;
+
+        if level == 0 {
+            return Some(LeveledCompactionTask {
+                upper_level: None,
+                upper_level_sst_ids: _snapshot.l0_sstables.clone(),
+                lower_level: base_level,
+                lower_level_sst_ids: self.find_overlapping_ssts(
+                    _snapshot,
+                    &_snapshot.l0_sstables,
+                    base_level,
+                ),
+                is_lower_level_bottom_level: base_level == max_levels,
+            });
+        }
+
+        let selected_sst = Self::pick_sst(_snapshot, level)?;
+        Some(LeveledCompactionTask {
+            upper_level: Some(level),
+            upper_level_sst_ids: vec![selected_sst],
+            lower_level: level + 1,
+            lower_level_sst_ids: self.find_overlapping_ssts(_snapshot, &[selected_sst], level + 1),
+            is_lower_level_bottom_level: level + 1 == max_levels,
+        })
     }
 
+    /// Apply the compaction result.
+    ///
+    /// Removes the compacted SSTs from the upper level, or L0, and the lower level, and adds the
+    /// output SSTs to the lower level, keeping it sorted by first key. SSTs flushed to L0 while the
+    /// compaction was running are kept. The output SSTs must already be in `sstables` of the
+    /// snapshot, except during recovery, where the SST objects are not loaded yet and the lower
+    /// level is sorted later by the caller. Returns the new state and the ids of the SSTs
+    /// to be removed.
     pub fn apply_compaction_result(
         &self,
         _snapshot: &LsmStorageState,
@@ -66,6 +292,53 @@ impl LeveledCompactionController {
         _output: &[usize],
         _in_recovery: bool,
     ) -> (LsmStorageState, Vec<usize>) {
-        unimplemented!()
+        let mut snapshot = _snapshot.clone();
+        let upper_level_sst_ids = _task
+            .upper_level_sst_ids
+            .iter()
+            .copied()
+            .collect::<HashSet<_>>();
+        let lower_level_sst_ids = _task
+            .lower_level_sst_ids
+            .iter()
+            .copied()
+            .collect::<HashSet<_>>();
+
+        let upper_level = match _task.upper_level {
+            Some(level) => &mut snapshot.levels[level - 1].1,
+            None => &mut snapshot.l0_sstables,
+        };
+        let upper_level_len = upper_level.len();
+        upper_level.retain(|id| !upper_level_sst_ids.contains(id));
+        assert_eq!(
+            upper_level_len - upper_level.len(),
+            upper_level_sst_ids.len(),
+            "compacted SSTs are missing from the upper level"
+        );
+
+        let lower_level = &mut snapshot.levels[_task.lower_level - 1].1;
+        let lower_level_len = lower_level.len();
+        lower_level.retain(|id| !lower_level_sst_ids.contains(id));
+        assert_eq!(
+            lower_level_len - lower_level.len(),
+            lower_level_sst_ids.len(),
+            "compacted SSTs are missing from the lower level"
+        );
+        lower_level.extend_from_slice(_output);
+        if !_in_recovery {
+            lower_level.sort_by(|a, b| {
+                let (a, b) = (&_snapshot.sstables[a], &_snapshot.sstables[b]);
+                a.comparator()
+                    .compare_keys(a.first_key().as_key_slice(), b.first_key().as_key_slice())
+            });
+        }
+
+        let files_to_remove = _task
+            .upper_level_sst_ids
+            .iter()
+            .chain(&_task.lower_level_sst_ids)
+            .copied()
+            .collect();
+        (snapshot, files_to_remove)
     }
 }
@@ -42,29 +42,111 @@ impl SimpleLeveledCompactionController {
         Self { options }
     }
 
+    /// Generates a task compacting all SSTs of `upper_level`, or L0 if `None`, into the level
+    /// below it. Returns `None` for the bottom level.
+    pub fn generate_manual_compaction_task(
+        &self,
+        snapshot: &LsmStorageState,
+        upper_level: Option<usize>,
+    ) -> Option<SimpleLeveledCompactionTask> {
+        let (upper_level_sst_ids, lower_level) = match upper_level {
+            None => (snapshot.l0_sstables.clone(), 1),
+            Some(level) if level < self.options.max_levels => {
+                (snapshot.levels[level - 1].1.clone(), level + 1)
+            }
+            Some(_) => return None,
+        };
+        Some(SimpleLeveledCompactionTask {
+            upper_level,
+            upper_level_sst_ids,
+            lower_level,
+            lower_level_sst_ids: snapshot.levels[lower_level - 1].1.clone(),
+            is_lower_level_bottom_level: lower_level == self.options.max_levels,
+        })
+    }
+
     /// Generates a compaction task.
     ///
+    /// L0 is compacted into L1 once it holds `level0_file_num_compaction_trigger` SSTs. Otherwise, a
+    /// level is compacted into the level below once the number of SSTs of the lower level is less
+    /// than `size_ratio_percent` percent of its own, starting from the top.
+    ///
     /// Returns `None` if no compaction needs to be scheduled. The order of SSTs in the compaction task id vector matters.
     pub fn generate_compaction_task(
         &self,
-        _snapshot: &LsmStorageState,
+        snapshot: &LsmStorageState,
     ) -> Option<SimpleLeveledCompactionTask> {
-        unimplemented!()
+        if self.options.max_levels == 0 {
+            return None;
+        }
+        if !snapshot.l0_sstables.is_empty()
+            && snapshot.l0_sstables.len() >= self.options.level0_file_num_compaction_trigger
+        {
+            return self.generate_manual_compaction_task(snapshot, None);
+        }
+        (1..self.options.max_levels)
+            .find(|&level| {
+                let upper_size = snapshot.levels[level - 1].1.len();
+                let lower_size = snapshot.levels[level].1.len();
+                upper_size > 0 && lower_size * 100 < upper_size * self.options.size_ratio_percent
+            })
+            .and_then(|level| self.generate_manual_compaction_task(snapshot, Some(level)))
     }
 
     /// Apply the compaction result.
     ///
-    /// The compactor will call this function with the compaction task and the list of SST ids generated. This function applies the
-    /// result and generates a new LSM state. The functions should only change `l0_sstables` and `levels` without changing memtables
-    /// and `sstables` hash map. Though there should only be one thread running compaction jobs, you should think about the case
-    /// where an L0 SST gets flushed while the compactor generates new SSTs, and with that in mind, you should do some sanity checks
-    /// in your implementation.
+    /// Removes the compacted SSTs from the upper level, or L0, and the lower level, and adds the
+    /// output SSTs to the lower level. SSTs flushed to L0 while the compaction was running are
+    /// kept, and so are SSTs ingested into the lower level, which the output SSTs are sorted in
+    /// with by first key. During recovery, the SST objects are not loaded yet, and the caller sorts
+    /// the levels later. Returns the new state and the ids of the SSTs to be removed.
     pub fn apply_compaction_result(
         &self,
-        _snapshot: &LsmStorageState,
-        _task: &SimpleLeveledCompactionTask,
-        _output: &[usize],
+        snapshot: &LsmStorageState,
+        task: &SimpleLeveledCompactionTask,
+        output: &[usize],
     ) -> (LsmStorageState, Vec<usize>) {
-        unimplemented!()
+        let mut new_snapshot = snapshot.clone();
+        let upper_level = match task.upper_level {
+            Some(level) => &mut new_snapshot.levels[level - 1].1,
+            None => &mut new_snapshot.l0_sstables,
+        };
+        let upper_level_len = upper_level.len();
+        upper_level.retain(|id| !task.upper_level_sst_ids.contains(id));
+        assert_eq!(
+            upper_level_len - upper_level.len(),
+            task.upper_level_sst_ids.len(),
+            "compacted SSTs are missing from the upper level"
+        );
+
+        let lower_level = &mut new_snapshot.levels[task.lower_level - 1].1;
+        let lower_level_len = lower_level.len();
+        lower_level.retain(|id| !task.lower_level_sst_ids.contains(id));
+        assert_eq!(
+            lower_level_len - lower_level.len(),
+            task.lower_level_sst_ids.len(),
+            "compacted SSTs are missing from the lower level"
+        );
+        let ingested = !lower_level.is_empty();
+        lower_level.extend_from_slice(output);
+        if ingested
+            && lower_level
+                .iter()
+                .all(|id| snapshot.sstables.contains_key(id))
+        {
+            lower_level.sort_by(|a, b| {
+                let (a, b) = (&snapshot.sstables[a], &snapshot.sstables[b]);
+                a.comparator()
+                    .compare_keys(a.first_key().as_key_slice(), b.first_key().as_key_slice())
+            });
+        }
+
+        let files_to_remove = task
+            .upper_level_sst_ids
+            .iter()
+            .chain(&task.lower_level_sst_ids)
+            .copied()
+            .collect();
+        (new_snapshot, files_to_remove)
     }
 }
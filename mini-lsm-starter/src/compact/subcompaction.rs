@@ -0,0 +1,137 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use bytes::Bytes;
+
+use crate::iterators::StorageIterator;
+use crate::key::KeySlice;
+use crate::range_tombstone::RangeTombstone;
+use crate::table::SsTable;
+
+/// A part of the user key range of a compaction, from `lower` inclusive to `upper` exclusive, where
+/// `None` is unbounded. Every version of a user key is in the same subcompaction, as garbage
+/// collection looks at all versions of a key together.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Subcompaction {
+    pub lower: Option<Bytes>,
+    pub upper: Option<Bytes>,
+}
+
+impl Subcompaction {
+    /// Split the key range of `inputs` into at most `max_subcompactions` subcompactions holding
+    /// about the same number of bytes, using the first keys of the data blocks as split points.
+    pub fn split(inputs: &[Arc<SsTable>], max_subcompactions: usize) -> Result<Vec<Self>> {
+        let mut points = Vec::new();
+        if max_subcompactions > 1 {
+            for sst in inputs {
+                for block_idx in 0..sst.num_of_blocks() {
+                    let meta = sst.block_meta_at(block_idx)?;
+                    points.push((meta.first_key.key_ref().to_vec(), meta.len as u64));
+                }
+            }
+        }
+        points.sort();
+        let total = points.iter().map(|(_, len)| len).sum::<u64>();
+        let target = total.div_ceil(max_subcompactions.max(1) as u64);
+        let mut splits: Vec<Bytes> = Vec::new();
+        let mut bytes = 0;
+        for (key, len) in &points {
+            if bytes >= target * (splits.len() as u64 + 1)
+                && splits.len() + 1 < max_subcompactions
+                && splits.last().is_none_or(|last| last[..] < key[..])
+                && &points[0].0 < key
+            {
+                splits.push(Bytes::copy_from_slice(key));
+            }
+            bytes += len;
+        }
+
+        let lowers = std::iter::once(None).chain(splits.iter().cloned().map(Some));
+        let uppers = splits
+            .iter()
+            .cloned()
+            .map(Some)
+            .chain(std::iter::once(None));
+        Ok(lowers
+            .zip(uppers)
+            .map(|(lower, upper)| Self { lower, upper })
+            .collect())
+    }
+
+    pub fn contains(&self, key: &[u8]) -> bool {
+        self.lower.as_ref().is_none_or(|lower| &lower[..] <= key)
+            && self.upper.as_ref().is_none_or(|upper| key < &upper[..])
+    }
+
+    /// The part of `tombstone` in the range, if any.
+    pub fn clip(&self, tombstone: &RangeTombstone) -> Option<RangeTombstone> {
+        let start = match &self.lower {
+            Some(lower) if lower > &tombstone.start => lower,
+            _ => &tombstone.start,
+        };
+        let end = match &self.upper {
+            Some(upper) if upper < &tombstone.end => upper,
+            _ => &tombstone.end,
+        };
+        (start < end).then(|| RangeTombstone::new(start, end, tombstone.ts))
+    }
+}
+
+/// Iterates over the entries of `iter` whose user keys are below `upper`.
+pub struct BoundedIterator<I> {
+    iter: I,
+    upper: Option<Bytes>,
+}
+
+impl<I> BoundedIterator<I> {
+    pub fn new(iter: I, upper: Option<Bytes>) -> Self {
+        Self { iter, upper }
+    }
+}
+
+impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> StorageIterator
+    for BoundedIterator<I>
+{
+    type KeyType<'a>
+        = KeySlice<'a>
+    where
+        Self: 'a;
+
+    fn value(&self) -> &[u8] {
+        self.iter.value()
+    }
+
+    fn key(&self) -> KeySlice<'_> {
+        self.iter.key()
+    }
+
+    fn is_valid(&self) -> bool {
+        self.iter.is_valid()
+            && self
+                .upper
+                .as_ref()
+                .is_none_or(|upper| self.iter.key().key_ref() < &upper[..])
+    }
+
+    fn next(&mut self) -> Result<()> {
+        self.iter.next()
+    }
+
+    fn num_active_iterators(&self) -> usize {
+        self.iter.num_active_iterators()
+    }
+}
@@ -12,6 +12,8 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::HashMap;
+
 use serde::{Deserialize, Serialize};
 
 use crate::lsm_storage::LsmStorageState;
@@ -40,19 +42,133 @@ impl TieredCompactionController {
         Self { options }
     }
 
+    /// Generates a compaction task once there are at least `num_tiers` tiers, sizing tiers by
+    /// their number of SSTs. Tiers are ordered from the latest to the earliest, and a task always
+    /// merges a run of the latest tiers. In order of priority:
+    ///
+    /// * Space amplification: all tiers are merged when the tiers above the bottom tier hold at
+    ///   least `max_size_amplification_percent` of the bottom tier.
+    /// * Size ratio: the latest tiers are merged down to the first tier whose preceding tiers hold
+    ///   more than `(100 + size_ratio)%` of it, if that merges at least `min_merge_width` tiers.
+    /// * Tier count: the latest tiers are merged so that the number of tiers drops below
+    ///   `num_tiers`.
+    ///
+    /// No task merges more than `max_merge_width` tiers, except for the full compaction on space
+    /// amplification.
     pub fn generate_compaction_task(
         &self,
         _snapshot: &LsmStorageState,
     ) -> Option<TieredCompactionTask> {
-        unimplemented!()
+        assert!(
+            _snapshot.l0_sstables.is_empty(),
+            "should not add l0 ssts in tiered compaction"
+        );
+        let levels = &_snapshot.levels;
+        if levels.len() < self.options.num_tiers {
+            return None;
+        }
+
+        let upper_size = levels[..levels.len() - 1]
+            .iter()
+            .map(|(_, ssts)| ssts.len())
+            .sum::<usize>();
+        let bottom_size = levels.last().unwrap().1.len();
+        if upper_size * 100 >= self.options.max_size_amplification_percent * bottom_size {
+            return Some(TieredCompactionTask {
+                tiers: levels.clone(),
+                bottom_tier_included: true,
+            });
+        }
+
+        let max_merge_width = self.options.max_merge_width.unwrap_or(usize::MAX);
+        let size_ratio_trigger = (100.0 + self.options.size_ratio as f64) / 100.0;
+        let mut upper_size = 0;
+        for idx in 1..levels.len() {
+            upper_size += levels[idx - 1].1.len();
+            let num_tiers = idx + 1;
+            if upper_size as f64 / levels[idx].1.len() as f64 > size_ratio_trigger
+                && num_tiers >= self.options.min_merge_width
+            {
+                return Some(self.merge_latest_tiers(_snapshot, num_tiers.min(max_merge_width)));
+            }
+        }
+
+        let num_tiers = (levels.len() + 2)
+            .saturating_sub(self.options.num_tiers)
+            .min(levels.len())
+            .min(max_merge_width);
+        (num_tiers >= 2).then(|| self.merge_latest_tiers(_snapshot, num_tiers))
+    }
+
+    /// Generates a task merging all tiers into one, as every tier covers the whole key range.
+    /// Returns `None` if there is no tier.
+    pub fn generate_manual_compaction_task(
+        &self,
+        snapshot: &LsmStorageState,
+    ) -> Option<TieredCompactionTask> {
+        (!snapshot.levels.is_empty()).then(|| TieredCompactionTask {
+            tiers: snapshot.levels.clone(),
+            bottom_tier_included: true,
+        })
+    }
+
+    fn merge_latest_tiers(
+        &self,
+        snapshot: &LsmStorageState,
+        num_tiers: usize,
+    ) -> TieredCompactionTask {
+        TieredCompactionTask {
+            tiers: snapshot.levels[..num_tiers].to_vec(),
+            bottom_tier_included: num_tiers == snapshot.levels.len(),
+        }
     }
 
+    /// Apply the compaction result.
+    ///
+    /// Replaces the merged tiers with a single tier holding the output SSTs, identified by the id
+    /// of its first SST, at the position of the merged tiers. Tiers flushed while the compaction
+    /// was running stay above it. Returns the new state and the ids of the SSTs to be removed.
     pub fn apply_compaction_result(
         &self,
         _snapshot: &LsmStorageState,
         _task: &TieredCompactionTask,
         _output: &[usize],
     ) -> (LsmStorageState, Vec<usize>) {
-        unimplemented!()
+        let mut snapshot = _snapshot.clone();
+        let merged_tiers = _task
+            .tiers
+            .iter()
+            .map(|(tier_id, ssts)| (*tier_id, ssts))
+            .collect::<HashMap<_, _>>();
+        let position = snapshot
+            .levels
+            .iter()
+            .position(|(tier_id, _)| merged_tiers.contains_key(tier_id))
+            .expect("merged tiers are missing from the state");
+        let mut files_to_remove = Vec::new();
+        snapshot.levels.retain(|(tier_id, ssts)| {
+            let Some(merged_ssts) = merged_tiers.get(tier_id) else {
+                return true;
+            };
+            assert_eq!(
+                *merged_ssts, ssts,
+                "tier {} changed during compaction",
+                tier_id
+            );
+            files_to_remove.extend_from_slice(ssts);
+            false
+        });
+        assert_eq!(
+            snapshot.levels.len() + merged_tiers.len(),
+            _snapshot.levels.len(),
+            "merged tiers are missing from the state"
+        );
+        // everything may have been deleted, leaving no tier
+        if let Some(&tier_id) = _output.first() {
+            snapshot
+                .levels
+                .insert(position, (tier_id, _output.to_vec()));
+        }
+        (snapshot, files_to_remove)
     }
 }
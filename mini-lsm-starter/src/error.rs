@@ -0,0 +1,38 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/// Errors that callers may want to tell apart. Functions of this crate return `anyhow::Result`;
+/// use `anyhow::Error::downcast_ref::<Error>` to match on these.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// An iterator is moved after it reaches its end (or its start when moving backward).
+    #[error("iterator is exhausted")]
+    IteratorExhausted,
+    /// The data read from the disk is malformed or fails its checksum.
+    #[error("data corruption: {detail}")]
+    Corruption { detail: String },
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    /// A write to a storage opened read-only.
+    #[error("the storage is opened read-only")]
+    ReadOnly,
+}
+
+impl Error {
+    pub fn corruption(detail: impl Into<String>) -> Self {
+        Error::Corruption {
+            detail: detail.into(),
+        }
+    }
+}
@@ -0,0 +1,141 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Snapshots of the data of a storage as a bundle of SSTs, to seed another storage with, e.g., a
+//! replica. Unlike a checkpoint, a bundle is not a storage directory: it holds the latest visible
+//! value of each key as of one timestamp, with the tombstones, the merge operands and the value
+//! log pointers resolved, and is loaded with `import_snapshot` through `ingest_sst`.
+
+use std::fs::File;
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+
+use crate::iterators::StorageIterator;
+use crate::key::KeySlice;
+use crate::lsm_storage::LsmStorageInner;
+
+/// Name of the descriptor of a bundle in its directory.
+pub const SNAPSHOT_DESCRIPTOR: &str = "SNAPSHOT";
+
+/// The descriptor of a bundle written by `export_snapshot`, as JSON.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotDescriptor {
+    /// The timestamp of the view, also the timestamp of all versions in the SSTs.
+    pub read_ts: u64,
+    /// The SSTs in key order, which do not overlap.
+    pub files: Vec<SnapshotFile>,
+    pub num_keys: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotFile {
+    /// Name of the SST in the directory of the bundle.
+    pub name: String,
+    pub size: u64,
+}
+
+impl SnapshotDescriptor {
+    /// Read the descriptor of the bundle in `dir`, checking that its SSTs are there.
+    pub fn load(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref();
+        let path = dir.join(SNAPSHOT_DESCRIPTOR);
+        let data = std::fs::read(&path)
+            .with_context(|| format!("failed to read snapshot descriptor {}", path.display()))?;
+        let descriptor: Self = serde_json::from_slice(&data)
+            .with_context(|| format!("invalid snapshot descriptor {}", path.display()))?;
+        for file in &descriptor.files {
+            let size = std::fs::metadata(dir.join(&file.name))
+                .with_context(|| format!("SST {} of the snapshot is missing", file.name))?
+                .len();
+            if size != file.size {
+                bail!(
+                    "SST {} of the snapshot has {} bytes, expected {}",
+                    file.name,
+                    size,
+                    file.size
+                );
+            }
+        }
+        Ok(descriptor)
+    }
+}
+
+impl LsmStorageInner {
+    /// Export the data visible at the latest commit to a bundle in `dir`, which must not exist
+    /// yet, without stopping the writes. The values are split into SSTs of about
+    /// `target_sst_size`. The descriptor is written last, so a bundle is complete once it exists.
+    /// The column families are not exported.
+    pub fn export_snapshot(&self, dir: impl AsRef<Path>) -> Result<SnapshotDescriptor> {
+        let dir = dir.as_ref();
+        if let Some(parent) = dir.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::create_dir(dir)
+            .with_context(|| format!("failed to create snapshot {}", dir.display()))?;
+        let read_ts = self.mvcc().latest_commit_ts();
+        let mut iter = self.scan_with_ts(Bound::Unbounded, Bound::Unbounded, read_ts)?;
+        let mut descriptor = SnapshotDescriptor {
+            read_ts,
+            files: Vec::new(),
+            num_keys: 0,
+        };
+        let mut builder = self.new_sst_builder();
+        while iter.is_valid() {
+            builder.add(KeySlice::from_slice(iter.key(), read_ts), iter.value());
+            descriptor.num_keys += 1;
+            iter.next()?;
+            if builder.estimated_size() >= self.options.target_sst_size || !iter.is_valid() {
+                let builder = std::mem::replace(&mut builder, self.new_sst_builder());
+                let name = format!("{:05}.sst", descriptor.files.len());
+                let sst = builder.build(descriptor.files.len(), None, dir.join(&name))?;
+                descriptor.files.push(SnapshotFile {
+                    name,
+                    size: sst.file_size(),
+                });
+            }
+        }
+
+        let path = dir.join(SNAPSHOT_DESCRIPTOR);
+        let tmp_path = path.with_extension("tmp");
+        std::fs::write(&tmp_path, serde_json::to_vec(&descriptor)?)?;
+        File::open(&tmp_path)?.sync_all()?;
+        std::fs::rename(&tmp_path, &path)?;
+        File::open(dir)?.sync_all()?;
+        Ok(descriptor)
+    }
+
+    /// Import the bundle exported by `export_snapshot` into `dir`, see `ingest_sst` for the
+    /// requirements on the data in the storage, e.g., an empty one. The latest commit of the
+    /// storage is moved up to the timestamp of the bundle if it is older. Returns the new SST ids.
+    pub fn import_snapshot(&self, dir: impl AsRef<Path>) -> Result<Vec<usize>> {
+        self.check_bytewise("import_snapshot")?;
+        let dir = dir.as_ref();
+        let descriptor = SnapshotDescriptor::load(dir)?;
+        {
+            let _write_lock = self.mvcc().write_lock.lock();
+            if self.mvcc().latest_commit_ts() < descriptor.read_ts {
+                self.mvcc().update_commit_ts(descriptor.read_ts);
+            }
+        }
+        let paths = descriptor
+            .files
+            .iter()
+            .map(|file| dir.join(&file.name))
+            .collect::<Vec<PathBuf>>();
+        self.ingest_sst(&paths)
+    }
+}
@@ -0,0 +1,170 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Fault injection for crash tests. The storage evaluates a named failpoint right before each
+//! write that matters for durability, with the path of the file it writes. A `FailScenario` set
+//! up on a directory makes the failpoints of the files under it return an error, either once or
+//! from a given hit on, the latter standing for a process killed at that point.
+//!
+//! The scenarios are scoped by directory, so that tests running in parallel on their own
+//! directories do not see each other's faults. Without any scenario, evaluating a failpoint is a
+//! single atomic load.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use parking_lot::Mutex;
+
+use crate::error::Error;
+
+/// Creating and writing an SST file, see `FileObject::create`.
+pub const FILE_CREATE: &str = "file-create";
+/// Syncing an SST file after writing it.
+pub const FILE_SYNC: &str = "file-sync";
+/// Appending a record to the WAL.
+pub const WAL_APPEND: &str = "wal-append";
+/// Appending a record to the manifest, or replacing it with a snapshot.
+pub const MANIFEST_WRITE: &str = "manifest-write";
+
+/// What a `FailScenario` does once its failpoint is hit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailAction {
+    /// Fail that hit only.
+    Error,
+    /// Fail that hit and every later one of any failpoint, as if the process were killed.
+    Crash,
+}
+
+struct Scenario {
+    id: usize,
+    dir: PathBuf,
+    /// The failpoint to fail, `None` for any of them.
+    point: Option<String>,
+    /// The hits of `point` to let through before failing, `None` if nothing is armed.
+    remaining: Option<usize>,
+    action: FailAction,
+    crashed: bool,
+    /// Number of hits of all failpoints under `dir`.
+    hits: usize,
+}
+
+static SCENARIOS: Mutex<Vec<Scenario>> = Mutex::new(Vec::new());
+static NUM_SCENARIOS: AtomicUsize = AtomicUsize::new(0);
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Faults injected into the storage in a directory, removed when dropped.
+pub struct FailScenario {
+    id: usize,
+}
+
+impl FailScenario {
+    /// Count the failpoints hit by the files under `dir`, without failing any yet.
+    pub fn setup(dir: impl AsRef<Path>) -> Self {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        SCENARIOS.lock().push(Scenario {
+            id,
+            dir: dir.as_ref().to_path_buf(),
+            point: None,
+            remaining: None,
+            action: FailAction::Error,
+            crashed: false,
+            hits: 0,
+        });
+        NUM_SCENARIOS.fetch_add(1, Ordering::Release);
+        Self { id }
+    }
+
+    fn with<T>(&self, f: impl FnOnce(&mut Scenario) -> T) -> T {
+        let mut scenarios = SCENARIOS.lock();
+        let scenario = scenarios
+            .iter_mut()
+            .find(|scenario| scenario.id == self.id)
+            .unwrap();
+        f(scenario)
+    }
+
+    /// Apply `action` at the hit of `point`, or of any failpoint if `None`, after letting `skip`
+    /// hits of it through. Replaces the previous arming, and recovers from a crash.
+    pub fn arm(&self, point: Option<&str>, skip: usize, action: FailAction) {
+        self.with(|scenario| {
+            scenario.point = point.map(str::to_string);
+            scenario.remaining = Some(skip);
+            scenario.action = action;
+            scenario.crashed = false;
+        })
+    }
+
+    /// Stop failing, e.g., to reopen the storage after a crash.
+    pub fn disarm(&self) {
+        self.with(|scenario| {
+            scenario.remaining = None;
+            scenario.crashed = false;
+        })
+    }
+
+    /// Number of failpoints hit so far, including the failed ones.
+    pub fn hits(&self) -> usize {
+        self.with(|scenario| scenario.hits)
+    }
+
+    /// Whether a `FailAction::Crash` has fired.
+    pub fn crashed(&self) -> bool {
+        self.with(|scenario| scenario.crashed)
+    }
+}
+
+impl Drop for FailScenario {
+    fn drop(&mut self) {
+        SCENARIOS.lock().retain(|scenario| scenario.id != self.id);
+        NUM_SCENARIOS.fetch_sub(1, Ordering::Release);
+    }
+}
+
+/// Evaluate the failpoint `point` before writing the file at `path`, returning the injected error
+/// if a scenario on a directory of `path` fails it.
+pub(crate) fn eval(point: &str, path: &Path) -> Result<(), Error> {
+    if NUM_SCENARIOS.load(Ordering::Acquire) == 0 {
+        return Ok(());
+    }
+    let mut scenarios = SCENARIOS.lock();
+    let Some(scenario) = scenarios
+        .iter_mut()
+        .find(|scenario| path.starts_with(&scenario.dir))
+    else {
+        return Ok(());
+    };
+    scenario.hits += 1;
+    let fail = scenario.crashed
+        || match scenario.remaining.as_mut() {
+            Some(remaining) if scenario.point.as_deref().is_none_or(|p| p == point) => {
+                if *remaining == 0 {
+                    scenario.remaining = None;
+                    scenario.crashed = scenario.action == FailAction::Crash;
+                    true
+                } else {
+                    *remaining -= 1;
+                    false
+                }
+            }
+            _ => false,
+        };
+    if fail {
+        return Err(Error::Io(std::io::Error::other(format!(
+            "injected fault at {} of {}",
+            point,
+            path.display()
+        ))));
+    }
+    Ok(())
+}
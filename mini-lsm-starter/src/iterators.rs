@@ -12,12 +12,18 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+pub mod async_iterator;
+pub mod boxed_iterator;
 pub mod concat_iterator;
+pub mod keys_iterator;
 pub mod merge_iterator;
+pub mod scan_cursor;
 pub mod two_merge_iterator;
 
+use crate::key::{ComparableKey, KeySlice};
+
 pub trait StorageIterator {
-    type KeyType<'a>: PartialEq + Eq + PartialOrd + Ord
+    type KeyType<'a>: PartialEq + Eq + PartialOrd + Ord + ComparableKey
     where
         Self: 'a;
 
@@ -33,8 +39,46 @@ pub trait StorageIterator {
     /// Move to the next position.
     fn next(&mut self) -> anyhow::Result<()>;
 
+    /// Move to the first key that >= `key`. The default implementation calls `next` and hence can
+    /// only move forward; iterators that can reposition themselves should override it.
+    fn seek_to_key(&mut self, key: KeySlice) -> anyhow::Result<()>
+    where
+        Self: for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>,
+    {
+        while self.is_valid() && self.key() < key {
+            self.next()?;
+        }
+        Ok(())
+    }
+
+    /// Move forward by up to `n` entries and return how many entries were skipped, which is less
+    /// than `n` only if the iterator reaches the end.
+    fn next_n(&mut self, n: usize) -> anyhow::Result<usize> {
+        let mut advanced = 0;
+        while advanced < n && self.is_valid() {
+            self.next()?;
+            advanced += 1;
+        }
+        Ok(advanced)
+    }
+
     /// Number of underlying active iterators for this iterator.
     fn num_active_iterators(&self) -> usize {
         1
     }
 }
+
+/// A `StorageIterator` that can also move backward.
+///
+/// Implementors should also override `StorageIterator::seek_to_key` so that it repositions the
+/// iterator from any state, including after moving backward. An iterator generic over its key
+/// type, e.g., `TwoMergeIterator`, cannot seek its inner iterators there, and its `seek_to_key`
+/// only moves forward, so callers reposition it with `seek_for_prev` after moving backward.
+pub trait ReversibleIterator: StorageIterator {
+    /// Move to the previous position. The iterator becomes invalid when moving before the first
+    /// key.
+    fn prev(&mut self) -> anyhow::Result<()>;
+
+    /// Move to the last key that <= `key`, the iterator becomes invalid if there is none.
+    fn seek_for_prev(&mut self, key: KeySlice) -> anyhow::Result<()>;
+}
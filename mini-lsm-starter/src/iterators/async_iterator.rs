@@ -0,0 +1,91 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::future::Future;
+
+use anyhow::Result;
+
+use super::StorageIterator;
+
+/// The async counterpart of `StorageIterator`: only moving the iterator may touch the disk, so
+/// only `next` is async, and the current entry is read synchronously.
+pub trait AsyncStorageIterator {
+    type KeyType<'a>: PartialEq + Eq + PartialOrd + Ord
+    where
+        Self: 'a;
+
+    /// Get the current value.
+    fn value(&self) -> &[u8];
+
+    /// Get the current key.
+    fn key(&self) -> Self::KeyType<'_>;
+
+    /// Check if the current iterator is valid.
+    fn is_valid(&self) -> bool;
+
+    /// Move to the next position.
+    fn next(&mut self) -> impl Future<Output = Result<()>> + Send;
+}
+
+/// Turns a `StorageIterator` into an `AsyncStorageIterator` by moving it on the blocking thread
+/// pool of tokio. Must be used within a tokio runtime.
+///
+/// The iterator is handed to the blocking thread during `next`, so if the future of `next` is
+/// dropped before it completes, the iterator is lost and becomes invalid.
+pub struct SpawnBlockingIterator<I> {
+    iter: Option<I>,
+}
+
+impl<I: StorageIterator + Send + 'static> SpawnBlockingIterator<I> {
+    pub fn new(iter: I) -> Self {
+        Self { iter: Some(iter) }
+    }
+
+    /// Get the wrapped iterator back, or `None` if it was lost by a cancelled `next`.
+    pub fn into_inner(self) -> Option<I> {
+        self.iter
+    }
+}
+
+impl<I: StorageIterator + Send + 'static> AsyncStorageIterator for SpawnBlockingIterator<I> {
+    type KeyType<'a>
+        = I::KeyType<'a>
+    where
+        Self: 'a;
+
+    fn value(&self) -> &[u8] {
+        self.iter.as_ref().expect("iterator lost").value()
+    }
+
+    fn key(&self) -> Self::KeyType<'_> {
+        self.iter.as_ref().expect("iterator lost").key()
+    }
+
+    fn is_valid(&self) -> bool {
+        self.iter.as_ref().is_some_and(|iter| iter.is_valid())
+    }
+
+    async fn next(&mut self) -> Result<()> {
+        let Some(mut iter) = self.iter.take() else {
+            anyhow::bail!("the iterator is lost by a cancelled next");
+        };
+        let (iter, result) = tokio::task::spawn_blocking(move || {
+            let result = iter.next();
+            (iter, result)
+        })
+        .await?;
+        self.iter = Some(iter);
+        result
+    }
+}
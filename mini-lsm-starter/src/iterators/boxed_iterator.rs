@@ -0,0 +1,95 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use anyhow::Result;
+
+use super::StorageIterator;
+use crate::key::KeySlice;
+
+/// An object-safe counterpart of `StorageIterator`. `StorageIterator` has a generic associated
+/// type and therefore cannot be made into a trait object.
+trait DynStorageIterator {
+    fn value(&self) -> &[u8];
+
+    fn key(&self) -> KeySlice<'_>;
+
+    fn is_valid(&self) -> bool;
+
+    fn next(&mut self) -> Result<()>;
+
+    fn num_active_iterators(&self) -> usize;
+}
+
+impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> DynStorageIterator for I {
+    fn value(&self) -> &[u8] {
+        StorageIterator::value(self)
+    }
+
+    fn key(&self) -> KeySlice<'_> {
+        StorageIterator::key(self)
+    }
+
+    fn is_valid(&self) -> bool {
+        StorageIterator::is_valid(self)
+    }
+
+    fn next(&mut self) -> Result<()> {
+        StorageIterator::next(self)
+    }
+
+    fn num_active_iterators(&self) -> usize {
+        StorageIterator::num_active_iterators(self)
+    }
+}
+
+/// A type-erased iterator, so that iterators of different types can be composed, e.g., in a
+/// `MergeIterator<BoxedStorageIterator>`.
+pub struct BoxedStorageIterator {
+    inner: Box<dyn DynStorageIterator + Send>,
+}
+
+impl BoxedStorageIterator {
+    pub fn new<I>(iter: I) -> Self
+    where
+        I: 'static + Send + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>,
+    {
+        Self {
+            inner: Box::new(iter),
+        }
+    }
+}
+
+impl StorageIterator for BoxedStorageIterator {
+    type KeyType<'a> = KeySlice<'a>;
+
+    fn value(&self) -> &[u8] {
+        self.inner.value()
+    }
+
+    fn key(&self) -> KeySlice<'_> {
+        self.inner.key()
+    }
+
+    fn is_valid(&self) -> bool {
+        self.inner.is_valid()
+    }
+
+    fn next(&mut self) -> Result<()> {
+        self.inner.next()
+    }
+
+    fn num_active_iterators(&self) -> usize {
+        self.inner.num_active_iterators()
+    }
+}
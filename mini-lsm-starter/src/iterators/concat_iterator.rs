@@ -12,16 +12,15 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-#![allow(unused_variables)] // TODO(you): remove this lint after implementing this mod
-#![allow(dead_code)] // TODO(you): remove this lint after implementing this mod
-
+use std::ops::Bound;
 use std::sync::Arc;
 
 use anyhow::Result;
 
-use super::StorageIterator;
+use super::{ReversibleIterator, StorageIterator};
 use crate::{
-    key::KeySlice,
+    error::Error,
+    key::{KeyBytes, KeySlice, TS_RANGE_BEGIN, TS_RANGE_END},
     table::{SsTable, SsTableIterator},
 };
 
@@ -31,15 +30,107 @@ pub struct SstConcatIterator {
     current: Option<SsTableIterator>,
     next_sst_idx: usize,
     sstables: Vec<Arc<SsTable>>,
+    /// See `SsTableIterator::with_readahead`, 0 to disable.
+    readahead_bytes: usize,
 }
 
 impl SstConcatIterator {
+    fn check_sst_valid(sstables: &[Arc<SsTable>]) {
+        for sst in sstables.windows(2) {
+            debug_assert!(
+                sst[0]
+                    .comparator()
+                    .compare_keys(
+                        sst[0].last_key().as_key_slice(),
+                        sst[1].first_key().as_key_slice()
+                    )
+                    .is_lt(),
+                "sst {} and sst {} overlap",
+                sst[0].sst_id(),
+                sst[1].sst_id()
+            );
+        }
+    }
+
     pub fn create_and_seek_to_first(sstables: Vec<Arc<SsTable>>) -> Result<Self> {
-        unimplemented!()
+        Self::check_sst_valid(&sstables);
+        let mut iter = Self {
+            current: None,
+            next_sst_idx: 0,
+            sstables,
+            readahead_bytes: 0,
+        };
+        iter.move_until_valid()?;
+        Ok(iter)
     }
 
     pub fn create_and_seek_to_key(sstables: Vec<Arc<SsTable>>, key: KeySlice) -> Result<Self> {
-        unimplemented!()
+        Self::check_sst_valid(&sstables);
+        let mut iter = Self {
+            current: None,
+            next_sst_idx: 0,
+            sstables,
+            readahead_bytes: 0,
+        };
+        iter.seek_to_key(key)?;
+        Ok(iter)
+    }
+
+    /// Create a new iterator and seek to the latest version of the first user key within the lower
+    /// bound on user keys.
+    pub fn create_and_seek_to_range(
+        sstables: Vec<Arc<SsTable>>,
+        lower: Bound<&[u8]>,
+    ) -> Result<Self> {
+        match lower {
+            Bound::Included(key) => {
+                Self::create_and_seek_to_key(sstables, KeySlice::from_slice(key, TS_RANGE_BEGIN))
+            }
+            Bound::Excluded(key) => {
+                let mut iter = Self::create_and_seek_to_key(
+                    sstables,
+                    KeySlice::from_slice(key, TS_RANGE_END),
+                )?;
+                while iter.is_valid() && iter.key().key_ref() == key {
+                    iter.next()?;
+                }
+                Ok(iter)
+            }
+            Bound::Unbounded => Self::create_and_seek_to_first(sstables),
+        }
+    }
+
+    /// Prefetch the blocks of the tables when moving through them sequentially, see
+    /// `SsTableIterator::with_readahead`.
+    pub fn with_readahead(mut self, readahead_bytes: usize) -> Self {
+        self.readahead_bytes = readahead_bytes;
+        self.current = self
+            .current
+            .map(|iter| iter.with_readahead(readahead_bytes));
+        self
+    }
+
+    /// The first key across all tables, `None` if there is no table.
+    pub fn table_first_key(&self) -> Option<&KeyBytes> {
+        self.sstables.first().map(|sst| sst.first_key())
+    }
+
+    /// The last key across all tables, `None` if there is no table.
+    pub fn table_last_key(&self) -> Option<&KeyBytes> {
+        self.sstables.last().map(|sst| sst.last_key())
+    }
+
+    /// Open the tables from `next_sst_idx` on until one has a key left.
+    fn move_until_valid(&mut self) -> Result<()> {
+        while !self.is_valid() && self.next_sst_idx < self.sstables.len() {
+            let sst = self.sstables[self.next_sst_idx].clone();
+            self.current = Some(
+                SsTableIterator::create_and_seek_to_first(sst)?
+                    .with_readahead(self.readahead_bytes),
+            );
+            self.next_sst_idx += 1;
+        }
+        Ok(())
     }
 }
 
@@ -47,22 +138,88 @@ impl StorageIterator for SstConcatIterator {
     type KeyType<'a> = KeySlice<'a>;
 
     fn key(&self) -> KeySlice {
-        unimplemented!()
+        self.current.as_ref().unwrap().key()
     }
 
     fn value(&self) -> &[u8] {
-        unimplemented!()
+        self.current.as_ref().unwrap().value()
     }
 
     fn is_valid(&self) -> bool {
-        unimplemented!()
+        self.current.as_ref().is_some_and(|iter| iter.is_valid())
     }
 
     fn next(&mut self) -> Result<()> {
-        unimplemented!()
+        if !self.is_valid() {
+            return Err(Error::IteratorExhausted.into());
+        }
+        self.current.as_mut().unwrap().next()?;
+        self.move_until_valid()
     }
 
     fn num_active_iterators(&self) -> usize {
         1
     }
+
+    /// Open the first table that ends at or after `key`, which holds the first key >= `key`, and
+    /// seek in it.
+    fn seek_to_key(&mut self, key: KeySlice) -> Result<()> {
+        let idx = self.sstables.partition_point(|sst| {
+            sst.comparator()
+                .compare_keys(sst.last_key().as_key_slice(), key)
+                .is_lt()
+        });
+        self.current = None;
+        self.next_sst_idx = idx;
+        if let Some(sst) = self.sstables.get(idx) {
+            self.current = Some(
+                SsTableIterator::create_and_seek_to_key(sst.clone(), key)?
+                    .with_readahead(self.readahead_bytes),
+            );
+            self.next_sst_idx = idx + 1;
+        }
+        Ok(())
+    }
+}
+
+impl ReversibleIterator for SstConcatIterator {
+    /// Move to the previous key, stepping into the last key of the previous table if needed.
+    fn prev(&mut self) -> Result<()> {
+        if !self.is_valid() {
+            return Err(Error::IteratorExhausted.into());
+        }
+        let current = self.current.as_mut().unwrap();
+        current.prev()?;
+        if !current.is_valid() && self.next_sst_idx > 1 {
+            let sst = self.sstables[self.next_sst_idx - 2].clone();
+            let last_key = sst.last_key().clone();
+            let mut iter = SsTableIterator::create_and_seek_to_first(sst)?
+                .with_readahead(self.readahead_bytes);
+            iter.seek_for_prev(last_key.as_key_slice())?;
+            self.current = Some(iter);
+            self.next_sst_idx -= 1;
+        }
+        Ok(())
+    }
+
+    /// Open the last table that starts at or before `key`, which holds the last key <= `key`, and
+    /// seek in it. The iterator becomes invalid if all keys are greater than `key`.
+    fn seek_for_prev(&mut self, key: KeySlice) -> Result<()> {
+        let idx = self.sstables.partition_point(|sst| {
+            sst.comparator()
+                .compare_keys(sst.first_key().as_key_slice(), key)
+                .is_le()
+        });
+        self.current = None;
+        self.next_sst_idx = 0;
+        if idx > 0 {
+            let mut iter =
+                SsTableIterator::create_and_seek_to_first(self.sstables[idx - 1].clone())?
+                    .with_readahead(self.readahead_bytes);
+            iter.seek_for_prev(key)?;
+            self.current = Some(iter);
+            self.next_sst_idx = idx;
+        }
+        Ok(())
+    }
 }
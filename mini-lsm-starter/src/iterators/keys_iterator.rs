@@ -0,0 +1,50 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use anyhow::Result;
+
+use super::StorageIterator;
+
+/// Drives an inner iterator but only exposes its keys, for counting or key-existence scans.
+/// Pair it with a keys-only inner iterator (e.g., `SsTableIterator::create_keys_only_and_seek_to_first`)
+/// to skip resolving values altogether.
+pub struct KeysIterator<I: StorageIterator> {
+    inner: I,
+}
+
+impl<I: StorageIterator> KeysIterator<I> {
+    pub fn new(inner: I) -> Self {
+        Self { inner }
+    }
+
+    /// Get the current key.
+    pub fn key(&self) -> I::KeyType<'_> {
+        self.inner.key()
+    }
+
+    /// Check if the current iterator is valid.
+    pub fn is_valid(&self) -> bool {
+        self.inner.is_valid()
+    }
+
+    /// Move to the next key.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<()> {
+        self.inner.next()
+    }
+
+    pub fn into_inner(self) -> I {
+        self.inner
+    }
+}
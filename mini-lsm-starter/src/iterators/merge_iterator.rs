@@ -15,68 +15,308 @@
 #![allow(unused_variables)] // TODO(you): remove this lint after implementing this mod
 #![allow(dead_code)] // TODO(you): remove this lint after implementing this mod
 
-use std::cmp::{self};
-use std::collections::BinaryHeap;
-use std::collections::binary_heap::PeekMut;
+use anyhow::Result;
 
-use anyhow::{Result, anyhow};
+use crate::error::Error;
+use crate::key::{ComparableKey, Comparator, KeySlice, KeyVec, TS_DEFAULT, bytewise_comparator};
 
-use crate::key::KeySlice;
+use super::{ReversibleIterator, StorageIterator};
 
-use super::StorageIterator;
+/// A child of a `MergeIterator`, tagged with its priority and the comparator of the keys, which
+/// all children of a `MergeIterator` share. On equal keys the child with the smaller priority
+/// takes precedence. Priorities are unique within a `MergeIterator`, so two valid children are
+/// never tied.
+pub(crate) struct MergeWrapper<I: StorageIterator>(pub u64, pub Box<I>, pub Comparator);
 
-struct HeapWrapper<I: StorageIterator>(pub usize, pub Box<I>);
-
-impl<I: StorageIterator> PartialEq for HeapWrapper<I> {
-    fn eq(&self, other: &Self) -> bool {
-        self.cmp(other) == cmp::Ordering::Equal
+impl<I: StorageIterator> MergeWrapper<I> {
+    /// A wrapper of an iterator ordered bytewise.
+    pub fn new(priority: u64, iter: Box<I>) -> Self {
+        Self(priority, iter, bytewise_comparator())
     }
-}
-
-impl<I: StorageIterator> Eq for HeapWrapper<I> {}
 
-impl<I: StorageIterator> PartialOrd for HeapWrapper<I> {
-    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
-        Some(self.cmp(other))
+    /// Whether this child comes before `other` when moving in `direction`: by the key, ascending
+    /// forward and descending backward, then by the priority. An exhausted child comes last.
+    fn precedes(&self, other: &Self, direction: Direction) -> bool {
+        match (self.1.is_valid(), other.1.is_valid()) {
+            (true, true) => {
+                let order = self.1.key().compare_with(&other.1.key(), &*self.2);
+                let order = match direction {
+                    Direction::Forward => order,
+                    Direction::Backward => order.reverse(),
+                };
+                order.then(self.0.cmp(&other.0)).is_lt()
+            }
+            (valid, other_valid) => valid && !other_valid,
+        }
     }
 }
 
-// For BinaryHeap to compare the iter's order
-impl<I: StorageIterator> Ord for HeapWrapper<I> {
-    fn cmp(&self, other: &Self) -> cmp::Ordering {
-        self.1
-            .key()
-            .cmp(&other.1.key())
-            .then(self.0.cmp(&other.0))
-            .reverse()
-    }
+/// The direction a `MergeIterator` moved in last.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    Forward,
+    Backward,
 }
 
 /// Merge multiple iterators of the same type. If the same key occurs multiple times in some
 /// iterators, prefer the one with smaller index.
+///
+/// The children are the leaves of a loser tree, whose internal nodes keep the loser of the match
+/// between the winners of their subtrees. Once the winner, i.e., the current child, moves, only the
+/// matches on the path from its leaf to the root are replayed, one comparison per level, where a
+/// binary heap compares with both children of each node on the way down.
 pub struct MergeIterator<I: StorageIterator> {
-    iters: BinaryHeap<HeapWrapper<I>>,
-    current: Option<HeapWrapper<I>>,
+    children: Vec<MergeWrapper<I>>,
+    /// `tree[0]` is the index of the winner, and `tree[node]` for `1 <= node < children.len()` the
+    /// loser at the internal node `node`, whose subtrees are the nodes `2 * node` and
+    /// `2 * node + 1`, where the node `children.len() + i` is the leaf of `children[i]`. Empty
+    /// without any child or after an error, when the iterator is invalid.
+    tree: Vec<usize>,
+    direction: Direction,
+    /// The key the current child was at before it moved, kept to reuse the buffer.
+    last_key: KeyVec,
 }
 
 impl<I: StorageIterator> MergeIterator<I> {
     pub fn create(iters: Vec<Box<I>>) -> Self {
-        // unimplemented!()
-        // Assume the iters are sorted by Version
-        let mut heap = BinaryHeap::<HeapWrapper<I>>::new();
-        let mut i = 0;
-        for iter in iters {
-            if !iter.is_valid() {
-                continue;
+        let capacity = iters.len();
+        Self::create_with_capacity(iters, capacity)
+    }
+
+    /// Create a merge iterator, reserving space for `capacity` iterators up front so that the
+    /// children do not grow while being collected. The tree is built in one pass.
+    pub fn create_with_capacity(iters: impl IntoIterator<Item = Box<I>>, capacity: usize) -> Self {
+        Self::from_wrapper_iter(
+            iters
+                .into_iter()
+                .enumerate()
+                .map(|(i, iter)| MergeWrapper::new(i as u64, iter)),
+            capacity,
+        )
+    }
+
+    /// Create a merge iterator where each child carries an explicit priority instead of using its
+    /// position: on equal keys the child with the lower priority wins. Priorities must be unique.
+    pub fn create_with_priorities(iters: Vec<(u64, Box<I>)>) -> Self {
+        Self::from_wrappers(
+            iters
+                .into_iter()
+                .map(|(priority, iter)| MergeWrapper::new(priority, iter))
+                .collect(),
+        )
+    }
+
+    /// Create a merge iterator from wrappers built by the caller, e.g., ones taken back with
+    /// `into_wrappers` and repositioned, so that a compaction planner controls the priorities
+    /// directly. Priorities must be unique.
+    pub(crate) fn from_wrappers(wrappers: Vec<MergeWrapper<I>>) -> Self {
+        debug_assert!(
+            {
+                let mut priorities = wrappers.iter().map(|w| w.0).collect::<Vec<_>>();
+                priorities.sort_unstable();
+                priorities.windows(2).all(|w| w[0] != w[1])
+            },
+            "merge priorities must be unique"
+        );
+        let capacity = wrappers.len();
+        Self::from_wrapper_iter(wrappers.into_iter(), capacity)
+    }
+
+    fn from_wrapper_iter(wrappers: impl Iterator<Item = MergeWrapper<I>>, capacity: usize) -> Self {
+        let mut children = Vec::with_capacity(capacity);
+        children.extend(wrappers);
+        let mut iter = MergeIterator {
+            children,
+            tree: Vec::new(),
+            direction: Direction::Forward,
+            last_key: KeyVec::new(),
+        };
+        iter.build_tree();
+        iter
+    }
+
+    /// Order the keys by `comparator` instead of bytewise, as the children are. Called right after
+    /// creating the iterator, which is positioned at the smallest key again.
+    pub fn with_comparator(mut self, comparator: Comparator) -> Self {
+        for child in &mut self.children {
+            child.2 = comparator.clone();
+        }
+        self.direction = Direction::Forward;
+        self.build_tree();
+        self
+    }
+
+    /// Take back all child iterators with their priorities, in no particular order.
+    pub(crate) fn into_wrappers(mut self) -> Vec<MergeWrapper<I>> {
+        self.tree.clear();
+        std::mem::take(&mut self.children)
+    }
+
+    /// Play all the matches of the children at their positions in `direction`.
+    fn build_tree(&mut self) {
+        let n = self.children.len();
+        self.tree.clear();
+        if n == 0 {
+            return;
+        }
+        self.tree.resize(n, 0);
+        // the winners of the subtrees of the internal nodes
+        let mut winners = vec![0; n];
+        for node in (1..n).rev() {
+            let [left, right] = [2 * node, 2 * node + 1]
+                .map(|node| if node >= n { node - n } else { winners[node] });
+            let (winner, loser) =
+                if self.children[right].precedes(&self.children[left], self.direction) {
+                    (right, left)
+                } else {
+                    (left, right)
+                };
+            winners[node] = winner;
+            self.tree[node] = loser;
+        }
+        self.tree[0] = if n == 1 { 0 } else { winners[1] };
+    }
+
+    /// Replay the matches on the path from the leaf of `child`, the winner before it moved, to the
+    /// root.
+    fn replay(&mut self, child: usize) {
+        let mut winner = child;
+        let mut node = (self.children.len() + child) / 2;
+        while node >= 1 {
+            let loser = self.tree[node];
+            if self.children[loser].precedes(&self.children[winner], self.direction) {
+                self.tree[node] = winner;
+                winner = loser;
             }
-            heap.push(HeapWrapper(i, iter));
-            i += 1;
+            node /= 2;
         }
-        let current = heap.pop();
-        MergeIterator {
-            iters: heap,
-            current,
+        self.tree[0] = winner;
+    }
+
+    /// The child the iterator is at, if it is valid.
+    fn current(&self) -> Option<&MergeWrapper<I>> {
+        self.tree
+            .first()
+            .map(|&winner| &self.children[winner])
+            .filter(|child| child.1.is_valid())
+    }
+}
+
+impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> MergeIterator<I> {
+    /// Skip all remaining versions of the current user key, moving to the next distinct user key.
+    pub fn next_distinct_key(&mut self) -> Result<()> {
+        if !self.is_valid() {
+            return Err(Error::IteratorExhausted.into());
+        }
+        let user_key = self.key().key_ref().to_vec();
+        while self.is_valid() && self.key().key_ref() == user_key {
+            self.next()?;
         }
+        Ok(())
+    }
+
+    /// Return the smallest key among the children that differs from the current key. This is the
+    /// key `next` lands on unless the current child, or another child at the current key, moves
+    /// to a smaller key, which is not known without moving them. `None` if there is no such key,
+    /// or if the iterator last moved backward, when the other children are before the current key.
+    ///
+    /// The children are not moved. The nodes on the path from the current child to the root keep
+    /// the winners of the other subtrees, and a subtree is only searched further while its winner
+    /// is at the current key.
+    pub fn peek_next_key(&self) -> Option<KeySlice<'_>> {
+        if self.direction == Direction::Backward {
+            return None;
+        }
+        let current = self.current()?;
+        let n = self.children.len();
+        let mut next = None;
+        let mut node = n + self.tree[0];
+        while node > 1 {
+            self.find_next(node ^ 1, self.tree[node / 2], current.1.key(), &mut next);
+            node /= 2;
+        }
+        next.map(|child: usize| self.children[child].1.key())
+    }
+
+    /// Search the subtree at `node`, whose winner is `winner`, for the child at the smallest key
+    /// other than `key`, and keep it in `next` if it precedes the one found so far.
+    fn find_next(&self, node: usize, winner: usize, key: KeySlice, next: &mut Option<usize>) {
+        let child = &self.children[winner];
+        if !child.1.is_valid() {
+            // all children of the subtree are exhausted
+            return;
+        }
+        if child.1.key() != key {
+            if next.is_none_or(|next| child.precedes(&self.children[next], Direction::Forward)) {
+                *next = Some(winner);
+            }
+            return;
+        }
+        let n = self.children.len();
+        if node >= n {
+            return;
+        }
+        // the winner comes from one subtree of the node, and the loser is the winner of the other
+        let mut winner_side = n + winner;
+        while winner_side / 2 != node {
+            winner_side /= 2;
+        }
+        self.find_next(winner_side, winner, key, next);
+        self.find_next(winner_side ^ 1, self.tree[node], key, next);
+    }
+
+    /// Move the current child with `step`, and then the other children at the key it was at, as
+    /// the current child shadows their entries. With equal keys, the children win in the order of
+    /// their priorities, so each of them is the winner in turn. An error leaves the iterator
+    /// invalid until it seeks.
+    fn step_current(&mut self, step: impl Fn(&mut I) -> Result<()>) -> Result<()> {
+        let current = self.tree[0];
+        self.last_key.set_from_slice(self.children[current].1.key());
+        let mut winner = current;
+        loop {
+            if let Err(e) = step(&mut self.children[winner].1) {
+                self.tree.clear();
+                return Err(e);
+            }
+            self.replay(winner);
+            winner = self.tree[0];
+            let child = &self.children[winner];
+            if winner == current
+                || !child.1.is_valid()
+                || child.1.key() != self.last_key.as_key_slice()
+            {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Move every child with `position`, and rebuild the tree for `direction`.
+    fn reposition(
+        &mut self,
+        direction: Direction,
+        position: impl Fn(&mut I) -> Result<()>,
+    ) -> Result<()> {
+        self.tree.clear();
+        self.direction = direction;
+        for child in &mut self.children {
+            position(&mut child.1)?;
+        }
+        self.build_tree();
+        Ok(())
+    }
+
+    /// Turn around after moving backward: move every child iterator to the first key greater than
+    /// the current key and pick the smallest one.
+    fn switch_to_forward(&mut self) -> Result<()> {
+        let key = self.key().to_key_vec();
+        let key = key.as_key_slice();
+        self.reposition(Direction::Forward, |iter| {
+            iter.seek_to_key(key)?;
+            if iter.is_valid() && iter.key() == key {
+                iter.next()?;
+            }
+            Ok(())
+        })
     }
 }
 
@@ -86,64 +326,83 @@ impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> StorageIt
     type KeyType<'a> = KeySlice<'a>;
 
     fn key(&self) -> KeySlice {
-        // unimplemented!()
-        match &self.current {
-            Some(wrapper) => wrapper.1.key(),
-            None => KeySlice::from_slice(&[]),
+        match self.current() {
+            Some(child) => child.1.key(),
+            None => KeySlice::from_slice(&[], TS_DEFAULT),
         }
     }
 
     fn value(&self) -> &[u8] {
-        match &self.current {
-            Some(wrapper) => wrapper.1.value(),
+        match self.current() {
+            Some(child) => child.1.value(),
             None => &[],
         }
     }
 
     fn is_valid(&self) -> bool {
-        match &self.current {
-            Some(wrapper) => wrapper.1.is_valid(),
-            None => false,
-        }
+        self.current().is_some()
     }
 
     fn next(&mut self) -> Result<()> {
         if !self.is_valid() {
-            return Err(anyhow::anyhow!("iterator has errored"));
-        }
-        // 1. 获取当前正在使用的迭代器（current 必然是有效值，除非已经迭代结束）
-        let current_wrapper = self.current.as_mut().unwrap();
-
-        // 2. 去重逻辑：
-        while let Some(mut top) = self.iters.peek_mut() {
-            if top.1.key() == current_wrapper.1.key() {
-                if let Err(e) = top.1.next() {
-                    PeekMut::pop(top);
-                    self.current = None;
-                    return Err(e);
-                }
-                if !top.1.is_valid() {
-                    PeekMut::pop(top);
-                }
-                // 注意：PeekMut 在这里会自动根据 top.1.key() 重新平衡堆
-            } else {
-                break;
-            }
+            return Err(Error::IteratorExhausted.into());
+        }
+        if self.direction == Direction::Backward {
+            return self.switch_to_forward();
         }
+        self.step_current(|iter| iter.next())
+    }
 
-        // 3. 推进当前的迭代器
-        current_wrapper.1.next()?;
+    /// Re-seek every child iterator to the first key that >= `key` and rebuild the tree.
+    fn seek_to_key(&mut self, key: KeySlice) -> Result<()> {
+        self.reposition(Direction::Forward, |iter| iter.seek_to_key(key))
+    }
 
-        // 4. 将当前的迭代器重新放回堆（如果依然有效）
-        // 这里需要把 current 拿出来
-        let current_inner = self.current.take().unwrap();
-        if current_inner.1.is_valid() {
-            self.iters.push(current_inner);
-        }
+    /// Sum over the children that are not exhausted.
+    fn num_active_iterators(&self) -> usize {
+        self.children
+            .iter()
+            .filter(|child| child.1.is_valid())
+            .map(|child| child.1.num_active_iterators())
+            .sum()
+    }
+}
+
+impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>> + ReversibleIterator>
+    MergeIterator<I>
+{
+    /// Turn around after moving forward: move every child iterator to the last key smaller than
+    /// the current key and pick the largest one.
+    fn switch_to_backward(&mut self) -> Result<()> {
+        let key = self.key().to_key_vec();
+        let key = key.as_key_slice();
+        self.reposition(Direction::Backward, |iter| {
+            iter.seek_for_prev(key)?;
+            if iter.is_valid() && iter.key() == key {
+                iter.prev()?;
+            }
+            Ok(())
+        })
+    }
+}
 
-        // 5. 从堆中弹出新的最小值作为 current
-        self.current = self.iters.pop();
+impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>> + ReversibleIterator>
+    ReversibleIterator for MergeIterator<I>
+{
+    /// Mirror of `next`: step the current iterator backward, skip the other versions of the
+    /// current key, and pick the largest key, preferring the smaller priority on equal keys.
+    fn prev(&mut self) -> Result<()> {
+        if !self.is_valid() {
+            return Err(Error::IteratorExhausted.into());
+        }
+        if self.direction == Direction::Forward {
+            return self.switch_to_backward();
+        }
+        self.step_current(|iter| iter.prev())
+    }
 
-        Ok(())
+    /// Re-seek every child iterator to the last key that <= `key` and rebuild the tree.
+    fn seek_for_prev(&mut self, key: KeySlice) -> Result<()> {
+        self.reposition(Direction::Backward, |iter| iter.seek_for_prev(key))
     }
 }
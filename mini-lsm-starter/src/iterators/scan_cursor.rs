@@ -0,0 +1,88 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use anyhow::Result;
+
+use super::StorageIterator;
+use crate::key::{KeySlice, KeyVec};
+
+/// A scan that can continue on a different iterator, e.g., after the SSTs it reads from are
+/// replaced by compaction. The cursor remembers the last key it moved past, and `reposition`
+/// resumes right after that key, so no key is yielded twice or skipped.
+pub struct ScanCursor<I> {
+    iter: I,
+    /// The last key the cursor moved past with `next`.
+    last_key: Option<KeyVec>,
+}
+
+impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> ScanCursor<I> {
+    pub fn new(iter: I) -> Self {
+        Self {
+            iter,
+            last_key: None,
+        }
+    }
+
+    /// The last key the cursor moved past, `None` if `next` has not been called.
+    pub fn last_key(&self) -> Option<KeySlice<'_>> {
+        self.last_key.as_ref().map(|key| key.as_key_slice())
+    }
+
+    /// Continue the scan on `iter`, moving it to the first key after the last key the cursor
+    /// moved past. If the cursor has not moved yet, `iter` is used at its current position.
+    pub fn reposition(&mut self, mut iter: I) -> Result<()> {
+        if let Some(last_key) = &self.last_key {
+            iter.seek_to_key(last_key.as_key_slice())?;
+            if iter.is_valid() && iter.key() == last_key.as_key_slice() {
+                iter.next()?;
+            }
+        }
+        self.iter = iter;
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> I {
+        self.iter
+    }
+}
+
+impl<I: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>> StorageIterator
+    for ScanCursor<I>
+{
+    type KeyType<'a> = KeySlice<'a>;
+
+    fn value(&self) -> &[u8] {
+        self.iter.value()
+    }
+
+    fn key(&self) -> KeySlice<'_> {
+        self.iter.key()
+    }
+
+    fn is_valid(&self) -> bool {
+        self.iter.is_valid()
+    }
+
+    fn next(&mut self) -> Result<()> {
+        match &mut self.last_key {
+            Some(last_key) => last_key.set_from_slice(self.iter.key()),
+            None => self.last_key = Some(self.iter.key().to_key_vec()),
+        }
+        self.iter.next()
+    }
+
+    fn num_active_iterators(&self) -> usize {
+        self.iter.num_active_iterators()
+    }
+}
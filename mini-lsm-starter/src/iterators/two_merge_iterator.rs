@@ -12,19 +12,21 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-#![allow(unused_variables)] // TODO(you): remove this lint after implementing this mod
-#![allow(dead_code)] // TODO(you): remove this lint after implementing this mod
-
 use anyhow::Result;
 
-use super::StorageIterator;
+use super::{ReversibleIterator, StorageIterator};
+use crate::error::Error;
+use crate::key::{ComparableKey, Comparator, KeySlice, KeyVec, bytewise_comparator};
 
 /// Merges two iterators of different types into one. If the two iterators have the same key, only
 /// produce the key once and prefer the entry from A.
 pub struct TwoMergeIterator<A: StorageIterator, B: StorageIterator> {
     a: A,
     b: B,
-    // Add fields as need
+    /// Whether the current entry comes from A
+    choose_a: bool,
+    /// The order of the keys of both iterators
+    comparator: Comparator,
 }
 
 impl<
@@ -33,7 +35,46 @@ impl<
 > TwoMergeIterator<A, B>
 {
     pub fn create(a: A, b: B) -> Result<Self> {
-        unimplemented!()
+        Self::create_with_comparator(a, b, bytewise_comparator())
+    }
+
+    /// Merge two iterators whose keys are ordered by `comparator`.
+    pub fn create_with_comparator(a: A, b: B, comparator: Comparator) -> Result<Self> {
+        let mut iter = Self {
+            a,
+            b,
+            choose_a: false,
+            comparator,
+        };
+        iter.skip_b()?;
+        iter.choose_a = iter.choose_a();
+        Ok(iter)
+    }
+
+    /// The order of the keys of both iterators.
+    pub fn comparator(&self) -> &Comparator {
+        &self.comparator
+    }
+
+    fn choose_a(&self) -> bool {
+        if !self.a.is_valid() {
+            return false;
+        }
+        if !self.b.is_valid() {
+            return true;
+        }
+        self.a
+            .key()
+            .compare_with(&self.b.key(), &*self.comparator)
+            .is_lt()
+    }
+
+    /// Move B past the current key of A, which shadows it.
+    fn skip_b(&mut self) -> Result<()> {
+        if self.a.is_valid() && self.b.is_valid() && self.b.key() == self.a.key() {
+            self.b.next()?;
+        }
+        Ok(())
     }
 }
 
@@ -45,18 +86,110 @@ impl<
     type KeyType<'a> = A::KeyType<'a>;
 
     fn key(&self) -> Self::KeyType<'_> {
-        unimplemented!()
+        if self.choose_a {
+            self.a.key()
+        } else {
+            self.b.key()
+        }
     }
 
     fn value(&self) -> &[u8] {
-        unimplemented!()
+        if self.choose_a {
+            self.a.value()
+        } else {
+            self.b.value()
+        }
     }
 
     fn is_valid(&self) -> bool {
-        unimplemented!()
+        if self.choose_a {
+            self.a.is_valid()
+        } else {
+            self.b.is_valid()
+        }
     }
 
     fn next(&mut self) -> Result<()> {
-        unimplemented!()
+        if self.choose_a {
+            self.a.next()?;
+        } else {
+            self.b.next()?;
+        }
+        self.skip_b()?;
+        self.choose_a = self.choose_a();
+        Ok(())
+    }
+
+    fn num_active_iterators(&self) -> usize {
+        self.a.num_active_iterators() + self.b.num_active_iterators()
+    }
+}
+
+impl<A, B> TwoMergeIterator<A, B>
+where
+    A: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>> + ReversibleIterator,
+    B: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>> + ReversibleIterator,
+{
+    /// Pick the larger of the two entries the iterators are at after moving backward, and move the
+    /// other iterator to its first key after it, so that `next` works as if it got there by moving
+    /// forward.
+    fn choose_backward(&mut self) -> Result<()> {
+        self.choose_a = self.a.is_valid()
+            && (!self.b.is_valid()
+                || self
+                    .a
+                    .key()
+                    .compare_with(&self.b.key(), &*self.comparator)
+                    .is_ge());
+        if !self.is_valid() {
+            return Ok(());
+        }
+        let key = self.key().to_key_vec();
+        if self.choose_a {
+            self.b.seek_to_key(key.as_key_slice())?;
+            if self.b.is_valid() && self.b.key() == key.as_key_slice() {
+                self.b.next()?;
+            }
+        } else {
+            self.a.seek_to_key(key.as_key_slice())?;
+        }
+        Ok(())
+    }
+
+    /// Move both iterators to the last key < `key`.
+    fn seek_before(&mut self, key: &KeyVec) -> Result<()> {
+        self.a.seek_for_prev(key.as_key_slice())?;
+        if self.a.is_valid() && self.a.key() == key.as_key_slice() {
+            self.a.prev()?;
+        }
+        self.b.seek_for_prev(key.as_key_slice())?;
+        if self.b.is_valid() && self.b.key() == key.as_key_slice() {
+            self.b.prev()?;
+        }
+        Ok(())
+    }
+}
+
+/// `StorageIterator::seek_to_key` is not overridden, as the iterator is generic over the key type
+/// and cannot seek its inner iterators by a `KeySlice`, so it only moves forward. Reposition the
+/// iterator with `seek_for_prev` instead.
+impl<A, B> ReversibleIterator for TwoMergeIterator<A, B>
+where
+    A: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>> + ReversibleIterator,
+    B: 'static + for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>> + ReversibleIterator,
+{
+    fn prev(&mut self) -> Result<()> {
+        if !self.is_valid() {
+            return Err(Error::IteratorExhausted.into());
+        }
+        let key = self.key().to_key_vec();
+        self.seek_before(&key)?;
+        self.choose_backward()
+    }
+
+    fn seek_for_prev(&mut self, key: KeySlice) -> Result<()> {
+        self.a.seek_for_prev(key)?;
+        self.b.seek_for_prev(key)?;
+        self.choose_backward()
     }
 }
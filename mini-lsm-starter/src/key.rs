@@ -12,49 +12,137 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::cmp::{Ordering, Reverse};
 use std::fmt::Debug;
+use std::sync::Arc;
 
 use bytes::Bytes;
 
-pub const TS_ENABLED: bool = false;
+pub const TS_ENABLED: bool = true;
 
-pub struct Key<T: AsRef<[u8]>>(T);
+/// A user key and the timestamp of the version it belongs to. Keys are ordered by the user key
+/// ascending and then by the timestamp descending, so the latest version of a user key comes first.
+pub struct Key<T: AsRef<[u8]>>(T, u64);
 
 pub type KeySlice<'a> = Key<&'a [u8]>;
 pub type KeyVec = Key<Vec<u8>>;
 pub type KeyBytes = Key<Bytes>;
 
+/// Timestamp of keys that are not versioned, e.g., in tests.
+pub const TS_DEFAULT: u64 = 0;
+
+pub const TS_MAX: u64 = u64::MAX;
+pub const TS_MIN: u64 = u64::MIN;
+/// A key with this timestamp sorts before all versions of the same user key.
+pub const TS_RANGE_BEGIN: u64 = TS_MAX;
+/// A key with this timestamp sorts after all versions of the same user key.
+pub const TS_RANGE_END: u64 = TS_MIN;
+
+/// Orders the user keys of a storage, see `LsmStorageOptions::comparator`. The versions of a user
+/// key are still ordered by the timestamp descending.
+///
+/// A comparator must be a total order, and only compare keys with the same bytes as equal. Its name
+/// is persisted in the manifest, and a storage refuses to open with a comparator of another name,
+/// so the name must change whenever the order does.
+pub trait KeyComparator: Debug + Send + Sync {
+    fn name(&self) -> &str;
+
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering;
+
+    /// Compare by the user key with this comparator, then by the timestamp descending.
+    fn compare_keys(&self, a: KeySlice, b: KeySlice) -> Ordering {
+        self.compare(a.0, b.0).then(b.1.cmp(&a.1))
+    }
+
+    /// Whether this comparator orders the keys as `BytewiseComparator` does.
+    fn is_bytewise(&self) -> bool {
+        self.name() == BYTEWISE_COMPARATOR_NAME
+    }
+}
+
+pub type Comparator = Arc<dyn KeyComparator>;
+
+/// Name of `BytewiseComparator`.
+pub const BYTEWISE_COMPARATOR_NAME: &str = "mini-lsm.BytewiseComparator";
+
+/// Orders the user keys lexicographically by their bytes, the default.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct BytewiseComparator;
+
+impl KeyComparator for BytewiseComparator {
+    fn name(&self) -> &str {
+        BYTEWISE_COMPARATOR_NAME
+    }
+
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        a.cmp(b)
+    }
+}
+
+/// The default comparator, `BytewiseComparator`.
+pub fn bytewise_comparator() -> Comparator {
+    Arc::new(BytewiseComparator)
+}
+
+/// Keys that can be ordered by a `KeyComparator` of their user keys.
+pub trait ComparableKey {
+    fn compare_with(&self, other: &Self, comparator: &dyn KeyComparator) -> Ordering;
+}
+
+impl ComparableKey for KeySlice<'_> {
+    fn compare_with(&self, other: &Self, comparator: &dyn KeyComparator) -> Ordering {
+        comparator.compare_keys(*self, *other)
+    }
+}
+
+impl ComparableKey for &[u8] {
+    fn compare_with(&self, other: &Self, comparator: &dyn KeyComparator) -> Ordering {
+        comparator.compare(self, other)
+    }
+}
+
 impl<T: AsRef<[u8]>> Key<T> {
     pub fn into_inner(self) -> T {
         self.0
     }
 
-    pub fn len(&self) -> usize {
+    /// Length of the user key.
+    pub fn key_len(&self) -> usize {
         self.0.as_ref().len()
     }
 
+    /// Length of the user key and the timestamp once encoded.
+    pub fn raw_len(&self) -> usize {
+        self.0.as_ref().len() + std::mem::size_of::<u64>()
+    }
+
     pub fn is_empty(&self) -> bool {
         self.0.as_ref().is_empty()
     }
 
+    pub fn ts(&self) -> u64 {
+        self.1
+    }
+
     pub fn for_testing_ts(self) -> u64 {
-        0
+        self.1
     }
 }
 
 impl Key<Vec<u8>> {
     pub fn new() -> Self {
-        Self(Vec::new())
+        Self(Vec::new(), TS_DEFAULT)
     }
 
-    /// Create a `KeyVec` from a `Vec<u8>`. Will be removed in week 3.
-    pub fn from_vec(key: Vec<u8>) -> Self {
-        Self(key)
+    /// Create a `KeyVec` from a `Vec<u8>` and a timestamp.
+    pub fn from_vec_with_ts(key: Vec<u8>, ts: u64) -> Self {
+        Self(key, ts)
     }
 
     /// Clears the key and set ts to 0.
     pub fn clear(&mut self) {
-        self.0.clear()
+        self.0.clear();
+        self.1 = TS_DEFAULT;
     }
 
     /// Append a slice to the end of the key
@@ -62,22 +150,32 @@ impl Key<Vec<u8>> {
         self.0.extend(data)
     }
 
-    /// Set the key from a slice without re-allocating. The signature will change in week 3.
+    /// Keep the first `len` bytes of the key
+    pub fn truncate(&mut self, len: usize) {
+        self.0.truncate(len)
+    }
+
+    pub fn set_ts(&mut self, ts: u64) {
+        self.1 = ts;
+    }
+
+    /// Set the key from a slice without re-allocating.
     pub fn set_from_slice(&mut self, key_slice: KeySlice) {
         self.0.clear();
         self.0.extend(key_slice.0);
+        self.1 = key_slice.1;
     }
 
     pub fn as_key_slice(&self) -> KeySlice {
-        Key(self.0.as_slice())
+        Key(self.0.as_slice(), self.1)
     }
 
     pub fn into_key_bytes(self) -> KeyBytes {
-        Key(self.0.into())
+        Key(self.0.into(), self.1)
     }
 
-    /// Always use `raw_ref` to access the key in week 1 + 2. This function will be removed in week 3.
-    pub fn raw_ref(&self) -> &[u8] {
+    /// The user key part of the key, i.e., without the timestamp.
+    pub fn key_ref(&self) -> &[u8] {
         self.0.as_ref()
     }
 
@@ -86,27 +184,27 @@ impl Key<Vec<u8>> {
     }
 
     pub fn for_testing_from_vec_no_ts(key: Vec<u8>) -> Self {
-        Self(key)
+        Self(key, TS_DEFAULT)
     }
 }
 
 impl Key<Bytes> {
     pub fn as_key_slice(&self) -> KeySlice {
-        Key(&self.0)
+        Key(&self.0, self.1)
     }
 
-    /// Create a `KeyBytes` from a `Bytes`. Will be removed in week 3.
-    pub fn from_bytes(bytes: Bytes) -> KeyBytes {
-        Key(bytes)
+    /// Create a `KeyBytes` from a `Bytes` and a timestamp.
+    pub fn from_bytes_with_ts(bytes: Bytes, ts: u64) -> KeyBytes {
+        Key(bytes, ts)
     }
 
-    /// Always use `raw_ref` to access the key in week 1 + 2. This function will be removed in week 3.
-    pub fn raw_ref(&self) -> &[u8] {
+    /// The user key part of the key, i.e., without the timestamp.
+    pub fn key_ref(&self) -> &[u8] {
         self.0.as_ref()
     }
 
     pub fn for_testing_from_bytes_no_ts(bytes: Bytes) -> KeyBytes {
-        Key(bytes)
+        Key(bytes, TS_DEFAULT)
     }
 
     pub fn for_testing_key_ref(&self) -> &[u8] {
@@ -116,16 +214,16 @@ impl Key<Bytes> {
 
 impl<'a> Key<&'a [u8]> {
     pub fn to_key_vec(self) -> KeyVec {
-        Key(self.0.to_vec())
+        Key(self.0.to_vec(), self.1)
     }
 
-    /// Create a key slice from a slice. Will be removed in week 3.
-    pub fn from_slice(slice: &'a [u8]) -> Self {
-        Self(slice)
+    /// Create a key slice from a slice and a timestamp.
+    pub fn from_slice(slice: &'a [u8], ts: u64) -> Self {
+        Self(slice, ts)
     }
 
-    /// Always use `raw_ref` to access the key in week 1 + 2. This function will be removed in week 3.
-    pub fn raw_ref(self) -> &'a [u8] {
+    /// The user key part of the key, i.e., without the timestamp.
+    pub fn key_ref(self) -> &'a [u8] {
         self.0
     }
 
@@ -134,29 +232,29 @@ impl<'a> Key<&'a [u8]> {
     }
 
     pub fn for_testing_from_slice_no_ts(slice: &'a [u8]) -> Self {
-        Self(slice)
+        Self(slice, TS_DEFAULT)
     }
 
-    pub fn for_testing_from_slice_with_ts(slice: &'a [u8], _ts: u64) -> Self {
-        Self(slice)
+    pub fn for_testing_from_slice_with_ts(slice: &'a [u8], ts: u64) -> Self {
+        Self(slice, ts)
     }
 }
 
 impl<T: AsRef<[u8]> + Debug> Debug for Key<T> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.0.fmt(f)
+        f.debug_tuple("Key").field(&self.0).field(&self.1).finish()
     }
 }
 
 impl<T: AsRef<[u8]> + Default> Default for Key<T> {
     fn default() -> Self {
-        Self(T::default())
+        Self(T::default(), TS_DEFAULT)
     }
 }
 
 impl<T: AsRef<[u8]> + PartialEq> PartialEq for Key<T> {
     fn eq(&self, other: &Self) -> bool {
-        self.0.eq(&other.0)
+        (self.0.as_ref(), self.1).eq(&(other.0.as_ref(), other.1))
     }
 }
 
@@ -164,7 +262,7 @@ impl<T: AsRef<[u8]> + Eq> Eq for Key<T> {}
 
 impl<T: AsRef<[u8]> + Clone> Clone for Key<T> {
     fn clone(&self) -> Self {
-        Self(self.0.clone())
+        Self(self.0.clone(), self.1)
     }
 }
 
@@ -172,12 +270,19 @@ impl<T: AsRef<[u8]> + Copy> Copy for Key<T> {}
 
 impl<T: AsRef<[u8]> + PartialOrd> PartialOrd for Key<T> {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        self.0.partial_cmp(&other.0)
+        Some(self.cmp_key(other))
     }
 }
 
 impl<T: AsRef<[u8]> + Ord> Ord for Key<T> {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.0.cmp(&other.0)
+        self.cmp_key(other)
+    }
+}
+
+impl<T: AsRef<[u8]>> Key<T> {
+    /// Compare by the user key ascending, then by the timestamp descending.
+    fn cmp_key(&self, other: &Self) -> std::cmp::Ordering {
+        (self.0.as_ref(), Reverse(self.1)).cmp(&(other.0.as_ref(), Reverse(other.1)))
     }
 }
@@ -12,17 +12,32 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+pub mod backup;
 pub mod block;
+pub mod block_cache;
+pub mod checkpoint;
+pub mod column_family;
 pub mod compact;
 pub mod debug;
+pub mod error;
+pub mod export;
+pub mod failpoint;
 pub mod iterators;
 pub mod key;
 pub mod lsm_iterator;
 pub mod lsm_storage;
 pub mod manifest;
 pub mod mem_table;
+pub mod merge_operator;
+pub mod metrics;
 pub mod mvcc;
+pub mod range_tombstone;
+pub mod rate_limiter;
+pub mod replication;
+pub mod secondary_index;
 pub mod table;
+pub mod ttl;
+pub mod vlog;
 pub mod wal;
 
 #[cfg(test)]
@@ -12,39 +12,229 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-#![allow(unused_variables)] // TODO(you): remove this lint after implementing this mod
-#![allow(dead_code)] // TODO(you): remove this lint after implementing this mod
+use std::ops::Bound;
+use std::sync::Arc;
 
-use anyhow::Result;
+use anyhow::{Result, bail};
+use bytes::Bytes;
 
 use crate::{
-    iterators::{StorageIterator, merge_iterator::MergeIterator},
+    iterators::{
+        ReversibleIterator, StorageIterator, async_iterator::SpawnBlockingIterator,
+        concat_iterator::SstConcatIterator, merge_iterator::MergeIterator,
+        two_merge_iterator::TwoMergeIterator,
+    },
+    key::{KeySlice, TS_RANGE_BEGIN, TS_RANGE_END},
     mem_table::MemTableIterator,
+    merge_operator::{self, MergeOperator},
+    range_tombstone::{RangeTombstone, RangeTombstones},
+    ttl,
+    vlog::ValueLogSnapshot,
 };
 
 /// Represents the internal type for an LSM iterator. This type will be changed across the course for multiple times.
-type LsmIteratorInner = MergeIterator<MemTableIterator>;
+/// Each SST in L0 is a sorted run of its own.
+pub(crate) type LsmIteratorInner =
+    TwoMergeIterator<MergeIterator<MemTableIterator>, MergeIterator<SstConcatIterator>>;
 
+/// The iterator returned by `MiniLsm::scan_async`.
+pub type AsyncLsmIterator = SpawnBlockingIterator<FusedIterator<LsmIterator>>;
+
+/// Iterates over the user keys of the LSM tree within the bounds as of `read_ts`: each user key is
+/// yielded once, with its latest version whose timestamp <= `read_ts`, and deleted keys are skipped.
+/// A key is deleted if the latest version is a tombstone, is covered by a range tombstone, or has
+/// expired when the iterator is created. A separated value is read from the value log only when the
+/// iterator stops at its version. If the latest version is a merge operand, the operands down to the
+/// first version that is not one are folded with `merge_operator`, and an empty result deletes the
+/// key.
 pub struct LsmIterator {
     inner: LsmIteratorInner,
+    read_ts: u64,
+    lower: Bound<Bytes>,
+    upper: Bound<Bytes>,
+    range_tombstones: RangeTombstones,
+    /// Milliseconds since the epoch to check the expiry of values against.
+    now: u64,
+    value_logs: ValueLogSnapshot,
+    merge_operator: Option<Arc<dyn MergeOperator>>,
+    /// The value of the current version if it is separated, read from `value_logs`, or the folded
+    /// value if it is a merge operand.
+    resolved_value: Option<Bytes>,
 }
 
 impl LsmIterator {
-    pub(crate) fn new(iter: LsmIteratorInner) -> Result<Self> {
-        // TODO - skip the tombstone
-        let mut lsm_iter = Self { inner: iter };
+    pub(crate) fn new(
+        iter: LsmIteratorInner,
+        read_ts: u64,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        range_tombstones: RangeTombstones,
+        value_logs: ValueLogSnapshot,
+        merge_operator: Option<Arc<dyn MergeOperator>>,
+    ) -> Result<Self> {
+        let mut lsm_iter = Self {
+            inner: iter,
+            read_ts,
+            lower: lower.map(Bytes::copy_from_slice),
+            upper: upper.map(Bytes::copy_from_slice),
+            range_tombstones,
+            now: ttl::now(),
+            value_logs,
+            merge_operator,
+            resolved_value: None,
+        };
         lsm_iter.move_to_valid()?;
         Ok(lsm_iter)
     }
+
+    /// Resolve the value of the current version, as the latest visible version of its key, and
+    /// return whether the key is not deleted: a separated value is read from the value log, and
+    /// merge operands are folded.
+    fn resolve_value(&mut self) -> Result<bool> {
+        self.resolved_value = None;
+        let key = self.inner.key();
+        let value = self.inner.value();
+        if value.is_empty()
+            || self.range_tombstones.covers(key.key_ref(), key.ts())
+            || ttl::is_expired(value, self.now)
+        {
+            return Ok(false);
+        }
+        if ttl::is_merge_operand(value) {
+            let merged = self.merge_operands()?;
+            let live = !merged.is_empty();
+            self.resolved_value = Some(merged);
+            return Ok(live);
+        }
+        if ttl::is_separated(value) {
+            let raw = Bytes::copy_from_slice(value);
+            self.resolved_value = Some(self.value_logs.resolve(&raw)?);
+        }
+        Ok(true)
+    }
+
+    /// Fold the merge operands from the current version down to the first version of its key that
+    /// is not one, and move back to the current version.
+    fn merge_operands(&mut self) -> Result<Bytes> {
+        let user_key = Bytes::copy_from_slice(self.inner.key().key_ref());
+        let Some(operator) = self.merge_operator.clone() else {
+            bail!(
+                "no merge operator to read the merge operands of {:?}",
+                user_key
+            );
+        };
+        let ts = self.inner.key().ts();
+        let mut operands = Vec::new();
+        let mut base = None;
+        while self.inner.is_valid() && self.inner.key().key_ref() == user_key {
+            let key = self.inner.key();
+            let value = self.inner.value();
+            if self.range_tombstones.covers(key.key_ref(), key.ts()) {
+                break;
+            }
+            if !ttl::is_merge_operand(value) {
+                base = Some(Bytes::copy_from_slice(value));
+                break;
+            }
+            operands.push(Bytes::copy_from_slice(value));
+            self.inner.next()?;
+        }
+        self.inner
+            .seek_for_prev(KeySlice::from_slice(&user_key, ts))?;
+        merge_operator::fold(
+            &operator,
+            &user_key,
+            base.as_ref(),
+            &operands,
+            self.now,
+            &self.value_logs,
+        )
+    }
+
+    fn above_lower(&self, key: &[u8]) -> bool {
+        let comparator = self.inner.comparator();
+        match &self.lower {
+            Bound::Included(lower) => comparator.compare(key, lower).is_ge(),
+            Bound::Excluded(lower) => comparator.compare(key, lower).is_gt(),
+            Bound::Unbounded => true,
+        }
+    }
+
+    fn below_upper(&self, key: &[u8]) -> bool {
+        let comparator = self.inner.comparator();
+        match &self.upper {
+            Bound::Included(upper) => comparator.compare(key, upper).is_le(),
+            Bound::Excluded(upper) => comparator.compare(key, upper).is_lt(),
+            Bound::Unbounded => true,
+        }
+    }
+
+    /// Move forward to the latest visible version of a user key that is not deleted, starting from
+    /// the current entry.
     fn move_to_valid(&mut self) -> Result<()> {
-        while self.inner.is_valid() {
-            if !self.inner.value().is_empty() {
+        while self.inner.is_valid() && self.below_upper(self.inner.key().key_ref()) {
+            if self.inner.key().ts() > self.read_ts {
+                self.inner.next()?;
+                continue;
+            }
+            // the first version within `read_ts` is the latest visible one
+            if self.resolve_value()? {
                 break;
             }
+            self.skip_versions()?;
+        }
+        Ok(())
+    }
+
+    /// Move forward past the remaining versions of the current user key.
+    fn skip_versions(&mut self) -> Result<()> {
+        let user_key = self.inner.key().key_ref().to_vec();
+        while self.inner.is_valid() && self.inner.key().key_ref() == user_key {
             self.inner.next()?;
         }
         Ok(())
     }
+
+    /// Move backward past all versions of `user_key`, which the iterator is at or after.
+    fn move_before(&mut self, user_key: &[u8]) -> Result<()> {
+        self.inner
+            .seek_for_prev(KeySlice::from_slice(user_key, TS_RANGE_BEGIN))?;
+        while self.inner.is_valid() && self.inner.key().key_ref() == user_key {
+            self.inner.prev()?;
+        }
+        Ok(())
+    }
+
+    /// Mirror of `move_to_valid` when moving backward: find the last user key up to the current one
+    /// that is visible and not deleted, and move to its latest visible version.
+    fn move_to_valid_backward(&mut self) -> Result<()> {
+        while self.inner.is_valid() {
+            let user_key = self.inner.key().key_ref().to_vec();
+            if !self.above_lower(&user_key) {
+                break;
+            }
+            if self.below_upper(&user_key) {
+                // moving backward goes from the earliest version to the latest
+                let mut visible_ts = None;
+                while self.inner.is_valid()
+                    && self.inner.key().key_ref() == user_key
+                    && self.inner.key().ts() <= self.read_ts
+                {
+                    visible_ts = Some(self.inner.key().ts());
+                    self.inner.prev()?;
+                }
+                if let Some(ts) = visible_ts {
+                    self.inner
+                        .seek_for_prev(KeySlice::from_slice(&user_key, ts))?;
+                    if self.resolve_value()? {
+                        break;
+                    }
+                }
+            }
+            self.move_before(&user_key)?;
+        }
+        Ok(())
+    }
 }
 
 impl StorageIterator for LsmIterator {
@@ -52,18 +242,184 @@ impl StorageIterator for LsmIterator {
 
     fn is_valid(&self) -> bool {
         self.inner.is_valid()
+            && self.above_lower(self.inner.key().key_ref())
+            && self.below_upper(self.inner.key().key_ref())
     }
 
     fn key(&self) -> &[u8] {
-        self.inner.key().raw_ref()
+        self.inner.key().key_ref()
+    }
+
+    fn value(&self) -> &[u8] {
+        match &self.resolved_value {
+            Some(value) => value,
+            None => ttl::decode_value(self.inner.value()).0,
+        }
+    }
+
+    fn next(&mut self) -> Result<()> {
+        self.skip_versions()?;
+        self.move_to_valid()
+    }
+
+    fn num_active_iterators(&self) -> usize {
+        self.inner.num_active_iterators()
+    }
+}
+
+impl ReversibleIterator for LsmIterator {
+    fn prev(&mut self) -> Result<()> {
+        let user_key = self.inner.key().key_ref().to_vec();
+        self.move_before(&user_key)?;
+        self.move_to_valid_backward()
+    }
+
+    /// Move to the last user key that <= the user key of `key`.
+    fn seek_for_prev(&mut self, key: KeySlice) -> Result<()> {
+        // start from the upper bound if `key` is beyond it
+        let user_key = match &self.upper {
+            Bound::Included(upper) | Bound::Excluded(upper) if !self.below_upper(key.key_ref()) => {
+                upper.clone()
+            }
+            _ => Bytes::copy_from_slice(key.key_ref()),
+        };
+        self.inner
+            .seek_for_prev(KeySlice::from_slice(&user_key, TS_RANGE_END))?;
+        self.move_to_valid_backward()
+    }
+}
+
+/// What a version yielded by `ChangeIterator` writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Put,
+    /// A tombstone, whose value is empty.
+    Delete,
+    /// A merge operand, see `MergeOperator`.
+    Merge,
+}
+
+/// A version of a key, see `LsmStorageInner::history`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyVersion {
+    pub ts: u64,
+    pub kind: ChangeKind,
+    /// The value written, the operand of a merge, or empty for a delete.
+    pub value: Bytes,
+    /// The expiry of the value in milliseconds since the epoch, if it is written with a TTL.
+    pub expire_at: Option<u64>,
+}
+
+/// Iterates over the versions committed after `since_ts` and at or before `read_ts`, for
+/// consumers to apply the changes incrementally, see `LsmStorageInner::scan_changes`. The versions
+/// are ordered by user key, and from the latest to the earliest for a key. Unlike `LsmIterator`,
+/// every version is yielded, with its timestamp, including the tombstones, the merge operands, and
+/// the values that expired or are covered by a range tombstone; the range deletes are returned by
+/// `range_deletes` instead. A separated value is read from the value log only when the iterator
+/// stops at its version.
+pub struct ChangeIterator {
+    inner: LsmIteratorInner,
+    since_ts: u64,
+    read_ts: u64,
+    /// Sorted by their timestamps.
+    range_deletes: Vec<RangeTombstone>,
+    value_logs: ValueLogSnapshot,
+    /// The value of the current version if it is separated, read from `value_logs`.
+    resolved_value: Option<Bytes>,
+}
+
+impl ChangeIterator {
+    pub(crate) fn new(
+        iter: LsmIteratorInner,
+        since_ts: u64,
+        read_ts: u64,
+        range_deletes: Vec<RangeTombstone>,
+        value_logs: ValueLogSnapshot,
+    ) -> Result<Self> {
+        let mut change_iter = Self {
+            inner: iter,
+            since_ts,
+            read_ts,
+            range_deletes,
+            value_logs,
+            resolved_value: None,
+        };
+        change_iter.move_to_valid()?;
+        Ok(change_iter)
+    }
+
+    /// The timestamp the changes are read up to, i.e., the `since_ts` of the next call to pick up
+    /// from.
+    pub fn read_ts(&self) -> u64 {
+        self.read_ts
+    }
+
+    /// The range deletes committed after `since_ts` and at or before `read_ts`, from the earliest
+    /// to the latest.
+    pub fn range_deletes(&self) -> &[RangeTombstone] {
+        &self.range_deletes
+    }
+
+    pub fn kind(&self) -> ChangeKind {
+        let value = self.inner.value();
+        if value.is_empty() {
+            ChangeKind::Delete
+        } else if ttl::is_merge_operand(value) {
+            ChangeKind::Merge
+        } else {
+            ChangeKind::Put
+        }
+    }
+
+    /// The expiry of the current value in milliseconds since the epoch, if it is written with a
+    /// TTL.
+    pub fn expire_at(&self) -> Option<u64> {
+        ttl::decode_value(self.inner.value()).1
+    }
+
+    /// Move forward to the first version within the timestamps, starting from the current entry.
+    fn move_to_valid(&mut self) -> Result<()> {
+        while self.inner.is_valid()
+            && !(self.since_ts + 1..=self.read_ts).contains(&self.inner.key().ts())
+        {
+            self.inner.next()?;
+        }
+        self.resolved_value = None;
+        if self.inner.is_valid() && ttl::is_separated(self.inner.value()) {
+            let raw = Bytes::copy_from_slice(self.inner.value());
+            self.resolved_value = Some(self.value_logs.resolve(&raw)?);
+        }
+        Ok(())
+    }
+}
+
+impl StorageIterator for ChangeIterator {
+    type KeyType<'a> = KeySlice<'a>;
+
+    fn is_valid(&self) -> bool {
+        self.inner.is_valid()
+    }
+
+    /// The user key and the commit timestamp of the version.
+    fn key(&self) -> KeySlice<'_> {
+        self.inner.key()
     }
 
+    /// The value written, the operand of a merge, or empty for a tombstone.
     fn value(&self) -> &[u8] {
-        self.inner.value()
+        match &self.resolved_value {
+            Some(value) => value,
+            None => ttl::decode_value(self.inner.value()).0,
+        }
     }
 
     fn next(&mut self) -> Result<()> {
-        self.inner.next()
+        self.inner.next()?;
+        self.move_to_valid()
+    }
+
+    fn num_active_iterators(&self) -> usize {
+        self.inner.num_active_iterators()
     }
 }
 
@@ -123,4 +479,36 @@ impl<I: StorageIterator> StorageIterator for FusedIterator<I> {
 
         Ok(())
     }
+
+    fn num_active_iterators(&self) -> usize {
+        self.iter.num_active_iterators()
+    }
+}
+
+/// Moving backward is fused the same way as `next`: `prev` on an invalid iterator does nothing, and
+/// the iterator stays unusable after an error.
+impl<I: ReversibleIterator> ReversibleIterator for FusedIterator<I> {
+    fn prev(&mut self) -> Result<()> {
+        if self.has_errored {
+            return Err(anyhow::anyhow!("cannot use again"));
+        }
+        if self.iter.is_valid()
+            && let Err(e) = self.iter.prev()
+        {
+            self.has_errored = true;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn seek_for_prev(&mut self, key: KeySlice) -> Result<()> {
+        if self.has_errored {
+            return Err(anyhow::anyhow!("cannot use again"));
+        }
+        if let Err(e) = self.iter.seek_for_prev(key) {
+            self.has_errored = true;
+            return Err(e);
+        }
+        Ok(())
+    }
 }
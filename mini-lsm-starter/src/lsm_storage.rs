@@ -15,29 +15,60 @@
 #![allow(unused_variables)] // TODO(you): remove this lint after implementing this mod
 #![allow(dead_code)] // TODO(you): remove this lint after implementing this mod
 
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
 use std::ops::Bound;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 
-use anyhow::Result;
+use anyhow::{Context, Result, bail};
 use bytes::Bytes;
 use parking_lot::{Mutex, MutexGuard, RwLock};
 
-use crate::block::Block;
+pub use crate::block_cache::{BlockCache, BlockCachePolicy, CacheStats};
+use crate::column_family::ColumnFamily;
 use crate::compact::{
-    CompactionController, CompactionOptions, LeveledCompactionController, LeveledCompactionOptions,
-    SimpleLeveledCompactionController, SimpleLeveledCompactionOptions, TieredCompactionController,
+    CompactionController, CompactionOptions, LeveledCompactionOptions,
+    SimpleLeveledCompactionOptions,
 };
+use crate::error::Error;
+use crate::export::SnapshotDescriptor;
+use crate::iterators::StorageIterator;
+use crate::iterators::async_iterator::SpawnBlockingIterator;
+use crate::iterators::concat_iterator::SstConcatIterator;
 use crate::iterators::merge_iterator::MergeIterator;
-use crate::lsm_iterator::{FusedIterator, LsmIterator};
-use crate::manifest::Manifest;
+use crate::iterators::two_merge_iterator::TwoMergeIterator;
+use crate::key::{
+    BYTEWISE_COMPARATOR_NAME, Comparator, KeyBytes, KeyComparator, KeySlice, TS_DEFAULT,
+    TS_RANGE_BEGIN, TS_RANGE_END, bytewise_comparator,
+};
+use crate::lsm_iterator::{
+    AsyncLsmIterator, ChangeIterator, ChangeKind, FusedIterator, KeyVersion, LsmIterator,
+    LsmIteratorInner,
+};
+use crate::manifest::{Manifest, ManifestRecord};
 use crate::mem_table::{MemTable, MemTableIterator};
-use crate::mvcc::LsmMvccInner;
-use crate::table::SsTable;
-
-pub type BlockCache = moka::sync::Cache<(usize, usize), Arc<Block>>;
+use crate::merge_operator::MergeOperator;
+use crate::metrics::Metrics;
+use crate::mvcc::txn::Transaction;
+use crate::mvcc::{LsmMvccInner, MvccStorage, Snapshot};
+use crate::range_tombstone::{RangeTombstone, RangeTombstones};
+use crate::rate_limiter::{IoPriority, RateLimiter};
+use crate::replication::{
+    ReplicationBatch, ReplicationLog, ReplicationOp, ReplicationRecord, Subscription,
+};
+use crate::secondary_index::{Index, IndexExtractor, IndexIterator};
+use crate::table::{
+    DIRECT_IO_ALIGNMENT, DirectIoWriter, FileObject, MmapWriter, PrefixExtractor,
+    RateLimitedWriter, SsTable, SsTableBuilder, TablePropertiesCollectorFactory,
+};
+use crate::ttl;
+use crate::vlog::{ValueLogFile, ValueLogHead, ValueLogSnapshot, ValueLogs, ValuePointer};
+pub use crate::wal::SyncPolicy;
+use crate::wal::Wal;
 
 /// Represents the state of the storage engine.
 #[derive(Clone)]
@@ -58,9 +89,110 @@ pub struct LsmStorageState {
 pub enum WriteBatchRecord<T: AsRef<[u8]>> {
     Put(T, T),
     Del(T),
+    /// A merge operand, see `LsmStorageInner::merge`.
+    Merge(T, T),
+}
+
+/// A group of puts, deletes and merges that `LsmStorageInner::write_batch` commits together.
+#[derive(Default)]
+pub struct WriteBatch {
+    records: Vec<WriteBatchRecord<Bytes>>,
+}
+
+impl WriteBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn put(&mut self, key: &[u8], value: &[u8]) -> &mut Self {
+        self.records.push(WriteBatchRecord::Put(
+            Bytes::copy_from_slice(key),
+            Bytes::copy_from_slice(value),
+        ));
+        self
+    }
+
+    pub fn delete(&mut self, key: &[u8]) -> &mut Self {
+        self.records
+            .push(WriteBatchRecord::Del(Bytes::copy_from_slice(key)));
+        self
+    }
+
+    pub fn merge(&mut self, key: &[u8], operand: &[u8]) -> &mut Self {
+        self.records.push(WriteBatchRecord::Merge(
+            Bytes::copy_from_slice(key),
+            Bytes::copy_from_slice(operand),
+        ));
+        self
+    }
+
+    pub fn records(&self) -> &[WriteBatchRecord<Bytes>] {
+        &self.records
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
 }
 
 impl LsmStorageState {
+    /// Whether any memtable, SST or range tombstone has user keys in `first..=last`.
+    fn overlaps(&self, first: &[u8], last: &[u8]) -> bool {
+        let in_memtables = std::iter::once(&self.memtable)
+            .chain(self.imm_memtables.iter())
+            .any(|memtable| {
+                memtable
+                    .scan(Bound::Included(first), Bound::Included(last))
+                    .is_valid()
+                    || memtable
+                        .range_tombstones()
+                        .iter()
+                        .any(|tombstone| tombstone.overlaps(first, last))
+            });
+        in_memtables
+            || self.sstables.values().any(|sst| {
+                (sst.num_of_blocks() > 0
+                    && sst.first_key().key_ref() <= last
+                    && first <= sst.last_key().key_ref())
+                    || sst
+                        .range_tombstones()
+                        .iter()
+                        .any(|tombstone| tombstone.overlaps(first, last))
+            })
+    }
+
+    /// The range tombstones of all memtables and SSTs that are visible at `read_ts`.
+    pub(crate) fn range_tombstones(&self, read_ts: u64) -> RangeTombstones {
+        let memtables = std::iter::once(&self.memtable)
+            .chain(self.imm_memtables.iter())
+            .flat_map(|memtable| memtable.range_tombstones());
+        let ssts = self
+            .sstables
+            .values()
+            .flat_map(|sst| sst.range_tombstones().iter().cloned());
+        RangeTombstones::new(memtables.chain(ssts), read_ts)
+    }
+
+    /// Add ingested SSTs to the level with the id `level`, or as the oldest tier if `None`. The
+    /// SSTs of a level are to be sorted by their first keys afterwards.
+    fn install_ingested(&mut self, level: Option<usize>, sst_ids: &[usize]) {
+        match level {
+            Some(level) => {
+                let (_, ssts) = self
+                    .levels
+                    .iter_mut()
+                    .find(|(id, _)| *id == level)
+                    .expect("ingested into an existing level");
+                ssts.extend_from_slice(sst_ids);
+            }
+            None => self.levels.push((sst_ids[0], sst_ids.to_vec())),
+        }
+    }
+
     fn create(options: &LsmStorageOptions) -> Self {
         let levels = match &options.compaction_options {
             CompactionOptions::Leveled(LeveledCompactionOptions { max_levels, .. })
@@ -72,7 +204,7 @@ impl LsmStorageState {
             CompactionOptions::NoCompaction => vec![(1, Vec::new())],
         };
         Self {
-            memtable: Arc::new(MemTable::create(0)),
+            memtable: Arc::new(MemTable::create(0).with_comparator(options.comparator.clone())),
             imm_memtables: Vec::new(),
             l0_sstables: Vec::new(),
             levels,
@@ -89,9 +221,64 @@ pub struct LsmStorageOptions {
     pub target_sst_size: usize,
     // Maximum number of memtables in memory, flush to L0 when exceeding this limit
     pub num_memtable_limit: usize,
+    // Maximum number of immutable memtables waiting for the flush thread, a write freezing the
+    // memtable beyond it flushes the oldest ones itself, `None` for no limit
+    pub max_imm_memtables: Option<usize>,
+    // Freeze the memtable once it reaches this many bytes, `target_sst_size` if `None`
+    pub memtable_max_size: Option<usize>,
+    // Freeze the memtable once it holds this many versions and range tombstones, `None` for no
+    // limit
+    pub memtable_max_entries: Option<usize>,
+    // Freeze the memtable once its WAL reaches this many bytes, `None` for no limit
+    pub memtable_max_wal_size: Option<u64>,
     pub compaction_options: CompactionOptions,
+    // Split a compaction into at most this many subcompactions over disjoint key ranges
+    pub max_subcompactions: usize,
+    // Number of threads running the subcompactions of a compaction
+    pub compaction_threads: usize,
     pub enable_wal: bool,
+    // When the writes to the WAL are synced to the disk
+    pub sync_policy: SyncPolicy,
     pub serializable: bool,
+    // Maximum number of blocks in the block cache
+    pub block_cache_capacity: u64,
+    pub block_cache_policy: BlockCachePolicy,
+    // Read SSTs through memory mappings instead of syscalls
+    pub use_mmap: bool,
+    // Read and write SSTs with direct I/O bypassing the page cache, so that the block cache is the
+    // only cache of the SSTs, overrides `use_mmap`
+    pub use_direct_io: bool,
+    // Keep a log of the committed writes in `replication/` for the followers, see `subscribe`
+    pub enable_replication_log: bool,
+    // Expire the values written without an explicit TTL after this duration
+    pub default_ttl: Option<Duration>,
+    // Store values of at least this many bytes in the value log, `None` to keep all values inline
+    pub value_threshold: Option<usize>,
+    // Value log file size in bytes, a new file is started when exceeding this limit
+    pub value_log_file_size: usize,
+    // Garbage-collect a value log file once this fraction of its bytes is dead
+    pub value_log_gc_ratio: f64,
+    // Limit the SSTs written by flushes and compactions to this many bytes per second, `None` for
+    // no limit
+    pub background_write_rate: Option<u64>,
+    // Bytes the background writes may burst above the rate limit
+    pub background_write_burst: u64,
+    // Options of the column families by name when reopening them, the others use these options
+    pub column_family_options: HashMap<String, LsmStorageOptions>,
+    // Folds the operands written by `merge`, which must be set to write or read them
+    pub merge_operator: Option<Arc<dyn MergeOperator>>,
+    // Prefetch this many bytes of blocks ahead of a scan or a compaction moving through an SST
+    // sequentially, 0 to read blocks on demand only
+    pub readahead_bytes: usize,
+    // Create the collectors of the user-defined properties of each SST flushed or compacted
+    pub table_properties_collectors: Vec<Arc<dyn TablePropertiesCollectorFactory>>,
+    // Add the prefixes of the keys to the bloom filters of the SSTs flushed or compacted, for
+    // `prefix_scan` to skip the SSTs without the prefix
+    pub prefix_extractor: Option<Arc<dyn PrefixExtractor>>,
+    // Orders the user keys, persisted in the manifest so that the storage is never reopened with
+    // another order. Range deletes, prefix scans, transactions and ingesting SSTs require the
+    // default bytewise order
+    pub comparator: Comparator,
 }
 
 impl LsmStorageOptions {
@@ -100,9 +287,33 @@ impl LsmStorageOptions {
             block_size: 4096,
             target_sst_size: 2 << 20,
             compaction_options: CompactionOptions::NoCompaction,
+            max_subcompactions: 1,
+            compaction_threads: 1,
             enable_wal: false,
+            sync_policy: SyncPolicy::Never,
             num_memtable_limit: 50,
+            max_imm_memtables: None,
+            memtable_max_size: None,
+            memtable_max_entries: None,
+            memtable_max_wal_size: None,
             serializable: false,
+            block_cache_capacity: 1024,
+            block_cache_policy: BlockCachePolicy::Lru,
+            use_mmap: false,
+            use_direct_io: false,
+            enable_replication_log: false,
+            default_ttl: None,
+            value_threshold: None,
+            value_log_file_size: 64 << 20,
+            value_log_gc_ratio: 0.5,
+            background_write_rate: None,
+            background_write_burst: 1 << 20,
+            column_family_options: HashMap::new(),
+            merge_operator: None,
+            readahead_bytes: 0,
+            table_properties_collectors: Vec::new(),
+            prefix_extractor: None,
+            comparator: bytewise_comparator(),
         }
     }
 
@@ -111,9 +322,33 @@ impl LsmStorageOptions {
             block_size: 4096,
             target_sst_size: 2 << 20,
             compaction_options: CompactionOptions::NoCompaction,
+            max_subcompactions: 1,
+            compaction_threads: 1,
             enable_wal: false,
+            sync_policy: SyncPolicy::Never,
             num_memtable_limit: 2,
+            max_imm_memtables: None,
+            memtable_max_size: None,
+            memtable_max_entries: None,
+            memtable_max_wal_size: None,
             serializable: false,
+            block_cache_capacity: 1024,
+            block_cache_policy: BlockCachePolicy::Lru,
+            use_mmap: false,
+            use_direct_io: false,
+            enable_replication_log: false,
+            default_ttl: None,
+            value_threshold: None,
+            value_log_file_size: 64 << 20,
+            value_log_gc_ratio: 0.5,
+            background_write_rate: None,
+            background_write_burst: 1 << 20,
+            column_family_options: HashMap::new(),
+            merge_operator: None,
+            readahead_bytes: 0,
+            table_properties_collectors: Vec::new(),
+            prefix_extractor: None,
+            comparator: bytewise_comparator(),
         }
     }
 
@@ -122,23 +357,79 @@ impl LsmStorageOptions {
             block_size: 4096,
             target_sst_size: 1 << 20, // 1MB
             compaction_options,
+            max_subcompactions: 1,
+            compaction_threads: 1,
             enable_wal: false,
+            sync_policy: SyncPolicy::Never,
             num_memtable_limit: 2,
+            max_imm_memtables: None,
+            memtable_max_size: None,
+            memtable_max_entries: None,
+            memtable_max_wal_size: None,
             serializable: false,
+            block_cache_capacity: 1024,
+            block_cache_policy: BlockCachePolicy::Lru,
+            use_mmap: false,
+            use_direct_io: false,
+            enable_replication_log: false,
+            default_ttl: None,
+            value_threshold: None,
+            value_log_file_size: 64 << 20,
+            value_log_gc_ratio: 0.5,
+            background_write_rate: None,
+            background_write_burst: 1 << 20,
+            column_family_options: HashMap::new(),
+            merge_operator: None,
+            readahead_bytes: 0,
+            table_properties_collectors: Vec::new(),
+            prefix_extractor: None,
+            comparator: bytewise_comparator(),
         }
     }
 }
 
+/// What a compaction filter does with a key-value pair.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CompactionDecision {
+    Keep,
+    /// Delete the key, as if it were deleted when the version was written.
+    Remove,
+    /// Replace the value of the version.
+    ChangeValue(Bytes),
+}
+
+/// A user callback that drops or rewrites key-value pairs during compaction, e.g., to expire
+/// them. It is only called on the latest version of each key at or below the watermark, as the
+/// newer versions may still be read by snapshots and the older ones are dropped anyway, and never
+/// on tombstones. A value separated into the value log is passed as its encoded `ValuePointer`.
+pub trait CustomCompactionFilter: std::fmt::Debug + Send + Sync {
+    fn filter(&self, key: KeySlice, value: &[u8]) -> CompactionDecision;
+}
+
 #[derive(Clone, Debug)]
 pub enum CompactionFilter {
+    /// Remove the keys with the prefix.
     Prefix(Bytes),
+    Custom(Arc<dyn CustomCompactionFilter>),
+}
+
+impl CompactionFilter {
+    pub fn filter(&self, key: KeySlice, value: &[u8]) -> CompactionDecision {
+        match self {
+            CompactionFilter::Prefix(prefix) if key.key_ref().starts_with(prefix) => {
+                CompactionDecision::Remove
+            }
+            CompactionFilter::Prefix(_) => CompactionDecision::Keep,
+            CompactionFilter::Custom(filter) => filter.filter(key, value),
+        }
+    }
 }
 
 /// The storage interface of the LSM tree.
 pub(crate) struct LsmStorageInner {
     pub(crate) state: Arc<RwLock<Arc<LsmStorageState>>>,
     pub(crate) state_lock: Mutex<()>,
-    path: PathBuf,
+    pub(crate) path: PathBuf,
     pub(crate) block_cache: Arc<BlockCache>,
     next_sst_id: AtomicUsize,
     pub(crate) options: Arc<LsmStorageOptions>,
@@ -146,6 +437,47 @@ pub(crate) struct LsmStorageInner {
     pub(crate) manifest: Option<Manifest>,
     pub(crate) mvcc: Option<LsmMvccInner>,
     pub(crate) compaction_filters: Arc<Mutex<Vec<CompactionFilter>>>,
+    /// The value log files that separated values are read from.
+    pub(crate) value_logs: ValueLogs,
+    /// The value log file that separated values are appended to, created on the first one.
+    pub(crate) value_log_head: Mutex<Option<ValueLogHead>>,
+    /// Serializes the garbage collection runs of the value log.
+    pub(crate) value_log_gc_lock: Mutex<()>,
+    /// Shared by flushes and compactions, set with `background_write_rate`.
+    pub(crate) rate_limiter: Option<RateLimiter>,
+    /// Serializes the compactions, so that a manual compaction does not pick the inputs of a
+    /// running one.
+    pub(crate) compaction_lock: Mutex<()>,
+    /// Serializes the flushes, which build the SST of the oldest immutable memtable without
+    /// holding `state_lock`, so that the writes can freeze memtables meanwhile.
+    pub(crate) flush_lock: Mutex<()>,
+    /// Opened with `open_readonly`, rejecting all writes.
+    pub(crate) read_only: bool,
+    /// The column families other than the default one, by name.
+    pub(crate) column_families: RwLock<BTreeMap<String, Arc<ColumnFamily>>>,
+    pub(crate) metrics: Arc<Metrics>,
+    /// The log of the committed writes, with `enable_replication_log`.
+    pub(crate) replication_log: Option<Arc<ReplicationLog>>,
+    /// The registered secondary indexes, by name.
+    pub(crate) indexes: RwLock<BTreeMap<String, Index>>,
+}
+
+/// A flush running in the background, see `LsmStorageInner::flush_async`.
+pub struct FlushHandle {
+    handle: std::thread::JoinHandle<Result<()>>,
+}
+
+impl FlushHandle {
+    pub fn is_finished(&self) -> bool {
+        self.handle.is_finished()
+    }
+
+    /// Wait until the flushed memtables are SSTs.
+    pub fn wait(self) -> Result<()> {
+        self.handle
+            .join()
+            .map_err(|e| anyhow::anyhow!("flush thread panicked: {:?}", e))?
+    }
 }
 
 /// A thin wrapper for `LsmStorageInner` and the user interface for MiniLSM.
@@ -159,41 +491,131 @@ pub struct MiniLsm {
     compaction_notifier: crossbeam_channel::Sender<()>,
     /// The handle for the compaction thread. (In week 2)
     compaction_thread: Mutex<Option<std::thread::JoinHandle<()>>>,
+    /// Notifies the value log garbage collection thread to stop working.
+    value_log_gc_notifier: crossbeam_channel::Sender<()>,
+    /// The handle for the value log garbage collection thread.
+    value_log_gc_thread: Mutex<Option<std::thread::JoinHandle<()>>>,
+    /// Notifies the WAL sync thread to stop working.
+    wal_sync_notifier: crossbeam_channel::Sender<()>,
+    /// The handle for the WAL sync thread, only for `SyncPolicy::Interval`.
+    wal_sync_thread: Mutex<Option<std::thread::JoinHandle<()>>>,
 }
 
 impl Drop for MiniLsm {
     fn drop(&mut self) {
         self.compaction_notifier.send(()).ok();
         self.flush_notifier.send(()).ok();
+        self.value_log_gc_notifier.send(()).ok();
+        self.wal_sync_notifier.send(()).ok();
     }
 }
 
 impl MiniLsm {
+    /// Stop the background threads and persist the memtables: with WAL enabled, the WALs are
+    /// synced and replayed on the next open; otherwise, all memtables are flushed to SSTs.
     pub fn close(&self) -> Result<()> {
-        unimplemented!()
+        self.compaction_notifier.send(()).ok();
+        self.flush_notifier.send(()).ok();
+        self.value_log_gc_notifier.send(()).ok();
+        self.wal_sync_notifier.send(()).ok();
+        for thread in [
+            &self.compaction_thread,
+            &self.flush_thread,
+            &self.value_log_gc_thread,
+            &self.wal_sync_thread,
+        ] {
+            if let Some(handle) = thread.lock().take() {
+                handle
+                    .join()
+                    .map_err(|e| anyhow::anyhow!("background thread panicked: {:?}", e))?;
+            }
+        }
+
+        if self.inner.read_only {
+            return Ok(());
+        }
+        if self.inner.options.enable_wal {
+            self.inner.sync()?;
+            self.inner.sync_dir()?;
+            return Ok(());
+        }
+        self.inner.flush_column_families()?;
+        if !self.inner.state.read().memtable.is_empty() {
+            self.inner
+                .force_freeze_memtable(&self.inner.state_lock.lock())?;
+        }
+        while !self.inner.state.read().imm_memtables.is_empty() {
+            self.inner.force_flush_next_imm_memtable()?;
+        }
+        self.inner.sync_dir()
     }
 
     /// Start the storage engine by either loading an existing directory or creating a new one if the directory does
     /// not exist.
     pub fn open(path: impl AsRef<Path>, options: LsmStorageOptions) -> Result<Arc<Self>> {
-        let inner = Arc::new(LsmStorageInner::open(path, options)?);
+        let block_cache =
+            BlockCache::with_policy(options.block_cache_capacity, options.block_cache_policy);
+        Self::open_with_block_cache(path, options, Arc::new(block_cache))
+    }
+
+    /// Start the storage engine with a block cache shared with other engines. Each engine should
+    /// use a handle with its own namespace, see `BlockCache::with_namespace`.
+    pub fn open_with_block_cache(
+        path: impl AsRef<Path>,
+        options: LsmStorageOptions,
+        block_cache: Arc<BlockCache>,
+    ) -> Result<Arc<Self>> {
+        let inner = Arc::new(LsmStorageInner::open_with_block_cache(
+            path,
+            options,
+            block_cache,
+        )?);
         let (tx1, rx) = crossbeam_channel::unbounded();
         let compaction_thread = inner.spawn_compaction_thread(rx)?;
         let (tx2, rx) = crossbeam_channel::unbounded();
         let flush_thread = inner.spawn_flush_thread(rx)?;
+        let (tx3, rx) = crossbeam_channel::unbounded();
+        let value_log_gc_thread = inner.spawn_value_log_gc_thread(rx)?;
+        let (tx4, rx) = crossbeam_channel::unbounded();
+        let wal_sync_thread = inner.spawn_wal_sync_thread(rx)?;
         Ok(Arc::new(Self {
             inner,
             flush_notifier: tx2,
             flush_thread: Mutex::new(flush_thread),
             compaction_notifier: tx1,
             compaction_thread: Mutex::new(compaction_thread),
+            value_log_gc_notifier: tx3,
+            value_log_gc_thread: Mutex::new(value_log_gc_thread),
+            wal_sync_notifier: tx4,
+            wal_sync_thread: Mutex::new(wal_sync_thread),
         }))
     }
 
-    pub fn new_txn(&self) -> Result<()> {
+    /// Open the storage for reading only, without starting any background thread, see
+    /// `LsmStorageInner::open_readonly`.
+    pub fn open_readonly(path: impl AsRef<Path>, options: LsmStorageOptions) -> Result<Arc<Self>> {
+        let inner = Arc::new(LsmStorageInner::open_readonly(path, options)?);
+        Ok(Arc::new(Self {
+            inner,
+            flush_notifier: crossbeam_channel::unbounded().0,
+            flush_thread: Mutex::new(None),
+            compaction_notifier: crossbeam_channel::unbounded().0,
+            compaction_thread: Mutex::new(None),
+            value_log_gc_notifier: crossbeam_channel::unbounded().0,
+            value_log_gc_thread: Mutex::new(None),
+            wal_sync_notifier: crossbeam_channel::unbounded().0,
+            wal_sync_thread: Mutex::new(None),
+        }))
+    }
+
+    pub fn new_txn(&self) -> Result<Arc<Transaction>> {
         self.inner.new_txn()
     }
 
+    pub fn new_snapshot(&self) -> Snapshot {
+        self.inner.new_snapshot()
+    }
+
     pub fn write_batch<T: AsRef<[u8]>>(&self, batch: &[WriteBatchRecord<T>]) -> Result<()> {
         self.inner.write_batch(batch)
     }
@@ -206,6 +628,31 @@ impl MiniLsm {
         self.inner.get(key)
     }
 
+    pub fn get_at(&self, key: &[u8], ts: u64) -> Result<Option<Bytes>> {
+        self.inner.get_at(key, ts)
+    }
+
+    pub fn multi_get(&self, keys: &[&[u8]]) -> Result<Vec<Option<Bytes>>> {
+        self.inner.multi_get(keys)
+    }
+
+    pub fn cache_stats(&self) -> HashMap<usize, CacheStats> {
+        self.inner.cache_stats()
+    }
+
+    /// The metrics of the storage, without those of the column families, see `Metrics::snapshot`.
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.inner.metrics.clone()
+    }
+
+    pub fn approximate_size(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<u64> {
+        self.inner.approximate_size(lower, upper)
+    }
+
+    pub fn approximate_num_keys(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<u64> {
+        self.inner.approximate_num_keys(lower, upper)
+    }
+
     pub fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
         self.inner.put(key, value)
     }
@@ -214,10 +661,103 @@ impl MiniLsm {
         self.inner.delete(key)
     }
 
+    pub fn merge(&self, key: &[u8], operand: &[u8]) -> Result<()> {
+        self.inner.merge(key, operand)
+    }
+
+    pub fn put_with_ttl(&self, key: &[u8], value: &[u8], ttl: Duration) -> Result<()> {
+        self.inner.put_with_ttl(key, value, ttl)
+    }
+
+    pub fn ingest_sst(&self, paths: &[impl AsRef<Path>]) -> Result<Vec<usize>> {
+        self.inner.ingest_sst(paths)
+    }
+
+    pub fn delete_range(&self, start: &[u8], end: &[u8]) -> Result<()> {
+        self.inner.delete_range(start, end)
+    }
+
     pub fn sync(&self) -> Result<()> {
         self.inner.sync()
     }
 
+    pub fn subscribe(&self, from_sequence: u64) -> Result<Subscription> {
+        self.inner.subscribe(from_sequence)
+    }
+
+    pub fn trim_replication_log(&self, sequence: u64) -> Result<usize> {
+        self.inner.trim_replication_log(sequence)
+    }
+
+    pub fn create_cf(&self, name: &str, options: LsmStorageOptions) -> Result<()> {
+        self.inner.create_cf(name, options)
+    }
+
+    pub fn drop_cf(&self, name: &str) -> Result<()> {
+        self.inner.drop_cf(name)
+    }
+
+    pub fn column_family_names(&self) -> Vec<String> {
+        self.inner.column_family_names()
+    }
+
+    pub fn put_cf(&self, cf: &str, key: &[u8], value: &[u8]) -> Result<()> {
+        self.inner.put_cf(cf, key, value)
+    }
+
+    pub fn delete_cf(&self, cf: &str, key: &[u8]) -> Result<()> {
+        self.inner.delete_cf(cf, key)
+    }
+
+    pub fn merge_cf(&self, cf: &str, key: &[u8], operand: &[u8]) -> Result<()> {
+        self.inner.merge_cf(cf, key, operand)
+    }
+
+    pub fn write_batch_cf<T: AsRef<[u8]>>(
+        &self,
+        cf: &str,
+        batch: &[WriteBatchRecord<T>],
+    ) -> Result<()> {
+        self.inner.write_batch_cf(cf, batch)
+    }
+
+    pub fn get_cf(&self, cf: &str, key: &[u8]) -> Result<Option<Bytes>> {
+        self.inner.get_cf(cf, key)
+    }
+
+    pub fn scan_cf(
+        &self,
+        cf: &str,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<FusedIterator<LsmIterator>> {
+        self.inner.scan_cf(cf, lower, upper)
+    }
+
+    pub fn refresh(&self) -> Result<()> {
+        self.inner.refresh()
+    }
+
+    pub fn create_checkpoint(&self, dir: impl AsRef<Path>) -> Result<()> {
+        self.inner.create_checkpoint(dir)
+    }
+
+    pub fn export_snapshot(&self, dir: impl AsRef<Path>) -> Result<SnapshotDescriptor> {
+        self.inner.export_snapshot(dir)
+    }
+
+    pub fn import_snapshot(&self, dir: impl AsRef<Path>) -> Result<Vec<usize>> {
+        self.inner.import_snapshot(dir)
+    }
+
+    pub fn flush(&self) -> Result<()> {
+        self.inner.flush()
+    }
+
+    pub fn flush_async(&self) -> Result<FlushHandle> {
+        self.inner.flush_async()
+    }
+
     pub fn scan(
         &self,
         lower: Bound<&[u8]>,
@@ -226,6 +766,76 @@ impl MiniLsm {
         self.inner.scan(lower, upper)
     }
 
+    pub fn scan_at(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        ts: u64,
+    ) -> Result<FusedIterator<LsmIterator>> {
+        self.inner.scan_at(lower, upper, ts)
+    }
+
+    pub fn prefix_scan(&self, prefix: &[u8]) -> Result<FusedIterator<LsmIterator>> {
+        self.inner.prefix_scan(prefix)
+    }
+
+    pub fn scan_changes(&self, since_ts: u64) -> Result<ChangeIterator> {
+        self.inner.scan_changes(since_ts)
+    }
+
+    pub fn history(&self, key: &[u8]) -> Result<Vec<KeyVersion>> {
+        self.inner.history(key)
+    }
+
+    pub fn register_index(&self, name: &str, extractor: Arc<dyn IndexExtractor>) -> Result<()> {
+        self.inner.register_index(name, extractor)
+    }
+
+    pub fn drop_index(&self, name: &str) -> Result<()> {
+        self.inner.drop_index(name)
+    }
+
+    pub fn index_names(&self) -> Vec<String> {
+        self.inner.index_names()
+    }
+
+    pub fn scan_index(
+        &self,
+        name: &str,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<IndexIterator> {
+        self.inner.scan_index(name, lower, upper)
+    }
+
+    /// Like `get`, but reads on the blocking thread pool of tokio instead of the calling thread.
+    /// Must be called within a tokio runtime.
+    pub async fn get_async(&self, key: &[u8]) -> Result<Option<Bytes>> {
+        let inner = self.inner.clone();
+        let key = Bytes::copy_from_slice(key);
+        tokio::task::spawn_blocking(move || inner.get(&key)).await?
+    }
+
+    /// Like `scan`, but creates and moves the iterator on the blocking thread pool of tokio, see
+    /// `SpawnBlockingIterator`. Must be called within a tokio runtime.
+    pub async fn scan_async(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<AsyncLsmIterator> {
+        let inner = self.inner.clone();
+        let lower = lower.map(Bytes::copy_from_slice);
+        let upper = upper.map(Bytes::copy_from_slice);
+        let iter = tokio::task::spawn_blocking(move || {
+            inner.scan(
+                lower.as_ref().map(|key| &key[..]),
+                upper.as_ref().map(|key| &key[..]),
+            )
+        })
+        .await??;
+        Ok(SpawnBlockingIterator::new(iter))
+    }
+
     /// Only call this in test cases due to race conditions
     pub fn force_flush(&self) -> Result<()> {
         if !self.inner.state.read().memtable.is_empty() {
@@ -241,6 +851,22 @@ impl MiniLsm {
     pub fn force_full_compaction(&self) -> Result<()> {
         self.inner.force_full_compaction()
     }
+
+    pub fn compact_range(&self, lower: Option<&[u8]>, upper: Option<&[u8]>) -> Result<()> {
+        self.inner.compact_range(lower, upper)
+    }
+
+    pub fn gc_compact(&self, threshold: f64) -> Result<usize> {
+        self.inner.gc_compact(threshold)
+    }
+
+    pub fn gc_value_log(&self) -> Result<Vec<usize>> {
+        self.inner.gc_value_log()
+    }
+
+    pub fn clear_cache(&self) {
+        self.inner.clear_cache()
+    }
 }
 
 impl LsmStorageInner {
@@ -256,40 +882,542 @@ impl LsmStorageInner {
     /// Start the storage engine by either loading an existing directory or creating a new one if the directory does
     /// not exist.
     pub(crate) fn open(path: impl AsRef<Path>, options: LsmStorageOptions) -> Result<Self> {
+        let block_cache =
+            BlockCache::with_policy(options.block_cache_capacity, options.block_cache_policy);
+        Self::open_with_block_cache(path, options, Arc::new(block_cache))
+    }
+
+    pub(crate) fn open_with_block_cache(
+        path: impl AsRef<Path>,
+        options: LsmStorageOptions,
+        block_cache: Arc<BlockCache>,
+    ) -> Result<Self> {
         let path = path.as_ref();
-        let state = LsmStorageState::create(&options);
+        let mut state = LsmStorageState::create(&options);
 
-        let compaction_controller = match &options.compaction_options {
-            CompactionOptions::Leveled(options) => {
-                CompactionController::Leveled(LeveledCompactionController::new(options.clone()))
-            }
-            CompactionOptions::Tiered(options) => {
-                CompactionController::Tiered(TieredCompactionController::new(options.clone()))
+        let compaction_controller = CompactionController::new(&options.compaction_options);
+
+        std::fs::create_dir_all(path).context("failed to create storage directory")?;
+        let manifest_path = path.join("MANIFEST");
+        let mut next_sst_id = 1;
+        let mut column_families = BTreeMap::new();
+        // the writes to the column families logged to the WALs
+        let mut column_family_entries = Vec::new();
+        // the WALs holding only writes to the column families, removed once they are flushed
+        let mut empty_wals = Vec::new();
+        let manifest = if manifest_path.exists() {
+            let (manifest, records) = Manifest::recover(&manifest_path)?;
+            column_families = Self::column_families_in(&records);
+            let memtables;
+            (state, memtables, next_sst_id) =
+                Self::replay_manifest(&options, &compaction_controller, records)?;
+            Self::load_ssts(
+                path,
+                &options,
+                &block_cache,
+                &compaction_controller,
+                &mut state,
+                &HashMap::new(),
+            )?;
+
+            if options.enable_wal {
+                for id in memtables {
+                    // a memtable created without WAL has no data to recover
+                    let wal_path = Self::path_of_wal_static(path, id);
+                    if !wal_path.exists() {
+                        continue;
+                    }
+                    let (memtable, entries) = MemTable::recover_from_shared_wal(
+                        id,
+                        &wal_path,
+                        options.comparator.clone(),
+                    )?;
+                    column_family_entries.extend(entries);
+                    if memtable.is_empty() {
+                        empty_wals.push(wal_path);
+                        continue;
+                    }
+                    state.imm_memtables.push(Arc::new(memtable));
+                }
             }
-            CompactionOptions::Simple(options) => CompactionController::Simple(
-                SimpleLeveledCompactionController::new(options.clone()),
-            ),
-            CompactionOptions::NoCompaction => CompactionController::NoCompaction,
+            manifest
+        } else {
+            let manifest = Manifest::create(&manifest_path)?;
+            manifest.add_record_when_init(ManifestRecord::Comparator(
+                options.comparator.name().to_string(),
+            ))?;
+            manifest
         };
 
+        let value_logs = ValueLogs::default();
+        next_sst_id = next_sst_id.max(Self::open_value_logs(path, &value_logs)?);
+
+        // continue from the latest timestamp that made it to disk
+        let mut last_ts = state
+            .sstables
+            .values()
+            .map(|sst| sst.max_ts())
+            .chain(state.imm_memtables.iter().map(|memtable| memtable.max_ts()))
+            .max()
+            .unwrap_or(TS_DEFAULT);
+        let replication_log = if options.enable_replication_log {
+            let log = ReplicationLog::open(path.join("replication"), last_ts + 1)?;
+            // without the WAL, the last writes may be logged but lost, and their sequence numbers
+            // are not reused
+            last_ts = last_ts.max(log.last_sequence());
+            Some(log)
+        } else {
+            None
+        };
+
+        let memtable_id = next_sst_id;
+        // record the memtable before creating its WAL, see `force_freeze_memtable`
+        manifest.add_record_when_init(ManifestRecord::NewMemtable(memtable_id))?;
+        let memtable = if options.enable_wal {
+            MemTable::create_with_wal(memtable_id, Self::path_of_wal_static(path, memtable_id))?
+        } else {
+            MemTable::create(memtable_id)
+        };
+        state.memtable = Arc::new(memtable.with_comparator(options.comparator.clone()));
+
+        let rate_limiter = options
+            .background_write_rate
+            .map(|rate| RateLimiter::new(rate, options.background_write_burst));
+        let metrics = Arc::new(Metrics::new(block_cache.clone()));
         let storage = Self {
             state: Arc::new(RwLock::new(Arc::new(state))),
             state_lock: Mutex::new(()),
             path: path.to_path_buf(),
-            block_cache: Arc::new(BlockCache::new(1024)),
-            next_sst_id: AtomicUsize::new(1),
+            block_cache,
+            next_sst_id: AtomicUsize::new(memtable_id + 1),
             compaction_controller,
-            manifest: None,
+            manifest: Some(manifest),
             options: options.into(),
-            mvcc: None,
+            mvcc: Some(LsmMvccInner::new(last_ts)),
             compaction_filters: Arc::new(Mutex::new(Vec::new())),
+            value_logs,
+            value_log_head: Mutex::new(None),
+            value_log_gc_lock: Mutex::new(()),
+            rate_limiter,
+            compaction_lock: Mutex::new(()),
+            flush_lock: Mutex::new(()),
+            read_only: false,
+            column_families: RwLock::new(BTreeMap::new()),
+            metrics,
+            replication_log,
+            indexes: RwLock::new(BTreeMap::new()),
         };
+        storage.sync_dir()?;
+        storage.open_column_families(column_families, column_family_entries)?;
+        for wal_path in empty_wals {
+            std::fs::remove_file(wal_path)?;
+        }
+
+        Ok(storage)
+    }
+
+    /// Open the storage at `path` for reading only, e.g., by another process than the one writing
+    /// it, without modifying anything in the directory. Only the flushed data is visible, as of
+    /// the manifest at the time of opening; `refresh` picks up the changes made since.
+    pub(crate) fn open_readonly(
+        path: impl AsRef<Path>,
+        options: LsmStorageOptions,
+    ) -> Result<Self> {
+        let block_cache =
+            BlockCache::with_policy(options.block_cache_capacity, options.block_cache_policy);
+        Self::open_readonly_with_block_cache(path, options, Arc::new(block_cache))
+    }
 
+    pub(crate) fn open_readonly_with_block_cache(
+        path: impl AsRef<Path>,
+        options: LsmStorageOptions,
+        block_cache: Arc<BlockCache>,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        let column_families =
+            Self::column_families_in(&Manifest::read_records(path.join("MANIFEST"))?);
+        let compaction_controller = CompactionController::new(&options.compaction_options);
+        let (mut state, next_sst_id) = Self::read_state(
+            path,
+            &options,
+            &block_cache,
+            &compaction_controller,
+            &HashMap::new(),
+        )?;
+        let value_logs = ValueLogs::default();
+        let next_sst_id = next_sst_id.max(Self::open_value_logs(path, &value_logs)?);
+        state.memtable =
+            Arc::new(MemTable::create(next_sst_id).with_comparator(options.comparator.clone()));
+        let last_ts = Self::max_sst_ts(&state);
+        let metrics = Arc::new(Metrics::new(block_cache.clone()));
+        let storage = Self {
+            state: Arc::new(RwLock::new(Arc::new(state))),
+            state_lock: Mutex::new(()),
+            path: path.to_path_buf(),
+            block_cache,
+            next_sst_id: AtomicUsize::new(next_sst_id + 1),
+            compaction_controller,
+            manifest: None,
+            options: options.into(),
+            mvcc: Some(LsmMvccInner::new(last_ts)),
+            compaction_filters: Arc::new(Mutex::new(Vec::new())),
+            value_logs,
+            value_log_head: Mutex::new(None),
+            value_log_gc_lock: Mutex::new(()),
+            rate_limiter: None,
+            compaction_lock: Mutex::new(()),
+            flush_lock: Mutex::new(()),
+            read_only: true,
+            column_families: RwLock::new(BTreeMap::new()),
+            metrics,
+            replication_log: None,
+            indexes: RwLock::new(BTreeMap::new()),
+        };
+        storage.open_column_families(column_families, Vec::new())?;
         Ok(storage)
     }
 
+    /// Pick up the SSTs written and removed by the writer of a storage opened with
+    /// `open_readonly` since it was opened or last refreshed.
+    pub fn refresh(&self) -> Result<()> {
+        if !self.read_only {
+            bail!("only a storage opened read-only can be refreshed");
+        }
+        let _state_lock = self.state_lock.lock();
+        let snapshot = self.state.read().clone();
+        let (mut state, _) = Self::read_state(
+            &self.path,
+            &self.options,
+            &self.block_cache,
+            &self.compaction_controller,
+            &snapshot.sstables,
+        )?;
+        Self::open_value_logs(&self.path, &self.value_logs)?;
+        state.memtable = snapshot.memtable.clone();
+        self.mvcc().update_commit_ts(Self::max_sst_ts(&state));
+        *self.state.write() = Arc::new(state);
+        for id in snapshot.sstables.keys() {
+            if !self.state.read().sstables.contains_key(id) {
+                self.block_cache.invalidate_sst(*id);
+            }
+        }
+        for cf in self.column_families() {
+            cf.inner.refresh()?;
+        }
+        Ok(())
+    }
+
+    /// Replay the manifest at `path` without modifying it and open the SSTs it lists, reusing the
+    /// ones in `loaded`. Returns the state without memtables and the next SST id.
+    fn read_state(
+        path: &Path,
+        options: &LsmStorageOptions,
+        block_cache: &Arc<BlockCache>,
+        compaction_controller: &CompactionController,
+        loaded: &HashMap<usize, Arc<SsTable>>,
+    ) -> Result<(LsmStorageState, usize)> {
+        let mut attempt = 0;
+        loop {
+            let records = Manifest::read_records(path.join("MANIFEST"))?;
+            let (mut state, _, next_sst_id) =
+                Self::replay_manifest(options, compaction_controller, records)?;
+            match Self::load_ssts(
+                path,
+                options,
+                block_cache,
+                compaction_controller,
+                &mut state,
+                loaded,
+            ) {
+                Ok(()) => return Ok((state, next_sst_id)),
+                // the writer removes the inputs of a compaction after logging it, so the SSTs are
+                // found in the manifest read next
+                Err(_) if attempt < 3 => attempt += 1,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Apply the manifest `records` to an empty state. Returns the state, whose `sstables` are not
+    /// loaded yet, the ids of the memtables that are not flushed, from latest to earliest, and the
+    /// next SST id.
+    fn replay_manifest(
+        options: &LsmStorageOptions,
+        compaction_controller: &CompactionController,
+        records: Vec<ManifestRecord>,
+    ) -> Result<(LsmStorageState, Vec<usize>, usize)> {
+        let mut state = LsmStorageState::create(options);
+        let mut next_sst_id = 1;
+        let mut memtables = Vec::new();
+        let mut comparator = None;
+        for record in records {
+            match record {
+                ManifestRecord::Flush(sst_id) => {
+                    let pos = memtables
+                        .iter()
+                        .position(|&id| id == sst_id)
+                        .with_context(|| format!("flushed memtable {} not found", sst_id))?;
+                    memtables.remove(pos);
+                    if compaction_controller.flush_to_l0() {
+                        state.l0_sstables.insert(0, sst_id);
+                    } else {
+                        state.levels.insert(0, (sst_id, vec![sst_id]));
+                    }
+                    next_sst_id = next_sst_id.max(sst_id + 1);
+                }
+                ManifestRecord::NewMemtable(id) => {
+                    memtables.insert(0, id);
+                    next_sst_id = next_sst_id.max(id + 1);
+                }
+                ManifestRecord::Ingest { level, ssts } => {
+                    state.install_ingested(level, &ssts);
+                    let max_id = ssts.iter().max().copied().unwrap_or_default();
+                    next_sst_id = next_sst_id.max(max_id + 1);
+                }
+                ManifestRecord::Compaction(task, output) => {
+                    (state, _) =
+                        compaction_controller.apply_compaction_result(&state, &task, &output, true);
+                    let max_id = output.iter().max().copied().unwrap_or_default();
+                    next_sst_id = next_sst_id.max(max_id + 1);
+                }
+                // see `column_families_in`
+                ManifestRecord::CreateColumnFamily { .. } | ManifestRecord::DropColumnFamily(_) => {
+                }
+                ManifestRecord::Comparator(name) => comparator = Some(name),
+                ManifestRecord::Snapshot {
+                    memtables: snapshot_memtables,
+                    l0_sstables,
+                    levels,
+                    next_sst_id: snapshot_next_sst_id,
+                    comparator: snapshot_comparator,
+                    ..
+                } => {
+                    memtables = snapshot_memtables;
+                    state.l0_sstables = l0_sstables;
+                    state.levels = levels;
+                    next_sst_id = snapshot_next_sst_id;
+                    comparator = snapshot_comparator;
+                }
+            }
+        }
+        let comparator = comparator.as_deref().unwrap_or(BYTEWISE_COMPARATOR_NAME);
+        if comparator != options.comparator.name() {
+            bail!(
+                "the storage is ordered by comparator {}, but opened with comparator {}",
+                comparator,
+                options.comparator.name()
+            );
+        }
+        Ok((state, memtables, next_sst_id))
+    }
+
+    /// Open the SSTs in the levels of `state`, reusing the ones in `loaded`, and sort the levels.
+    fn load_ssts(
+        path: &Path,
+        options: &LsmStorageOptions,
+        block_cache: &Arc<BlockCache>,
+        compaction_controller: &CompactionController,
+        state: &mut LsmStorageState,
+        loaded: &HashMap<usize, Arc<SsTable>>,
+    ) -> Result<()> {
+        for &sst_id in state
+            .l0_sstables
+            .iter()
+            .chain(state.levels.iter().flat_map(|(_, ssts)| ssts))
+        {
+            if let Some(sst) = loaded.get(&sst_id) {
+                state.sstables.insert(sst_id, sst.clone());
+                continue;
+            }
+            let sst_path = Self::path_of_sst_static(path, sst_id);
+            let file = Self::open_sst_file(options, &sst_path)
+                .with_context(|| format!("failed to open SST {}", sst_id))?;
+            let sst = SsTable::open(sst_id, Some(block_cache.clone()), file)?
+                .with_comparator(options.comparator.clone());
+            state.sstables.insert(sst_id, Arc::new(sst));
+        }
+        // compaction results and ingested SSTs are applied without the SST objects during
+        // recovery
+        if compaction_controller.flush_to_l0() {
+            for (_, ssts) in &mut state.levels {
+                ssts.sort_by(|a, b| {
+                    options.comparator.compare_keys(
+                        state.sstables[a].first_key().as_key_slice(),
+                        state.sstables[b].first_key().as_key_slice(),
+                    )
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Open the value log files in `path` that are not in `value_logs` yet, and return the id
+    /// after the largest one.
+    fn open_value_logs(path: &Path, value_logs: &ValueLogs) -> Result<usize> {
+        let mut next_id = 1;
+        for entry in std::fs::read_dir(path)? {
+            let file_path = entry?.path();
+            if file_path.extension().is_some_and(|ext| ext == "vlog")
+                && let Some(id) = file_path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .and_then(|stem| stem.parse::<usize>().ok())
+            {
+                if value_logs.snapshot().get(id).is_none() {
+                    value_logs.insert(Arc::new(ValueLogFile::open(id, &file_path)?));
+                }
+                next_id = next_id.max(id + 1);
+            }
+        }
+        Ok(next_id)
+    }
+
+    /// The largest timestamp in the SSTs of `state`.
+    fn max_sst_ts(state: &LsmStorageState) -> u64 {
+        state
+            .sstables
+            .values()
+            .map(|sst| sst.max_ts())
+            .max()
+            .unwrap_or(TS_DEFAULT)
+    }
+
+    pub(crate) fn check_writable(&self) -> Result<()> {
+        if self.read_only {
+            bail!(Error::ReadOnly);
+        }
+        Ok(())
+    }
+
+    /// Fail unless the keys are in the bytewise order, which `feature` relies on.
+    pub(crate) fn check_bytewise(&self, feature: &str) -> Result<()> {
+        if !self.options.comparator.is_bytewise() {
+            bail!(
+                "{} requires the bytewise order, but the storage is ordered by comparator {}",
+                feature,
+                self.options.comparator.name()
+            );
+        }
+        Ok(())
+    }
+
+    /// Sync the value log and the WAL, in this order, so that a pointer never outlives the value.
+    /// The replication log is synced too.
     pub fn sync(&self) -> Result<()> {
-        unimplemented!()
+        for cf in self.column_families() {
+            cf.inner.sync_value_log()?;
+        }
+        self.sync_value_log()?;
+        if let Some(log) = &self.replication_log {
+            log.sync()?;
+        }
+        self.state.read().memtable.sync_wal()
+    }
+
+    fn replication_log(&self) -> Result<&Arc<ReplicationLog>> {
+        self.replication_log
+            .as_ref()
+            .context("the replication log is not enabled")
+    }
+
+    /// Stream the committed writes from the one committed at `from_sequence` on, i.e., the next
+    /// sequence number a follower expects, or 1 for all of them. The sequence number of a write is
+    /// its commit timestamp. Requires `enable_replication_log`, and `from_sequence` not trimmed.
+    /// The writes to the column families and the ingested SSTs are not replicated.
+    pub fn subscribe(&self, from_sequence: u64) -> Result<Subscription> {
+        self.replication_log()?.subscribe(from_sequence)
+    }
+
+    /// Remove the replication log of the writes before `sequence` once all followers have
+    /// applied them, see `ReplicationLog::trim`.
+    pub fn trim_replication_log(&self, sequence: u64) -> Result<usize> {
+        self.replication_log()?.trim(sequence)
+    }
+
+    /// Sync the WAL in the background for `SyncPolicy::Interval`.
+    pub(crate) fn spawn_wal_sync_thread(
+        self: &Arc<Self>,
+        rx: crossbeam_channel::Receiver<()>,
+    ) -> Result<Option<std::thread::JoinHandle<()>>> {
+        let SyncPolicy::Interval(interval) = self.options.sync_policy else {
+            return Ok(None);
+        };
+        if !self.options.enable_wal {
+            return Ok(None);
+        }
+        let this = self.clone();
+        let handle = std::thread::spawn(move || {
+            let ticker = crossbeam_channel::tick(interval);
+            loop {
+                crossbeam_channel::select! {
+                    recv(ticker) -> _ => if let Err(e) = this.sync() {
+                        eprintln!("wal sync failed: {}", e);
+                    },
+                    recv(rx) -> _ => return
+                }
+            }
+        });
+        Ok(Some(handle))
+    }
+
+    /// Drop all blocks in the block cache.
+    pub fn clear_cache(&self) {
+        self.block_cache.clear();
+    }
+
+    /// The block cache hit, miss and eviction counters of each SST.
+    pub fn cache_stats(&self) -> HashMap<usize, CacheStats> {
+        self.block_cache.cache_stats()
+    }
+
+    /// Estimate the on-disk size in bytes of the data blocks holding keys in the range, from the
+    /// block metas, without reading any data block. Blocks partially in the range count in full,
+    /// and the mem-tables are not included.
+    pub fn approximate_size(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<u64> {
+        Ok(self.approximate_range_stats(lower, upper)?.0)
+    }
+
+    /// Estimate the number of entries in the SSTs with keys in the range, pro-rating the entries of
+    /// each SST by the size of its blocks in the range, see `approximate_size`. Every version and
+    /// tombstone of a key counts.
+    pub fn approximate_num_keys(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<u64> {
+        Ok(self.approximate_range_stats(lower, upper)?.1)
+    }
+
+    fn approximate_range_stats(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<(u64, u64)> {
+        let state = self.state.read().clone();
+        let (mut size, mut num_entries) = (0, 0);
+        for sst in state.sstables.values() {
+            if sst.num_of_blocks() == 0
+                || !range_overlap(
+                    lower,
+                    upper,
+                    sst.first_key(),
+                    sst.last_key(),
+                    &*self.options.comparator,
+                )
+            {
+                continue;
+            }
+            let lower = match lower {
+                Bound::Included(key) => KeySlice::from_slice(key, TS_RANGE_BEGIN),
+                Bound::Excluded(key) => KeySlice::from_slice(key, TS_RANGE_END),
+                Bound::Unbounded => sst.first_key().as_key_slice(),
+            };
+            let upper = match upper {
+                Bound::Included(key) => KeySlice::from_slice(key, TS_RANGE_END),
+                Bound::Excluded(key) => KeySlice::from_slice(key, TS_RANGE_BEGIN),
+                Bound::Unbounded => sst.last_key().as_key_slice(),
+            };
+            let (sst_size, sst_entries) = sst.approximate_range_stats(lower, upper)?;
+            size += sst_size;
+            num_entries += sst_entries;
+        }
+        Ok((size, num_entries))
     }
 
     pub fn add_compaction_filter(&self, compaction_filter: CompactionFilter) {
@@ -297,49 +1425,415 @@ impl LsmStorageInner {
         compaction_filters.push(compaction_filter);
     }
 
+    /// The compaction filters to apply in a compaction, in the order they are added.
+    pub(crate) fn compaction_filters(&self) -> Vec<CompactionFilter> {
+        self.compaction_filters.lock().clone()
+    }
+
     /// Get a key from the storage. In day 7, this can be further optimized by using a bloom filter.
     pub fn get(&self, _key: &[u8]) -> Result<Option<Bytes>> {
+        let start = Instant::now();
+        let value = self.get_with_ts(_key, self.mvcc().latest_commit_ts());
+        self.metrics.get_latency.record(start.elapsed());
+        value
+    }
+
+    /// Get a key as of the commit timestamp `ts`, i.e., the value of its latest version committed
+    /// at or before `ts`. The versions superseded before the watermark may have been removed by
+    /// compactions, so keep a snapshot at `ts` to read at it reliably.
+    pub fn get_at(&self, key: &[u8], ts: u64) -> Result<Option<Bytes>> {
+        self.check_read_ts(ts)?;
+        let start = Instant::now();
+        let value = self.get_with_ts(key, ts);
+        self.metrics.get_latency.record(start.elapsed());
+        value
+    }
+
+    /// Refuse to read at a timestamp not committed yet, whose writes may be partially applied.
+    fn check_read_ts(&self, ts: u64) -> Result<()> {
+        let latest_commit_ts = self.mvcc().latest_commit_ts();
+        if ts > latest_commit_ts {
+            bail!(
+                "cannot read at timestamp {}, after the latest commit timestamp {}",
+                ts,
+                latest_commit_ts
+            );
+        }
+        Ok(())
+    }
+
+    /// Get the latest version of a key committed at or before `read_ts`.
+    pub(crate) fn get_with_ts(&self, _key: &[u8], read_ts: u64) -> Result<Option<Bytes>> {
+        // the value logs first, see `ValueLogs::snapshot`
+        let value_logs = self.value_logs.snapshot();
+        let state = self.state.read().clone();
+        let Some(version) = Self::latest_version(&state, _key, read_ts)? else {
+            return Ok(None);
+        };
+        let range_tombstones = state.range_tombstones(read_ts);
+        self.visible_value(
+            _key,
+            version,
+            read_ts,
+            &range_tombstones,
+            ttl::now(),
+            &value_logs,
+        )
+    }
+
+    /// The user value of the latest version of a key within `read_ts`, unless the version is a
+    /// tombstone, is covered by a range tombstone, or has expired at `now`. A separated value is
+    /// read from `value_logs`, and the operands of a merge operand are folded by a scan of the key.
+    fn visible_value(
+        &self,
+        key: &[u8],
+        (ts, value): (u64, Bytes),
+        read_ts: u64,
+        range_tombstones: &RangeTombstones,
+        now: u64,
+        value_logs: &ValueLogSnapshot,
+    ) -> Result<Option<Bytes>> {
+        if value.is_empty() || range_tombstones.covers(key, ts) || ttl::is_expired(&value, now) {
+            return Ok(None);
+        }
+        if ttl::is_merge_operand(&value) {
+            // the iterator reads the older versions to fold the operands onto
+            let iter = self.scan_with_ts(Bound::Included(key), Bound::Included(key), read_ts)?;
+            return Ok(iter
+                .is_valid()
+                .then(|| Bytes::copy_from_slice(iter.value())));
+        }
+        value_logs.resolve(&value).map(Some)
+    }
+
+    /// The timestamp and the stored value of the latest version of a key committed at or before
+    /// `read_ts`, including tombstones.
+    pub(crate) fn latest_version(
+        state: &LsmStorageState,
+        key: &[u8],
+        read_ts: u64,
+    ) -> Result<Option<(u64, Bytes)>> {
+        let key = KeySlice::from_slice(key, read_ts);
+        // the first version found is the latest one within `read_ts`
+        let result = std::iter::once(&state.memtable)
+            .chain(state.imm_memtables.iter())
+            .find_map(|memtable| memtable.get_version(key));
+        if result.is_some() {
+            return Ok(result);
+        }
+
+        // SSTs from latest to earliest: L0, then the levels or tiers
+        for sst_id in state
+            .l0_sstables
+            .iter()
+            .chain(state.levels.iter().flat_map(|(_, ssts)| ssts))
+        {
+            if let Some(version) = state.sstables[sst_id].get_version(key)? {
+                return Ok(Some(version));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Get a batch of keys as of the same commit timestamp, returning the values in the order of
+    /// `keys`. The keys are looked up in sorted order, so each SST probes its bloom filter once per
+    /// key and reads each of its blocks at most once for the whole batch.
+    pub fn multi_get(&self, keys: &[&[u8]]) -> Result<Vec<Option<Bytes>>> {
+        let read_ts = self.mvcc().latest_commit_ts();
+        // the value logs first, see `ValueLogs::snapshot`
+        let value_logs = self.value_logs.snapshot();
         let state = self.state.read().clone();
 
-        let search = |m: &Arc<MemTable>| {
-            m.get(_key)
-                .map(|v| if v.is_empty() { None } else { Some(v) })
+        let mut order = (0..keys.len()).collect::<Vec<_>>();
+        order.sort_by(|&a, &b| self.options.comparator.compare(keys[a], keys[b]));
+        // `None` until the latest version of the key is found in a memtable or an SST
+        let mut results: Vec<Option<Option<Bytes>>> = vec![None; keys.len()];
+        let range_tombstones = state.range_tombstones(read_ts);
+        let now = ttl::now();
+        let visible = |key, version| {
+            self.visible_value(key, version, read_ts, &range_tombstones, now, &value_logs)
         };
 
-        let result =
-            search(&state.memtable).or_else(|| state.imm_memtables.iter().find_map(search));
+        for memtable in std::iter::once(&state.memtable).chain(state.imm_memtables.iter()) {
+            for (key, result) in keys.iter().zip(results.iter_mut()) {
+                if result.is_none()
+                    && let Some(version) = memtable.get_version(KeySlice::from_slice(key, read_ts))
+                {
+                    *result = Some(visible(key, version)?);
+                }
+            }
+        }
 
-        Ok(result.flatten())
+        // SSTs from latest to earliest: L0, then the levels or tiers
+        for sst_id in state
+            .l0_sstables
+            .iter()
+            .chain(state.levels.iter().flat_map(|(_, ssts)| ssts))
+        {
+            let pending = order
+                .iter()
+                .copied()
+                .filter(|&idx| results[idx].is_none())
+                .collect::<Vec<_>>();
+            if pending.is_empty() {
+                break;
+            }
+            let lookups = pending
+                .iter()
+                .map(|&idx| KeySlice::from_slice(keys[idx], read_ts))
+                .collect::<Vec<_>>();
+            let versions = state.sstables[sst_id].get_versions(&lookups)?;
+            for (idx, version) in pending.into_iter().zip(versions) {
+                results[idx] = version
+                    .map(|version| visible(keys[idx], version))
+                    .transpose()?;
+            }
+        }
+        Ok(results.into_iter().map(Option::flatten).collect())
     }
 
-    /// Write a batch of data into the storage. Implement in week 2 day 7.
+    /// Write a batch of data into the storage. The batch is appended to the WAL as one record and
+    /// applied to a single memtable: the memtable cannot be frozen in the middle of a batch, and
+    /// recovery replays the whole batch or none of it. Deletes are written as empty values. All
+    /// records of the batch share one commit timestamp.
     pub fn write_batch<T: AsRef<[u8]>>(&self, _batch: &[WriteBatchRecord<T>]) -> Result<()> {
-        unimplemented!()
+        self.write_batch_inner(_batch)?;
+        Ok(())
+    }
+
+    /// Write a batch of data into the storage and return its commit timestamp. The values expire
+    /// after `default_ttl` if set.
+    pub(crate) fn write_batch_inner<T: AsRef<[u8]>>(
+        &self,
+        _batch: &[WriteBatchRecord<T>],
+    ) -> Result<u64> {
+        let expire_at = self.options.default_ttl.map(ttl::expire_at);
+        self.write_batch_expiring(_batch, expire_at)
+    }
+
+    /// Write a batch of data into the storage whose values expire at `expire_at`, and return its
+    /// commit timestamp. Values of at least `value_threshold` bytes are appended to the value log.
+    fn write_batch_expiring<T: AsRef<[u8]>>(
+        &self,
+        batch: &[WriteBatchRecord<T>],
+        expire_at: Option<u64>,
+    ) -> Result<u64> {
+        self.check_writable()?;
+        self.check_batch_len(batch, expire_at)?;
+        let start = Instant::now();
+        let (ts, memtable, separated, index_memtables) = {
+            // batches are applied in the order of their timestamps
+            let _write_lock = self.mvcc().write_lock.lock();
+            let ts = self.mvcc().latest_commit_ts() + 1;
+            let index_updates = self.index_updates(batch, ts)?;
+            let (values, separated) = self.encode_values(batch, ts, expire_at)?;
+            let data = batch_data(batch, &values, ts);
+            // freezing takes the write lock, so the memtable stays the same during the batch
+            let state = self.state.read();
+            let index_memtables = self.put_batch_indexed(&state.memtable, &data, &index_updates)?;
+            if let Some(log) = &self.replication_log {
+                log.append(&replication_batch(batch, ts, expire_at))?;
+            }
+            self.mvcc().update_commit_ts(ts);
+            (ts, state.memtable.clone(), separated, index_memtables)
+        };
+        // sync outside of the write lock so that concurrent writes can share the sync
+        self.sync_write(&memtable, separated.then_some(self))?;
+        self.try_freeze(&memtable)?;
+        for (cf, cf_memtable) in index_memtables {
+            cf.inner.try_freeze(&cf_memtable)?;
+        }
+        self.metrics.put_latency.record(start.elapsed());
+        Ok(ts)
+    }
+
+    /// Check that the records of `batch` fit in the WAL before any of them is written.
+    pub(crate) fn check_batch_len<T: AsRef<[u8]>>(
+        &self,
+        batch: &[WriteBatchRecord<T>],
+        expire_at: Option<u64>,
+    ) -> Result<()> {
+        for record in batch {
+            match record {
+                // the separated values are only stored as pointers in the WAL
+                WriteBatchRecord::Put(key, value) if !self.is_separated(value.as_ref()) => {
+                    let value = ttl::encode_value(value.as_ref(), expire_at);
+                    Wal::check_entry_len(key.as_ref(), &value)?
+                }
+                WriteBatchRecord::Put(key, _) | WriteBatchRecord::Del(key) => {
+                    Wal::check_entry_len(key.as_ref(), b"")?
+                }
+                WriteBatchRecord::Merge(key, operand) => {
+                    let operand = ttl::encode_merge_operand(operand.as_ref());
+                    Wal::check_entry_len(key.as_ref(), &operand)?
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The values of `batch` as stored in the memtable, and whether any of them is separated: the
+    /// values of at least `value_threshold` bytes are appended to the value log, as the value log
+    /// records carry the commit timestamp `ts`, and stored as pointers. Merge operands are stored
+    /// inline and never expire. Called with the write lock held.
+    pub(crate) fn encode_values<'a, T: AsRef<[u8]>>(
+        &self,
+        batch: &'a [WriteBatchRecord<T>],
+        ts: u64,
+        expire_at: Option<u64>,
+    ) -> Result<(Vec<Cow<'a, [u8]>>, bool)> {
+        let mut separated = false;
+        let values = batch
+            .iter()
+            .map(|record| match record {
+                WriteBatchRecord::Put(key, value) if self.is_separated(value.as_ref()) => {
+                    separated = true;
+                    let pointer = self.append_value(key.as_ref(), ts, value.as_ref())?;
+                    Ok(Cow::Owned(ttl::encode_pointer(
+                        &pointer.encode(),
+                        expire_at,
+                    )))
+                }
+                WriteBatchRecord::Put(_, value) => Ok(ttl::encode_value(value.as_ref(), expire_at)),
+                WriteBatchRecord::Del(_) => Ok(Cow::Borrowed(&b""[..])),
+                WriteBatchRecord::Merge(..) if self.options.merge_operator.is_none() => {
+                    bail!("no merge operator is set")
+                }
+                WriteBatchRecord::Merge(_, operand) => {
+                    Ok(Cow::Owned(ttl::encode_merge_operand(operand.as_ref())))
+                }
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok((values, separated))
+    }
+
+    /// Delete all keys in `start..end` with a range tombstone, which hides the versions of the
+    /// keys written before it from the reads that see it.
+    pub fn delete_range(&self, start: &[u8], end: &[u8]) -> Result<()> {
+        self.check_writable()?;
+        self.check_bytewise("delete_range")?;
+        if start >= end {
+            return Ok(());
+        }
+        Wal::check_entry_len(start, b"")?;
+        Wal::check_entry_len(end, b"")?;
+        let memtable = {
+            let _write_lock = self.mvcc().write_lock.lock();
+            let ts = self.mvcc().latest_commit_ts() + 1;
+            let state = self.state.read();
+            state
+                .memtable
+                .delete_range(RangeTombstone::new(start, end, ts))?;
+            if let Some(log) = &self.replication_log {
+                log.append(&ReplicationBatch {
+                    sequence: ts,
+                    records: vec![ReplicationRecord {
+                        key: Bytes::copy_from_slice(start),
+                        op: ReplicationOp::DeleteRange {
+                            end: Bytes::copy_from_slice(end),
+                        },
+                    }],
+                })?;
+            }
+            self.mvcc().update_commit_ts(ts);
+            state.memtable.clone()
+        };
+        self.sync_write(&memtable, None)?;
+        self.try_freeze(&memtable)
+    }
+
+    /// Sync a write to the WAL of `memtable` before it returns if `sync_policy` requires so. The
+    /// value log of `separated_in` is synced first if the write has separated values there, see
+    /// `sync`.
+    pub(crate) fn sync_write(
+        &self,
+        memtable: &MemTable,
+        separated_in: Option<&LsmStorageInner>,
+    ) -> Result<()> {
+        if !self.options.enable_wal {
+            return Ok(());
+        }
+        let group = match self.options.sync_policy {
+            SyncPolicy::PerWrite => false,
+            SyncPolicy::Group => true,
+            SyncPolicy::Interval(_) | SyncPolicy::Never => return Ok(()),
+        };
+        if let Some(storage) = separated_in {
+            storage.sync_value_log()?;
+        }
+        if let Some(log) = &self.replication_log {
+            log.sync()?;
+        }
+        if group {
+            memtable.sync_wal_group()
+        } else {
+            memtable.sync_wal()
+        }
+    }
+
+    /// Whether `memtable` reaches any of `memtable_max_size`, `memtable_max_entries` and
+    /// `memtable_max_wal_size`.
+    fn should_freeze(&self, memtable: &MemTable) -> bool {
+        let options = &self.options;
+        memtable.approximate_size() >= options.memtable_max_size.unwrap_or(options.target_sst_size)
+            || options
+                .memtable_max_entries
+                .is_some_and(|limit| memtable.num_entries() >= limit)
+            || options
+                .memtable_max_wal_size
+                .is_some_and(|limit| memtable.wal_size() >= limit)
+    }
+
+    /// Freeze the memtable if it reaches any of the freeze triggers, see `should_freeze`. Then, if
+    /// there are more than `max_imm_memtables` immutable memtables, i.e., the flush thread falls
+    /// behind, flush the oldest ones before returning.
+    pub(crate) fn try_freeze(&self, memtable: &MemTable) -> Result<()> {
+        if self.should_freeze(memtable) {
+            let start = Instant::now();
+            {
+                let state_lock = self.state_lock.lock();
+                // check again, another thread may have frozen the memtable already
+                if self.should_freeze(&self.state.read().memtable) {
+                    self.force_freeze_memtable(&state_lock)?;
+                }
+            }
+            if let Some(max_imm_memtables) = self.options.max_imm_memtables {
+                while self.state.read().imm_memtables.len() > max_imm_memtables {
+                    self.force_flush_next_imm_memtable()?;
+                }
+            }
+            self.metrics.add_stall(start.elapsed());
+        }
+        Ok(())
     }
 
     /// Put a key-value pair into the storage by writing into the current memtable.
     pub fn put(&self, _key: &[u8], _value: &[u8]) -> Result<()> {
         // unimplemented!()
-        // let cur_state = self.state.write();
-        let cur_state = self.state.read().clone();
-        let res = cur_state.memtable.put(_key, _value);
-        let mut cur_size = cur_state.memtable.approximate_size();
-        let target_size = self.options.clone().target_sst_size;
-        if cur_size >= target_size {
-            let state_lock = self.state_lock.lock();
-            // check again
-            cur_size = cur_state.memtable.approximate_size();
-            if cur_size >= target_size {
-                self.force_freeze_memtable(&state_lock)?;
-            }
-        }
-        res
+        self.write_batch(&[WriteBatchRecord::Put(_key, _value)])
     }
 
     /// Remove a key from the storage by writing an empty value.
     pub fn delete(&self, _key: &[u8]) -> Result<()> {
         // unimplemented!()
-        self.put(_key, b"")
+        self.write_batch(&[WriteBatchRecord::Del(_key)])
+    }
+
+    /// Write a merge operand for a key, which the reads and compactions fold onto the existing
+    /// value with the `merge_operator`, without reading the value first. Fails if no merge operator
+    /// is set.
+    pub fn merge(&self, key: &[u8], operand: &[u8]) -> Result<()> {
+        self.write_batch(&[WriteBatchRecord::Merge(key, operand)])
+    }
+
+    /// Put a key-value pair into the storage that expires after `ttl`, overriding `default_ttl`.
+    /// Expired values are hidden from reads and dropped by compaction.
+    pub fn put_with_ttl(&self, key: &[u8], value: &[u8], ttl: Duration) -> Result<()> {
+        self.write_batch_expiring(
+            &[WriteBatchRecord::Put(key, value)],
+            Some(ttl::expire_at(ttl)),
+        )?;
+        Ok(())
     }
 
     pub(crate) fn path_of_sst_static(path: impl AsRef<Path>, id: usize) -> PathBuf {
@@ -350,6 +1844,108 @@ impl LsmStorageInner {
         Self::path_of_sst_static(&self.path, id)
     }
 
+    /// Open the SST file at `path` for reading, with direct I/O or through a memory mapping as
+    /// set in the options.
+    fn open_sst_file(options: &LsmStorageOptions, path: &Path) -> Result<FileObject> {
+        if options.use_direct_io {
+            FileObject::open_direct(path)
+        } else if options.use_mmap {
+            FileObject::open_mmap(path)
+        } else {
+            FileObject::open(path)
+        }
+    }
+
+    /// A builder for an SST of the storage, with the properties collectors of the options.
+    pub(crate) fn new_sst_builder(&self) -> SsTableBuilder {
+        let mut builder = SsTableBuilder::new(self.options.block_size);
+        if self.options.use_direct_io {
+            builder = builder.with_block_alignment(DIRECT_IO_ALIGNMENT);
+        }
+        for factory in &self.options.table_properties_collectors {
+            builder = builder.with_properties_collector(factory.create());
+        }
+        if let Some(extractor) = &self.options.prefix_extractor {
+            builder = builder.with_prefix_extractor(extractor.clone());
+        }
+        builder
+    }
+
+    /// Write the SST of `sst_id` to the disk, to be read with direct I/O or through a memory
+    /// mapping as set in the options. The write goes through the rate limiter with `priority` if
+    /// any.
+    pub(crate) fn build_sst(
+        &self,
+        builder: SsTableBuilder,
+        sst_id: usize,
+        priority: IoPriority,
+    ) -> Result<SsTable> {
+        let path = self.path_of_sst(sst_id);
+        let block_cache = Some(self.block_cache.clone());
+        let sst = if self.options.use_direct_io {
+            let writer = DirectIoWriter {
+                path: &path,
+                rate_limiter: self
+                    .rate_limiter
+                    .as_ref()
+                    .map(|rate_limiter| (rate_limiter, priority)),
+            };
+            builder.build_with_writer(sst_id, block_cache, writer)
+        } else if let Some(rate_limiter) = &self.rate_limiter {
+            let writer = RateLimitedWriter {
+                path: &path,
+                rate_limiter,
+                priority,
+                use_mmap: self.options.use_mmap,
+            };
+            builder.build_with_writer(sst_id, block_cache, writer)
+        } else if self.options.use_mmap {
+            builder.build_with_writer(sst_id, block_cache, MmapWriter(&path))
+        } else {
+            builder.build(sst_id, block_cache, path)
+        }?;
+        Ok(sst.with_comparator(self.options.comparator.clone()))
+    }
+
+    pub(crate) fn path_of_vlog(&self, id: usize) -> PathBuf {
+        self.path.join(format!("{:05}.vlog", id))
+    }
+
+    /// Whether a value is stored in the value log instead of inline.
+    fn is_separated(&self, value: &[u8]) -> bool {
+        self.options
+            .value_threshold
+            .is_some_and(|threshold| value.len() >= threshold)
+    }
+
+    /// Append a value to the value log, starting a new file if there is none yet or the current one
+    /// is full. Called with the write lock held.
+    pub(crate) fn append_value(&self, key: &[u8], ts: u64, value: &[u8]) -> Result<ValuePointer> {
+        let mut head = self.value_log_head.lock();
+        if head
+            .as_ref()
+            .is_none_or(|head| head.size() >= self.options.value_log_file_size as u64)
+        {
+            if let Some(head) = head.as_ref() {
+                head.sync()?;
+            }
+            let id = self.next_sst_id();
+            let new_head = ValueLogHead::create(id, self.path_of_vlog(id))?;
+            self.sync_dir()?;
+            self.value_logs.insert(new_head.file.clone());
+            *head = Some(new_head);
+        }
+        head.as_mut().unwrap().append(key, ts, value)
+    }
+
+    /// Sync the value log file being appended to, if any.
+    pub(crate) fn sync_value_log(&self) -> Result<()> {
+        match self.value_log_head.lock().as_ref() {
+            Some(head) => head.sync(),
+            None => Ok(()),
+        }
+    }
+
     pub(crate) fn path_of_wal_static(path: impl AsRef<Path>, id: usize) -> PathBuf {
         path.as_ref().join(format!("{:05}.wal", id))
     }
@@ -359,55 +1955,612 @@ impl LsmStorageInner {
     }
 
     pub(super) fn sync_dir(&self) -> Result<()> {
-        unimplemented!()
+        std::fs::File::open(&self.path)?.sync_all()?;
+        Ok(())
+    }
+
+    /// Log `record` to the manifest after applying it to the state, compacting the manifest into a
+    /// snapshot of the current state once it grows too large.
+    pub(crate) fn add_manifest_record(
+        &self,
+        state_lock_observer: &MutexGuard<'_, ()>,
+        record: ManifestRecord,
+    ) -> Result<()> {
+        let Some(manifest) = &self.manifest else {
+            return Ok(());
+        };
+        manifest.add_record(state_lock_observer, record)?;
+        if manifest.needs_compaction() {
+            let state = self.state.read().clone();
+            let memtables = std::iter::once(&state.memtable)
+                .chain(&state.imm_memtables)
+                .map(|memtable| memtable.id())
+                .collect();
+            manifest.compact(
+                state_lock_observer,
+                ManifestRecord::Snapshot {
+                    memtables,
+                    l0_sstables: state.l0_sstables.clone(),
+                    levels: state.levels.clone(),
+                    next_sst_id: self.next_sst_id.load(std::sync::atomic::Ordering::SeqCst),
+                    column_families: self.column_family_ids(),
+                    comparator: Some(self.options.comparator.name().to_string()),
+                },
+            )?;
+        }
+        Ok(())
     }
 
     /// Force freeze the current memtable to an immutable memtable
     pub fn force_freeze_memtable(&self, _state_lock_observer: &MutexGuard<'_, ()>) -> Result<()> {
+        self.check_writable()?;
         let id = self.next_sst_id();
-        let memtable = MemTable::create_with_wal(id, self.path_of_sst(id))?;
-        {
+        // record the memtable before creating its WAL: a crash in between leaves a memtable
+        // without WAL, which the recovery skips, rather than a WAL the manifest does not know of,
+        // whose id would be taken again
+        self.add_manifest_record(_state_lock_observer, ManifestRecord::NewMemtable(id))?;
+        let memtable = if self.options.enable_wal {
+            MemTable::create_with_wal(id, self.path_of_wal(id))?
+        } else {
+            MemTable::create(id)
+        }
+        .with_comparator(self.options.comparator.clone());
+        let frozen = {
             let mut state = self.state.write();
             let mut new_state = state.as_ref().clone();
-            new_state
-                .imm_memtables
-                .insert(0, new_state.memtable.clone());
+            let frozen = new_state.memtable.clone();
+            new_state.imm_memtables.insert(0, frozen.clone());
             new_state.memtable = Arc::new(memtable);
 
             *state = Arc::new(new_state);
+            frozen
+        };
+        if self.options.sync_policy != SyncPolicy::Never {
+            // the frozen memtable is no longer synced by the policy
+            self.sync_value_log()?;
+            frozen.sync_wal()?;
         }
+        self.sync_dir()?;
         Ok(())
     }
 
-    /// Force flush the earliest-created immutable memtable to disk
-    pub fn force_flush_next_imm_memtable(&self) -> Result<()> {
-        unimplemented!()
+    /// Ingest SSTs built outside of the storage, e.g., by `SsTableBuilder`, without going through
+    /// the memtables. The files are verified and copied into the storage under new ids, and then
+    /// installed by a single manifest record into the bottom level, or as the oldest tier for
+    /// tiered compaction. As the versions keep their timestamps, the SSTs may not overlap each
+    /// other or any key or range tombstone in the storage, and may not hold versions newer than the
+    /// latest commit. Returns the new SST ids in the order of `paths`.
+    pub fn ingest_sst(&self, paths: &[impl AsRef<Path>]) -> Result<Vec<usize>> {
+        self.check_writable()?;
+        self.check_bytewise("ingest_sst")?;
+        let state_lock = self.state_lock.lock();
+        let mut ssts = Vec::with_capacity(paths.len());
+        for (idx, path) in paths.iter().enumerate() {
+            let path = path.as_ref();
+            let sst = SsTable::open(0, None, FileObject::open(path)?)
+                .and_then(|sst| sst.verify().map(|_| sst))
+                .with_context(|| format!("failed to verify SST {}", path.display()))?;
+            if sst.num_of_blocks() == 0 {
+                bail!("SST {} has no key to ingest", path.display());
+            }
+            if sst.max_ts() > self.mvcc().latest_commit_ts() {
+                bail!(
+                    "SST {} has versions at ts {}, newer than the latest commit",
+                    path.display(),
+                    sst.max_ts()
+                );
+            }
+            ssts.push((idx, path, sst));
+        }
+        if ssts.is_empty() {
+            return Ok(Vec::new());
+        }
+        ssts.sort_by(|(_, _, a), (_, _, b)| a.first_key().cmp(b.first_key()));
+        for pair in ssts.windows(2) {
+            if pair[1].2.first_key().key_ref() <= pair[0].2.last_key().key_ref() {
+                bail!(
+                    "SSTs {} and {} overlap",
+                    pair[0].1.display(),
+                    pair[1].1.display()
+                );
+            }
+        }
+        let snapshot = self.state.read().clone();
+        for (_, path, sst) in &ssts {
+            if snapshot.overlaps(sst.first_key().key_ref(), sst.last_key().key_ref()) {
+                bail!("SST {} overlaps the data in the storage", path.display());
+            }
+        }
+
+        // copy the files in key order, so that the ids of a new tier are sorted as well
+        let mut ids = vec![0; paths.len()];
+        let mut new_ssts = Vec::with_capacity(ssts.len());
+        for (idx, path, _) in &ssts {
+            let sst_id = self.next_sst_id();
+            let sst_path = self.path_of_sst(sst_id);
+            let result = std::fs::copy(path, &sst_path)
+                .and_then(|_| std::fs::File::open(&sst_path)?.sync_all())
+                .map_err(anyhow::Error::from)
+                .and_then(|_| {
+                    let file = Self::open_sst_file(&self.options, &sst_path)?;
+                    SsTable::open(sst_id, Some(self.block_cache.clone()), file)
+                });
+            match result {
+                Ok(sst) => new_ssts.push(Arc::new(sst)),
+                Err(e) => {
+                    for sst in &new_ssts {
+                        std::fs::remove_file(self.path_of_sst(sst.sst_id()))?;
+                    }
+                    let _ = std::fs::remove_file(&sst_path);
+                    return Err(e.context(format!("failed to copy SST {}", path.display())));
+                }
+            }
+            ids[*idx] = sst_id;
+        }
+        self.sync_dir()?;
+
+        let sst_ids = new_ssts.iter().map(|sst| sst.sst_id()).collect::<Vec<_>>();
+        let level = if self.compaction_controller.flush_to_l0() {
+            snapshot.levels.last().map(|(level, _)| *level)
+        } else {
+            None
+        };
+        {
+            let mut state = self.state.write();
+            let mut new_state = state.as_ref().clone();
+            for sst in new_ssts {
+                new_state.sstables.insert(sst.sst_id(), sst);
+            }
+            new_state.install_ingested(level, &sst_ids);
+            if let Some(level) = level {
+                let sstables = &new_state.sstables;
+                let (_, ssts) = new_state
+                    .levels
+                    .iter_mut()
+                    .find(|(id, _)| *id == level)
+                    .unwrap();
+                ssts.sort_by(|a, b| sstables[a].first_key().cmp(sstables[b].first_key()));
+            }
+            *state = Arc::new(new_state);
+        }
+        self.add_manifest_record(
+            &state_lock,
+            ManifestRecord::Ingest {
+                level,
+                ssts: sst_ids,
+            },
+        )?;
+        Ok(ids)
     }
 
-    pub fn new_txn(&self) -> Result<()> {
-        // no-op
+    /// Force flush the earliest-created immutable memtable to disk, if any. Only installing the SST
+    /// takes `state_lock`.
+    pub fn force_flush_next_imm_memtable(&self) -> Result<()> {
+        let _flush_lock = self.flush_lock.lock();
+        let Some(memtable) = self.state.read().imm_memtables.last().cloned() else {
+            return Ok(());
+        };
+        if self.options.enable_wal {
+            // the WAL to remove may log writes to the column families, see `ColumnFamily`
+            self.flush_column_families()?;
+        }
+        // the values the SST points to must be on the disk first
+        self.sync_value_log()?;
+        let mut builder = self.new_sst_builder();
+        memtable.flush(&mut builder)?;
+        let sst_id = memtable.id();
+        let sst = Arc::new(self.build_sst(builder, sst_id, IoPriority::High)?);
+        self.metrics
+            .bytes_flushed
+            .fetch_add(sst.file_size(), Ordering::Relaxed);
+
+        let state_lock = self.state_lock.lock();
+        {
+            let mut state = self.state.write();
+            let mut new_state = state.as_ref().clone();
+            let flushed = new_state.imm_memtables.pop().unwrap();
+            assert_eq!(flushed.id(), sst_id);
+            if self.compaction_controller.flush_to_l0() {
+                new_state.l0_sstables.insert(0, sst_id);
+            } else {
+                new_state.levels.insert(0, (sst_id, vec![sst_id]));
+            }
+            new_state.sstables.insert(sst_id, sst);
+            *state = Arc::new(new_state);
+        }
+        self.add_manifest_record(&state_lock, ManifestRecord::Flush(sst_id))?;
+        self.sync_dir()?;
+        if self.options.enable_wal {
+            std::fs::remove_file(self.path_of_wal(sst_id))?;
+        }
         Ok(())
     }
 
-    /// Create an iterator over a range of keys.
+    /// Freeze the current memtable and flush it together with the immutable memtables before it,
+    /// returning once all of them are SSTs. The column families are flushed first.
+    pub fn flush(&self) -> Result<()> {
+        self.check_writable()?;
+        self.flush_column_families()?;
+        match self.freeze_for_flush()? {
+            Some(memtable_id) => self.flush_until(memtable_id),
+            None => Ok(()),
+        }
+    }
+
+    /// Like `flush`, but the memtables are flushed on a new thread. The current memtable is frozen
+    /// before returning, so the writes after it are not part of the flush.
+    pub fn flush_async(self: &Arc<Self>) -> Result<FlushHandle> {
+        let memtable_id = self.freeze_for_flush()?;
+        let this = self.clone();
+        let handle = std::thread::spawn(move || match memtable_id {
+            Some(memtable_id) => this.flush_until(memtable_id),
+            None => Ok(()),
+        });
+        Ok(FlushHandle { handle })
+    }
+
+    /// Freeze the current memtable unless it is empty, and return the id of the latest immutable
+    /// memtable if any.
+    fn freeze_for_flush(&self) -> Result<Option<usize>> {
+        self.check_writable()?;
+        let state_lock = self.state_lock.lock();
+        if !self.state.read().memtable.is_empty() {
+            self.force_freeze_memtable(&state_lock)?;
+        }
+        Ok(self
+            .state
+            .read()
+            .imm_memtables
+            .first()
+            .map(|memtable| memtable.id()))
+    }
+
+    /// Flush the immutable memtables up to the one of `memtable_id`, from the earliest.
+    fn flush_until(&self, memtable_id: usize) -> Result<()> {
+        loop {
+            let next = self
+                .state
+                .read()
+                .imm_memtables
+                .last()
+                .map(|memtable| memtable.id());
+            match next {
+                Some(id) if id <= memtable_id => self.force_flush_next_imm_memtable()?,
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    /// Start a transaction that reads from the latest committed state.
+    pub fn new_txn(self: &Arc<Self>) -> Result<Arc<Transaction>> {
+        // the local writes of a transaction are kept in the bytewise order
+        self.check_bytewise("new_txn")?;
+        Ok(self.mvcc().new_txn(self.clone(), self.options.serializable))
+    }
+
+    /// Take a snapshot of the latest committed state.
+    pub fn new_snapshot(self: &Arc<Self>) -> Snapshot {
+        self.mvcc().new_snapshot(self.clone())
+    }
+
+    /// Create an iterator over a range of keys. Only the SSTs that overlap with the range are
+    /// opened, and the iterator becomes invalid as soon as it passes `upper`.
     pub fn scan(
         &self,
-        _lower: Bound<&[u8]>,
-        _upper: Bound<&[u8]>,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<FusedIterator<LsmIterator>> {
+        let start = Instant::now();
+        let iter = self.scan_with_ts(lower, upper, self.mvcc().latest_commit_ts());
+        self.metrics.scan_latency.record(start.elapsed());
+        iter
+    }
+
+    /// Create an iterator over a range of keys as of the commit timestamp `ts`, see `get_at`.
+    pub fn scan_at(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        ts: u64,
+    ) -> Result<FusedIterator<LsmIterator>> {
+        self.check_read_ts(ts)?;
+        let start = Instant::now();
+        let iter = self.scan_with_ts(lower, upper, ts);
+        self.metrics.scan_latency.record(start.elapsed());
+        iter
+    }
+
+    /// Create an iterator over the keys starting with `prefix`. With `prefix_extractor`, the SSTs
+    /// whose bloom filters rule out the prefix are skipped.
+    pub fn prefix_scan(&self, prefix: &[u8]) -> Result<FusedIterator<LsmIterator>> {
+        self.check_bytewise("prefix_scan")?;
+        let start = Instant::now();
+        let upper = prefix_upper_bound(prefix);
+        let iter = self.scan_with_prefix(
+            Bound::Included(prefix),
+            upper.as_deref().map_or(Bound::Unbounded, Bound::Excluded),
+            self.mvcc().latest_commit_ts(),
+            Some(prefix),
+        );
+        self.metrics.scan_latency.record(start.elapsed());
+        iter
+    }
+
+    /// Create an iterator over the versions committed after `since_ts`, up to the latest commit
+    /// timestamp, see `ChangeIterator`. A consumer can pass the `read_ts` of the previous
+    /// iterator to get the changes since then. The versions superseded before the watermark may
+    /// have been removed by compactions, so to see every version, e.g., not only the latest one of
+    /// a key written twice, keep a snapshot at `since_ts` until the changes are consumed.
+    pub fn scan_changes(&self, since_ts: u64) -> Result<ChangeIterator> {
+        let start = Instant::now();
+        // the value logs first, see `ValueLogs::snapshot`
+        let value_logs = self.value_logs.snapshot();
+        let state = self.state.read().clone();
+        let read_ts = self.mvcc().latest_commit_ts();
+        let iter = self.merge_versions(
+            &state,
+            Bound::Unbounded,
+            Bound::Unbounded,
+            None,
+            Some(since_ts),
+        )?;
+        let mut range_deletes = state
+            .range_tombstones(read_ts)
+            .into_iter()
+            .filter(|tombstone| tombstone.ts > since_ts)
+            .collect::<Vec<_>>();
+        // compactions copy a tombstone to every output SST it overlaps
+        range_deletes.sort_by(|a, b| (a.ts, &a.start).cmp(&(b.ts, &b.start)));
+        range_deletes.dedup();
+        let iter = ChangeIterator::new(iter, since_ts, read_ts, range_deletes, value_logs);
+        self.metrics.scan_latency.record(start.elapsed());
+        iter
+    }
+
+    /// The versions of a key up to the latest commit timestamp, from the latest to the earliest,
+    /// including the tombstones and the merge operands. The range deletes covering the key are
+    /// returned as deletes at their timestamps. As with `scan_changes`, the versions superseded
+    /// before the watermark may have been removed by compactions.
+    pub fn history(&self, key: &[u8]) -> Result<Vec<KeyVersion>> {
+        // the value logs first, see `ValueLogs::snapshot`
+        let value_logs = self.value_logs.snapshot();
+        let state = self.state.read().clone();
+        let read_ts = self.mvcc().latest_commit_ts();
+        let iter = self.merge_versions(
+            &state,
+            Bound::Included(key),
+            Bound::Included(key),
+            None,
+            None,
+        )?;
+        // the SSTs are not bounded by `key`, so the iterator may go on with the next keys
+        let mut iter = ChangeIterator::new(iter, 0, read_ts, Vec::new(), value_logs)?;
+        let mut versions = Vec::new();
+        while iter.is_valid() && iter.key().key_ref() == key {
+            versions.push(KeyVersion {
+                ts: iter.key().ts(),
+                kind: iter.kind(),
+                value: Bytes::copy_from_slice(iter.value()),
+                expire_at: iter.expire_at(),
+            });
+            iter.next()?;
+        }
+        versions.extend(
+            state
+                .range_tombstones(read_ts)
+                .into_iter()
+                .filter(|tombstone| tombstone.contains(key))
+                .map(|tombstone| KeyVersion {
+                    ts: tombstone.ts,
+                    kind: ChangeKind::Delete,
+                    value: Bytes::new(),
+                    expire_at: None,
+                }),
+        );
+        // compactions copy a tombstone to every output SST it overlaps
+        versions.sort_by_key(|version| Reverse(version.ts));
+        versions.dedup();
+        Ok(versions)
+    }
+
+    /// Create an iterator over a range of keys that sees the versions committed at or before
+    /// `read_ts`.
+    pub(crate) fn scan_with_ts(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        read_ts: u64,
     ) -> Result<FusedIterator<LsmIterator>> {
+        self.scan_with_prefix(lower, upper, read_ts, None)
+    }
+
+    /// `scan_with_ts`, skipping the SSTs that do not contain `prefix` by their bloom filters, see
+    /// `SsTable::may_contain_prefix`.
+    fn scan_with_prefix(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        read_ts: u64,
+        prefix: Option<&[u8]>,
+    ) -> Result<FusedIterator<LsmIterator>> {
+        // the value logs first, see `ValueLogs::snapshot`
+        let value_logs = self.value_logs.snapshot();
         let state = self.state.read().clone();
-        // FusedIterator::new(LsmIterator::new(iter))
+        let iter = self.merge_versions(&state, lower, upper, prefix, None)?;
+        let range_tombstones = state.range_tombstones(read_ts);
+        Ok(FusedIterator::new(LsmIterator::new(
+            iter,
+            read_ts,
+            lower,
+            upper,
+            range_tombstones,
+            value_logs,
+            self.options.merge_operator.clone(),
+        )?))
+    }
 
+    /// The versions of the keys in the range in the memtables and the SSTs of `state`, merged,
+    /// skipping the SSTs without any version after `since_ts` if set.
+    fn merge_versions(
+        &self,
+        state: &LsmStorageState,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        prefix: Option<&[u8]>,
+        since_ts: Option<u64>,
+    ) -> Result<LsmIteratorInner> {
         let mut vec = Vec::<Box<MemTableIterator>>::with_capacity(state.imm_memtables.len() + 1);
 
-        let act_iter = state.memtable.scan(_lower, _upper);
+        let act_iter = state.memtable.scan(lower, upper);
         vec.push(Box::new(act_iter));
         for mem in &state.imm_memtables {
-            let iter = mem.scan(_lower, _upper);
+            let iter = mem.scan(lower, upper);
             vec.push(Box::new(iter));
         }
 
-        let merge_iter = MergeIterator::create(vec);
-        Ok(FusedIterator::new(LsmIterator::new(merge_iter)?))
+        let comparator = &self.options.comparator;
+        let memtable_iter = MergeIterator::create(vec).with_comparator(comparator.clone());
+
+        // SSTs from latest to earliest: every L0 SST, then every level or tier, as one sorted run
+        let runs = state
+            .l0_sstables
+            .iter()
+            .map(|sst_id| vec![*sst_id])
+            .chain(state.levels.iter().map(|(_, ssts)| ssts.clone()));
+        let mut sst_iters = Vec::new();
+        for run in runs {
+            let ssts = run
+                .iter()
+                .map(|sst_id| state.sstables[sst_id].clone())
+                .filter(|sst| {
+                    // an SST may hold range tombstones only
+                    sst.num_of_blocks() > 0
+                        && since_ts.is_none_or(|since_ts| sst.max_ts() > since_ts)
+                        && range_overlap(
+                            lower,
+                            upper,
+                            sst.first_key(),
+                            sst.last_key(),
+                            &*self.options.comparator,
+                        )
+                        && prefix
+                            .zip(self.options.prefix_extractor.as_deref())
+                            .is_none_or(|(prefix, extractor)| {
+                                sst.may_contain_prefix(extractor, prefix)
+                            })
+                })
+                .collect::<Vec<_>>();
+            if !ssts.is_empty() {
+                let iter = SstConcatIterator::create_and_seek_to_range(ssts, lower)?
+                    .with_readahead(self.options.readahead_bytes);
+                sst_iters.push(Box::new(iter));
+            }
+        }
+        let sst_iter = MergeIterator::create(sst_iters).with_comparator(comparator.clone());
+
+        TwoMergeIterator::create_with_comparator(memtable_iter, sst_iter, comparator.clone())
+    }
+}
+
+/// The entries of `batch` with the `values` encoded by `LsmStorageInner::encode_values`.
+pub(crate) fn batch_data<'a, T: AsRef<[u8]>>(
+    batch: &'a [WriteBatchRecord<T>],
+    values: &'a [Cow<'a, [u8]>],
+    ts: u64,
+) -> Vec<(KeySlice<'a>, &'a [u8])> {
+    batch
+        .iter()
+        .zip(values.iter())
+        .map(|(record, value)| match record {
+            WriteBatchRecord::Put(key, _)
+            | WriteBatchRecord::Del(key)
+            | WriteBatchRecord::Merge(key, _) => {
+                (KeySlice::from_slice(key.as_ref(), ts), value.as_ref())
+            }
+        })
+        .collect()
+}
+
+/// The records of `batch`, committed at `ts`, for the replication log.
+fn replication_batch<T: AsRef<[u8]>>(
+    batch: &[WriteBatchRecord<T>],
+    ts: u64,
+    expire_at: Option<u64>,
+) -> ReplicationBatch {
+    let records = batch
+        .iter()
+        .map(|record| match record {
+            WriteBatchRecord::Put(key, value) => ReplicationRecord {
+                key: Bytes::copy_from_slice(key.as_ref()),
+                op: ReplicationOp::Put {
+                    value: Bytes::copy_from_slice(value.as_ref()),
+                    expire_at,
+                },
+            },
+            WriteBatchRecord::Del(key) => ReplicationRecord {
+                key: Bytes::copy_from_slice(key.as_ref()),
+                op: ReplicationOp::Delete,
+            },
+            WriteBatchRecord::Merge(key, operand) => ReplicationRecord {
+                key: Bytes::copy_from_slice(key.as_ref()),
+                op: ReplicationOp::Merge(Bytes::copy_from_slice(operand.as_ref())),
+            },
+        })
+        .collect();
+    ReplicationBatch {
+        sequence: ts,
+        records,
+    }
+}
+
+/// The smallest key greater than all keys starting with `prefix`, `None` if there is none.
+fn prefix_upper_bound(prefix: &[u8]) -> Option<Vec<u8>> {
+    let len = prefix.iter().rposition(|byte| *byte != u8::MAX)?;
+    let mut upper = prefix[..=len].to_vec();
+    upper[len] += 1;
+    Some(upper)
+}
+
+/// Whether the user keys of an SST from `first_key` to `last_key` overlap with the range.
+fn range_overlap(
+    lower: Bound<&[u8]>,
+    upper: Bound<&[u8]>,
+    first_key: &KeyBytes,
+    last_key: &KeyBytes,
+    comparator: &dyn KeyComparator,
+) -> bool {
+    let above_lower = match lower {
+        Bound::Included(lower) => comparator.compare(last_key.key_ref(), lower).is_ge(),
+        Bound::Excluded(lower) => comparator.compare(last_key.key_ref(), lower).is_gt(),
+        Bound::Unbounded => true,
+    };
+    let below_upper = match upper {
+        Bound::Included(upper) => comparator.compare(first_key.key_ref(), upper).is_le(),
+        Bound::Excluded(upper) => comparator.compare(first_key.key_ref(), upper).is_lt(),
+        Bound::Unbounded => true,
+    };
+    above_lower && below_upper
+}
+
+impl MvccStorage for LsmStorageInner {
+    fn mvcc(&self) -> &LsmMvccInner {
+        LsmStorageInner::mvcc(self)
+    }
+
+    fn get_with_ts(&self, key: &[u8], read_ts: u64) -> Result<Option<Bytes>> {
+        LsmStorageInner::get_with_ts(self, key, read_ts)
+    }
+
+    fn scan_with_ts(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        read_ts: u64,
+    ) -> Result<FusedIterator<LsmIterator>> {
+        LsmStorageInner::scan_with_ts(self, lower, upper, read_ts)
+    }
+
+    fn write_batch_inner(&self, batch: &[WriteBatchRecord<Bytes>]) -> Result<u64> {
+        LsmStorageInner::write_batch_inner(self, batch)
     }
 }
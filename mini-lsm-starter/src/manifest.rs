@@ -12,37 +12,152 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-#![allow(unused_variables)] // TODO(you): remove this lint after implementing this mod
-#![allow(dead_code)] // TODO(you): remove this lint after implementing this mod
-
-use std::fs::File;
-use std::path::Path;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
-use anyhow::Result;
+use anyhow::{Context, Result, bail};
+use bytes::{Buf, BufMut};
 use parking_lot::{Mutex, MutexGuard};
 use serde::{Deserialize, Serialize};
 
 use crate::compact::CompactionTask;
+use crate::error::Error;
+use crate::failpoint;
+
+/// The manifest is compacted once it grows beyond this size by default, see
+/// `Manifest::with_compaction_threshold`.
+pub const DEFAULT_MANIFEST_COMPACTION_THRESHOLD: u64 = 4 << 20;
 
+/// A log of the changes to the structure of the LSM tree. Every record is appended as:
+///
+/// `| len (u64) | JSON-encoded record | checksum (u32) |`
+///
+/// where the checksum covers the encoded record. Replaying the records from the start rebuilds the
+/// memtable ids, the L0 SSTs and the levels of the LSM tree.
 pub struct Manifest {
     file: Arc<Mutex<File>>,
+    path: PathBuf,
+    /// Size of the manifest file, to decide when to compact it
+    size: Mutex<u64>,
+    compaction_threshold: u64,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Debug)]
 pub enum ManifestRecord {
     Flush(usize),
     NewMemtable(usize),
     Compaction(CompactionTask, Vec<usize>),
+    /// SSTs ingested into the level with the id, or as the oldest tier if `None`.
+    Ingest {
+        level: Option<usize>,
+        ssts: Vec<usize>,
+    },
+    /// A column family created with the id, whose SSTs are in the directory of its own and listed
+    /// by the manifest there.
+    CreateColumnFamily {
+        id: usize,
+        name: String,
+    },
+    DropColumnFamily(usize),
+    /// The name of the comparator ordering the keys, recorded when the storage is created. A
+    /// manifest without it is of the bytewise order.
+    Comparator(String),
+    /// The whole structure of the LSM tree when the manifest was compacted, replacing all records
+    /// before it.
+    Snapshot {
+        /// Ids of the memtables, from latest to earliest
+        memtables: Vec<usize>,
+        l0_sstables: Vec<usize>,
+        levels: Vec<(usize, Vec<usize>)>,
+        next_sst_id: usize,
+        /// Ids and names of the column families
+        #[serde(default)]
+        column_families: Vec<(usize, String)>,
+        /// Name of the comparator, see `ManifestRecord::Comparator`
+        #[serde(default)]
+        comparator: Option<String>,
+    },
 }
 
 impl Manifest {
     pub fn create(_path: impl AsRef<Path>) -> Result<Self> {
-        unimplemented!()
+        let path = _path.as_ref();
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .context("failed to create manifest")?;
+        Ok(Self::new(file, path, 0))
+    }
+
+    fn new(file: File, path: &Path, size: u64) -> Self {
+        Self {
+            file: Arc::new(Mutex::new(file)),
+            path: path.to_path_buf(),
+            size: Mutex::new(size),
+            compaction_threshold: DEFAULT_MANIFEST_COMPACTION_THRESHOLD,
+        }
+    }
+
+    /// Set the size beyond which `needs_compaction` reports the manifest should be compacted.
+    pub fn with_compaction_threshold(mut self, threshold: u64) -> Self {
+        self.compaction_threshold = threshold;
+        self
     }
 
+    /// Read all records of the manifest at `path` and reopen it for appending. A record cut short at
+    /// the end of the file, i.e., a write interrupted by a crash, is dropped; a record whose
+    /// checksum does not match is reported as corruption.
     pub fn recover(_path: impl AsRef<Path>) -> Result<(Self, Vec<ManifestRecord>)> {
-        unimplemented!()
+        let path = _path.as_ref();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .open(path)
+            .context("failed to recover manifest")?;
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf)?;
+        let (records, valid_len) = Self::decode_records(&buf)?;
+        if valid_len < buf.len() {
+            file.set_len(valid_len as u64)?;
+            file.sync_all()?;
+        }
+        Ok((Self::new(file, path, valid_len as u64), records))
+    }
+
+    /// Read all records of the manifest at `path` without modifying it. A record cut short at the
+    /// end of the file may be being written by another process, and is ignored.
+    pub fn read_records(path: impl AsRef<Path>) -> Result<Vec<ManifestRecord>> {
+        let buf = std::fs::read(path).context("failed to read manifest")?;
+        Ok(Self::decode_records(&buf)?.0)
+    }
+
+    /// Decode the complete records at the start of `buf`, and return them with their total length.
+    fn decode_records(buf: &[u8]) -> Result<(Vec<ManifestRecord>, usize)> {
+        let mut rbuf = buf;
+        let mut records = Vec::new();
+        let mut valid_len = 0;
+        while rbuf.remaining() >= 8 {
+            let len = (&rbuf[..]).get_u64() as usize;
+            if rbuf.remaining() < 8 + len + 4 {
+                break;
+            }
+            rbuf.advance(8);
+            let data = &rbuf[..len];
+            rbuf.advance(len);
+            if rbuf.get_u32() != crc32fast::hash(data) {
+                bail!(Error::corruption(format!(
+                    "manifest record at offset {} checksum mismatched",
+                    valid_len
+                )));
+            }
+            records.push(serde_json::from_slice(data)?);
+            valid_len += 8 + len + 4;
+        }
+        Ok((records, valid_len))
     }
 
     pub fn add_record(
@@ -54,6 +169,53 @@ impl Manifest {
     }
 
     pub fn add_record_when_init(&self, _record: ManifestRecord) -> Result<()> {
-        unimplemented!()
+        let buf = Self::encode_record(&_record)?;
+        failpoint::eval(failpoint::MANIFEST_WRITE, &self.path)?;
+        let mut file = self.file.lock();
+        file.write_all(&buf)?;
+        file.sync_all()?;
+        *self.size.lock() += buf.len() as u64;
+        Ok(())
+    }
+
+    fn encode_record(record: &ManifestRecord) -> Result<Vec<u8>> {
+        let data = serde_json::to_vec(record)?;
+        let mut buf = Vec::with_capacity(data.len() + 12);
+        buf.put_u64(data.len() as u64);
+        buf.put_slice(&data);
+        buf.put_u32(crc32fast::hash(&data));
+        Ok(buf)
+    }
+
+    /// Whether the manifest has grown beyond the compaction threshold.
+    pub fn needs_compaction(&self) -> bool {
+        *self.size.lock() > self.compaction_threshold
+    }
+
+    /// Replace all records of the manifest with `snapshot`, which should be a
+    /// `ManifestRecord::Snapshot` of the current structure of the LSM tree. The new manifest is
+    /// written to a temporary file and renamed over the old one, so a crash leaves either of them
+    /// intact.
+    pub fn compact(
+        &self,
+        _state_lock_observer: &MutexGuard<()>,
+        snapshot: ManifestRecord,
+    ) -> Result<()> {
+        let buf = Self::encode_record(&snapshot)?;
+        failpoint::eval(failpoint::MANIFEST_WRITE, &self.path)?;
+        let tmp_path = self.path.with_extension("tmp");
+        let mut tmp_file = File::create(&tmp_path)?;
+        tmp_file.write_all(&buf)?;
+        tmp_file.sync_all()?;
+        drop(tmp_file);
+
+        let mut file = self.file.lock();
+        std::fs::rename(&tmp_path, &self.path)?;
+        if let Some(dir) = self.path.parent() {
+            File::open(dir)?.sync_all()?;
+        }
+        *file = OpenOptions::new().append(true).open(&self.path)?;
+        *self.size.lock() = buf.len() as u64;
+        Ok(())
     }
 }
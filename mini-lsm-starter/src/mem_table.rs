@@ -22,30 +22,62 @@ use std::sync::atomic::AtomicUsize;
 
 use anyhow::{Context, Result};
 use bytes::Bytes;
-use crossbeam_skiplist::{SkipMap, map};
-use ouroboros::self_referencing;
+use parking_lot::RwLock;
 
-use crate::iterators::StorageIterator;
-use crate::key::KeySlice;
+use crate::error::Error;
+use crate::iterators::{ReversibleIterator, StorageIterator};
+use crate::key::{
+    Comparator, KeyBytes, KeySlice, TS_DEFAULT, TS_RANGE_BEGIN, TS_RANGE_END, bytewise_comparator,
+};
+use crate::range_tombstone::RangeTombstone;
 use crate::table::SsTableBuilder;
-use crate::wal::Wal;
+use crate::wal::{ColumnFamilyBatch, ColumnFamilyEntries, Wal};
 
-/// A basic mem-table based on crossbeam-skiplist.
+mod skiplist;
+
+use skiplist::Cursor;
+pub(crate) use skiplist::SkipList;
+
+/// A basic mem-table based on an arena-backed skiplist, see `SkipList`.
 ///
 /// An initial implementation of memtable is part of week 1, day 1. It will be incrementally implemented in other
 /// chapters of week 1 and week 2.
 pub struct MemTable {
-    map: Arc<SkipMap<Bytes, Bytes>>,
+    map: Arc<SkipList>,
+    /// Range tombstones, in the order they are written.
+    range_tombstones: RwLock<Vec<RangeTombstone>>,
     wal: Option<Wal>,
     id: usize,
-    approximate_size: Arc<AtomicUsize>,
+    /// Size of the range tombstones, the size of the versions is the memory usage of `map`.
+    range_tombstones_size: AtomicUsize,
 }
 
-/// Create a bound of `Bytes` from a bound of `&[u8]`.
-pub(crate) fn map_bound(bound: Bound<&[u8]>) -> Bound<Bytes> {
+/// Create a lower bound of keys covering all versions of the user keys within `bound`.
+pub(crate) fn map_lower_bound(bound: Bound<&[u8]>) -> Bound<KeyBytes> {
     match bound {
-        Bound::Included(x) => Bound::Included(Bytes::copy_from_slice(x)),
-        Bound::Excluded(x) => Bound::Excluded(Bytes::copy_from_slice(x)),
+        Bound::Included(x) => Bound::Included(KeyBytes::from_bytes_with_ts(
+            Bytes::copy_from_slice(x),
+            TS_RANGE_BEGIN,
+        )),
+        Bound::Excluded(x) => Bound::Excluded(KeyBytes::from_bytes_with_ts(
+            Bytes::copy_from_slice(x),
+            TS_RANGE_END,
+        )),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+/// Create an upper bound of keys covering all versions of the user keys within `bound`.
+pub(crate) fn map_upper_bound(bound: Bound<&[u8]>) -> Bound<KeyBytes> {
+    match bound {
+        Bound::Included(x) => Bound::Included(KeyBytes::from_bytes_with_ts(
+            Bytes::copy_from_slice(x),
+            TS_RANGE_END,
+        )),
+        Bound::Excluded(x) => Bound::Excluded(KeyBytes::from_bytes_with_ts(
+            Bytes::copy_from_slice(x),
+            TS_RANGE_BEGIN,
+        )),
         Bound::Unbounded => Bound::Unbounded,
     }
 }
@@ -54,10 +86,11 @@ impl MemTable {
     /// Create a new mem-table.
     pub fn create(_id: usize) -> Self {
         MemTable {
-            map: Arc::new(SkipMap::new()),
+            map: Arc::new(SkipList::new()),
+            range_tombstones: RwLock::new(Vec::new()),
             wal: None,
             id: _id,
-            approximate_size: Arc::new(AtomicUsize::new(0)),
+            range_tombstones_size: AtomicUsize::new(0),
         }
     }
 
@@ -66,24 +99,56 @@ impl MemTable {
         let wal = Wal::create(_path)
             .with_context(|| format!("failed to create WAL for memtable {}", _id))?;
         Ok(MemTable {
-            map: Arc::new(SkipMap::new()),
+            map: Arc::new(SkipList::new()),
+            range_tombstones: RwLock::new(Vec::new()),
             wal: Some(wal),
             id: _id,
-            approximate_size: Arc::new(AtomicUsize::new(0)),
+            range_tombstones_size: AtomicUsize::new(0),
         })
     }
 
+    /// Order the keys of this empty mem-table by `comparator` instead of bytewise.
+    pub fn with_comparator(mut self, comparator: Comparator) -> Self {
+        debug_assert!(self.is_empty(), "the comparator is set before any write");
+        self.map = Arc::new(SkipList::with_comparator(comparator));
+        self
+    }
+
     /// Create a memtable from WAL
     pub fn recover_from_wal(_id: usize, _path: impl AsRef<Path>) -> Result<Self> {
-        unimplemented!()
+        Self::recover_from_shared_wal(_id, _path, bytewise_comparator())
+            .map(|(memtable, _)| memtable)
+    }
+
+    /// Create a memtable ordered by `comparator` from a WAL that also logs the writes to column
+    /// families, and return the entries of the column families along with it.
+    pub fn recover_from_shared_wal(
+        id: usize,
+        path: impl AsRef<Path>,
+        comparator: Comparator,
+    ) -> Result<(Self, ColumnFamilyEntries)> {
+        let map = Arc::new(SkipList::with_comparator(comparator));
+        let mut range_tombstones = Vec::new();
+        let mut column_families = Vec::new();
+        let wal = Wal::recover(path, &map, &mut range_tombstones, &mut column_families)
+            .with_context(|| format!("failed to recover memtable {} from WAL", id))?;
+        let range_tombstones_size = range_tombstones.iter().map(RangeTombstone::raw_len).sum();
+        let memtable = MemTable {
+            map,
+            range_tombstones: RwLock::new(range_tombstones),
+            wal: Some(wal),
+            id,
+            range_tombstones_size: AtomicUsize::new(range_tombstones_size),
+        };
+        Ok((memtable, column_families))
     }
 
     pub fn for_testing_put_slice(&self, key: &[u8], value: &[u8]) -> Result<()> {
-        self.put(key, value)
+        self.put(KeySlice::from_slice(key, TS_DEFAULT), value)
     }
 
     pub fn for_testing_get_slice(&self, key: &[u8]) -> Option<Bytes> {
-        self.get(key)
+        self.get(KeySlice::from_slice(key, TS_DEFAULT))
     }
 
     pub fn for_testing_scan_slice(
@@ -97,11 +162,19 @@ impl MemTable {
         self.scan(lower, upper)
     }
 
-    /// Get a value by key.
-    pub fn get(&self, _key: &[u8]) -> Option<Bytes> {
+    /// Get the value of the latest version of the user key of `_key` with a timestamp <=
+    /// `_key.ts()`. A deleted key has an empty value.
+    pub fn get(&self, _key: KeySlice) -> Option<Bytes> {
         // unimplemented!()
-        // TODO - should be able to deal with tomb.
-        self.map.get(_key).map(|entry| entry.value().clone())
+        self.get_version(_key).map(|(_, value)| value)
+    }
+
+    /// `get`, also returning the timestamp of the version found.
+    pub fn get_version(&self, key: KeySlice) -> Option<(u64, Bytes)> {
+        let cursor = self.map.seek(Bound::Included(key))?;
+        let found = self.map.key(cursor);
+        (found.key_ref() == key.key_ref())
+            .then(|| (found.ts(), Bytes::copy_from_slice(self.map.value(cursor))))
     }
 
     /// Put a key-value pair into the mem-table.
@@ -109,20 +182,65 @@ impl MemTable {
     /// In week 1, day 1, simply put the key-value pair into the skipmap.
     /// In week 2, day 6, also flush the data to WAL.
     /// In week 3, day 5, modify the function to use the batch API.
-    pub fn put(&self, _key: &[u8], _value: &[u8]) -> Result<()> {
+    pub fn put(&self, _key: KeySlice, _value: &[u8]) -> Result<()> {
         // unimplemented!()
-        self.map
-            .insert(Bytes::copy_from_slice(_key), Bytes::copy_from_slice(_value));
-        self.approximate_size.fetch_add(
-            _key.len() + _value.len(),
-            std::sync::atomic::Ordering::Relaxed,
-        );
-        Ok(())
+        self.put_batch(&[(_key, _value)])
     }
 
-    /// Implement this in week 3, day 5; if you want to implement this earlier, use `&[u8]` as the key type.
+    /// Put all key-value pairs into the mem-table, logging them to the WAL as a single record
+    /// first, so that they are recovered all together or not at all.
     pub fn put_batch(&self, _data: &[(KeySlice, &[u8])]) -> Result<()> {
-        unimplemented!()
+        if let Some(ref wal) = self.wal {
+            wal.put_batch(_data)?;
+        }
+        for (key, value) in _data {
+            self.map.insert(*key, value);
+        }
+        Ok(())
+    }
+
+    /// `put_batch`, logging the batches written to column families in `column_families` with it
+    /// as one WAL record, without putting them into this mem-table, see `log_column_family_batch`.
+    pub fn put_batch_with_column_families(
+        &self,
+        data: &[(KeySlice, &[u8])],
+        column_families: &[ColumnFamilyBatch],
+    ) -> Result<()> {
+        if let Some(ref wal) = self.wal {
+            wal.put_batch_with_column_families(data, column_families)?;
+        }
+        for (key, value) in data {
+            self.map.insert(*key, value);
+        }
+        Ok(())
+    }
+
+    /// Delete the user keys in the range of the tombstone, logging it to the WAL first.
+    pub fn delete_range(&self, tombstone: RangeTombstone) -> Result<()> {
+        if let Some(ref wal) = self.wal {
+            wal.put_range_tombstone(&tombstone)?;
+        }
+        self.range_tombstones_size
+            .fetch_add(tombstone.raw_len(), std::sync::atomic::Ordering::Relaxed);
+        self.range_tombstones.write().push(tombstone);
+        Ok(())
+    }
+
+    /// Log a batch written to the column family of `column_family_id` to the WAL, without putting
+    /// it into this mem-table. The column family keeps the batch in a mem-table of its own.
+    pub fn log_column_family_batch(
+        &self,
+        column_family_id: usize,
+        data: &[(KeySlice, &[u8])],
+    ) -> Result<()> {
+        if let Some(ref wal) = self.wal {
+            wal.put_column_family_batch(column_family_id, data)?;
+        }
+        Ok(())
+    }
+
+    pub fn range_tombstones(&self) -> Vec<RangeTombstone> {
+        self.range_tombstones.read().clone()
     }
 
     pub fn sync_wal(&self) -> Result<()> {
@@ -132,60 +250,138 @@ impl MemTable {
         Ok(())
     }
 
-    /// Get an iterator over a range of keys.
+    /// Sync the WAL together with the concurrent writers, see `Wal::sync_group`.
+    pub fn sync_wal_group(&self) -> Result<()> {
+        if let Some(ref wal) = self.wal {
+            wal.sync_group()?;
+        }
+        Ok(())
+    }
+
+    /// Get an iterator over all versions of a range of user keys.
     pub fn scan(&self, _lower: Bound<&[u8]>, _upper: Bound<&[u8]>) -> MemTableIterator {
-        let lower = map_bound(_lower);
-        let upper = map_bound(_upper);
-        let mut iter = MemTableIterator::new(
-            self.map.clone(),
-            |map| map.range((lower, upper)),
-            (Bytes::new(), Bytes::new()),
-        );
-        iter.with_mut(|field| {
-            if let Some(entry) = field.iter.next() {
-                *field.item = (entry.key().clone(), entry.value().clone());
-            }
-        });
+        let bounds = (map_lower_bound(_lower), map_upper_bound(_upper));
+        let cursor = self.map.seek(bounds.0.as_ref().map(KeyBytes::as_key_slice));
+        let mut iter = MemTableIterator {
+            map: self.map.clone(),
+            cursor: None,
+            bounds,
+        };
+        iter.cursor = iter.within_range(cursor);
         iter
     }
 
     /// Flush the mem-table to SSTable. Implement in week 1 day 6.
     pub fn flush(&self, _builder: &mut SsTableBuilder) -> Result<()> {
-        unimplemented!()
+        for (key, value) in self.map.iter() {
+            _builder.add(key, value);
+        }
+        for tombstone in self.range_tombstones.read().iter() {
+            _builder.add_range_tombstone(tombstone.clone());
+        }
+        Ok(())
     }
 
     pub fn id(&self) -> usize {
         self.id
     }
 
+    pub fn comparator(&self) -> &Comparator {
+        self.map.comparator()
+    }
+
+    /// The largest timestamp of all keys in the mem-table, 0 if it is empty.
+    pub fn max_ts(&self) -> u64 {
+        let range_tombstones = self.range_tombstones.read();
+        self.map
+            .iter()
+            .map(|(key, _)| key.ts())
+            .chain(range_tombstones.iter().map(|tombstone| tombstone.ts))
+            .max()
+            .unwrap_or_default()
+    }
+
+    /// Memory taken by the versions in the arena of the skiplist, including the nodes and the
+    /// replaced values, plus the size of the range tombstones.
     pub fn approximate_size(&self) -> usize {
-        self.approximate_size
-            .load(std::sync::atomic::Ordering::Relaxed)
+        self.map.memory_usage()
+            + self
+                .range_tombstones_size
+                .load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    /// Number of versions and range tombstones in the mem-table.
+    pub fn num_entries(&self) -> usize {
+        self.map.len() + self.range_tombstones.read().len()
+    }
+
+    /// Size of the WAL in bytes, 0 without WAL.
+    pub fn wal_size(&self) -> u64 {
+        self.wal.as_ref().map_or(0, Wal::size)
     }
 
     /// Only use this function when closing the database
     pub fn is_empty(&self) -> bool {
-        self.map.is_empty()
+        self.map.is_empty() && self.range_tombstones.read().is_empty()
     }
 }
 
-type SkipMapRangeIter<'a> =
-    crossbeam_skiplist::map::Range<'a, Bytes, (Bound<Bytes>, Bound<Bytes>), Bytes, Bytes>;
-
-/// An iterator over a range of `SkipMap`. This is a self-referential structure and please refer to week 1, day 2
-/// chapter for more information.
+/// An iterator over a range of the skiplist of a mem-table. It keeps the skiplist alive, so it
+/// reads the keys and the values in place.
 ///
 /// This is part of week 1, day 2.
-#[self_referencing]
 pub struct MemTableIterator {
-    /// Stores a reference to the skipmap.
-    map: Arc<SkipMap<Bytes, Bytes>>,
-    /// Stores a skipmap iterator that refers to the lifetime of `MemTableIterator` itself.
-    #[borrows(map)]
-    #[not_covariant]
-    iter: SkipMapRangeIter<'this>,
-    /// Stores the current key-value pair.
-    item: (Bytes, Bytes),
+    map: Arc<SkipList>,
+    /// The current entry, `None` once the iterator is invalid. It is detached from the borrow of
+    /// `map`, which the iterator keeps alive.
+    cursor: Option<Cursor<'static>>,
+    /// The range of the scan, which moving backward and seeking stay within.
+    bounds: (Bound<KeyBytes>, Bound<KeyBytes>),
+}
+
+impl MemTableIterator {
+    /// `cursor` detached from the borrow of `map`, or `None` if it is `None` or out of the range of
+    /// the scan. The result is only to be stored in `self.cursor`.
+    fn within_range(&self, cursor: Option<Cursor<'_>>) -> Option<Cursor<'static>> {
+        let bounds = &self.bounds;
+        let comparator = self.map.comparator();
+        let cursor = cursor.filter(|cursor| {
+            let key = self.map.key(*cursor);
+            let above_lower = match &bounds.0 {
+                Bound::Included(lower) => {
+                    comparator.compare_keys(key, lower.as_key_slice()).is_ge()
+                }
+                Bound::Excluded(lower) => {
+                    comparator.compare_keys(key, lower.as_key_slice()).is_gt()
+                }
+                Bound::Unbounded => true,
+            };
+            above_lower && self.below_upper(key)
+        });
+        // SAFETY: the cursor comes from `map`, which lives as long as the iterator holding it.
+        cursor.map(|cursor| unsafe { cursor.detach() })
+    }
+
+    fn below_upper(&self, key: KeySlice) -> bool {
+        let comparator = self.map.comparator();
+        match &self.bounds.1 {
+            Bound::Included(upper) => comparator.compare_keys(key, upper.as_key_slice()).is_le(),
+            Bound::Excluded(upper) => comparator.compare_keys(key, upper.as_key_slice()).is_lt(),
+            Bound::Unbounded => true,
+        }
+    }
+
+    /// The last entry in the range of the scan whose key is within `bound` from above.
+    fn last_within(&self, bound: Bound<KeySlice>) -> Option<Cursor<'_>> {
+        let bound = match bound {
+            Bound::Included(key) | Bound::Excluded(key) if !self.below_upper(key) => {
+                // `bound` is beyond the range, clip it to the upper bound of the scan
+                self.bounds.1.as_ref().map(KeyBytes::as_key_slice)
+            }
+            bound => bound,
+        };
+        self.map.seek_for_prev(bound)
+    }
 }
 
 impl StorageIterator for MemTableIterator {
@@ -193,27 +389,58 @@ impl StorageIterator for MemTableIterator {
 
     fn value(&self) -> &[u8] {
         // unimplemented!()
-        &self.borrow_item().1[..]
+        self.cursor.map_or(&[][..], |cursor| self.map.value(cursor))
     }
 
     fn key(&self) -> KeySlice {
         // unimplemented!()
-        KeySlice::from_slice(&self.borrow_item().0[..])
+        self.cursor
+            .map_or(KeySlice::default(), |cursor| self.map.key(cursor))
     }
 
     fn is_valid(&self) -> bool {
         // unimplemented!()
-        self.with(|field| !field.item.0.is_empty())
+        self.cursor.is_some()
     }
 
     fn next(&mut self) -> Result<()> {
-        self.with_mut(|field| {
-            if let Some(entry) = field.iter.next() {
-                *field.item = (entry.key().clone(), entry.value().clone());
-            } else {
-                *field.item = (Bytes::new(), Bytes::new());
-            }
-        });
+        let next = self.cursor.and_then(|cursor| self.map.next(cursor));
+        self.cursor = self.within_range(next);
+        Ok(())
+    }
+
+    /// Move to the first key that >= `key` within the range of the scan, from any position.
+    fn seek_to_key(&mut self, key: KeySlice) -> Result<()> {
+        let comparator = self.map.comparator();
+        let below_lower = match &self.bounds.0 {
+            Bound::Included(lower) => comparator.compare_keys(key, lower.as_key_slice()).is_lt(),
+            Bound::Excluded(lower) => comparator.compare_keys(key, lower.as_key_slice()).is_le(),
+            Bound::Unbounded => false,
+        };
+        let cursor = if below_lower {
+            self.map
+                .seek(self.bounds.0.as_ref().map(KeyBytes::as_key_slice))
+        } else {
+            self.map.seek(Bound::Included(key))
+        };
+        self.cursor = self.within_range(cursor);
+        Ok(())
+    }
+}
+
+impl ReversibleIterator for MemTableIterator {
+    fn prev(&mut self) -> Result<()> {
+        let Some(cursor) = self.cursor else {
+            return Err(Error::IteratorExhausted.into());
+        };
+        let prev = self.last_within(Bound::Excluded(self.map.key(cursor)));
+        self.cursor = self.within_range(prev);
+        Ok(())
+    }
+
+    fn seek_for_prev(&mut self, key: KeySlice) -> Result<()> {
+        let prev = self.last_within(Bound::Included(key));
+        self.cursor = self.within_range(prev);
         Ok(())
     }
 }
@@ -0,0 +1,430 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A concurrent skiplist of the mem-table, allocating its nodes, keys and values from an arena.
+//!
+//! Nothing is freed before the whole skiplist is dropped, so a node never moves or goes away once
+//! it is linked, and readers follow the links without any lock. Writers link a new node level by
+//! level with compare-and-swap, from the bottom up. Putting a key that is already in the skiplist
+//! replaces the value of its node; the old value stays in the arena for the readers still on it.
+
+use std::alloc::{self, Layout};
+use std::marker::PhantomData;
+use std::ops::Bound;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, AtomicU64, AtomicUsize, Ordering};
+
+use parking_lot::Mutex;
+
+use crate::key::{Comparator, KeySlice, bytewise_comparator};
+
+const MAX_HEIGHT: usize = 12;
+
+/// Size of the first chunk of an arena. Each following chunk doubles up to `MAX_CHUNK_SIZE`, so
+/// that a small mem-table does not take a large chunk.
+const MIN_CHUNK_SIZE: usize = 4 << 10;
+
+const MAX_CHUNK_SIZE: usize = 1 << 20;
+
+/// A contiguous block of memory of an arena, handed out by bumping `offset`.
+struct Chunk {
+    base: *mut u8,
+    cap: usize,
+    offset: AtomicUsize,
+}
+
+impl Chunk {
+    fn new(cap: usize) -> Self {
+        let layout = Layout::from_size_align(cap, 8).unwrap();
+        let base = unsafe { alloc::alloc_zeroed(layout) };
+        if base.is_null() {
+            alloc::handle_alloc_error(layout);
+        }
+        Self {
+            base,
+            cap,
+            offset: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl Drop for Chunk {
+    fn drop(&mut self) {
+        let layout = Layout::from_size_align(self.cap, 8).unwrap();
+        unsafe { alloc::dealloc(self.base, layout) };
+    }
+}
+
+/// A bump allocator. Allocating only takes the lock when the current chunk is full.
+struct Arena {
+    current: AtomicPtr<Chunk>,
+    /// Boxed, so that `current` stays valid as the vector grows.
+    #[allow(clippy::vec_box)]
+    chunks: Mutex<Vec<Box<Chunk>>>,
+    memory_usage: AtomicUsize,
+}
+
+impl Arena {
+    fn new() -> Self {
+        Self {
+            current: AtomicPtr::new(ptr::null_mut()),
+            chunks: Mutex::new(Vec::new()),
+            memory_usage: AtomicUsize::new(0),
+        }
+    }
+
+    /// Allocate `size` bytes aligned to `align`, which is at most 8.
+    fn alloc(&self, size: usize, align: usize) -> *mut u8 {
+        // reserve the room for the padding as well, since the offset is unknown beforehand
+        let reserved = size + align - 1;
+        loop {
+            let current = self.current.load(Ordering::Acquire);
+            if let Some(chunk) = unsafe { current.as_ref() } {
+                let offset = chunk.offset.fetch_add(reserved, Ordering::Relaxed);
+                if offset + reserved <= chunk.cap {
+                    self.memory_usage.fetch_add(size, Ordering::Relaxed);
+                    let ptr = unsafe { chunk.base.add(offset) };
+                    return unsafe { ptr.add(ptr.align_offset(align)) };
+                }
+            }
+            self.grow(current, reserved);
+        }
+    }
+
+    /// Replace the `full` chunk with a new one of at least `min_cap` bytes, unless another writer
+    /// already did.
+    fn grow(&self, full: *mut Chunk, min_cap: usize) {
+        let mut chunks = self.chunks.lock();
+        if self.current.load(Ordering::Acquire) != full {
+            return;
+        }
+        let cap = chunks
+            .last()
+            .map_or(MIN_CHUNK_SIZE, |chunk| (chunk.cap * 2).min(MAX_CHUNK_SIZE))
+            .max(min_cap);
+        let mut chunk = Box::new(Chunk::new(cap));
+        self.current.store(&mut *chunk, Ordering::Release);
+        chunks.push(chunk);
+    }
+
+    /// Bytes allocated, not counting the padding for the alignment.
+    fn memory_usage(&self) -> usize {
+        self.memory_usage.load(Ordering::Relaxed)
+    }
+
+    /// Copy `data` into the arena.
+    fn alloc_slice(&self, data: &[u8]) -> *const u8 {
+        let ptr = self.alloc(data.len(), 1);
+        unsafe { ptr::copy_nonoverlapping(data.as_ptr(), ptr, data.len()) };
+        ptr
+    }
+
+    /// Copy `value` into the arena after its length, see `Node::value`.
+    fn alloc_value(&self, value: &[u8]) -> *mut u8 {
+        let ptr = self.alloc(4 + value.len(), 1);
+        unsafe {
+            ptr::copy_nonoverlapping((value.len() as u32).to_le_bytes().as_ptr(), ptr, 4);
+            ptr::copy_nonoverlapping(value.as_ptr(), ptr.add(4), value.len());
+        }
+        ptr
+    }
+}
+
+/// A node of the skiplist, followed in the arena by its `height` links to the next nodes, from
+/// the bottom level up.
+#[repr(C)]
+struct Node {
+    key: *const u8,
+    key_len: usize,
+    ts: u64,
+    /// The value after its length in a little-endian u32.
+    value: AtomicPtr<u8>,
+    height: usize,
+}
+
+impl Node {
+    /// The link to the next node on `level`.
+    ///
+    /// # Safety
+    ///
+    /// `node` must be a node of a skiplist alive for `'a`, and `level` lower than its height.
+    unsafe fn next<'a>(node: *const Node, level: usize) -> &'a AtomicPtr<Node> {
+        unsafe { &*(node.add(1) as *const AtomicPtr<Node>).add(level) }
+    }
+
+    /// # Safety
+    ///
+    /// `node` must be a node of a skiplist alive for `'a`, other than the head.
+    unsafe fn key<'a>(node: *const Node) -> KeySlice<'a> {
+        unsafe {
+            let node = &*node;
+            KeySlice::from_slice(std::slice::from_raw_parts(node.key, node.key_len), node.ts)
+        }
+    }
+
+    /// # Safety
+    ///
+    /// `node` must be a node of a skiplist alive for `'a`, other than the head.
+    unsafe fn value<'a>(node: *const Node) -> &'a [u8] {
+        unsafe {
+            let ptr = (*node).value.load(Ordering::Acquire);
+            let mut len = [0; 4];
+            ptr::copy_nonoverlapping(ptr, len.as_mut_ptr(), 4);
+            std::slice::from_raw_parts(ptr.add(4), u32::from_le_bytes(len) as usize)
+        }
+    }
+}
+
+/// A position in a `SkipList`, which borrows the skiplist it comes from so that it cannot outlive
+/// its node.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Cursor<'a>(*const Node, PhantomData<&'a SkipList>);
+
+impl Cursor<'_> {
+    /// Detach the cursor from the borrow of its skiplist.
+    ///
+    /// # Safety
+    ///
+    /// The skiplist must outlive every use of the returned cursor.
+    pub unsafe fn detach(self) -> Cursor<'static> {
+        Cursor(self.0, PhantomData)
+    }
+}
+
+// A cursor only points to the immutable parts of a node, or to its atomics.
+unsafe impl Send for Cursor<'_> {}
+unsafe impl Sync for Cursor<'_> {}
+
+/// A skiplist of the versions of the keys, ordered by `comparator`.
+pub(crate) struct SkipList {
+    arena: Arena,
+    comparator: Comparator,
+    /// A node without key of `MAX_HEIGHT`, before the first node on every level.
+    head: *const Node,
+    len: AtomicUsize,
+    /// State of the random generator of the heights, see `random_height`.
+    rng: AtomicU64,
+}
+
+// The nodes are only modified through atomics, and live as long as the arena.
+unsafe impl Send for SkipList {}
+unsafe impl Sync for SkipList {}
+
+impl SkipList {
+    pub fn new() -> Self {
+        Self::with_comparator(bytewise_comparator())
+    }
+
+    pub fn with_comparator(comparator: Comparator) -> Self {
+        let arena = Arena::new();
+        let head = Self::alloc_node(&arena, KeySlice::from_slice(&[], 0), &[], MAX_HEIGHT);
+        // the head is not part of the data
+        arena.memory_usage.store(0, Ordering::Relaxed);
+        Self {
+            arena,
+            comparator,
+            head,
+            len: AtomicUsize::new(0),
+            rng: AtomicU64::new(0x2545_f491_4f6c_dd1d),
+        }
+    }
+
+    fn alloc_node(arena: &Arena, key: KeySlice, value: &[u8], height: usize) -> *const Node {
+        let key_ptr = arena.alloc_slice(key.key_ref());
+        let value_ptr = arena.alloc_value(value);
+        let size = size_of::<Node>() + height * size_of::<AtomicPtr<Node>>();
+        let node = arena.alloc(size, align_of::<Node>()) as *mut Node;
+        unsafe {
+            node.write(Node {
+                key: key_ptr,
+                key_len: key.key_len(),
+                ts: key.ts(),
+                value: AtomicPtr::new(value_ptr),
+                height,
+            });
+            let tower = node.add(1) as *mut AtomicPtr<Node>;
+            for level in 0..height {
+                tower.add(level).write(AtomicPtr::new(ptr::null_mut()));
+            }
+        }
+        node
+    }
+
+    /// A height with a probability of 1/4 of growing by each level. The generator is seeded with
+    /// a constant, so that a skiplist takes the same memory for the same writes, and concurrent
+    /// writers racing on it only make the heights less random.
+    fn random_height(&self) -> usize {
+        let mut x = self.rng.load(Ordering::Relaxed);
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.rng.store(x, Ordering::Relaxed);
+        let mut height = 1;
+        while height < MAX_HEIGHT && x & 3 == 0 {
+            height += 1;
+            x >>= 2;
+        }
+        height
+    }
+
+    /// Move right from `node` on `level` while the next node is before `key`, and return the last
+    /// node before `key` with the one after it.
+    fn find_on_level(
+        &self,
+        mut node: *const Node,
+        level: usize,
+        key: KeySlice,
+    ) -> (*const Node, *const Node) {
+        loop {
+            let next = unsafe { Node::next(node, level) }.load(Ordering::Acquire);
+            if next.is_null()
+                || self
+                    .comparator
+                    .compare_keys(unsafe { Node::key(next) }, key)
+                    .is_ge()
+            {
+                return (node, next);
+            }
+            node = next;
+        }
+    }
+
+    /// Insert the version `key`, or replace its value if it is in the skiplist.
+    pub fn insert(&self, key: KeySlice, value: &[u8]) {
+        let mut prev = [self.head; MAX_HEIGHT];
+        let mut next = [ptr::null(); MAX_HEIGHT];
+        let mut node = self.head;
+        for level in (0..MAX_HEIGHT).rev() {
+            (prev[level], next[level]) = self.find_on_level(node, level, key);
+            node = prev[level];
+        }
+        if !next[0].is_null() && unsafe { Node::key(next[0]) } == key {
+            self.replace_value(next[0], value);
+            return;
+        }
+
+        let height = self.random_height();
+        let node = Self::alloc_node(&self.arena, key, value, height);
+        for level in 0..height {
+            loop {
+                let link = unsafe { Node::next(node, level) };
+                link.store(next[level] as *mut Node, Ordering::Relaxed);
+                if unsafe { Node::next(prev[level], level) }
+                    .compare_exchange(
+                        next[level] as *mut Node,
+                        node as *mut Node,
+                        Ordering::Release,
+                        Ordering::Relaxed,
+                    )
+                    .is_ok()
+                {
+                    break;
+                }
+                // another writer linked a node in between, search again from where we were
+                (prev[level], next[level]) = self.find_on_level(prev[level], level, key);
+                if level == 0 && !next[0].is_null() && unsafe { Node::key(next[0]) } == key {
+                    // the other writer inserted the same version, our node stays unlinked
+                    self.replace_value(next[0], value);
+                    return;
+                }
+            }
+        }
+        self.len.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn replace_value(&self, node: *const Node, value: &[u8]) {
+        let value = self.arena.alloc_value(value);
+        unsafe { &*node }.value.store(value, Ordering::Release);
+    }
+
+    /// The first node whose key is within `lower` from below.
+    pub fn seek(&self, lower: Bound<KeySlice>) -> Option<Cursor<'_>> {
+        let before = |key: KeySlice| match lower {
+            Bound::Included(lower) => self.comparator.compare_keys(key, lower).is_lt(),
+            Bound::Excluded(lower) => self.comparator.compare_keys(key, lower).is_le(),
+            Bound::Unbounded => false,
+        };
+        let mut node = self.head;
+        for level in (0..MAX_HEIGHT).rev() {
+            loop {
+                let next = unsafe { Node::next(node, level) }.load(Ordering::Acquire);
+                if next.is_null() || !before(unsafe { Node::key(next) }) {
+                    break;
+                }
+                node = next;
+            }
+        }
+        self.next(Cursor(node, PhantomData))
+    }
+
+    /// The last node whose key is within `upper` from above.
+    pub fn seek_for_prev(&self, upper: Bound<KeySlice>) -> Option<Cursor<'_>> {
+        let within = |key: KeySlice| match upper {
+            Bound::Included(upper) => self.comparator.compare_keys(key, upper).is_le(),
+            Bound::Excluded(upper) => self.comparator.compare_keys(key, upper).is_lt(),
+            Bound::Unbounded => true,
+        };
+        let mut node = self.head;
+        for level in (0..MAX_HEIGHT).rev() {
+            loop {
+                let next = unsafe { Node::next(node, level) }.load(Ordering::Acquire);
+                if next.is_null() || !within(unsafe { Node::key(next) }) {
+                    break;
+                }
+                node = next;
+            }
+        }
+        (node != self.head).then_some(Cursor(node, PhantomData))
+    }
+
+    pub fn comparator(&self) -> &Comparator {
+        &self.comparator
+    }
+
+    pub fn next<'a>(&'a self, cursor: Cursor<'a>) -> Option<Cursor<'a>> {
+        let next = unsafe { Node::next(cursor.0, 0) }.load(Ordering::Acquire);
+        (!next.is_null()).then_some(Cursor(next, PhantomData))
+    }
+
+    /// The key at `cursor`, which is valid as long as both this skiplist and the one of `cursor`.
+    pub fn key<'a>(&'a self, cursor: Cursor<'a>) -> KeySlice<'a> {
+        unsafe { Node::key(cursor.0) }
+    }
+
+    /// The value at `cursor`, which is valid as long as both this skiplist and the one of `cursor`.
+    pub fn value<'a>(&'a self, cursor: Cursor<'a>) -> &'a [u8] {
+        unsafe { Node::value(cursor.0) }
+    }
+
+    /// Iterate over all versions in order.
+    pub fn iter(&self) -> impl Iterator<Item = (KeySlice<'_>, &[u8])> {
+        std::iter::successors(self.seek(Bound::Unbounded), |cursor| self.next(*cursor))
+            .map(|cursor| (self.key(cursor), self.value(cursor)))
+    }
+
+    /// Number of versions.
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Bytes taken from the arena by the nodes, the keys and the values, including the replaced
+    /// values.
+    pub fn memory_usage(&self) -> usize {
+        self.arena.memory_usage()
+    }
+}
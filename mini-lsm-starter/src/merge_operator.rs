@@ -0,0 +1,109 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Read-modify-write without a read. `LsmStorageInner::merge` writes a merge operand as a version
+//! of its own, and the reads fold the operands of a key onto the version below them with the
+//! `merge_operator` of the storage. A compaction folds the operands at or below the watermark into
+//! a value once it sees the version below them, or if no older version is left outside of it.
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use bytes::{BufMut, Bytes};
+
+use crate::ttl;
+use crate::vlog::ValueLogSnapshot;
+
+/// A user callback that combines the merge operands of a key with its existing value, e.g., to
+/// add to a counter or append to a list. It must be deterministic, as the operands may be folded
+/// by any read or compaction.
+pub trait MergeOperator: std::fmt::Debug + Send + Sync {
+    /// The value of `key` after applying `operands`, from the earliest to the latest, to the
+    /// `existing` value, `None` if the key does not exist. An empty result deletes the key.
+    fn full_merge(&self, key: &[u8], existing: Option<&[u8]>, operands: &[&[u8]]) -> Bytes;
+}
+
+/// Adds up little-endian `u64`s with wrapping, where a missing or malformed value counts as 0.
+#[derive(Debug, Default)]
+pub struct UInt64AddOperator;
+
+impl UInt64AddOperator {
+    fn decode(value: &[u8]) -> u64 {
+        value.try_into().map_or(0, u64::from_le_bytes)
+    }
+}
+
+impl MergeOperator for UInt64AddOperator {
+    fn full_merge(&self, _key: &[u8], existing: Option<&[u8]>, operands: &[&[u8]]) -> Bytes {
+        let sum = operands
+            .iter()
+            .fold(existing.map_or(0, Self::decode), |sum, operand| {
+                sum.wrapping_add(Self::decode(operand))
+            });
+        Bytes::copy_from_slice(&sum.to_le_bytes())
+    }
+}
+
+/// Appends the operands to the existing value, separated by `delimiter`.
+#[derive(Debug, Default)]
+pub struct StringAppendOperator {
+    pub delimiter: Vec<u8>,
+}
+
+impl StringAppendOperator {
+    pub fn new(delimiter: &[u8]) -> Self {
+        Self {
+            delimiter: delimiter.to_vec(),
+        }
+    }
+}
+
+impl MergeOperator for StringAppendOperator {
+    fn full_merge(&self, _key: &[u8], existing: Option<&[u8]>, operands: &[&[u8]]) -> Bytes {
+        let mut buf = existing.map(<[u8]>::to_vec).unwrap_or_default();
+        for operand in operands {
+            if !buf.is_empty() {
+                buf.put_slice(&self.delimiter);
+            }
+            buf.put_slice(operand);
+        }
+        buf.into()
+    }
+}
+
+/// Fold the stored merge `operands` of `key`, from the latest to the earliest as they are read,
+/// onto the stored value `base` of the version below them, `None` if there is none or it is
+/// covered by a range tombstone. A tombstone or a value expired at `now` is no existing value, and
+/// a separated one is read from `value_logs`. Returns the user value.
+pub(crate) fn fold(
+    operator: &Arc<dyn MergeOperator>,
+    key: &[u8],
+    base: Option<&Bytes>,
+    operands: &[Bytes],
+    now: u64,
+    value_logs: &ValueLogSnapshot,
+) -> Result<Bytes> {
+    let existing = match base {
+        Some(base) if !base.is_empty() && !ttl::is_expired(base, now) => {
+            Some(value_logs.resolve(base)?)
+        }
+        _ => None,
+    };
+    let operands = operands
+        .iter()
+        .rev()
+        .map(|operand| ttl::decode_value(operand).0)
+        .collect::<Vec<_>>();
+    Ok(operator.full_merge(key, existing.as_deref(), &operands))
+}
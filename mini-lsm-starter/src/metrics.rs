@@ -0,0 +1,243 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Counters and latency histograms of a storage engine, updated by the reads, the writes and the
+//! background work, and read with `Metrics::snapshot`, see `MiniLsm::metrics`.
+
+use std::fmt::Write;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::block_cache::{BlockCache, CacheStats};
+
+/// Number of buckets of a `Histogram`: the i-th one counts the samples of at most 2^i
+/// microseconds, and the last one all the longer ones.
+const NUM_BUCKETS: usize = 26;
+
+/// A histogram of durations with exponential buckets, from 1us to about 16s.
+pub struct Histogram {
+    buckets: [AtomicU64; NUM_BUCKETS],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+/// A snapshot of a `Histogram`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HistogramSnapshot {
+    /// Number of samples in each bucket, see `HistogramSnapshot::bucket_bound_micros`.
+    pub buckets: Vec<u64>,
+    pub count: u64,
+    pub sum_micros: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+            count: AtomicU64::new(0),
+            sum_micros: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Histogram {
+    pub fn record(&self, duration: Duration) {
+        let micros = duration.as_micros().min(u64::MAX as u128) as u64;
+        // the first bucket whose bound 2^i is at least `micros`
+        let bucket = (u64::BITS - micros.saturating_sub(1).leading_zeros()) as usize;
+        self.buckets[bucket.min(NUM_BUCKETS - 1)].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(micros, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> HistogramSnapshot {
+        HistogramSnapshot {
+            buckets: self
+                .buckets
+                .iter()
+                .map(|bucket| bucket.load(Ordering::Relaxed))
+                .collect(),
+            count: self.count.load(Ordering::Relaxed),
+            sum_micros: self.sum_micros.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl HistogramSnapshot {
+    /// The upper bound of the `bucket`-th bucket in microseconds, `None` for the last one.
+    pub fn bucket_bound_micros(bucket: usize) -> Option<u64> {
+        (bucket < NUM_BUCKETS - 1).then(|| 1 << bucket)
+    }
+
+    pub fn mean_micros(&self) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        self.sum_micros as f64 / self.count as f64
+    }
+
+    /// The upper bound of the bucket holding the `p`-th percentile, 0 < `p` <= 100, or `None` if
+    /// there is no sample or it is in the last bucket.
+    pub fn percentile_micros(&self, p: f64) -> Option<u64> {
+        if self.count == 0 {
+            return None;
+        }
+        let rank = ((self.count as f64 * p / 100.0).ceil() as u64).clamp(1, self.count);
+        let mut seen = 0;
+        for (bucket, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Self::bucket_bound_micros(bucket);
+            }
+        }
+        None
+    }
+}
+
+/// The metrics of a storage engine. The latencies are those of the calls to the storage, and the
+/// bytes are those of the SST files written.
+pub struct Metrics {
+    /// Latency of `get`.
+    pub get_latency: Histogram,
+    /// Latency of every write: `put`, `delete`, `merge` and write batches.
+    pub put_latency: Histogram,
+    /// Latency of `scan` creating and positioning the iterator, not of iterating.
+    pub scan_latency: Histogram,
+    pub bytes_flushed: AtomicU64,
+    /// Bytes of the SSTs written by compactions.
+    pub bytes_compacted: AtomicU64,
+    /// Time the writes spent freezing the memtable once it is full, including waiting for a flush
+    /// or a compaction installing its output.
+    pub stall_micros: AtomicU64,
+    block_cache: Arc<BlockCache>,
+}
+
+/// A snapshot of `Metrics`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MetricsSnapshot {
+    pub get_latency: HistogramSnapshot,
+    pub put_latency: HistogramSnapshot,
+    pub scan_latency: HistogramSnapshot,
+    /// Lookups into the block cache of the storage, including the ones into removed SSTs.
+    pub block_cache: CacheStats,
+    pub bytes_flushed: u64,
+    pub bytes_compacted: u64,
+    pub stall_micros: u64,
+}
+
+impl Metrics {
+    pub fn new(block_cache: Arc<BlockCache>) -> Self {
+        Self {
+            get_latency: Histogram::default(),
+            put_latency: Histogram::default(),
+            scan_latency: Histogram::default(),
+            bytes_flushed: AtomicU64::new(0),
+            bytes_compacted: AtomicU64::new(0),
+            stall_micros: AtomicU64::new(0),
+            block_cache,
+        }
+    }
+
+    pub(crate) fn add_stall(&self, duration: Duration) {
+        self.stall_micros
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            get_latency: self.get_latency.snapshot(),
+            put_latency: self.put_latency.snapshot(),
+            scan_latency: self.scan_latency.snapshot(),
+            block_cache: self.block_cache.total_stats(),
+            bytes_flushed: self.bytes_flushed.load(Ordering::Relaxed),
+            bytes_compacted: self.bytes_compacted.load(Ordering::Relaxed),
+            stall_micros: self.stall_micros.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl MetricsSnapshot {
+    /// Hits over lookups of the block cache, 0 without lookups.
+    pub fn block_cache_hit_rate(&self) -> f64 {
+        let lookups = self.block_cache.hits + self.block_cache.misses;
+        if lookups == 0 {
+            return 0.0;
+        }
+        self.block_cache.hits as f64 / lookups as f64
+    }
+
+    /// Bytes written to SSTs by flushes and compactions for each byte flushed, 0 before the first
+    /// flush.
+    pub fn write_amplification(&self) -> f64 {
+        if self.bytes_flushed == 0 {
+            return 0.0;
+        }
+        (self.bytes_flushed + self.bytes_compacted) as f64 / self.bytes_flushed as f64
+    }
+
+    /// Render the metrics in the Prometheus text exposition format, with the names prefixed by
+    /// `mini_lsm_` and the latencies in seconds.
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        for (name, histogram) in [
+            ("get", &self.get_latency),
+            ("put", &self.put_latency),
+            ("scan", &self.scan_latency),
+        ] {
+            let name = format!("mini_lsm_{}_latency_seconds", name);
+            writeln!(out, "# TYPE {} histogram", name).unwrap();
+            let mut cumulative = 0;
+            for (bucket, count) in histogram.buckets.iter().enumerate() {
+                cumulative += count;
+                let le = match HistogramSnapshot::bucket_bound_micros(bucket) {
+                    Some(bound) => (bound as f64 / 1e6).to_string(),
+                    None => "+Inf".to_string(),
+                };
+                writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, le, cumulative).unwrap();
+            }
+            writeln!(out, "{}_sum {}", name, histogram.sum_micros as f64 / 1e6).unwrap();
+            writeln!(out, "{}_count {}", name, histogram.count).unwrap();
+        }
+        for (name, kind, value) in [
+            (
+                "block_cache_hits_total",
+                "counter",
+                self.block_cache.hits as f64,
+            ),
+            (
+                "block_cache_misses_total",
+                "counter",
+                self.block_cache.misses as f64,
+            ),
+            ("block_cache_hit_rate", "gauge", self.block_cache_hit_rate()),
+            ("flushed_bytes_total", "counter", self.bytes_flushed as f64),
+            (
+                "compacted_bytes_total",
+                "counter",
+                self.bytes_compacted as f64,
+            ),
+            ("write_amplification", "gauge", self.write_amplification()),
+            (
+                "stall_seconds_total",
+                "counter",
+                self.stall_micros as f64 / 1e6,
+            ),
+        ] {
+            writeln!(out, "# TYPE mini_lsm_{} {}", name, kind).unwrap();
+            writeln!(out, "mini_lsm_{} {}", name, value).unwrap();
+        }
+        out
+    }
+}
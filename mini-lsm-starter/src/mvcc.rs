@@ -12,22 +12,86 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-#![allow(unused_variables)] // TODO(you): remove this lint after implementing this mod
-#![allow(dead_code)] // TODO(you): remove this lint after implementing this mod
+#![allow(dead_code)] // the mini-lsm crate shares this module without snapshots or transactions
 
 pub mod txn;
 pub mod watermark;
 
 use std::{
     collections::{BTreeMap, HashSet},
-    sync::Arc,
+    ops::Bound,
+    sync::{Arc, atomic::AtomicBool},
 };
 
+use anyhow::Result;
+use bytes::Bytes;
+use crossbeam_skiplist::SkipMap;
 use parking_lot::Mutex;
 
 use self::{txn::Transaction, watermark::Watermark};
-use crate::lsm_storage::LsmStorageInner;
+use crate::{
+    lsm_iterator::{FusedIterator, LsmIterator},
+    lsm_storage::WriteBatchRecord,
+};
+
+/// The reads of a storage engine at a given timestamp, and its batch writes. The mvcc modules are
+/// shared with the `mini-lsm` crate, whose storage has no timestamped reads, so snapshots and
+/// transactions reach the storage through this trait instead of `LsmStorageInner`.
+pub(crate) trait MvccStorage: Send + Sync {
+    fn mvcc(&self) -> &LsmMvccInner;
+
+    /// Get the latest version of a key committed at or before `read_ts`.
+    fn get_with_ts(&self, key: &[u8], read_ts: u64) -> Result<Option<Bytes>>;
+
+    /// Create an iterator over a range of keys that sees the versions committed at or before
+    /// `read_ts`.
+    fn scan_with_ts(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        read_ts: u64,
+    ) -> Result<FusedIterator<LsmIterator>>;
+
+    /// Write a batch of data into the storage and return its commit timestamp.
+    fn write_batch_inner(&self, batch: &[WriteBatchRecord<Bytes>]) -> Result<u64>;
+}
+
+/// A read-only view of the storage as of `read_ts`: versions committed after the snapshot was
+/// taken are not visible. While the snapshot is alive, the watermark stays at or below `read_ts`,
+/// so compaction keeps the versions it can see.
+pub struct Snapshot {
+    pub(crate) read_ts: u64,
+    pub(crate) inner: Arc<dyn MvccStorage>,
+}
+
+impl Snapshot {
+    pub fn read_ts(&self) -> u64 {
+        self.read_ts
+    }
 
+    pub fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
+        self.inner.get_with_ts(key, self.read_ts)
+    }
+
+    /// Scan the snapshot. The iterator does not keep the snapshot alive, so the snapshot should
+    /// outlive it.
+    pub fn scan(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<FusedIterator<LsmIterator>> {
+        self.inner.scan_with_ts(lower, upper, self.read_ts)
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        self.inner.mvcc().ts.lock().1.remove_reader(self.read_ts);
+    }
+}
+
+/// The write set of a committed transaction, which transactions that overlap with it are validated
+/// against.
 pub(crate) struct CommittedTxnData {
     pub(crate) key_hashes: HashSet<u32>,
     #[allow(dead_code)]
@@ -67,7 +131,30 @@ impl LsmMvccInner {
         ts.1.watermark().unwrap_or(ts.0)
     }
 
-    pub fn new_txn(&self, inner: Arc<LsmStorageInner>, serializable: bool) -> Arc<Transaction> {
-        unimplemented!()
+    /// Take a snapshot at the latest commit timestamp. The snapshot holds back the watermark until
+    /// it is dropped.
+    pub(crate) fn new_snapshot(&self, inner: Arc<dyn MvccStorage>) -> Snapshot {
+        let mut ts = self.ts.lock();
+        let read_ts = ts.0;
+        ts.1.add_reader(read_ts);
+        Snapshot { read_ts, inner }
+    }
+
+    pub(crate) fn new_txn(
+        &self,
+        inner: Arc<dyn MvccStorage>,
+        serializable: bool,
+    ) -> Arc<Transaction> {
+        let mut ts = self.ts.lock();
+        let read_ts = ts.0;
+        ts.1.add_reader(read_ts);
+        Arc::new(Transaction {
+            read_ts,
+            inner,
+            local_storage: Arc::new(SkipMap::new()),
+            committed: Arc::new(AtomicBool::new(false)),
+            key_hashes: Mutex::new((HashSet::new(), HashSet::new())),
+            serializable,
+        })
     }
 }
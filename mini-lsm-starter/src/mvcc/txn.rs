@@ -12,16 +12,16 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-#![allow(unused_variables)] // TODO(you): remove this lint after implementing this mod
-#![allow(dead_code)] // TODO(you): remove this lint after implementing this mod
-
 use std::{
     collections::HashSet,
     ops::Bound,
-    sync::{Arc, atomic::AtomicBool},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
 };
 
-use anyhow::Result;
+use anyhow::{Result, bail};
 use bytes::Bytes;
 use crossbeam_skiplist::SkipMap;
 use ouroboros::self_referencing;
@@ -30,42 +30,166 @@ use parking_lot::Mutex;
 use crate::{
     iterators::{StorageIterator, two_merge_iterator::TwoMergeIterator},
     lsm_iterator::{FusedIterator, LsmIterator},
-    lsm_storage::LsmStorageInner,
+    lsm_storage::WriteBatchRecord,
+    mvcc::{CommittedTxnData, MvccStorage},
 };
 
+/// An optimistic transaction. It reads the storage as of `read_ts` and buffers its writes locally
+/// until commit, when they are validated against the transactions committed in the meantime and
+/// applied as one batch at a new commit timestamp.
 pub struct Transaction {
     pub(crate) read_ts: u64,
-    pub(crate) inner: Arc<LsmStorageInner>,
+    pub(crate) inner: Arc<dyn MvccStorage>,
     pub(crate) local_storage: Arc<SkipMap<Bytes, Bytes>>,
     pub(crate) committed: Arc<AtomicBool>,
     /// Write set and read set
-    pub(crate) key_hashes: Option<Mutex<(HashSet<u32>, HashSet<u32>)>>,
+    pub(crate) key_hashes: Mutex<(HashSet<u32>, HashSet<u32>)>,
+    /// Track the read set and validate it on commit, in addition to the write set
+    pub(crate) serializable: bool,
 }
 
 impl Transaction {
+    pub fn read_ts(&self) -> u64 {
+        self.read_ts
+    }
+
+    fn check_active(&self) -> Result<()> {
+        if self.committed.load(Ordering::SeqCst) {
+            bail!("transaction already committed");
+        }
+        Ok(())
+    }
+
+    fn add_read(&self, key: &[u8]) {
+        if self.serializable {
+            self.key_hashes
+                .lock()
+                .1
+                .insert(farmhash::fingerprint32(key));
+        }
+    }
+
+    /// Get a key, seeing the writes of this transaction on top of the storage as of `read_ts`.
     pub fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
-        unimplemented!()
+        self.check_active()?;
+        self.add_read(key);
+        if let Some(entry) = self.local_storage.get(key) {
+            let value = entry.value();
+            return Ok((!value.is_empty()).then(|| value.clone()));
+        }
+        self.inner.get_with_ts(key, self.read_ts)
     }
 
     pub fn scan(self: &Arc<Self>, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<TxnIterator> {
-        unimplemented!()
+        self.check_active()?;
+        let mut local_iter = TxnLocalIteratorBuilder {
+            map: self.local_storage.clone(),
+            iter_builder: |map| {
+                map.range((
+                    lower.map(Bytes::copy_from_slice),
+                    upper.map(Bytes::copy_from_slice),
+                ))
+            },
+            item: (Bytes::new(), Bytes::new()),
+        }
+        .build();
+        local_iter.next()?;
+        let storage_iter = self.inner.scan_with_ts(lower, upper, self.read_ts)?;
+        TxnIterator::create(
+            self.clone(),
+            TwoMergeIterator::create(local_iter, storage_iter)?,
+        )
     }
 
     pub fn put(&self, key: &[u8], value: &[u8]) {
-        unimplemented!()
+        assert!(
+            !self.committed.load(Ordering::SeqCst),
+            "transaction already committed"
+        );
+        self.local_storage
+            .insert(Bytes::copy_from_slice(key), Bytes::copy_from_slice(value));
+        self.key_hashes
+            .lock()
+            .0
+            .insert(farmhash::fingerprint32(key));
     }
 
+    /// Remove a key by writing an empty value.
     pub fn delete(&self, key: &[u8]) {
-        unimplemented!()
+        self.put(key, b"");
     }
 
+    /// Commit the transaction. It fails if a transaction committed after `read_ts` wrote a key this
+    /// transaction writes, or, in serializable mode, a key it read. Only writes made by other
+    /// transactions are tracked, so writes made directly to the storage never cause a conflict.
     pub fn commit(&self) -> Result<()> {
-        unimplemented!()
+        if self
+            .committed
+            .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+            .is_err()
+        {
+            bail!("transaction already committed");
+        }
+        let mvcc = self.inner.mvcc();
+        let _commit_lock = mvcc.commit_lock.lock();
+        let key_hashes = self.key_hashes.lock();
+        let (write_set, read_set) = &*key_hashes;
+        if write_set.is_empty() {
+            // a read-only transaction always sees a consistent snapshot
+            return Ok(());
+        }
+
+        let mut committed_txns = mvcc.committed_txns.lock();
+        for (commit_ts, txn) in committed_txns.range(self.read_ts + 1..) {
+            if !txn.key_hashes.is_disjoint(write_set)
+                || (self.serializable && !txn.key_hashes.is_disjoint(read_set))
+            {
+                bail!(
+                    "transaction at read_ts {} conflicts with the transaction committed at {}",
+                    self.read_ts,
+                    commit_ts
+                );
+            }
+        }
+
+        let batch = self
+            .local_storage
+            .iter()
+            .map(|entry| {
+                if entry.value().is_empty() {
+                    WriteBatchRecord::Del(entry.key().clone())
+                } else {
+                    WriteBatchRecord::Put(entry.key().clone(), entry.value().clone())
+                }
+            })
+            .collect::<Vec<_>>();
+        let commit_ts = self.inner.write_batch_inner(&batch)?;
+        committed_txns.insert(
+            commit_ts,
+            CommittedTxnData {
+                key_hashes: write_set.clone(),
+                read_ts: self.read_ts,
+                commit_ts,
+            },
+        );
+
+        // live transactions read at or after the watermark, and are only validated against the
+        // transactions committed after they started
+        let watermark = mvcc.watermark();
+        while let Some(entry) = committed_txns.first_entry() {
+            if *entry.key() > watermark {
+                break;
+            }
+            entry.remove();
+        }
+        Ok(())
     }
 }
 
 impl Drop for Transaction {
-    fn drop(&mut self) {}
+    fn drop(&mut self) {
+        self.inner.mvcc().ts.lock().1.remove_reader(self.read_ts);
+    }
 }
 
 type SkipMapRangeIter<'a> =
@@ -87,19 +211,26 @@ impl StorageIterator for TxnLocalIterator {
     type KeyType<'a> = &'a [u8];
 
     fn value(&self) -> &[u8] {
-        unimplemented!()
+        &self.borrow_item().1[..]
     }
 
     fn key(&self) -> &[u8] {
-        unimplemented!()
+        &self.borrow_item().0[..]
     }
 
     fn is_valid(&self) -> bool {
-        unimplemented!()
+        !self.borrow_item().0.is_empty()
     }
 
     fn next(&mut self) -> Result<()> {
-        unimplemented!()
+        self.with_mut(|field| {
+            *field.item = field
+                .iter
+                .next()
+                .map(|entry| (entry.key().clone(), entry.value().clone()))
+                .unwrap_or_default();
+        });
+        Ok(())
     }
 }
 
@@ -113,7 +244,20 @@ impl TxnIterator {
         txn: Arc<Transaction>,
         iter: TwoMergeIterator<TxnLocalIterator, FusedIterator<LsmIterator>>,
     ) -> Result<Self> {
-        unimplemented!()
+        let mut iter = Self { _txn: txn, iter };
+        iter.move_to_valid()?;
+        Ok(iter)
+    }
+
+    /// Skip the keys deleted by the transaction, and add the key it stops at to the read set.
+    fn move_to_valid(&mut self) -> Result<()> {
+        while self.iter.is_valid() && self.iter.value().is_empty() {
+            self.iter.next()?;
+        }
+        if self.iter.is_valid() {
+            self._txn.add_read(self.iter.key());
+        }
+        Ok(())
     }
 }
 
@@ -136,7 +280,8 @@ impl StorageIterator for TxnIterator {
     }
 
     fn next(&mut self) -> Result<()> {
-        unimplemented!()
+        self.iter.next()?;
+        self.move_to_valid()
     }
 
     fn num_active_iterators(&self) -> usize {
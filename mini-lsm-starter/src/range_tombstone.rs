@@ -0,0 +1,156 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use anyhow::{Result, bail};
+use bytes::{Buf, BufMut, Bytes};
+
+use crate::error::Error;
+
+/// Deletes every version of the user keys in `start..end` with a timestamp < `ts`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RangeTombstone {
+    pub start: Bytes,
+    pub end: Bytes,
+    pub ts: u64,
+}
+
+impl RangeTombstone {
+    pub fn new(start: &[u8], end: &[u8], ts: u64) -> Self {
+        Self {
+            start: Bytes::copy_from_slice(start),
+            end: Bytes::copy_from_slice(end),
+            ts,
+        }
+    }
+
+    /// Whether the user key is within the range, regardless of the timestamps.
+    pub fn contains(&self, key: &[u8]) -> bool {
+        &self.start[..] <= key && key < &self.end[..]
+    }
+
+    /// Whether the version of `key` at `ts` is deleted by this tombstone.
+    pub fn covers(&self, key: &[u8], ts: u64) -> bool {
+        ts < self.ts && self.contains(key)
+    }
+
+    /// Whether the range overlaps the user keys in `first..=last`.
+    pub fn overlaps(&self, first: &[u8], last: &[u8]) -> bool {
+        &self.start[..] <= last && first < &self.end[..]
+    }
+
+    /// Size of the tombstone when encoded.
+    pub fn raw_len(&self) -> usize {
+        2 + self.start.len() + 2 + self.end.len() + 8
+    }
+
+    /// Encode the tombstone as `start_len (u16) | start | end_len (u16) | end | ts (u64)`.
+    pub fn encode(&self, buf: &mut impl BufMut) {
+        buf.put_u16(self.start.len() as u16);
+        buf.put_slice(&self.start);
+        buf.put_u16(self.end.len() as u16);
+        buf.put_slice(&self.end);
+        buf.put_u64(self.ts);
+    }
+
+    pub fn decode(buf: &mut &[u8]) -> Result<Self> {
+        let start = Self::decode_slice(buf)?;
+        let end = Self::decode_slice(buf)?;
+        let ts = buf.try_get_u64()?;
+        Ok(Self { start, end, ts })
+    }
+
+    fn decode_slice(buf: &mut &[u8]) -> Result<Bytes> {
+        let len = buf.try_get_u16()? as usize;
+        if buf.remaining() < len {
+            bail!(Error::corruption("range tombstone truncated"));
+        }
+        Ok(buf.copy_to_bytes(len))
+    }
+
+    /// Encode a block of tombstones, framed by their number at the front and a checksum at the
+    /// end.
+    pub fn encode_block(tombstones: &[RangeTombstone], buf: &mut Vec<u8>) {
+        let original_len = buf.len();
+        buf.put_u32(tombstones.len() as u32);
+        for tombstone in tombstones {
+            tombstone.encode(buf);
+        }
+        buf.put_u32(crc32fast::hash(&buf[original_len..]));
+    }
+
+    pub fn decode_block(data: &[u8]) -> Result<Vec<RangeTombstone>> {
+        if data.len() < 8 {
+            bail!(Error::corruption(format!(
+                "range tombstone block too short: {} bytes",
+                data.len()
+            )));
+        }
+        let (mut buf, mut checksum) = data.split_at(data.len() - 4);
+        if checksum.get_u32() != crc32fast::hash(buf) {
+            bail!(Error::corruption(
+                "range tombstone block checksum mismatched"
+            ));
+        }
+        let num = buf.get_u32() as usize;
+        let mut tombstones = Vec::with_capacity(num);
+        for _ in 0..num {
+            tombstones.push(Self::decode(&mut buf)?);
+        }
+        if buf.has_remaining() {
+            bail!(Error::corruption(format!(
+                "{} trailing bytes after range tombstones",
+                buf.remaining()
+            )));
+        }
+        Ok(tombstones)
+    }
+}
+
+/// The range tombstones visible to a reader at `read_ts`. Range deletes are expected to be rare,
+/// so a lookup simply checks every tombstone.
+#[derive(Clone, Debug, Default)]
+pub struct RangeTombstones {
+    tombstones: Vec<RangeTombstone>,
+}
+
+impl RangeTombstones {
+    pub fn new(tombstones: impl IntoIterator<Item = RangeTombstone>, read_ts: u64) -> Self {
+        Self {
+            tombstones: tombstones
+                .into_iter()
+                .filter(|tombstone| tombstone.ts <= read_ts)
+                .collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tombstones.is_empty()
+    }
+
+    /// Whether the version of `key` at `ts` is deleted by any of the tombstones.
+    pub fn covers(&self, key: &[u8], ts: u64) -> bool {
+        self.tombstones
+            .iter()
+            .any(|tombstone| tombstone.covers(key, ts))
+    }
+}
+
+impl IntoIterator for RangeTombstones {
+    type Item = RangeTombstone;
+    type IntoIter = std::vec::IntoIter<RangeTombstone>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.tombstones.into_iter()
+    }
+}
@@ -0,0 +1,122 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::time::{Duration, Instant};
+
+use parking_lot::{Condvar, Mutex};
+
+/// Who is writing to the disk. Writers of a higher priority are served first when both wait for
+/// the rate limiter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoPriority {
+    /// Flushes, which the writes may be stalled on.
+    High,
+    /// Compactions.
+    Low,
+}
+
+/// A token bucket that limits the bytes written by background work: it refills at `bytes_per_sec`
+/// up to `burst_bytes`, and a write takes as many tokens as its bytes, waiting until there are
+/// enough. While a high priority write is waiting, low priority writes wait as well.
+pub struct RateLimiter {
+    bytes_per_sec: u64,
+    burst_bytes: u64,
+    state: Mutex<RateLimiterState>,
+    /// Notifies the low priority writers when no high priority writer is waiting.
+    high_done: Condvar,
+}
+
+struct RateLimiterState {
+    available: f64,
+    last_refill: Instant,
+    high_waiting: usize,
+    /// Total bytes granted to each priority, see `IoPriority`.
+    total_bytes: [u64; 2],
+}
+
+impl RateLimiter {
+    /// Create a rate limiter that allows `bytes_per_sec` with bursts of up to `burst_bytes`, both
+    /// at least 1. The bucket starts full.
+    pub fn new(bytes_per_sec: u64, burst_bytes: u64) -> Self {
+        let burst_bytes = burst_bytes.max(1);
+        Self {
+            bytes_per_sec: bytes_per_sec.max(1),
+            burst_bytes,
+            state: Mutex::new(RateLimiterState {
+                available: burst_bytes as f64,
+                last_refill: Instant::now(),
+                high_waiting: 0,
+                total_bytes: [0; 2],
+            }),
+            high_done: Condvar::new(),
+        }
+    }
+
+    pub fn bytes_per_sec(&self) -> u64 {
+        self.bytes_per_sec
+    }
+
+    pub fn burst_bytes(&self) -> u64 {
+        self.burst_bytes
+    }
+
+    /// Total bytes granted to the writers of `priority`.
+    pub fn total_bytes(&self, priority: IoPriority) -> u64 {
+        self.state.lock().total_bytes[priority as usize]
+    }
+
+    /// Wait until `bytes` may be written. Requests larger than `burst_bytes` are granted in
+    /// pieces of at most `burst_bytes`.
+    pub fn request(&self, bytes: u64, priority: IoPriority) {
+        let mut remaining = bytes;
+        while remaining > 0 {
+            let piece = remaining.min(self.burst_bytes);
+            self.request_piece(piece, priority);
+            remaining -= piece;
+        }
+    }
+
+    fn request_piece(&self, bytes: u64, priority: IoPriority) {
+        let mut state = self.state.lock();
+        if priority == IoPriority::High {
+            state.high_waiting += 1;
+        }
+        loop {
+            let now = Instant::now();
+            let refill =
+                now.duration_since(state.last_refill).as_secs_f64() * self.bytes_per_sec as f64;
+            state.available = (state.available + refill).min(self.burst_bytes as f64);
+            state.last_refill = now;
+            if priority == IoPriority::Low && state.high_waiting > 0 {
+                self.high_done.wait(&mut state);
+                continue;
+            }
+            if state.available >= bytes as f64 {
+                state.available -= bytes as f64;
+                break;
+            }
+            let deficit = bytes as f64 - state.available;
+            let wait = Duration::from_secs_f64(deficit / self.bytes_per_sec as f64);
+            // the lock is released while sleeping so that others can check the bucket
+            parking_lot::MutexGuard::unlocked(&mut state, || std::thread::sleep(wait));
+        }
+        state.total_bytes[priority as usize] += bytes;
+        if priority == IoPriority::High {
+            state.high_waiting -= 1;
+            if state.high_waiting == 0 {
+                self.high_done.notify_all();
+            }
+        }
+    }
+}
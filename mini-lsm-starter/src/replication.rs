@@ -0,0 +1,425 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A log of the committed writes of a storage, for followers to replicate it: each write, i.e., a
+//! batch, a single put or delete or a range delete, is appended with its commit timestamp as its
+//! sequence number, and `subscribe` streams the writes from a sequence number on. Unlike the
+//! WALs, which are removed once their memtables are flushed, the log is kept until `trim`, so a
+//! follower that remembers the last sequence number it applied can resume after a disconnect or a
+//! restart. The values are logged as written, e.g., without the value log pointers, so a follower
+//! does not need any other file of the storage.
+//!
+//! The log is a directory of segments, each named after the first sequence number it may hold,
+//! `| body_len (u32) | body | checksum (u32) |` records where the body is:
+//!
+//! `| sequence (u64) | (op (u8) | key_len (u16) | key | value_len (u32) | value | expire_at (u64)?)* |`
+//!
+//! and `expire_at` follows the value of a put with a TTL only.
+
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result, bail};
+use bytes::{Buf, BufMut, Bytes};
+use parking_lot::{Condvar, Mutex};
+
+use crate::error::Error;
+
+/// A segment is sealed and a new one started once it reaches this size.
+pub const SEGMENT_SIZE: u64 = 4 << 20;
+
+const OP_PUT: u8 = 0;
+const OP_PUT_EXPIRING: u8 = 1;
+const OP_DELETE: u8 = 2;
+const OP_MERGE: u8 = 3;
+const OP_DELETE_RANGE: u8 = 4;
+
+/// A write to a key in a `ReplicationBatch`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplicationOp {
+    /// A value, expiring at `expire_at` in milliseconds since the Unix epoch if set.
+    Put {
+        value: Bytes,
+        expire_at: Option<u64>,
+    },
+    Delete,
+    /// A merge operand, see `LsmStorageInner::merge`.
+    Merge(Bytes),
+    /// Delete the keys from the key of the record, inclusive, to `end`, exclusive.
+    DeleteRange {
+        end: Bytes,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplicationRecord {
+    pub key: Bytes,
+    pub op: ReplicationOp,
+}
+
+/// The records of a write, committed at `sequence`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplicationBatch {
+    pub sequence: u64,
+    pub records: Vec<ReplicationRecord>,
+}
+
+impl ReplicationBatch {
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = vec![0; 4];
+        buf.put_u64(self.sequence);
+        for record in &self.records {
+            let (op, value, expire_at): (u8, &[u8], _) = match &record.op {
+                ReplicationOp::Put {
+                    value,
+                    expire_at: None,
+                } => (OP_PUT, value, None),
+                ReplicationOp::Put {
+                    value,
+                    expire_at: Some(expire_at),
+                } => (OP_PUT_EXPIRING, value, Some(*expire_at)),
+                ReplicationOp::Delete => (OP_DELETE, &[], None),
+                ReplicationOp::Merge(operand) => (OP_MERGE, operand, None),
+                ReplicationOp::DeleteRange { end } => (OP_DELETE_RANGE, end, None),
+            };
+            buf.put_u8(op);
+            buf.put_u16(record.key.len() as u16);
+            buf.put_slice(&record.key);
+            buf.put_u32(value.len() as u32);
+            buf.put_slice(value);
+            if let Some(expire_at) = expire_at {
+                buf.put_u64(expire_at);
+            }
+        }
+        let body_len = buf.len() - 4;
+        (&mut buf[..4]).put_u32(body_len as u32);
+        buf.put_u32(crc32fast::hash(&buf[4..]));
+        buf
+    }
+
+    fn decode(mut body: &[u8]) -> Result<Self> {
+        let sequence = body.try_get_u64()?;
+        let mut records = Vec::new();
+        while body.has_remaining() {
+            let op = body.try_get_u8()?;
+            let key_len = body.try_get_u16()? as usize;
+            if body.remaining() < key_len {
+                bail!(Error::corruption("replication record truncated"));
+            }
+            let key = Bytes::copy_from_slice(&body[..key_len]);
+            body.advance(key_len);
+            let value_len = body.try_get_u32()? as usize;
+            if body.remaining() < value_len {
+                bail!(Error::corruption("replication record truncated"));
+            }
+            let value = Bytes::copy_from_slice(&body[..value_len]);
+            body.advance(value_len);
+            let op = match op {
+                OP_PUT => ReplicationOp::Put {
+                    value,
+                    expire_at: None,
+                },
+                OP_PUT_EXPIRING => ReplicationOp::Put {
+                    value,
+                    expire_at: Some(body.try_get_u64()?),
+                },
+                OP_DELETE => ReplicationOp::Delete,
+                OP_MERGE => ReplicationOp::Merge(value),
+                OP_DELETE_RANGE => ReplicationOp::DeleteRange { end: value },
+                op => bail!(Error::corruption(format!("unknown replication op {}", op))),
+            };
+            records.push(ReplicationRecord { key, op });
+        }
+        Ok(Self { sequence, records })
+    }
+}
+
+struct LogState {
+    /// The first sequence numbers of the segments, in order. The last one is being appended.
+    segments: Vec<u64>,
+    active: File,
+    /// Bytes of the complete records in the active segment.
+    active_len: u64,
+    /// The sequence number of the last record, 0 if there is none.
+    last_sequence: u64,
+}
+
+/// The replication log of a storage, see the module documentation.
+pub struct ReplicationLog {
+    dir: PathBuf,
+    state: Mutex<LogState>,
+    /// Notifies the subscriptions waiting for a record.
+    appended: Condvar,
+}
+
+fn path_of_segment(dir: &Path, first_sequence: u64) -> PathBuf {
+    dir.join(format!("{:020}.rlog", first_sequence))
+}
+
+impl ReplicationLog {
+    /// Open the log in `dir`, or create it with a segment starting at `next_sequence`. A record cut
+    /// short at the end of the last segment, i.e., an append interrupted by a crash, is dropped.
+    pub fn open(dir: impl AsRef<Path>, next_sequence: u64) -> Result<Arc<Self>> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir)?;
+        let mut segments = Vec::new();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "rlog") {
+                let first_sequence = path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .and_then(|stem| stem.parse::<u64>().ok())
+                    .with_context(|| format!("invalid replication segment {}", path.display()))?;
+                segments.push(first_sequence);
+            }
+        }
+        segments.sort_unstable();
+        let (active, active_len, last_sequence) = match segments.last() {
+            Some(&first_sequence) => {
+                let path = path_of_segment(dir, first_sequence);
+                let mut file = OpenOptions::new().read(true).append(true).open(&path)?;
+                let mut buf = Vec::new();
+                file.read_to_end(&mut buf)?;
+                let mut valid_len = 0;
+                let mut last_sequence = first_sequence.saturating_sub(1);
+                while let Some((batch, len)) = read_record(&buf[valid_len..])? {
+                    last_sequence = batch.sequence;
+                    valid_len += len;
+                }
+                file.set_len(valid_len as u64)?;
+                (file, valid_len as u64, last_sequence)
+            }
+            None => {
+                segments.push(next_sequence);
+                let file = File::create_new(path_of_segment(dir, next_sequence))?;
+                File::open(dir)?.sync_all()?;
+                (file, 0, next_sequence.saturating_sub(1))
+            }
+        };
+        Ok(Arc::new(Self {
+            dir: dir.to_path_buf(),
+            state: Mutex::new(LogState {
+                segments,
+                active,
+                active_len,
+                last_sequence,
+            }),
+            appended: Condvar::new(),
+        }))
+    }
+
+    /// Append a batch, whose sequence number must be above the ones appended before. Called in
+    /// the order of the commits.
+    pub fn append(&self, batch: &ReplicationBatch) -> Result<()> {
+        let record = batch.encode();
+        let mut state = self.state.lock();
+        if batch.sequence <= state.last_sequence {
+            bail!(
+                "replication sequence {} is not after {}",
+                batch.sequence,
+                state.last_sequence
+            );
+        }
+        if state.active_len >= SEGMENT_SIZE {
+            state.active.sync_all()?;
+            state.active = File::create_new(path_of_segment(&self.dir, batch.sequence))?;
+            state.active_len = 0;
+            state.segments.push(batch.sequence);
+            File::open(&self.dir)?.sync_all()?;
+        }
+        state.active.write_all(&record)?;
+        state.active_len += record.len() as u64;
+        state.last_sequence = batch.sequence;
+        self.appended.notify_all();
+        Ok(())
+    }
+
+    /// Sync the records appended so far to the disk.
+    pub fn sync(&self) -> Result<()> {
+        self.state.lock().active.sync_data()?;
+        Ok(())
+    }
+
+    /// The sequence number of the last record, or the one before the first sequence number of
+    /// the log if it is empty.
+    pub fn last_sequence(&self) -> u64 {
+        self.state.lock().last_sequence
+    }
+
+    /// The first sequence number that `subscribe` can start from.
+    pub fn first_sequence(&self) -> u64 {
+        self.state.lock().segments[0]
+    }
+
+    /// Remove the segments holding sequence numbers below `sequence` only, e.g., once all
+    /// followers have applied them. The active segment is kept. Returns the number of segments
+    /// removed.
+    pub fn trim(&self, sequence: u64) -> Result<usize> {
+        let removed = {
+            let mut state = self.state.lock();
+            // a segment only holds sequence numbers below the first one of the next segment
+            let num_removed = state
+                .segments
+                .windows(2)
+                .take_while(|pair| pair[1] <= sequence)
+                .count();
+            state.segments.drain(..num_removed).collect::<Vec<_>>()
+        };
+        for first_sequence in &removed {
+            std::fs::remove_file(path_of_segment(&self.dir, *first_sequence))?;
+        }
+        Ok(removed.len())
+    }
+
+    /// Stream the batches with a sequence number of at least `from_sequence`, which may not be
+    /// below `first_sequence`.
+    pub fn subscribe(self: &Arc<Self>, from_sequence: u64) -> Result<Subscription> {
+        let state = self.state.lock();
+        let Some(idx) = state
+            .segments
+            .iter()
+            .rposition(|&first_sequence| first_sequence <= from_sequence)
+        else {
+            bail!(
+                "replication log starts at sequence {}, after {}",
+                state.segments[0],
+                from_sequence
+            );
+        };
+        Ok(Subscription {
+            log: self.clone(),
+            segment: state.segments[idx],
+            file: None,
+            offset: 0,
+            from_sequence,
+        })
+    }
+}
+
+/// Read the record at the start of `buf` and its length, or `None` if `buf` ends before the record.
+fn read_record(buf: &[u8]) -> Result<Option<(ReplicationBatch, usize)>> {
+    if buf.len() < 4 {
+        return Ok(None);
+    }
+    let body_len = (&buf[..4]).get_u32() as usize;
+    if buf.len() < 4 + body_len + 4 {
+        return Ok(None);
+    }
+    let body = &buf[4..4 + body_len];
+    if (&buf[4 + body_len..]).get_u32() != crc32fast::hash(body) {
+        bail!(Error::corruption("replication record checksum mismatched"));
+    }
+    Ok(Some((ReplicationBatch::decode(body)?, 4 + body_len + 4)))
+}
+
+/// A stream of the batches of a `ReplicationLog` from a sequence number on, see
+/// `ReplicationLog::subscribe`. It reads the segments on its own, so a slow follower does not hold
+/// up the writes.
+pub struct Subscription {
+    log: Arc<ReplicationLog>,
+    /// The first sequence number of the segment being read.
+    segment: u64,
+    file: Option<File>,
+    /// Offset of the next record in the segment.
+    offset: u64,
+    from_sequence: u64,
+}
+
+impl Subscription {
+    /// The next batch, or `None` if all batches appended so far are streamed.
+    pub fn try_next(&mut self) -> Result<Option<ReplicationBatch>> {
+        loop {
+            let (readable, next_segment) = {
+                let state = self.log.state.lock();
+                let Some(idx) = state
+                    .segments
+                    .iter()
+                    .position(|&first_sequence| first_sequence == self.segment)
+                else {
+                    bail!(
+                        "replication segment {} is trimmed before it is streamed",
+                        self.segment
+                    );
+                };
+                match state.segments.get(idx + 1) {
+                    Some(&next) => (None, Some(next)),
+                    None => (Some(state.active_len), None),
+                }
+            };
+            if self.file.is_none() {
+                self.file = Some(File::open(path_of_segment(&self.log.dir, self.segment))?);
+            }
+            let file = self.file.as_ref().unwrap();
+            // a sealed segment is complete, the active one up to the last complete record
+            let len = match readable {
+                Some(len) => len,
+                None => file.metadata()?.len(),
+            };
+            if self.offset + 4 > len {
+                let Some(next_segment) = next_segment else {
+                    return Ok(None);
+                };
+                self.segment = next_segment;
+                self.file = None;
+                self.offset = 0;
+                continue;
+            }
+            let mut header = [0; 4];
+            file.read_exact_at(&mut header, self.offset)?;
+            let record_len = 4 + (&header[..]).get_u32() as u64 + 4;
+            if self.offset + record_len > len {
+                bail!(Error::corruption(format!(
+                    "replication segment {} truncated",
+                    self.segment
+                )));
+            }
+            let mut buf = vec![0; record_len as usize];
+            file.read_exact_at(&mut buf, self.offset)?;
+            let (batch, _) = read_record(&buf)?.unwrap();
+            self.offset += record_len;
+            if batch.sequence >= self.from_sequence {
+                return Ok(Some(batch));
+            }
+        }
+    }
+
+    /// The next batch, waiting up to `timeout` for one to be appended.
+    pub fn next_timeout(&mut self, timeout: Duration) -> Result<Option<ReplicationBatch>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(batch) = self.try_next()? {
+                return Ok(Some(batch));
+            }
+            let mut state = self.log.state.lock();
+            // `try_next` stopped at the end of the active segment, unless a batch was appended since
+            let caught_up =
+                state.segments.last() == Some(&self.segment) && state.active_len <= self.offset;
+            if caught_up
+                && self
+                    .log
+                    .appended
+                    .wait_until(&mut state, deadline)
+                    .timed_out()
+            {
+                drop(state);
+                return self.try_next();
+            }
+        }
+    }
+}
@@ -0,0 +1,428 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Secondary indexes over the records of a storage. A user registers an `IndexExtractor` under a
+//! name, which maps each record to the keys it is indexed by, and the storage maintains an entry
+//! per index key and record in the column family `index:<name>`. The entries of a write are logged
+//! in the same WAL record as the write itself, so they are recovered together. `scan_index` looks
+//! up the records by a range of index keys.
+//!
+//! An entry is keyed by the index key, with each zero byte escaped as `0 0xff` and terminated by
+//! `0 0`, followed by the primary key, so that the entries sort by the index keys first, and its
+//! value is the primary key.
+//!
+//! The extractors are not persisted, so they are to be registered again right after reopening the
+//! storage, before any write. The records deleted by a range delete or expired keep their entries,
+//! which `scan_index` skips, and merge operands cannot be written to a storage with indexes.
+
+use std::collections::HashMap;
+use std::ops::Bound;
+use std::sync::Arc;
+
+use anyhow::{Context, Result, bail};
+use bytes::Bytes;
+
+use crate::column_family::ColumnFamily;
+use crate::error::Error;
+use crate::iterators::StorageIterator;
+use crate::key::KeySlice;
+use crate::lsm_iterator::{FusedIterator, LsmIterator};
+use crate::lsm_storage::{LsmStorageInner, WriteBatchRecord, batch_data};
+use crate::mem_table::MemTable;
+
+/// The prefix of the names of the column families holding the index entries.
+pub const INDEX_COLUMN_FAMILY_PREFIX: &str = "index:";
+
+/// Number of entries written at once when filling a new index.
+const BACKFILL_BATCH_SIZE: usize = 1024;
+
+/// A user callback that maps a record to the keys it is indexed by. It must be deterministic, as
+/// the index keys of the previous value of a record are computed again to remove its entries.
+pub trait IndexExtractor: std::fmt::Debug + Send + Sync {
+    /// The index keys of the record of `key` with `value`, empty if it is not indexed.
+    fn extract(&self, key: &[u8], value: &[u8]) -> Vec<Bytes>;
+}
+
+/// The index entries of a write, by the column families of their indexes.
+pub(crate) type IndexUpdates = Vec<(Arc<ColumnFamily>, Vec<WriteBatchRecord<Vec<u8>>>)>;
+
+/// A registered index.
+#[derive(Clone)]
+pub(crate) struct Index {
+    pub(crate) column_family: Arc<ColumnFamily>,
+    pub(crate) extractor: Arc<dyn IndexExtractor>,
+}
+
+/// The name of the column family of the index `name`.
+pub fn index_column_family(name: &str) -> String {
+    format!("{}{}", INDEX_COLUMN_FAMILY_PREFIX, name)
+}
+
+fn encode_index_key(index_key: &[u8], buf: &mut Vec<u8>) {
+    for &byte in index_key {
+        buf.push(byte);
+        if byte == 0 {
+            buf.push(0xff);
+        }
+    }
+}
+
+/// The key of the entry of the record of `primary_key` with the index key `index_key`.
+fn encode_entry(index_key: &[u8], primary_key: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(index_key.len() + 2 + primary_key.len());
+    encode_index_key(index_key, &mut buf);
+    buf.extend_from_slice(&[0, 0]);
+    buf.extend_from_slice(primary_key);
+    buf
+}
+
+/// The index key and the primary key of the key of an entry.
+fn decode_entry(entry: &[u8]) -> Result<(Vec<u8>, &[u8])> {
+    let mut index_key = Vec::new();
+    let mut idx = 0;
+    while idx < entry.len() {
+        if entry[idx] != 0 {
+            index_key.push(entry[idx]);
+            idx += 1;
+            continue;
+        }
+        match entry.get(idx + 1) {
+            Some(0xff) => index_key.push(0),
+            Some(0) => return Ok((index_key, &entry[idx + 2..])),
+            _ => break,
+        }
+        idx += 2;
+    }
+    bail!(Error::corruption("malformed index entry"))
+}
+
+/// The bound of the entry keys for a bound of the index keys: the entries of an index key are
+/// between the index key followed by `0 0` and by `0 1`.
+fn entry_bound(bound: Bound<&[u8]>, lower: bool) -> Bound<Vec<u8>> {
+    let with_suffix = |index_key: &[u8], suffix: u8| {
+        let mut buf = Vec::with_capacity(index_key.len() + 2);
+        encode_index_key(index_key, &mut buf);
+        buf.extend_from_slice(&[0, suffix]);
+        buf
+    };
+    match (bound, lower) {
+        (Bound::Included(key), true) => Bound::Included(with_suffix(key, 0)),
+        (Bound::Excluded(key), true) => Bound::Included(with_suffix(key, 1)),
+        (Bound::Included(key), false) => Bound::Excluded(with_suffix(key, 1)),
+        (Bound::Excluded(key), false) => Bound::Excluded(with_suffix(key, 0)),
+        (Bound::Unbounded, _) => Bound::Unbounded,
+    }
+}
+
+/// The entries to write for a record changing from `old` to `new`.
+fn entry_updates(
+    extractor: &dyn IndexExtractor,
+    key: &[u8],
+    old: Option<&[u8]>,
+    new: Option<&[u8]>,
+    updates: &mut Vec<WriteBatchRecord<Vec<u8>>>,
+) {
+    let old_keys = old.map_or_else(Vec::new, |value| extractor.extract(key, value));
+    let new_keys = new.map_or_else(Vec::new, |value| extractor.extract(key, value));
+    for index_key in &old_keys {
+        if !new_keys.contains(index_key) {
+            updates.push(WriteBatchRecord::Del(encode_entry(index_key, key)));
+        }
+    }
+    for index_key in &new_keys {
+        if !old_keys.contains(index_key) {
+            updates.push(WriteBatchRecord::Put(
+                encode_entry(index_key, key),
+                key.to_vec(),
+            ));
+        }
+    }
+}
+
+impl LsmStorageInner {
+    /// Register the index `name` maintained by `extractor`. The index is created, and filled from
+    /// the records of the storage with the writes blocked, unless it exists, e.g., when
+    /// registering it again after reopening the storage.
+    pub fn register_index(&self, name: &str, extractor: Arc<dyn IndexExtractor>) -> Result<()> {
+        if self.indexes.read().contains_key(name) {
+            bail!("index {} is already registered", name);
+        }
+        // the entries of an index are looked up by the prefix of the indexed value
+        self.check_bytewise("secondary indexes")?;
+        let cf_name = index_column_family(name);
+        let created = !self.column_families.read().contains_key(&cf_name);
+        if created {
+            let mut options = self.options.as_ref().clone();
+            // the entries are small and do not expire with the records
+            options.value_threshold = None;
+            options.default_ttl = None;
+            options.merge_operator = None;
+            self.create_cf(&cf_name, options)?;
+        }
+        let column_family = self.column_family(&cf_name)?;
+        let _write_lock = self.mvcc().write_lock.lock();
+        if created {
+            let mut iter = self.scan_with_ts(
+                Bound::Unbounded,
+                Bound::Unbounded,
+                self.mvcc().latest_commit_ts(),
+            )?;
+            let mut entries = Vec::new();
+            while iter.is_valid() {
+                entry_updates(
+                    extractor.as_ref(),
+                    iter.key(),
+                    None,
+                    Some(iter.value()),
+                    &mut entries,
+                );
+                if entries.len() >= BACKFILL_BATCH_SIZE {
+                    self.write_batch_cf(&cf_name, &entries)?;
+                    entries.clear();
+                }
+                iter.next()?;
+            }
+            if !entries.is_empty() {
+                self.write_batch_cf(&cf_name, &entries)?;
+            }
+        }
+        self.indexes.write().insert(
+            name.to_string(),
+            Index {
+                column_family,
+                extractor,
+            },
+        );
+        Ok(())
+    }
+
+    /// Unregister the index `name` and drop its column family.
+    pub fn drop_index(&self, name: &str) -> Result<()> {
+        {
+            let _write_lock = self.mvcc().write_lock.lock();
+            if self.indexes.write().remove(name).is_none() {
+                bail!("index {} is not registered", name);
+            }
+        }
+        self.drop_cf(&index_column_family(name))
+    }
+
+    /// The names of the registered indexes.
+    pub fn index_names(&self) -> Vec<String> {
+        self.indexes.read().keys().cloned().collect()
+    }
+
+    /// Whether the column family `name` holds a registered index.
+    pub(crate) fn is_index_column_family(&self, name: &str) -> bool {
+        name.strip_prefix(INDEX_COLUMN_FAMILY_PREFIX)
+            .is_some_and(|index| self.indexes.read().contains_key(index))
+    }
+
+    /// The index entries to write for `batch`, committed at `ts`, with the column families of
+    /// their indexes. Called with the write lock held, before taking the state.
+    pub(crate) fn index_updates<T: AsRef<[u8]>>(
+        &self,
+        batch: &[WriteBatchRecord<T>],
+        ts: u64,
+    ) -> Result<IndexUpdates> {
+        let indexes = self.indexes.read();
+        if indexes.is_empty() {
+            return Ok(Vec::new());
+        }
+        // the values of the keys as of before the batch, or as written earlier in the batch
+        let mut latest = HashMap::<&[u8], Option<Bytes>>::new();
+        let mut updates = (0..indexes.len()).map(|_| Vec::new()).collect::<Vec<_>>();
+        for record in batch {
+            let (key, new) = match record {
+                WriteBatchRecord::Put(key, value) => (key.as_ref(), Some(value.as_ref())),
+                WriteBatchRecord::Del(key) => (key.as_ref(), None),
+                WriteBatchRecord::Merge(..) => {
+                    bail!("merge operands cannot be written to a storage with indexes")
+                }
+            };
+            let old = match latest.get(key) {
+                Some(old) => old.clone(),
+                None => self.get_with_ts(key, ts - 1)?,
+            };
+            for (index, updates) in indexes.values().zip(updates.iter_mut()) {
+                entry_updates(index.extractor.as_ref(), key, old.as_deref(), new, updates);
+            }
+            latest.insert(key, new.map(Bytes::copy_from_slice));
+        }
+        Ok(indexes
+            .values()
+            .zip(updates)
+            .filter(|(_, updates)| !updates.is_empty())
+            .map(|(index, updates)| (index.column_family.clone(), updates))
+            .collect())
+    }
+
+    /// Put `data` into `memtable`, and `updates` into the column families of their indexes, all
+    /// logged as one WAL record. Returns the column families written to and their memtables.
+    /// Called with the write lock held.
+    pub(crate) fn put_batch_indexed(
+        &self,
+        memtable: &MemTable,
+        data: &[(KeySlice, &[u8])],
+        updates: &IndexUpdates,
+    ) -> Result<Vec<(Arc<ColumnFamily>, Arc<MemTable>)>> {
+        if updates.is_empty() {
+            memtable.put_batch(data)?;
+            return Ok(Vec::new());
+        }
+        // taken in the order of the index names, and never while holding another one
+        let _cf_write_locks = updates
+            .iter()
+            .map(|(cf, _)| cf.inner.mvcc().write_lock.lock())
+            .collect::<Vec<_>>();
+        for (cf, updates) in updates {
+            // the index keys are longer than the primary keys
+            cf.inner.check_batch_len(updates, None)?;
+        }
+        let mut cf_values = Vec::with_capacity(updates.len());
+        for (cf, updates) in updates {
+            let cf_ts = cf.inner.mvcc().latest_commit_ts() + 1;
+            // the entries never expire
+            let (values, _) = cf.inner.encode_values(updates, cf_ts, None)?;
+            cf_values.push((cf_ts, values));
+        }
+        let cf_data = updates
+            .iter()
+            .zip(cf_values.iter())
+            .map(|((cf, updates), (cf_ts, values))| (cf.id, batch_data(updates, values, *cf_ts)))
+            .collect::<Vec<_>>();
+        memtable.put_batch_with_column_families(data, &cf_data)?;
+        let mut cf_memtables = Vec::with_capacity(updates.len());
+        for (((cf, _), (cf_ts, _)), (_, data)) in
+            updates.iter().zip(cf_values.iter()).zip(cf_data.iter())
+        {
+            let cf_memtable = cf.inner.state.read().memtable.clone();
+            cf_memtable.put_batch(data)?;
+            cf.inner.mvcc().update_commit_ts(*cf_ts);
+            cf_memtables.push((cf.clone(), cf_memtable));
+        }
+        Ok(cf_memtables)
+    }
+
+    /// Create an iterator over the records with an index key of the index `name` in the range, by
+    /// index key and then by primary key. A record with several index keys in the range is yielded
+    /// once for each. The entries and the records are read as of the same write.
+    pub fn scan_index(
+        self: &Arc<Self>,
+        name: &str,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<IndexIterator> {
+        let index = self
+            .indexes
+            .read()
+            .get(name)
+            .cloned()
+            .with_context(|| format!("index {} is not registered", name))?;
+        let lower = entry_bound(lower, true);
+        let upper = entry_bound(upper, false);
+        let (read_ts, entries) = {
+            // the entries are written with the records under the write lock
+            let _write_lock = self.mvcc().write_lock.lock();
+            let entries = index.column_family.inner.scan(
+                lower.as_ref().map(Vec::as_slice),
+                upper.as_ref().map(Vec::as_slice),
+            )?;
+            (self.mvcc().latest_commit_ts(), entries)
+        };
+        IndexIterator::new(self.clone(), index.extractor, entries, read_ts)
+    }
+}
+
+/// Iterates over the records found by `LsmStorageInner::scan_index`: the key is the primary key
+/// and the value is the value of the record. The entries of the records that no longer have their
+/// index keys, e.g., deleted by a range delete or expired, are skipped.
+pub struct IndexIterator {
+    storage: Arc<LsmStorageInner>,
+    extractor: Arc<dyn IndexExtractor>,
+    entries: FusedIterator<LsmIterator>,
+    read_ts: u64,
+    index_key: Vec<u8>,
+    primary_key: Bytes,
+    value: Bytes,
+}
+
+impl IndexIterator {
+    fn new(
+        storage: Arc<LsmStorageInner>,
+        extractor: Arc<dyn IndexExtractor>,
+        entries: FusedIterator<LsmIterator>,
+        read_ts: u64,
+    ) -> Result<Self> {
+        let mut iter = Self {
+            storage,
+            extractor,
+            entries,
+            read_ts,
+            index_key: Vec::new(),
+            primary_key: Bytes::new(),
+            value: Bytes::new(),
+        };
+        iter.move_to_valid()?;
+        Ok(iter)
+    }
+
+    /// The index key of the current record.
+    pub fn index_key(&self) -> &[u8] {
+        &self.index_key
+    }
+
+    /// Move forward to the first entry whose record has its index key, starting from the current
+    /// one.
+    fn move_to_valid(&mut self) -> Result<()> {
+        while self.entries.is_valid() {
+            let (index_key, primary_key) = decode_entry(self.entries.key())?;
+            if let Some(value) = self.storage.get_with_ts(primary_key, self.read_ts)?
+                && self
+                    .extractor
+                    .extract(primary_key, &value)
+                    .iter()
+                    .any(|key| key[..] == index_key[..])
+            {
+                self.primary_key = Bytes::copy_from_slice(primary_key);
+                self.index_key = index_key;
+                self.value = value;
+                return Ok(());
+            }
+            self.entries.next()?;
+        }
+        Ok(())
+    }
+}
+
+impl StorageIterator for IndexIterator {
+    type KeyType<'a> = &'a [u8];
+
+    fn is_valid(&self) -> bool {
+        self.entries.is_valid()
+    }
+
+    fn key(&self) -> &[u8] {
+        &self.primary_key
+    }
+
+    fn value(&self) -> &[u8] {
+        &self.value
+    }
+
+    fn next(&mut self) -> Result<()> {
+        self.entries.next()?;
+        self.move_to_valid()
+    }
+}
@@ -15,22 +15,51 @@
 // #![allow(unused_variables)] // TODO(you): remove this lint after implementing this mod
 // #![allow(dead_code)] // TODO(you): remove this lint after implementing this mod
 
+mod async_table;
 pub(crate) mod bloom;
 mod builder;
+mod compression;
+mod descending_builder;
+mod direct_io;
+mod footer;
+mod index;
 mod iterator;
+mod prefetch;
+mod prefix;
+mod properties;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+mod uring;
 
+use std::collections::HashMap;
 use std::fs::File;
+use std::io::Write;
+use std::ops::Range;
 use std::path::Path;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
 
-use anyhow::{Ok, Result};
-pub use builder::SsTableBuilder;
-use bytes::{Buf, BufMut};
-pub use iterator::SsTableIterator;
+use anyhow::{Ok, Result, bail};
+pub use async_table::AsyncSsTable;
+pub use builder::{SsTableBuilder, SstSplitter, build_from_iter, flush_memtable};
+use bytes::{Buf, BufMut, Bytes};
+pub use compression::CompressionType;
+pub use descending_builder::DescendingSsTableBuilder;
+pub use direct_io::DIRECT_IO_ALIGNMENT;
+pub use footer::{Footer, SST_FORMAT_VERSION, SST_FORMAT_VERSION_LEGACY, SST_MAGIC};
+pub(crate) use footer::{SST_FORMAT_VERSION_INDEX_KIND, SectionLayout};
+pub(crate) use index::{BlockIndex, PartitionedIndex};
+pub use iterator::{SsTableEntries, SsTableIterator};
+pub use prefix::{FixedPrefixExtractor, PREFIX_EXTRACTOR_PROPERTY, PrefixExtractor};
+pub use properties::{TableProperties, TablePropertiesCollector, TablePropertiesCollectorFactory};
 
-use crate::block::Block;
-use crate::key::{KeyBytes, KeySlice};
+use crate::block::{Block, BlockIterator, SIZEOF_U16};
+use crate::error::Error;
+use crate::failpoint;
+use crate::iterators::boxed_iterator::BoxedStorageIterator;
+use crate::key::{Comparator, KeyBytes, KeySlice, bytewise_comparator};
 use crate::lsm_storage::BlockCache;
+use crate::range_tombstone::RangeTombstone;
+use crate::rate_limiter::{IoPriority, RateLimiter};
 
 use self::bloom::Bloom;
 
@@ -38,6 +67,13 @@ use self::bloom::Bloom;
 pub struct BlockMeta {
     /// Offset of this data block.
     pub offset: usize,
+    /// Length of the encoded block followed by its checksum.
+    pub len: usize,
+    /// Length of the region the block occupies in the file, including the padding after it.
+    pub padded_len: usize,
+    /// Number of entries in the data block, so that iterators can skip the block without reading
+    /// it.
+    pub num_entries: usize,
     /// The first key of the data block.
     pub first_key: KeyBytes,
     /// The last key of the data block.
@@ -45,66 +81,187 @@ pub struct BlockMeta {
 }
 
 impl BlockMeta {
-    pub fn new(offset: usize, first_key: KeyBytes, last_key: KeyBytes) -> Self {
+    pub fn new(
+        offset: usize,
+        len: usize,
+        padded_len: usize,
+        num_entries: usize,
+        first_key: KeyBytes,
+        last_key: KeyBytes,
+    ) -> Self {
         BlockMeta {
             offset,
+            len,
+            padded_len,
+            num_entries,
             first_key,
             last_key,
         }
     }
 
-    /// Encode block meta to a buffer.
-    /// You may add extra fields to the buffer,
-    /// in order to help keep track of `first_key` when decoding from the same buffer in the future.
-    /// assume the block meta can be loaded in memory entirely, so there's no need to put offsets of metas.
-    pub fn encode_block_meta(block_meta: &[BlockMeta], buf: &mut Vec<u8>) {
-        let estimated_size = block_meta.len() * 40;
+    /// Encode block meta to a buffer, followed by the largest timestamp of the SST.
+    /// The metas are framed by the number of entries at the front and a checksum of the whole
+    /// meta region at the end, so that a corrupted or truncated meta region can be detected.
+    pub fn encode_block_meta(block_meta: &[BlockMeta], max_ts: u64, buf: &mut Vec<u8>) {
+        let estimated_size = block_meta.len() * 52;
         buf.reserve(estimated_size);
+        let original_len = buf.len();
         buf.put_u32(block_meta.len() as u32);
         for meta in block_meta {
             buf.put_u32(meta.offset as u32);
-            // put first key len and key data.
-            buf.put_u16(meta.first_key.len() as u16);
-            buf.put(meta.first_key.raw_ref());
-            // put last key len and value data
-            buf.put_u16(meta.last_key.len() as u16);
-            buf.put(meta.last_key.raw_ref());
+            buf.put_u32(meta.len as u32);
+            buf.put_u32(meta.padded_len as u32);
+            buf.put_u32(meta.num_entries as u32);
+            // put first key len, key data and ts.
+            buf.put_u16(meta.first_key.key_len() as u16);
+            buf.put(meta.first_key.key_ref());
+            buf.put_u64(meta.first_key.ts());
+            // put last key len, key data and ts
+            buf.put_u16(meta.last_key.key_len() as u16);
+            buf.put(meta.last_key.key_ref());
+            buf.put_u64(meta.last_key.ts());
         }
+        buf.put_u64(max_ts);
+        buf.put_u32(crc32fast::hash(&buf[original_len..]));
     }
 
-    /// Decode block meta from a buffer.
-    pub fn decode_block_meta(buf: impl Buf) -> Vec<BlockMeta> {
-        let mut buf = buf;
+    /// Decode block meta and the largest timestamp of the SST from a buffer, validating the
+    /// checksum and the number of entries.
+    pub fn decode_block_meta(data: &[u8]) -> Result<(Vec<BlockMeta>, u64)> {
+        if data.len() < 16 {
+            bail!(Error::corruption(format!(
+                "block meta too short: {} bytes",
+                data.len()
+            )));
+        }
+        let (mut buf, mut checksum) = data.split_at(data.len() - 4);
+        if checksum.get_u32() != crc32fast::hash(buf) {
+            bail!(Error::corruption("block meta checksum mismatched"));
+        }
         let size = buf.get_u32() as usize;
+        let (mut buf, mut max_ts) = buf.split_at(buf.len() - 8);
+        let max_ts = max_ts.get_u64();
         let mut block_metas = Vec::<BlockMeta>::with_capacity(size);
-        for i in 0..size {
-            let offset = buf.get_u32() as usize;
-            let first_key_len = buf.get_u16() as usize;
-            let first_key = KeyBytes::from_bytes(buf.copy_to_bytes(first_key_len));
-            let last_key_len = buf.get_u16() as usize;
-            let last_key = KeyBytes::from_bytes(buf.copy_to_bytes(last_key_len));
+        for _ in 0..size {
+            let offset = buf.try_get_u32()? as usize;
+            let len = buf.try_get_u32()? as usize;
+            let padded_len = buf.try_get_u32()? as usize;
+            let num_entries = buf.try_get_u32()? as usize;
+            let first_key = Self::decode_key(&mut buf)?;
+            let last_key = Self::decode_key(&mut buf)?;
             block_metas.push(BlockMeta {
                 offset,
+                len,
+                padded_len,
+                num_entries,
                 first_key,
                 last_key,
             });
         }
-        block_metas
+        if buf.has_remaining() {
+            bail!(Error::corruption(format!(
+                "{} trailing bytes after block meta",
+                buf.remaining()
+            )));
+        }
+        Ok((block_metas, max_ts))
     }
+
+    /// Decode a key encoded as `key_len (u16) | key | ts (u64)`.
+    fn decode_key(buf: &mut &[u8]) -> Result<KeyBytes> {
+        let key_len = buf.try_get_u16()? as usize;
+        if buf.remaining() < key_len + 8 {
+            bail!(Error::corruption("block meta truncated"));
+        }
+        let key = buf.copy_to_bytes(key_len);
+        Ok(KeyBytes::from_bytes_with_ts(key, buf.get_u64()))
+    }
+}
+
+const SIZEOF_U32: usize = std::mem::size_of::<u32>();
+
+/// The result of a point lookup in an SST, telling a deleted key apart from an absent one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GetResult {
+    /// The key is not in the SST.
+    NotFound,
+    /// The key is deleted by a tombstone (an empty value) in the SST.
+    Deleted,
+    /// The key has a value in the SST.
+    Found(Bytes),
+}
+
+impl GetResult {
+    fn from_version(version: Option<(u64, Bytes)>) -> Self {
+        match version {
+            None => GetResult::NotFound,
+            Some((_, value)) if value.is_empty() => GetResult::Deleted,
+            Some((_, value)) => GetResult::Found(value),
+        }
+    }
+}
+
+/// Where the bytes of a `FileObject` live.
+enum FileBacking {
+    Disk(File),
+    Memory(Bytes),
+    /// A read-only memory mapping of the file, owned by the `Bytes`.
+    Mapped(Bytes),
+    /// A file opened with `O_DIRECT`, read in aligned pages bypassing the page cache.
+    Direct(File),
 }
 
 /// A file object.
-pub struct FileObject(Option<File>, u64);
+pub struct FileObject(Option<FileBacking>, u64);
 
 impl FileObject {
     pub fn read(&self, offset: u64, len: u64) -> Result<Vec<u8>> {
+        Ok(self.read_bytes(offset, len)?.into())
+    }
+
+    /// Read `len` bytes at `offset`. In-memory and memory-mapped files are sliced without copying.
+    pub fn read_bytes(&self, offset: u64, len: u64) -> Result<Bytes> {
         use std::os::unix::fs::FileExt;
-        let mut data = vec![0; len as usize];
-        self.0
-            .as_ref()
-            .unwrap()
-            .read_exact_at(&mut data[..], offset)?;
-        Ok(data)
+        match self.0.as_ref().unwrap() {
+            FileBacking::Disk(file) => {
+                let mut data = vec![0; len as usize];
+                file.read_exact_at(&mut data[..], offset)
+                    .map_err(Error::Io)?;
+                Ok(data.into())
+            }
+            FileBacking::Direct(file) => Ok(direct_io::read_at(file, offset, len)
+                .map_err(Error::Io)?
+                .into()),
+            FileBacking::Memory(bytes) | FileBacking::Mapped(bytes) => {
+                let end = offset.checked_add(len).filter(|end| *end <= self.1);
+                let Some(end) = end else {
+                    bail!(
+                        "read out of range: offset {}, len {}, size {}",
+                        offset,
+                        len,
+                        self.1
+                    );
+                };
+                Ok(bytes.slice(offset as usize..end as usize))
+            }
+        }
+    }
+
+    /// Read the `(offset, len)` ranges. With the `io-uring` feature on Linux, the reads of a file
+    /// on the disk are submitted as one batch, and otherwise they are issued one after another.
+    pub fn read_batch(&self, ranges: &[(u64, u64)]) -> Result<Vec<Bytes>> {
+        #[cfg(all(feature = "io-uring", target_os = "linux"))]
+        if let Some(FileBacking::Disk(file)) = &self.0
+            && ranges.len() > 1
+            && let Some(result) = uring::read_batch(file, ranges)
+        {
+            let bufs = result.map_err(Error::Io)?;
+            return Ok(bufs.into_iter().map(Bytes::from).collect());
+        }
+        ranges
+            .iter()
+            .map(|&(offset, len)| self.read_bytes(offset, len))
+            .collect()
     }
 
     pub fn size(&self) -> u64 {
@@ -113,18 +270,186 @@ impl FileObject {
 
     /// Create a new file object (day 2) and write the file to the disk (day 4).
     pub fn create(path: &Path, data: Vec<u8>) -> Result<Self> {
-        std::fs::write(path, &data)?;
-        File::open(path)?.sync_all()?;
+        failpoint::eval(failpoint::FILE_CREATE, path)?;
+        std::fs::write(path, &data).map_err(Error::Io)?;
+        failpoint::eval(failpoint::FILE_SYNC, path)?;
+        File::open(path)
+            .and_then(|file| file.sync_all())
+            .map_err(Error::Io)?;
         Ok(FileObject(
-            Some(File::options().read(true).write(false).open(path)?),
+            Some(FileBacking::Disk(
+                File::options()
+                    .read(true)
+                    .write(false)
+                    .open(path)
+                    .map_err(Error::Io)?,
+            )),
             data.len() as u64,
         ))
     }
 
     pub fn open(path: &Path) -> Result<Self> {
-        let file = File::options().read(true).write(false).open(path)?;
-        let size = file.metadata()?.len();
-        Ok(FileObject(Some(file), size))
+        let file = File::options()
+            .read(true)
+            .write(false)
+            .open(path)
+            .map_err(Error::Io)?;
+        let size = file.metadata().map_err(Error::Io)?.len();
+        Ok(FileObject(Some(FileBacking::Disk(file)), size))
+    }
+
+    /// Open the file and map it into memory, so that reads slice the mapping instead of issuing a
+    /// syscall each. Falls back to `open` if the file cannot be mapped, e.g., on a platform without
+    /// mmap.
+    pub fn open_mmap(path: &Path) -> Result<Self> {
+        let file = File::options()
+            .read(true)
+            .write(false)
+            .open(path)
+            .map_err(Error::Io)?;
+        // SAFETY: SST files are immutable once written, and are only removed after the storage
+        // engine drops them. Truncating a mapped file from elsewhere crashes the process.
+        match unsafe { memmap2::Mmap::map(&file) } {
+            std::result::Result::Ok(mmap) => {
+                let size = mmap.len() as u64;
+                Ok(FileObject(
+                    Some(FileBacking::Mapped(Bytes::from_owner(mmap))),
+                    size,
+                ))
+            }
+            Err(_) => Self::open(path),
+        }
+    }
+
+    /// Open the file for direct I/O, so that reads bypass the page cache and the block cache is the
+    /// only cache of the SST. Falls back to `open` if the platform or the file system does not
+    /// support direct I/O.
+    pub fn open_direct(path: &Path) -> Result<Self> {
+        let file = match direct_io::open(path, false) {
+            std::result::Result::Ok(file) => file,
+            Err(_) => return Self::open(path),
+        };
+        let size = file.metadata().map_err(Error::Io)?.len();
+        Ok(FileObject(Some(FileBacking::Direct(file)), size))
+    }
+
+    /// Whether the file is read with direct I/O.
+    pub fn is_direct(&self) -> bool {
+        matches!(self.0, Some(FileBacking::Direct(_)))
+    }
+
+    /// Whether the file is read through a memory mapping.
+    pub fn is_mmap(&self) -> bool {
+        matches!(self.0, Some(FileBacking::Mapped(_)))
+    }
+
+    /// Create a file object backed by an in-memory buffer instead of a file on the disk.
+    pub fn from_bytes(data: impl Into<Bytes>) -> Self {
+        let data = data.into();
+        let size = data.len() as u64;
+        FileObject(Some(FileBacking::Memory(data)), size)
+    }
+}
+
+/// A destination that `SsTableBuilder` writes the encoded SST into.
+pub trait SsTableWriter {
+    /// Persist the encoded SST and return a `FileObject` to read it back.
+    fn write_sst(self, data: Vec<u8>) -> Result<FileObject>;
+}
+
+/// Writes the SST to a file on the disk, see `FileObject::create`.
+impl SsTableWriter for &Path {
+    fn write_sst(self, data: Vec<u8>) -> Result<FileObject> {
+        FileObject::create(self, data)
+    }
+}
+
+/// Writes the SST to a file on the disk like `&Path`, and reads it back through a memory mapping,
+/// see `FileObject::open_mmap`.
+pub struct MmapWriter<'a>(pub &'a Path);
+
+impl SsTableWriter for MmapWriter<'_> {
+    fn write_sst(self, data: Vec<u8>) -> Result<FileObject> {
+        drop(FileObject::create(self.0, data)?);
+        FileObject::open_mmap(self.0)
+    }
+}
+
+/// Writes the SST to a file on the disk like `&Path` or `MmapWriter`, in pieces of at most the burst
+/// size of the rate limiter, each after the rate limiter grants it.
+pub struct RateLimitedWriter<'a> {
+    pub path: &'a Path,
+    pub rate_limiter: &'a RateLimiter,
+    pub priority: IoPriority,
+    pub use_mmap: bool,
+}
+
+impl SsTableWriter for RateLimitedWriter<'_> {
+    fn write_sst(self, data: Vec<u8>) -> Result<FileObject> {
+        failpoint::eval(failpoint::FILE_CREATE, self.path)?;
+        let mut file = File::create(self.path).map_err(Error::Io)?;
+        for piece in data.chunks(self.rate_limiter.burst_bytes() as usize) {
+            self.rate_limiter.request(piece.len() as u64, self.priority);
+            file.write_all(piece).map_err(Error::Io)?;
+        }
+        failpoint::eval(failpoint::FILE_SYNC, self.path)?;
+        file.sync_all().map_err(Error::Io)?;
+        drop(file);
+        if self.use_mmap {
+            FileObject::open_mmap(self.path)
+        } else {
+            FileObject::open(self.path)
+        }
+    }
+}
+
+/// Writes the SST to a file on the disk with direct I/O, optionally through a rate limiter like
+/// `RateLimitedWriter`, and reads it back with direct I/O, see `FileObject::open_direct`. Falls
+/// back to buffered I/O if direct I/O is not supported.
+pub struct DirectIoWriter<'a> {
+    pub path: &'a Path,
+    pub rate_limiter: Option<(&'a RateLimiter, IoPriority)>,
+}
+
+impl SsTableWriter for DirectIoWriter<'_> {
+    fn write_sst(self, data: Vec<u8>) -> Result<FileObject> {
+        failpoint::eval(failpoint::FILE_CREATE, self.path)?;
+        let file = match direct_io::open(self.path, true) {
+            std::result::Result::Ok(file) => file,
+            Err(_) => {
+                return match self.rate_limiter {
+                    Some((rate_limiter, priority)) => RateLimitedWriter {
+                        path: self.path,
+                        rate_limiter,
+                        priority,
+                        use_mmap: false,
+                    }
+                    .write_sst(data),
+                    None => FileObject::create(self.path, data),
+                };
+            }
+        };
+        failpoint::eval(failpoint::FILE_SYNC, self.path)?;
+        direct_io::write_file(&file, &data, self.rate_limiter).map_err(Error::Io)?;
+        drop(file);
+        FileObject::open_direct(self.path)
+    }
+}
+
+/// Keeps the SST in memory only, see `FileObject::from_bytes`.
+pub(crate) struct InMemoryWriter;
+
+impl SsTableWriter for InMemoryWriter {
+    fn write_sst(self, data: Vec<u8>) -> Result<FileObject> {
+        Ok(FileObject::from_bytes(data))
+    }
+}
+
+/// Appends the SST to the buffer. The returned `FileObject` reads from its own copy of the data.
+impl SsTableWriter for &mut Vec<u8> {
+    fn write_sst(self, data: Vec<u8>) -> Result<FileObject> {
+        self.extend_from_slice(&data);
+        Ok(FileObject::from_bytes(data))
     }
 }
 
@@ -132,8 +457,10 @@ impl FileObject {
 pub struct SsTable {
     /// The actual storage unit of SsTable, the format is as above.
     pub(crate) file: FileObject,
-    /// The meta blocks that hold info for data blocks.
+    /// The meta blocks that hold info for data blocks, empty if the index is partitioned.
     pub(crate) block_meta: Vec<BlockMeta>,
+    /// The top-level index of the index blocks, if the index is partitioned.
+    pub(crate) partitioned_index: Option<PartitionedIndex>,
     /// The offset that indicates the start point of meta blocks in `file`.
     pub(crate) block_meta_offset: usize,
     id: usize,
@@ -143,6 +470,42 @@ pub struct SsTable {
     pub(crate) bloom: Option<Bloom>,
     /// The maximum timestamp stored in this SST, implemented in week 3.
     max_ts: u64,
+    range_tombstones: Vec<RangeTombstone>,
+    /// The format the SST is encoded in, see `SST_FORMAT_VERSION`.
+    format_version: u32,
+    /// Whether to verify block checksums when reading blocks from the disk.
+    pub(crate) verify_on_read: AtomicBool,
+    /// Number of entries, known when built or from the properties, or counted on first use
+    /// otherwise, see `num_entries`.
+    pub(crate) num_entries: OnceLock<usize>,
+    /// The properties section, `None` in the formats without one.
+    properties: Option<TableProperties>,
+    /// The order of the keys, which is not recorded in the SST, see `with_comparator`.
+    comparator: Comparator,
+    _invalidator: Option<CacheInvalidator>,
+}
+
+/// Drops the cached blocks of an SST when the table is dropped, e.g., after it is removed by
+/// compaction. It is a field rather than a `Drop` of `SsTable`, so the file can still be moved
+/// out of a table.
+pub(crate) struct CacheInvalidator {
+    block_cache: Arc<BlockCache>,
+    sst_id: usize,
+}
+
+impl CacheInvalidator {
+    pub(crate) fn new(sst_id: usize, block_cache: Option<&Arc<BlockCache>>) -> Option<Self> {
+        block_cache.map(|block_cache| Self {
+            block_cache: block_cache.clone(),
+            sst_id,
+        })
+    }
+}
+
+impl Drop for CacheInvalidator {
+    fn drop(&mut self) {
+        self.block_cache.invalidate_sst(self.sst_id);
+    }
 }
 
 impl SsTable {
@@ -154,27 +517,65 @@ impl SsTable {
     /// Open SSTable from a file.
     pub fn open(id: usize, block_cache: Option<Arc<BlockCache>>, file: FileObject) -> Result<Self> {
         // unimplemented!()
-        let size = file.size();
-        let floor_data = file.read(size - 4, 4)?;
-        let meta_offset = u32::from_be_bytes(floor_data[..4].try_into().unwrap()) as u64;
-        let meta_data = file.read(meta_offset, size - 4 - meta_offset)?;
+        let layout = SectionLayout::read(&file)?;
+        let read_section = |range: &Range<u64>| file.read(range.start, range.end - range.start);
+        let bloom = Bloom::decode(&read_section(&layout.bloom)?)?;
+        let range_tombstones =
+            RangeTombstone::decode_block(&read_section(&layout.range_tombstones)?)?;
+        let meta_data = read_section(&layout.meta)?;
+        let meta_offset = layout.meta.start;
+        let meta_data = compression::decompress(meta_data.into())?;
         // let block_data = file.read(0, meta_offset);
-        let block_meta = BlockMeta::decode_block_meta(&meta_data[..]);
-        let first_key = block_meta.first().unwrap().first_key.clone();
-        let last_key = block_meta.last().unwrap().last_key.clone();
+        let (index, max_ts) = if layout.version >= SST_FORMAT_VERSION_INDEX_KIND {
+            BlockIndex::decode(&meta_data[..])?
+        } else {
+            let (block_meta, max_ts) = BlockMeta::decode_block_meta(&meta_data[..])?;
+            (BlockIndex::Full(block_meta), max_ts)
+        };
+        let properties = if layout.properties.is_empty() {
+            None
+        } else {
+            Some(TableProperties::decode(&read_section(&layout.properties)?)?)
+        };
+        let num_entries = properties
+            .as_ref()
+            .map_or_else(OnceLock::new, |properties| {
+                OnceLock::from(properties.num_entries as usize)
+            });
+        let (first_key, last_key) = index.key_range();
+        let (block_meta, partitioned_index) = index.into_parts();
+        let invalidator = CacheInvalidator::new(id, block_cache.as_ref());
         Ok(SsTable {
             file,
             block_meta,
+            partitioned_index,
             block_meta_offset: meta_offset as usize,
             id,
             block_cache,
             first_key,
             last_key,
-            bloom: None,
-            max_ts: 0,
+            bloom: Some(bloom),
+            max_ts,
+            range_tombstones,
+            format_version: layout.version,
+            verify_on_read: AtomicBool::new(true),
+            num_entries,
+            properties,
+            comparator: bytewise_comparator(),
+            _invalidator: invalidator,
         })
     }
 
+    /// Order the keys of this SST by `comparator`, which must be the one it was built with.
+    pub fn with_comparator(mut self, comparator: Comparator) -> Self {
+        self.comparator = comparator;
+        self
+    }
+
+    pub fn comparator(&self) -> &Comparator {
+        &self.comparator
+    }
+
     /// Create a mock SST with only first key + last key metadata
     pub fn create_meta_only(
         id: usize,
@@ -185,6 +586,7 @@ impl SsTable {
         Self {
             file: FileObject(None, file_size),
             block_meta: vec![],
+            partitioned_index: None,
             block_meta_offset: 0,
             id,
             block_cache: None,
@@ -192,50 +594,426 @@ impl SsTable {
             last_key,
             bloom: None,
             max_ts: 0,
+            range_tombstones: Vec::new(),
+            format_version: SST_FORMAT_VERSION,
+            verify_on_read: AtomicBool::new(true),
+            num_entries: OnceLock::from(0),
+            properties: None,
+            comparator: bytewise_comparator(),
+            _invalidator: None,
         }
     }
 
     /// Read a block from the disk.
     pub fn read_block(&self, block_idx: usize) -> Result<Arc<Block>> {
-        // unimplemented!()
-        if block_idx >= self.block_meta.len() {
+        let block_data = self.read_block_data(block_idx, self.verify_on_read())?;
+        let block = Block::decode_shared(block_data)?;
+        Ok(Arc::new(block))
+    }
+
+    /// Read the encoded block from the disk, optionally verifying its checksum.
+    fn read_block_data(&self, block_idx: usize, verify: bool) -> Result<Bytes> {
+        if block_idx >= self.num_of_blocks() {
             return Err(anyhow::anyhow!("wrong idx"));
         }
-        let offset = self.block_meta[block_idx].offset;
-        let offset_end = if block_idx + 1 < self.block_meta.len() {
-            self.block_meta[block_idx + 1].offset
-        } else {
-            self.block_meta_offset // 指向 meta 区域的开头，即最后一个 block 的结尾
+        let meta = self.block_meta_at(block_idx)?;
+        self.read_region(&meta, verify, format_args!("block {}", block_idx))
+    }
+
+    /// Read the block or the index block that `meta` points to, optionally verifying its checksum.
+    /// `name` is how errors refer to the block.
+    fn read_region(
+        &self,
+        meta: &BlockMeta,
+        verify: bool,
+        name: std::fmt::Arguments,
+    ) -> Result<Bytes> {
+        Self::check_region(meta, name)?;
+        let block_data = self.file.read_bytes(meta.offset as u64, meta.len as u64)?;
+        Self::decode_region(block_data, verify, name)
+    }
+
+    fn check_region(meta: &BlockMeta, name: std::fmt::Arguments) -> Result<()> {
+        // the block trailer is a codec byte and a checksum
+        if meta.len < SIZEOF_U32 + 1 || meta.len > meta.padded_len {
+            bail!(Error::corruption(format!(
+                "{} has invalid length {} (padded to {})",
+                name, meta.len, meta.padded_len
+            )));
+        }
+        Ok(())
+    }
+
+    /// Verify the checksum of the block or the index block read by `read_region`, and decompress
+    /// it.
+    fn decode_region(block_data: Bytes, verify: bool, name: std::fmt::Arguments) -> Result<Bytes> {
+        let block_len = block_data.len() - SIZEOF_U32;
+        if verify {
+            let checksum = (&block_data[block_len..]).get_u32();
+            if checksum != crc32fast::hash(&block_data[..block_len]) {
+                bail!(Error::corruption(format!("{} checksum mismatched", name)));
+            }
+        }
+        compression::decompress(block_data.slice(..block_len))
+    }
+
+    /// The meta of the `block_idx`-th block. With a partitioned index, the index block holding it
+    /// is read through the block cache.
+    pub fn block_meta_at(&self, block_idx: usize) -> Result<BlockMeta> {
+        let Some(index) = &self.partitioned_index else {
+            return match self.block_meta.get(block_idx) {
+                Some(meta) => Ok(meta.clone()),
+                None => bail!("block index {} out of range", block_idx),
+            };
         };
-        let data_len = offset_end - offset;
+        let block = self.read_index_block_cached(block_idx / index.partition_size)?;
+        let mut iter = BlockIterator::create_and_seek_to_first(block);
+        iter.seek_to_index(block_idx % index.partition_size);
+        if !iter.is_valid() {
+            bail!(Error::corruption(format!(
+                "block {} missing in the index",
+                block_idx
+            )));
+        }
+        PartitionedIndex::decode_entry(&iter)
+    }
 
-        let block_data = self.file.read(offset as u64, data_len as u64)?;
-        let block = Block::decode(&block_data[..]);
-        Ok(Arc::new(block))
+    /// Read the `partition`-th index block, with block cache. Index blocks are cached after the
+    /// data blocks, at `num_of_blocks() + partition`.
+    fn read_index_block_cached(&self, partition: usize) -> Result<Arc<Block>> {
+        let index = self
+            .partitioned_index
+            .as_ref()
+            .expect("the index is partitioned");
+        let read = || -> Result<Arc<Block>> {
+            let data = self.read_region(
+                &index.partitions[partition],
+                self.verify_on_read(),
+                format_args!("index block {}", partition),
+            )?;
+            Ok(Arc::new(Block::decode_shared(data)?))
+        };
+        match self.block_cache {
+            Some(ref cache) => cache.try_get_with((self.id, index.num_blocks + partition), read),
+            None => read(),
+        }
+    }
+
+    /// Whether block checksums are verified when reading blocks from the disk.
+    pub fn verify_on_read(&self) -> bool {
+        self.verify_on_read.load(Ordering::Relaxed)
+    }
+
+    /// Enable or disable checksum verification of blocks read from the disk. Checksums are always
+    /// written, so a table read without verification can still be checked with `verify`.
+    pub fn set_verify_on_read(&self, verify: bool) {
+        self.verify_on_read.store(verify, Ordering::Relaxed);
+    }
+
+    /// Verify the checksums of all index blocks and data blocks, regardless of `verify_on_read`.
+    pub fn verify(&self) -> Result<()> {
+        if let Some(index) = &self.partitioned_index {
+            for (partition, meta) in index.partitions.iter().enumerate() {
+                self.read_region(meta, true, format_args!("index block {}", partition))?;
+            }
+        }
+        for block_idx in 0..self.num_of_blocks() {
+            self.read_block_data(block_idx, true)?;
+        }
+        Ok(())
     }
 
     /// Read a block from disk, with block cache. (Day 4)
+    /// A cached block that fails validation is evicted and read from the disk again.
     pub fn read_block_cached(&self, block_idx: usize) -> Result<Arc<Block>> {
         if let Some(ref cache) = self.block_cache {
-            let block = cache
-                .try_get_with((self.id, block_idx), || self.read_block(block_idx))
-                .map_err(|e| anyhow::anyhow!("{}", e))?;
-            Ok(block)
+            let block = cache.try_get_with((self.id, block_idx), || self.read_block(block_idx))?;
+            if block.validate().is_ok() {
+                return Ok(block);
+            }
+            // the cached block is corrupted, evict it and read it from the disk once more
+            cache.invalidate_block(self.id, block_idx);
+            cache.try_get_with((self.id, block_idx), || self.read_block(block_idx))
         } else {
             self.read_block(block_idx)
         }
     }
+
+    /// Read the blocks in `blocks`, in that order, with block cache. The uncached ones are read
+    /// from the disk as one batch, see `FileObject::read_batch`.
+    pub fn read_blocks_cached(&self, blocks: &[usize]) -> Result<Vec<Arc<Block>>> {
+        let mut uncached = blocks
+            .iter()
+            .copied()
+            .filter(|&block_idx| {
+                self.block_cache
+                    .as_ref()
+                    .is_none_or(|cache| !cache.contains_block(self.id, block_idx))
+            })
+            .collect::<Vec<_>>();
+        uncached.sort_unstable();
+        uncached.dedup();
+        let mut loaded = uncached
+            .iter()
+            .copied()
+            .zip(self.read_blocks(&uncached)?)
+            .collect::<HashMap<_, _>>();
+        blocks
+            .iter()
+            .map(
+                |&block_idx| match (&self.block_cache, loaded.remove(&block_idx)) {
+                    (Some(cache), Some(block)) => {
+                        cache.try_get_with((self.id, block_idx), || Ok(block))
+                    }
+                    (None, Some(block)) => {
+                        // a block requested twice is read once
+                        loaded.insert(block_idx, block.clone());
+                        Ok(block)
+                    }
+                    (_, None) => self.read_block_cached(block_idx),
+                },
+            )
+            .collect()
+    }
+
+    /// Read the blocks in `blocks` from the disk as one batch, without block cache.
+    fn read_blocks(&self, blocks: &[usize]) -> Result<Vec<Arc<Block>>> {
+        let metas = blocks
+            .iter()
+            .map(|&block_idx| {
+                if block_idx >= self.num_of_blocks() {
+                    bail!("wrong idx");
+                }
+                let meta = self.block_meta_at(block_idx)?;
+                Self::check_region(&meta, format_args!("block {}", block_idx))?;
+                Ok(meta)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let ranges = metas
+            .iter()
+            .map(|meta| (meta.offset as u64, meta.len as u64))
+            .collect::<Vec<_>>();
+        let verify = self.verify_on_read();
+        let data = self.file.read_batch(&ranges)?;
+        blocks
+            .iter()
+            .zip(data)
+            .map(|(block_idx, data)| {
+                let data = Self::decode_region(data, verify, format_args!("block {}", block_idx))?;
+                Ok(Arc::new(Block::decode_shared(data)?))
+            })
+            .collect()
+    }
+
+    /// Load the blocks in `blocks` that are not cached into the block cache on the prefetch thread
+    /// shared by all tables, as one batch of reads, so that a sequential scan finds them there. The
+    /// request is dropped if the thread falls too far behind, and skipped if the table is dropped
+    /// before the thread gets to it. Does nothing without a block cache. Errors are left to the
+    /// reads of the blocks.
+    pub fn prefetch_blocks(self: &Arc<Self>, blocks: Range<usize>) {
+        if self.block_cache.is_some() {
+            prefetch::prefetch(self, blocks);
+        }
+    }
+
+    /// Load the blocks in `blocks` that are not cached into the block cache, see `prefetch_blocks`.
+    fn load_blocks(&self, blocks: Range<usize>) {
+        let Some(cache) = &self.block_cache else {
+            return;
+        };
+        let uncached = blocks
+            .filter(|&block_idx| !cache.contains_block(self.id, block_idx))
+            .collect::<Vec<_>>();
+        let _ = self.read_blocks_cached(&uncached);
+    }
+
     /// Find the block that may contain `key`.
     /// Note: You may want to make use of the `first_key` stored in `BlockMeta`.
     /// You may also assume the key-value pairs stored in each consecutive block are sorted.
-    pub fn find_block_idx(&self, key: KeySlice) -> usize {
-        // 寻找第一个 last_key >= key 的 block 索引
-        self.block_meta
-            .partition_point(|meta| meta.last_key.as_key_slice() < key)
+    /// With a partitioned index, the top-level index locates the index block, which is then searched
+    /// for the block.
+    pub fn find_block_idx(&self, key: KeySlice) -> Result<usize> {
+        let Some(index) = &self.partitioned_index else {
+            // 寻找第一个 last_key >= key 的 block 索引
+            return Ok(self.block_meta.partition_point(|meta| {
+                self.comparator
+                    .compare_keys(meta.last_key.as_key_slice(), key)
+                    .is_lt()
+            }));
+        };
+        let partition = index.find_partition(key, &*self.comparator);
+        if partition >= index.partitions.len() {
+            return Ok(index.num_blocks);
+        }
+        let block = self.read_index_block_cached(partition)?;
+        let iter =
+            BlockIterator::create_keys_only_and_seek_to_key_by(block, key, &*self.comparator);
+        if !iter.is_valid() {
+            bail!(Error::corruption(format!(
+                "index block {} ends before its last key",
+                partition
+            )));
+        }
+        Ok(partition * index.partition_size + iter.index())
+    }
+
+    /// Number of blocks whose first key <= `key`, i.e., the block holding the greatest key <= `key`
+    /// is the one before it.
+    pub(crate) fn num_of_blocks_starting_le(&self, key: KeySlice) -> Result<usize> {
+        let block_idx = self.find_block_idx(key)?;
+        if block_idx < self.num_of_blocks()
+            && self
+                .comparator
+                .compare_keys(self.block_meta_at(block_idx)?.first_key.as_key_slice(), key)
+                .is_le()
+        {
+            return Ok(block_idx + 1);
+        }
+        Ok(block_idx)
+    }
+    /// Create a type-erased iterator over the whole table.
+    pub fn scan_all(self: &Arc<Self>) -> Result<BoxedStorageIterator> {
+        Ok(BoxedStorageIterator::new(
+            SsTableIterator::create_and_seek_to_first(self.clone())?,
+        ))
+    }
+
+    /// Look up the latest version of the user key of `key` with a timestamp <= `key.ts()` in this
+    /// SST, reporting whether it is found, deleted, or absent.
+    pub fn get_kind(&self, key: KeySlice) -> Result<GetResult> {
+        Ok(GetResult::from_version(self.get_version(key)?))
+    }
+
+    /// `get_kind`, returning the timestamp and the value of the version found instead. The value
+    /// of a tombstone is empty.
+    pub fn get_version(&self, key: KeySlice) -> Result<Option<(u64, Bytes)>> {
+        let Some(block_idx) = self.find_block_for_get(key)? else {
+            return Ok(None);
+        };
+        Ok(self.get_version_in_block(self.read_block_cached(block_idx)?, key))
+    }
+
+    /// `get_kind` for a batch of keys sorted by user key, reading each block at most once. The
+    /// results are in the order of `keys`.
+    pub fn get_kinds(&self, keys: &[KeySlice]) -> Result<Vec<GetResult>> {
+        Ok(self
+            .get_versions(keys)?
+            .into_iter()
+            .map(GetResult::from_version)
+            .collect())
+    }
+
+    /// `get_version` for a batch of keys sorted by user key, see `get_kinds`.
+    pub fn get_versions(&self, keys: &[KeySlice]) -> Result<Vec<Option<(u64, Bytes)>>> {
+        let block_idxs = keys
+            .iter()
+            .map(|&key| self.find_block_for_get(key))
+            .collect::<Result<Vec<_>>>()?;
+        // sorted keys fall into the blocks in order, and the blocks are read as one batch
+        let mut needed = block_idxs.iter().flatten().copied().collect::<Vec<_>>();
+        needed.dedup();
+        let blocks = needed
+            .iter()
+            .copied()
+            .zip(self.read_blocks_cached(&needed)?)
+            .collect::<HashMap<_, _>>();
+        Ok(keys
+            .iter()
+            .zip(block_idxs)
+            .map(|(&key, block_idx)| {
+                block_idx.and_then(|idx| self.get_version_in_block(blocks[&idx].clone(), key))
+            })
+            .collect())
+    }
+
+    /// The block that may contain `key`, or `None` if the key range or the bloom filter rule the
+    /// key out.
+    fn find_block_for_get(&self, key: KeySlice) -> Result<Option<usize>> {
+        if self
+            .comparator
+            .compare(key.key_ref(), self.first_key.key_ref())
+            .is_lt()
+            || self
+                .comparator
+                .compare(key.key_ref(), self.last_key.key_ref())
+                .is_gt()
+            || !self.may_contain(key)
+        {
+            return Ok(None);
+        }
+        let block_idx = self.find_block_idx(key)?;
+        Ok((block_idx < self.num_of_blocks()).then_some(block_idx))
+    }
+
+    fn get_version_in_block(&self, block: Arc<Block>, key: KeySlice) -> Option<(u64, Bytes)> {
+        let iter = BlockIterator::create_and_seek_to_key_by(block, key, &*self.comparator);
+        (iter.is_valid() && iter.key().key_ref() == key.key_ref())
+            .then(|| (iter.key().ts(), Bytes::copy_from_slice(iter.value())))
     }
+
+    /// Read the `entry_idx`-th entry of the `block_idx`-th block.
+    pub fn entry_at(&self, block_idx: usize, entry_idx: usize) -> Result<(KeyBytes, Bytes)> {
+        if block_idx >= self.num_of_blocks() {
+            bail!(
+                "block index {} out of range, sst {} has {} blocks",
+                block_idx,
+                self.id,
+                self.num_of_blocks()
+            );
+        }
+        let block = self.read_block_cached(block_idx)?;
+        let num_of_entries = block.offsets.len();
+        if entry_idx >= num_of_entries {
+            bail!(
+                "entry index {} out of range, block {} has {} entries",
+                entry_idx,
+                block_idx,
+                num_of_entries
+            );
+        }
+        let mut iter = BlockIterator::create_and_seek_to_first(block);
+        iter.seek_to_index(entry_idx);
+        Ok((
+            iter.key().to_key_vec().into_key_bytes(),
+            Bytes::copy_from_slice(iter.value()),
+        ))
+    }
+
+    /// Check the bloom filter: returns false only if no version of the user key of `key` is in
+    /// this SST. Always true if the SST has no bloom filter.
+    pub fn may_contain(&self, key: KeySlice) -> bool {
+        self.bloom
+            .as_ref()
+            .is_none_or(|bloom| bloom.may_contain(farmhash::fingerprint32(key.key_ref())))
+    }
+
+    /// Check the prefixes in the bloom filter: returns false only if no key starting with
+    /// `key_prefix` is in this SST. Always true if the SST has no prefixes of `extractor` in its
+    /// bloom filter, or `key_prefix` has no prefix.
+    pub fn may_contain_prefix(&self, extractor: &dyn PrefixExtractor, key_prefix: &[u8]) -> bool {
+        let (Some(bloom), Some(properties), Some(prefix)) =
+            (&self.bloom, &self.properties, extractor.prefix(key_prefix))
+        else {
+            return true;
+        };
+        properties
+            .user_properties
+            .get(PREFIX_EXTRACTOR_PROPERTY)
+            .is_none_or(|name| name.as_slice() != extractor.name().as_bytes())
+            || bloom.may_contain(farmhash::fingerprint32(prefix))
+    }
+
     /// Get number of data blocks.
     pub fn num_of_blocks(&self) -> usize {
-        self.block_meta.len()
+        match &self.partitioned_index {
+            Some(index) => index.num_blocks,
+            None => self.block_meta.len(),
+        }
+    }
+
+    /// Whether the block metas are stored in index blocks, see `SsTableBuilder::with_partitioned_index`.
+    pub fn has_partitioned_index(&self) -> bool {
+        self.partitioned_index.is_some()
     }
 
     pub fn first_key(&self) -> &KeyBytes {
@@ -250,11 +1028,148 @@ impl SsTable {
         self.file.1
     }
 
+    /// Size of the SST file in bytes, including the block meta, the bloom filter and the footers.
+    pub fn file_size(&self) -> u64 {
+        self.file.size()
+    }
+
+    /// Size of the data blocks in bytes, excluding the index blocks, the block meta, the bloom
+    /// filter and the footers.
+    pub fn data_size(&self) -> u64 {
+        // the index blocks, if any, are written right after the data blocks
+        match self
+            .partitioned_index
+            .as_ref()
+            .and_then(|index| index.partitions.first())
+        {
+            Some(first_partition) => first_partition.offset as u64,
+            None => self.block_meta_offset as u64,
+        }
+    }
+
     pub fn sst_id(&self) -> usize {
         self.id
     }
 
+    pub fn range_tombstones(&self) -> &[RangeTombstone] {
+        &self.range_tombstones
+    }
+
+    /// The format the SST is encoded in. SSTs in older formats are read as is, and rewritten in the
+    /// current format only when compacted.
+    pub fn format_version(&self) -> u32 {
+        self.format_version
+    }
+
     pub fn max_ts(&self) -> u64 {
         self.max_ts
     }
+
+    /// The properties recorded by `SsTableBuilder`, `None` if the SST is in a format without
+    /// them.
+    pub fn properties(&self) -> Option<&TableProperties> {
+        self.properties.as_ref()
+    }
+
+    /// Number of entries, i.e., versions, in the data blocks. An SST without properties counts
+    /// them from the footer of each block on the first call, read bypassing the block cache without
+    /// decoding the blocks, and caches the result.
+    pub fn num_entries(&self) -> Result<usize> {
+        if let Some(num_entries) = self.num_entries.get() {
+            return Ok(*num_entries);
+        }
+        let mut num_entries = 0;
+        for block_idx in 0..self.num_of_blocks() {
+            let data = self.read_block_data(block_idx, false)?;
+            if data.len() < SIZEOF_U16 {
+                bail!(Error::corruption(format!("block {} too short", block_idx)));
+            }
+            num_entries += (&data[data.len() - SIZEOF_U16..]).get_u16() as usize;
+        }
+        Ok(*self.num_entries.get_or_init(|| num_entries))
+    }
+
+    /// Estimate the size of the data blocks and the number of entries holding keys from `lower` to
+    /// `upper`, without reading any data block. The size is that of every block overlapping the
+    /// range, computed from the offsets in the block meta, and the entries are those of the SST
+    /// pro-rated by that size.
+    pub fn approximate_range_stats(&self, lower: KeySlice, upper: KeySlice) -> Result<(u64, u64)> {
+        if self.comparator.compare_keys(lower, upper).is_gt() {
+            return Ok((0, 0));
+        }
+        let start = self.find_block_idx(lower)?;
+        let end = self.num_of_blocks_starting_le(upper)?;
+        if start >= end {
+            return Ok((0, 0));
+        }
+        let first = self.block_meta_at(start)?;
+        let last = self.block_meta_at(end - 1)?;
+        let size = (last.offset + last.padded_len - first.offset) as u64;
+        let data_size = self.data_size().max(1);
+        let num_entries = (self.num_entries()? as u128 * size as u128 / data_size as u128) as u64;
+        Ok((size, num_entries))
+    }
+
+    /// Collect a snapshot of the statistics of this SST. Block sizes come from the block meta, and
+    /// entries are counted as in `num_entries`.
+    pub fn stats(&self) -> Result<SsTableStats> {
+        let num_entries = self.num_entries()?;
+        let block_sizes = (0..self.num_of_blocks())
+            .map(|block_idx| Ok(self.block_meta_at(block_idx)?.padded_len))
+            .collect::<Result<Vec<_>>>()?;
+        let block_sizes = block_sizes.into_iter();
+        Ok(SsTableStats {
+            num_blocks: self.num_of_blocks(),
+            num_entries,
+            data_size: self.data_size(),
+            min_block_size: block_sizes.clone().min().unwrap_or(0),
+            avg_block_size: block_sizes.clone().sum::<usize>() / self.num_of_blocks().max(1),
+            max_block_size: block_sizes.max().unwrap_or(0),
+            first_key: self.first_key.clone(),
+            last_key: self.last_key.clone(),
+            max_ts: self.max_ts,
+            bloom_bits: self
+                .bloom
+                .as_ref()
+                .map_or(0, |bloom| bloom.filter.len() * 8),
+            bloom_estimated_fpr: self
+                .bloom
+                .as_ref()
+                .map(|bloom| bloom.estimated_fpr(num_entries)),
+        })
+    }
+}
+
+/// A snapshot of the statistics of an SST, see `SsTable::stats`. Block sizes are the sizes on disk,
+/// including the checksum and the alignment padding.
+#[derive(Debug, Clone)]
+pub struct SsTableStats {
+    pub num_blocks: usize,
+    pub num_entries: usize,
+    /// Size of all data blocks, see `SsTable::data_size`.
+    pub data_size: u64,
+    pub min_block_size: usize,
+    pub avg_block_size: usize,
+    pub max_block_size: usize,
+    pub first_key: KeyBytes,
+    pub last_key: KeyBytes,
+    pub max_ts: u64,
+    /// Number of bits of the bloom filter, 0 if there is none.
+    pub bloom_bits: usize,
+    /// Estimated false positive rate of the bloom filter, `None` if there is none.
+    pub bloom_estimated_fpr: Option<f64>,
+}
+
+impl std::fmt::Debug for SsTable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SsTable")
+            .field("id", &self.id)
+            .field("num_of_blocks", &self.num_of_blocks())
+            .field("block_meta_offset", &self.block_meta_offset)
+            .field("first_key", &self.first_key)
+            .field("last_key", &self.last_key)
+            .field("has_bloom", &self.bloom.is_some())
+            .field("max_ts", &self.max_ts)
+            .finish()
+    }
 }
@@ -0,0 +1,50 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use anyhow::Result;
+
+use super::SsTable;
+use crate::block::Block;
+
+/// An `SsTable` handle for async code. Blocks in the block cache are returned right away, and the
+/// others are read on the blocking thread pool of tokio, so that the executor threads are never
+/// blocked on the disk. Must be used within a tokio runtime.
+#[derive(Clone)]
+pub struct AsyncSsTable {
+    table: Arc<SsTable>,
+}
+
+impl AsyncSsTable {
+    pub fn new(table: Arc<SsTable>) -> Self {
+        Self { table }
+    }
+
+    pub fn table(&self) -> &Arc<SsTable> {
+        &self.table
+    }
+
+    /// Read a block through the block cache, see `SsTable::read_block_cached`.
+    pub async fn read_block(&self, block_idx: usize) -> Result<Arc<Block>> {
+        if let Some(cache) = &self.table.block_cache
+            && let Some(block) = cache.get_if_present((self.table.sst_id(), block_idx))
+            && block.validate().is_ok()
+        {
+            return Ok(block);
+        }
+        let table = self.table.clone();
+        tokio::task::spawn_blocking(move || table.read_block_cached(block_idx)).await?
+    }
+}
@@ -14,9 +14,11 @@
 
 // Copyright 2021 TiKV Project Authors. Licensed under Apache-2.0.
 
-use anyhow::Result;
+use anyhow::{Result, bail};
 use bytes::{BufMut, Bytes, BytesMut};
 
+use crate::error::Error;
+
 /// Implements a bloom filter
 pub struct Bloom {
     /// data of filter in bits
@@ -61,6 +63,9 @@ impl<T: AsMut<[u8]>> BitSliceMut for T {
 impl Bloom {
     /// Decode a bloom filter
     pub fn decode(buf: &[u8]) -> Result<Self> {
+        if buf.is_empty() {
+            bail!(Error::corruption("empty bloom filter"));
+        }
         let filter = &buf[..buf.len() - 1];
         let k = buf[buf.len() - 1];
         Ok(Self {
@@ -92,9 +97,15 @@ impl Bloom {
         let nbits = nbytes * 8;
         let mut filter = BytesMut::with_capacity(nbytes);
         filter.resize(nbytes, 0);
-
-        // TODO: build the bloom filter
-
+        for h in keys {
+            let mut h = *h;
+            let delta = h.rotate_left(15);
+            for _ in 0..k {
+                let bit_pos = (h as usize) % nbits;
+                filter.set_bit(bit_pos, true);
+                h = h.wrapping_add(delta);
+            }
+        }
         Self {
             filter: filter.freeze(),
             k: k as u8,
@@ -102,17 +113,32 @@ impl Bloom {
     }
 
     /// Check if a bloom filter may contain some data
-    pub fn may_contain(&self, h: u32) -> bool {
+    pub fn may_contain(&self, mut h: u32) -> bool {
         if self.k > 30 {
             // potential new encoding for short bloom filters
             true
         } else {
             let nbits = self.filter.bit_len();
             let delta = h.rotate_left(15);
-
-            // TODO: probe the bloom filter
-
+            for _ in 0..self.k {
+                let bit_pos = h % (nbits as u32);
+                if !self.filter.get_bit(bit_pos as usize) {
+                    return false;
+                }
+                h = h.wrapping_add(delta);
+            }
             true
         }
     }
+
+    /// Estimate the false positive rate of the filter after `num_keys` keys are inserted, using
+    /// `(1 - e^(-k * n / m)) ^ k` where `m` is the number of bits and `k` the number of hashes.
+    pub fn estimated_fpr(&self, num_keys: usize) -> f64 {
+        if self.k > 30 {
+            return 1.0;
+        }
+        let nbits = self.filter.bit_len() as f64;
+        let k = self.k as f64;
+        (1.0 - (-k * num_keys as f64 / nbits).exp()).powf(k)
+    }
 }
@@ -15,28 +15,120 @@
 // #![allow(unused_variables)] // TODO(you): remove this lint after implementing this mod
 // #![allow(dead_code)] // TODO(you): remove this lint after implementing this mod
 
-use std::sync::Arc;
+use std::collections::BTreeMap;
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, OnceLock};
 use std::{mem, path::Path};
 
-use anyhow::Result;
+use anyhow::{Result, anyhow, bail};
 use bytes::{BufMut, Bytes};
 
-use super::{BlockMeta, SsTable};
-use crate::table::FileObject;
+use super::bloom::Bloom;
+use super::compression::{self, CompressionType};
+use super::{
+    BlockIndex, BlockMeta, CacheInvalidator, Footer, PREFIX_EXTRACTOR_PROPERTY, PartitionedIndex,
+    PrefixExtractor, SIZEOF_U32, SST_FORMAT_VERSION, SsTable, TableProperties,
+    TablePropertiesCollector,
+};
+use crate::iterators::StorageIterator;
+use crate::table::{InMemoryWriter, SsTableWriter};
 use crate::{
-    block::BlockBuilder,
-    key::{KeyBytes, KeySlice},
-    lsm_storage::BlockCache,
+    block::{Block, BlockBuilder, BlockRefIterator, MAX_VALUE_LEN},
+    key::{Comparator, KeySlice, KeyVec, TS_DEFAULT, bytewise_comparator},
+    lsm_storage::{BlockCache, CompactionDecision, CompactionFilter},
+    merge_operator::{self, MergeOperator},
+    range_tombstone::{RangeTombstone, RangeTombstones},
+    ttl,
+    vlog::ValueLogSnapshot,
 };
 
+/// Where `SsTableBuilder::add_from_iter_until` ends an SST: once `estimated_size`, i.e., the
+/// finished blocks, reaches the target size, or at a boundary between the SSTs of the grandparent
+/// level once it reaches half of it, so that the SST overlaps fewer SSTs when it is compacted into
+/// that level. An SST always ends before a user key, never between two of its versions.
+#[derive(Debug, Clone)]
+pub struct SstSplitter {
+    target_size: usize,
+    /// The first user keys of the SSTs of the grandparent level, sorted.
+    boundaries: Vec<Bytes>,
+    comparator: Comparator,
+}
+
+impl SstSplitter {
+    pub fn new(target_size: usize) -> Self {
+        Self {
+            target_size,
+            boundaries: Vec::new(),
+            comparator: bytewise_comparator(),
+        }
+    }
+
+    /// Prefer ending the SSTs at `boundaries`, sorted by `comparator`.
+    pub fn with_boundaries(mut self, boundaries: Vec<Bytes>, comparator: Comparator) -> Self {
+        self.boundaries = boundaries;
+        self.comparator = comparator;
+        self
+    }
+
+    /// The number of boundaries at or before `key`.
+    fn boundary_idx(&self, key: &[u8]) -> usize {
+        self.boundaries
+            .partition_point(|boundary| self.comparator.compare(boundary, key).is_le())
+    }
+
+    /// Whether to end an SST of `size` bytes before `key`, where `start` is the boundary index of
+    /// its first key, set at the first key.
+    fn should_split(&self, size: usize, start: &mut Option<usize>, key: &[u8]) -> bool {
+        if size >= self.target_size {
+            return true;
+        }
+        if self.boundaries.is_empty() {
+            return false;
+        }
+        let idx = self.boundary_idx(key);
+        size >= self.target_size / 2 && idx != *start.get_or_insert(idx)
+    }
+}
+
 /// Builds an SSTable from key-value pairs.
 pub struct SsTableBuilder {
     builder: BlockBuilder,
-    first_key: Vec<u8>,
-    last_key: Vec<u8>,
+    first_key: KeyVec,
+    last_key: KeyVec,
     data: Vec<u8>,
     pub(crate) meta: Vec<BlockMeta>,
     block_size: usize,
+    /// Each block's on-disk region is padded to a multiple of this, 0 or 1 for no padding.
+    block_alignment: usize,
+    /// A last block smaller than this many bytes is merged into the previous block, 0 to disable.
+    min_block_fill: usize,
+    /// Store a value equal to the previous entry's value in the block as a back-reference.
+    value_dedup: bool,
+    /// The first entry that could not be added, reported by `build`.
+    error: Option<anyhow::Error>,
+    /// Hashes of all keys added, for the bloom filter.
+    key_hashes: Vec<u32>,
+    /// Bits per key of the bloom filter, derived from a 1% false positive rate if not set.
+    bloom_bits_per_key: Option<usize>,
+    /// The codec data blocks are compressed with.
+    compression: CompressionType,
+    /// Whether the block meta is compressed with `compression` as well.
+    compress_meta: bool,
+    /// The largest timestamp of all keys and range tombstones added.
+    max_ts: u64,
+    /// Range tombstones, stored in a block of their own after the block meta.
+    range_tombstones: Vec<RangeTombstone>,
+    /// Number of block metas per index block, `None` to keep all block metas in the meta section.
+    index_partition_size: Option<usize>,
+    /// Folds the merge operands in `add_from_iter`, reading separated values from the snapshot.
+    merge_operator: Option<(Arc<dyn MergeOperator>, ValueLogSnapshot)>,
+    /// Statistics of the entries added, written to the properties section.
+    properties: TableProperties,
+    properties_collectors: Vec<Box<dyn TablePropertiesCollector>>,
+    /// Adds the prefixes of the keys to the bloom filter as well.
+    prefix_extractor: Option<Arc<dyn PrefixExtractor>>,
+    /// The prefix last added to the bloom filter, as consecutive keys mostly share it.
+    last_prefix: Option<Vec<u8>>,
 }
 
 impl SsTableBuilder {
@@ -45,49 +137,489 @@ impl SsTableBuilder {
         // unimplemented!()
         SsTableBuilder {
             builder: BlockBuilder::new(block_size),
-            first_key: Vec::new(),
-            last_key: Vec::new(),
+            first_key: KeyVec::new(),
+            last_key: KeyVec::new(),
             data: Vec::new(),
             meta: Vec::new(),
             block_size,
+            block_alignment: 0,
+            min_block_fill: 0,
+            value_dedup: false,
+            error: None,
+            key_hashes: Vec::new(),
+            bloom_bits_per_key: None,
+            compression: CompressionType::None,
+            compress_meta: true,
+            max_ts: 0,
+            range_tombstones: Vec::new(),
+            index_partition_size: None,
+            merge_operator: None,
+            properties: TableProperties::default(),
+            properties_collectors: Vec::new(),
+            prefix_extractor: None,
+            last_prefix: None,
         }
     }
 
+    /// Pad every block, together with its checksum, with zeros to a multiple of `alignment`
+    /// bytes, so that blocks start at aligned offsets, e.g. 4096 for direct I/O.
+    pub fn with_block_alignment(mut self, alignment: usize) -> Self {
+        self.block_alignment = alignment;
+        self
+    }
+
+    /// Store each value that byte-equals the value of the previous entry in the same block as a
+    /// back-reference instead of repeating its bytes. Reads resolve it transparently. Off by
+    /// default.
+    pub fn with_value_dedup(mut self, enabled: bool) -> Self {
+        self.value_dedup = enabled;
+        self.builder = self.new_block_builder();
+        self
+    }
+
+    /// Use `bits_per_key` bits per key for the bloom filter of the SST instead of deriving it from a
+    /// 1% false positive rate. More bits lower the false positive rate at the cost of space.
+    pub fn with_bloom_bits_per_key(mut self, bits_per_key: usize) -> Self {
+        self.bloom_bits_per_key = Some(bits_per_key);
+        self
+    }
+
+    /// Compress every block with `compression` before writing it. A block that does not shrink is
+    /// stored uncompressed. Reads decompress blocks transparently, and the block cache holds the
+    /// decompressed blocks.
+    pub fn with_compression(mut self, compression: CompressionType) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Whether to compress the block meta with the codec of the data blocks, on by default. The
+    /// block meta is read once when opening the SST.
+    pub fn with_meta_compression(mut self, enabled: bool) -> Self {
+        self.compress_meta = enabled;
+        self
+    }
+
+    /// Store the block metas in index blocks of `blocks_per_partition` metas each, written after the
+    /// data blocks, and keep only the top-level index of the index blocks in the meta section. An
+    /// opened SST then holds the top-level index in memory and reads the index blocks through the
+    /// block cache, which pays off for SSTs with many blocks.
+    pub fn with_partitioned_index(mut self, blocks_per_partition: usize) -> Self {
+        self.index_partition_size = Some(blocks_per_partition.max(1));
+        self
+    }
+
+    /// Fold the merge operands at or below the watermark in `add_from_iter` with `operator`,
+    /// reading the separated values they are folded onto from `value_logs`. Without it, the
+    /// operands and the version below them are kept as is.
+    pub fn with_merge_operator(
+        mut self,
+        operator: Arc<dyn MergeOperator>,
+        value_logs: ValueLogSnapshot,
+    ) -> Self {
+        self.merge_operator = Some((operator, value_logs));
+        self
+    }
+
+    /// Feed every entry added to `collector`, and record its properties in the properties section.
+    pub fn with_properties_collector(
+        mut self,
+        collector: Box<dyn TablePropertiesCollector>,
+    ) -> Self {
+        self.properties_collectors.push(collector);
+        self
+    }
+
+    /// Add the prefix of every key by `extractor` to the bloom filter, and record the name of the
+    /// extractor in the properties, see `SsTable::may_contain_prefix`.
+    pub fn with_prefix_extractor(mut self, extractor: Arc<dyn PrefixExtractor>) -> Self {
+        self.prefix_extractor = Some(extractor);
+        self
+    }
+
+    fn new_block_builder(&self) -> BlockBuilder {
+        BlockBuilder::new(self.block_size).with_value_dedup(self.value_dedup)
+    }
+
     /// Adds a key-value pair to SSTable.
     ///
     /// Note: You should split a new block when the current block is full.(`std::mem::replace` may
     /// be helpful here)
+    ///
+    /// A value longer than `MAX_VALUE_LEN` bytes is not added, and fails `build` instead.
     pub fn add(&mut self, key: KeySlice, value: &[u8]) {
-        // unimplemented!()
+        if value.len() > MAX_VALUE_LEN {
+            self.error.get_or_insert_with(|| {
+                anyhow!(
+                    "value of {:?} is {} bytes, longer than {} bytes",
+                    key,
+                    value.len(),
+                    MAX_VALUE_LEN
+                )
+            });
+            return;
+        }
+        self.record_entry(key, value);
         if self.builder.add(key, value) {
-            self.last_key = Vec::from(key.raw_ref());
+            self.last_key.set_from_slice(key);
             // if empty
             if self.first_key.is_empty() {
-                self.first_key = self.last_key.clone();
+                self.first_key.set_from_slice(key);
             }
             return;
         }
-        // get the old builder, create a new builder
-        let old_builder = mem::replace(&mut self.builder, BlockBuilder::new(self.block_size));
-        let block = old_builder.build();
-        let encoded_block = block.encode();
+        // full, finish the current block and add again to a new block
+        self.finish_block();
 
-        let offset = self.data.len();
-        self.data.extend_from_slice(&encoded_block);
-        // full ,add a new BlockMeta, then add again
+        let _ = self.builder.add(key, value);
+        self.last_key.set_from_slice(key);
+        self.first_key.set_from_slice(key);
+    }
+
+    /// Record an entry in the bloom filter, the max timestamp and the properties.
+    fn record_entry(&mut self, key: KeySlice, value: &[u8]) {
+        // all versions of a user key share one entry in the bloom filter
+        self.key_hashes.push(farmhash::fingerprint32(key.key_ref()));
+        if let Some(prefix) = self
+            .prefix_extractor
+            .as_ref()
+            .and_then(|extractor| extractor.prefix(key.key_ref()))
+            && self.last_prefix.as_deref() != Some(prefix)
+        {
+            self.key_hashes.push(farmhash::fingerprint32(prefix));
+            self.last_prefix = Some(prefix.to_vec());
+        }
+        self.max_ts = self.max_ts.max(key.ts());
+        self.properties.add(key, value);
+        for collector in &mut self.properties_collectors {
+            collector.add(key, value);
+        }
+    }
 
-        let first_keyb = Bytes::copy_from_slice(&self.first_key);
-        let last_keyb = Bytes::copy_from_slice(&self.last_key);
-        let meta = BlockMeta::new(
+    /// Add a range tombstone. An SST may hold range tombstones only, without any key.
+    pub fn add_range_tombstone(&mut self, tombstone: RangeTombstone) {
+        self.max_ts = self.max_ts.max(tombstone.ts);
+        self.range_tombstones.push(tombstone);
+    }
+
+    /// Add the remaining entries of `iter` and the range tombstones of the inputs, garbage
+    /// collecting what no reader can see any more. Versions newer than `watermark` are all kept, as
+    /// snapshots may still read them. Of the versions at or below the watermark, only the latest one
+    /// of each key is visible to any reader, and the older ones are dropped, as is the latest one if
+    /// a range tombstone at or below the watermark covers it. Tombstones (empty values) and range
+    /// tombstones are written as is if `keep_tombstones` is set, which is required unless the SST
+    /// goes to the bottom level, and the ones at or below the watermark are dropped otherwise. The
+    /// latest live version of each key at or below the watermark becomes a tombstone if it has
+    /// expired, and is otherwise passed through `compaction_filters` in order, which see the user
+    /// value without its expiry. A removed one becomes a tombstone as well. If that version is a
+    /// merge operand, the operands below it down to the first version that is not one are folded
+    /// into a value at its timestamp, which then goes through the filters. Without that version,
+    /// the operands are folded only if `keep_tombstones` is not set, as the older versions may
+    /// still be in the lower levels otherwise, and are kept as is if not folded.
+    pub fn add_from_iter<I>(
+        &mut self,
+        iter: &mut I,
+        keep_tombstones: bool,
+        watermark: u64,
+        range_tombstones: &[RangeTombstone],
+        compaction_filters: &[CompactionFilter],
+    ) -> Result<()>
+    where
+        // `'static` works around the lifetime limitation of higher-ranked bounds on `KeyType`
+        I: for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>> + 'static,
+    {
+        self.add_from_iter_until(
+            iter,
+            keep_tombstones,
+            watermark,
+            range_tombstones,
+            compaction_filters,
+            &SstSplitter::new(usize::MAX),
+        )
+    }
+
+    /// Like `add_from_iter`, but stops before the user key where `splitter` ends the SST, leaving
+    /// the rest of `iter` to the next SST. The range tombstones are only added once `iter` is
+    /// exhausted, i.e., to the last SST.
+    pub fn add_from_iter_until<I>(
+        &mut self,
+        iter: &mut I,
+        keep_tombstones: bool,
+        watermark: u64,
+        range_tombstones: &[RangeTombstone],
+        compaction_filters: &[CompactionFilter],
+        splitter: &SstSplitter,
+    ) -> Result<()>
+    where
+        I: for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>> + 'static,
+    {
+        let expired = RangeTombstones::new(range_tombstones.iter().cloned(), watermark);
+        let now = ttl::now();
+        let mut last_key = Vec::new();
+        // whether a version of `last_key` at or below the watermark has been seen
+        let mut below_watermark = false;
+        let mut start_boundary = None;
+        while iter.is_valid() {
+            let key = iter.key();
+            if key.key_ref() != last_key {
+                if splitter.should_split(self.estimated_size(), &mut start_boundary, key.key_ref())
+                {
+                    return Ok(());
+                }
+                last_key.clear();
+                last_key.extend_from_slice(key.key_ref());
+                below_watermark = false;
+            }
+            if key.ts() > watermark {
+                self.add(key, iter.value());
+            } else if !below_watermark {
+                below_watermark = true;
+                if ttl::is_merge_operand(iter.value()) && !expired.covers(key.key_ref(), key.ts()) {
+                    // moves to the version below the operands
+                    self.add_merged(iter, keep_tombstones, &expired, compaction_filters, now)?;
+                    continue;
+                }
+                let mut value = Some(Bytes::copy_from_slice(iter.value()));
+                if expired.covers(key.key_ref(), key.ts()) {
+                    value = None;
+                } else if ttl::is_expired(iter.value(), now) {
+                    // a tombstone still shadows the older versions in the lower levels
+                    value = Some(Bytes::new());
+                }
+                self.add_filtered(key, value, keep_tombstones, compaction_filters);
+            }
+            iter.next()?;
+        }
+        for tombstone in range_tombstones {
+            if keep_tombstones || tombstone.ts > watermark {
+                self.add_range_tombstone(tombstone.clone());
+            }
+        }
+        Ok(())
+    }
+
+    /// Pass the latest `value` of `key` at or below the watermark, `None` to drop it, through
+    /// `compaction_filters` and add it, see `add_from_iter`.
+    fn add_filtered(
+        &mut self,
+        key: KeySlice,
+        mut value: Option<Bytes>,
+        keep_tombstones: bool,
+        compaction_filters: &[CompactionFilter],
+    ) {
+        for filter in compaction_filters {
+            let Some(current) = value.as_ref().filter(|value| !value.is_empty()) else {
+                break;
+            };
+            let (user_value, expire_at) = ttl::decode_value(current);
+            match filter.filter(key, user_value) {
+                CompactionDecision::Keep => {}
+                CompactionDecision::Remove => value = Some(Bytes::new()),
+                CompactionDecision::ChangeValue(new_value) => {
+                    value = Some(ttl::encode_value(&new_value, expire_at).into_owned().into())
+                }
+            }
+        }
+        if let Some(value) = value
+            && (keep_tombstones || !value.is_empty())
+        {
+            self.add(key, &value);
+        }
+    }
+
+    /// Fold the merge operands from the current version of `iter`, the latest one of its key at or
+    /// below the watermark, and add the result, see `add_from_iter`. Leaves `iter` at the version
+    /// below the operands, or at the next key.
+    fn add_merged<I>(
+        &mut self,
+        iter: &mut I,
+        keep_tombstones: bool,
+        expired: &RangeTombstones,
+        compaction_filters: &[CompactionFilter],
+        now: u64,
+    ) -> Result<()>
+    where
+        I: for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>> + 'static,
+    {
+        let latest = iter.key().to_key_vec();
+        let mut operands = Vec::new();
+        // the key and the value of the version below the operands, `None` for a covered one
+        let mut base = None;
+        let mut complete = !keep_tombstones;
+        while iter.is_valid() && iter.key().key_ref() == latest.key_ref() {
+            let key = iter.key();
+            if expired.covers(key.key_ref(), key.ts()) {
+                complete = true;
+                break;
+            }
+            if !ttl::is_merge_operand(iter.value()) {
+                base = Some((key.to_key_vec(), Bytes::copy_from_slice(iter.value())));
+                complete = true;
+                break;
+            }
+            operands.push((key.to_key_vec(), Bytes::copy_from_slice(iter.value())));
+            iter.next()?;
+        }
+        match &self.merge_operator {
+            Some((operator, value_logs)) if complete => {
+                let values = operands
+                    .into_iter()
+                    .map(|(_, value)| value)
+                    .collect::<Vec<_>>();
+                let base = base.map(|(_, value)| value);
+                let merged = merge_operator::fold(
+                    operator,
+                    latest.key_ref(),
+                    base.as_ref(),
+                    &values,
+                    now,
+                    value_logs,
+                )?;
+                let value = ttl::encode_value(&merged, None).into_owned().into();
+                self.add_filtered(
+                    latest.as_key_slice(),
+                    Some(value),
+                    keep_tombstones,
+                    compaction_filters,
+                );
+            }
+            _ => {
+                for (key, value) in operands {
+                    self.add(key.as_key_slice(), &value);
+                }
+                if let Some((key, value)) = base {
+                    self.add(key.as_key_slice(), &value);
+                    iter.next()?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether no key or range tombstone is added.
+    pub fn is_empty(&self) -> bool {
+        self.meta.is_empty() && self.builder.is_empty() && self.range_tombstones.is_empty()
+    }
+
+    /// Encode and compress the current block followed by the codec and a checksum, and record its
+    /// meta.
+    fn finish_block(&mut self) {
+        let new_builder = self.new_block_builder();
+        let old_builder = mem::replace(&mut self.builder, new_builder);
+        self.push_block(&old_builder.build());
+    }
+
+    /// Append a block built elsewhere, whose keys all come after the keys added before, e.g., one
+    /// of the blocks `DescendingSsTableBuilder` builds from the largest keys down.
+    pub(crate) fn add_block(&mut self, block: &Block) {
+        self.split_block();
+        let mut iter = BlockRefIterator::create_and_seek_to_first(block);
+        while iter.is_valid() {
+            self.record_entry(iter.key(), iter.value());
+            if self.first_key.is_empty() {
+                self.first_key.set_from_slice(iter.key());
+            }
+            self.last_key.set_from_slice(iter.key());
+            iter.next();
+        }
+        self.push_block(block);
+    }
+
+    /// Write a block holding the keys from `first_key` to `last_key`.
+    fn push_block(&mut self, block: &Block) {
+        let (offset, len, padded_len) = self.write_block(&block.encode());
+        self.meta.push(BlockMeta::new(
             offset,
-            KeyBytes::from_bytes(first_keyb),
-            KeyBytes::from_bytes(last_keyb),
-        );
-        self.meta.push(meta);
+            len,
+            padded_len,
+            block.offsets.len(),
+            self.first_key.clone().into_key_bytes(),
+            self.last_key.clone().into_key_bytes(),
+        ));
+        self.first_key.clear();
+    }
 
-        let _ = self.builder.add(key, value);
-        self.last_key = Vec::from(key.raw_ref());
-        self.first_key = self.last_key.clone();
+    /// Append an encoded block, compressed and followed by the codec, a checksum and the padding.
+    /// Returns its offset, its length and the length with the padding.
+    fn write_block(&mut self, encoded_block: &[u8]) -> (usize, usize, usize) {
+        let offset = self.data.len();
+        compression::compress_into(self.compression, encoded_block, &mut self.data);
+        self.data.put_u32(crc32fast::hash(&self.data[offset..]));
+        let len = self.data.len() - offset;
+        if self.block_alignment > 1 {
+            self.data
+                .resize(offset + len.next_multiple_of(self.block_alignment), 0);
+        }
+        (offset, len, self.data.len() - offset)
+    }
+
+    /// Write the index blocks if the index is partitioned, and return the index to put in the
+    /// meta section.
+    fn finish_index(&mut self) -> Result<BlockIndex> {
+        let block_meta = mem::take(&mut self.meta);
+        let Some(partition_size) = self.index_partition_size.filter(|_| !block_meta.is_empty())
+        else {
+            return Ok(BlockIndex::Full(block_meta));
+        };
+        let index_blocks = PartitionedIndex::build_index_blocks(&block_meta, partition_size)?;
+        let partitions = index_blocks
+            .iter()
+            .zip(block_meta.chunks(partition_size))
+            .map(|(index_block, metas)| {
+                let (offset, len, padded_len) = self.write_block(&index_block.encode());
+                BlockMeta::new(
+                    offset,
+                    len,
+                    padded_len,
+                    metas.iter().map(|meta| meta.num_entries).sum(),
+                    metas[0].first_key.clone(),
+                    metas[metas.len() - 1].last_key.clone(),
+                )
+            })
+            .collect();
+        Ok(BlockIndex::Partitioned(PartitionedIndex {
+            partitions,
+            partition_size,
+            num_blocks: block_meta.len(),
+        }))
+    }
+
+    /// Finish the current block, if any, so that the next key starts a new block.
+    pub(crate) fn split_block(&mut self) {
+        if !self.builder.is_empty() {
+            self.finish_block();
+        }
+    }
+
+    /// Merge the last block into the previous one on build if its estimated size is below
+    /// `min_fill` bytes, instead of writing a tiny trailing block. The merged block may exceed the
+    /// target block size.
+    pub fn with_min_block_fill(mut self, min_fill: usize) -> Self {
+        self.min_block_fill = min_fill;
+        self
+    }
+
+    /// Reopen the last finished block and append the entries of the current block to it.
+    fn fold_into_previous_block(&mut self) {
+        let prev = self.meta.pop().expect("no previous block");
+        let prev_data = &self.data[prev.offset..prev.offset + prev.len - SIZEOF_U32];
+        let prev_block = Block::decode(
+            &compression::decompress(Bytes::copy_from_slice(prev_data))
+                .expect("decompress a block just compressed"),
+        );
+        // large enough to hold the entries of both blocks
+        let merged_size = prev.len + self.builder.estimated_size();
+        let mut builder =
+            BlockBuilder::from_block(&prev_block, merged_size).with_value_dedup(self.value_dedup);
+        let tail = mem::replace(&mut self.builder, BlockBuilder::new(0)).build();
+        let mut iter = BlockRefIterator::create_and_seek_to_first(&tail);
+        while iter.is_valid() {
+            let added = builder.add(iter.key(), iter.value());
+            debug_assert!(added, "the merged block has room for all entries");
+            iter.next();
+        }
+        self.data.truncate(prev.offset);
+        self.builder = builder;
+        self.first_key = prev.first_key.as_key_slice().to_key_vec();
     }
 
     /// Get the estimated size of the SSTable.
@@ -101,44 +633,103 @@ impl SsTableBuilder {
 
     /// Builds the SSTable and writes it to the given path. Use the `FileObject` structure to manipulate the disk objects.
     pub fn build(
-        #[allow(unused_mut)] mut self,
+        self,
         id: usize,
         block_cache: Option<Arc<BlockCache>>,
         path: impl AsRef<Path>,
     ) -> Result<SsTable> {
-        if !self.builder.is_empty() {
-            let old_builder =
-                std::mem::replace(&mut self.builder, BlockBuilder::new(self.block_size));
-            let block = old_builder.build();
-            let encoded_block = block.encode();
-
-            // 记录这最后一个 Block 的元数据
-            self.meta.push(BlockMeta {
-                offset: self.data.len(),
-                first_key: KeyBytes::from_bytes(Bytes::copy_from_slice(&self.first_key)),
-                last_key: KeyBytes::from_bytes(Bytes::copy_from_slice(&self.last_key)),
-            });
+        self.build_with_writer(id, block_cache, path.as_ref())
+    }
+
+    /// Builds the SSTable without touching the disk. The table is backed by an in-memory buffer.
+    pub fn build_in_memory(
+        self,
+        id: usize,
+        block_cache: Option<Arc<BlockCache>>,
+    ) -> Result<SsTable> {
+        self.build_with_writer(id, block_cache, InMemoryWriter)
+    }
 
-            self.data.extend_from_slice(&encoded_block);
+    /// Builds the SSTable and hands the encoded bytes to `writer`, which decides where they are
+    /// stored.
+    pub fn build_with_writer(
+        mut self,
+        id: usize,
+        block_cache: Option<Arc<BlockCache>>,
+        writer: impl SsTableWriter,
+    ) -> Result<SsTable> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+        if !self.builder.is_empty() {
+            if !self.meta.is_empty() && self.builder.estimated_size() < self.min_block_fill {
+                self.fold_into_previous_block();
+            }
+            self.finish_block();
+        }
+        self.properties.data_size = self.data.len() as u64;
+        self.properties.num_range_tombstones = self.range_tombstones.len() as u64;
+        for collector in &mut self.properties_collectors {
+            self.properties.user_properties.extend(collector.finish());
+        }
+        if let Some(extractor) = &self.prefix_extractor {
+            self.properties.user_properties.insert(
+                PREFIX_EXTRACTOR_PROPERTY.to_string(),
+                extractor.name().as_bytes().to_vec(),
+            );
         }
 
+        let index = self.finish_index()?;
         let meta_offset = self.data.len();
-        BlockMeta::encode_block_meta(&self.meta, &mut self.data); // write meta information to data.
-        self.data.put_u32(meta_offset as u32);
-        let file = FileObject::create(path.as_ref(), self.data)?;
+        let mut meta = Vec::new();
+        index.encode(self.max_ts, &mut meta); // write meta information to data.
+        let meta_compression = if self.compress_meta {
+            self.compression
+        } else {
+            CompressionType::None
+        };
+        compression::compress_into(meta_compression, &meta, &mut self.data);
+        let range_tombstone_offset = self.data.len();
+        RangeTombstone::encode_block(&self.range_tombstones, &mut self.data);
+        let bits_per_key = self
+            .bloom_bits_per_key
+            .unwrap_or_else(|| Bloom::bloom_bits_per_key(self.key_hashes.len(), 0.01));
+        let bloom = Bloom::build_from_key_hashes(&self.key_hashes, bits_per_key);
+        let bloom_offset = self.data.len();
+        bloom.encode(&mut self.data);
+        let properties_offset = self.data.len();
+        self.properties.encode(&mut self.data);
+        Footer {
+            meta_offset: meta_offset as u64,
+            range_tombstone_offset: range_tombstone_offset as u64,
+            bloom_offset: bloom_offset as u64,
+            properties_offset: Some(properties_offset as u64),
+            version: SST_FORMAT_VERSION,
+        }
+        .encode(&mut self.data);
+        let file = writer.write_sst(self.data)?;
 
-        let first_key = &self.meta.first().unwrap().first_key;
-        let last_key = &self.meta.last().unwrap().last_key;
+        let (first_key, last_key) = index.key_range();
+        let (block_meta, partitioned_index) = index.into_parts();
+        let invalidator = CacheInvalidator::new(id, block_cache.as_ref());
         Ok(SsTable {
             file,
-            block_meta: self.meta.clone(),
+            block_meta,
+            partitioned_index,
             block_meta_offset: meta_offset,
             id,
             block_cache,
-            first_key: first_key.clone(),
-            last_key: last_key.clone(),
-            bloom: None,
-            max_ts: 0,
+            first_key,
+            last_key,
+            bloom: Some(bloom),
+            max_ts: self.max_ts,
+            range_tombstones: self.range_tombstones,
+            format_version: SST_FORMAT_VERSION,
+            verify_on_read: AtomicBool::new(true),
+            num_entries: OnceLock::from(self.properties.num_entries as usize),
+            properties: Some(self.properties),
+            comparator: bytewise_comparator(),
+            _invalidator: invalidator,
         })
     }
 
@@ -147,3 +738,45 @@ impl SsTableBuilder {
         self.build(0, None, path)
     }
 }
+
+/// Flush a sorted map of unversioned keys into a new SST at `path`, with the keys at `TS_DEFAULT`.
+/// Empty values are written as is and act as tombstones when the SST is read.
+pub fn flush_memtable(
+    map: &BTreeMap<Bytes, Bytes>,
+    id: usize,
+    block_size: usize,
+    block_cache: Option<Arc<BlockCache>>,
+    path: impl AsRef<Path>,
+) -> Result<SsTable> {
+    if map.is_empty() {
+        bail!("cannot flush an empty memtable into sst {}", id);
+    }
+    let mut builder = SsTableBuilder::new(block_size);
+    for (key, value) in map {
+        builder.add(KeySlice::from_slice(key, TS_DEFAULT), value);
+    }
+    builder.build(id, block_cache, path)
+}
+
+/// Build an SST at `path` from the remaining entries of `iter`, e.g., the merged inputs of a
+/// compaction, with the garbage collection of `SsTableBuilder::add_from_iter`. Returns `None` if
+/// there is no entry left to write.
+pub fn build_from_iter<I>(
+    iter: &mut I,
+    keep_tombstones: bool,
+    watermark: u64,
+    id: usize,
+    block_size: usize,
+    block_cache: Option<Arc<BlockCache>>,
+    path: impl AsRef<Path>,
+) -> Result<Option<SsTable>>
+where
+    I: for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>> + 'static,
+{
+    let mut builder = SsTableBuilder::new(block_size);
+    builder.add_from_iter(iter, keep_tombstones, watermark, &[], &[])?;
+    if builder.is_empty() {
+        return Ok(None);
+    }
+    builder.build(id, block_cache, path).map(Some)
+}
@@ -0,0 +1,91 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use anyhow::{Result, bail};
+use bytes::{BufMut, Bytes};
+
+use crate::error::Error;
+
+/// The codec a block of an SST is compressed with. The codec is recorded in the trailer of every
+/// block, so that blocks compressed differently can be read alike.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CompressionType {
+    #[default]
+    None,
+    Lz4,
+    Zstd,
+}
+
+/// The zstd level used for blocks, favoring speed as blocks are compressed on every flush.
+const ZSTD_LEVEL: i32 = 3;
+
+impl CompressionType {
+    fn to_u8(self) -> u8 {
+        match self {
+            CompressionType::None => 0,
+            CompressionType::Lz4 => 1,
+            CompressionType::Zstd => 2,
+        }
+    }
+
+    fn from_u8(codec: u8) -> Result<Self> {
+        Ok(match codec {
+            0 => CompressionType::None,
+            1 => CompressionType::Lz4,
+            2 => CompressionType::Zstd,
+            _ => bail!(Error::corruption(format!(
+                "unknown compression codec {}",
+                codec
+            ))),
+        })
+    }
+
+    fn compress(self, data: &[u8]) -> Option<Vec<u8>> {
+        match self {
+            CompressionType::None => None,
+            CompressionType::Lz4 => Some(lz4_flex::compress_prepend_size(data)),
+            CompressionType::Zstd => zstd::bulk::compress(data, ZSTD_LEVEL).ok(),
+        }
+    }
+}
+
+/// Append `data` compressed with `compression`, followed by the codec. The data is stored
+/// uncompressed instead if compressing it does not make it smaller.
+pub(crate) fn compress_into(compression: CompressionType, data: &[u8], buf: &mut Vec<u8>) {
+    match compression.compress(data) {
+        Some(compressed) if compressed.len() < data.len() => {
+            buf.put_slice(&compressed);
+            buf.put_u8(compression.to_u8());
+        }
+        _ => {
+            buf.put_slice(data);
+            buf.put_u8(CompressionType::None.to_u8());
+        }
+    }
+}
+
+/// Restore the data written by `compress_into`. Uncompressed data is returned without copying.
+pub(crate) fn decompress(data: Bytes) -> Result<Bytes> {
+    let Some((&codec, payload)) = data.split_last() else {
+        bail!(Error::corruption("missing compression codec"));
+    };
+    let decompressed = match CompressionType::from_u8(codec)? {
+        CompressionType::None => return Ok(data.slice(..payload.len())),
+        CompressionType::Lz4 => lz4_flex::decompress_size_prepended(payload)
+            .map_err(|e| Error::corruption(format!("lz4: {}", e)))?,
+        CompressionType::Zstd => zstd::stream::decode_all(payload)
+            .map_err(|e| Error::corruption(format!("zstd: {}", e)))?,
+    };
+    Ok(decompressed.into())
+}
@@ -0,0 +1,135 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{Result, bail};
+
+use super::{SsTable, SsTableBuilder, SsTableWriter};
+use crate::block::{Block, BlockBuilder, MAX_VALUE_LEN, SIZEOF_U16};
+use crate::key::{KeySlice, KeyVec};
+use crate::lsm_storage::BlockCache;
+
+/// Builds an SSTable from key-value pairs given in strictly descending key order.
+///
+/// Entries are grouped into blocks as they arrive, and each block is encoded in ascending order
+/// once it is full. On build, the blocks are written from the smallest keys to the largest, so the
+/// resulting SST has the same layout as one built by `SsTableBuilder` and can be read with
+/// `SsTableIterator`.
+pub struct DescendingSsTableBuilder {
+    /// Entries of the block being filled, in descending order.
+    pending: Vec<(KeyVec, Vec<u8>)>,
+    /// Encoded size of `pending` without prefix compression, which the block cannot exceed.
+    pending_size: usize,
+    /// Finished blocks, each in ascending order. The last block holds the smallest keys.
+    blocks: Vec<Block>,
+    /// The smallest key added so far.
+    last_added: KeyVec,
+    block_size: usize,
+}
+
+impl DescendingSsTableBuilder {
+    /// Create a builder based on target block size.
+    pub fn new(block_size: usize) -> Self {
+        DescendingSsTableBuilder {
+            pending: Vec::new(),
+            pending_size: SIZEOF_U16,
+            blocks: Vec::new(),
+            last_added: KeyVec::new(),
+            block_size,
+        }
+    }
+
+    /// Adds a key-value pair to SSTable. The key must be smaller than all keys added before.
+    pub fn add(&mut self, key: KeySlice, value: &[u8]) -> Result<()> {
+        if !self.is_empty() && key >= self.last_added.as_key_slice() {
+            bail!(
+                "keys must be added in descending order: {:?} after {:?}",
+                key,
+                self.last_added
+            );
+        }
+        if value.len() > MAX_VALUE_LEN {
+            bail!(
+                "value of {:?} is {} bytes, longer than {} bytes",
+                key,
+                value.len(),
+                MAX_VALUE_LEN
+            );
+        }
+        // the key length, the key, the value length, the value and the offset, as `BlockBuilder`
+        // counts an entry without a prefix shared with the previous key
+        let entry_size = SIZEOF_U16 * 2 + key.raw_len() + SIZEOF_U16 + value.len() + SIZEOF_U16;
+        if !self.pending.is_empty() && self.pending_size + entry_size > self.block_size {
+            self.finish_block();
+        }
+        self.pending.push((key.to_key_vec(), value.to_vec()));
+        self.pending_size += entry_size;
+        self.last_added.set_from_slice(key);
+        Ok(())
+    }
+
+    /// Encode the pending entries into a block in ascending order.
+    fn finish_block(&mut self) {
+        let mut builder = BlockBuilder::new(self.block_size);
+        for (key, value) in self.pending.drain(..).rev() {
+            let added = builder.add(key.as_key_slice(), &value);
+            debug_assert!(added, "the block has room for all pending entries");
+        }
+        self.blocks.push(builder.build());
+        self.pending_size = SIZEOF_U16;
+    }
+
+    /// Returns true if no key has been added.
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty() && self.blocks.is_empty()
+    }
+
+    /// Builds the SSTable and writes it to the given path.
+    pub fn build(
+        self,
+        id: usize,
+        block_cache: Option<Arc<BlockCache>>,
+        path: impl AsRef<Path>,
+    ) -> Result<SsTable> {
+        self.build_with_writer(id, block_cache, path.as_ref())
+    }
+
+    /// Builds the SSTable and hands the encoded bytes to `writer`, see
+    /// `SsTableBuilder::build_with_writer`.
+    pub fn build_with_writer(
+        mut self,
+        id: usize,
+        block_cache: Option<Arc<BlockCache>>,
+        writer: impl SsTableWriter,
+    ) -> Result<SsTable> {
+        if self.is_empty() {
+            bail!("cannot build an empty sst {}", id);
+        }
+        if !self.pending.is_empty() {
+            self.finish_block();
+        }
+        let mut builder = SsTableBuilder::new(self.block_size);
+        for block in self.blocks.iter().rev() {
+            builder.add_block(block);
+        }
+        builder.build_with_writer(id, block_cache, writer)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn build_for_test(self, path: impl AsRef<Path>) -> Result<SsTable> {
+        self.build(0, None, path)
+    }
+}
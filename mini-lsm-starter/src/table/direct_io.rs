@@ -0,0 +1,134 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Reading and writing SST files with `O_DIRECT`, bypassing the page cache. The offsets, the
+//! lengths and the memory of the buffers of direct I/O must all be aligned to the logical block
+//! size of the device, so reads are widened to `DIRECT_IO_ALIGNMENT` and writes are padded to it.
+
+use std::alloc::{self, Layout};
+use std::fs::File;
+use std::io;
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+
+use crate::rate_limiter::{IoPriority, RateLimiter};
+
+/// The alignment of direct I/O, which covers the logical block sizes of common devices.
+pub const DIRECT_IO_ALIGNMENT: usize = 4096;
+
+/// A zeroed buffer whose memory and length are aligned to `DIRECT_IO_ALIGNMENT`.
+struct AlignedBuffer {
+    ptr: *mut u8,
+    len: usize,
+}
+
+impl AlignedBuffer {
+    /// A buffer of at least `len` bytes.
+    fn new(len: usize) -> Self {
+        let len = align_up(len.max(1));
+        let layout = Layout::from_size_align(len, DIRECT_IO_ALIGNMENT).unwrap();
+        let ptr = unsafe { alloc::alloc_zeroed(layout) };
+        if ptr.is_null() {
+            alloc::handle_alloc_error(layout);
+        }
+        Self { ptr, len }
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.ptr, self.len) }
+    }
+}
+
+impl Drop for AlignedBuffer {
+    fn drop(&mut self) {
+        let layout = Layout::from_size_align(self.len, DIRECT_IO_ALIGNMENT).unwrap();
+        unsafe { alloc::dealloc(self.ptr, layout) };
+    }
+}
+
+fn align_down(offset: usize) -> usize {
+    offset / DIRECT_IO_ALIGNMENT * DIRECT_IO_ALIGNMENT
+}
+
+fn align_up(offset: usize) -> usize {
+    offset.div_ceil(DIRECT_IO_ALIGNMENT) * DIRECT_IO_ALIGNMENT
+}
+
+/// Open the file at `path` for direct I/O, creating or truncating it for `write`. Fails if the
+/// platform or the file system does not support direct I/O.
+#[cfg(target_os = "linux")]
+pub(crate) fn open(path: &Path, write: bool) -> io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+    File::options()
+        .read(true)
+        .write(write)
+        .create(write)
+        .truncate(write)
+        .custom_flags(libc::O_DIRECT)
+        .open(path)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn open(_path: &Path, _write: bool) -> io::Result<File> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "direct I/O is only supported on Linux",
+    ))
+}
+
+/// Read `len` bytes at `offset` of a file opened by `open`, through the aligned pages covering
+/// them.
+pub(crate) fn read_at(file: &File, offset: u64, len: u64) -> io::Result<Vec<u8>> {
+    let (offset, len) = (offset as usize, len as usize);
+    let start = align_down(offset);
+    let end = offset + len;
+    let mut buf = AlignedBuffer::new(end - start);
+    let mut read = 0;
+    // the last page of the file is short, where the read stops early
+    while start + read < end {
+        let n = file.read_at(&mut buf.as_mut_slice()[read..], (start + read) as u64)?;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        read += n;
+    }
+    Ok(buf.as_slice()[offset - start..end - start].to_vec())
+}
+
+/// Write `data` to a `file` opened by `open` for writing, and sync it. The last page is padded
+/// with zeros for the write and cut off afterwards. With `rate_limiter`, each piece of at most the
+/// burst size, rounded up to the alignment, is written once the rate limiter grants it.
+pub(crate) fn write_file(
+    file: &File,
+    data: &[u8],
+    rate_limiter: Option<(&RateLimiter, IoPriority)>,
+) -> io::Result<()> {
+    let mut buf = AlignedBuffer::new(data.len());
+    buf.as_mut_slice()[..data.len()].copy_from_slice(data);
+    let piece_len = rate_limiter.map_or(buf.len, |(rate_limiter, _)| {
+        align_up(rate_limiter.burst_bytes() as usize)
+    });
+    for (idx, piece) in buf.as_slice().chunks(piece_len).enumerate() {
+        if let Some((rate_limiter, priority)) = rate_limiter {
+            rate_limiter.request(piece.len() as u64, priority);
+        }
+        file.write_all_at(piece, (idx * piece_len) as u64)?;
+    }
+    file.set_len(data.len() as u64)?;
+    file.sync_all()
+}
@@ -0,0 +1,203 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ops::Range;
+
+use anyhow::{Result, bail};
+use bytes::{Buf, BufMut};
+
+use super::FileObject;
+use crate::error::Error;
+
+/// Identifies an SST with a footer. Chosen so that it is unlikely to be the tail of an SST in the
+/// legacy format.
+pub const SST_MAGIC: u64 = 0x4d69_6e69_4c53_4d21;
+
+/// The format written by `SsTableBuilder`.
+pub const SST_FORMAT_VERSION: u32 = 3;
+
+/// Since this version, the meta section starts with the kind of the index, which may be
+/// partitioned. Before it, the meta section holds the metas of all blocks.
+pub(crate) const SST_FORMAT_VERSION_INDEX_KIND: u32 = 2;
+
+/// Since this version, the SST ends with a properties section after the bloom filter, and the
+/// footer records its offset. Before it, the footer is `Footer::SIZE_WITHOUT_PROPERTIES` bytes.
+pub(crate) const SST_FORMAT_VERSION_PROPERTIES: u32 = 3;
+
+/// SSTs without a footer, where each section is followed by its offset as a u32:
+///
+/// `| data blocks | meta | meta_offset | range tombstones | range_tombstone_offset | bloom | bloom_offset |`
+pub const SST_FORMAT_VERSION_LEGACY: u32 = 0;
+
+/// The footer at the end of an SST:
+///
+/// `| meta_offset (u64) | range_tombstone_offset (u64) | bloom_offset (u64) | properties_offset (u64) | version (u32) | checksum (u32) | magic (u64) |`
+///
+/// where the checksum covers the fields before it, and `properties_offset` is only there since
+/// `SST_FORMAT_VERSION_PROPERTIES`. The sections are laid out in the order of the offsets, each
+/// ending where the next one starts, and the last one ends at the footer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Footer {
+    pub meta_offset: u64,
+    pub range_tombstone_offset: u64,
+    pub bloom_offset: u64,
+    /// `None` in the formats without a properties section.
+    pub properties_offset: Option<u64>,
+    pub version: u32,
+}
+
+/// Where the sections of an SST are in the file, regardless of the format version.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct SectionLayout {
+    pub(crate) version: u32,
+    pub(crate) meta: Range<u64>,
+    pub(crate) range_tombstones: Range<u64>,
+    pub(crate) bloom: Range<u64>,
+    /// Empty in the formats without a properties section.
+    pub(crate) properties: Range<u64>,
+}
+
+impl Footer {
+    pub const SIZE: usize = 8 * 4 + 4 + 4 + 8;
+
+    /// Size of the footer in the formats without a properties section.
+    pub const SIZE_WITHOUT_PROPERTIES: usize = 8 * 3 + 4 + 4 + 8;
+
+    /// Size of the footer of an SST in format `version`.
+    pub fn size_of_version(version: u32) -> usize {
+        if version >= SST_FORMAT_VERSION_PROPERTIES {
+            Self::SIZE
+        } else {
+            Self::SIZE_WITHOUT_PROPERTIES
+        }
+    }
+
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        let original_len = buf.len();
+        buf.put_u64(self.meta_offset);
+        buf.put_u64(self.range_tombstone_offset);
+        buf.put_u64(self.bloom_offset);
+        if self.version >= SST_FORMAT_VERSION_PROPERTIES {
+            buf.put_u64(self.properties_offset.unwrap_or_default());
+        }
+        buf.put_u32(self.version);
+        buf.put_u32(crc32fast::hash(&buf[original_len..]));
+        buf.put_u64(SST_MAGIC);
+    }
+
+    /// Decode the footer from the tail of an SST, at least as long as the footer of its version.
+    /// Returns `None` if the magic number is not there, i.e., the SST has no footer.
+    pub fn decode(data: &[u8]) -> Result<Option<Self>> {
+        if data.len() < Self::SIZE_WITHOUT_PROPERTIES
+            || (&data[data.len() - 8..]).get_u64() != SST_MAGIC
+        {
+            return Ok(None);
+        }
+        let version = (&data[data.len() - 16..]).get_u32();
+        if !(1..=SST_FORMAT_VERSION).contains(&version) {
+            bail!(
+                "unsupported sst format version {}, expect at most {}",
+                version,
+                SST_FORMAT_VERSION
+            );
+        }
+        let size = Self::size_of_version(version);
+        if data.len() < size {
+            bail!(Error::corruption(format!(
+                "sst footer truncated: {} bytes",
+                data.len()
+            )));
+        }
+        let footer = &data[data.len() - size..data.len() - 8];
+        let (mut buf, mut checksum) = footer.split_at(size - 12);
+        if checksum.get_u32() != crc32fast::hash(buf) {
+            bail!(Error::corruption("sst footer checksum mismatched"));
+        }
+        Ok(Some(Self {
+            meta_offset: buf.get_u64(),
+            range_tombstone_offset: buf.get_u64(),
+            bloom_offset: buf.get_u64(),
+            properties_offset: (version >= SST_FORMAT_VERSION_PROPERTIES).then(|| buf.get_u64()),
+            version,
+        }))
+    }
+}
+
+impl SectionLayout {
+    /// Locate the sections of an SST from its footer, or from the trailing offsets of the legacy
+    /// format if it has no footer.
+    pub(crate) fn read(file: &FileObject) -> Result<Self> {
+        let size = file.size();
+        let tail_len = size.min(Footer::SIZE as u64);
+        if let Some(footer) = Footer::decode(&file.read(size - tail_len, tail_len)?)? {
+            let end = size - Footer::size_of_version(footer.version) as u64;
+            let properties_offset = footer.properties_offset.unwrap_or(end);
+            if !(footer.meta_offset <= footer.range_tombstone_offset
+                && footer.range_tombstone_offset <= footer.bloom_offset
+                && footer.bloom_offset <= properties_offset
+                && properties_offset <= end)
+            {
+                bail!(Error::corruption(format!(
+                    "sst footer offsets {:?} out of range",
+                    footer
+                )));
+            }
+            return Ok(Self {
+                version: footer.version,
+                meta: footer.meta_offset..footer.range_tombstone_offset,
+                range_tombstones: footer.range_tombstone_offset..footer.bloom_offset,
+                bloom: footer.bloom_offset..properties_offset,
+                properties: properties_offset..end,
+            });
+        }
+        Self::read_legacy(file)
+    }
+
+    fn read_legacy(file: &FileObject) -> Result<Self> {
+        let size = file.size();
+        if size < 12 {
+            bail!(Error::corruption(format!("sst too short: {} bytes", size)));
+        }
+        let read_offset =
+            |at: u64| -> Result<u64> { Ok((&file.read(at, 4)?[..]).get_u32() as u64) };
+        let bloom_offset = read_offset(size - 4)?;
+        if bloom_offset < 8 || bloom_offset > size - 4 {
+            bail!(Error::corruption(format!(
+                "bloom filter offset {} out of range",
+                bloom_offset
+            )));
+        }
+        let range_tombstone_offset = read_offset(bloom_offset - 4)?;
+        if range_tombstone_offset < 4 || range_tombstone_offset > bloom_offset - 4 {
+            bail!(Error::corruption(format!(
+                "range tombstone offset {} out of range",
+                range_tombstone_offset
+            )));
+        }
+        let meta_offset = read_offset(range_tombstone_offset - 4)?;
+        if meta_offset > range_tombstone_offset - 4 {
+            bail!(Error::corruption(format!(
+                "block meta offset {} out of range",
+                meta_offset
+            )));
+        }
+        Ok(Self {
+            version: SST_FORMAT_VERSION_LEGACY,
+            meta: meta_offset..range_tombstone_offset - 4,
+            range_tombstones: range_tombstone_offset..bloom_offset - 4,
+            bloom: bloom_offset..size - 4,
+            properties: size..size,
+        })
+    }
+}
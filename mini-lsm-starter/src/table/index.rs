@@ -0,0 +1,182 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use anyhow::{Result, bail};
+use bytes::{Buf, BufMut};
+
+use super::BlockMeta;
+use crate::block::{Block, BlockBuilder, BlockIterator};
+use crate::error::Error;
+use crate::key::{KeyBytes, KeyComparator, KeySlice};
+
+/// The meta section holds all block metas, see `BlockMeta::encode_block_meta`.
+const INDEX_FULL: u8 = 0;
+/// The meta section holds the top-level index of a partitioned index, see `PartitionedIndex`.
+const INDEX_PARTITIONED: u8 = 1;
+
+/// An index whose block metas are stored in index blocks of `partition_size` entries each, so that
+/// only the top-level index of the index blocks is kept in memory, and the index blocks are read
+/// through the block cache like data blocks.
+///
+/// An index block is a `Block` with an entry for each data block, keyed by its last key:
+///
+/// `| offset (u64) | len (u64) | padded_len (u64) | num_entries (u64) | first_key_len (u16) | first_key | ts (u64) |`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct PartitionedIndex {
+    /// Where the index blocks are, with the first key of their first data block, the last key of
+    /// their last data block and the number of entries of their data blocks.
+    pub(crate) partitions: Vec<BlockMeta>,
+    pub(crate) partition_size: usize,
+    pub(crate) num_blocks: usize,
+}
+
+/// The decoded meta section of an SST.
+pub(crate) enum BlockIndex {
+    Full(Vec<BlockMeta>),
+    Partitioned(PartitionedIndex),
+}
+
+impl PartitionedIndex {
+    /// Build the index block of each group of `partition_size` block metas.
+    pub(crate) fn build_index_blocks(
+        block_meta: &[BlockMeta],
+        partition_size: usize,
+    ) -> Result<Vec<Block>> {
+        block_meta
+            .chunks(partition_size)
+            .map(|metas| {
+                let mut builder = BlockBuilder::new(u16::MAX as usize);
+                for meta in metas {
+                    let mut value = Vec::with_capacity(42 + meta.first_key.key_len());
+                    value.put_u64(meta.offset as u64);
+                    value.put_u64(meta.len as u64);
+                    value.put_u64(meta.padded_len as u64);
+                    value.put_u64(meta.num_entries as u64);
+                    value.put_u16(meta.first_key.key_len() as u16);
+                    value.put_slice(meta.first_key.key_ref());
+                    value.put_u64(meta.first_key.ts());
+                    if !builder.add(meta.last_key.as_key_slice(), &value) {
+                        bail!(
+                            "{} block metas do not fit in an index block, use smaller partitions",
+                            partition_size
+                        );
+                    }
+                }
+                Ok(builder.build())
+            })
+            .collect()
+    }
+
+    /// Decode the block meta at the current entry of an index block.
+    pub(crate) fn decode_entry(iter: &BlockIterator) -> Result<BlockMeta> {
+        let mut buf = iter.value();
+        let offset = buf.try_get_u64()? as usize;
+        let len = buf.try_get_u64()? as usize;
+        let padded_len = buf.try_get_u64()? as usize;
+        let num_entries = buf.try_get_u64()? as usize;
+        let key_len = buf.try_get_u16()? as usize;
+        if buf.remaining() != key_len + 8 {
+            bail!(Error::corruption("index block entry malformed"));
+        }
+        let first_key = buf.copy_to_bytes(key_len);
+        let ts = buf.get_u64();
+        Ok(BlockMeta::new(
+            offset,
+            len,
+            padded_len,
+            num_entries,
+            KeyBytes::from_bytes_with_ts(first_key, ts),
+            iter.key().to_key_vec().into_key_bytes(),
+        ))
+    }
+
+    /// The partition that may hold the first block whose last key >= `key`, or
+    /// `partitions.len()` if all keys are smaller.
+    pub(crate) fn find_partition(&self, key: KeySlice, comparator: &dyn KeyComparator) -> usize {
+        self.partitions.partition_point(|meta| {
+            comparator
+                .compare_keys(meta.last_key.as_key_slice(), key)
+                .is_lt()
+        })
+    }
+}
+
+impl BlockIndex {
+    /// The first and the last key of the SST, both empty for an SST without any key.
+    pub(crate) fn key_range(&self) -> (KeyBytes, KeyBytes) {
+        let metas = match self {
+            BlockIndex::Full(block_meta) => block_meta,
+            BlockIndex::Partitioned(index) => &index.partitions,
+        };
+        match (metas.first(), metas.last()) {
+            (Some(first), Some(last)) => (first.first_key.clone(), last.last_key.clone()),
+            _ => Default::default(),
+        }
+    }
+
+    /// Split into the block metas kept in memory, empty if the index is partitioned, and the
+    /// partitioned index if any.
+    pub(crate) fn into_parts(self) -> (Vec<BlockMeta>, Option<PartitionedIndex>) {
+        match self {
+            BlockIndex::Full(block_meta) => (block_meta, None),
+            BlockIndex::Partitioned(index) => (Vec::new(), Some(index)),
+        }
+    }
+
+    /// Encode the meta section, led by the kind of the index.
+    pub(crate) fn encode(&self, max_ts: u64, buf: &mut Vec<u8>) {
+        match self {
+            BlockIndex::Full(block_meta) => {
+                buf.put_u8(INDEX_FULL);
+                BlockMeta::encode_block_meta(block_meta, max_ts, buf);
+            }
+            BlockIndex::Partitioned(index) => {
+                buf.put_u8(INDEX_PARTITIONED);
+                buf.put_u32(index.num_blocks as u32);
+                buf.put_u32(index.partition_size as u32);
+                BlockMeta::encode_block_meta(&index.partitions, max_ts, buf);
+            }
+        }
+    }
+
+    /// Decode the meta section and the largest timestamp of the SST.
+    pub(crate) fn decode(mut data: &[u8]) -> Result<(Self, u64)> {
+        match data.try_get_u8()? {
+            INDEX_FULL => {
+                let (block_meta, max_ts) = BlockMeta::decode_block_meta(data)?;
+                Ok((BlockIndex::Full(block_meta), max_ts))
+            }
+            INDEX_PARTITIONED => {
+                let num_blocks = data.try_get_u32()? as usize;
+                let partition_size = data.try_get_u32()? as usize;
+                let (partitions, max_ts) = BlockMeta::decode_block_meta(data)?;
+                if partition_size == 0 || partitions.len() != num_blocks.div_ceil(partition_size) {
+                    bail!(Error::corruption(format!(
+                        "{} index partitions of size {} for {} blocks",
+                        partitions.len(),
+                        partition_size,
+                        num_blocks
+                    )));
+                }
+                let index = PartitionedIndex {
+                    partitions,
+                    partition_size,
+                    num_blocks,
+                };
+                Ok((BlockIndex::Partitioned(index), max_ts))
+            }
+            kind => bail!(Error::corruption(format!("unknown index kind {}", kind))),
+        }
+    }
+}
@@ -15,22 +15,50 @@
 // #![allow(unused_variables)] // TODO(you): remove this lint after implementing this mod
 // #![allow(dead_code)] // TODO(you): remove this lint after implementing this mod
 
+use std::ops::Bound;
 use std::sync::Arc;
 
 use anyhow::{Ok, Result};
+use bytes::Bytes;
 
 use super::SsTable;
 use crate::{
-    block::BlockIterator,
-    iterators::StorageIterator,
-    key::{KeyBytes, KeySlice},
+    block::{Block, BlockIterator},
+    error::Error,
+    iterators::{ReversibleIterator, StorageIterator},
+    key::{KeyBytes, KeySlice, TS_RANGE_BEGIN, TS_RANGE_END},
 };
 
+/// Number of consecutive moves to the next block after which the access is taken as sequential.
+const READAHEAD_SEQUENTIAL_BLOCKS: usize = 2;
+
+/// The state of the readahead of an `SsTableIterator`, see `SsTableIterator::with_readahead`.
+struct Readahead {
+    bytes: usize,
+    /// Number of moves to the next block since the last seek.
+    sequential_blocks: usize,
+    /// The blocks before this one are prefetched or read.
+    prefetched_until: usize,
+    /// The block at which to prefetch the next blocks.
+    next_trigger: usize,
+}
+
+impl Readahead {
+    fn reset(&mut self) {
+        self.sequential_blocks = 0;
+        self.prefetched_until = 0;
+        self.next_trigger = 0;
+    }
+}
+
 /// An iterator over the contents of an SSTable.
 pub struct SsTableIterator {
     table: Arc<SsTable>,
     blk_iter: BlockIterator,
     blk_idx: usize,
+    /// Do not resolve values of the entries, see `BlockIterator::create_keys_only_and_seek_to_first`.
+    keys_only: bool,
+    readahead: Option<Readahead>,
 }
 
 impl SsTableIterator {
@@ -43,31 +71,186 @@ impl SsTableIterator {
             table,
             blk_iter,
             blk_idx: 0,
+            keys_only: false,
+            readahead: None,
+        })
+    }
+
+    /// Create a new iterator that only yields keys and seek to the first key in the first data
+    /// block. `value` must not be called on this iterator.
+    pub fn create_keys_only_and_seek_to_first(table: Arc<SsTable>) -> Result<Self> {
+        let block = table.read_block_cached(0)?;
+        let blk_iter = BlockIterator::create_keys_only_and_seek_to_first(block);
+
+        Ok(SsTableIterator {
+            table,
+            blk_iter,
+            blk_idx: 0,
+            keys_only: true,
+            readahead: None,
         })
     }
 
+    /// Create a new iterator and seek to the first key-value pair within the lower bound on user
+    /// keys, i.e., to the latest version of the first user key within the bound.
+    pub fn create_and_seek_to_range(table: Arc<SsTable>, lower: Bound<&[u8]>) -> Result<Self> {
+        match lower {
+            Bound::Included(key) => {
+                Self::create_and_seek_to_key(table, KeySlice::from_slice(key, TS_RANGE_BEGIN))
+            }
+            Bound::Excluded(key) => {
+                let mut iter =
+                    Self::create_and_seek_to_key(table, KeySlice::from_slice(key, TS_RANGE_END))?;
+                while iter.is_valid() && iter.key().key_ref() == key {
+                    iter.next()?;
+                }
+                Ok(iter)
+            }
+            Bound::Unbounded => Self::create_and_seek_to_first(table),
+        }
+    }
+
+    /// The first key of the underlying table, taken from the SST metadata without reading blocks.
+    pub fn table_first_key(&self) -> &KeyBytes {
+        self.table.first_key()
+    }
+
+    /// The last key of the underlying table, taken from the SST metadata without reading blocks.
+    pub fn table_last_key(&self) -> &KeyBytes {
+        self.table.last_key()
+    }
+
+    /// Index of the data block the iterator is currently in, equals the number of blocks once the
+    /// iterator reaches the end.
+    pub fn current_block_index(&self) -> usize {
+        self.blk_idx
+    }
+
+    /// Rough progress of the scan through the table, from 0.0 at the first block to 1.0 at the end.
+    pub fn progress(&self) -> f64 {
+        let num_blocks = self.table.num_of_blocks();
+        if num_blocks == 0 {
+            return 1.0;
+        }
+        (self.blk_idx.min(num_blocks) as f64) / (num_blocks as f64)
+    }
+
+    /// Once the iterator moves through consecutive blocks, prefetch the blocks of the next
+    /// `readahead_bytes` into the block cache in the background, so that a long scan does not wait
+    /// for each block to be read. Seeking starts the detection of sequential access over. Does
+    /// nothing if `readahead_bytes` is 0 or the table has no block cache.
+    pub fn with_readahead(mut self, readahead_bytes: usize) -> Self {
+        self.readahead = (readahead_bytes > 0).then_some(Readahead {
+            bytes: readahead_bytes,
+            sequential_blocks: 0,
+            prefetched_until: 0,
+            next_trigger: 0,
+        });
+        self
+    }
+
+    /// Prefetch the blocks after the current one if the access is sequential. The next blocks are
+    /// prefetched once the iterator is halfway through the prefetched ones.
+    fn readahead(&mut self) -> Result<()> {
+        let Some(readahead) = &mut self.readahead else {
+            return Ok(());
+        };
+        readahead.sequential_blocks += 1;
+        if readahead.sequential_blocks < READAHEAD_SEQUENTIAL_BLOCKS
+            || self.blk_idx < readahead.next_trigger
+        {
+            return Ok(());
+        }
+        let mut end = self.blk_idx + 1;
+        let mut bytes = 0;
+        while end < self.table.num_of_blocks() && bytes < readahead.bytes {
+            bytes += self.table.block_meta_at(end)?.len;
+            end += 1;
+        }
+        let start = (self.blk_idx + 1).max(readahead.prefetched_until);
+        if start < end {
+            self.table.prefetch_blocks(start..end);
+            readahead.prefetched_until = end;
+        }
+        readahead.next_trigger = (self.blk_idx + 1 + end).div_ceil(2);
+        Ok(())
+    }
+
+    /// Start the detection of sequential access over after a seek.
+    fn reset_readahead(&mut self) {
+        if let Some(readahead) = &mut self.readahead {
+            readahead.reset();
+        }
+    }
+
+    /// Turn the iterator into a standard iterator over the remaining entries. The yielded keys and
+    /// values share the buffers of the blocks they are read from instead of being copied.
+    pub fn into_entries(self) -> SsTableEntries {
+        SsTableEntries { iter: Some(self) }
+    }
+
+    fn block_iter_from_first(&self, block: Arc<Block>) -> BlockIterator {
+        if self.keys_only {
+            BlockIterator::create_keys_only_and_seek_to_first(block)
+        } else {
+            BlockIterator::create_and_seek_to_first(block)
+        }
+    }
+
+    fn block_iter_from_key(&self, block: Arc<Block>, key: KeySlice) -> BlockIterator {
+        let comparator = &**self.table.comparator();
+        if self.keys_only {
+            BlockIterator::create_keys_only_and_seek_to_key_by(block, key, comparator)
+        } else {
+            BlockIterator::create_and_seek_to_key_by(block, key, comparator)
+        }
+    }
+
+    /// Move to the first entry of the next data block, or to the end if there is none.
+    fn move_to_next_block(&mut self) -> Result<()> {
+        self.blk_idx += 1;
+        if self.blk_idx < self.table.num_of_blocks() {
+            self.readahead()?;
+            let block = self.table.read_block_cached(self.blk_idx)?;
+            self.blk_iter = self.block_iter_from_first(block);
+        }
+        Ok(())
+    }
+
     /// Seek to the first key-value pair in the first data block.
     pub fn seek_to_first(&mut self) -> Result<()> {
+        self.reset_readahead();
         let block = self.table.read_block_cached(0)?;
-        self.blk_iter = BlockIterator::create_and_seek_to_first(block);
+        self.blk_iter = self.block_iter_from_first(block);
         self.blk_idx = 0;
         Ok(())
     }
 
+    /// Seek to the greatest key that <= `key` (floor seek), stepping back into the previous block
+    /// when the target block starts after `key`. The iterator becomes invalid if all keys are
+    /// greater than `key`. Same as `ReversibleIterator::seek_for_prev`.
+    pub fn seek_to_key_le(&mut self, key: KeySlice) -> Result<()> {
+        self.seek_for_prev(key)
+    }
+
     /// Create a new iterator and seek to the first key-value pair which >= `key`.
     pub fn create_and_seek_to_key(table: Arc<SsTable>, key: KeySlice) -> Result<Self> {
         // 1. 使用修正后的 find_block_idx (逻辑应为: meta.last_key < key)
-        let mut blk_idx = table.find_block_idx(key);
-        let block = table.read_block_cached(blk_idx)?;
-        let mut blk_iter = BlockIterator::create_and_seek_to_key(block, key);
+        let mut blk_idx = table.find_block_idx(key)?;
+        // when `key` is beyond the last key, seeking in the last block yields an invalid iterator
+        let block = table.read_block_cached(blk_idx.min(table.num_of_blocks() - 1))?;
+        let mut blk_iter =
+            BlockIterator::create_and_seek_to_key_by(block, key, &**table.comparator());
         // 2. 检查索引是否越界（即 key 比整个 SST 最大的 key 还要大）
-        if blk_idx >= table.block_meta.len() {
+        if blk_idx >= table.num_of_blocks() {
             // 如果越界，返回一个无效的迭代器（blk_idx 设为越界值，blk_iter 为空或无效）
             // 注意：这里需要确保你有一个能创建“空”BlockIterator 的方法
             return Ok(SsTableIterator {
                 table,
                 blk_iter,
                 blk_idx,
+                keys_only: false,
+                readahead: None,
             });
         }
 
@@ -80,7 +263,7 @@ impl SsTableIterator {
         // 我们必须跳到下一个 Block 的第一个 key
         if !blk_iter.is_valid() {
             blk_idx += 1;
-            if blk_idx < table.block_meta.len() {
+            if blk_idx < table.num_of_blocks() {
                 let next_block = table.read_block_cached(blk_idx)?;
                 blk_iter = BlockIterator::create_and_seek_to_first(next_block);
             }
@@ -90,31 +273,10 @@ impl SsTableIterator {
             table,
             blk_iter,
             blk_idx,
+            keys_only: false,
+            readahead: None,
         })
     }
-
-    /// Seek to the first key-value pair which >= `key`.
-    /// Note: You probably want to review the handout for detailed explanation when implementing
-    /// this function.
-    pub fn seek_to_key(&mut self, key: KeySlice) -> Result<()> {
-        self.blk_idx = self.table.find_block_idx(key);
-        if self.blk_idx >= self.table.block_meta.len() {
-            return Ok(());
-        }
-        let block = self.table.read_block_cached(self.blk_idx)?;
-        self.blk_iter = BlockIterator::create_and_seek_to_key(block, key);
-
-        // 关键：如果在这个 block 里没找到（比如 key 刚好落在两个 block 之间的间隙）
-        // 需要跳转到下一个 block 的开头
-        if !self.blk_iter.is_valid() {
-            self.blk_idx += 1;
-            if self.blk_idx < self.table.block_meta.len() {
-                let block = self.table.read_block_cached(self.blk_idx)?;
-                self.blk_iter = BlockIterator::create_and_seek_to_first(block);
-            }
-        }
-        Ok(())
-    }
 }
 
 impl StorageIterator for SsTableIterator {
@@ -132,26 +294,131 @@ impl StorageIterator for SsTableIterator {
 
     /// Return whether the current block iterator is valid or not.
     fn is_valid(&self) -> bool {
-        self.blk_iter.is_valid() && self.blk_idx < self.table.block_meta.len()
+        self.blk_iter.is_valid() && self.blk_idx < self.table.num_of_blocks()
     }
 
     /// Move to the next `key` in the block.
     /// Note: You may want to check if the current block iterator is valid after the move.
     fn next(&mut self) -> Result<()> {
         if !self.is_valid() {
-            return Err(anyhow::anyhow!("SsTableIter is not valid!"));
+            return Err(Error::IteratorExhausted.into());
         }
         self.blk_iter.next();
+        if !self.blk_iter.is_valid() {
+            self.move_to_next_block()?;
+        }
+        Ok(())
+    }
+
+    /// Skip to the target entry directly within a block, and skip the blocks in between by the
+    /// number of entries in their metas without reading them.
+    fn next_n(&mut self, n: usize) -> Result<usize> {
+        if n == 0 || !self.is_valid() {
+            return Ok(0);
+        }
+        let remaining = self.blk_iter.remaining();
+        if n < remaining {
+            self.blk_iter.seek_to_index(self.blk_iter.index() + n);
+            return Ok(n);
+        }
+        let mut advanced = remaining;
+        let mut target_idx = self.blk_idx + 1;
+        while target_idx < self.table.num_of_blocks() {
+            let num_entries = self.table.block_meta_at(target_idx)?.num_entries;
+            if advanced + num_entries > n {
+                break;
+            }
+            advanced += num_entries;
+            target_idx += 1;
+        }
+        if target_idx > self.blk_idx + 1 {
+            self.reset_readahead();
+        }
+        self.blk_idx = target_idx - 1;
+        self.move_to_next_block()?;
+        if self.is_valid() && advanced < n {
+            self.blk_iter.seek_to_index(n - advanced);
+            advanced = n;
+        }
+        Ok(advanced)
+    }
+
+    /// Seek to the first key-value pair which >= `key`.
+    /// Note: You probably want to review the handout for detailed explanation when implementing
+    /// this function.
+    fn seek_to_key(&mut self, key: KeySlice) -> Result<()> {
+        self.reset_readahead();
+        self.blk_idx = self.table.find_block_idx(key)?;
+        if self.blk_idx >= self.table.num_of_blocks() {
+            return Ok(());
+        }
+        let block = self.table.read_block_cached(self.blk_idx)?;
+        self.blk_iter = self.block_iter_from_key(block, key);
+
+        // 关键：如果在这个 block 里没找到（比如 key 刚好落在两个 block 之间的间隙）
+        // 需要跳转到下一个 block 的开头
         if !self.blk_iter.is_valid() {
             self.blk_idx += 1;
-            if self.blk_idx < self.table.block_meta.len() {
+            if self.blk_idx < self.table.num_of_blocks() {
                 let block = self.table.read_block_cached(self.blk_idx)?;
-                self.blk_iter = BlockIterator::create_and_seek_to_first(block);
-            } else {
-                // no more block
-                return Ok(());
+                self.blk_iter = self.block_iter_from_first(block);
             }
         }
         Ok(())
     }
 }
+
+impl ReversibleIterator for SsTableIterator {
+    /// Move to the previous `key`, stepping into the last key of the previous block if needed.
+    fn prev(&mut self) -> Result<()> {
+        if !self.is_valid() {
+            return Err(Error::IteratorExhausted.into());
+        }
+        self.blk_iter.prev();
+        if !self.blk_iter.is_valid() && self.blk_idx > 0 {
+            self.reset_readahead();
+            self.blk_idx -= 1;
+            let block = self.table.read_block_cached(self.blk_idx)?;
+            self.blk_iter = self.block_iter_from_first(block);
+            self.blk_iter.seek_to_last();
+        }
+        Ok(())
+    }
+
+    /// Seek to the last key-value pair which <= `key`. The iterator is left invalid at the first
+    /// block if all keys are greater than `key`.
+    fn seek_for_prev(&mut self, key: KeySlice) -> Result<()> {
+        self.reset_readahead();
+        let blk_idx = self.table.num_of_blocks_starting_le(key)?;
+        self.blk_idx = blk_idx.saturating_sub(1);
+        let block = self.table.read_block_cached(self.blk_idx)?;
+        self.blk_iter = self.block_iter_from_first(block);
+        if blk_idx == 0 {
+            self.blk_iter.prev();
+        } else {
+            self.blk_iter
+                .seek_for_prev_by(key, &**self.table.comparator());
+        }
+        Ok(())
+    }
+}
+
+/// Yields the entries of an `SsTableIterator` as `(KeyBytes, Bytes)` pairs, which share the block
+/// buffers and stay valid as long as they are held. Stops after returning an error.
+pub struct SsTableEntries {
+    iter: Option<SsTableIterator>,
+}
+
+impl Iterator for SsTableEntries {
+    type Item = Result<(KeyBytes, Bytes)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let iter = self.iter.as_mut().filter(|iter| iter.is_valid())?;
+        let entry = (iter.blk_iter.key_bytes(), iter.blk_iter.value_bytes());
+        if let Err(e) = iter.next() {
+            self.iter = None;
+            return Some(Err(e));
+        }
+        Some(Ok(entry))
+    }
+}
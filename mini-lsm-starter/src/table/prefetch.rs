@@ -0,0 +1,55 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ops::Range;
+use std::sync::mpsc::{self, SyncSender};
+use std::sync::{Arc, OnceLock, Weak};
+
+use super::SsTable;
+
+/// Number of prefetch requests that may wait for the prefetch thread. Further requests are
+/// dropped, as the blocks are read anyway once the scan gets to them.
+const PREFETCH_QUEUE_LEN: usize = 64;
+
+/// A request to load `blocks` of `table` into its block cache. The request does not keep the table
+/// alive, so that nothing is loaded for a table dropped in the meantime, e.g., after a compaction
+/// removed it.
+struct PrefetchRequest {
+    table: Weak<SsTable>,
+    blocks: Range<usize>,
+}
+
+/// Queue the prefetch of `blocks` of `table` to the prefetch thread shared by all tables, which is
+/// started by the first request. The request is dropped if the queue is full.
+pub(super) fn prefetch(table: &Arc<SsTable>, blocks: Range<usize>) {
+    static QUEUE: OnceLock<SyncSender<PrefetchRequest>> = OnceLock::new();
+    let queue = QUEUE.get_or_init(|| {
+        let (sender, receiver) = mpsc::sync_channel::<PrefetchRequest>(PREFETCH_QUEUE_LEN);
+        std::thread::Builder::new()
+            .name("sst-prefetch".to_string())
+            .spawn(move || {
+                for request in receiver {
+                    if let Some(table) = request.table.upgrade() {
+                        table.load_blocks(request.blocks);
+                    }
+                }
+            })
+            .expect("failed to spawn the prefetch thread");
+        sender
+    });
+    let _ = queue.try_send(PrefetchRequest {
+        table: Arc::downgrade(table),
+        blocks,
+    });
+}
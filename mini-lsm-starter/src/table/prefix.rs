@@ -0,0 +1,57 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/// The user property recording the name of the `PrefixExtractor` whose prefixes are in the bloom
+/// filter of an SST.
+pub const PREFIX_EXTRACTOR_PROPERTY: &str = "mini-lsm.prefix-extractor";
+
+/// Extracts the prefix of a user key, which `SsTableBuilder::with_prefix_extractor` adds to the
+/// bloom filter next to the whole key, so that a prefix scan can skip the SSTs without the prefix.
+pub trait PrefixExtractor: std::fmt::Debug + Send + Sync {
+    /// Identifies the extractor, recorded in each SST. The prefixes in the bloom filter of an SST
+    /// are only used when it records the name of the extractor of the scan, so changing how the
+    /// prefixes are extracted requires a new name.
+    fn name(&self) -> &str;
+
+    /// The prefix of `key`, or `None` if the key has no prefix. A key starting with a key that
+    /// has a prefix must have the same prefix, as a prefix scan looks up the prefix of the prefix
+    /// it scans.
+    fn prefix<'a>(&self, key: &'a [u8]) -> Option<&'a [u8]>;
+}
+
+/// Takes the first `len` bytes of a key as its prefix. Shorter keys have no prefix.
+#[derive(Debug)]
+pub struct FixedPrefixExtractor {
+    len: usize,
+    name: String,
+}
+
+impl FixedPrefixExtractor {
+    pub fn new(len: usize) -> Self {
+        Self {
+            len,
+            name: format!("fixed:{}", len),
+        }
+    }
+}
+
+impl PrefixExtractor for FixedPrefixExtractor {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn prefix<'a>(&self, key: &'a [u8]) -> Option<&'a [u8]> {
+        key.get(..self.len)
+    }
+}
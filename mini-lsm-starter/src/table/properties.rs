@@ -0,0 +1,151 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeMap;
+
+use anyhow::{Result, bail};
+use bytes::{Buf, BufMut};
+
+use crate::error::Error;
+use crate::key::KeySlice;
+
+/// Statistics of an SST recorded by `SsTableBuilder` in the properties section, together with the
+/// properties of the `TablePropertiesCollector`s of the builder.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TableProperties {
+    /// Number of entries, i.e., versions, including the tombstones.
+    pub num_entries: u64,
+    /// Number of tombstones (empty values).
+    pub num_deletions: u64,
+    pub num_range_tombstones: u64,
+    /// Size of all keys, with their timestamps, before block encoding and compression.
+    pub raw_key_size: u64,
+    /// Size of all stored values before block encoding and compression.
+    pub raw_value_size: u64,
+    /// Size of the data blocks on the disk, see `SsTable::data_size`.
+    pub data_size: u64,
+    /// The properties of the collectors, by name.
+    pub user_properties: BTreeMap<String, Vec<u8>>,
+}
+
+/// Collects user-defined properties of an SST while it is built, e.g., the number of keys with a
+/// prefix, to be read back with `SsTable::properties`. See `SsTableBuilder::with_properties_collector`.
+pub trait TablePropertiesCollector: Send {
+    /// Called with each entry added to the SST, in order.
+    fn add(&mut self, key: KeySlice, value: &[u8]);
+
+    /// The properties to record once all entries are added. The properties of all collectors of a
+    /// builder share one namespace, and a later collector overwrites a property of the same name.
+    fn finish(&mut self) -> BTreeMap<String, Vec<u8>>;
+}
+
+/// Creates a `TablePropertiesCollector` for each SST the storage writes, see
+/// `LsmStorageOptions::table_properties_collectors`.
+pub trait TablePropertiesCollectorFactory: std::fmt::Debug + Send + Sync {
+    fn create(&self) -> Box<dyn TablePropertiesCollector>;
+}
+
+impl TableProperties {
+    /// The raw size of the keys and values over their size on the disk, 1.0 for an SST without
+    /// data blocks.
+    pub fn compression_ratio(&self) -> f64 {
+        if self.data_size == 0 {
+            return 1.0;
+        }
+        (self.raw_key_size + self.raw_value_size) as f64 / self.data_size as f64
+    }
+
+    /// The tombstones and range tombstones over all entries, including the range tombstones, 0
+    /// for an empty SST.
+    pub fn deleted_fraction(&self) -> f64 {
+        let deletes = self.num_deletions + self.num_range_tombstones;
+        let entries = self.num_entries + self.num_range_tombstones;
+        if entries == 0 {
+            return 0.0;
+        }
+        deletes as f64 / entries as f64
+    }
+
+    /// Record an entry added to the SST.
+    pub(crate) fn add(&mut self, key: KeySlice, value: &[u8]) {
+        self.num_entries += 1;
+        if value.is_empty() {
+            self.num_deletions += 1;
+        }
+        self.raw_key_size += key.raw_len() as u64;
+        self.raw_value_size += value.len() as u64;
+    }
+
+    /// Encode the properties section, followed by a checksum:
+    ///
+    /// `| num_entries | num_deletions | num_range_tombstones | raw_key_size | raw_value_size | data_size | num_user_properties (u32) | (name_len (u16) | name | value_len (u32) | value)* | checksum (u32) |`
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        let original_len = buf.len();
+        buf.put_u64(self.num_entries);
+        buf.put_u64(self.num_deletions);
+        buf.put_u64(self.num_range_tombstones);
+        buf.put_u64(self.raw_key_size);
+        buf.put_u64(self.raw_value_size);
+        buf.put_u64(self.data_size);
+        buf.put_u32(self.user_properties.len() as u32);
+        for (name, value) in &self.user_properties {
+            buf.put_u16(name.len() as u16);
+            buf.put_slice(name.as_bytes());
+            buf.put_u32(value.len() as u32);
+            buf.put_slice(value);
+        }
+        buf.put_u32(crc32fast::hash(&buf[original_len..]));
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        if data.len() < 8 * 6 + 4 + 4 {
+            bail!(Error::corruption(format!(
+                "properties block too short: {} bytes",
+                data.len()
+            )));
+        }
+        let (mut buf, mut checksum) = data.split_at(data.len() - 4);
+        if checksum.get_u32() != crc32fast::hash(buf) {
+            bail!(Error::corruption("properties block checksum mismatched"));
+        }
+        let mut properties = Self {
+            num_entries: buf.get_u64(),
+            num_deletions: buf.get_u64(),
+            num_range_tombstones: buf.get_u64(),
+            raw_key_size: buf.get_u64(),
+            raw_value_size: buf.get_u64(),
+            data_size: buf.get_u64(),
+            user_properties: BTreeMap::new(),
+        };
+        let num_user_properties = buf.get_u32();
+        for _ in 0..num_user_properties {
+            let name_len = buf.try_get_u16()? as usize;
+            if buf.remaining() < name_len {
+                bail!(Error::corruption("properties block truncated"));
+            }
+            let name = String::from_utf8(buf.copy_to_bytes(name_len).to_vec())
+                .map_err(|_| Error::corruption("property name is not utf-8"))?;
+            let value_len = buf.try_get_u32()? as usize;
+            if buf.remaining() < value_len {
+                bail!(Error::corruption("properties block truncated"));
+            }
+            let value = buf.copy_to_bytes(value_len).to_vec();
+            properties.user_properties.insert(name, value);
+        }
+        if buf.has_remaining() {
+            bail!(Error::corruption("properties block has trailing bytes"));
+        }
+        Ok(properties)
+    }
+}
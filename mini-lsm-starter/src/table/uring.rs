@@ -0,0 +1,119 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Batched reads through io_uring, enabled with the `io-uring` feature on Linux. The reads of a
+//! batch are submitted together on a ring of the calling thread, so that the device serves them in
+//! parallel instead of one `pread` after another.
+
+use std::cell::RefCell;
+use std::fs::File;
+use std::io;
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use io_uring::{IoUring, opcode, types};
+
+/// Number of reads in flight on a ring, larger batches are submitted in rounds.
+const QUEUE_DEPTH: usize = 64;
+
+/// Whether creating a ring failed, e.g., on an old kernel or in a sandbox that forbids io_uring.
+static UNAVAILABLE: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    /// The ring of the thread, created by its first batch.
+    static RING: RefCell<Option<IoUring>> = const { RefCell::new(None) };
+}
+
+/// Read the `(offset, len)` ranges of `file`, or `None` if io_uring is unavailable and the caller
+/// should read them with `pread`.
+pub(crate) fn read_batch(file: &File, ranges: &[(u64, u64)]) -> Option<io::Result<Vec<Vec<u8>>>> {
+    if UNAVAILABLE.load(Ordering::Relaxed) {
+        return None;
+    }
+    RING.with(|ring| {
+        let mut ring = ring.borrow_mut();
+        if ring.is_none() {
+            match IoUring::new(QUEUE_DEPTH as u32) {
+                Ok(new_ring) => *ring = Some(new_ring),
+                Err(_) => {
+                    UNAVAILABLE.store(true, Ordering::Relaxed);
+                    return None;
+                }
+            }
+        }
+        let mut bufs = ranges
+            .iter()
+            .map(|&(_, len)| vec![0; len as usize])
+            .collect::<Vec<_>>();
+        for start in (0..ranges.len()).step_by(QUEUE_DEPTH) {
+            let end = (start + QUEUE_DEPTH).min(ranges.len());
+            if let Err(e) = read_round(ring.as_mut().unwrap(), file, ranges, &mut bufs, start..end)
+            {
+                // the kernel may still write into the buffers of the reads in flight, which live
+                // as long as the ring
+                *ring = None;
+                std::mem::forget(bufs);
+                return Some(Err(e));
+            }
+        }
+        Some(Ok(bufs))
+    })
+}
+
+/// Submit the reads of `ranges[round]` into `bufs[round]` and wait for all of them.
+fn read_round(
+    ring: &mut IoUring,
+    file: &File,
+    ranges: &[(u64, u64)],
+    bufs: &mut [Vec<u8>],
+    round: std::ops::Range<usize>,
+) -> io::Result<()> {
+    let fd = types::Fd(file.as_raw_fd());
+    for idx in round.clone() {
+        let buf = &mut bufs[idx];
+        let entry = opcode::Read::new(fd, buf.as_mut_ptr(), buf.len() as u32)
+            .offset(ranges[idx].0)
+            .build()
+            .user_data(idx as u64);
+        // SAFETY: the buffer is neither moved nor dropped until the read completes, and the ring
+        // has room for a round.
+        unsafe { ring.submission().push(&entry) }.map_err(io::Error::other)?;
+    }
+    let mut pending = round.len();
+    while pending > 0 {
+        match ring.submit_and_wait(pending) {
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+        let completed = ring
+            .completion()
+            .map(|cqe| (cqe.user_data() as usize, cqe.result()))
+            .collect::<Vec<_>>();
+        pending -= completed.len();
+        for (idx, result) in completed {
+            if result < 0 {
+                return Err(io::Error::from_raw_os_error(-result));
+            }
+            // a short read is only expected at the end of the file, where `pread` reports it
+            let read = result as usize;
+            let buf = &mut bufs[idx];
+            if read < buf.len() {
+                file.read_exact_at(&mut buf[read..], ranges[idx].0 + read as u64)?;
+            }
+        }
+    }
+    Ok(())
+}
@@ -1,7 +1,56 @@
 //! DO NOT MODIFY -- Mini-LSM tests modules
 //! This file will be automatically rewritten by the copy-test command.
 
+mod approximate_size;
+mod async_read;
+mod backup;
+mod batch_read;
+mod block;
+mod block_cache;
+mod bloom;
+mod change_iterator;
+mod checkpoint;
+mod column_family;
+mod compaction_filter;
+mod compaction_picker;
+mod comparator;
+mod concat_iterator;
+mod crash;
+mod direct_io;
+mod error;
+mod export;
+mod flush;
 mod harness;
+mod history;
+mod ingest;
+mod keys_iterator;
+mod lsm_iterator;
+mod manifest;
+mod manual_compaction;
+mod mem_table;
+mod merge_iterator;
+mod merge_operator;
+mod metrics;
+mod multi_get;
+mod prefix_scan;
+mod range_tombstone;
+mod rate_limiter;
+mod read_only;
+mod readahead;
+mod replication;
+mod scan_cursor;
+mod secondary_index;
+mod snapshot;
+mod sst;
+mod sst_iterator;
+mod sst_splitter;
+mod subcompaction;
+mod table_properties;
+mod ttl;
+mod two_merge_iterator;
+mod txn;
+mod vlog;
+mod wal;
 mod week1_day1;
 mod week1_day2;
 mod week1_day3;
@@ -0,0 +1,155 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ops::Bound;
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use super::harness::key_of;
+use crate::key::{KeySlice, TS_RANGE_BEGIN, TS_RANGE_END};
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+use crate::table::{FileObject, SsTable, SsTableBuilder};
+
+#[test]
+fn test_sst_approximate_range_stats() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    let mut builder = SsTableBuilder::new(128);
+    for idx in 0..1000 {
+        builder.add(
+            KeySlice::for_testing_from_slice_no_ts(&key_of(idx)),
+            b"value",
+        );
+    }
+    let sst = builder.build_for_test(&path).unwrap();
+    assert_eq!(sst.num_entries().unwrap(), 1000);
+    // read from the properties once opened
+    let sst = SsTable::open_for_test(FileObject::open(&path).unwrap()).unwrap();
+    assert_eq!(sst.num_entries().unwrap(), 1000);
+
+    let range = |lower: usize, upper: usize| {
+        sst.approximate_range_stats(
+            KeySlice::from_slice(&key_of(lower), TS_RANGE_BEGIN),
+            KeySlice::from_slice(&key_of(upper), TS_RANGE_END),
+        )
+        .unwrap()
+    };
+    assert_eq!(range(0, 999), (sst.data_size(), 1000));
+    let (size, num_entries) = range(250, 749);
+    let half = sst.data_size() / 2;
+    assert!(size >= half && size <= half + 2 * 128, "size={}", size);
+    assert!(
+        (500..=520).contains(&num_entries),
+        "entries={}",
+        num_entries
+    );
+    // one block
+    let block = sst.block_meta_at(3).unwrap();
+    let (size, _) = sst
+        .approximate_range_stats(
+            block.first_key.as_key_slice(),
+            block.last_key.as_key_slice(),
+        )
+        .unwrap();
+    assert_eq!(size, block.padded_len as u64);
+    // nothing in the range
+    assert_eq!(range(500, 400), (0, 0));
+    assert_eq!(
+        sst.approximate_range_stats(
+            KeySlice::from_slice(b"z", TS_RANGE_BEGIN),
+            KeySlice::from_slice(b"zz", TS_RANGE_END),
+        )
+        .unwrap(),
+        (0, 0)
+    );
+}
+
+#[test]
+fn test_approximate_size() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.block_size = 128;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for idx in 0..1000 {
+        storage.put(&key_of(idx), b"value").unwrap();
+    }
+    // the mem-tables are not included
+    assert_eq!(
+        storage
+            .approximate_size(Bound::Unbounded, Bound::Unbounded)
+            .unwrap(),
+        0
+    );
+    storage.flush().unwrap();
+    for idx in 0..500 {
+        storage.put(&key_of(idx), b"value").unwrap();
+    }
+    storage.flush().unwrap();
+
+    let state = storage.inner.state.read().clone();
+    let data_size = state
+        .sstables
+        .values()
+        .map(|sst| sst.data_size())
+        .sum::<u64>();
+    assert_eq!(
+        storage
+            .approximate_size(Bound::Unbounded, Bound::Unbounded)
+            .unwrap(),
+        data_size
+    );
+    assert_eq!(
+        storage
+            .approximate_num_keys(Bound::Unbounded, Bound::Unbounded)
+            .unwrap(),
+        1500
+    );
+    // both SSTs hold the lower half, only the first one the upper half
+    let lower_half = storage
+        .approximate_num_keys(Bound::Unbounded, Bound::Excluded(&key_of(500)))
+        .unwrap();
+    let upper_half = storage
+        .approximate_num_keys(Bound::Included(&key_of(500)), Bound::Unbounded)
+        .unwrap();
+    assert!((1000..=1030).contains(&lower_half), "{}", lower_half);
+    assert!((500..=530).contains(&upper_half), "{}", upper_half);
+    assert!(
+        storage
+            .approximate_size(Bound::Included(&key_of(500)), Bound::Unbounded)
+            .unwrap()
+            < storage
+                .approximate_size(Bound::Unbounded, Bound::Excluded(&key_of(500)))
+                .unwrap()
+    );
+    assert_eq!(
+        storage
+            .approximate_num_keys(Bound::Excluded(&key_of(999)), Bound::Unbounded)
+            .unwrap(),
+        0
+    );
+    storage.close().unwrap();
+    drop(storage);
+
+    // the same estimates after reopening, with the entries counted from the disk
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert_eq!(
+        storage
+            .approximate_num_keys(Bound::Unbounded, Bound::Unbounded)
+            .unwrap(),
+        1500
+    );
+    let sst = Arc::clone(storage.inner.state.read().sstables.values().next().unwrap());
+    assert!(sst.num_entries.get().is_some());
+}
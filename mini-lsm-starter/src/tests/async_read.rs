@@ -0,0 +1,97 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ops::Bound;
+
+use tempfile::tempdir;
+
+use super::harness::key_of;
+use crate::iterators::async_iterator::AsyncStorageIterator;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+use crate::table::AsyncSsTable;
+
+fn value_of(idx: usize) -> Vec<u8> {
+    format!("value_{:05}", idx).into_bytes()
+}
+
+#[test]
+fn test_async_read() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, LsmStorageOptions::default_for_week1_test()).unwrap();
+    for idx in 0..500 {
+        storage.put(&key_of(idx), &value_of(idx)).unwrap();
+    }
+    storage.force_flush().unwrap();
+    for idx in (0..500).step_by(2) {
+        storage.delete(&key_of(idx)).unwrap();
+    }
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(async {
+        assert_eq!(
+            storage.get_async(&key_of(1)).await.unwrap().unwrap(),
+            value_of(1)
+        );
+        assert_eq!(storage.get_async(&key_of(2)).await.unwrap(), None);
+        assert_eq!(storage.get_async(b"missing").await.unwrap(), None);
+
+        let mut iter = storage
+            .scan_async(Bound::Included(&key_of(100)), Bound::Excluded(&key_of(200)))
+            .await
+            .unwrap();
+        for idx in (101..200).step_by(2) {
+            assert!(iter.is_valid());
+            assert_eq!(iter.key(), key_of(idx));
+            assert_eq!(iter.value(), value_of(idx));
+            iter.next().await.unwrap();
+        }
+        assert!(!iter.is_valid());
+        iter.next().await.unwrap();
+        assert!(!iter.is_valid());
+    });
+}
+
+#[test]
+fn test_async_sst_read_block() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, LsmStorageOptions::default_for_week1_test()).unwrap();
+    for idx in 0..500 {
+        storage.put(&key_of(idx), &value_of(idx)).unwrap();
+    }
+    storage.force_flush().unwrap();
+    storage.clear_cache();
+    let sst = {
+        let state = storage.inner.state.read();
+        state.sstables[&state.l0_sstables[0]].clone()
+    };
+    let sst_id = sst.sst_id();
+    assert!(sst.num_of_blocks() > 1);
+    let table = AsyncSsTable::new(sst.clone());
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(async {
+        for block_idx in 0..sst.num_of_blocks() {
+            let block = table.read_block(block_idx).await.unwrap();
+            assert_eq!(block.data, sst.read_block(block_idx).unwrap().data);
+        }
+        let stats = storage.cache_stats()[&sst_id];
+        assert_eq!(stats.misses, sst.num_of_blocks() as u64);
+        assert_eq!(stats.hits, 0);
+        // cached blocks are returned without a miss
+        table.read_block(0).await.unwrap();
+        let stats = storage.cache_stats()[&sst_id];
+        assert_eq!(stats.misses, sst.num_of_blocks() as u64);
+        assert_eq!(stats.hits, 1);
+    });
+}
@@ -0,0 +1,200 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Bound;
+use std::path::Path;
+
+use tempfile::tempdir;
+
+use super::harness::{key_of, wal_options};
+use crate::backup::BackupEngine;
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+
+fn options() -> LsmStorageOptions {
+    let mut options = wal_options();
+    options.value_threshold = Some(16);
+    options
+}
+
+fn scan_all(storage: &MiniLsm) -> BTreeMap<Vec<u8>, Vec<u8>> {
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    let mut result = BTreeMap::new();
+    while iter.is_valid() {
+        result.insert(iter.key().to_vec(), iter.value().to_vec());
+        iter.next().unwrap();
+    }
+    result
+}
+
+fn file_names(path: &Path) -> BTreeSet<String> {
+    std::fs::read_dir(path)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect()
+}
+
+fn write_round(storage: &MiniLsm, round: usize) {
+    for idx in round * 100..(round + 1) * 100 {
+        // every other value goes to the value log
+        let value = vec![b'a' + (round % 26) as u8; if idx % 2 == 0 { 8 } else { 32 }];
+        storage.put(&key_of(idx), &value).unwrap();
+    }
+    storage.flush().unwrap();
+}
+
+fn restore_and_scan(
+    engine: &BackupEngine,
+    backup_id: u64,
+    target: &Path,
+) -> BTreeMap<Vec<u8>, Vec<u8>> {
+    engine.restore(backup_id, target).unwrap();
+    let restored = MiniLsm::open(target, options()).unwrap();
+    let result = scan_all(&restored);
+    restored.close().unwrap();
+    result
+}
+
+#[test]
+fn test_incremental_backup() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(dir.path().join("db"), options()).unwrap();
+    let engine = BackupEngine::open(dir.path().join("backup")).unwrap();
+    let shared = dir.path().join("backup").join("shared");
+
+    write_round(&storage, 0);
+    let first = engine.create_backup(&storage).unwrap();
+    let expected_first = scan_all(&storage);
+    let first_ssts = file_names(&shared)
+        .into_iter()
+        .filter(|name| name.ends_with(".sst"))
+        .collect::<BTreeSet<_>>();
+    assert!(!first_ssts.is_empty());
+    let first_modified = first_ssts
+        .iter()
+        .map(|name| {
+            std::fs::metadata(shared.join(name))
+                .unwrap()
+                .modified()
+                .unwrap()
+        })
+        .collect::<Vec<_>>();
+
+    write_round(&storage, 1);
+    storage.delete(&key_of(0)).unwrap();
+    let second = engine.create_backup(&storage).unwrap();
+    let expected_second = scan_all(&storage);
+    assert_eq!(second, first + 1);
+    // the SSTs of the first backup are not copied again
+    let second_ssts = file_names(&shared)
+        .into_iter()
+        .filter(|name| name.ends_with(".sst"))
+        .collect::<BTreeSet<_>>();
+    assert!(second_ssts.is_superset(&first_ssts));
+    assert!(second_ssts.len() > first_ssts.len());
+    for (name, modified) in first_ssts.iter().zip(first_modified) {
+        let metadata = std::fs::metadata(shared.join(name)).unwrap();
+        assert_eq!(metadata.modified().unwrap(), modified);
+    }
+    let backups = engine.list_backups().unwrap();
+    assert_eq!(
+        backups.iter().map(|info| info.id).collect::<Vec<_>>(),
+        vec![first, second]
+    );
+    assert!(backups[1].num_files > backups[0].num_files);
+    // no staging directory is left in the storage
+    assert!(
+        file_names(&dir.path().join("db"))
+            .iter()
+            .all(|name| !name.starts_with("backup-"))
+    );
+
+    // the storage goes on after the backups
+    write_round(&storage, 2);
+    storage.force_full_compaction().unwrap();
+    storage.gc_value_log().unwrap();
+    storage.close().unwrap();
+
+    assert_eq!(
+        restore_and_scan(&engine, first, &dir.path().join("restore1")),
+        expected_first
+    );
+    assert_eq!(
+        restore_and_scan(&engine, second, &dir.path().join("restore2")),
+        expected_second
+    );
+    // the target must be empty
+    assert!(engine.restore(first, dir.path().join("restore2")).is_err());
+    assert!(engine.restore(100, dir.path().join("restore3")).is_err());
+}
+
+#[test]
+fn test_backup_retention() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(dir.path().join("db"), options()).unwrap();
+    let engine = BackupEngine::open(dir.path().join("backup")).unwrap();
+    let shared = dir.path().join("backup").join("shared");
+
+    let mut ids = Vec::new();
+    for round in 0..4 {
+        write_round(&storage, round);
+        // after the compaction, the SSTs of the earlier backups are no longer live
+        storage.force_full_compaction().unwrap();
+        ids.push(engine.create_backup(&storage).unwrap());
+    }
+    let expected = scan_all(&storage);
+    storage.close().unwrap();
+    let all_files = file_names(&shared);
+
+    engine.purge_old_backups(2).unwrap();
+    assert_eq!(
+        engine
+            .list_backups()
+            .unwrap()
+            .iter()
+            .map(|info| info.id)
+            .collect::<Vec<_>>(),
+        ids[2..]
+    );
+    let kept_files = file_names(&shared);
+    assert!(kept_files.len() < all_files.len());
+    assert!(kept_files.is_subset(&all_files));
+    assert_eq!(
+        file_names(&dir.path().join("backup").join("private")),
+        ids[2..].iter().map(|id| id.to_string()).collect()
+    );
+
+    engine.delete_backup(ids[2]).unwrap();
+    assert!(engine.delete_backup(ids[2]).is_err());
+    assert_eq!(engine.list_backups().unwrap().len(), 1);
+    assert_eq!(
+        restore_and_scan(&engine, ids[3], &dir.path().join("restore")),
+        expected
+    );
+
+    // the files of an interrupted backup are removed
+    std::fs::write(shared.join("99999_1.sst"), b"").unwrap();
+    std::fs::create_dir(dir.path().join("backup").join("private").join("5")).unwrap();
+    engine.garbage_collect().unwrap();
+    assert!(!shared.join("99999_1.sst").exists());
+    assert!(!dir.path().join("backup").join("private").join("5").exists());
+
+    // the ids follow the latest backup
+    let storage = MiniLsm::open(dir.path().join("db"), options()).unwrap();
+    assert_eq!(engine.create_backup(&storage).unwrap(), ids[3] + 1);
+    engine.purge_old_backups(0).unwrap();
+    assert!(engine.list_backups().unwrap().is_empty());
+    assert!(file_names(&shared).is_empty());
+}
@@ -0,0 +1,88 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use super::harness::key_of;
+use crate::key::KeySlice;
+use crate::lsm_storage::BlockCache;
+use crate::table::{FileObject, SsTableBuilder};
+
+#[test]
+fn test_file_read_batch() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    let data = (0..100000).map(|idx| (idx % 251) as u8).collect::<Vec<_>>();
+    let file = FileObject::create(&path, data.clone()).unwrap();
+    // more ranges than the reads in flight at once
+    let ranges = (0..200)
+        .map(|idx| ((idx * 499) as u64, (idx % 7 + 1) as u64 * 60))
+        .collect::<Vec<_>>();
+    let bufs = file.read_batch(&ranges).unwrap();
+    assert_eq!(bufs.len(), ranges.len());
+    for (&(offset, len), buf) in ranges.iter().zip(&bufs) {
+        assert_eq!(buf, &data[offset as usize..(offset + len) as usize]);
+    }
+    assert!(file.read_batch(&[]).unwrap().is_empty());
+    assert!(file.read_batch(&[(0, 10), (99990, 20)]).is_err());
+
+    let file = FileObject::from_bytes(data.clone());
+    assert_eq!(
+        file.read_batch(&[(5, 5), (0, 1)]).unwrap(),
+        [&data[5..10], &data[0..1]]
+    );
+}
+
+#[test]
+fn test_read_blocks_cached() {
+    let dir = tempdir().unwrap();
+    let cache = Arc::new(BlockCache::new(1024));
+    let mut builder = SsTableBuilder::new(64);
+    for idx in 0..500 {
+        builder.add(
+            KeySlice::for_testing_from_slice_no_ts(&key_of(idx)),
+            format!("value_{:05}", idx).as_bytes(),
+        );
+    }
+    let sst = builder
+        .build(1, Some(cache.clone()), dir.path().join("1.sst"))
+        .unwrap();
+    assert!(sst.num_of_blocks() > 10);
+    sst.read_block_cached(3).unwrap();
+
+    let blocks = sst.read_blocks_cached(&[5, 3, 1, 5]).unwrap();
+    for (block, block_idx) in blocks.iter().zip([5, 3, 1, 5]) {
+        assert_eq!(block.data, sst.read_block(block_idx).unwrap().data);
+    }
+    let stats = cache.cache_stats()[&1];
+    // blocks 1 and 5 are read as one batch, the other two lookups hit
+    assert_eq!((stats.hits, stats.misses), (2, 3));
+    assert!(cache.contains_block(1, 1) && cache.contains_block(1, 5));
+
+    // a batch of keys reads each of their blocks once
+    let keys = [0, 1, 100, 250, 251, 499]
+        .map(key_of)
+        .map(|key| KeySlice::for_testing_from_slice_no_ts(&key).to_key_vec());
+    let lookups = keys
+        .iter()
+        .map(|key| key.as_key_slice())
+        .collect::<Vec<_>>();
+    let versions = sst.get_versions(&lookups).unwrap();
+    for (idx, version) in [0, 1, 100, 250, 251, 499].into_iter().zip(versions) {
+        assert_eq!(version.unwrap().1, format!("value_{:05}", idx).as_bytes());
+    }
+    assert!(sst.read_blocks_cached(&[0, sst.num_of_blocks()]).is_err());
+}
@@ -0,0 +1,201 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use crate::block::{Block, BlockBuilder, BlockIterator, BlockRefIterator};
+use crate::key::KeySlice;
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:03}", idx * 5).into_bytes()
+}
+
+fn value_of(idx: usize) -> Vec<u8> {
+    format!("value_{:010}", idx).into_bytes()
+}
+
+fn generate_block(num_of_keys: usize) -> Block {
+    let mut builder = BlockBuilder::new(10000);
+    for idx in 0..num_of_keys {
+        assert!(builder.add(
+            KeySlice::for_testing_from_slice_no_ts(&key_of(idx)),
+            &value_of(idx)
+        ));
+    }
+    builder.build()
+}
+
+fn collect(block: Block) -> Vec<(Vec<u8>, Vec<u8>)> {
+    let mut iter = BlockIterator::create_and_seek_to_first(Arc::new(block));
+    let mut result = Vec::new();
+    while iter.is_valid() {
+        result.push((
+            iter.key().for_testing_key_ref().to_vec(),
+            iter.value().to_vec(),
+        ));
+        iter.next();
+    }
+    result
+}
+
+#[test]
+fn test_block_decode_shared() {
+    let encoded = generate_block(100).encode();
+    let copied = Block::decode(&encoded);
+    let shared = Block::decode_shared(encoded.clone()).unwrap();
+    // the shared block points into the encoded buffer instead of a copy of it
+    assert_eq!(shared.data.as_ptr(), encoded.as_ptr());
+    assert_ne!(copied.data.as_ptr(), encoded.as_ptr());
+    assert_eq!(shared.offsets, copied.offsets);
+    let expected = (0..100)
+        .map(|x| (key_of(x), value_of(x)))
+        .collect::<Vec<_>>();
+    assert_eq!(collect(copied), expected);
+    assert_eq!(collect(shared), expected);
+}
+
+#[test]
+fn test_block_decode_shared_corrupted() {
+    let encoded = generate_block(10).encode();
+    assert!(Block::decode_shared(encoded.slice(..1)).is_err());
+    // claims more entries than the block can hold
+    let mut data = encoded.to_vec();
+    let len = data.len();
+    data[len - 2..].copy_from_slice(&u16::MAX.to_be_bytes());
+    assert!(Block::decode_shared(data.into()).is_err());
+}
+
+#[test]
+fn test_block_respects_block_size() {
+    for block_size in [64, 100, 256, 4096] {
+        let mut builder = BlockBuilder::new(block_size);
+        let mut idx = 0;
+        while builder.add(
+            KeySlice::for_testing_from_slice_no_ts(&key_of(idx)),
+            &value_of(idx),
+        ) {
+            idx += 1;
+        }
+        assert!(idx > 0);
+        let estimated_size = builder.estimated_size();
+        let encoded = builder.build().encode();
+        assert_eq!(encoded.len(), estimated_size);
+        assert!(encoded.len() <= block_size);
+        // the rejected entry would not have fit
+        assert!(encoded.len() + key_of(idx).len() + value_of(idx).len() + 14 > block_size);
+    }
+}
+
+#[test]
+fn test_block_builder_from_block() {
+    let block = Block::decode(&generate_block(10).encode());
+    let mut builder = BlockBuilder::from_block(&block, 10000);
+    assert!(!builder.is_empty());
+    for idx in 10..20 {
+        assert!(builder.add(
+            KeySlice::for_testing_from_slice_no_ts(&key_of(idx)),
+            &value_of(idx)
+        ));
+    }
+    let expected = (0..20)
+        .map(|idx| (key_of(idx), value_of(idx)))
+        .collect::<Vec<_>>();
+    let appended = builder.build();
+    assert_eq!(appended.encode(), generate_block(20).encode());
+    assert_eq!(collect(appended), expected);
+
+    // entries of the original block are kept even if the new block size is smaller
+    let mut builder = BlockBuilder::from_block(&block, 16);
+    assert!(!builder.add(
+        KeySlice::for_testing_from_slice_no_ts(&key_of(10)),
+        &value_of(10)
+    ));
+    assert_eq!(collect(builder.build()), expected[..10]);
+}
+
+#[test]
+fn test_block_ref_iterator() {
+    let block = generate_block(100);
+    let mut iter = BlockRefIterator::create_and_seek_to_first(&block);
+    let mut result = Vec::new();
+    while iter.is_valid() {
+        result.push((
+            iter.key().for_testing_key_ref().to_vec(),
+            iter.value().to_vec(),
+        ));
+        iter.next();
+    }
+    let key = key_of(42);
+    let iter = BlockRefIterator::create_and_seek_to_key(
+        &block,
+        KeySlice::for_testing_from_slice_no_ts(&key),
+    );
+    assert_eq!(iter.key().for_testing_key_ref(), key);
+    assert_eq!(iter.value(), value_of(42));
+    assert_eq!(result, collect(block));
+}
+
+#[test]
+fn test_block_prefix_compression() {
+    let long_key =
+        |idx: usize| format!("/tenant/0042/table/orders/row/{:05}", idx * 2).into_bytes();
+    let num_of_keys = 100;
+    let mut builder = BlockBuilder::new(10000);
+    for idx in 0..num_of_keys {
+        assert!(builder.add(KeySlice::for_testing_from_slice_no_ts(&long_key(idx)), b"v"));
+    }
+    let block = Arc::new(builder.build());
+    let uncompressed = num_of_keys * (long_key(0).len() + 1 + 4);
+    assert!(block.data.len() * 2 < uncompressed);
+    assert!(Block::decode_shared(block.encode()).is_ok());
+
+    let mut iter = BlockIterator::create_and_seek_to_first(block.clone());
+    for idx in 0..num_of_keys {
+        assert_eq!(iter.key().for_testing_key_ref(), long_key(idx));
+        iter.next();
+    }
+    assert!(!iter.is_valid());
+
+    // seek to existing keys and into the gaps, within and across restart points
+    for idx in 0..num_of_keys {
+        let key = long_key(idx);
+        iter.seek_to_key(KeySlice::for_testing_from_slice_no_ts(&key));
+        assert_eq!(iter.key().for_testing_key_ref(), key);
+        iter.prev();
+        if idx > 0 {
+            assert_eq!(iter.key().for_testing_key_ref(), long_key(idx - 1));
+        } else {
+            assert!(!iter.is_valid());
+        }
+
+        let mut gap = key.clone();
+        gap.push(0);
+        iter.seek_to_key(KeySlice::for_testing_from_slice_no_ts(&gap));
+        if idx + 1 < num_of_keys {
+            assert_eq!(iter.key().for_testing_key_ref(), long_key(idx + 1));
+        } else {
+            assert!(!iter.is_valid());
+        }
+        iter.seek_for_prev(KeySlice::for_testing_from_slice_no_ts(&gap));
+        assert_eq!(iter.key().for_testing_key_ref(), key);
+    }
+    iter.seek_for_prev(KeySlice::for_testing_from_slice_no_ts(b"/"));
+    assert!(!iter.is_valid());
+
+    // an entry claiming a longer shared prefix than the previous key has is rejected
+    let mut data = block.encode().to_vec();
+    let offset = block.offsets[1] as usize;
+    data[offset..offset + 2].copy_from_slice(&u16::MAX.to_be_bytes());
+    assert!(Block::decode_shared(data.into()).is_err());
+}
@@ -0,0 +1,278 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use super::harness::{build_sst, key_of};
+use crate::block::Block;
+use crate::iterators::StorageIterator;
+use crate::key::KeySlice;
+use crate::lsm_storage::{
+    BlockCache, BlockCachePolicy, CacheStats, LsmStorageInner, LsmStorageOptions,
+};
+use crate::table::{SsTable, SsTableBuilder, SsTableIterator};
+
+fn build_sst_with_prefix(
+    id: usize,
+    cache: Arc<BlockCache>,
+    path: &std::path::Path,
+    value_prefix: &str,
+) -> SsTable {
+    let mut builder = SsTableBuilder::new(64);
+    for idx in 0..50 {
+        let value = format!("{}_{:05}", value_prefix, idx);
+        builder.add(
+            KeySlice::for_testing_from_slice_no_ts(&key_of(idx)),
+            value.as_bytes(),
+        );
+    }
+    builder.build(id, Some(cache), path).unwrap()
+}
+
+#[test]
+fn test_invalidate_sst() {
+    let dir = tempdir().unwrap();
+    let cache = Arc::new(BlockCache::new(1024));
+    let sst1 = build_sst(1, 0..50, Some(cache.clone()), dir.path().join("1.sst"));
+    let sst2 = build_sst(2, 0..50, Some(cache.clone()), dir.path().join("2.sst"));
+    assert!(sst1.num_of_blocks() > 1);
+    for sst in [&sst1, &sst2] {
+        for idx in 0..sst.num_of_blocks() {
+            sst.read_block_cached(idx).unwrap();
+            assert!(cache.contains_block(sst.sst_id(), idx));
+        }
+    }
+    cache.invalidate_sst(1);
+    for idx in 0..sst1.num_of_blocks() {
+        assert!(!cache.contains_block(1, idx));
+    }
+    for idx in 0..sst2.num_of_blocks() {
+        assert!(cache.contains_block(2, idx));
+    }
+    // blocks of the invalidated SST can still be loaded again
+    sst1.read_block_cached(0).unwrap();
+    assert!(cache.contains_block(1, 0));
+}
+
+#[test]
+fn test_clear_cache() {
+    let dir = tempdir().unwrap();
+    let cache = Arc::new(BlockCache::new(1024));
+    let sst = build_sst(1, 0..50, Some(cache.clone()), dir.path().join("1.sst"));
+    sst.read_block_cached(0).unwrap();
+    assert!(cache.contains_block(1, 0));
+    cache.clear();
+    assert!(!cache.contains_block(1, 0));
+}
+
+#[test]
+fn test_drop_sst_invalidates_blocks() {
+    let dir = tempdir().unwrap();
+    let cache = Arc::new(BlockCache::new(1024));
+    let sst1 = build_sst(1, 0..50, Some(cache.clone()), dir.path().join("1.sst"));
+    let sst2 = build_sst(2, 0..50, Some(cache.clone()), dir.path().join("2.sst"));
+    sst1.read_block_cached(0).unwrap();
+    sst2.read_block_cached(0).unwrap();
+    drop(sst1);
+    assert!(!cache.contains_block(1, 0));
+    assert!(cache.contains_block(2, 0));
+}
+
+#[test]
+fn test_shared_cache_namespaces() {
+    let dir = tempdir().unwrap();
+    let cache = BlockCache::new(1024);
+    let cache1 = Arc::new(cache.with_namespace(1));
+    let cache2 = Arc::new(cache.with_namespace(2));
+    // two stores using the same SST id
+    let sst1 = Arc::new(build_sst_with_prefix(
+        1,
+        cache1.clone(),
+        &dir.path().join("store1.sst"),
+        "store1",
+    ));
+    let sst2 = Arc::new(build_sst_with_prefix(
+        1,
+        cache2.clone(),
+        &dir.path().join("store2.sst"),
+        "store2",
+    ));
+    for _ in 0..2 {
+        for (sst, prefix) in [(&sst1, "store1"), (&sst2, "store2")] {
+            let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+            let mut idx = 0;
+            while iter.is_valid() {
+                assert_eq!(iter.value(), format!("{}_{:05}", prefix, idx).as_bytes());
+                iter.next().unwrap();
+                idx += 1;
+            }
+            assert_eq!(idx, 50);
+        }
+    }
+    cache1.invalidate_sst(1);
+    assert!(!cache1.contains_block(1, 0));
+    assert!(cache2.contains_block(1, 0));
+    cache2.clear();
+    assert!(!cache2.contains_block(1, 0));
+}
+
+#[test]
+fn test_next_n_skips_blocks() {
+    let dir = tempdir().unwrap();
+    let cache = Arc::new(BlockCache::new(1024));
+    let sst = build_sst(1, 0..50, Some(cache.clone()), dir.path().join("1.sst"));
+    assert!(sst.num_of_blocks() > 4);
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+    assert_eq!(iter.next_n(45).unwrap(), 45);
+    assert_eq!(iter.key().for_testing_key_ref(), b"key_00045");
+    // only the first block and the block holding the target entry are read
+    let target_idx = iter.current_block_index();
+    for idx in 0..sst.num_of_blocks() {
+        assert_eq!(
+            cache.contains_block(1, idx),
+            idx == 0 || idx == target_idx,
+            "block {}",
+            idx
+        );
+    }
+    let num_entries = (0..sst.num_of_blocks())
+        .map(|idx| sst.block_meta_at(idx).unwrap().num_entries)
+        .sum::<usize>();
+    assert_eq!(num_entries, 50);
+}
+
+#[test]
+fn test_corrupted_cache_entry_is_reread() {
+    let dir = tempdir().unwrap();
+    let cache = Arc::new(BlockCache::new(1024));
+    let sst = build_sst(1, 0..50, Some(cache.clone()), dir.path().join("1.sst"));
+    let expected = sst.read_block(1).unwrap().encode();
+    sst.read_block_cached(1).unwrap();
+
+    // an entry pointing past the end of the data
+    let corrupted = Block {
+        data: expected.slice(..8),
+        offsets: vec![0, 4],
+    };
+    cache.insert((1, 1), Arc::new(corrupted));
+    assert_eq!(sst.read_block_cached(1).unwrap().encode(), expected);
+    assert!(cache.contains_block(1, 1));
+
+    // a corrupted entry does not abort a scan
+    cache.insert(
+        (1, 0),
+        Arc::new(Block {
+            data: expected.slice(..1),
+            offsets: vec![0],
+        }),
+    );
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst).unwrap();
+    let mut count = 0;
+    while iter.is_valid() {
+        assert_eq!(iter.key().for_testing_key_ref(), key_of(count));
+        iter.next().unwrap();
+        count += 1;
+    }
+    assert_eq!(count, 50);
+}
+
+fn empty_block() -> Arc<Block> {
+    Arc::new(Block {
+        data: Bytes::new(),
+        offsets: vec![],
+    })
+}
+
+/// Fill a cache of 2 blocks with blocks `1` and `2`, look them up in `hits`, then insert block `3`
+/// and return the block evicted.
+fn evicted_by(policy: BlockCachePolicy, hits: &[usize]) -> usize {
+    let cache = BlockCache::with_policy(2, policy);
+    cache.insert((1, 0), empty_block());
+    cache.insert((2, 0), empty_block());
+    for &sst_id in hits {
+        cache
+            .try_get_with((sst_id, 0), || panic!("block {sst_id} is cached"))
+            .unwrap();
+    }
+    cache.insert((3, 0), empty_block());
+    assert!(cache.contains_block(3, 0));
+    assert_eq!(cache.entry_count(), 2);
+    let evicted = (1..=2)
+        .filter(|&sst_id| !cache.contains_block(sst_id, 0))
+        .collect::<Vec<_>>();
+    assert_eq!(evicted.len(), 1);
+    evicted[0]
+}
+
+#[test]
+fn test_cache_eviction_policies() {
+    use BlockCachePolicy::{Clock, Lfu, Lru};
+    // block 1 is used more often, block 2 more recently
+    assert_eq!(evicted_by(Lru, &[1, 1, 2]), 1);
+    assert_eq!(evicted_by(Lfu, &[1, 1, 2]), 2);
+    // both blocks get a second chance, and then the hand is back at block 1
+    assert_eq!(evicted_by(Clock, &[1, 1, 2]), 1);
+    // block 1 is used more recently, and the hand passes it first
+    assert_eq!(evicted_by(Lru, &[2, 1]), 2);
+    assert_eq!(evicted_by(Lfu, &[2, 1]), 2);
+    assert_eq!(evicted_by(Clock, &[1]), 2);
+}
+
+#[test]
+fn test_cache_stats() {
+    let dir = tempdir().unwrap();
+    let cache = BlockCache::with_policy(2, BlockCachePolicy::Lru);
+    let cache1 = Arc::new(cache.with_namespace(1));
+    let sst1 = build_sst(1, 0..50, Some(cache1.clone()), dir.path().join("1.sst"));
+    let sst2 = build_sst(
+        2,
+        0..50,
+        Some(Arc::new(cache.with_namespace(2))),
+        dir.path().join("2.sst"),
+    );
+    for idx in [0, 1, 0, 2] {
+        sst1.read_block_cached(idx).unwrap();
+    }
+    sst2.read_block_cached(0).unwrap();
+    assert_eq!(
+        cache1.cache_stats()[&1],
+        CacheStats {
+            hits: 1,
+            misses: 3,
+            evictions: 2,
+        }
+    );
+    assert_eq!(cache1.cache_stats().len(), 1);
+    cache1.invalidate_sst(1);
+    assert!(cache1.cache_stats().is_empty());
+    assert_eq!(cache.with_namespace(2).cache_stats()[&2].misses, 1);
+
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.block_cache_capacity = 16;
+    options.block_cache_policy = BlockCachePolicy::Clock;
+    let storage = LsmStorageInner::open(dir.path().join("storage"), options).unwrap();
+    assert_eq!(storage.block_cache.policy(), BlockCachePolicy::Clock);
+    storage.put(b"a", b"1").unwrap();
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    storage.force_flush_next_imm_memtable().unwrap();
+    storage.get(b"a").unwrap();
+    storage.get(b"a").unwrap();
+    let sst_id = storage.state.read().l0_sstables[0];
+    assert_eq!(storage.cache_stats()[&sst_id].hits, 1);
+}
@@ -0,0 +1,52 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::table::bloom::Bloom;
+
+fn key_hash(idx: usize) -> u32 {
+    farmhash::fingerprint32(format!("key_{:010}", idx).as_bytes())
+}
+
+#[test]
+fn test_bloom_no_false_negative() {
+    let hashes = (0..1000).map(key_hash).collect::<Vec<_>>();
+    let bloom = Bloom::build_from_key_hashes(&hashes, Bloom::bloom_bits_per_key(1000, 0.01));
+    for h in hashes {
+        assert!(bloom.may_contain(h));
+    }
+}
+
+#[test]
+fn test_bloom_estimated_fpr() {
+    let num_keys = 10000;
+    let num_probes = 100000;
+    let hashes = (0..num_keys).map(key_hash).collect::<Vec<_>>();
+    for bits_per_key in [2, 4, 10] {
+        let bloom = Bloom::build_from_key_hashes(&hashes, bits_per_key);
+        let estimated = bloom.estimated_fpr(num_keys);
+        let false_positives = (num_keys..num_keys + num_probes)
+            .filter(|idx| bloom.may_contain(key_hash(*idx)))
+            .count();
+        let measured = false_positives as f64 / num_probes as f64;
+        assert!(
+            (measured - estimated).abs() < estimated * 0.2 + 0.002,
+            "bits_per_key={}, estimated={}, measured={}",
+            bits_per_key,
+            estimated,
+            measured
+        );
+        // a filter holding more keys than it was sized for is expected to be worse
+        assert!(bloom.estimated_fpr(num_keys * 4) > estimated);
+    }
+}
@@ -0,0 +1,142 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use super::harness::{key_of, leveled_options};
+use crate::iterators::StorageIterator;
+use crate::lsm_iterator::{ChangeIterator, ChangeKind};
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm, WriteBatchRecord};
+use crate::merge_operator::StringAppendOperator;
+
+fn options() -> LsmStorageOptions {
+    let mut options = leveled_options();
+    options.value_threshold = Some(16);
+    options.target_sst_size = 4096;
+    options
+}
+
+/// The changes as `(key, ts, kind, value)`.
+fn collect(mut iter: ChangeIterator) -> Vec<(Bytes, u64, ChangeKind, Bytes)> {
+    let mut changes = Vec::new();
+    while iter.is_valid() {
+        changes.push((
+            Bytes::copy_from_slice(iter.key().key_ref()),
+            iter.key().ts(),
+            iter.kind(),
+            Bytes::copy_from_slice(iter.value()),
+        ));
+        iter.next().unwrap();
+    }
+    changes
+}
+
+#[test]
+fn test_scan_changes() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(dir.path(), options()).unwrap();
+    for idx in 0..100 {
+        storage.put(&key_of(idx), b"v1").unwrap();
+    }
+    storage.force_flush().unwrap();
+    let since_ts = storage.inner.mvcc().latest_commit_ts();
+
+    // every other value goes to the value log
+    let mut expected = Vec::new();
+    for idx in 50..150 {
+        let value = vec![b'a' + (idx % 2) as u8; if idx % 2 == 0 { 8 } else { 32 }];
+        storage.put(&key_of(idx), &value).unwrap();
+        let ts = storage.inner.mvcc().latest_commit_ts();
+        expected.push((Bytes::from(key_of(idx)), ts, ChangeKind::Put, value.into()));
+    }
+    storage.force_flush().unwrap();
+    for idx in (0..50).step_by(10) {
+        storage.delete(&key_of(idx)).unwrap();
+        let ts = storage.inner.mvcc().latest_commit_ts();
+        expected.push((
+            Bytes::from(key_of(idx)),
+            ts,
+            ChangeKind::Delete,
+            Bytes::new(),
+        ));
+    }
+    storage.delete_range(&key_of(20), &key_of(30)).unwrap();
+    let range_delete_ts = storage.inner.mvcc().latest_commit_ts();
+    expected.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let iter = storage.scan_changes(since_ts).unwrap();
+    assert_eq!(iter.read_ts(), range_delete_ts);
+    assert_eq!(iter.range_deletes().len(), 1);
+    assert_eq!(iter.range_deletes()[0].ts, range_delete_ts);
+    assert_eq!(collect(iter), expected);
+
+    // nothing changed since the last read
+    let iter = storage.scan_changes(range_delete_ts).unwrap();
+    assert!(!iter.is_valid());
+    assert!(iter.range_deletes().is_empty());
+    // all versions since the beginning
+    assert_eq!(
+        collect(storage.scan_changes(0).unwrap()).len(),
+        100 + 100 + 5
+    );
+}
+
+#[test]
+fn test_scan_changes_all_versions() {
+    let dir = tempdir().unwrap();
+    let mut options = options();
+    options.merge_operator = Some(Arc::new(StringAppendOperator::new(b",")));
+    let storage = MiniLsm::open(dir.path(), options).unwrap();
+    storage.put(b"a", b"0").unwrap();
+    storage.put(b"b", b"0").unwrap();
+    // the snapshot keeps the versions after it through compactions
+    let snapshot = storage.new_snapshot();
+    let since_ts = snapshot.read_ts();
+    storage.put(b"a", b"1").unwrap();
+    storage
+        .write_batch(&[
+            WriteBatchRecord::Put(&b"a"[..], &b"2"[..]),
+            WriteBatchRecord::Merge(&b"b"[..], &b"x"[..]),
+        ])
+        .unwrap();
+    storage
+        .put_with_ttl(b"c", b"expiring", Duration::from_secs(3600))
+        .unwrap();
+    storage.force_flush().unwrap();
+    storage.force_full_compaction().unwrap();
+
+    let iter = storage.scan_changes(since_ts).unwrap();
+    let changes = collect(iter);
+    let summary = changes
+        .iter()
+        .map(|(key, ts, kind, value)| (&key[..], *ts - since_ts, *kind, &value[..]))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        summary,
+        vec![
+            (&b"a"[..], 2, ChangeKind::Put, &b"2"[..]),
+            (&b"a"[..], 1, ChangeKind::Put, &b"1"[..]),
+            (&b"b"[..], 2, ChangeKind::Merge, &b"x"[..]),
+            (&b"c"[..], 3, ChangeKind::Put, &b"expiring"[..]),
+        ]
+    );
+    let mut iter = storage.scan_changes(since_ts).unwrap();
+    iter.next_n(3).unwrap();
+    assert!(iter.expire_at().is_some());
+    drop(snapshot);
+}
@@ -0,0 +1,114 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use tempfile::tempdir;
+
+use super::harness::{key_of, wal_options};
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+
+fn options() -> LsmStorageOptions {
+    let mut options = wal_options();
+    options.value_threshold = Some(16);
+    options
+}
+
+fn scan_all(storage: &MiniLsm) -> BTreeMap<Vec<u8>, Vec<u8>> {
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    let mut result = BTreeMap::new();
+    while iter.is_valid() {
+        result.insert(iter.key().to_vec(), iter.value().to_vec());
+        iter.next().unwrap();
+    }
+    result
+}
+
+#[test]
+fn test_checkpoint() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(dir.path().join("db"), options()).unwrap();
+    for idx in 0..200 {
+        // every other value goes to the value log
+        let value = vec![b'a' + (idx % 2) as u8; if idx % 2 == 0 { 8 } else { 32 }];
+        storage.put(&key_of(idx), &value).unwrap();
+        if idx % 50 == 49 {
+            storage.flush().unwrap();
+        }
+    }
+    storage.delete(&key_of(0)).unwrap();
+    storage.delete_range(&key_of(10), &key_of(20)).unwrap();
+    let expected = scan_all(&storage);
+
+    let checkpoint = dir.path().join("checkpoint");
+    storage.create_checkpoint(&checkpoint).unwrap();
+    assert!(storage.create_checkpoint(&checkpoint).is_err());
+
+    // the checkpoint is not affected by the writes, compactions and garbage collection after it
+    for idx in 0..200 {
+        storage.put(&key_of(idx), &[b'z'; 32]).unwrap();
+    }
+    storage.flush().unwrap();
+    storage.force_full_compaction().unwrap();
+    storage.gc_value_log().unwrap();
+    storage.close().unwrap();
+    drop(storage);
+
+    let restored = MiniLsm::open(&checkpoint, options()).unwrap();
+    assert_eq!(scan_all(&restored), expected);
+    restored.put(b"new", b"1").unwrap();
+    restored.close().unwrap();
+    drop(restored);
+    let storage = MiniLsm::open(dir.path().join("db"), options()).unwrap();
+    assert_eq!(storage.get(b"new").unwrap(), None);
+    assert_eq!(storage.get(&key_of(0)).unwrap().unwrap(), &[b'z'; 32][..]);
+}
+
+#[test]
+fn test_checkpoint_concurrent_writes() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(dir.path().join("db"), options()).unwrap();
+    let stop = Arc::new(AtomicBool::new(false));
+    let writer = {
+        let storage = storage.clone();
+        let stop = stop.clone();
+        std::thread::spawn(move || {
+            let mut idx = 0;
+            while !stop.load(Ordering::SeqCst) || idx < 1000 {
+                storage.put(&key_of(idx), &[b'v'; 32]).unwrap();
+                idx += 1;
+            }
+        })
+    };
+    while storage.get(&key_of(500)).unwrap().is_none() {
+        std::thread::yield_now();
+    }
+    let checkpoint = dir.path().join("checkpoint");
+    storage.create_checkpoint(&checkpoint).unwrap();
+    stop.store(true, Ordering::SeqCst);
+    writer.join().unwrap();
+    storage.close().unwrap();
+
+    // the keys are written in order, so the checkpoint holds a prefix of them
+    let restored = MiniLsm::open(&checkpoint, options()).unwrap();
+    let keys = scan_all(&restored).into_keys().collect::<Vec<_>>();
+    assert!(keys.len() > 500);
+    for (idx, key) in keys.iter().enumerate() {
+        assert_eq!(key, &key_of(idx));
+    }
+}
@@ -0,0 +1,293 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::path::Path;
+
+use tempfile::tempdir;
+
+use super::harness::{key_of, wal_options};
+use crate::backup::BackupEngine;
+use crate::compact::{CompactionOptions, LeveledCompactionOptions};
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::{MiniLsm, SyncPolicy};
+use crate::wal::MAX_KEY_LEN;
+
+fn scan_cf(storage: &MiniLsm, cf: &str) -> BTreeMap<Vec<u8>, Vec<u8>> {
+    let mut iter = storage
+        .scan_cf(cf, Bound::Unbounded, Bound::Unbounded)
+        .unwrap();
+    let mut result = BTreeMap::new();
+    while iter.is_valid() {
+        result.insert(iter.key().to_vec(), iter.value().to_vec());
+        iter.next().unwrap();
+    }
+    result
+}
+
+fn files_with_extension(path: &Path, ext: &str) -> usize {
+    std::fs::read_dir(path)
+        .unwrap()
+        .filter(|entry| {
+            entry
+                .as_ref()
+                .unwrap()
+                .path()
+                .extension()
+                .is_some_and(|e| e == ext)
+        })
+        .count()
+}
+
+#[test]
+fn test_column_families() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, wal_options()).unwrap();
+    storage.create_cf("users", wal_options()).unwrap();
+    storage.create_cf("orders", wal_options()).unwrap();
+    assert!(storage.create_cf("users", wal_options()).is_err());
+    assert!(storage.create_cf("default", wal_options()).is_err());
+    assert_eq!(
+        storage.column_family_names(),
+        vec!["default", "orders", "users"]
+    );
+
+    // the same key in each column family
+    storage.put(b"k", b"default").unwrap();
+    storage.put_cf("users", b"k", b"users").unwrap();
+    storage.put_cf("orders", b"k", b"orders").unwrap();
+    storage.put_cf("users", b"u", b"1").unwrap();
+    assert_eq!(storage.get(b"k").unwrap().unwrap(), &b"default"[..]);
+    assert_eq!(
+        storage.get_cf("default", b"k").unwrap().unwrap(),
+        &b"default"[..]
+    );
+    assert_eq!(
+        storage.get_cf("users", b"k").unwrap().unwrap(),
+        &b"users"[..]
+    );
+    assert_eq!(
+        storage.get_cf("orders", b"k").unwrap().unwrap(),
+        &b"orders"[..]
+    );
+    assert_eq!(storage.get(b"u").unwrap(), None);
+    assert_eq!(storage.get_cf("orders", b"u").unwrap(), None);
+    assert!(storage.get_cf("missing", b"k").is_err());
+    assert!(storage.put_cf("missing", b"k", b"1").is_err());
+
+    storage.delete_cf("users", b"k").unwrap();
+    assert_eq!(storage.get_cf("users", b"k").unwrap(), None);
+    assert_eq!(storage.get(b"k").unwrap().unwrap(), &b"default"[..]);
+    assert_eq!(
+        scan_cf(&storage, "users"),
+        BTreeMap::from([(b"u".to_vec(), b"1".to_vec())])
+    );
+
+    // each column family has its own SSTs
+    storage.flush().unwrap();
+    let users = storage.inner.column_families.read()["users"].clone();
+    assert_eq!(users.inner.state.read().l0_sstables.len(), 1);
+    assert_eq!(storage.inner.state.read().l0_sstables.len(), 1);
+    assert_eq!(storage.get_cf("users", b"u").unwrap().unwrap(), &b"1"[..]);
+
+    storage.drop_cf("users").unwrap();
+    assert!(!users.inner.path.exists());
+    assert!(storage.get_cf("users", b"u").is_err());
+    assert!(storage.drop_cf("users").is_err());
+    assert!(storage.drop_cf("default").is_err());
+    assert_eq!(storage.column_family_names(), vec!["default", "orders"]);
+    storage.close().unwrap();
+    drop(storage);
+
+    let storage = MiniLsm::open(&dir, wal_options()).unwrap();
+    assert_eq!(storage.column_family_names(), vec!["default", "orders"]);
+    assert_eq!(
+        storage.get_cf("orders", b"k").unwrap().unwrap(),
+        &b"orders"[..]
+    );
+    assert_eq!(storage.get(b"k").unwrap().unwrap(), &b"default"[..]);
+}
+
+#[test]
+fn test_column_family_shared_wal() {
+    let dir = tempdir().unwrap();
+    let mut options = wal_options();
+    options.sync_policy = SyncPolicy::PerWrite;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    storage.create_cf("cf", options.clone()).unwrap();
+    let cf_path = storage.inner.column_families.read()["cf"]
+        .inner
+        .path
+        .clone();
+    for idx in 0..100 {
+        storage.put_cf("cf", &key_of(idx), b"1").unwrap();
+    }
+    storage.put(b"default", b"1").unwrap();
+    // the writes to the column family are only in the WAL of the storage
+    assert_eq!(files_with_extension(&cf_path, "wal"), 0);
+    assert_eq!(files_with_extension(&cf_path, "sst"), 0);
+    assert_eq!(files_with_extension(dir.path(), "wal"), 1);
+    // no close, as in a crash
+    drop(storage);
+
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    // recovered from the WAL and flushed
+    assert_eq!(files_with_extension(&cf_path, "sst"), 1);
+    assert_eq!(scan_cf(&storage, "cf").len(), 100);
+    assert_eq!(storage.get(b"default").unwrap().unwrap(), &b"1"[..]);
+    for idx in 0..100 {
+        storage.put_cf("cf", &key_of(idx), b"2").unwrap();
+    }
+    // the WAL logging the writes above is removed after flushing the column family
+    storage.put(b"default", b"2").unwrap();
+    storage.flush().unwrap();
+    assert_eq!(files_with_extension(&cf_path, "sst"), 2);
+    storage.put_cf("cf", &key_of(0), b"3").unwrap();
+    drop(storage);
+
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    assert_eq!(
+        storage.get_cf("cf", &key_of(0)).unwrap().unwrap(),
+        &b"3"[..]
+    );
+    assert_eq!(
+        storage.get_cf("cf", &key_of(99)).unwrap().unwrap(),
+        &b"2"[..]
+    );
+    assert_eq!(storage.get(b"default").unwrap().unwrap(), &b"2"[..]);
+
+    // the writes to a dropped column family are not replayed
+    storage.put_cf("cf", &key_of(0), b"4").unwrap();
+    storage.drop_cf("cf").unwrap();
+    drop(storage);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert_eq!(storage.column_family_names(), vec!["default"]);
+    assert!(!cf_path.exists());
+}
+
+#[test]
+fn test_column_family_key_len_limit() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, wal_options()).unwrap();
+    storage.create_cf("cf", wal_options()).unwrap();
+    // a key as long as the column family marker would be replayed as a column family batch
+    let longest_key = vec![b'k'; MAX_KEY_LEN];
+    let too_long_key = vec![b'k'; MAX_KEY_LEN + 1];
+    assert!(storage.put(&too_long_key, b"1").is_err());
+    assert!(storage.put_cf("cf", &too_long_key, b"1").is_err());
+    storage.put(&longest_key, b"default").unwrap();
+    storage.put_cf("cf", &longest_key, b"cf").unwrap();
+    // no close, as in a crash
+    drop(storage);
+
+    let storage = MiniLsm::open(&dir, wal_options()).unwrap();
+    assert_eq!(storage.get(&longest_key).unwrap().unwrap(), &b"default"[..]);
+    assert_eq!(
+        storage.get_cf("cf", &longest_key).unwrap().unwrap(),
+        &b"cf"[..]
+    );
+}
+
+#[test]
+fn test_column_family_options() {
+    let dir = tempdir().unwrap();
+    let mut cf_options = wal_options();
+    cf_options.target_sst_size = 4096;
+    cf_options.memtable_max_entries = Some(100);
+    cf_options.compaction_options = CompactionOptions::Leveled(LeveledCompactionOptions {
+        level_size_multiplier: 2,
+        level0_file_num_compaction_trigger: 2,
+        max_levels: 4,
+        base_level_size_mb: 1,
+    });
+    cf_options.value_threshold = Some(16);
+    let storage = MiniLsm::open(&dir, wal_options()).unwrap();
+    storage.create_cf("cf", cf_options.clone()).unwrap();
+    for idx in 0..1000 {
+        storage.put_cf("cf", &key_of(idx), &[b'v'; 32]).unwrap();
+        storage.put(&key_of(idx), b"1").unwrap();
+    }
+    // the column family is frozen by its own trigger
+    let cf = storage.inner.column_families.read()["cf"].clone();
+    assert!(cf.inner.state.read().imm_memtables.len() >= 9);
+    assert!(storage.inner.state.read().imm_memtables.is_empty());
+
+    storage.flush().unwrap();
+    // compacted by the compaction thread of the storage
+    let mut compacted = false;
+    for _ in 0..100 {
+        if cf.inner.state.read().l0_sstables.len() < 2 {
+            compacted = true;
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    }
+    assert!(compacted);
+    assert!(
+        storage
+            .inner
+            .state
+            .read()
+            .levels
+            .iter()
+            .all(|(_, ssts)| ssts.is_empty())
+    );
+    // the values of the column family are separated
+    assert!(files_with_extension(&cf.inner.path, "vlog") > 0);
+    assert_eq!(files_with_extension(dir.path(), "vlog"), 0);
+    storage.close().unwrap();
+    drop(storage);
+
+    let mut options = wal_options();
+    options
+        .column_family_options
+        .insert("cf".to_string(), cf_options);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    let values = scan_cf(&storage, "cf");
+    assert_eq!(values.len(), 1000);
+    assert!(values.values().all(|value| value == &[b'v'; 32]));
+    assert_eq!(storage.get(&key_of(999)).unwrap().unwrap(), &b"1"[..]);
+}
+
+#[test]
+fn test_column_family_checkpoint_and_backup() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(dir.path().join("db"), wal_options()).unwrap();
+    storage.create_cf("cf", wal_options()).unwrap();
+    storage.put_cf("cf", b"flushed", b"1").unwrap();
+    storage.flush().unwrap();
+    storage.put_cf("cf", b"unflushed", b"1").unwrap();
+    storage.put(b"default", b"1").unwrap();
+
+    let checkpoint = dir.path().join("checkpoint");
+    storage.create_checkpoint(&checkpoint).unwrap();
+    let engine = BackupEngine::open(dir.path().join("backup")).unwrap();
+    let backup_id = engine.create_backup(&storage).unwrap();
+    storage.put_cf("cf", b"after", b"1").unwrap();
+    storage.close().unwrap();
+
+    let restored = dir.path().join("restored");
+    engine.restore(backup_id, &restored).unwrap();
+    for path in [checkpoint, restored] {
+        let storage = MiniLsm::open(&path, wal_options()).unwrap();
+        assert_eq!(storage.column_family_names(), vec!["default", "cf"]);
+        assert_eq!(
+            scan_cf(&storage, "cf").into_keys().collect::<Vec<_>>(),
+            vec![b"flushed".to_vec(), b"unflushed".to_vec()]
+        );
+        assert_eq!(storage.get(b"default").unwrap().unwrap(), &b"1"[..]);
+        storage.close().unwrap();
+    }
+}
@@ -0,0 +1,139 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ops::Bound;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::iterators::StorageIterator;
+use crate::key::KeySlice;
+use crate::lsm_storage::{
+    CompactionDecision, CompactionFilter, CustomCompactionFilter, LsmStorageInner,
+    LsmStorageOptions,
+};
+use crate::mem_table::MemTable;
+use crate::table::{SsTableBuilder, SsTableIterator};
+
+/// Removes the values starting with `expired`, and upper-cases the values starting with `upper`.
+#[derive(Debug)]
+struct ValueFilter;
+
+impl CustomCompactionFilter for ValueFilter {
+    fn filter(&self, _key: KeySlice, value: &[u8]) -> CompactionDecision {
+        if value.starts_with(b"expired") {
+            CompactionDecision::Remove
+        } else if value.starts_with(b"upper") {
+            CompactionDecision::ChangeValue(Bytes::from(value.to_ascii_uppercase()))
+        } else {
+            CompactionDecision::Keep
+        }
+    }
+}
+
+#[test]
+fn test_compaction_filter_decisions() {
+    let prefix = CompactionFilter::Prefix(Bytes::from_static(b"tmp_"));
+    let key = |key: &'static [u8]| KeySlice::for_testing_from_slice_with_ts(key, 1);
+    assert_eq!(
+        prefix.filter(key(b"tmp_1"), b"v"),
+        CompactionDecision::Remove
+    );
+    assert_eq!(prefix.filter(key(b"tm"), b"v"), CompactionDecision::Keep);
+    let custom = CompactionFilter::Custom(Arc::new(ValueFilter));
+    assert_eq!(
+        custom.filter(key(b"a"), b"upper"),
+        CompactionDecision::ChangeValue(Bytes::from_static(b"UPPER"))
+    );
+    assert_eq!(custom.filter(key(b"a"), b"value"), CompactionDecision::Keep);
+}
+
+#[test]
+fn test_add_from_iter_compaction_filters() {
+    let memtable = MemTable::create(0);
+    let put = |key: &[u8], ts: u64, value: &[u8]| {
+        memtable
+            .put(KeySlice::for_testing_from_slice_with_ts(key, ts), value)
+            .unwrap();
+    };
+    put(b"a", 1, b"expired");
+    put(b"b", 1, b"upper");
+    put(b"b", 5, b"expired");
+    put(b"c", 2, b"value");
+    put(b"d", 1, b"");
+    put(b"tmp_1", 1, b"upper");
+    let filters = [
+        CompactionFilter::Prefix(Bytes::from_static(b"tmp_")),
+        CompactionFilter::Custom(Arc::new(ValueFilter)),
+    ];
+
+    let build = |keep_tombstones: bool| {
+        let dir = tempdir().unwrap();
+        let mut iter = memtable.scan(Bound::Unbounded, Bound::Unbounded);
+        let mut builder = SsTableBuilder::new(128);
+        builder
+            .add_from_iter(&mut iter, keep_tombstones, 3, &[], &filters)
+            .unwrap();
+        let sst = Arc::new(builder.build_for_test(dir.path().join("1.sst")).unwrap());
+        let mut iter = SsTableIterator::create_and_seek_to_first(sst).unwrap();
+        let mut versions = Vec::new();
+        while iter.is_valid() {
+            versions.push((
+                String::from_utf8(iter.key().key_ref().to_vec()).unwrap(),
+                iter.key().ts(),
+                String::from_utf8(iter.value().to_vec()).unwrap(),
+            ));
+            iter.next().unwrap();
+        }
+        versions
+    };
+    let versions = |expected: &[(&str, u64, &str)]| {
+        expected
+            .iter()
+            .map(|(key, ts, value)| (key.to_string(), *ts, value.to_string()))
+            .collect::<Vec<_>>()
+    };
+
+    // the versions above the watermark are not filtered, and the removed ones become tombstones
+    assert_eq!(
+        build(true),
+        versions(&[
+            ("a", 1, ""),
+            ("b", 5, "expired"),
+            ("b", 1, "UPPER"),
+            ("c", 2, "value"),
+            ("d", 1, ""),
+            ("tmp_1", 1, "")
+        ])
+    );
+    assert_eq!(
+        build(false),
+        versions(&[("b", 5, "expired"), ("b", 1, "UPPER"), ("c", 2, "value")])
+    );
+}
+
+#[test]
+fn test_storage_add_compaction_filter() {
+    let dir = tempdir().unwrap();
+    let storage =
+        LsmStorageInner::open(dir.path(), LsmStorageOptions::default_for_week1_test()).unwrap();
+    assert!(storage.compaction_filters().is_empty());
+    storage.add_compaction_filter(CompactionFilter::Prefix(Bytes::from_static(b"tmp_")));
+    storage.add_compaction_filter(CompactionFilter::Custom(Arc::new(ValueFilter)));
+    let filters = storage.compaction_filters();
+    assert_eq!(filters.len(), 2);
+    assert!(matches!(&filters[0], CompactionFilter::Prefix(prefix) if prefix == "tmp_"));
+    assert!(matches!(&filters[1], CompactionFilter::Custom(_)));
+}
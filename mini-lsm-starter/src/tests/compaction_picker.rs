@@ -0,0 +1,377 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::compact::{
+    LeveledCompactionController, LeveledCompactionOptions, SimpleLeveledCompactionController,
+    SimpleLeveledCompactionOptions, TieredCompactionController, TieredCompactionOptions,
+    pick_overlapping,
+};
+use crate::key::KeySlice;
+use crate::lsm_storage::LsmStorageState;
+use crate::mem_table::MemTable;
+use crate::table::{SsTable, SsTableBuilder};
+
+fn sst_of(first: &str, last: &str) -> Arc<SsTable> {
+    let mut builder = SsTableBuilder::new(128);
+    builder.add(
+        KeySlice::for_testing_from_slice_no_ts(first.as_bytes()),
+        b"v",
+    );
+    if last != first {
+        builder.add(
+            KeySlice::for_testing_from_slice_no_ts(last.as_bytes()),
+            b"v",
+        );
+    }
+    Arc::new(builder.build_in_memory(0, None).unwrap())
+}
+
+fn level_of(ranges: &[(&str, &str)]) -> Vec<Arc<SsTable>> {
+    ranges
+        .iter()
+        .map(|(first, last)| sst_of(first, last))
+        .collect()
+}
+
+#[test]
+fn test_pick_overlapping_simple() {
+    let level_n = level_of(&[("a", "c"), ("e", "g"), ("m", "p")]);
+    let level_n1 = level_of(&[("a", "b"), ("c", "d"), ("f", "h"), ("i", "l"), ("q", "z")]);
+    assert_eq!(
+        pick_overlapping(&level_n, &level_n1, 0),
+        (vec![0], vec![0, 1])
+    );
+    assert_eq!(pick_overlapping(&level_n, &level_n1, 2), (vec![2], vec![]));
+}
+
+#[test]
+fn test_pick_overlapping_chained_expansion() {
+    // picking [e, g] pulls in [f, k] from level n+1, which pulls in [j, n] from level n, which
+    // pulls in [m, o] from level n+1 again
+    let level_n = level_of(&[("a", "c"), ("e", "g"), ("j", "n"), ("x", "y")]);
+    let level_n1 = level_of(&[("a", "d"), ("f", "k"), ("m", "o"), ("p", "w")]);
+    assert_eq!(
+        pick_overlapping(&level_n, &level_n1, 1),
+        (vec![1, 2], vec![1, 2])
+    );
+    // boundary keys equal across levels count as overlap
+    let level_n = level_of(&[("a", "c"), ("d", "f")]);
+    let level_n1 = level_of(&[("c", "d"), ("g", "h")]);
+    assert_eq!(
+        pick_overlapping(&level_n, &level_n1, 0),
+        (vec![0, 1], vec![0])
+    );
+    // overlapping SSTs within level n, as in L0
+    let level_n = level_of(&[("a", "z"), ("b", "c"), ("k", "l")]);
+    let level_n1 = level_of(&[("a", "b"), ("y", "z")]);
+    assert_eq!(
+        pick_overlapping(&level_n, &level_n1, 1),
+        (vec![0, 1, 2], vec![0, 1])
+    );
+}
+
+fn sst_with_id(id: usize, first: usize, last: usize) -> Arc<SsTable> {
+    let mut builder = SsTableBuilder::new(4096);
+    for key in first..=last {
+        builder.add(
+            KeySlice::for_testing_from_slice_no_ts(format!("{:05}", key).as_bytes()),
+            &[b'v'; 64],
+        );
+    }
+    Arc::new(builder.build_in_memory(id, None).unwrap())
+}
+
+/// Builds a state of L0 SSTs and levels of SSTs, each given as `(id, first_key, last_key)`.
+fn state_of(l0: &[(usize, usize, usize)], levels: &[&[(usize, usize, usize)]]) -> LsmStorageState {
+    let mut sstables = HashMap::new();
+    let mut ids_of = |ssts: &[(usize, usize, usize)]| {
+        ssts.iter()
+            .map(|&(id, first, last)| {
+                sstables.insert(id, sst_with_id(id, first, last));
+                id
+            })
+            .collect::<Vec<_>>()
+    };
+    let l0_sstables = ids_of(l0);
+    let levels = levels
+        .iter()
+        .enumerate()
+        .map(|(idx, ssts)| (idx + 1, ids_of(ssts)))
+        .collect();
+    LsmStorageState {
+        memtable: Arc::new(MemTable::create(0)),
+        imm_memtables: Vec::new(),
+        l0_sstables,
+        levels,
+        sstables,
+    }
+}
+
+fn leveled_controller(base_level_size_mb: usize) -> LeveledCompactionController {
+    LeveledCompactionController::new(LeveledCompactionOptions {
+        level_size_multiplier: 2,
+        level0_file_num_compaction_trigger: 2,
+        max_levels: 3,
+        base_level_size_mb,
+    })
+}
+
+#[test]
+fn test_leveled_l0_compaction() {
+    let controller = leveled_controller(1);
+    let mut state = state_of(&[(1, 0, 10)], &[&[], &[], &[(2, 0, 5), (3, 20, 30)]]);
+    assert!(controller.generate_compaction_task(&state).is_none());
+
+    // the levels are smaller than the base level size, L0 goes straight to the bottom level
+    let l0 = sst_with_id(4, 8, 15);
+    state.sstables.insert(4, l0);
+    state.l0_sstables.insert(0, 4);
+    let task = controller.generate_compaction_task(&state).unwrap();
+    assert_eq!(task.upper_level, None);
+    assert_eq!(task.upper_level_sst_ids, vec![4, 1]);
+    assert_eq!(task.lower_level, 3);
+    assert_eq!(task.lower_level_sst_ids, vec![2]);
+    assert!(task.is_lower_level_bottom_level);
+
+    // an SST flushed during the compaction stays in L0
+    state.sstables.insert(5, sst_with_id(5, 0, 1));
+    state.l0_sstables.insert(0, 5);
+    state.sstables.insert(6, sst_with_id(6, 0, 15));
+    let (state, removed) = controller.apply_compaction_result(&state, &task, &[6], false);
+    assert_eq!(removed, vec![4, 1, 2]);
+    assert_eq!(state.l0_sstables, vec![5]);
+    assert_eq!(state.levels[2].1, vec![6, 3]);
+}
+
+#[test]
+fn test_leveled_size_ratio_compaction() {
+    // with no base level size, level sizes target half the size of the level below them
+    let controller = leveled_controller(0);
+    let state = state_of(
+        &[],
+        &[&[], &[(1, 0, 19), (2, 40, 59)], &[(3, 0, 29), (4, 30, 59)]],
+    );
+    // L2 holds as much data as L3 but targets half of it
+    let task = controller.generate_compaction_task(&state).unwrap();
+    assert_eq!(task.upper_level, Some(2));
+    assert_eq!(task.upper_level_sst_ids, vec![1]);
+    assert_eq!(task.lower_level, 3);
+    assert_eq!(task.lower_level_sst_ids, vec![3]);
+    assert!(task.is_lower_level_bottom_level);
+
+    let state = state_of(&[], &[&[], &[(1, 0, 9)], &[(3, 0, 29), (4, 30, 59)]]);
+    assert!(controller.generate_compaction_task(&state).is_none());
+}
+
+fn sst_of_deletes(id: usize, first: usize, last: usize) -> Arc<SsTable> {
+    let mut builder = SsTableBuilder::new(4096);
+    for key in first..=last {
+        builder.add(
+            KeySlice::for_testing_from_slice_no_ts(format!("{:05}", key).as_bytes()),
+            b"",
+        );
+    }
+    Arc::new(builder.build_in_memory(id, None).unwrap())
+}
+
+#[test]
+fn test_leveled_deletes_priority() {
+    let controller = leveled_controller(0);
+    let mut state = state_of(&[], &[&[], &[(1, 0, 9)], &[(3, 0, 29), (4, 30, 59)]]);
+    assert!(controller.level_scores(&state)[2] < 1.0);
+    assert!(controller.generate_compaction_task(&state).is_none());
+
+    // L2 is below its target size, but holds deletes only
+    state.sstables.insert(1, sst_of_deletes(1, 0, 9));
+    assert!(controller.level_scores(&state)[2] > 1.0);
+    let task = controller.generate_compaction_task(&state).unwrap();
+    assert_eq!(task.upper_level, Some(2));
+    assert_eq!(task.upper_level_sst_ids, vec![1]);
+    assert_eq!(task.lower_level_sst_ids, vec![3]);
+
+    // the SST of deletes goes before an older one without
+    let mut state = state_of(&[], &[&[], &[(1, 0, 9)], &[(3, 0, 29), (4, 30, 59)]]);
+    state.sstables.insert(2, sst_of_deletes(2, 40, 49));
+    state.levels[1].1.push(2);
+    let task = controller.generate_compaction_task(&state).unwrap();
+    assert_eq!(task.upper_level_sst_ids, vec![2]);
+    assert_eq!(task.lower_level_sst_ids, vec![4]);
+}
+
+#[test]
+fn test_leveled_highest_score_first() {
+    let controller = leveled_controller(0);
+    // L0 is due, but L2 holds four times its target size
+    let state = state_of(
+        &[(5, 0, 1), (6, 0, 1)],
+        &[&[], &[(1, 0, 29), (2, 30, 59)], &[(3, 0, 29)]],
+    );
+    let scores = controller.level_scores(&state);
+    assert_eq!(scores[0], 1.0);
+    assert!(scores[2] > scores[0]);
+    assert_eq!(scores[3], 0.0);
+    let task = controller.generate_compaction_task(&state).unwrap();
+    assert_eq!(task.upper_level, Some(2));
+    // the oldest SST of equally scored ones
+    assert_eq!(task.upper_level_sst_ids, vec![1]);
+
+    let state = state_of(&[(5, 0, 1), (6, 0, 1)], &[&[], &[(1, 0, 9)], &[(3, 0, 29)]]);
+    let task = controller.generate_compaction_task(&state).unwrap();
+    assert_eq!(task.upper_level, None);
+    assert_eq!(task.lower_level, 1);
+}
+
+#[test]
+fn test_simple_leveled_compaction() {
+    let controller = SimpleLeveledCompactionController::new(SimpleLeveledCompactionOptions {
+        size_ratio_percent: 200,
+        level0_file_num_compaction_trigger: 2,
+        max_levels: 3,
+    });
+    let mut state = state_of(&[(1, 0, 10)], &[&[], &[], &[]]);
+    assert!(controller.generate_compaction_task(&state).is_none());
+
+    state.sstables.insert(2, sst_with_id(2, 5, 15));
+    state.l0_sstables.insert(0, 2);
+    let task = controller.generate_compaction_task(&state).unwrap();
+    assert_eq!(task.upper_level, None);
+    assert_eq!(task.upper_level_sst_ids, vec![2, 1]);
+    assert_eq!(task.lower_level, 1);
+    assert!(task.lower_level_sst_ids.is_empty());
+    assert!(!task.is_lower_level_bottom_level);
+
+    // an SST flushed during the compaction stays in L0
+    state.sstables.insert(3, sst_with_id(3, 0, 1));
+    state.l0_sstables.insert(0, 3);
+    state.sstables.insert(4, sst_with_id(4, 0, 15));
+    let (state, removed) = controller.apply_compaction_result(&state, &task, &[4]);
+    assert_eq!(removed, vec![2, 1]);
+    assert_eq!(state.l0_sstables, vec![3]);
+    assert_eq!(state.levels[0].1, vec![4]);
+
+    // L2 has fewer SSTs than 200% of L1
+    let task = controller.generate_compaction_task(&state).unwrap();
+    assert_eq!(task.upper_level, Some(1));
+    assert_eq!(task.upper_level_sst_ids, vec![4]);
+    assert_eq!(task.lower_level, 2);
+    assert!(!task.is_lower_level_bottom_level);
+
+    // the size ratio of L1 and L2 is met, while L3 is smaller than L2
+    let mut state = state_of(&[], &[&[(1, 0, 9)], &[(2, 0, 4), (3, 5, 9)], &[(4, 0, 3)]]);
+    let task = controller.generate_compaction_task(&state).unwrap();
+    assert_eq!(task.upper_level, Some(2));
+    assert_eq!(task.upper_level_sst_ids, vec![2, 3]);
+    assert_eq!(task.lower_level, 3);
+    assert_eq!(task.lower_level_sst_ids, vec![4]);
+    assert!(task.is_lower_level_bottom_level);
+
+    // an SST ingested into the bottom level during the compaction is kept in key order
+    state.sstables.insert(5, sst_with_id(5, 20, 29));
+    state.levels[2].1.insert(0, 5);
+    state.sstables.insert(6, sst_with_id(6, 0, 9));
+    let (state, removed) = controller.apply_compaction_result(&state, &task, &[6]);
+    assert_eq!(removed, vec![2, 3, 4]);
+    assert!(state.levels[1].1.is_empty());
+    assert_eq!(state.levels[2].1, vec![6, 5]);
+    // L1 is compacted next, as L2 is empty now
+    let task = controller.generate_compaction_task(&state).unwrap();
+    assert_eq!(task.upper_level, Some(1));
+}
+/// Builds a state of tiers from the latest to the earliest, each given as its number of SSTs. The
+/// SST ids grow from the earliest tier, and each tier is identified by its first SST.
+fn tiers_of(sizes: &[usize]) -> LsmStorageState {
+    let mut state = state_of(&[], &[]);
+    let mut next_id = sizes.iter().sum::<usize>();
+    for &size in sizes {
+        next_id -= size;
+        let ssts = (next_id..next_id + size).collect::<Vec<_>>();
+        for &id in &ssts {
+            state.sstables.insert(id, sst_with_id(id, id, id));
+        }
+        state.levels.push((next_id, ssts));
+    }
+    state
+}
+
+fn tiered_controller(max_merge_width: Option<usize>) -> TieredCompactionController {
+    TieredCompactionController::new(TieredCompactionOptions {
+        num_tiers: 3,
+        max_size_amplification_percent: 200,
+        size_ratio: 1,
+        min_merge_width: 2,
+        max_merge_width,
+    })
+}
+
+fn merged_tier_ids(controller: &TieredCompactionController, sizes: &[usize]) -> Option<Vec<usize>> {
+    let state = tiers_of(sizes);
+    let task = controller.generate_compaction_task(&state)?;
+    assert_eq!(task.bottom_tier_included, task.tiers.len() == sizes.len());
+    Some(task.tiers.iter().map(|(tier_id, _)| *tier_id).collect())
+}
+
+#[test]
+fn test_tiered_compaction_triggers() {
+    let controller = tiered_controller(None);
+    let state = tiers_of(&[1, 2, 8]);
+    let tier_ids = state.levels.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+    // not enough tiers
+    assert_eq!(merged_tier_ids(&controller, &[1, 8]), None);
+    // space amplification, 6 / 3 >= 200%
+    assert_eq!(merged_tier_ids(&controller, &[2, 4, 3]).unwrap().len(), 3);
+    // size ratio, 3 / 2 > 101%
+    assert_eq!(merged_tier_ids(&controller, &[3, 2, 20]).unwrap().len(), 2);
+    // tier count, no size ratio is violated
+    assert_eq!(
+        merged_tier_ids(&controller, &[1, 2, 8]).unwrap(),
+        tier_ids[..2]
+    );
+    assert_eq!(
+        merged_tier_ids(&controller, &[1, 2, 4, 8, 100])
+            .unwrap()
+            .len(),
+        4
+    );
+    let controller = tiered_controller(Some(2));
+    assert_eq!(
+        merged_tier_ids(&controller, &[1, 2, 4, 8, 100])
+            .unwrap()
+            .len(),
+        2
+    );
+}
+
+#[test]
+fn test_tiered_apply_compaction_result() {
+    let controller = tiered_controller(None);
+    let mut state = tiers_of(&[1, 2, 8]);
+    let task = controller.generate_compaction_task(&state).unwrap();
+    // a tier flushed during the compaction stays on top of the merged tier
+    state.sstables.insert(20, sst_with_id(20, 0, 1));
+    state.levels.insert(0, (20, vec![20]));
+    for id in [21, 22] {
+        state.sstables.insert(id, sst_with_id(id, id, id));
+    }
+    let bottom_tier = state.levels[3].clone();
+    let (state, mut removed) = controller.apply_compaction_result(&state, &task, &[21, 22]);
+    removed.sort();
+    assert_eq!(removed, vec![8, 9, 10]);
+    assert_eq!(
+        state.levels,
+        vec![(20, vec![20]), (21, vec![21, 22]), bottom_tier]
+    );
+}
@@ -0,0 +1,160 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::cmp::Ordering;
+use std::ops::Bound;
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use super::harness::leveled_options;
+use crate::iterators::StorageIterator;
+use crate::key::KeyComparator;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+
+/// Orders the keys in the reverse of the bytewise order.
+#[derive(Debug)]
+struct ReverseComparator;
+
+impl KeyComparator for ReverseComparator {
+    fn name(&self) -> &str {
+        "test.ReverseComparator"
+    }
+
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        b.cmp(a)
+    }
+}
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:04}", idx).into_bytes()
+}
+
+fn options() -> LsmStorageOptions {
+    let mut options = leveled_options();
+    options.comparator = Arc::new(ReverseComparator);
+    options
+}
+
+fn scan_keys(storage: &MiniLsm, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Vec<Vec<u8>> {
+    let mut iter = storage.scan(lower, upper).unwrap();
+    let mut keys = Vec::new();
+    while iter.is_valid() {
+        keys.push(iter.key().to_vec());
+        iter.next().unwrap();
+    }
+    keys
+}
+
+fn check_reverse_order(storage: &MiniLsm) {
+    for idx in 0..400 {
+        let expected = (idx % 3 != 0).then(|| format!("value_{}", idx).into_bytes());
+        assert_eq!(
+            storage
+                .get(&key_of(idx))
+                .unwrap()
+                .map(|value| value.to_vec()),
+            expected
+        );
+    }
+    let expected = (0..400)
+        .rev()
+        .filter(|idx| idx % 3 != 0)
+        .map(key_of)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        scan_keys(storage, Bound::Unbounded, Bound::Unbounded),
+        expected
+    );
+    // the lower bound is the greater key in the bytewise order
+    let expected = (100..=200)
+        .rev()
+        .filter(|idx| idx % 3 != 0)
+        .map(key_of)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        scan_keys(
+            storage,
+            Bound::Included(&key_of(200)),
+            Bound::Included(&key_of(100))
+        ),
+        expected
+    );
+    assert!(
+        scan_keys(
+            storage,
+            Bound::Included(&key_of(100)),
+            Bound::Included(&key_of(200))
+        )
+        .is_empty()
+    );
+}
+
+#[test]
+fn test_comparator_orders_memtables_and_ssts() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    for idx in 0..400 {
+        storage
+            .put(&key_of(idx), format!("value_{}", idx).as_bytes())
+            .unwrap();
+        if idx % 100 == 99 {
+            storage.force_flush().unwrap();
+        }
+    }
+    for idx in (0..400).step_by(3) {
+        storage.delete(&key_of(idx)).unwrap();
+    }
+    check_reverse_order(&storage);
+
+    storage.force_flush().unwrap();
+    storage.force_full_compaction().unwrap();
+    check_reverse_order(&storage);
+    storage.close().unwrap();
+
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    check_reverse_order(&storage);
+    storage.close().unwrap();
+}
+
+#[test]
+fn test_comparator_persisted_in_manifest() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    storage.put(b"key", b"value").unwrap();
+    storage.force_flush().unwrap();
+    storage.close().unwrap();
+
+    let mut bytewise = options();
+    bytewise.comparator = LsmStorageOptions::default_for_week1_test().comparator;
+    let err = MiniLsm::open(&dir, bytewise).err().unwrap();
+    assert!(
+        err.to_string().contains("test.ReverseComparator"),
+        "{}",
+        err
+    );
+
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    assert_eq!(&storage.get(b"key").unwrap().unwrap()[..], b"value");
+    storage.close().unwrap();
+}
+
+#[test]
+fn test_comparator_refuses_bytewise_features() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, options()).unwrap();
+    assert!(storage.delete_range(b"a", b"b").is_err());
+    assert!(storage.new_txn().is_err());
+    storage.close().unwrap();
+}
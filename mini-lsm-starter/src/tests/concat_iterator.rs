@@ -0,0 +1,211 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ops::Bound;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::{TempDir, tempdir};
+
+use super::harness::generate_sst;
+use crate::iterators::concat_iterator::SstConcatIterator;
+use crate::iterators::{ReversibleIterator, StorageIterator};
+use crate::key::KeySlice;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+use crate::table::SsTable;
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:03}", idx).into_bytes()
+}
+
+fn value_of(idx: usize) -> Vec<u8> {
+    format!("value_{:03}", idx).into_bytes()
+}
+
+/// 5 SSTs of 10 keys each, with gaps of 5 keys between them.
+fn generate_run() -> (TempDir, Vec<Arc<SsTable>>) {
+    let dir = tempdir().unwrap();
+    let ssts = (0..5)
+        .map(|sst_idx| {
+            let data = (sst_idx * 15..sst_idx * 15 + 10)
+                .map(|idx| (Bytes::from(key_of(idx)), Bytes::from(value_of(idx))))
+                .collect();
+            let path = dir.path().join(format!("{}.sst", sst_idx));
+            Arc::new(generate_sst(sst_idx, path, data, None))
+        })
+        .collect();
+    (dir, ssts)
+}
+
+fn run_keys() -> Vec<usize> {
+    (0..5)
+        .flat_map(|sst_idx| sst_idx * 15..sst_idx * 15 + 10)
+        .collect()
+}
+
+fn check_from(iter: &mut SstConcatIterator, keys: &[usize]) {
+    for &idx in keys {
+        assert!(iter.is_valid());
+        assert_eq!(iter.key().for_testing_key_ref(), key_of(idx));
+        assert_eq!(iter.value(), value_of(idx));
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_concat_iterator() {
+    let (_dir, ssts) = generate_run();
+    let keys = run_keys();
+    let mut iter = SstConcatIterator::create_and_seek_to_first(ssts.clone()).unwrap();
+    check_from(&mut iter, &keys);
+    assert!(iter.next().is_err());
+
+    for (pos, &idx) in keys.iter().enumerate() {
+        let mut iter = SstConcatIterator::create_and_seek_to_key(
+            ssts.clone(),
+            KeySlice::for_testing_from_slice_no_ts(&key_of(idx)),
+        )
+        .unwrap();
+        check_from(&mut iter, &keys[pos..]);
+    }
+    // a key in the gap between two tables
+    let mut iter = SstConcatIterator::create_and_seek_to_key(
+        ssts.clone(),
+        KeySlice::for_testing_from_slice_no_ts(&key_of(12)),
+    )
+    .unwrap();
+    check_from(&mut iter, &keys[10..]);
+    let mut iter =
+        SstConcatIterator::create_and_seek_to_range(ssts.clone(), Bound::Excluded(&key_of(9)))
+            .unwrap();
+    check_from(&mut iter, &keys[10..]);
+    let iter = SstConcatIterator::create_and_seek_to_range(ssts, Bound::Included(b"zzz")).unwrap();
+    assert!(!iter.is_valid());
+
+    let mut iter = SstConcatIterator::create_and_seek_to_first(vec![]).unwrap();
+    assert!(!iter.is_valid());
+    assert_eq!(iter.table_first_key(), None);
+    iter.seek_for_prev(KeySlice::for_testing_from_slice_no_ts(b"zzz"))
+        .unwrap();
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_concat_iterator_prev() {
+    let (_dir, ssts) = generate_run();
+    let keys = run_keys();
+    let mut iter = SstConcatIterator::create_and_seek_to_first(ssts).unwrap();
+    iter.seek_for_prev(KeySlice::for_testing_from_slice_no_ts(b"zzz"))
+        .unwrap();
+    for &idx in keys.iter().rev() {
+        assert!(iter.is_valid());
+        assert_eq!(iter.key().for_testing_key_ref(), key_of(idx));
+        assert_eq!(iter.value(), value_of(idx));
+        iter.prev().unwrap();
+    }
+    assert!(!iter.is_valid());
+    assert!(iter.prev().is_err());
+
+    // a key in the gap between two tables lands on the last key of the first one
+    iter.seek_for_prev(KeySlice::for_testing_from_slice_no_ts(&key_of(27)))
+        .unwrap();
+    assert_eq!(iter.key().for_testing_key_ref(), key_of(24));
+    // turn around and cross a table boundary in both directions
+    iter.next().unwrap();
+    assert_eq!(iter.key().for_testing_key_ref(), key_of(30));
+    iter.prev().unwrap();
+    assert_eq!(iter.key().for_testing_key_ref(), key_of(24));
+    iter.seek_to_key(KeySlice::for_testing_from_slice_no_ts(&key_of(0)))
+        .unwrap();
+    check_from(&mut iter, &keys);
+    iter.seek_for_prev(KeySlice::for_testing_from_slice_no_ts(b"key"))
+        .unwrap();
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_storage_scan_sorted_run() {
+    let dir = tempdir().unwrap();
+    let storage =
+        LsmStorageInner::open(dir.path(), LsmStorageOptions::default_for_week1_test()).unwrap();
+    let flush = || {
+        storage
+            .force_freeze_memtable(&storage.state_lock.lock())
+            .unwrap();
+        storage.force_flush_next_imm_memtable().unwrap();
+    };
+    for key in ["a", "b", "c"] {
+        storage.put(key.as_bytes(), b"1").unwrap();
+    }
+    flush();
+    for key in ["d", "e", "f"] {
+        storage.put(key.as_bytes(), b"1").unwrap();
+    }
+    flush();
+    {
+        // move both L0 SSTs, which do not overlap, to a sorted run in L1
+        let mut state = storage.state.write();
+        let mut new_state = state.as_ref().clone();
+        let mut run = std::mem::take(&mut new_state.l0_sstables);
+        run.reverse();
+        new_state.levels = vec![(1, run)];
+        *state = Arc::new(new_state);
+    }
+    storage.put(b"b", b"2").unwrap();
+    storage.delete(b"e").unwrap();
+    flush();
+    storage.put(b"c", b"3").unwrap();
+    storage.delete(b"d").unwrap();
+    storage.put(b"g", b"3").unwrap();
+
+    let collect = |lower: Bound<&[u8]>, upper: Bound<&[u8]>| {
+        let mut iter = storage.scan(lower, upper).unwrap();
+        let mut forward = Vec::new();
+        while iter.is_valid() {
+            forward.push((iter.key().to_vec(), iter.value().to_vec()));
+            iter.next().unwrap();
+        }
+        iter.seek_for_prev(KeySlice::for_testing_from_slice_no_ts(b"z"))
+            .unwrap();
+        let mut backward = Vec::new();
+        while iter.is_valid() {
+            backward.push((iter.key().to_vec(), iter.value().to_vec()));
+            iter.prev().unwrap();
+        }
+        backward.reverse();
+        assert_eq!(forward, backward);
+        forward
+    };
+    let kv = |key: &str, value: &str| (key.as_bytes().to_vec(), value.as_bytes().to_vec());
+    assert_eq!(
+        collect(Bound::Unbounded, Bound::Unbounded),
+        vec![
+            kv("a", "1"),
+            kv("b", "2"),
+            kv("c", "3"),
+            kv("f", "1"),
+            kv("g", "3")
+        ]
+    );
+    assert_eq!(
+        collect(Bound::Excluded(b"a"), Bound::Excluded(b"f")),
+        vec![kv("b", "2"), kv("c", "3")]
+    );
+    assert_eq!(
+        collect(Bound::Included(b"d"), Bound::Included(b"f")),
+        vec![kv("f", "1")]
+    );
+    assert_eq!(collect(Bound::Included(b"x"), Bound::Unbounded), vec![]);
+}
@@ -0,0 +1,190 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Kills the storage at random failpoints in the middle of a workload, and checks that the
+//! reopened storage holds the writes of a prefix of the workload, including all the acknowledged
+//! ones.
+
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::path::Path;
+
+use anyhow::Result;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use tempfile::tempdir;
+
+use super::harness::leveled_options;
+use crate::failpoint::{self, FailAction, FailScenario};
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+
+#[derive(Debug, Clone, Copy)]
+enum Op {
+    Put(usize, usize),
+    Delete(usize),
+    Flush,
+    Compact,
+}
+
+type Model = BTreeMap<Vec<u8>, Vec<u8>>;
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:03}", idx).into_bytes()
+}
+
+fn value_of(op_idx: usize) -> Vec<u8> {
+    format!("value_{:05}", op_idx).into_bytes()
+}
+
+fn workload(seed: u64, num_ops: usize) -> Vec<Op> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    (0..num_ops)
+        .map(|op_idx| match rng.gen_range(0..100) {
+            0..5 => Op::Flush,
+            5..7 => Op::Compact,
+            7..25 => Op::Delete(rng.gen_range(0..50)),
+            _ => Op::Put(rng.gen_range(0..50), op_idx),
+        })
+        .collect()
+}
+
+fn options() -> LsmStorageOptions {
+    let mut options = leveled_options();
+    options.enable_wal = true;
+    options.memtable_max_entries = Some(16);
+    options
+}
+
+fn apply(storage: &LsmStorageInner, op: Op) -> Result<()> {
+    match op {
+        Op::Put(key, op_idx) => storage.put(&key_of(key), &value_of(op_idx)),
+        Op::Delete(key) => storage.delete(&key_of(key)),
+        Op::Flush => storage.flush(),
+        Op::Compact => storage.force_full_compaction(),
+    }
+}
+
+/// The state after the first `num_ops` ops.
+fn expected_state(ops: &[Op], num_ops: usize) -> Model {
+    let mut model = Model::new();
+    for op in &ops[..num_ops] {
+        match *op {
+            Op::Put(key, op_idx) => {
+                model.insert(key_of(key), value_of(op_idx));
+            }
+            Op::Delete(key) => {
+                model.remove(&key_of(key));
+            }
+            Op::Flush | Op::Compact => {}
+        }
+    }
+    model
+}
+
+fn state_of(storage: &LsmStorageInner) -> Model {
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    let mut state = Model::new();
+    while iter.is_valid() {
+        state.insert(iter.key().to_vec(), iter.value().to_vec());
+        iter.next().unwrap();
+    }
+    state
+}
+
+/// Run `ops` until the first failure, and return the number of ops acknowledged.
+fn run_until_failure(dir: &Path, ops: &[Op]) -> usize {
+    let Ok(storage) = LsmStorageInner::open(dir, options()) else {
+        return 0;
+    };
+    ops.iter()
+        .take_while(|op| apply(&storage, **op).is_ok())
+        .count()
+}
+
+#[test]
+fn test_fail_once() {
+    let dir = tempdir().unwrap();
+    let scenario = FailScenario::setup(dir.path());
+    let storage = LsmStorageInner::open(dir.path(), options()).unwrap();
+    scenario.arm(Some(failpoint::WAL_APPEND), 1, FailAction::Error);
+    storage.put(b"1", b"1").unwrap();
+    assert!(storage.put(b"2", b"2").is_err());
+    storage.put(b"3", b"3").unwrap();
+    assert!(!scenario.crashed());
+
+    scenario.arm(Some(failpoint::FILE_CREATE), 0, FailAction::Crash);
+    assert!(storage.flush().is_err());
+    assert!(scenario.crashed());
+    // any later failpoint fails as well
+    assert!(storage.put(b"4", b"4").is_err());
+    drop(storage);
+
+    scenario.disarm();
+    let storage = LsmStorageInner::open(dir.path(), options()).unwrap();
+    assert_eq!(
+        state_of(&storage),
+        Model::from([
+            (b"1".to_vec(), b"1".to_vec()),
+            (b"3".to_vec(), b"3".to_vec())
+        ])
+    );
+}
+
+#[test]
+fn test_scenarios_scoped_by_directory() {
+    let dir = tempdir().unwrap();
+    let other_dir = tempdir().unwrap();
+    let scenario = FailScenario::setup(dir.path());
+    scenario.arm(None, 0, FailAction::Crash);
+    let storage = LsmStorageInner::open(other_dir.path(), options()).unwrap();
+    storage.put(b"1", b"1").unwrap();
+    storage.flush().unwrap();
+    assert_eq!(scenario.hits(), 0);
+}
+
+#[test]
+fn test_crash_at_random_failpoints() {
+    let seed = 42;
+    let ops = workload(seed, 300);
+    let dir = tempdir().unwrap();
+    let num_hits = {
+        let scenario = FailScenario::setup(dir.path());
+        assert_eq!(run_until_failure(dir.path(), &ops), ops.len());
+        scenario.hits()
+    };
+    assert!(num_hits > 100, "{}", num_hits);
+
+    let mut rng = StdRng::seed_from_u64(seed);
+    for _ in 0..30 {
+        let crash_at = rng.gen_range(0..num_hits);
+        let dir = tempdir().unwrap();
+        let scenario = FailScenario::setup(dir.path());
+        scenario.arm(None, crash_at, FailAction::Crash);
+        let acked = run_until_failure(dir.path(), &ops);
+        assert!(scenario.crashed(), "no crash at hit {}", crash_at);
+
+        scenario.disarm();
+        let storage = LsmStorageInner::open(dir.path(), options()).unwrap();
+        let state = state_of(&storage);
+        // the op that failed may or may not have been persisted
+        assert!(
+            state == expected_state(&ops, acked)
+                || (acked < ops.len() && state == expected_state(&ops, acked + 1)),
+            "inconsistent state after crashing at hit {} with {} ops acknowledged",
+            crash_at,
+            acked
+        );
+    }
+}
@@ -0,0 +1,94 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ops::Bound;
+
+use tempfile::tempdir;
+
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+use crate::rate_limiter::IoPriority;
+use crate::table::{DIRECT_IO_ALIGNMENT, DirectIoWriter, FileObject, SsTableWriter};
+
+#[test]
+fn test_direct_io_unaligned_reads() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    let data = (0..10000).map(|idx| (idx % 251) as u8).collect::<Vec<_>>();
+    let writer = DirectIoWriter {
+        path: &path,
+        rate_limiter: None,
+    };
+    let file = writer.write_sst(data.clone()).unwrap();
+    // the padding of the last page is cut off
+    assert_eq!(file.size(), data.len() as u64);
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), data.len() as u64);
+    for (offset, len) in [
+        (0, 1),
+        (1, 4095),
+        (4095, 2),
+        (4096, 4096),
+        (5000, 5000),
+        (9999, 1),
+    ] {
+        assert_eq!(
+            file.read(offset as u64, len as u64).unwrap(),
+            &data[offset..offset + len]
+        );
+    }
+    assert!(file.read(9990, 20).is_err());
+
+    let file = FileObject::open_direct(&path).unwrap();
+    assert_eq!(file.read(0, data.len() as u64).unwrap(), data);
+}
+
+#[test]
+fn test_direct_io_storage() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.use_direct_io = true;
+    options.background_write_rate = Some(1 << 30);
+    options.background_write_burst = 1000;
+    let storage = LsmStorageInner::open(&dir, options.clone()).unwrap();
+    for idx in 0..1000 {
+        let key = format!("key_{:05}", idx);
+        storage.put(key.as_bytes(), key.as_bytes()).unwrap();
+    }
+    storage.flush().unwrap();
+    storage.delete(b"key_00500").unwrap();
+    storage.flush().unwrap();
+    let sst_id = storage.state.read().l0_sstables[1];
+    let file_size = std::fs::metadata(storage.path_of_sst(sst_id))
+        .unwrap()
+        .len();
+    // the blocks are padded to the alignment, and the rate limiter grants aligned pieces
+    assert!(file_size > DIRECT_IO_ALIGNMENT as u64);
+    let limiter = storage.rate_limiter.as_ref().unwrap();
+    assert!(limiter.total_bytes(IoPriority::High) >= file_size);
+    drop(storage);
+
+    let storage = LsmStorageInner::open(&dir, options).unwrap();
+    assert_eq!(
+        storage.get(b"key_00999").unwrap().unwrap(),
+        &b"key_00999"[..]
+    );
+    assert!(storage.get(b"key_00500").unwrap().is_none());
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    let mut cnt = 0;
+    while iter.is_valid() {
+        cnt += 1;
+        iter.next().unwrap();
+    }
+    assert_eq!(cnt, 999);
+}
@@ -0,0 +1,78 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use super::harness::build_sst;
+use crate::error::Error;
+use crate::iterators::StorageIterator;
+use crate::iterators::merge_iterator::MergeIterator;
+use crate::lsm_storage::BlockCache;
+use crate::table::{FileObject, SsTable, SsTableIterator};
+
+#[test]
+fn test_error_iterator_exhausted() {
+    let dir = tempdir().unwrap();
+    let sst = build_sst(1, 0..20, None, dir.path().join("1.sst"));
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst).unwrap();
+    while iter.is_valid() {
+        iter.next().unwrap();
+    }
+    let err = iter.next().unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<Error>(),
+        Some(Error::IteratorExhausted)
+    ));
+
+    let mut iter = MergeIterator::create(vec![Box::new(iter)]);
+    assert!(!iter.is_valid());
+    let err = iter.next().unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<Error>(),
+        Some(Error::IteratorExhausted)
+    ));
+}
+
+#[test]
+fn test_error_corruption() {
+    for cache in [None, Some(Arc::new(BlockCache::new(16)))] {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("1.sst");
+        let sst = build_sst(1, 0..20, cache.clone(), &path);
+        let mut data = std::fs::read(&path).unwrap();
+        data[sst.block_meta[0].offset + 1] ^= 0x5a;
+        std::fs::write(&path, &data).unwrap();
+        let sst = SsTable::open(1, cache, FileObject::open(&path).unwrap()).unwrap();
+        let err = sst.read_block_cached(0).unwrap_err();
+        assert!(
+            matches!(err.downcast_ref::<Error>(), Some(Error::Corruption { .. })),
+            "{:?}",
+            err
+        );
+    }
+}
+
+#[test]
+fn test_error_io() {
+    let dir = tempdir().unwrap();
+    let err = FileObject::open(&dir.path().join("missing.sst"))
+        .err()
+        .unwrap();
+    match err.downcast_ref::<Error>() {
+        Some(Error::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::NotFound),
+        other => panic!("unexpected error {:?}", other),
+    }
+}
@@ -0,0 +1,115 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeMap;
+use std::ops::Bound;
+
+use tempfile::tempdir;
+
+use super::harness::{key_of, leveled_options};
+use crate::export::{SNAPSHOT_DESCRIPTOR, SnapshotDescriptor};
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+
+fn options() -> LsmStorageOptions {
+    let mut options = leveled_options();
+    options.enable_wal = true;
+    options.value_threshold = Some(16);
+    options.target_sst_size = 4096;
+    options
+}
+
+fn scan_all(storage: &MiniLsm) -> BTreeMap<Vec<u8>, Vec<u8>> {
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    let mut result = BTreeMap::new();
+    while iter.is_valid() {
+        result.insert(iter.key().to_vec(), iter.value().to_vec());
+        iter.next().unwrap();
+    }
+    result
+}
+
+#[test]
+fn test_export_import_snapshot() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(dir.path().join("source"), options()).unwrap();
+    for idx in 0..500 {
+        // every other value goes to the value log
+        let value = vec![b'a' + (idx % 2) as u8; if idx % 2 == 0 { 8 } else { 32 }];
+        storage.put(&key_of(idx), &value).unwrap();
+        if idx % 100 == 99 {
+            storage.flush().unwrap();
+        }
+    }
+    storage.put(&key_of(1), b"updated").unwrap();
+    storage.delete(&key_of(0)).unwrap();
+    storage.delete_range(&key_of(10), &key_of(20)).unwrap();
+    let expected = scan_all(&storage);
+    assert_eq!(expected.len(), 489);
+
+    let bundle = dir.path().join("bundle");
+    let descriptor = storage.export_snapshot(&bundle).unwrap();
+    assert!(storage.export_snapshot(&bundle).is_err());
+    assert_eq!(descriptor.num_keys, 489);
+    assert!(descriptor.files.len() > 1);
+    assert_eq!(SnapshotDescriptor::load(&bundle).unwrap(), descriptor);
+    // later writes are not in the bundle
+    storage.put(&key_of(2), b"later").unwrap();
+
+    let replica = MiniLsm::open(dir.path().join("replica"), options()).unwrap();
+    let ids = replica.import_snapshot(&bundle).unwrap();
+    assert_eq!(ids.len(), descriptor.files.len());
+    assert_eq!(scan_all(&replica), expected);
+    // the writes after the import shadow the imported versions
+    replica.put(&key_of(1), b"replica").unwrap();
+    assert_eq!(replica.get(&key_of(1)).unwrap().unwrap(), &b"replica"[..]);
+    // the imported data overlaps
+    assert!(replica.import_snapshot(&bundle).is_err());
+    replica.close().unwrap();
+    drop(replica);
+
+    let replica = MiniLsm::open(dir.path().join("replica"), options()).unwrap();
+    assert_eq!(replica.get(&key_of(1)).unwrap().unwrap(), &b"replica"[..]);
+    assert_eq!(replica.get(&key_of(3)).unwrap().unwrap(), vec![b'b'; 32]);
+    assert_eq!(replica.get(&key_of(15)).unwrap(), None);
+    assert_eq!(scan_all(&replica).len(), 489);
+    replica.close().unwrap();
+    storage.close().unwrap();
+}
+
+#[test]
+fn test_import_snapshot_damaged() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(dir.path().join("source"), options()).unwrap();
+    let empty = dir.path().join("empty");
+    let descriptor = storage.export_snapshot(&empty).unwrap();
+    assert!(descriptor.files.is_empty());
+    for idx in 0..100 {
+        storage.put(&key_of(idx), b"value").unwrap();
+    }
+    let bundle = dir.path().join("bundle");
+    let descriptor = storage.export_snapshot(&bundle).unwrap();
+
+    let replica = MiniLsm::open(dir.path().join("replica"), options()).unwrap();
+    assert!(replica.import_snapshot(&empty).unwrap().is_empty());
+    let sst_path = bundle.join(&descriptor.files[0].name);
+    let data = std::fs::read(&sst_path).unwrap();
+    std::fs::write(&sst_path, &data[..data.len() - 1]).unwrap();
+    assert!(replica.import_snapshot(&bundle).is_err());
+    std::fs::remove_file(bundle.join(SNAPSHOT_DESCRIPTOR)).unwrap();
+    assert!(replica.import_snapshot(&bundle).is_err());
+    assert!(scan_all(&replica).is_empty());
+    replica.close().unwrap();
+    storage.close().unwrap();
+}
@@ -0,0 +1,151 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tempfile::tempdir;
+
+use super::harness::key_of;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions, MiniLsm};
+
+#[test]
+fn test_flush() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorageInner::open(&dir, LsmStorageOptions::default_for_week1_test()).unwrap();
+    // nothing to flush
+    storage.flush().unwrap();
+    assert!(storage.state.read().l0_sstables.is_empty());
+
+    for idx in 0..100 {
+        storage.put(&key_of(idx), b"1").unwrap();
+    }
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    for idx in 100..200 {
+        storage.put(&key_of(idx), b"2").unwrap();
+    }
+    storage.flush().unwrap();
+    {
+        let state = storage.state.read();
+        assert!(state.memtable.is_empty());
+        assert!(state.imm_memtables.is_empty());
+        assert_eq!(state.l0_sstables.len(), 2);
+    }
+    assert_eq!(storage.get(&key_of(0)).unwrap().unwrap(), &b"1"[..]);
+    assert_eq!(storage.get(&key_of(199)).unwrap().unwrap(), &b"2"[..]);
+    storage.flush().unwrap();
+    assert_eq!(storage.state.read().l0_sstables.len(), 2);
+}
+
+#[test]
+fn test_flush_async() {
+    let dir = tempdir().unwrap();
+    let storage =
+        Arc::new(LsmStorageInner::open(&dir, LsmStorageOptions::default_for_week1_test()).unwrap());
+    for idx in 0..100 {
+        storage.put(&key_of(idx), b"1").unwrap();
+    }
+    let handle = storage.flush_async().unwrap();
+    // the writes after `flush_async` go to the new memtable
+    storage.put(&key_of(100), b"2").unwrap();
+    handle.wait().unwrap();
+    {
+        let state = storage.state.read();
+        assert!(state.imm_memtables.is_empty());
+        assert_eq!(state.l0_sstables.len(), 1);
+        assert_eq!(state.memtable.num_entries(), 1);
+    }
+    assert_eq!(storage.get(&key_of(0)).unwrap().unwrap(), &b"1"[..]);
+    assert_eq!(storage.get(&key_of(100)).unwrap().unwrap(), &b"2"[..]);
+}
+
+#[test]
+fn test_freeze_triggers() {
+    let freeze_count = |options: LsmStorageOptions| {
+        let dir = tempdir().unwrap();
+        let storage = LsmStorageInner::open(&dir, options).unwrap();
+        for idx in 0..100 {
+            storage.put(&key_of(idx), &[b'x'; 100]).unwrap();
+        }
+        storage.state.read().imm_memtables.len()
+    };
+
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    assert_eq!(freeze_count(options.clone()), 0);
+
+    options.memtable_max_entries = Some(10);
+    assert_eq!(freeze_count(options.clone()), 10);
+
+    options.memtable_max_entries = None;
+    // each entry takes 110 bytes of key and value, plus its node in the skiplist
+    options.memtable_max_size = Some(1100);
+    let frozen = freeze_count(options.clone());
+    assert!((11..50).contains(&frozen), "{}", frozen);
+
+    options.memtable_max_size = None;
+    options.memtable_max_wal_size = Some(4096);
+    // without WAL, the WAL size is always 0
+    assert_eq!(freeze_count(options.clone()), 0);
+    options.enable_wal = true;
+    let frozen = freeze_count(options);
+    assert!((2..10).contains(&frozen), "{}", frozen);
+}
+
+#[test]
+fn test_max_imm_memtables() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.memtable_max_entries = Some(10);
+    options.max_imm_memtables = Some(3);
+    let storage = LsmStorageInner::open(&dir, options).unwrap();
+    for idx in 0..100 {
+        storage.put(&key_of(idx), b"1").unwrap();
+        assert!(storage.state.read().imm_memtables.len() <= 3);
+    }
+    {
+        let state = storage.state.read();
+        assert_eq!(state.imm_memtables.len(), 3);
+        // the oldest memtables are flushed first
+        assert_eq!(state.l0_sstables.len(), 7);
+        let mut imm_ids = state.imm_memtables.iter().map(|memtable| memtable.id());
+        assert!(imm_ids.all(|id| state.l0_sstables.iter().all(|sst_id| *sst_id < id)));
+    }
+    for idx in 0..100 {
+        assert_eq!(storage.get(&key_of(idx)).unwrap().unwrap(), &b"1"[..]);
+    }
+}
+
+#[test]
+fn test_background_flush() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week1_day6_test();
+    options.memtable_max_entries = Some(10);
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for idx in 0..100 {
+        storage.put(&key_of(idx), b"1").unwrap();
+    }
+    // the flush thread keeps fewer than `num_memtable_limit` immutable memtables
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while storage.inner.state.read().imm_memtables.len() >= 2 {
+        assert!(Instant::now() < deadline, "memtables not flushed");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert!(!storage.inner.state.read().l0_sstables.is_empty());
+    for idx in 0..100 {
+        assert_eq!(storage.get(&key_of(idx)).unwrap().unwrap(), &b"1"[..]);
+    }
+    storage.close().unwrap();
+}
@@ -29,7 +29,7 @@ use crate::{
     },
     iterators::{StorageIterator, merge_iterator::MergeIterator},
     key::{KeySlice, TS_ENABLED},
-    lsm_storage::{BlockCache, LsmStorageInner, LsmStorageState, MiniLsm},
+    lsm_storage::{BlockCache, LsmStorageInner, LsmStorageOptions, LsmStorageState, MiniLsm},
     table::{SsTable, SsTableBuilder, SsTableIterator},
 };
 
@@ -227,6 +227,57 @@ pub fn generate_sst_with_ts(
     builder.build(id, block_cache, path.as_ref()).unwrap()
 }
 
+/// The key of the `idx`-th entry of a test, which sorts by `idx`.
+pub fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx).into_bytes()
+}
+
+/// A builder with 64-byte blocks holding `key_of(idx)` with the value `value_{idx}` for each of
+/// `keys`, which must be ascending.
+pub fn sst_builder_of(keys: impl IntoIterator<Item = usize>) -> SsTableBuilder {
+    let mut builder = SsTableBuilder::new(64);
+    for idx in keys {
+        builder.add(
+            KeySlice::for_testing_from_slice_no_ts(&key_of(idx)),
+            format!("value_{:05}", idx).as_bytes(),
+        );
+    }
+    builder
+}
+
+/// Build the SST of `sst_builder_of(keys)` at `path`.
+pub fn build_sst(
+    id: usize,
+    keys: impl IntoIterator<Item = usize>,
+    block_cache: Option<Arc<BlockCache>>,
+    path: impl AsRef<Path>,
+) -> Arc<SsTable> {
+    Arc::new(
+        sst_builder_of(keys)
+            .build(id, block_cache, path.as_ref())
+            .unwrap(),
+    )
+}
+
+/// Options of a storage without compaction that writes the WAL.
+pub fn wal_options() -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.enable_wal = true;
+    options
+}
+
+/// Options of a storage with leveled compaction over 3 levels, compacting L0 from 2 SSTs on.
+pub fn leveled_options() -> LsmStorageOptions {
+    LsmStorageOptions::default_for_week2_test(CompactionOptions::Leveled(
+        LeveledCompactionOptions {
+            level_size_multiplier: 2,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 3,
+            base_level_size_mb: 1,
+        },
+    ))
+}
+
 pub fn sync(storage: &LsmStorageInner) {
     storage
         .force_freeze_memtable(&storage.state_lock.lock())
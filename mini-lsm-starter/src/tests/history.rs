@@ -0,0 +1,154 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ops::Bound;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use super::harness::{key_of, leveled_options};
+use crate::iterators::StorageIterator;
+use crate::lsm_iterator::{ChangeKind, KeyVersion};
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+
+fn options() -> LsmStorageOptions {
+    let mut options = leveled_options();
+    options.value_threshold = Some(16);
+    options
+}
+
+fn scan_at(storage: &MiniLsm, ts: u64) -> Vec<(Vec<u8>, Vec<u8>)> {
+    let mut iter = storage
+        .scan_at(Bound::Unbounded, Bound::Unbounded, ts)
+        .unwrap();
+    let mut entries = Vec::new();
+    while iter.is_valid() {
+        entries.push((iter.key().to_vec(), iter.value().to_vec()));
+        iter.next().unwrap();
+    }
+    entries
+}
+
+#[test]
+fn test_get_and_scan_at() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(dir.path(), options()).unwrap();
+    for idx in 0..10 {
+        storage.put(&key_of(idx), b"v1").unwrap();
+    }
+    storage.force_flush().unwrap();
+    // the snapshot keeps the versions at `ts1` from compactions
+    let snapshot = storage.new_snapshot();
+    let ts1 = storage.inner.mvcc().latest_commit_ts();
+    for idx in 0..10 {
+        storage.put(&key_of(idx), b"v2").unwrap();
+    }
+    storage.delete(&key_of(3)).unwrap();
+    let ts2 = storage.inner.mvcc().latest_commit_ts();
+    storage.force_flush().unwrap();
+    storage.force_full_compaction().unwrap();
+
+    for idx in 0..10 {
+        assert_eq!(
+            storage.get_at(&key_of(idx), ts1).unwrap(),
+            Some(Bytes::from_static(b"v1"))
+        );
+    }
+    assert_eq!(storage.get_at(&key_of(3), ts2).unwrap(), None);
+    assert_eq!(
+        storage.get_at(&key_of(3), ts2 - 1).unwrap(),
+        Some(Bytes::from_static(b"v2"))
+    );
+    // nothing is written before the first put
+    assert_eq!(storage.get_at(&key_of(0), 0).unwrap(), None);
+
+    let expected = (0..10)
+        .map(|idx| (key_of(idx), b"v1".to_vec()))
+        .collect::<Vec<_>>();
+    assert_eq!(scan_at(&storage, ts1), expected);
+    let expected = (0..10)
+        .filter(|idx| *idx != 3)
+        .map(|idx| (key_of(idx), b"v2".to_vec()))
+        .collect::<Vec<_>>();
+    assert_eq!(scan_at(&storage, ts2), expected);
+
+    // the timestamps not committed yet are refused
+    assert!(storage.get_at(&key_of(0), ts2 + 1).is_err());
+    assert!(
+        storage
+            .scan_at(Bound::Unbounded, Bound::Unbounded, ts2 + 1)
+            .is_err()
+    );
+    drop(snapshot);
+    storage.close().unwrap();
+}
+
+#[test]
+fn test_history() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(dir.path(), options()).unwrap();
+    let snapshot = storage.new_snapshot();
+    let version = |ts: u64, kind: ChangeKind, value: &[u8]| KeyVersion {
+        ts,
+        kind,
+        value: Bytes::copy_from_slice(value),
+        expire_at: None,
+    };
+    let mut expected = Vec::new();
+    storage.put(b"key", b"v1").unwrap();
+    expected.push(version(
+        storage.inner.mvcc().latest_commit_ts(),
+        ChangeKind::Put,
+        b"v1",
+    ));
+    storage.put(b"other", b"v1").unwrap();
+    storage.force_flush().unwrap();
+    // separated into the value log
+    let large = vec![b'x'; 64];
+    storage.put(b"key", &large).unwrap();
+    expected.push(version(
+        storage.inner.mvcc().latest_commit_ts(),
+        ChangeKind::Put,
+        &large,
+    ));
+    storage.delete(b"key").unwrap();
+    expected.push(version(
+        storage.inner.mvcc().latest_commit_ts(),
+        ChangeKind::Delete,
+        b"",
+    ));
+    storage.force_flush().unwrap();
+    storage.put(b"key", b"v3").unwrap();
+    expected.push(version(
+        storage.inner.mvcc().latest_commit_ts(),
+        ChangeKind::Put,
+        b"v3",
+    ));
+    storage.delete_range(b"k", b"l").unwrap();
+    expected.push(version(
+        storage.inner.mvcc().latest_commit_ts(),
+        ChangeKind::Delete,
+        b"",
+    ));
+    expected.reverse();
+
+    assert_eq!(storage.history(b"key").unwrap(), expected);
+    storage.force_flush().unwrap();
+    storage.force_full_compaction().unwrap();
+    assert_eq!(storage.history(b"key").unwrap(), expected);
+    assert_eq!(storage.history(b"other").unwrap().len(), 1);
+    assert!(storage.history(b"missing").unwrap().is_empty());
+    drop(snapshot);
+    storage.close().unwrap();
+}
@@ -0,0 +1,159 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use super::harness::{generate_sst, generate_sst_with_ts, sync};
+use crate::compact::{CompactionOptions, TieredCompactionOptions};
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+
+fn external_sst(dir: &Path, name: &str, keys: &[&str]) -> PathBuf {
+    let path = dir.join(name);
+    let data = keys
+        .iter()
+        .map(|key| {
+            (
+                Bytes::copy_from_slice(key.as_bytes()),
+                Bytes::from("ingested"),
+            )
+        })
+        .collect();
+    generate_sst(0, &path, data, None);
+    path
+}
+
+fn scan_keys(storage: &LsmStorageInner) -> Vec<String> {
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    let mut keys = Vec::new();
+    while iter.is_valid() {
+        keys.push(String::from_utf8(iter.key().to_vec()).unwrap());
+        iter.next().unwrap();
+    }
+    keys
+}
+
+#[test]
+fn test_ingest_sst() {
+    let dir = tempdir().unwrap();
+    let external = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week1_test();
+    let storage = LsmStorageInner::open(dir.path(), options.clone()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    sync(&storage);
+    storage.put(b"z", b"1").unwrap();
+
+    let paths = [
+        external_sst(external.path(), "1.sst", &["m", "n"]),
+        external_sst(external.path(), "2.sst", &["c", "d"]),
+    ];
+    let ids = storage.ingest_sst(&paths).unwrap();
+    assert!(storage.ingest_sst(&[] as &[PathBuf]).unwrap().is_empty());
+    // the ids follow the key order, and are returned in the order of the paths
+    assert!(ids[1] < ids[0]);
+    {
+        let state = storage.state.read();
+        assert_eq!(state.levels.last().unwrap().1, vec![ids[1], ids[0]]);
+    }
+    let check = |storage: &LsmStorageInner| {
+        assert_eq!(scan_keys(storage), vec!["a", "c", "d", "m", "n", "z"]);
+        assert_eq!(storage.get(b"c").unwrap().unwrap(), &b"ingested"[..]);
+        // the files are copied into the storage
+        assert!(storage.path_of_sst(ids[0]).exists());
+    };
+    check(&storage);
+    // a later write shadows the ingested version
+    storage.put(b"m", b"2").unwrap();
+    assert_eq!(storage.get(b"m").unwrap().unwrap(), &b"2"[..]);
+    drop(storage);
+    std::fs::remove_dir_all(external.path()).unwrap();
+
+    let storage = LsmStorageInner::open(dir.path(), options).unwrap();
+    assert_eq!(storage.get(b"m").unwrap().unwrap(), &b"ingested"[..]);
+    assert_eq!(scan_keys(&storage), vec!["a", "c", "d", "m", "n"]);
+}
+
+#[test]
+fn test_ingest_sst_rejected() {
+    let dir = tempdir().unwrap();
+    let external = tempdir().unwrap();
+    let storage =
+        LsmStorageInner::open(dir.path(), LsmStorageOptions::default_for_week1_test()).unwrap();
+    storage.put(b"b", b"1").unwrap();
+    sync(&storage);
+    storage.put(b"m", b"1").unwrap();
+    storage.delete_range(b"x", b"y").unwrap();
+
+    let path = |name: &str, keys: &[&str]| external_sst(external.path(), name, keys);
+    for paths in [
+        // overlapping each other
+        vec![path("1.sst", &["c", "e"]), path("2.sst", &["d", "f"])],
+        // overlapping an SST, a memtable or a range tombstone
+        vec![path("3.sst", &["a", "c"])],
+        vec![path("4.sst", &["l", "n"])],
+        vec![path("5.sst", &["xa"])],
+    ] {
+        assert!(storage.ingest_sst(&paths).is_err());
+    }
+    let newer = external.path().join("6.sst");
+    generate_sst_with_ts(
+        0,
+        &newer,
+        vec![((Bytes::from("d"), 100), Bytes::from("1"))],
+        None,
+    );
+    assert!(storage.ingest_sst(&[newer]).is_err());
+    let corrupted = path("7.sst", &["d"]);
+    let mut data = std::fs::read(&corrupted).unwrap();
+    data[0] ^= 1;
+    std::fs::write(&corrupted, data).unwrap();
+    assert!(storage.ingest_sst(&[corrupted]).is_err());
+
+    // nothing is installed
+    assert_eq!(scan_keys(&storage), vec!["b", "m"]);
+    assert_eq!(storage.state.read().sstables.len(), 1);
+}
+
+#[test]
+fn test_ingest_sst_tiered() {
+    let dir = tempdir().unwrap();
+    let external = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Tiered(
+        TieredCompactionOptions {
+            num_tiers: 3,
+            max_size_amplification_percent: 200,
+            size_ratio: 1,
+            min_merge_width: 2,
+            max_merge_width: None,
+        },
+    ));
+    let storage = LsmStorageInner::open(dir.path(), options.clone()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    sync(&storage);
+    let ids = storage
+        .ingest_sst(&[external_sst(external.path(), "1.sst", &["c"])])
+        .unwrap();
+    let levels = storage.state.read().levels.clone();
+    assert_eq!(levels.len(), 2);
+    assert_eq!(levels[1], (ids[0], ids.clone()));
+    drop(storage);
+
+    let storage = LsmStorageInner::open(dir.path(), options).unwrap();
+    assert_eq!(storage.state.read().levels, levels);
+    assert_eq!(scan_keys(&storage), vec!["a", "c"]);
+}
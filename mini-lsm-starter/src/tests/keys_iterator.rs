@@ -0,0 +1,55 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use crate::iterators::StorageIterator;
+use crate::iterators::keys_iterator::KeysIterator;
+use crate::key::KeySlice;
+use crate::table::{SsTableBuilder, SsTableIterator};
+
+#[test]
+fn test_keys_only_sst_scan() {
+    let mut builder = SsTableBuilder::new(64);
+    for idx in 0..100 {
+        let key = format!("key_{:03}", idx * 5);
+        let value = format!("value_{:010}", idx);
+        builder.add(
+            KeySlice::for_testing_from_slice_no_ts(key.as_bytes()),
+            value.as_bytes(),
+        );
+    }
+    let dir = tempdir().unwrap();
+    let sst = Arc::new(builder.build_for_test(dir.path().join("1.sst")).unwrap());
+    assert!(sst.num_of_blocks() > 1);
+
+    let mut full_keys = Vec::new();
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+    while iter.is_valid() {
+        full_keys.push(iter.key().for_testing_key_ref().to_vec());
+        iter.next().unwrap();
+    }
+
+    let mut keys = Vec::new();
+    let mut iter =
+        KeysIterator::new(SsTableIterator::create_keys_only_and_seek_to_first(sst).unwrap());
+    while iter.is_valid() {
+        keys.push(iter.key().for_testing_key_ref().to_vec());
+        iter.next().unwrap();
+    }
+    assert_eq!(full_keys.len(), 100);
+    assert_eq!(keys, full_keys);
+}
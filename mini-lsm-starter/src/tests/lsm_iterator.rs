@@ -0,0 +1,280 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ops::Bound;
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use crate::iterators::merge_iterator::MergeIterator;
+use crate::iterators::two_merge_iterator::TwoMergeIterator;
+use crate::iterators::{ReversibleIterator, StorageIterator};
+use crate::key::KeySlice;
+use crate::lsm_iterator::LsmIterator;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+use crate::mem_table::MemTable;
+use crate::range_tombstone::RangeTombstones;
+use crate::vlog::ValueLogs;
+
+fn collect_backward<I>(iter: &mut I) -> Vec<(Vec<u8>, Vec<u8>)>
+where
+    I: ReversibleIterator + for<'a> StorageIterator<KeyType<'a> = &'a [u8]>,
+{
+    let mut result = Vec::new();
+    while iter.is_valid() {
+        result.push((iter.key().to_vec(), iter.value().to_vec()));
+        iter.prev().unwrap();
+    }
+    result
+}
+
+fn kv(key: &str, value: &str) -> (Vec<u8>, Vec<u8>) {
+    (key.as_bytes().to_vec(), value.as_bytes().to_vec())
+}
+
+#[test]
+fn test_memtable_iter_prev() {
+    let memtable = MemTable::create(0);
+    for key in ["a", "b", "c", "d", "e"] {
+        memtable
+            .for_testing_put_slice(key.as_bytes(), key.as_bytes())
+            .unwrap();
+    }
+    let mut iter = memtable.for_testing_scan_slice(Bound::Excluded(b"a"), Bound::Excluded(b"e"));
+    // seeking beyond the range clips to its last key
+    iter.seek_for_prev(KeySlice::for_testing_from_slice_no_ts(b"z"))
+        .unwrap();
+    let mut keys = Vec::new();
+    while iter.is_valid() {
+        keys.push(iter.key().for_testing_key_ref().to_vec());
+        iter.prev().unwrap();
+    }
+    assert_eq!(keys, vec![b"d".to_vec(), b"c".to_vec(), b"b".to_vec()]);
+    assert!(iter.prev().is_err());
+
+    // turn around and move forward again
+    iter.seek_for_prev(KeySlice::for_testing_from_slice_no_ts(b"cc"))
+        .unwrap();
+    assert_eq!(iter.key().for_testing_key_ref(), b"c");
+    iter.next().unwrap();
+    assert_eq!(iter.key().for_testing_key_ref(), b"d");
+    iter.next().unwrap();
+    assert!(!iter.is_valid());
+    iter.seek_to_key(KeySlice::for_testing_from_slice_no_ts(b"a"))
+        .unwrap();
+    assert_eq!(iter.key().for_testing_key_ref(), b"b");
+    iter.seek_for_prev(KeySlice::for_testing_from_slice_no_ts(b"a"))
+        .unwrap();
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_lsm_iter_prev() {
+    let dir = tempdir().unwrap();
+    let storage =
+        LsmStorageInner::open(dir.path(), LsmStorageOptions::default_for_week1_test()).unwrap();
+    storage.put(b"1", b"233").unwrap();
+    storage.put(b"2", b"2333").unwrap();
+    storage.put(b"3", b"23333").unwrap();
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    storage.delete(b"1").unwrap();
+    storage.delete(b"2").unwrap();
+    storage.put(b"3", b"2333").unwrap();
+    storage.put(b"4", b"23333").unwrap();
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    storage.put(b"1", b"233333").unwrap();
+    storage.put(b"3", b"233333").unwrap();
+    storage.delete(b"5").unwrap();
+
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    iter.seek_for_prev(KeySlice::for_testing_from_slice_no_ts(b"9"))
+        .unwrap();
+    assert_eq!(
+        collect_backward(&mut iter),
+        vec![kv("4", "23333"), kv("3", "233333"), kv("1", "233333")]
+    );
+
+    // the deleted key 2 is skipped in both directions
+    let mut iter = storage
+        .scan(Bound::Included(b"2"), Bound::Included(b"3"))
+        .unwrap();
+    assert_eq!(iter.key(), b"3");
+    iter.prev().unwrap();
+    assert!(!iter.is_valid());
+    iter.seek_for_prev(KeySlice::for_testing_from_slice_no_ts(b"2"))
+        .unwrap();
+    assert!(!iter.is_valid());
+    iter.seek_for_prev(KeySlice::for_testing_from_slice_no_ts(b"4"))
+        .unwrap();
+    assert_eq!(iter.key(), b"3");
+    assert_eq!(iter.value(), b"233333");
+}
+
+#[test]
+fn test_lsm_iter_versions() {
+    let memtable = Arc::new(MemTable::create(0));
+    let put = |key: &[u8], ts: u64, value: &[u8]| {
+        memtable
+            .put(KeySlice::for_testing_from_slice_with_ts(key, ts), value)
+            .unwrap();
+    };
+    put(b"a", 1, b"a1");
+    put(b"a", 3, b"a3");
+    put(b"b", 2, b"b2");
+    put(b"b", 4, b"");
+    put(b"c", 4, b"c4");
+    put(b"d", 1, b"");
+    put(b"d", 3, b"d3");
+    let scan_at = |read_ts: u64| {
+        let iter = MergeIterator::create(vec![Box::new(
+            memtable.scan(Bound::Unbounded, Bound::Unbounded),
+        )]);
+        let iter = TwoMergeIterator::create(iter, MergeIterator::create(vec![])).unwrap();
+        LsmIterator::new(
+            iter,
+            read_ts,
+            Bound::Unbounded,
+            Bound::Unbounded,
+            RangeTombstones::default(),
+            ValueLogs::default().snapshot(),
+            None,
+        )
+        .unwrap()
+    };
+
+    let expected = [
+        (1, vec![kv("a", "a1")]),
+        (2, vec![kv("a", "a1"), kv("b", "b2")]),
+        (3, vec![kv("a", "a3"), kv("b", "b2"), kv("d", "d3")]),
+        (4, vec![kv("a", "a3"), kv("c", "c4"), kv("d", "d3")]),
+    ];
+    for (read_ts, expected) in expected {
+        let mut iter = scan_at(read_ts);
+        let mut forward = Vec::new();
+        while iter.is_valid() {
+            forward.push((iter.key().to_vec(), iter.value().to_vec()));
+            iter.next().unwrap();
+        }
+        assert_eq!(forward, expected);
+
+        iter.seek_for_prev(KeySlice::for_testing_from_slice_no_ts(b"z"))
+            .unwrap();
+        let backward = collect_backward(&mut iter);
+        assert_eq!(backward, expected.into_iter().rev().collect::<Vec<_>>());
+    }
+
+    // turning around lands on the visible version
+    let mut iter = scan_at(3);
+    iter.seek_for_prev(KeySlice::for_testing_from_slice_no_ts(b"c"))
+        .unwrap();
+    assert_eq!((iter.key(), iter.value()), (&b"b"[..], &b"b2"[..]));
+    iter.next().unwrap();
+    assert_eq!((iter.key(), iter.value()), (&b"d"[..], &b"d3"[..]));
+    iter.prev().unwrap();
+    assert_eq!((iter.key(), iter.value()), (&b"b"[..], &b"b2"[..]));
+    iter.prev().unwrap();
+    assert_eq!((iter.key(), iter.value()), (&b"a"[..], &b"a3"[..]));
+}
+
+#[test]
+fn test_storage_overwrite_versions() {
+    let dir = tempdir().unwrap();
+    let storage =
+        LsmStorageInner::open(dir.path(), LsmStorageOptions::default_for_week1_test()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage.put(b"a", b"2").unwrap();
+    storage.put(b"b", b"1").unwrap();
+    storage.delete(b"b").unwrap();
+    // every write gets its own timestamp, and the latest version wins
+    assert_eq!(storage.mvcc().latest_commit_ts(), 4);
+    assert_eq!(storage.get(b"a").unwrap().unwrap(), &b"2"[..]);
+    assert_eq!(storage.get(b"b").unwrap(), None);
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    assert_eq!((iter.key(), iter.value()), (&b"a"[..], &b"2"[..]));
+    iter.next().unwrap();
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_storage_scan_bounds() {
+    let dir = tempdir().unwrap();
+    let storage =
+        LsmStorageInner::open(dir.path(), LsmStorageOptions::default_for_week1_test()).unwrap();
+    for (key, value) in [("a", "1"), ("b", "1"), ("c", "1")] {
+        storage.put(key.as_bytes(), value.as_bytes()).unwrap();
+    }
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    storage.force_flush_next_imm_memtable().unwrap();
+    for (key, value) in [("d", "2"), ("e", "2"), ("f", "2")] {
+        storage.put(key.as_bytes(), value.as_bytes()).unwrap();
+    }
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    storage.force_flush_next_imm_memtable().unwrap();
+    storage.put(b"c", b"3").unwrap();
+    storage.put(b"g", b"3").unwrap();
+
+    let collect = |lower: Bound<&[u8]>, upper: Bound<&[u8]>| {
+        let mut iter = storage.scan(lower, upper).unwrap();
+        let mut result = Vec::new();
+        while iter.is_valid() {
+            result.push((iter.key().to_vec(), iter.value().to_vec()));
+            iter.next().unwrap();
+        }
+        result
+    };
+    assert_eq!(
+        collect(Bound::Included(b"b"), Bound::Included(b"d")),
+        vec![kv("b", "1"), kv("c", "3"), kv("d", "2")]
+    );
+    assert_eq!(
+        collect(Bound::Excluded(b"b"), Bound::Excluded(b"d")),
+        vec![kv("c", "3")]
+    );
+    assert_eq!(
+        collect(Bound::Unbounded, Bound::Excluded(b"b")),
+        vec![kv("a", "1")]
+    );
+    assert_eq!(
+        collect(Bound::Excluded(b"f"), Bound::Unbounded),
+        vec![kv("g", "3")]
+    );
+    assert_eq!(
+        collect(Bound::Excluded(b"c"), Bound::Excluded(b"d")),
+        vec![]
+    );
+    assert_eq!(
+        collect(Bound::Included(b"d"), Bound::Included(b"c")),
+        vec![]
+    );
+
+    // the SSTs outside of the range are not opened at all, nor is the memtable with no key in it
+    let iter = storage
+        .scan(Bound::Included(b"a"), Bound::Included(b"b"))
+        .unwrap();
+    assert_eq!(iter.num_active_iterators(), 1);
+    let iter = storage
+        .scan(Bound::Excluded(b"c"), Bound::Unbounded)
+        .unwrap();
+    assert_eq!(iter.num_active_iterators(), 2);
+    let iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    assert_eq!(iter.num_active_iterators(), 3);
+}
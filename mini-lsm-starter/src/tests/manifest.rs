@@ -0,0 +1,170 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fs::OpenOptions;
+
+use parking_lot::Mutex;
+use tempfile::tempdir;
+
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+use crate::manifest::{Manifest, ManifestRecord};
+
+fn record_ids(records: &[ManifestRecord]) -> Vec<(bool, usize)> {
+    records
+        .iter()
+        .map(|record| match record {
+            ManifestRecord::NewMemtable(id) => (false, *id),
+            ManifestRecord::Flush(id) => (true, *id),
+            _ => panic!("unexpected record {:?}", record),
+        })
+        .collect()
+}
+
+#[test]
+fn test_manifest_recover() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("MANIFEST");
+    let manifest = Manifest::create(&path).unwrap();
+    manifest
+        .add_record_when_init(ManifestRecord::NewMemtable(1))
+        .unwrap();
+    manifest
+        .add_record_when_init(ManifestRecord::NewMemtable(2))
+        .unwrap();
+    manifest
+        .add_record_when_init(ManifestRecord::Flush(1))
+        .unwrap();
+    drop(manifest);
+
+    let (manifest, records) = Manifest::recover(&path).unwrap();
+    assert_eq!(
+        record_ids(&records),
+        vec![(false, 1), (false, 2), (true, 1)]
+    );
+    manifest
+        .add_record_when_init(ManifestRecord::NewMemtable(3))
+        .unwrap();
+    drop(manifest);
+
+    // a crash in the middle of writing a record drops it
+    let file = OpenOptions::new().write(true).open(&path).unwrap();
+    let len = file.metadata().unwrap().len();
+    file.set_len(len - 2).unwrap();
+    drop(file);
+    let (_, records) = Manifest::recover(&path).unwrap();
+    assert_eq!(
+        record_ids(&records),
+        vec![(false, 1), (false, 2), (true, 1)]
+    );
+}
+
+#[test]
+fn test_manifest_compaction() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("MANIFEST");
+    let manifest = Manifest::create(&path)
+        .unwrap()
+        .with_compaction_threshold(256);
+    let state_lock = Mutex::new(());
+    let mut id = 0;
+    while !manifest.needs_compaction() {
+        id += 1;
+        manifest
+            .add_record(&state_lock.lock(), ManifestRecord::NewMemtable(id))
+            .unwrap();
+    }
+    manifest
+        .compact(
+            &state_lock.lock(),
+            ManifestRecord::Snapshot {
+                memtables: vec![id],
+                l0_sstables: vec![1],
+                levels: vec![(1, vec![])],
+                next_sst_id: id + 1,
+                column_families: vec![],
+                comparator: None,
+            },
+        )
+        .unwrap();
+    assert!(!manifest.needs_compaction());
+    manifest
+        .add_record(&state_lock.lock(), ManifestRecord::Flush(id))
+        .unwrap();
+    drop(manifest);
+
+    let (_, records) = Manifest::recover(&path).unwrap();
+    assert_eq!(records.len(), 2);
+    assert!(matches!(
+        &records[0],
+        ManifestRecord::Snapshot { memtables, next_sst_id, .. }
+            if *memtables == vec![id] && *next_sst_id == id + 1
+    ));
+    assert!(matches!(records[1], ManifestRecord::Flush(flushed) if flushed == id));
+}
+
+fn check_recovered(storage: &LsmStorageInner, keys: &[&[u8]]) {
+    for key in keys {
+        assert_eq!(
+            storage.get(key).unwrap().unwrap(),
+            [*key, b"_value"].concat()
+        );
+    }
+    assert_eq!(storage.get(b"deleted").unwrap(), None);
+}
+
+#[test]
+fn test_storage_recover_from_manifest() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.enable_wal = true;
+    let storage = LsmStorageInner::open(&dir, options.clone()).unwrap();
+    storage.put(b"deleted", b"value").unwrap();
+    storage.put(b"flushed", b"flushed_value").unwrap();
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    storage.force_flush_next_imm_memtable().unwrap();
+    storage.put(b"frozen", b"frozen_value").unwrap();
+    storage.delete(b"deleted").unwrap();
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    storage.put(b"active", b"active_value").unwrap();
+    storage.sync().unwrap();
+    let sst_id = storage.state.read().l0_sstables[0];
+    drop(storage);
+
+    let storage = LsmStorageInner::open(&dir, options).unwrap();
+    {
+        let state = storage.state.read();
+        assert_eq!(state.l0_sstables, vec![sst_id]);
+        // the frozen memtable and the active one are replayed from their WALs
+        assert_eq!(state.imm_memtables.len(), 2);
+        assert!(state.imm_memtables[0].id() > state.imm_memtables[1].id());
+        assert!(state.memtable.is_empty());
+        assert!(state.memtable.id() > state.imm_memtables[0].id());
+    }
+    check_recovered(&storage, &[b"flushed", b"frozen", b"active"]);
+    // the recovered memtables can be flushed
+    storage.force_flush_next_imm_memtable().unwrap();
+    storage.force_flush_next_imm_memtable().unwrap();
+    drop(storage);
+
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.enable_wal = true;
+    let storage = LsmStorageInner::open(&dir, options).unwrap();
+    assert_eq!(storage.state.read().l0_sstables.len(), 3);
+    assert!(storage.state.read().imm_memtables.is_empty());
+    check_recovered(&storage, &[b"flushed", b"frozen", b"active"]);
+}
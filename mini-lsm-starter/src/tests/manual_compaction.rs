@@ -0,0 +1,268 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ops::Bound;
+
+use tempfile::tempdir;
+
+use super::harness::{key_of, sync};
+use crate::compact::{
+    CompactionOptions, LeveledCompactionOptions, SimpleLeveledCompactionOptions,
+    TieredCompactionOptions,
+};
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+
+/// Write `0..1000` in five flushes of disjoint key ranges.
+fn write_ranges(storage: &LsmStorageInner) {
+    for range in 0..5 {
+        for idx in range * 200..(range + 1) * 200 {
+            storage.put(&key_of(idx), &key_of(idx)).unwrap();
+        }
+        sync(storage);
+    }
+}
+
+fn count_keys(storage: &LsmStorageInner) -> usize {
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    let mut count = 0;
+    while iter.is_valid() {
+        count += 1;
+        iter.next().unwrap();
+    }
+    count
+}
+
+fn check_level_sorted(storage: &LsmStorageInner, level: usize) {
+    let state = storage.state.read();
+    for pair in state.levels[level - 1].1.windows(2) {
+        let (first, second) = (&state.sstables[&pair[0]], &state.sstables[&pair[1]]);
+        assert!(first.last_key().key_ref() < second.first_key().key_ref());
+    }
+}
+
+#[test]
+fn test_compact_range_no_compaction() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorageInner::open(&dir, LsmStorageOptions::default_for_week1_test()).unwrap();
+    write_ranges(&storage);
+    let l0_sstables = storage.state.read().l0_sstables.clone();
+    assert_eq!(l0_sstables.len(), 5);
+
+    // only the SST of `400..600` overlaps the range
+    storage
+        .compact_range(Some(&key_of(450)), Some(&key_of(550)))
+        .unwrap();
+    {
+        let state = storage.state.read();
+        assert_eq!(state.l0_sstables.len(), 4);
+        assert!(!state.l0_sstables.contains(&l0_sstables[2]));
+        assert!(!state.levels[0].1.is_empty());
+    }
+
+    storage.compact_range(Some(&key_of(900)), None).unwrap();
+    assert_eq!(storage.state.read().l0_sstables.len(), 3);
+    check_level_sorted(&storage, 1);
+    // nothing overlaps the range
+    storage.compact_range(Some(b"z"), None).unwrap();
+    assert_eq!(storage.state.read().l0_sstables.len(), 3);
+
+    for idx in 0..100 {
+        storage.delete(&key_of(idx)).unwrap();
+    }
+    sync(&storage);
+    storage.force_full_compaction().unwrap();
+    assert!(storage.state.read().l0_sstables.is_empty());
+    check_level_sorted(&storage, 1);
+    assert_eq!(count_keys(&storage), 900);
+    assert_eq!(storage.get(&key_of(50)).unwrap(), None);
+    assert_eq!(
+        storage.get(&key_of(500)).unwrap().unwrap(),
+        &key_of(500)[..]
+    );
+}
+
+#[test]
+fn test_compact_range_leveled() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Leveled(
+        LeveledCompactionOptions {
+            level_size_multiplier: 2,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 3,
+            base_level_size_mb: 1,
+        },
+    ));
+    let storage = LsmStorageInner::open(&dir, options.clone()).unwrap();
+    write_ranges(&storage);
+    storage.force_full_compaction().unwrap();
+    {
+        // everything is compacted into the base level, which is the bottom level while small
+        let state = storage.state.read();
+        assert!(state.l0_sstables.is_empty());
+        assert!(state.levels[0].1.is_empty());
+        assert!(state.levels[1].1.is_empty());
+        assert!(!state.levels[2].1.is_empty());
+    }
+
+    for idx in 300..400 {
+        storage.put(&key_of(idx), b"updated").unwrap();
+    }
+    storage.delete(&key_of(350)).unwrap();
+    sync(&storage);
+    storage.put(&key_of(999), b"updated").unwrap();
+    sync(&storage);
+    storage
+        .compact_range(Some(&key_of(300)), Some(&key_of(399)))
+        .unwrap();
+    {
+        let state = storage.state.read();
+        assert_eq!(state.l0_sstables.len(), 1);
+    }
+    check_level_sorted(&storage, 3);
+    assert_eq!(storage.get(&key_of(300)).unwrap().unwrap(), &b"updated"[..]);
+    assert_eq!(storage.get(&key_of(350)).unwrap(), None);
+    assert_eq!(storage.get(&key_of(999)).unwrap().unwrap(), &b"updated"[..]);
+    assert_eq!(count_keys(&storage), 999);
+    drop(storage);
+
+    let storage = LsmStorageInner::open(&dir, options).unwrap();
+    assert_eq!(storage.state.read().l0_sstables.len(), 1);
+    assert_eq!(storage.get(&key_of(300)).unwrap().unwrap(), &b"updated"[..]);
+    assert_eq!(count_keys(&storage), 999);
+}
+
+#[test]
+fn test_compact_range_simple() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Simple(
+        SimpleLeveledCompactionOptions {
+            size_ratio_percent: 200,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 3,
+        },
+    ));
+    let storage = LsmStorageInner::open(&dir, options.clone()).unwrap();
+    write_ranges(&storage);
+    // the whole level is compacted into the level below, down to the bottom level
+    storage
+        .compact_range(Some(&key_of(450)), Some(&key_of(550)))
+        .unwrap();
+    {
+        let state = storage.state.read();
+        assert!(state.l0_sstables.is_empty());
+        assert!(state.levels[0].1.is_empty());
+        assert!(state.levels[1].1.is_empty());
+        assert!(!state.levels[2].1.is_empty());
+    }
+    check_level_sorted(&storage, 3);
+    assert_eq!(count_keys(&storage), 1000);
+
+    for idx in 0..100 {
+        storage.delete(&key_of(idx)).unwrap();
+    }
+    storage.put(&key_of(500), b"updated").unwrap();
+    sync(&storage);
+    storage.force_full_compaction().unwrap();
+    assert!(storage.state.read().l0_sstables.is_empty());
+    check_level_sorted(&storage, 3);
+    assert_eq!(count_keys(&storage), 900);
+    assert_eq!(storage.get(&key_of(500)).unwrap().unwrap(), &b"updated"[..]);
+    drop(storage);
+
+    // the compactions are replayed from the manifest
+    let storage = LsmStorageInner::open(&dir, options).unwrap();
+    check_level_sorted(&storage, 3);
+    assert_eq!(count_keys(&storage), 900);
+    assert_eq!(storage.get(&key_of(50)).unwrap(), None);
+    assert_eq!(storage.get(&key_of(500)).unwrap().unwrap(), &b"updated"[..]);
+}
+
+#[test]
+fn test_compact_range_tiered() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Tiered(
+        TieredCompactionOptions {
+            num_tiers: 10,
+            max_size_amplification_percent: 200,
+            size_ratio: 1,
+            min_merge_width: 2,
+            max_merge_width: None,
+        },
+    ));
+    let storage = LsmStorageInner::open(&dir, options).unwrap();
+    write_ranges(&storage);
+    assert_eq!(storage.state.read().levels.len(), 5);
+    storage.compact_range(Some(b"z"), None).unwrap();
+    assert_eq!(storage.state.read().levels.len(), 5);
+    // every tier covers the whole key range, so all of them are merged
+    storage
+        .compact_range(Some(&key_of(0)), Some(&key_of(10)))
+        .unwrap();
+    assert_eq!(storage.state.read().levels.len(), 1);
+    assert_eq!(count_keys(&storage), 1000);
+}
+
+#[test]
+fn test_gc_compact() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Leveled(
+        LeveledCompactionOptions {
+            level_size_multiplier: 2,
+            level0_file_num_compaction_trigger: 10,
+            max_levels: 3,
+            base_level_size_mb: 1,
+        },
+    ));
+    let storage = LsmStorageInner::open(&dir, options).unwrap();
+    write_ranges(&storage);
+    storage.force_full_compaction().unwrap();
+    // the bottom level is never selected
+    assert_eq!(storage.gc_compact(0.0).unwrap(), 0);
+
+    for idx in 0..200 {
+        storage.delete(&key_of(idx)).unwrap();
+    }
+    sync(&storage);
+    for idx in 500..510 {
+        storage.put(&key_of(idx), b"updated").unwrap();
+    }
+    storage.delete(&key_of(510)).unwrap();
+    sync(&storage);
+    let deleted_fractions = {
+        let state = storage.state.read();
+        state
+            .l0_sstables
+            .iter()
+            .map(|id| state.sstables[id].properties().unwrap().deleted_fraction())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(deleted_fractions.len(), 2);
+    assert!((deleted_fractions[0] - 1.0 / 11.0).abs() < 1e-9);
+    assert_eq!(deleted_fractions[1], 1.0);
+
+    assert_eq!(storage.gc_compact(0.5).unwrap(), 1);
+    {
+        // the SST of deletes is compacted away with the versions it deletes, the other one stays
+        let state = storage.state.read();
+        assert_eq!(state.l0_sstables.len(), 1);
+        for id in &state.levels[2].1 {
+            assert_eq!(state.sstables[id].properties().unwrap().num_deletions, 0);
+        }
+    }
+    assert_eq!(storage.gc_compact(0.5).unwrap(), 0);
+    assert_eq!(storage.get(&key_of(0)).unwrap(), None);
+    assert_eq!(storage.get(&key_of(505)).unwrap().unwrap(), &b"updated"[..]);
+    assert_eq!(count_keys(&storage), 799);
+}
@@ -0,0 +1,125 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ops::Bound;
+use std::sync::Arc;
+
+use super::harness::key_of;
+use crate::iterators::{ReversibleIterator, StorageIterator};
+use crate::key::KeySlice;
+use crate::mem_table::MemTable;
+
+#[test]
+fn test_memtable_concurrent_put() {
+    let memtable = Arc::new(MemTable::create(0));
+    let handles = (0..8)
+        .map(|thread| {
+            let memtable = memtable.clone();
+            std::thread::spawn(move || {
+                for idx in (thread..4000).step_by(8) {
+                    memtable
+                        .put(KeySlice::from_slice(&key_of(idx), 1), &key_of(idx))
+                        .unwrap();
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    assert_eq!(memtable.num_entries(), 4000);
+    let mut iter = memtable.scan(Bound::Unbounded, Bound::Unbounded);
+    for idx in 0..4000 {
+        assert!(iter.is_valid());
+        assert_eq!(iter.key().key_ref(), key_of(idx));
+        assert_eq!(iter.value(), key_of(idx));
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_memtable_overwrite_version() {
+    let memtable = MemTable::create(0);
+    memtable
+        .put(KeySlice::from_slice(b"a", 1), b"old-value")
+        .unwrap();
+    let size = memtable.approximate_size();
+    // a scan started before the overwrite keeps reading valid memory
+    let iter = memtable.scan(Bound::Unbounded, Bound::Unbounded);
+    memtable.put(KeySlice::from_slice(b"a", 1), b"new").unwrap();
+    assert_eq!(memtable.num_entries(), 1);
+    assert_eq!(
+        &memtable.get(KeySlice::from_slice(b"a", 1)).unwrap()[..],
+        b"new"
+    );
+    assert!(iter.value() == b"old-value" || iter.value() == b"new");
+    // the replaced value stays in the arena
+    assert!(memtable.approximate_size() >= size + 3);
+}
+
+#[test]
+fn test_memtable_size_includes_nodes() {
+    let memtable = MemTable::create(0);
+    assert_eq!(memtable.approximate_size(), 0);
+    for idx in 0..100 {
+        memtable
+            .put(KeySlice::from_slice(&key_of(idx), 1), b"value")
+            .unwrap();
+    }
+    let raw_size = 100 * (KeySlice::from_slice(&key_of(0), 1).raw_len() + 5);
+    assert!(
+        memtable.approximate_size() > raw_size,
+        "{} <= {}",
+        memtable.approximate_size(),
+        raw_size
+    );
+}
+
+#[test]
+fn test_memtable_iterator_bounds() {
+    let memtable = MemTable::create(0);
+    for idx in 0..10 {
+        for ts in 1..=3 {
+            memtable
+                .put(KeySlice::from_slice(&key_of(idx), ts), &[ts as u8])
+                .unwrap();
+        }
+    }
+    let mut iter = memtable.scan(
+        Bound::Included(&key_of(3)[..]),
+        Bound::Excluded(&key_of(5)[..]),
+    );
+    let mut keys = Vec::new();
+    while iter.is_valid() {
+        keys.push((iter.key().key_ref().to_vec(), iter.key().ts()));
+        iter.next().unwrap();
+    }
+    let expected = [3, 4]
+        .into_iter()
+        .flat_map(|idx| (1..=3).rev().map(move |ts| (key_of(idx), ts)))
+        .collect::<Vec<_>>();
+    assert_eq!(keys, expected);
+
+    iter.seek_for_prev(KeySlice::from_slice(&key_of(9), 1))
+        .unwrap();
+    assert_eq!(iter.key().key_ref(), key_of(4));
+    assert_eq!(iter.key().ts(), 1);
+    iter.seek_to_key(KeySlice::from_slice(&key_of(0), 3))
+        .unwrap();
+    assert_eq!(iter.key().key_ref(), key_of(3));
+    assert_eq!(iter.key().ts(), 3);
+    iter.prev().unwrap();
+    assert!(!iter.is_valid());
+}
@@ -0,0 +1,498 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use super::harness::{MockIterator, check_iter_result_by_key, generate_sst};
+use crate::iterators::merge_iterator::{MergeIterator, MergeWrapper};
+use crate::iterators::{ReversibleIterator, StorageIterator};
+use crate::key::KeySlice;
+use crate::table::{SsTableBuilder, SsTableIterator};
+
+fn kv(key: &str, value: &str) -> (Bytes, Bytes) {
+    (
+        Bytes::copy_from_slice(key.as_bytes()),
+        Bytes::copy_from_slice(value.as_bytes()),
+    )
+}
+
+fn key_of(idx: usize) -> String {
+    format!("key_{:03}", idx)
+}
+
+#[test]
+fn test_merge_seek_to_key_forward() {
+    let i1 = MockIterator::new(vec![kv("a", "1.1"), kv("c", "1.3"), kv("e", "1.5")]);
+    let i2 = MockIterator::new(vec![kv("b", "2.2"), kv("c", "2.3"), kv("d", "2.4")]);
+    let mut iter = MergeIterator::create(vec![Box::new(i1), Box::new(i2)]);
+    iter.seek_to_key(KeySlice::for_testing_from_slice_no_ts(b"c"))
+        .unwrap();
+    check_iter_result_by_key(
+        &mut iter,
+        vec![kv("c", "1.3"), kv("d", "2.4"), kv("e", "1.5")],
+    );
+}
+
+#[test]
+fn test_merge_seek_to_key_sst() {
+    let dir = tempdir().unwrap();
+    // even keys in the first SST, keys divisible by 3 in the second one
+    let data1 = (0..60)
+        .filter(|x| x % 2 == 0)
+        .map(|x| kv(&key_of(x), &format!("1.{}", x)))
+        .collect::<Vec<_>>();
+    let data2 = (0..60)
+        .filter(|x| x % 3 == 0)
+        .map(|x| kv(&key_of(x), &format!("2.{}", x)))
+        .collect::<Vec<_>>();
+    let sst1 = Arc::new(generate_sst(1, dir.path().join("1.sst"), data1, None));
+    let sst2 = Arc::new(generate_sst(2, dir.path().join("2.sst"), data2, None));
+    let mut iter = MergeIterator::create(vec![
+        Box::new(SsTableIterator::create_and_seek_to_first(sst1).unwrap()),
+        Box::new(SsTableIterator::create_and_seek_to_first(sst2).unwrap()),
+    ]);
+    let expected = |from: usize| {
+        (from..60)
+            .filter_map(|x| {
+                if x % 2 == 0 {
+                    Some(kv(&key_of(x), &format!("1.{}", x)))
+                } else if x % 3 == 0 {
+                    Some(kv(&key_of(x), &format!("2.{}", x)))
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>()
+    };
+
+    // move forward to a key, then seek both forward and backward, including after exhausting
+    iter.seek_to_key(KeySlice::for_testing_from_slice_no_ts(
+        key_of(31).as_bytes(),
+    ))
+    .unwrap();
+    assert_eq!(iter.key().for_testing_key_ref(), key_of(32).as_bytes());
+    iter.seek_to_key(KeySlice::for_testing_from_slice_no_ts(key_of(9).as_bytes()))
+        .unwrap();
+    check_iter_result_by_key(&mut iter, expected(9));
+    iter.seek_to_key(KeySlice::for_testing_from_slice_no_ts(
+        key_of(27).as_bytes(),
+    ))
+    .unwrap();
+    check_iter_result_by_key(&mut iter, expected(27));
+    iter.seek_to_key(KeySlice::for_testing_from_slice_no_ts(b"zzz"))
+        .unwrap();
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_merge_next_distinct_key() {
+    // three versions of each key, from the newest to the oldest iterator
+    let versions = (0..3)
+        .map(|version| {
+            MockIterator::new(
+                ["a", "b", "c", "d"]
+                    .iter()
+                    .map(|key| kv(key, &format!("{}.{}", key, version)))
+                    .collect(),
+            )
+        })
+        .map(Box::new)
+        .collect::<Vec<_>>();
+    let mut iter = MergeIterator::create(versions);
+    let mut result = Vec::new();
+    while iter.is_valid() {
+        result.push((
+            Bytes::copy_from_slice(iter.key().key_ref()),
+            Bytes::copy_from_slice(iter.value()),
+        ));
+        iter.next_distinct_key().unwrap();
+    }
+    assert_eq!(
+        result,
+        vec![
+            kv("a", "a.0"),
+            kv("b", "b.0"),
+            kv("c", "c.0"),
+            kv("d", "d.0")
+        ]
+    );
+    assert!(iter.next_distinct_key().is_err());
+}
+
+#[test]
+fn test_merge_precedence_winner_in_heap() {
+    // `current` starts at the second iterator, which then lands on a key that the first iterator
+    // (still in the heap) also has; the first iterator must win.
+    let i1 = MockIterator::new(vec![kv("b", "1.b"), kv("d", "1.d")]);
+    let i2 = MockIterator::new(vec![kv("a", "2.a"), kv("b", "2.b"), kv("c", "2.c")]);
+    let i3 = MockIterator::new(vec![kv("b", "3.b"), kv("c", "3.c"), kv("d", "3.d")]);
+    let mut iter = MergeIterator::create(vec![Box::new(i1), Box::new(i2), Box::new(i3)]);
+    check_iter_result_by_key(
+        &mut iter,
+        vec![
+            kv("a", "2.a"),
+            kv("b", "1.b"),
+            kv("c", "2.c"),
+            kv("d", "1.d"),
+        ],
+    );
+}
+
+#[test]
+fn test_merge_precedence_duplicate_keys() {
+    // every iterator has every key, the lowest index always wins regardless of its position
+    for num_iters in 1..6 {
+        let iters = (0..num_iters)
+            .map(|i| {
+                Box::new(MockIterator::new(
+                    (0..10)
+                        .map(|k| kv(&key_of(k), &format!("{}.{}", i, k)))
+                        .collect(),
+                ))
+            })
+            .collect::<Vec<_>>();
+        let mut iter = MergeIterator::create(iters);
+        check_iter_result_by_key(
+            &mut iter,
+            (0..10)
+                .map(|k| kv(&key_of(k), &format!("0.{}", k)))
+                .collect(),
+        );
+    }
+    // the winner for each key comes from a different iterator
+    let iters = (0..3)
+        .map(|i| {
+            Box::new(MockIterator::new(
+                (0..9)
+                    .filter(|k| k % 3 >= i)
+                    .map(|k| kv(&key_of(k), &format!("{}.{}", i, k)))
+                    .collect(),
+            ))
+        })
+        .collect::<Vec<_>>();
+    let mut iter = MergeIterator::create(iters);
+    check_iter_result_by_key(
+        &mut iter,
+        (0..9)
+            .map(|k| kv(&key_of(k), &format!("0.{}", k)))
+            .collect(),
+    );
+}
+
+#[test]
+fn test_merge_next_n() {
+    let i1 = MockIterator::new(vec![kv("a", "1.a"), kv("c", "1.c")]);
+    let i2 = MockIterator::new(vec![kv("b", "2.b"), kv("c", "2.c")]);
+    let mut iter = MergeIterator::create(vec![Box::new(i1), Box::new(i2)]);
+    assert_eq!(iter.next_n(1).unwrap(), 1);
+    assert_eq!(iter.key().for_testing_key_ref(), b"b");
+    let mut iter = MergeIterator::create(vec![Box::new(MockIterator::new(vec![
+        kv("a", "1.a"),
+        kv("b", "1.b"),
+        kv("c", "1.c"),
+    ]))]);
+    assert_eq!(iter.next_n(5).unwrap(), 3);
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_merge_with_priorities() {
+    let build = |order: &[u64]| {
+        let iters = order
+            .iter()
+            .map(|&priority| {
+                (
+                    priority,
+                    Box::new(MockIterator::new(vec![
+                        kv("a", &format!("{}.a", priority)),
+                        kv(&format!("b{}", priority), "b"),
+                        kv("c", &format!("{}.c", priority)),
+                    ])),
+                )
+            })
+            .collect();
+        MergeIterator::create_with_priorities(iters)
+    };
+    let expected = vec![
+        kv("a", "3.a"),
+        kv("b10", "b"),
+        kv("b3", "b"),
+        kv("b7", "b"),
+        kv("c", "3.c"),
+    ];
+    for order in [[3, 7, 10], [10, 7, 3], [7, 10, 3]] {
+        let mut iter = build(&order);
+        check_iter_result_by_key(&mut iter, expected.clone());
+    }
+}
+
+#[test]
+fn test_merge_from_wrappers() {
+    let child = |name: &str| {
+        Box::new(MockIterator::new(vec![
+            kv("a", &format!("{}.a", name)),
+            kv(&format!("b_{}", name), "b"),
+            kv("c", &format!("{}.c", name)),
+        ]))
+    };
+    let mut iter = MergeIterator::from_wrappers(vec![
+        MergeWrapper::new(20, child("x")),
+        MergeWrapper::new(5, child("y")),
+        MergeWrapper::new(12, child("z")),
+    ]);
+    check_iter_result_by_key(
+        &mut iter,
+        vec![
+            kv("a", "y.a"),
+            kv("b_x", "b"),
+            kv("b_y", "b"),
+            kv("b_z", "b"),
+            kv("c", "y.c"),
+        ],
+    );
+
+    // reuse the children with new priorities after rewinding them
+    let mut wrappers = iter.into_wrappers();
+    assert_eq!(wrappers.len(), 3);
+    for wrapper in &mut wrappers {
+        wrapper.1.index = 0;
+        wrapper.0 = if wrapper.0 == 12 { 0 } else { wrapper.0 };
+    }
+    let mut iter = MergeIterator::from_wrappers(wrappers);
+    check_iter_result_by_key(
+        &mut iter,
+        vec![
+            kv("a", "z.a"),
+            kv("b_x", "b"),
+            kv("b_y", "b"),
+            kv("b_z", "b"),
+            kv("c", "z.c"),
+        ],
+    );
+}
+
+#[test]
+fn test_merge_prev() {
+    // SST `i` holds the keys divisible by `i + 2`, with values telling which SST they come from
+    let mut expected = Vec::<(String, String)>::new();
+    let mut iters = Vec::new();
+    for i in 0..3 {
+        let mut builder = SsTableBuilder::new(64);
+        for x in (0..60).filter(|x| x % (i + 2) == 0) {
+            let value = format!("{}.{}", i, x);
+            builder.add(
+                KeySlice::for_testing_from_slice_no_ts(key_of(x).as_bytes()),
+                value.as_bytes(),
+            );
+            if !expected.iter().any(|(k, _)| *k == key_of(x)) {
+                expected.push((key_of(x), value));
+            }
+        }
+        let sst = Arc::new(builder.build_in_memory(i, None).unwrap());
+        iters.push(Box::new(
+            SsTableIterator::create_and_seek_to_first(sst).unwrap(),
+        ));
+    }
+    expected.sort();
+    let check = |iter: &MergeIterator<SsTableIterator>, pos: usize| {
+        assert!(iter.is_valid());
+        assert_eq!(iter.key().for_testing_key_ref(), expected[pos].0.as_bytes());
+        assert_eq!(iter.value(), expected[pos].1.as_bytes());
+    };
+
+    let mut iter = MergeIterator::create(iters);
+    // forward then backward returns to the start
+    for pos in 0..20 {
+        check(&iter, pos);
+        iter.next().unwrap();
+    }
+    for pos in (0..20).rev() {
+        iter.prev().unwrap();
+        check(&iter, pos);
+    }
+    iter.prev().unwrap();
+    assert!(!iter.is_valid());
+
+    // zigzag through the whole range
+    iter.seek_to_key(KeySlice::for_testing_from_slice_no_ts(b""))
+        .unwrap();
+    let mut pos = 0;
+    while pos + 2 < expected.len() {
+        iter.next().unwrap();
+        iter.next().unwrap();
+        iter.prev().unwrap();
+        pos += 1;
+        check(&iter, pos);
+    }
+
+    // backward from the end
+    iter.seek_for_prev(KeySlice::for_testing_from_slice_no_ts(b"key_999"))
+        .unwrap();
+    for pos in (0..expected.len()).rev() {
+        check(&iter, pos);
+        iter.prev().unwrap();
+    }
+    assert!(!iter.is_valid());
+    iter.seek_for_prev(KeySlice::for_testing_from_slice_no_ts(b"key_013"))
+        .unwrap();
+    check(
+        &iter,
+        expected.iter().position(|(k, _)| k == "key_012").unwrap(),
+    );
+    iter.next().unwrap();
+    check(
+        &iter,
+        expected.iter().position(|(k, _)| k == "key_014").unwrap(),
+    );
+}
+
+fn peek_next(iter: &MergeIterator<MockIterator>) -> Option<Vec<u8>> {
+    iter.peek_next_key()
+        .map(|key| key.for_testing_key_ref().to_vec())
+}
+
+#[test]
+fn test_merge_peek_next_key() {
+    let i1 = MockIterator::new(vec![kv("a", "1.a"), kv("b", "1.b"), kv("e", "1.e")]);
+    let i2 = MockIterator::new(vec![kv("a", "2.a"), kv("c", "2.c"), kv("e", "2.e")]);
+    let i3 = MockIterator::new(vec![kv("b", "3.b"), kv("d", "3.d"), kv("f", "3.f")]);
+    let mut iter = MergeIterator::create(vec![Box::new(i1), Box::new(i2), Box::new(i3)]);
+    let mut keys = Vec::new();
+    while iter.is_valid() {
+        let peeked = peek_next(&iter);
+        keys.push(iter.key().for_testing_key_ref().to_vec());
+        iter.next().unwrap();
+        assert_eq!(
+            iter.is_valid()
+                .then(|| iter.key().for_testing_key_ref().to_vec()),
+            peeked
+        );
+    }
+    assert_eq!(keys, vec![b"a", b"b", b"c", b"d", b"e", b"f"]);
+
+    // the next keys of the current child and of the children at the current key are not known
+    // without moving them
+    let i1 = MockIterator::new(vec![kv("a", "1.a"), kv("b", "1.b"), kv("d", "1.d")]);
+    let i2 = MockIterator::new(vec![kv("a", "2.a"), kv("c", "2.c")]);
+    let mut iter = MergeIterator::create(vec![Box::new(i1), Box::new(i2)]);
+    assert_eq!(peek_next(&iter), None);
+    iter.next().unwrap();
+    assert_eq!(iter.key().for_testing_key_ref(), b"b");
+    assert_eq!(peek_next(&iter), Some(b"c".to_vec()));
+    iter.next().unwrap();
+    assert_eq!(peek_next(&iter), Some(b"d".to_vec()));
+    iter.next().unwrap();
+    assert_eq!(peek_next(&iter), None);
+
+    // nothing is known after moving backward
+    let dir = tempdir().unwrap();
+    let iters = (0..2)
+        .map(|i| {
+            let data = (0..10)
+                .filter(|x| x % 2 == i)
+                .map(|x| kv(&key_of(x), &format!("{}", x)))
+                .collect::<Vec<_>>();
+            let path = dir.path().join(format!("{}.sst", i));
+            let sst = Arc::new(generate_sst(i, path, data, None));
+            Box::new(SsTableIterator::create_and_seek_to_first(sst).unwrap())
+        })
+        .collect();
+    let mut iter = MergeIterator::create(iters);
+    iter.seek_to_key(KeySlice::for_testing_from_slice_no_ts(key_of(5).as_bytes()))
+        .unwrap();
+    iter.prev().unwrap();
+    assert_eq!(iter.key().for_testing_key_ref(), key_of(4).as_bytes());
+    assert!(iter.peek_next_key().is_none());
+    iter.next().unwrap();
+    assert_eq!(iter.key().for_testing_key_ref(), key_of(5).as_bytes());
+    assert_eq!(
+        iter.peek_next_key().unwrap().for_testing_key_ref(),
+        key_of(6).as_bytes()
+    );
+}
+
+#[test]
+fn test_merge_peek_next_key_many_children() {
+    // the child `i` holds the keys `x` with `x % num_children` being `i` or `i + 1`, so that each
+    // key is held by two children, and the next key by a child not at the current key
+    for num_children in [3, 5, 8, 13] {
+        let iters = (0..num_children)
+            .map(|i| {
+                let data = (0..num_children * 10)
+                    .filter(|x| [i, (i + 1) % num_children].contains(&(x % num_children)))
+                    .map(|x| kv(&key_of(x), &format!("{}.{}", i, x)))
+                    .collect();
+                Box::new(MockIterator::new(data))
+            })
+            .collect();
+        let mut iter = MergeIterator::create(iters);
+        let mut num_keys = 0;
+        while iter.is_valid() {
+            let peeked = peek_next(&iter);
+            iter.next().unwrap();
+            num_keys += 1;
+            assert_eq!(
+                iter.is_valid()
+                    .then(|| iter.key().for_testing_key_ref().to_vec()),
+                peeked
+            );
+        }
+        assert_eq!(num_keys, num_children * 10);
+    }
+}
+
+#[test]
+fn test_merge_many_children() {
+    // odd numbers of children leave the leaves of the tree at different depths
+    for num_children in [1, 2, 3, 5, 8, 13, 33] {
+        let mut expected = Vec::<(String, String)>::new();
+        let mut iters = Vec::new();
+        for i in 0..num_children {
+            let mut builder = SsTableBuilder::new(64);
+            for x in (0..200).filter(|x| (x * 7 + i * 13) % 5 < 2) {
+                let value = format!("{}.{}", i, x);
+                builder.add(
+                    KeySlice::for_testing_from_slice_no_ts(key_of(x).as_bytes()),
+                    value.as_bytes(),
+                );
+                if !expected.iter().any(|(k, _)| *k == key_of(x)) {
+                    expected.push((key_of(x), value));
+                }
+            }
+            let sst = Arc::new(builder.build_in_memory(i, None).unwrap());
+            iters.push(Box::new(
+                SsTableIterator::create_and_seek_to_first(sst).unwrap(),
+            ));
+        }
+        expected.sort();
+        let mut iter = MergeIterator::create(iters);
+        for (key, value) in &expected {
+            assert_eq!(iter.key().for_testing_key_ref(), key.as_bytes());
+            assert_eq!(iter.value(), value.as_bytes());
+            iter.next().unwrap();
+        }
+        assert!(!iter.is_valid());
+        iter.seek_for_prev(KeySlice::for_testing_from_slice_no_ts(b"key_999"))
+            .unwrap();
+        for (key, value) in expected.iter().rev() {
+            assert_eq!(iter.key().for_testing_key_ref(), key.as_bytes());
+            assert_eq!(iter.value(), value.as_bytes());
+            iter.prev().unwrap();
+        }
+        assert!(!iter.is_valid());
+    }
+}
@@ -0,0 +1,301 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ops::Bound;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::iterators::{ReversibleIterator, StorageIterator};
+use crate::key::KeySlice;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm, WriteBatch};
+use crate::mem_table::MemTable;
+use crate::merge_operator::{StringAppendOperator, UInt64AddOperator};
+use crate::table::{SsTableBuilder, SsTableIterator};
+use crate::ttl;
+use crate::vlog::ValueLogs;
+
+fn counter_options() -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.merge_operator = Some(Arc::new(UInt64AddOperator));
+    options
+}
+
+fn list_options() -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.enable_wal = true;
+    options.merge_operator = Some(Arc::new(StringAppendOperator::new(b",")));
+    options
+}
+
+fn count(value: Option<Bytes>) -> u64 {
+    u64::from_le_bytes(value.unwrap()[..].try_into().unwrap())
+}
+
+fn scan_all(storage: &MiniLsm) -> Vec<(Vec<u8>, Vec<u8>)> {
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    let mut result = Vec::new();
+    while iter.is_valid() {
+        result.push((iter.key().to_vec(), iter.value().to_vec()));
+        iter.next().unwrap();
+    }
+    result
+}
+
+#[test]
+fn test_merge_counter() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, counter_options()).unwrap();
+    let one = 1u64.to_le_bytes();
+    for _ in 0..10 {
+        storage.merge(b"hits", &one).unwrap();
+    }
+    assert_eq!(count(storage.get(b"hits").unwrap()), 10);
+    let snapshot = storage.new_snapshot();
+
+    // onto a put, and after a delete
+    storage.put(b"base", &100u64.to_le_bytes()).unwrap();
+    storage.merge(b"base", &one).unwrap();
+    storage.merge(b"hits", &5u64.to_le_bytes()).unwrap();
+    storage.delete(b"hits").unwrap();
+    storage.merge(b"hits", &one).unwrap();
+    storage
+        .write_batch(
+            WriteBatch::new()
+                .merge(b"base", &one)
+                .merge(b"new", &one)
+                .records(),
+        )
+        .unwrap();
+    assert_eq!(count(storage.get(b"base").unwrap()), 102);
+    assert_eq!(count(storage.get(b"hits").unwrap()), 1);
+    assert_eq!(count(snapshot.get(b"hits").unwrap()), 10);
+    let values = storage
+        .multi_get(&[b"new", b"missing", b"base"])
+        .unwrap()
+        .into_iter()
+        .map(|value| value.map(|value| count(Some(value))))
+        .collect::<Vec<_>>();
+    assert_eq!(values, vec![Some(1), None, Some(102)]);
+    let scanned = scan_all(&storage)
+        .into_iter()
+        .map(|(key, value)| (key, count(Some(value.into()))))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        scanned,
+        vec![
+            (b"base".to_vec(), 102),
+            (b"hits".to_vec(), 1),
+            (b"new".to_vec(), 1)
+        ]
+    );
+
+    // a range tombstone hides the operands written before it
+    storage.delete_range(b"hits", b"hitz").unwrap();
+    storage.merge(b"hits", &one).unwrap();
+    assert_eq!(count(storage.get(b"hits").unwrap()), 1);
+
+    // merging requires a merge operator
+    let no_operator = MiniLsm::open(
+        dir.path().join("other"),
+        LsmStorageOptions::default_for_week1_test(),
+    )
+    .unwrap();
+    assert!(no_operator.merge(b"hits", &one).is_err());
+}
+
+#[test]
+fn test_merge_iterator_directions() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(&dir, list_options()).unwrap();
+    storage.merge(b"a", b"1").unwrap();
+    storage.put(b"b", b"x").unwrap();
+    storage.merge(b"c", b"1").unwrap();
+    storage.merge(b"c", b"2").unwrap();
+    storage.put(b"d", b"y").unwrap();
+    storage.merge(b"d", b"1").unwrap();
+    storage.flush().unwrap();
+    storage.merge(b"a", b"2").unwrap();
+    storage.merge(b"d", b"2").unwrap();
+
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    let mut forward = Vec::new();
+    while iter.is_valid() {
+        forward.push((iter.key().to_vec(), iter.value().to_vec()));
+        iter.next().unwrap();
+    }
+    let expected = vec![
+        (b"a".to_vec(), b"1,2".to_vec()),
+        (b"b".to_vec(), b"x".to_vec()),
+        (b"c".to_vec(), b"1,2".to_vec()),
+        (b"d".to_vec(), b"y,1,2".to_vec()),
+    ];
+    assert_eq!(forward, expected);
+
+    iter.seek_for_prev(KeySlice::from_slice(b"z", 0)).unwrap();
+    let mut backward = Vec::new();
+    while iter.is_valid() {
+        backward.push((iter.key().to_vec(), iter.value().to_vec()));
+        iter.prev().unwrap();
+    }
+    backward.reverse();
+    assert_eq!(backward, expected);
+}
+
+#[test]
+fn test_merge_flush_and_compaction() {
+    let dir = tempdir().unwrap();
+    let mut options = list_options();
+    options.value_threshold = Some(16);
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    // the operands are folded onto a separated value
+    let base = vec![b'v'; 32];
+    storage.put(b"list", &base).unwrap();
+    for round in 0..3 {
+        storage
+            .merge(b"list", round.to_string().as_bytes())
+            .unwrap();
+        storage
+            .merge(b"other", round.to_string().as_bytes())
+            .unwrap();
+        storage.flush().unwrap();
+    }
+    storage.merge(b"other", b"3").unwrap();
+    let mut expected = base.clone();
+    expected.extend_from_slice(b",0,1,2");
+    assert_eq!(storage.get(b"list").unwrap().unwrap(), expected);
+    assert_eq!(storage.get(b"other").unwrap().unwrap(), &b"0,1,2,3"[..]);
+
+    // recovered from the WAL
+    storage.close().unwrap();
+    drop(storage);
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    assert_eq!(storage.get(b"other").unwrap().unwrap(), &b"0,1,2,3"[..]);
+
+    // folded into one version by the compaction to the bottom level
+    storage.flush().unwrap();
+    storage.force_full_compaction().unwrap();
+    let state = storage.inner.state.read().clone();
+    assert!(state.l0_sstables.is_empty());
+    let mut versions = Vec::new();
+    for sst_id in &state.levels[0].1 {
+        let sst = state.sstables[sst_id].clone();
+        let mut iter = SsTableIterator::create_and_seek_to_first(sst).unwrap();
+        while iter.is_valid() {
+            assert!(!ttl::is_merge_operand(iter.value()));
+            versions.push(iter.key().key_ref().to_vec());
+            iter.next().unwrap();
+        }
+    }
+    assert_eq!(versions, vec![b"list".to_vec(), b"other".to_vec()]);
+    assert_eq!(storage.get(b"list").unwrap().unwrap(), expected);
+    assert_eq!(storage.get(b"other").unwrap().unwrap(), &b"0,1,2,3"[..]);
+    storage.merge(b"other", b"4").unwrap();
+    assert_eq!(storage.get(b"other").unwrap().unwrap(), &b"0,1,2,3,4"[..]);
+}
+
+#[test]
+fn test_add_from_iter_merge_operands() {
+    let memtable = MemTable::create(0);
+    let put = |key: &[u8], ts: u64, value: &[u8]| {
+        memtable
+            .put(KeySlice::for_testing_from_slice_with_ts(key, ts), value)
+            .unwrap();
+    };
+    let operand = |value: &[u8]| ttl::encode_merge_operand(value);
+    // folded onto the put below the operands
+    put(b"a", 1, b"x");
+    put(b"a", 2, &operand(b"1"));
+    put(b"a", 3, &operand(b"2"));
+    // no version below the operands, which may be in the lower levels
+    put(b"b", 2, &operand(b"1"));
+    put(b"b", 3, &operand(b"2"));
+    // operands above the watermark are kept as is
+    put(b"c", 3, &operand(b"1"));
+    put(b"c", 5, &operand(b"2"));
+
+    let build = |keep_tombstones: bool, with_operator: bool| {
+        let dir = tempdir().unwrap();
+        let mut iter = memtable.scan(Bound::Unbounded, Bound::Unbounded);
+        let mut builder = SsTableBuilder::new(128);
+        if with_operator {
+            builder = builder.with_merge_operator(
+                Arc::new(StringAppendOperator::new(b",")),
+                ValueLogs::default().snapshot(),
+            );
+        }
+        builder
+            .add_from_iter(&mut iter, keep_tombstones, 3, &[], &[])
+            .unwrap();
+        let sst = Arc::new(builder.build_for_test(dir.path().join("1.sst")).unwrap());
+        let mut iter = SsTableIterator::create_and_seek_to_first(sst).unwrap();
+        let mut versions = Vec::new();
+        while iter.is_valid() {
+            let value = iter.value();
+            let kind = if ttl::is_merge_operand(value) {
+                "merge"
+            } else {
+                "value"
+            };
+            versions.push((
+                String::from_utf8(iter.key().key_ref().to_vec()).unwrap(),
+                iter.key().ts(),
+                kind,
+                String::from_utf8(ttl::decode_value(value).0.to_vec()).unwrap(),
+            ));
+            iter.next().unwrap();
+        }
+        versions
+    };
+    let versions = |expected: &[(&str, u64, &'static str, &str)]| {
+        expected
+            .iter()
+            .map(|(key, ts, kind, value)| (key.to_string(), *ts, *kind, value.to_string()))
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        build(true, true),
+        versions(&[
+            ("a", 3, "value", "x,1,2"),
+            ("b", 3, "merge", "2"),
+            ("b", 2, "merge", "1"),
+            ("c", 5, "merge", "2"),
+            ("c", 3, "merge", "1"),
+        ])
+    );
+    assert_eq!(
+        build(false, true),
+        versions(&[
+            ("a", 3, "value", "x,1,2"),
+            ("b", 3, "value", "1,2"),
+            ("c", 5, "merge", "2"),
+            ("c", 3, "value", "1"),
+        ])
+    );
+    // without a merge operator, the operands and the version below them are kept
+    assert_eq!(
+        build(false, false),
+        versions(&[
+            ("a", 3, "merge", "2"),
+            ("a", 2, "merge", "1"),
+            ("a", 1, "value", "x"),
+            ("b", 3, "merge", "2"),
+            ("b", 2, "merge", "1"),
+            ("c", 5, "merge", "2"),
+            ("c", 3, "merge", "1"),
+        ])
+    );
+}
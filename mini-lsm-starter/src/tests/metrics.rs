@@ -0,0 +1,110 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ops::Bound;
+use std::time::Duration;
+
+use tempfile::tempdir;
+
+use super::harness::key_of;
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+use crate::metrics::{Histogram, HistogramSnapshot};
+
+#[test]
+fn test_histogram() {
+    let histogram = Histogram::default();
+    assert_eq!(histogram.snapshot().percentile_micros(50.0), None);
+    for micros in [0, 1, 2, 3, 100, 100, 100, 1000] {
+        histogram.record(Duration::from_micros(micros));
+    }
+    histogram.record(Duration::from_secs(3600));
+    let snapshot = histogram.snapshot();
+    assert_eq!(snapshot.count, 9);
+    assert_eq!(snapshot.sum_micros, 1306 + 3_600_000_000);
+    // 0 and 1 in the first bucket, 2 in the second, 3 in the third, 100 in the one up to 128
+    assert_eq!(&snapshot.buckets[..3], &[2, 1, 1]);
+    assert_eq!(snapshot.buckets[7], 3);
+    assert_eq!(snapshot.percentile_micros(50.0), Some(128));
+    assert_eq!(snapshot.percentile_micros(80.0), Some(1024));
+    // the hour is beyond the last bound
+    assert_eq!(snapshot.percentile_micros(100.0), None);
+    assert_eq!(
+        HistogramSnapshot::bucket_bound_micros(snapshot.buckets.len() - 1),
+        None
+    );
+}
+
+#[test]
+fn test_storage_metrics() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.block_size = 256;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for idx in 0..1000 {
+        storage.put(&key_of(idx), b"value").unwrap();
+    }
+    storage.flush().unwrap();
+    for idx in 0..500 {
+        storage.delete(&key_of(idx)).unwrap();
+    }
+    storage.flush().unwrap();
+    storage.inner.clear_cache();
+    for _ in 0..2 {
+        for idx in (0..1000).step_by(100) {
+            storage.get(&key_of(idx)).unwrap();
+        }
+    }
+    let mut iter = storage
+        .scan(Bound::Included(&key_of(500)), Bound::Unbounded)
+        .unwrap();
+    while iter.is_valid() {
+        iter.next().unwrap();
+    }
+
+    let metrics = storage.metrics().snapshot();
+    assert_eq!(metrics.put_latency.count, 1500);
+    assert_eq!(metrics.get_latency.count, 20);
+    assert_eq!(metrics.scan_latency.count, 1);
+    // the second round of gets hits the blocks read by the first one
+    assert!(metrics.block_cache.hits >= 10);
+    assert!(metrics.block_cache.misses > 0);
+    assert!(metrics.block_cache_hit_rate() > 0.0 && metrics.block_cache_hit_rate() < 1.0);
+    let state = storage.inner.state.read().clone();
+    let flushed = state
+        .sstables
+        .values()
+        .map(|sst| sst.file_size())
+        .sum::<u64>();
+    assert_eq!(metrics.bytes_flushed, flushed);
+    assert_eq!(metrics.bytes_compacted, 0);
+    assert_eq!(metrics.write_amplification(), 1.0);
+
+    // the counters of the compacted SSTs are kept
+    storage.force_full_compaction().unwrap();
+    let compacted = storage.metrics().snapshot();
+    assert!(compacted.bytes_compacted > 0);
+    assert!(compacted.write_amplification() > 1.0);
+    assert!(compacted.block_cache.hits >= metrics.block_cache.hits);
+
+    let text = compacted.to_prometheus();
+    assert!(text.contains("# TYPE mini_lsm_get_latency_seconds histogram\n"));
+    assert!(text.contains("mini_lsm_get_latency_seconds_bucket{le=\"+Inf\"} 20\n"));
+    assert!(text.contains("mini_lsm_put_latency_seconds_count 1500\n"));
+    assert!(text.contains(&format!(
+        "mini_lsm_flushed_bytes_total {}\n",
+        compacted.bytes_flushed
+    )));
+    assert!(text.contains("# TYPE mini_lsm_stall_seconds_total counter\n"));
+}
@@ -0,0 +1,143 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::key::KeySlice;
+use crate::lsm_storage::{BlockCache, LsmStorageInner, LsmStorageOptions};
+use crate::table::{GetResult, SsTableBuilder};
+
+#[test]
+fn test_sst_get_kinds() {
+    let dir = tempdir().unwrap();
+    let cache = Arc::new(BlockCache::new(1024));
+    let mut builder = SsTableBuilder::new(64);
+    for idx in 0..50 {
+        let key = format!("key_{:03}", idx);
+        let value = if idx % 10 == 5 {
+            String::new()
+        } else {
+            format!("value_{:03}", idx)
+        };
+        builder.add(
+            KeySlice::for_testing_from_slice_no_ts(key.as_bytes()),
+            value.as_bytes(),
+        );
+    }
+    let sst = builder
+        .build(1, Some(cache.clone()), dir.path().join("1.sst"))
+        .unwrap();
+    assert!(sst.num_of_blocks() > 3);
+
+    let last = format!("key_{:03}", 49);
+    let keys: Vec<&[u8]> = vec![
+        b"a",
+        b"key_000",
+        b"key_001",
+        b"key_005",
+        last.as_bytes(),
+        b"zzz",
+    ];
+    let lookups = keys
+        .iter()
+        .map(|key| KeySlice::for_testing_from_slice_no_ts(key))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        sst.get_kinds(&lookups).unwrap(),
+        vec![
+            GetResult::NotFound,
+            GetResult::Found(Bytes::from_static(b"value_000")),
+            GetResult::Found(Bytes::from_static(b"value_001")),
+            GetResult::Deleted,
+            GetResult::Found(Bytes::from_static(b"value_049")),
+            GetResult::NotFound,
+        ]
+    );
+    for (lookup, kind) in lookups.iter().zip(sst.get_kinds(&lookups).unwrap()) {
+        assert_eq!(sst.get_kind(*lookup).unwrap(), kind);
+    }
+    // only the blocks holding the keys are read
+    let read_blocks = lookups[1..5]
+        .iter()
+        .map(|lookup| sst.find_block_idx(*lookup).unwrap())
+        .collect::<Vec<_>>();
+    assert!(read_blocks.len() < sst.num_of_blocks());
+    for idx in 0..sst.num_of_blocks() {
+        assert_eq!(
+            cache.contains_block(1, idx),
+            read_blocks.contains(&idx),
+            "block {idx}"
+        );
+    }
+}
+
+#[test]
+fn test_storage_multi_get() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.block_size = 64;
+    let storage = LsmStorageInner::open(dir.path(), options).unwrap();
+    let flush = || {
+        storage
+            .force_freeze_memtable(&storage.state_lock.lock())
+            .unwrap();
+        storage.force_flush_next_imm_memtable().unwrap();
+    };
+    for idx in 0..30 {
+        let key = format!("key_{:03}", idx);
+        storage.put(key.as_bytes(), b"1").unwrap();
+    }
+    flush();
+    storage.put(b"key_001", b"2").unwrap();
+    storage.delete(b"key_002").unwrap();
+    flush();
+    storage.put(b"key_003", b"3").unwrap();
+    storage.delete(b"key_004").unwrap();
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    storage.put(b"key_005", b"4").unwrap();
+    storage.put(b"key_100", b"4").unwrap();
+
+    let keys: Vec<&[u8]> = vec![
+        b"key_100", b"key_029", b"key_005", b"key_004", b"key_003", b"key_002", b"key_001",
+        b"key_000", b"key_050", b"key_001",
+    ];
+    let values = storage.multi_get(&keys).unwrap();
+    assert_eq!(
+        values,
+        keys.iter()
+            .map(|key| storage.get(key).unwrap())
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(
+        values,
+        vec![
+            Some(Bytes::from_static(b"4")),
+            Some(Bytes::from_static(b"1")),
+            Some(Bytes::from_static(b"4")),
+            None,
+            Some(Bytes::from_static(b"3")),
+            None,
+            Some(Bytes::from_static(b"2")),
+            Some(Bytes::from_static(b"1")),
+            None,
+            Some(Bytes::from_static(b"2")),
+        ]
+    );
+    assert!(storage.multi_get(&[]).unwrap().is_empty());
+}
@@ -0,0 +1,116 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use crate::iterators::StorageIterator;
+use crate::key::KeySlice;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+use crate::table::{FixedPrefixExtractor, PREFIX_EXTRACTOR_PROPERTY, SsTableBuilder};
+
+fn collect_keys(storage: &LsmStorageInner, prefix: &[u8]) -> Vec<Vec<u8>> {
+    let mut iter = storage.prefix_scan(prefix).unwrap();
+    let mut keys = Vec::new();
+    while iter.is_valid() {
+        keys.push(iter.key().to_vec());
+        iter.next().unwrap();
+    }
+    keys
+}
+
+#[test]
+fn test_sst_may_contain_prefix() {
+    let dir = tempdir().unwrap();
+    let extractor = Arc::new(FixedPrefixExtractor::new(4));
+    let mut builder = SsTableBuilder::new(128)
+        .with_bloom_bits_per_key(20)
+        .with_prefix_extractor(extractor.clone());
+    for key in [&b"aaaa1"[..], b"aaaa2", b"bbbb1", b"dddd1"] {
+        builder.add(KeySlice::for_testing_from_slice_no_ts(key), b"value");
+    }
+    let sst = builder.build_for_test(dir.path().join("1.sst")).unwrap();
+    assert_eq!(
+        sst.properties().unwrap().user_properties[PREFIX_EXTRACTOR_PROPERTY],
+        b"fixed:4"
+    );
+    assert!(sst.may_contain_prefix(extractor.as_ref(), b"aaaa"));
+    assert!(sst.may_contain_prefix(extractor.as_ref(), b"bbbb1"));
+    assert!(!sst.may_contain_prefix(extractor.as_ref(), b"cccc"));
+    // too short to have a prefix
+    assert!(sst.may_contain_prefix(extractor.as_ref(), b"cc"));
+    // the prefixes of another extractor are not in the bloom filter
+    assert!(sst.may_contain_prefix(&FixedPrefixExtractor::new(3), b"ccc"));
+
+    let mut builder = SsTableBuilder::new(128);
+    builder.add(KeySlice::for_testing_from_slice_no_ts(b"aaaa1"), b"value");
+    let sst = builder.build_for_test(dir.path().join("2.sst")).unwrap();
+    assert!(sst.may_contain_prefix(extractor.as_ref(), b"cccc"));
+}
+
+#[test]
+fn test_prefix_scan() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.prefix_extractor = Some(Arc::new(FixedPrefixExtractor::new(2)));
+    let storage = LsmStorageInner::open(&dir, options).unwrap();
+    for prefix in [&b"aa"[..], b"ab", b"b\xff"] {
+        for idx in 0..3 {
+            let mut key = prefix.to_vec();
+            key.push(b'0' + idx);
+            storage.put(&key, b"value").unwrap();
+        }
+    }
+    storage.flush().unwrap();
+    storage.put(b"aa3", b"value").unwrap();
+    storage.delete(b"aa0").unwrap();
+    storage.put(b"\xff\xff", b"value").unwrap();
+
+    assert_eq!(
+        collect_keys(&storage, b"aa"),
+        vec![b"aa1".to_vec(), b"aa2".to_vec(), b"aa3".to_vec()]
+    );
+    assert_eq!(collect_keys(&storage, b"ab1"), vec![b"ab1".to_vec()]);
+    assert_eq!(collect_keys(&storage, b"b\xff").len(), 3);
+    assert_eq!(collect_keys(&storage, b"\xff"), vec![b"\xff\xff".to_vec()]);
+    assert_eq!(collect_keys(&storage, b"").len(), 10);
+    assert!(collect_keys(&storage, b"ac").is_empty());
+}
+
+#[test]
+fn test_prefix_scan_skips_ssts() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.prefix_extractor = Some(Arc::new(FixedPrefixExtractor::new(4)));
+    let storage = LsmStorageInner::open(&dir, options).unwrap();
+    for prefix in ["key1", "key3"] {
+        for idx in 0..100 {
+            storage
+                .put(format!("{}_{:03}", prefix, idx).as_bytes(), b"value")
+                .unwrap();
+        }
+    }
+    storage.flush().unwrap();
+    // `key2` is within the key range of the SST but not in its bloom filter
+    let lookups = |storage: &LsmStorageInner| {
+        let stats = storage.metrics.snapshot().block_cache;
+        stats.hits + stats.misses
+    };
+    let before = lookups(&storage);
+    assert!(collect_keys(&storage, b"key2").is_empty());
+    assert_eq!(lookups(&storage), before);
+    assert_eq!(collect_keys(&storage, b"key3").len(), 100);
+    assert!(lookups(&storage) > before);
+}
@@ -0,0 +1,234 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ops::Bound;
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use crate::error::Error;
+use crate::iterators::{ReversibleIterator, StorageIterator};
+use crate::key::KeySlice;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions, MiniLsm};
+use crate::mem_table::MemTable;
+use crate::range_tombstone::{RangeTombstone, RangeTombstones};
+use crate::table::{SsTableBuilder, SsTableIterator};
+
+fn scan_keys(storage: &LsmStorageInner) -> Vec<Vec<u8>> {
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    let mut forward = Vec::new();
+    while iter.is_valid() {
+        forward.push(iter.key().to_vec());
+        iter.next().unwrap();
+    }
+    iter.seek_for_prev(KeySlice::for_testing_from_slice_no_ts(b"z"))
+        .unwrap();
+    let mut backward = Vec::new();
+    while iter.is_valid() {
+        backward.push(iter.key().to_vec());
+        iter.prev().unwrap();
+    }
+    backward.reverse();
+    assert_eq!(forward, backward);
+    forward
+}
+
+fn keys(keys: &[&str]) -> Vec<Vec<u8>> {
+    keys.iter().map(|key| key.as_bytes().to_vec()).collect()
+}
+
+#[test]
+fn test_range_tombstone_encode() {
+    let tombstone = RangeTombstone::new(b"b", b"d", 3);
+    assert!(!tombstone.contains(b"a"));
+    assert!(tombstone.contains(b"b"));
+    assert!(tombstone.contains(b"c"));
+    assert!(!tombstone.contains(b"d"));
+    assert!(tombstone.covers(b"c", 2));
+    assert!(!tombstone.covers(b"c", 3));
+
+    let tombstones = vec![tombstone, RangeTombstone::new(b"", b"zzz", 7)];
+    let mut buf = Vec::new();
+    RangeTombstone::encode_block(&tombstones, &mut buf);
+    assert_eq!(RangeTombstone::decode_block(&buf).unwrap(), tombstones);
+    let mut empty = Vec::new();
+    RangeTombstone::encode_block(&[], &mut empty);
+    assert!(RangeTombstone::decode_block(&empty).unwrap().is_empty());
+
+    buf[5] ^= 1;
+    let err = RangeTombstone::decode_block(&buf).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<Error>(),
+        Some(Error::Corruption { .. })
+    ));
+
+    // the tombstones newer than the read timestamp are not visible
+    let visible = RangeTombstones::new(tombstones, 5);
+    assert!(visible.covers(b"c", 2));
+    assert!(!visible.covers(b"e", 2));
+}
+
+#[test]
+fn test_storage_delete_range() {
+    let dir = tempdir().unwrap();
+    let storage = Arc::new(
+        LsmStorageInner::open(dir.path(), LsmStorageOptions::default_for_week1_test()).unwrap(),
+    );
+    let flush = || {
+        storage
+            .force_freeze_memtable(&storage.state_lock.lock())
+            .unwrap();
+        storage.force_flush_next_imm_memtable().unwrap();
+    };
+    for key in ["a", "b", "c"] {
+        storage.put(key.as_bytes(), b"1").unwrap();
+    }
+    flush();
+    for key in ["d", "e"] {
+        storage.put(key.as_bytes(), b"1").unwrap();
+    }
+    let snapshot = storage.new_snapshot();
+    storage.delete_range(b"b", b"e").unwrap();
+    // an empty range writes nothing
+    storage.delete_range(b"x", b"a").unwrap();
+    assert_eq!(storage.mvcc().latest_commit_ts(), 6);
+
+    let check = |storage: &LsmStorageInner| {
+        assert_eq!(scan_keys(storage), keys(&["a", "e", "f"]));
+        assert_eq!(storage.get(b"a").unwrap().unwrap(), &b"1"[..]);
+        assert_eq!(storage.get(b"b").unwrap(), None);
+        assert_eq!(storage.get(b"d").unwrap(), None);
+        assert_eq!(storage.get(b"e").unwrap().unwrap(), &b"1"[..]);
+        assert_eq!(
+            storage.multi_get(&[b"c", b"a", b"e", b"d"]).unwrap(),
+            vec![None, Some(b"1"[..].into()), Some(b"1"[..].into()), None]
+        );
+    };
+    // a key written after the delete is visible again
+    storage.put(b"c", b"2").unwrap();
+    storage.delete(b"c").unwrap();
+    storage.put(b"f", b"1").unwrap();
+    check(&storage);
+
+    // the snapshot taken before the delete still sees the keys
+    assert_eq!(snapshot.get(b"b").unwrap().unwrap(), &b"1"[..]);
+    let mut iter = snapshot.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    let mut count = 0;
+    while iter.is_valid() {
+        count += 1;
+        iter.next().unwrap();
+    }
+    assert_eq!(count, 5);
+
+    // the tombstone is persisted in the SST, and deletes keys from the older SSTs
+    flush();
+    assert!(
+        !storage.state.read().l0_sstables.is_empty()
+            && storage.state.read().memtable.range_tombstones().is_empty()
+    );
+    check(&storage);
+
+    // an SST may hold a range tombstone only
+    storage.delete_range(b"a", b"b").unwrap();
+    flush();
+    let state = storage.state.read().clone();
+    let sst = &state.sstables[&state.l0_sstables[0]];
+    assert_eq!(sst.num_of_blocks(), 0);
+    assert_eq!(sst.range_tombstones().len(), 1);
+    assert_eq!(storage.get(b"a").unwrap(), None);
+    assert_eq!(scan_keys(&storage), keys(&["e", "f"]));
+}
+
+#[test]
+fn test_storage_delete_range_recover() {
+    for enable_wal in [true, false] {
+        let dir = tempdir().unwrap();
+        let mut options = LsmStorageOptions::default_for_week1_test();
+        options.enable_wal = enable_wal;
+        let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+        for key in ["a", "b", "c"] {
+            storage.put(key.as_bytes(), b"1").unwrap();
+        }
+        storage.delete_range(b"a", b"c").unwrap();
+        storage.close().unwrap();
+        drop(storage);
+
+        let storage = MiniLsm::open(&dir, options).unwrap();
+        assert_eq!(storage.get(b"a").unwrap(), None);
+        assert_eq!(storage.get(b"b").unwrap(), None);
+        assert_eq!(storage.get(b"c").unwrap().unwrap(), &b"1"[..]);
+    }
+}
+
+#[test]
+fn test_add_from_iter_range_tombstones() {
+    let memtable = MemTable::create(0);
+    let put = |key: &[u8], ts: u64, value: &[u8]| {
+        memtable
+            .put(KeySlice::for_testing_from_slice_with_ts(key, ts), value)
+            .unwrap();
+    };
+    put(b"a", 1, b"a1");
+    put(b"b", 1, b"b1");
+    put(b"b", 4, b"b4");
+    put(b"c", 2, b"c2");
+    put(b"d", 1, b"d1");
+    let tombstones = [
+        RangeTombstone::new(b"a", b"c", 3),
+        RangeTombstone::new(b"c", b"d", 5),
+    ];
+
+    let build = |keep_tombstones: bool, watermark: u64| {
+        let dir = tempdir().unwrap();
+        let mut iter = memtable.scan(Bound::Unbounded, Bound::Unbounded);
+        let mut builder = SsTableBuilder::new(128);
+        builder
+            .add_from_iter(&mut iter, keep_tombstones, watermark, &tombstones, &[])
+            .unwrap();
+        let sst = Arc::new(builder.build_for_test(dir.path().join("1.sst")).unwrap());
+        let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+        let mut versions = Vec::new();
+        while iter.is_valid() {
+            versions.push((iter.key().key_ref().to_vec(), iter.key().ts()));
+            iter.next().unwrap();
+        }
+        (versions, sst.range_tombstones().len())
+    };
+
+    // the covered versions are dropped only if the tombstone is at or below the watermark
+    assert_eq!(
+        build(true, 4),
+        (
+            vec![(b"b".to_vec(), 4), (b"c".to_vec(), 2), (b"d".to_vec(), 1)],
+            2
+        )
+    );
+    assert_eq!(
+        build(false, 5),
+        (vec![(b"b".to_vec(), 4), (b"d".to_vec(), 1)], 0)
+    );
+    assert_eq!(
+        build(false, 2),
+        (
+            vec![
+                (b"a".to_vec(), 1),
+                (b"b".to_vec(), 4),
+                (b"b".to_vec(), 1),
+                (b"c".to_vec(), 2),
+                (b"d".to_vec(), 1)
+            ],
+            2
+        )
+    );
+}
@@ -0,0 +1,87 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+use tempfile::tempdir;
+
+use super::harness::sync;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+use crate::rate_limiter::{IoPriority, RateLimiter};
+
+#[test]
+fn test_rate_limiter() {
+    let limiter = RateLimiter::new(100_000, 10_000);
+    let start = Instant::now();
+    // the bucket starts full
+    limiter.request(10_000, IoPriority::Low);
+    assert!(start.elapsed() < Duration::from_millis(50));
+    // larger than the burst, granted in pieces
+    limiter.request(30_000, IoPriority::Low);
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(280), "{:?}", elapsed);
+    assert!(elapsed < Duration::from_secs(2), "{:?}", elapsed);
+    assert_eq!(limiter.total_bytes(IoPriority::Low), 40_000);
+    assert_eq!(limiter.total_bytes(IoPriority::High), 0);
+}
+
+#[test]
+fn test_rate_limiter_priority() {
+    let limiter = Arc::new(RateLimiter::new(100_000, 20_000));
+    limiter.request(20_000, IoPriority::Low);
+    let done = Arc::new(Mutex::new(Vec::new()));
+    let spawn = |priority: IoPriority| {
+        let limiter = limiter.clone();
+        let done = done.clone();
+        std::thread::spawn(move || {
+            limiter.request(20_000, priority);
+            done.lock().push(priority);
+        })
+    };
+    let low = spawn(IoPriority::Low);
+    std::thread::sleep(Duration::from_millis(20));
+    let high = spawn(IoPriority::High);
+    low.join().unwrap();
+    high.join().unwrap();
+    // the flush overtakes the compaction that started waiting earlier
+    assert_eq!(*done.lock(), vec![IoPriority::High, IoPriority::Low]);
+}
+
+#[test]
+fn test_rate_limited_flush() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.background_write_rate = Some(1 << 30);
+    options.background_write_burst = 4096;
+    let storage = LsmStorageInner::open(&dir, options).unwrap();
+    for idx in 0..1000 {
+        let key = format!("key_{:05}", idx);
+        storage.put(key.as_bytes(), key.as_bytes()).unwrap();
+    }
+    sync(&storage);
+    let sst_id = storage.state.read().l0_sstables[0];
+    let file_size = std::fs::metadata(storage.path_of_sst(sst_id))
+        .unwrap()
+        .len();
+    assert!(file_size > 4096);
+    let limiter = storage.rate_limiter.as_ref().unwrap();
+    assert_eq!(limiter.total_bytes(IoPriority::High), file_size);
+    assert_eq!(limiter.total_bytes(IoPriority::Low), 0);
+    assert_eq!(
+        storage.get(b"key_00999").unwrap().unwrap(),
+        &b"key_00999"[..]
+    );
+}
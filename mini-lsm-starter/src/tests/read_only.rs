@@ -0,0 +1,109 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use tempfile::tempdir;
+
+use super::harness::{key_of, wal_options};
+use crate::error::Error;
+use crate::lsm_storage::MiniLsm;
+
+/// The name and content of every file in `path`.
+fn dir_contents(path: &Path) -> BTreeMap<String, Vec<u8>> {
+    std::fs::read_dir(path)
+        .unwrap()
+        .map(|entry| {
+            let entry = entry.unwrap();
+            (
+                entry.file_name().into_string().unwrap(),
+                std::fs::read(entry.path()).unwrap(),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn test_open_readonly() {
+    let dir = tempdir().unwrap();
+    let primary = MiniLsm::open(&dir, wal_options()).unwrap();
+    for idx in 0..100 {
+        primary.put(&key_of(idx), b"1").unwrap();
+    }
+    primary.flush().unwrap();
+    primary.put(b"unflushed", b"1").unwrap();
+    primary.sync().unwrap();
+
+    let contents = dir_contents(dir.path());
+    let reader = MiniLsm::open_readonly(&dir, wal_options()).unwrap();
+    assert_eq!(reader.get(&key_of(0)).unwrap().unwrap(), &b"1"[..]);
+    // only the flushed data is visible
+    assert_eq!(reader.get(b"unflushed").unwrap(), None);
+
+    let is_read_only = |result: anyhow::Result<()>| {
+        matches!(
+            result.unwrap_err().downcast_ref::<Error>(),
+            Some(Error::ReadOnly)
+        )
+    };
+    assert!(is_read_only(reader.put(b"1", b"1")));
+    assert!(is_read_only(reader.delete_range(b"1", b"2")));
+    assert!(is_read_only(reader.flush()));
+    assert!(is_read_only(reader.force_full_compaction()));
+    assert!(is_read_only(reader.gc_value_log().map(|_| ())));
+    reader.close().unwrap();
+    drop(reader);
+    assert_eq!(dir_contents(dir.path()), contents);
+
+    // the primary cannot be refreshed
+    assert!(primary.refresh().is_err());
+}
+
+#[test]
+fn test_readonly_refresh() {
+    let dir = tempdir().unwrap();
+    let primary = MiniLsm::open(&dir, wal_options()).unwrap();
+    for idx in 0..100 {
+        primary.put(&key_of(idx), b"1").unwrap();
+    }
+    primary.flush().unwrap();
+    let reader = MiniLsm::open_readonly(&dir, wal_options()).unwrap();
+
+    for idx in 50..150 {
+        primary.put(&key_of(idx), b"2").unwrap();
+    }
+    primary.delete(&key_of(0)).unwrap();
+    primary.flush().unwrap();
+    primary.force_full_compaction().unwrap();
+
+    // the SSTs removed by the compaction stay readable until the next refresh
+    assert_eq!(reader.get(&key_of(0)).unwrap().unwrap(), &b"1"[..]);
+    assert_eq!(reader.get(&key_of(50)).unwrap().unwrap(), &b"1"[..]);
+    assert_eq!(reader.get(&key_of(100)).unwrap(), None);
+
+    reader.refresh().unwrap();
+    assert_eq!(reader.get(&key_of(0)).unwrap(), None);
+    assert_eq!(reader.get(&key_of(50)).unwrap().unwrap(), &b"2"[..]);
+    assert_eq!(reader.get(&key_of(100)).unwrap().unwrap(), &b"2"[..]);
+    {
+        let primary_state = primary.inner.state.read();
+        let reader_state = reader.inner.state.read();
+        assert!(reader_state.l0_sstables.is_empty());
+        assert_eq!(reader_state.levels, primary_state.levels);
+    }
+    // nothing changed since
+    reader.refresh().unwrap();
+    assert_eq!(reader.get(&key_of(149)).unwrap().unwrap(), &b"2"[..]);
+}
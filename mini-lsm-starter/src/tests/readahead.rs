@@ -0,0 +1,159 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ops::Bound;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tempfile::tempdir;
+
+use super::harness::{build_sst, key_of};
+use crate::iterators::StorageIterator;
+use crate::iterators::concat_iterator::SstConcatIterator;
+use crate::key::KeySlice;
+use crate::lsm_storage::{BlockCache, LsmStorageOptions, MiniLsm};
+use crate::table::SsTableIterator;
+
+/// Wait for the background prefetch to load the block.
+fn wait_for_block(cache: &BlockCache, sst_id: usize, block_idx: usize) -> bool {
+    for _ in 0..100 {
+        if cache.contains_block(sst_id, block_idx) {
+            return true;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    false
+}
+
+fn entries(mut iter: impl for<'a> StorageIterator<KeyType<'a> = KeySlice<'a>>) -> Vec<Vec<u8>> {
+    let mut result = Vec::new();
+    while iter.is_valid() {
+        result.push(iter.key().key_ref().to_vec());
+        iter.next().unwrap();
+    }
+    result
+}
+
+#[test]
+fn test_sst_iterator_readahead() {
+    let dir = tempdir().unwrap();
+    let cache = Arc::new(BlockCache::new(4096));
+    let sst = build_sst(1, 0..500, Some(cache.clone()), dir.path().join("1.sst"));
+    let num_blocks = sst.num_of_blocks();
+    assert!(num_blocks > 20);
+    let block_len = sst.block_meta_at(3).unwrap().len;
+
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone())
+        .unwrap()
+        .with_readahead(block_len * 4);
+    let mut keys = Vec::new();
+    while iter.current_block_index() < 2 {
+        keys.push(iter.key().key_ref().to_vec());
+        iter.next().unwrap();
+    }
+    // sequential after moving through two blocks, the next ones are prefetched
+    assert!(wait_for_block(&cache, 1, 3));
+    assert!(wait_for_block(&cache, 1, 5));
+    assert!(!cache.contains_block(1, num_blocks - 1));
+    // the readahead does not change what the iterator yields
+    keys.extend(entries(iter));
+    assert_eq!(keys, (0..500).map(key_of).collect::<Vec<_>>());
+
+    // seeking around is not sequential, with a cache of its own as the prefetches above may
+    // still be running
+    let cache = Arc::new(BlockCache::new(4096));
+    let sst = build_sst(2, 0..500, Some(cache.clone()), dir.path().join("2.sst"));
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone())
+        .unwrap()
+        .with_readahead(block_len * 4);
+    for idx in [100, 400, 200, 300] {
+        iter.seek_to_key(KeySlice::for_testing_from_slice_no_ts(&key_of(idx)))
+            .unwrap();
+    }
+    std::thread::sleep(Duration::from_millis(100));
+    assert_eq!(cache.entry_count(), 5);
+
+    // nothing is prefetched without readahead
+    let cache = Arc::new(BlockCache::new(4096));
+    let sst = build_sst(3, 0..500, Some(cache.clone()), dir.path().join("3.sst"));
+    let mut iter = SstConcatIterator::create_and_seek_to_first(vec![sst.clone()]).unwrap();
+    for _ in 0..100 {
+        iter.next().unwrap();
+    }
+    std::thread::sleep(Duration::from_millis(100));
+    let block_idx = sst
+        .find_block_idx(KeySlice::for_testing_from_slice_no_ts(&key_of(100)))
+        .unwrap();
+    assert_eq!(cache.entry_count(), block_idx + 1);
+
+    // the tables opened by the concat iterator read ahead as well
+    let mut iter = iter.with_readahead(block_len * 4);
+    for _ in 0..100 {
+        iter.next().unwrap();
+    }
+    let block_idx = sst
+        .find_block_idx(KeySlice::for_testing_from_slice_no_ts(&key_of(200)))
+        .unwrap();
+    assert!(wait_for_block(&cache, 3, block_idx + 2));
+}
+
+#[test]
+fn test_scan_readahead() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.block_size = 64;
+    options.readahead_bytes = 1024;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    for idx in 0..500 {
+        storage
+            .put(&key_of(idx), format!("value_{:05}", idx).as_bytes())
+            .unwrap();
+    }
+    storage.flush().unwrap();
+    storage.inner.clear_cache();
+
+    let mut iter = storage
+        .scan(Bound::Included(&key_of(100)), Bound::Unbounded)
+        .unwrap();
+    let mut count = 0;
+    while iter.is_valid() {
+        assert_eq!(iter.key(), key_of(100 + count));
+        count += 1;
+        iter.next().unwrap();
+    }
+    assert_eq!(count, 400);
+    // the blocks before the lower bound are not read
+    let state = storage.inner.state.read().clone();
+    let sst_id = state.l0_sstables[0];
+    assert!(!storage.inner.block_cache.contains_block(sst_id, 0));
+    assert!(storage.inner.block_cache.entry_count() > 0);
+}
+
+#[test]
+fn test_prefetch_skips_dropped_sst() {
+    let dir = tempdir().unwrap();
+    let cache = Arc::new(BlockCache::new(4096));
+    let sst = build_sst(1, 0..500, Some(cache.clone()), dir.path().join("1.sst"));
+    let num_blocks = sst.num_of_blocks();
+    sst.prefetch_blocks(0..num_blocks);
+    drop(sst);
+    std::thread::sleep(Duration::from_millis(100));
+    assert!((0..num_blocks).all(|block_idx| !cache.contains_block(1, block_idx)));
+
+    // the prefetch thread keeps serving the tables still alive
+    let sst = build_sst(2, 0..500, Some(cache.clone()), dir.path().join("2.sst"));
+    sst.prefetch_blocks(2..4);
+    assert!(wait_for_block(&cache, 2, 3));
+    assert!(!cache.contains_block(2, 1));
+}
@@ -0,0 +1,236 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::time::Duration;
+
+use tempfile::tempdir;
+
+use super::harness::{key_of, leveled_options};
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm, WriteBatchRecord};
+use crate::replication::{ReplicationBatch, ReplicationOp, SEGMENT_SIZE, Subscription};
+
+fn options() -> LsmStorageOptions {
+    let mut options = leveled_options();
+    options.enable_wal = true;
+    options.enable_replication_log = true;
+    options.value_threshold = Some(16);
+    options.target_sst_size = 4096;
+    options
+}
+
+fn scan_all(storage: &MiniLsm) -> BTreeMap<Vec<u8>, Vec<u8>> {
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    let mut result = BTreeMap::new();
+    while iter.is_valid() {
+        result.insert(iter.key().to_vec(), iter.value().to_vec());
+        iter.next().unwrap();
+    }
+    result
+}
+
+/// Apply a replicated batch to `follower`, returning its sequence number.
+fn apply(follower: &MiniLsm, batch: &ReplicationBatch) -> u64 {
+    let mut records = Vec::new();
+    for record in &batch.records {
+        match &record.op {
+            ReplicationOp::Put { value, .. } => {
+                records.push(WriteBatchRecord::Put(record.key.clone(), value.clone()))
+            }
+            ReplicationOp::Delete => records.push(WriteBatchRecord::Del(record.key.clone())),
+            ReplicationOp::Merge(operand) => {
+                records.push(WriteBatchRecord::Merge(record.key.clone(), operand.clone()))
+            }
+            ReplicationOp::DeleteRange { end } => follower.delete_range(&record.key, end).unwrap(),
+        }
+    }
+    if !records.is_empty() {
+        follower.write_batch(&records).unwrap();
+    }
+    batch.sequence
+}
+
+/// Apply the batches streamed so far, returning the last sequence number applied.
+fn catch_up(follower: &MiniLsm, subscription: &mut Subscription, mut applied: u64) -> u64 {
+    while let Some(batch) = subscription.try_next().unwrap() {
+        assert!(batch.sequence > applied);
+        applied = apply(follower, &batch);
+    }
+    applied
+}
+
+#[test]
+fn test_replication_follower() {
+    let dir = tempdir().unwrap();
+    let leader = MiniLsm::open(dir.path().join("leader"), options()).unwrap();
+    let follower = MiniLsm::open(dir.path().join("follower"), options()).unwrap();
+    for idx in 0..300 {
+        // every other value goes to the value log of the leader
+        let value = vec![b'a' + (idx % 2) as u8; if idx % 2 == 0 { 8 } else { 32 }];
+        leader.put(&key_of(idx), &value).unwrap();
+    }
+    leader.force_flush().unwrap();
+    for idx in (0..300).step_by(7) {
+        leader.delete(&key_of(idx)).unwrap();
+    }
+    leader.delete_range(&key_of(100), &key_of(150)).unwrap();
+    leader
+        .write_batch(&[
+            WriteBatchRecord::Put(key_of(120), b"batch".to_vec()),
+            WriteBatchRecord::Del(key_of(1)),
+        ])
+        .unwrap();
+    leader
+        .put_with_ttl(b"ttl", b"value", Duration::from_secs(3600))
+        .unwrap();
+
+    let mut subscription = leader.subscribe(1).unwrap();
+    let applied = catch_up(&follower, &mut subscription, 0);
+    assert_eq!(scan_all(&follower), scan_all(&leader));
+    assert_eq!(applied, leader.inner.mvcc().latest_commit_ts());
+
+    // the TTL is replicated with the value
+    let mut subscription = leader.subscribe(applied).unwrap();
+    let batch = subscription.try_next().unwrap().unwrap();
+    assert_eq!(batch.sequence, applied);
+    assert!(matches!(
+        batch.records[0].op,
+        ReplicationOp::Put {
+            expire_at: Some(_),
+            ..
+        }
+    ));
+    assert!(subscription.try_next().unwrap().is_none());
+}
+
+#[test]
+fn test_replication_resume() {
+    let dir = tempdir().unwrap();
+    let follower = MiniLsm::open(dir.path().join("follower"), options()).unwrap();
+    let leader = MiniLsm::open(dir.path().join("leader"), options()).unwrap();
+    for idx in 0..100 {
+        leader.put(&key_of(idx), b"v1").unwrap();
+    }
+    let mut subscription = leader.subscribe(1).unwrap();
+    let applied = catch_up(&follower, &mut subscription, 0);
+    drop(subscription);
+    leader.close().unwrap();
+    drop(leader);
+
+    // the follower reconnects to the reopened leader from the next sequence number
+    let leader = MiniLsm::open(dir.path().join("leader"), options()).unwrap();
+    for idx in 50..150 {
+        leader.put(&key_of(idx), b"v2").unwrap();
+    }
+    let mut subscription = leader.subscribe(applied + 1).unwrap();
+    let applied = catch_up(&follower, &mut subscription, applied);
+    assert_eq!(scan_all(&follower), scan_all(&leader));
+
+    // a subscription waits for the next write
+    let writer = {
+        let leader = leader.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            leader.put(b"late", b"value").unwrap();
+        })
+    };
+    let batch = subscription
+        .next_timeout(Duration::from_secs(10))
+        .unwrap()
+        .unwrap();
+    assert_eq!(batch.sequence, applied + 1);
+    assert_eq!(&batch.records[0].key[..], b"late");
+    writer.join().unwrap();
+    assert!(
+        subscription
+            .next_timeout(Duration::from_millis(10))
+            .unwrap()
+            .is_none()
+    );
+    leader.close().unwrap();
+}
+
+#[test]
+fn test_replication_trim() {
+    let dir = tempdir().unwrap();
+    let mut options = options();
+    options.value_threshold = None;
+    let leader = MiniLsm::open(dir.path().join("leader"), options.clone()).unwrap();
+    let value = vec![b'v'; 4096];
+    let num_writes = 2 * SEGMENT_SIZE as usize / value.len();
+    for idx in 0..num_writes {
+        leader.put(&key_of(idx), &value).unwrap();
+    }
+    let mut lagging = leader.subscribe(1).unwrap();
+    assert!(lagging.try_next().unwrap().is_some());
+
+    let last = leader.inner.mvcc().latest_commit_ts();
+    assert!(leader.trim_replication_log(last).unwrap() >= 1);
+    assert!(leader.subscribe(1).is_err());
+    assert!(lagging.try_next().is_err());
+    // the writes after the trimmed segments are still streamed
+    let follower = MiniLsm::open(dir.path().join("follower"), options).unwrap();
+    let mut subscription = leader.subscribe(last).unwrap();
+    assert_eq!(catch_up(&follower, &mut subscription, 0), last);
+    assert_eq!(
+        follower.get(&key_of(num_writes - 1)).unwrap().unwrap(),
+        value
+    );
+    leader.close().unwrap();
+}
+
+#[test]
+fn test_replication_torn_tail() {
+    let dir = tempdir().unwrap();
+    let leader = MiniLsm::open(dir.path().join("leader"), options()).unwrap();
+    for idx in 0..10 {
+        leader.put(&key_of(idx), b"value").unwrap();
+    }
+    leader.close().unwrap();
+    drop(leader);
+
+    // an append cut short by a crash
+    let segment = std::fs::read_dir(dir.path().join("leader").join("replication"))
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap()
+        .path();
+    let mut data = std::fs::read(&segment).unwrap();
+    data.extend_from_slice(&[0, 0, 1, 0, 0, 0]);
+    std::fs::write(&segment, data).unwrap();
+
+    let leader = MiniLsm::open(dir.path().join("leader"), options()).unwrap();
+    leader.put(&key_of(10), b"value").unwrap();
+    let follower = MiniLsm::open(dir.path().join("follower"), options()).unwrap();
+    let mut subscription = leader.subscribe(1).unwrap();
+    assert_eq!(
+        catch_up(&follower, &mut subscription, 0),
+        leader.inner.mvcc().latest_commit_ts()
+    );
+    assert_eq!(scan_all(&follower), scan_all(&leader));
+    leader.close().unwrap();
+}
+
+#[test]
+fn test_replication_disabled() {
+    let dir = tempdir().unwrap();
+    let mut options = options();
+    options.enable_replication_log = false;
+    let storage = MiniLsm::open(dir.path(), options).unwrap();
+    assert!(storage.subscribe(1).is_err());
+    assert!(!dir.path().join("replication").exists());
+}
@@ -0,0 +1,73 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use super::harness::{key_of, sst_builder_of};
+use crate::iterators::StorageIterator;
+use crate::iterators::merge_iterator::MergeIterator;
+use crate::iterators::scan_cursor::ScanCursor;
+use crate::table::{SsTable, SsTableIterator};
+
+fn build_sst(id: usize, keys: impl Iterator<Item = usize>) -> Arc<SsTable> {
+    Arc::new(sst_builder_of(keys).build_in_memory(id, None).unwrap())
+}
+
+fn merged_iter(ssts: &[Arc<SsTable>]) -> MergeIterator<SsTableIterator> {
+    MergeIterator::create(
+        ssts.iter()
+            .map(|sst| Box::new(SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap()))
+            .collect(),
+    )
+}
+
+#[test]
+fn test_scan_cursor_reposition() {
+    // before compaction: even and odd keys in two SSTs; after: all keys in a single SST
+    let before = [
+        build_sst(1, (0..100).step_by(2)),
+        build_sst(2, (1..100).step_by(2)),
+    ];
+    let after = [build_sst(3, 0..100)];
+    for switch_at in [0, 1, 17, 64, 99, 100] {
+        let mut cursor = ScanCursor::new(merged_iter(&before));
+        let mut keys = Vec::new();
+        while cursor.is_valid() {
+            if keys.len() == switch_at {
+                cursor.reposition(merged_iter(&after)).unwrap();
+            }
+            keys.push(cursor.key().for_testing_key_ref().to_vec());
+            cursor.next().unwrap();
+        }
+        let expected = (0..100).map(key_of).collect::<Vec<_>>();
+        assert_eq!(keys, expected, "switched after {} keys", switch_at);
+    }
+}
+
+#[test]
+fn test_scan_cursor_reposition_missing_key() {
+    // the last yielded key is gone in the new set of SSTs
+    let mut cursor = ScanCursor::new(merged_iter(&[build_sst(1, 0..10)]));
+    for _ in 0..5 {
+        cursor.next().unwrap();
+    }
+    assert_eq!(cursor.last_key().unwrap().for_testing_key_ref(), key_of(4));
+    cursor
+        .reposition(merged_iter(&[build_sst(
+            2,
+            (0..10).filter(|idx| *idx != 4),
+        )]))
+        .unwrap();
+    assert_eq!(cursor.key().for_testing_key_ref(), key_of(5));
+}
@@ -0,0 +1,276 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ops::Bound;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use super::harness::leveled_options;
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm, WriteBatchRecord};
+use crate::secondary_index::{IndexExtractor, IndexIterator, index_column_family};
+use crate::wal::MAX_KEY_LEN;
+
+/// Indexes the records `name|city|tag,tag,...` by city.
+#[derive(Debug)]
+struct CityExtractor;
+
+impl IndexExtractor for CityExtractor {
+    fn extract(&self, _key: &[u8], value: &[u8]) -> Vec<Bytes> {
+        value
+            .split(|byte| *byte == b'|')
+            .nth(1)
+            .map(|city| vec![Bytes::copy_from_slice(city)])
+            .unwrap_or_default()
+    }
+}
+
+/// Indexes the records `name|city|tag,tag,...` by each tag.
+#[derive(Debug)]
+struct TagExtractor;
+
+impl IndexExtractor for TagExtractor {
+    fn extract(&self, _key: &[u8], value: &[u8]) -> Vec<Bytes> {
+        let Some(tags) = value.split(|byte| *byte == b'|').nth(2) else {
+            return Vec::new();
+        };
+        tags.split(|byte| *byte == b',')
+            .filter(|tag| !tag.is_empty())
+            .map(Bytes::copy_from_slice)
+            .collect()
+    }
+}
+
+fn options() -> LsmStorageOptions {
+    let mut options = leveled_options();
+    options.enable_wal = true;
+    options.target_sst_size = 4096;
+    options
+}
+
+/// The `(index key, primary key)` pairs of the iterator.
+fn collect(mut iter: IndexIterator) -> Vec<(String, String)> {
+    let mut result = Vec::new();
+    while iter.is_valid() {
+        result.push((
+            String::from_utf8(iter.index_key().to_vec()).unwrap(),
+            String::from_utf8(iter.key().to_vec()).unwrap(),
+        ));
+        iter.next().unwrap();
+    }
+    result
+}
+
+fn pairs(expected: &[(&str, &str)]) -> Vec<(String, String)> {
+    expected
+        .iter()
+        .map(|(index_key, key)| (index_key.to_string(), key.to_string()))
+        .collect()
+}
+
+fn num_entries(storage: &MiniLsm, index: &str) -> usize {
+    let mut iter = storage
+        .scan_cf(
+            &index_column_family(index),
+            Bound::Unbounded,
+            Bound::Unbounded,
+        )
+        .unwrap();
+    let mut cnt = 0;
+    while iter.is_valid() {
+        cnt += 1;
+        iter.next().unwrap();
+    }
+    cnt
+}
+
+#[test]
+fn test_secondary_index() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(dir.path(), options()).unwrap();
+    storage.put(b"alice", b"Alice|berlin|a,b").unwrap();
+    storage.put(b"bob", b"Bob|cairo|b").unwrap();
+    storage.force_flush().unwrap();
+    // the existing records are indexed on registration
+    storage
+        .register_index("city", Arc::new(CityExtractor))
+        .unwrap();
+    storage
+        .register_index("tag", Arc::new(TagExtractor))
+        .unwrap();
+    assert!(
+        storage
+            .register_index("city", Arc::new(CityExtractor))
+            .is_err()
+    );
+
+    storage.put(b"carol", b"Carol|berlin|").unwrap();
+    storage
+        .write_batch(&[
+            WriteBatchRecord::Put(&b"dave"[..], &b"Dave|athens|a"[..]),
+            // moves to another city within the batch
+            WriteBatchRecord::Put(&b"alice"[..], &b"Alice|dublin|a"[..]),
+            WriteBatchRecord::Put(&b"erin"[..], &b"Erin|cairo|c"[..]),
+            WriteBatchRecord::Del(&b"bob"[..]),
+        ])
+        .unwrap();
+    storage.put(b"erin", b"Erin|berlin|c").unwrap();
+    storage.force_flush().unwrap();
+
+    let iter = storage
+        .scan_index("city", Bound::Unbounded, Bound::Unbounded)
+        .unwrap();
+    assert_eq!(
+        collect(iter),
+        pairs(&[
+            ("athens", "dave"),
+            ("berlin", "carol"),
+            ("berlin", "erin"),
+            ("dublin", "alice"),
+        ])
+    );
+    let iter = storage
+        .scan_index(
+            "city",
+            Bound::Excluded(b"athens"),
+            Bound::Included(b"cairo"),
+        )
+        .unwrap();
+    assert!(iter.is_valid());
+    assert_eq!(iter.value(), b"Carol|berlin|");
+    assert_eq!(
+        collect(iter),
+        pairs(&[("berlin", "carol"), ("berlin", "erin")])
+    );
+    let iter = storage
+        .scan_index(
+            "city",
+            Bound::Included(b"berlin"),
+            Bound::Excluded(b"dublin"),
+        )
+        .unwrap();
+    assert_eq!(
+        collect(iter),
+        pairs(&[("berlin", "carol"), ("berlin", "erin")])
+    );
+    // a record with several index keys is found by each of them
+    let iter = storage
+        .scan_index("tag", Bound::Unbounded, Bound::Unbounded)
+        .unwrap();
+    assert_eq!(
+        collect(iter),
+        pairs(&[("a", "alice"), ("a", "dave"), ("c", "erin")])
+    );
+    // the entries of the previous values are removed
+    assert_eq!(num_entries(&storage, "city"), 4);
+    assert_eq!(num_entries(&storage, "tag"), 3);
+
+    assert!(storage.merge(b"alice", b"x").is_err());
+    assert!(storage.drop_cf(&index_column_family("city")).is_err());
+    storage.drop_index("city").unwrap();
+    assert_eq!(storage.index_names(), vec!["tag".to_string()]);
+    assert!(
+        storage
+            .scan_index("city", Bound::Unbounded, Bound::Unbounded)
+            .is_err()
+    );
+}
+
+#[test]
+fn test_secondary_index_recovery() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(dir.path(), options()).unwrap();
+    storage
+        .register_index("city", Arc::new(CityExtractor))
+        .unwrap();
+    for idx in 0..100 {
+        let value = format!("name{}|city{:02}|", idx, idx % 10);
+        storage
+            .put(format!("key{:03}", idx).as_bytes(), value.as_bytes())
+            .unwrap();
+    }
+    // the index entries are replayed from the WAL with the records
+    storage.close().unwrap();
+    drop(storage);
+
+    let storage = MiniLsm::open(dir.path(), options()).unwrap();
+    assert_eq!(num_entries(&storage, "city"), 100);
+    storage
+        .register_index("city", Arc::new(CityExtractor))
+        .unwrap();
+    storage.put(b"key000", b"name0|city99|").unwrap();
+    let iter = storage
+        .scan_index(
+            "city",
+            Bound::Included(b"city00"),
+            Bound::Included(b"city00"),
+        )
+        .unwrap();
+    let keys = collect(iter)
+        .into_iter()
+        .map(|(_, key)| key)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        keys,
+        (10..100)
+            .step_by(10)
+            .map(|idx| format!("key{:03}", idx))
+            .collect::<Vec<_>>()
+    );
+    storage.close().unwrap();
+}
+
+#[test]
+fn test_secondary_index_stale_entries() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(dir.path(), options()).unwrap();
+    storage
+        .register_index("tag", Arc::new(TagExtractor))
+        .unwrap();
+    // index keys with zero bytes sort as the raw bytes
+    storage.put(b"a", b"A||x\0y,x").unwrap();
+    storage.put(b"b", b"B||x\0").unwrap();
+    storage.put(b"c", b"C||x\x01").unwrap();
+    let iter = storage
+        .scan_index("tag", Bound::Excluded(b"x"), Bound::Unbounded)
+        .unwrap();
+    assert_eq!(collect(iter).len(), 3);
+    let iter = storage
+        .scan_index("tag", Bound::Included(b"x\0"), Bound::Excluded(b"x\x01"))
+        .unwrap();
+    assert_eq!(collect(iter), pairs(&[("x\0", "b"), ("x\0y", "a")]));
+
+    // a range delete leaves the entries behind, which the scans skip
+    storage.delete_range(b"a", b"b").unwrap();
+    let iter = storage
+        .scan_index("tag", Bound::Unbounded, Bound::Unbounded)
+        .unwrap();
+    assert_eq!(collect(iter), pairs(&[("x\0", "b"), ("x\x01", "c")]));
+}
+
+#[test]
+fn test_secondary_index_key_len_limit() {
+    let dir = tempdir().unwrap();
+    let storage = MiniLsm::open(dir.path(), options()).unwrap();
+    storage
+        .register_index("city", Arc::new(CityExtractor))
+        .unwrap();
+    // the primary key fits in a WAL record, but the index entry key holding it does not
+    let key = vec![b'k'; MAX_KEY_LEN];
+    assert!(storage.put(&key, b"K|berlin|").is_err());
+    assert_eq!(storage.get(&key).unwrap(), None);
+    assert_eq!(num_entries(&storage, "city"), 0);
+}
@@ -0,0 +1,171 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ops::Bound;
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use crate::iterators::StorageIterator;
+use crate::key::KeySlice;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+use crate::mem_table::MemTable;
+use crate::mvcc::watermark::Watermark;
+use crate::table::{SsTableIterator, build_from_iter};
+
+fn collect<I>(mut iter: I) -> Vec<(Vec<u8>, Vec<u8>)>
+where
+    I: for<'a> StorageIterator<KeyType<'a> = &'a [u8]>,
+{
+    let mut result = Vec::new();
+    while iter.is_valid() {
+        result.push((iter.key().to_vec(), iter.value().to_vec()));
+        iter.next().unwrap();
+    }
+    result
+}
+
+fn kv(key: &str, value: &str) -> (Vec<u8>, Vec<u8>) {
+    (key.as_bytes().to_vec(), value.as_bytes().to_vec())
+}
+
+#[test]
+fn test_watermark() {
+    let mut watermark = Watermark::new();
+    assert_eq!(watermark.watermark(), None);
+    watermark.add_reader(3);
+    watermark.add_reader(1);
+    watermark.add_reader(1);
+    assert_eq!(watermark.watermark(), Some(1));
+    assert_eq!(watermark.num_retained_snapshots(), 2);
+    watermark.remove_reader(1);
+    assert_eq!(watermark.watermark(), Some(1));
+    watermark.remove_reader(1);
+    assert_eq!(watermark.watermark(), Some(3));
+    watermark.remove_reader(3);
+    assert_eq!(watermark.watermark(), None);
+}
+
+#[test]
+fn test_snapshot_read() {
+    let dir = tempdir().unwrap();
+    let storage = Arc::new(
+        LsmStorageInner::open(dir.path(), LsmStorageOptions::default_for_week1_test()).unwrap(),
+    );
+    storage.put(b"a", b"1").unwrap();
+    storage.put(b"b", b"1").unwrap();
+    let snapshot1 = storage.new_snapshot();
+    storage.put(b"a", b"2").unwrap();
+    storage.delete(b"b").unwrap();
+    storage.put(b"c", b"2").unwrap();
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    let snapshot2 = storage.new_snapshot();
+    storage.put(b"b", b"3").unwrap();
+
+    assert_eq!(snapshot1.read_ts(), 2);
+    assert_eq!(snapshot1.get(b"a").unwrap().unwrap(), &b"1"[..]);
+    assert_eq!(snapshot1.get(b"b").unwrap().unwrap(), &b"1"[..]);
+    assert_eq!(snapshot1.get(b"c").unwrap(), None);
+    assert_eq!(
+        collect(snapshot1.scan(Bound::Unbounded, Bound::Unbounded).unwrap()),
+        vec![kv("a", "1"), kv("b", "1")]
+    );
+    assert_eq!(snapshot2.get(b"b").unwrap(), None);
+    assert_eq!(
+        collect(snapshot2.scan(Bound::Unbounded, Bound::Unbounded).unwrap()),
+        vec![kv("a", "2"), kv("c", "2")]
+    );
+    assert_eq!(
+        collect(storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap()),
+        vec![kv("a", "2"), kv("b", "3"), kv("c", "2")]
+    );
+
+    // the oldest live snapshot holds back the watermark
+    assert_eq!(storage.mvcc().watermark(), 2);
+    drop(snapshot1);
+    assert_eq!(storage.mvcc().watermark(), 5);
+    drop(snapshot2);
+    assert_eq!(storage.mvcc().watermark(), 6);
+}
+
+#[test]
+fn test_build_from_iter_gc_versions() {
+    let memtable = MemTable::create(0);
+    let put = |key: &[u8], ts: u64, value: &[u8]| {
+        memtable
+            .put(KeySlice::for_testing_from_slice_with_ts(key, ts), value)
+            .unwrap();
+    };
+    put(b"a", 1, b"a1");
+    put(b"a", 3, b"a3");
+    put(b"a", 5, b"a5");
+    put(b"b", 2, b"b2");
+    put(b"b", 4, b"");
+    put(b"c", 1, b"c1");
+    put(b"c", 6, b"");
+
+    let versions = |keep_tombstones: bool, watermark: u64| {
+        let dir = tempdir().unwrap();
+        let mut iter = memtable.scan(Bound::Unbounded, Bound::Unbounded);
+        let sst = build_from_iter(
+            &mut iter,
+            keep_tombstones,
+            watermark,
+            1,
+            128,
+            None,
+            dir.path().join("1.sst"),
+        )
+        .unwrap()
+        .unwrap();
+        let mut iter = SsTableIterator::create_and_seek_to_first(Arc::new(sst)).unwrap();
+        let mut versions = Vec::new();
+        while iter.is_valid() {
+            versions.push((iter.key().key_ref().to_vec(), iter.key().ts()));
+            iter.next().unwrap();
+        }
+        versions
+    };
+    let versions_of = |expected: &[(&[u8], u64)]| {
+        expected
+            .iter()
+            .map(|(key, ts)| (key.to_vec(), *ts))
+            .collect::<Vec<_>>()
+    };
+
+    // the versions older than the latest one at or below the watermark are dropped
+    assert_eq!(
+        versions(true, 3),
+        versions_of(&[
+            (b"a", 5),
+            (b"a", 3),
+            (b"b", 4),
+            (b"b", 2),
+            (b"c", 6),
+            (b"c", 1)
+        ])
+    );
+    assert_eq!(
+        versions(true, 4),
+        versions_of(&[(b"a", 5), (b"a", 3), (b"b", 4), (b"c", 6), (b"c", 1)])
+    );
+    // a tombstone at or below the watermark hides everything of its key at the bottom level
+    assert_eq!(
+        versions(false, 4),
+        versions_of(&[(b"a", 5), (b"a", 3), (b"c", 6), (b"c", 1)])
+    );
+    assert_eq!(versions(false, 6), versions_of(&[(b"a", 5)]));
+}
@@ -0,0 +1,1027 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::{TempDir, tempdir};
+
+use crate::block::MAX_VALUE_LEN;
+use crate::iterators::merge_iterator::MergeIterator;
+use crate::iterators::{ReversibleIterator, StorageIterator};
+use crate::key::{KeyBytes, KeySlice, TS_MAX};
+use crate::lsm_storage::{BlockCache, LsmStorageInner, LsmStorageOptions};
+use crate::table::{
+    BlockMeta, CompressionType, DescendingSsTableBuilder, FileObject, Footer, GetResult,
+    MmapWriter, SST_FORMAT_VERSION, SST_FORMAT_VERSION_LEGACY, SsTable, SsTableBuilder,
+    SsTableIterator, build_from_iter, flush_memtable,
+};
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:03}", idx * 5).into_bytes()
+}
+
+fn value_of(idx: usize) -> Vec<u8> {
+    format!("value_{:010}", idx).into_bytes()
+}
+
+fn num_of_keys() -> usize {
+    100
+}
+
+fn generate_sst() -> (TempDir, SsTable) {
+    let mut builder = SsTableBuilder::new(128);
+    for idx in 0..num_of_keys() {
+        builder.add(
+            KeySlice::for_testing_from_slice_no_ts(&key_of(idx)),
+            &value_of(idx),
+        );
+    }
+    let dir = tempdir().unwrap();
+    let sst = builder.build_for_test(dir.path().join("1.sst")).unwrap();
+    (dir, sst)
+}
+
+fn key_bytes(key: &str) -> KeyBytes {
+    KeyBytes::for_testing_from_bytes_no_ts(Bytes::copy_from_slice(key.as_bytes()))
+}
+
+#[test]
+fn test_block_meta_round_trip() {
+    let metas = vec![
+        BlockMeta::new(0, 90, 100, 3, key_bytes("a"), key_bytes("abc")),
+        BlockMeta::new(100, 133, 133, 5, key_bytes("b"), key_bytes("bcd")),
+        BlockMeta::new(233, 20, 4096, 1, key_bytes("c"), key_bytes("c")),
+        BlockMeta::new(
+            4329,
+            20,
+            20,
+            2,
+            KeyBytes::from_bytes_with_ts(Bytes::from_static(b"d"), 42),
+            KeyBytes::from_bytes_with_ts(Bytes::from_static(b"d"), 7),
+        ),
+    ];
+    let mut buf = b"data".to_vec();
+    BlockMeta::encode_block_meta(&metas, 42, &mut buf);
+    assert_eq!(
+        BlockMeta::decode_block_meta(&buf[4..]).unwrap(),
+        (metas, 42)
+    );
+    let mut buf = Vec::new();
+    BlockMeta::encode_block_meta(&[], 0, &mut buf);
+    assert_eq!(BlockMeta::decode_block_meta(&buf).unwrap(), (vec![], 0));
+}
+
+#[test]
+fn test_block_meta_corruption() {
+    let metas = vec![
+        BlockMeta::new(0, 90, 100, 3, key_bytes("a"), key_bytes("abc")),
+        BlockMeta::new(100, 133, 133, 5, key_bytes("b"), key_bytes("bcd")),
+    ];
+    let mut buf = Vec::new();
+    BlockMeta::encode_block_meta(&metas, 1, &mut buf);
+    for idx in 0..buf.len() {
+        let mut corrupted = buf.clone();
+        corrupted[idx] ^= 0x5a;
+        assert!(BlockMeta::decode_block_meta(&corrupted).is_err());
+    }
+    assert!(BlockMeta::decode_block_meta(&buf[..buf.len() - 1]).is_err());
+    assert!(BlockMeta::decode_block_meta(&buf[1..]).is_err());
+}
+
+#[test]
+fn test_sst_open_corrupted_meta() {
+    let (dir, sst) = generate_sst();
+    let path = dir.path().join("1.sst");
+    let mut data = std::fs::read(&path).unwrap();
+    data[sst.block_meta_offset + 5] ^= 0x5a;
+    let path = dir.path().join("2.sst");
+    std::fs::write(&path, &data).unwrap();
+    assert!(SsTable::open_for_test(FileObject::open(&path).unwrap()).is_err());
+}
+
+#[test]
+fn test_sst_footer() {
+    let (_dir, sst) = generate_sst();
+    assert_eq!(sst.format_version(), SST_FORMAT_VERSION);
+    let data = sst.file.read(0, sst.file.size()).unwrap();
+    let footer = Footer::decode(&data[data.len() - Footer::SIZE..])
+        .unwrap()
+        .unwrap();
+    assert_eq!(footer.meta_offset, sst.block_meta_offset as u64);
+    assert_eq!(footer.version, SST_FORMAT_VERSION);
+
+    let open = |data: &[u8]| SsTable::open_for_test(FileObject::from_bytes(data.to_vec()));
+    let with_footer = |footer: Footer| {
+        let mut data = data[..data.len() - Footer::SIZE].to_vec();
+        footer.encode(&mut data);
+        data
+    };
+    // a corrupted footer, a newer version and offsets out of order are rejected
+    let mut corrupted = data.clone();
+    let checksum_at = data.len() - 12;
+    corrupted[checksum_at] ^= 1;
+    assert!(open(&corrupted).is_err());
+    assert!(
+        open(&with_footer(Footer {
+            version: SST_FORMAT_VERSION + 1,
+            ..footer
+        }))
+        .is_err()
+    );
+    assert!(
+        open(&with_footer(Footer {
+            bloom_offset: footer.meta_offset - 1,
+            ..footer
+        }))
+        .is_err()
+    );
+
+    // the legacy format without a footer follows each section with its offset as a u32, and its
+    // meta section holds the block metas without the kind of the index
+    let mut legacy = data[..footer.meta_offset as usize].to_vec();
+    BlockMeta::encode_block_meta(&sst.block_meta, sst.max_ts(), &mut legacy);
+    // uncompressed
+    legacy.push(0);
+    legacy.extend_from_slice(&(footer.meta_offset as u32).to_be_bytes());
+    let range_tombstone_offset = legacy.len() as u32;
+    legacy.extend_from_slice(
+        &data[footer.range_tombstone_offset as usize..footer.bloom_offset as usize],
+    );
+    legacy.extend_from_slice(&range_tombstone_offset.to_be_bytes());
+    let bloom_offset = legacy.len() as u32;
+    legacy.extend_from_slice(
+        &data[footer.bloom_offset as usize..footer.properties_offset.unwrap() as usize],
+    );
+    legacy.extend_from_slice(&bloom_offset.to_be_bytes());
+    let legacy = Arc::new(open(&legacy).unwrap());
+    assert_eq!(legacy.format_version(), SST_FORMAT_VERSION_LEGACY);
+    assert_eq!(legacy.block_meta, sst.block_meta);
+    let mut iter = SsTableIterator::create_and_seek_to_first(legacy).unwrap();
+    for idx in 0..num_of_keys() {
+        assert_eq!(iter.key().for_testing_key_ref(), key_of(idx));
+        assert_eq!(iter.value(), value_of(idx));
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_sst_partitioned_index() {
+    let dir = tempdir().unwrap();
+    let cache = Arc::new(BlockCache::new(1024));
+    let build = |id: usize, partition_size: Option<usize>| {
+        let mut builder = SsTableBuilder::new(128);
+        if let Some(partition_size) = partition_size {
+            builder = builder.with_partitioned_index(partition_size);
+        }
+        for idx in 0..num_of_keys() {
+            builder.add(
+                KeySlice::for_testing_from_slice_no_ts(&key_of(idx)),
+                &value_of(idx),
+            );
+        }
+        let path = dir.path().join(format!("{}.sst", id));
+        builder.build(id, Some(cache.clone()), &path).unwrap();
+        SsTable::open(id, Some(cache.clone()), FileObject::open(&path).unwrap()).unwrap()
+    };
+    let full = build(1, None);
+    let sst = Arc::new(build(2, Some(3)));
+    assert!(!full.has_partitioned_index());
+    assert!(sst.has_partitioned_index());
+    assert!(sst.block_meta.is_empty());
+    assert!(sst.num_of_blocks() > 3 && sst.num_of_blocks() % 3 != 0);
+    assert_eq!(sst.num_of_blocks(), full.num_of_blocks());
+    // the data blocks are laid out the same, followed by the index blocks
+    for (block_idx, meta) in full.block_meta.iter().enumerate() {
+        assert_eq!(&sst.block_meta_at(block_idx).unwrap(), meta);
+    }
+    assert!(sst.block_meta_at(sst.num_of_blocks()).is_err());
+    assert_eq!(sst.first_key(), full.first_key());
+    assert_eq!(sst.last_key(), full.last_key());
+    assert_eq!(sst.data_size(), full.data_size());
+    assert!(sst.data_size() < sst.block_meta_offset as u64);
+    sst.verify().unwrap();
+    assert_eq!(
+        sst.stats().unwrap().avg_block_size,
+        full.stats().unwrap().avg_block_size
+    );
+
+    for idx in 0..num_of_keys() {
+        let key = key_of(idx);
+        let mut gap_key = key.clone();
+        gap_key.push(b'0');
+        for key in [key, gap_key] {
+            let key = KeySlice::for_testing_from_slice_no_ts(&key);
+            assert_eq!(
+                sst.find_block_idx(key).unwrap(),
+                full.find_block_idx(key).unwrap()
+            );
+            assert_eq!(sst.get_kind(key).unwrap(), full.get_kind(key).unwrap());
+        }
+    }
+    let key = |idx: usize| KeySlice::for_testing_from_slice_no_ts(&key_of(idx)).to_key_vec();
+    let mut iter =
+        SsTableIterator::create_and_seek_to_key(sst.clone(), key(42).as_key_slice()).unwrap();
+    assert_eq!(iter.key().for_testing_key_ref(), key_of(42));
+    iter.seek_to_key(KeySlice::for_testing_from_slice_no_ts(b"zzz"))
+        .unwrap();
+    assert!(!iter.is_valid());
+    iter.seek_for_prev(KeySlice::for_testing_from_slice_no_ts(b"zzz"))
+        .unwrap();
+    for idx in (0..num_of_keys()).rev() {
+        assert_eq!(iter.key().for_testing_key_ref(), key_of(idx));
+        assert_eq!(iter.value(), value_of(idx));
+        iter.prev().unwrap();
+    }
+    assert!(!iter.is_valid());
+
+    // the index blocks go through the block cache after the data blocks
+    let num_partitions = sst.num_of_blocks().div_ceil(3);
+    for partition in 0..num_partitions {
+        assert!(cache.contains_block(2, sst.num_of_blocks() + partition));
+    }
+    assert!(!cache.contains_block(2, sst.num_of_blocks() + num_partitions));
+    assert!(!cache.contains_block(1, full.num_of_blocks()));
+}
+
+#[test]
+fn test_sst_get_kind() {
+    // every third key is deleted
+    let mut builder = SsTableBuilder::new(128);
+    for idx in 0..num_of_keys() {
+        let value = if idx % 3 == 0 { vec![] } else { value_of(idx) };
+        builder.add(KeySlice::for_testing_from_slice_no_ts(&key_of(idx)), &value);
+    }
+    let dir = tempdir().unwrap();
+    let sst = builder.build_for_test(dir.path().join("1.sst")).unwrap();
+    assert!(sst.num_of_blocks() > 2);
+    for idx in 0..num_of_keys() {
+        let result = sst
+            .get_kind(KeySlice::for_testing_from_slice_no_ts(&key_of(idx)))
+            .unwrap();
+        if idx % 3 == 0 {
+            assert_eq!(result, GetResult::Deleted);
+        } else {
+            assert_eq!(result, GetResult::Found(Bytes::from(value_of(idx))));
+        }
+    }
+    // block boundaries, keys in the gaps between blocks, and keys out of range
+    for meta in &sst.block_meta {
+        let mut gap_key = meta.last_key.for_testing_key_ref().to_vec();
+        assert_ne!(
+            sst.get_kind(KeySlice::for_testing_from_slice_no_ts(&gap_key))
+                .unwrap(),
+            GetResult::NotFound
+        );
+        gap_key.push(b'0');
+        assert_eq!(
+            sst.get_kind(KeySlice::for_testing_from_slice_no_ts(&gap_key))
+                .unwrap(),
+            GetResult::NotFound
+        );
+    }
+    for key in [&b"a"[..], b"key_001", b"key_4951", b"zzz"] {
+        assert_eq!(
+            sst.get_kind(KeySlice::for_testing_from_slice_no_ts(key))
+                .unwrap(),
+            GetResult::NotFound
+        );
+    }
+}
+
+#[test]
+fn test_sst_debug() {
+    let (_dir, sst) = generate_sst();
+    let output = format!("{:?}", sst);
+    assert!(output.contains(&format!("num_of_blocks: {}", sst.num_of_blocks())));
+    assert!(output.contains(&format!("block_meta_offset: {}", sst.block_meta_offset)));
+    assert!(output.contains(r#"first_key: Key(b"key_000", 0)"#));
+    assert!(output.contains(r#"last_key: Key(b"key_495", 0)"#));
+    let block = sst.read_block(0).unwrap();
+    let output = format!("{:?}", block);
+    assert!(output.contains(&format!("num_of_elements: {}", block.offsets.len())));
+    assert!(!output.contains("key_000"));
+}
+
+#[test]
+fn test_sst_block_checksum() {
+    let (dir, sst) = generate_sst();
+    sst.verify().unwrap();
+    let path = dir.path().join("1.sst");
+    let mut data = std::fs::read(&path).unwrap();
+    // corrupt the value of the first entry: overlap (2) + key_len (2) + "key_000" (7) + ts (8) +
+    // value_len (2)
+    data[21] ^= 0x5a;
+    let path = dir.path().join("2.sst");
+    std::fs::write(&path, &data).unwrap();
+    let sst = Arc::new(SsTable::open_for_test(FileObject::open(&path).unwrap()).unwrap());
+    assert!(sst.verify_on_read());
+    assert!(sst.read_block(0).is_err());
+    assert!(SsTableIterator::create_and_seek_to_first(sst.clone()).is_err());
+    sst.read_block(1).unwrap();
+
+    sst.set_verify_on_read(false);
+    let iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+    assert_eq!(iter.key().for_testing_key_ref(), key_of(0));
+    assert_ne!(iter.value(), value_of(0));
+    assert!(sst.verify().is_err());
+}
+
+#[test]
+fn test_sst_scan_all() {
+    let (_dir, sst) = generate_sst();
+    let sst = Arc::new(sst);
+    let mut iter = sst.scan_all().unwrap();
+    let mut result = Vec::new();
+    while iter.is_valid() {
+        result.push((
+            iter.key().for_testing_key_ref().to_vec(),
+            iter.value().to_vec(),
+        ));
+        iter.next().unwrap();
+    }
+    let expected = (0..num_of_keys())
+        .map(|idx| (key_of(idx), value_of(idx)))
+        .collect::<Vec<_>>();
+    assert_eq!(result, expected);
+}
+
+#[test]
+fn test_sst_build_in_memory() {
+    let mut builder = SsTableBuilder::new(128);
+    for idx in 0..num_of_keys() {
+        builder.add(
+            KeySlice::for_testing_from_slice_no_ts(&key_of(idx)),
+            &value_of(idx),
+        );
+    }
+    let mut buf = Vec::new();
+    let sst = builder.build_with_writer(0, None, &mut buf).unwrap();
+    assert!(sst.num_of_blocks() > 1);
+    let (_dir, on_disk) = generate_sst();
+    assert_eq!(buf, on_disk.file.read(0, on_disk.file.size()).unwrap());
+
+    let sst = Arc::new(SsTable::open_for_test(FileObject::from_bytes(buf)).unwrap());
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+    for idx in 0..num_of_keys() {
+        assert!(iter.is_valid());
+        assert_eq!(iter.key().for_testing_key_ref(), key_of(idx));
+        assert_eq!(iter.value(), value_of(idx));
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+    assert!(sst.file.read(sst.file.size() - 2, 4).is_err());
+}
+
+#[test]
+fn test_flush_memtable() {
+    let mut map = BTreeMap::new();
+    for idx in 0..num_of_keys() {
+        let value = if idx % 3 == 0 {
+            Bytes::new()
+        } else {
+            Bytes::from(value_of(idx))
+        };
+        map.insert(Bytes::from(key_of(idx)), value);
+    }
+    let dir = tempdir().unwrap();
+    let sst = flush_memtable(&map, 1, 128, None, dir.path().join("1.sst")).unwrap();
+    assert_eq!(sst.sst_id(), 1);
+    assert!(sst.num_of_blocks() > 1);
+    for idx in 0..num_of_keys() {
+        let expected = if idx % 3 == 0 {
+            GetResult::Deleted
+        } else {
+            GetResult::Found(Bytes::from(value_of(idx)))
+        };
+        let key = key_of(idx);
+        assert_eq!(
+            sst.get_kind(KeySlice::for_testing_from_slice_no_ts(&key))
+                .unwrap(),
+            expected
+        );
+    }
+    assert_eq!(
+        sst.get_kind(KeySlice::for_testing_from_slice_no_ts(b"key_missing"))
+            .unwrap(),
+        GetResult::NotFound
+    );
+
+    let sst = Arc::new(sst);
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst).unwrap();
+    for (key, value) in &map {
+        assert_eq!(iter.key().for_testing_key_ref(), &key[..]);
+        assert_eq!(iter.value(), &value[..]);
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+
+    assert!(flush_memtable(&BTreeMap::new(), 2, 128, None, dir.path().join("2.sst")).is_err());
+}
+
+#[test]
+fn test_descending_sst_builder() {
+    let mut builder = DescendingSsTableBuilder::new(128);
+    for idx in (0..num_of_keys()).rev() {
+        builder
+            .add(
+                KeySlice::for_testing_from_slice_no_ts(&key_of(idx)),
+                &value_of(idx),
+            )
+            .unwrap();
+    }
+    let dir = tempdir().unwrap();
+    let sst = Arc::new(builder.build_for_test(dir.path().join("1.sst")).unwrap());
+    assert!(sst.num_of_blocks() > 1);
+    assert_eq!(sst.first_key().for_testing_key_ref(), key_of(0));
+    assert_eq!(
+        sst.last_key().for_testing_key_ref(),
+        key_of(num_of_keys() - 1)
+    );
+    for meta in &sst.block_meta {
+        assert!(meta.first_key <= meta.last_key);
+    }
+    for pair in sst.block_meta.windows(2) {
+        assert!(pair[0].last_key < pair[1].first_key);
+    }
+
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+    for idx in 0..num_of_keys() {
+        assert!(iter.is_valid());
+        assert_eq!(iter.key().for_testing_key_ref(), key_of(idx));
+        assert_eq!(iter.value(), value_of(idx));
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+    let key = key_of(42);
+    let iter =
+        SsTableIterator::create_and_seek_to_key(sst, KeySlice::for_testing_from_slice_no_ts(&key))
+            .unwrap();
+    assert_eq!(iter.key().for_testing_key_ref(), key);
+}
+
+#[test]
+fn test_descending_sst_builder_rejects_unordered_keys() {
+    let mut builder = DescendingSsTableBuilder::new(128);
+    builder
+        .add(KeySlice::for_testing_from_slice_no_ts(b"b"), b"1")
+        .unwrap();
+    assert!(
+        builder
+            .add(KeySlice::for_testing_from_slice_no_ts(b"b"), b"2")
+            .is_err()
+    );
+    assert!(
+        builder
+            .add(KeySlice::for_testing_from_slice_no_ts(b"c"), b"3")
+            .is_err()
+    );
+    builder
+        .add(KeySlice::for_testing_from_slice_no_ts(b"a"), b"4")
+        .unwrap();
+    let dir = tempdir().unwrap();
+    assert!(
+        DescendingSsTableBuilder::new(128)
+            .build_for_test(dir.path().join("1.sst"))
+            .is_err()
+    );
+}
+
+#[test]
+fn test_sst_block_alignment() {
+    let mut builder = SsTableBuilder::new(1024).with_block_alignment(4096);
+    for idx in 0..num_of_keys() {
+        builder.add(
+            KeySlice::for_testing_from_slice_no_ts(&key_of(idx)),
+            &value_of(idx),
+        );
+    }
+    let dir = tempdir().unwrap();
+    let sst = Arc::new(builder.build_for_test(dir.path().join("1.sst")).unwrap());
+    assert!(sst.num_of_blocks() > 1);
+    for meta in &sst.block_meta {
+        assert_eq!(meta.offset % 4096, 0);
+        assert_eq!(meta.padded_len, 4096);
+        assert!(meta.len < meta.padded_len);
+    }
+    assert_eq!(sst.block_meta_offset % 4096, 0);
+    sst.verify().unwrap();
+
+    // reopen the file to make sure the lengths are read back from the meta
+    let sst = Arc::new(
+        SsTable::open_for_test(FileObject::open(&dir.path().join("1.sst")).unwrap()).unwrap(),
+    );
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst).unwrap();
+    for idx in 0..num_of_keys() {
+        assert!(iter.is_valid());
+        assert_eq!(iter.key().for_testing_key_ref(), key_of(idx));
+        assert_eq!(iter.value(), value_of(idx));
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+
+    // no padding by default
+    let (_dir, sst) = generate_sst();
+    for meta in &sst.block_meta {
+        assert_eq!(meta.len, meta.padded_len);
+    }
+}
+
+#[test]
+fn test_sst_entry_at() {
+    let (_dir, sst) = generate_sst();
+    let mut entries = Vec::new();
+    for block_idx in 0..sst.num_of_blocks() {
+        let mut entry_idx = 0;
+        while let Ok(entry) = sst.entry_at(block_idx, entry_idx) {
+            entries.push(entry);
+            entry_idx += 1;
+        }
+        assert!(entry_idx > 0);
+    }
+    let expected = (0..num_of_keys())
+        .map(|idx| (key_bytes_of(idx), Bytes::from(value_of(idx))))
+        .collect::<Vec<_>>();
+    assert_eq!(entries, expected);
+    // the second entry of the second block
+    let first_block_len = (0..).find(|idx| sst.entry_at(0, *idx).is_err()).unwrap();
+    assert_eq!(sst.entry_at(1, 1).unwrap(), entries[first_block_len + 1]);
+    assert!(sst.entry_at(0, first_block_len).is_err());
+    assert!(sst.entry_at(sst.num_of_blocks(), 0).is_err());
+}
+
+fn key_bytes_of(idx: usize) -> KeyBytes {
+    KeyBytes::for_testing_from_bytes_no_ts(Bytes::from(key_of(idx)))
+}
+
+#[test]
+fn test_build_from_iter_tombstones() {
+    // the newer SST deletes every third key of the older one
+    let build = |entries: Vec<(usize, Vec<u8>)>| {
+        let mut builder = SsTableBuilder::new(128);
+        for (idx, value) in entries {
+            builder.add(KeySlice::for_testing_from_slice_no_ts(&key_of(idx)), &value);
+        }
+        Arc::new(builder.build_in_memory(0, None).unwrap())
+    };
+    let newer = build(
+        (0..num_of_keys())
+            .filter(|idx| idx % 3 == 0)
+            .map(|idx| (idx, Vec::new()))
+            .collect(),
+    );
+    let older = build((0..num_of_keys()).map(|idx| (idx, value_of(idx))).collect());
+    let merged = || {
+        MergeIterator::create(vec![
+            Box::new(SsTableIterator::create_and_seek_to_first(newer.clone()).unwrap()),
+            Box::new(SsTableIterator::create_and_seek_to_first(older.clone()).unwrap()),
+        ])
+    };
+    let dir = tempdir().unwrap();
+    let kept = build_from_iter(
+        &mut merged(),
+        true,
+        TS_MAX,
+        1,
+        128,
+        None,
+        dir.path().join("1.sst"),
+    )
+    .unwrap()
+    .unwrap();
+    let dropped = build_from_iter(
+        &mut merged(),
+        false,
+        TS_MAX,
+        2,
+        128,
+        None,
+        dir.path().join("2.sst"),
+    )
+    .unwrap()
+    .unwrap();
+    for idx in 0..num_of_keys() {
+        let key = key_of(idx);
+        let key = KeySlice::for_testing_from_slice_no_ts(&key);
+        if idx % 3 == 0 {
+            assert_eq!(kept.get_kind(key).unwrap(), GetResult::Deleted);
+            assert_eq!(dropped.get_kind(key).unwrap(), GetResult::NotFound);
+        } else {
+            let expected = GetResult::Found(Bytes::from(value_of(idx)));
+            assert_eq!(kept.get_kind(key).unwrap(), expected);
+            assert_eq!(dropped.get_kind(key).unwrap(), expected);
+        }
+    }
+
+    // nothing is left after dropping tombstones only
+    let mut iter = SsTableIterator::create_and_seek_to_first(newer.clone()).unwrap();
+    assert!(
+        build_from_iter(
+            &mut iter,
+            false,
+            TS_MAX,
+            3,
+            128,
+            None,
+            dir.path().join("3.sst")
+        )
+        .unwrap()
+        .is_none()
+    );
+}
+
+#[test]
+fn test_sst_file_size() {
+    let (dir, sst) = generate_sst();
+    let written = std::fs::metadata(dir.path().join("1.sst")).unwrap().len();
+    assert_eq!(sst.file_size(), written);
+    assert_eq!(sst.table_size(), written);
+    let meta = sst.block_meta.last().unwrap();
+    assert_eq!(sst.data_size(), (meta.offset + meta.padded_len) as u64);
+    assert!(sst.data_size() < sst.file_size());
+
+    let sst = SsTable::open_for_test(FileObject::open(&dir.path().join("1.sst")).unwrap()).unwrap();
+    assert_eq!(sst.file_size(), written);
+}
+
+#[test]
+fn test_sst_min_block_fill() {
+    let build = |num_of_keys: usize, min_fill: usize| {
+        let mut builder = SsTableBuilder::new(128).with_min_block_fill(min_fill);
+        for idx in 0..num_of_keys {
+            builder.add(
+                KeySlice::for_testing_from_slice_no_ts(&key_of(idx)),
+                &value_of(idx),
+            );
+        }
+        Arc::new(builder.build_in_memory(0, None).unwrap())
+    };
+    // find a number of keys that leaves a single entry in the last block
+    let num_of_keys = (50..)
+        .find(|n| build(*n, 0).num_of_blocks() > build(n - 1, 0).num_of_blocks())
+        .unwrap();
+    let runt = build(num_of_keys, 0);
+    let folded = build(num_of_keys, 64);
+    assert!(runt.num_of_blocks() > 1);
+    assert_eq!(folded.num_of_blocks(), runt.num_of_blocks() - 1);
+    let last = folded.num_of_blocks() - 1;
+    assert_eq!(
+        folded.block_meta[last].first_key,
+        runt.block_meta[last].first_key
+    );
+    assert_eq!(
+        folded.block_meta[last].last_key.for_testing_key_ref(),
+        key_of(num_of_keys - 1)
+    );
+    let mut iter = SsTableIterator::create_and_seek_to_first(folded).unwrap();
+    for idx in 0..num_of_keys {
+        assert!(iter.is_valid());
+        assert_eq!(iter.key().for_testing_key_ref(), key_of(idx));
+        assert_eq!(iter.value(), value_of(idx));
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+
+    // a single block is never folded
+    assert_eq!(build(1, 64).num_of_blocks(), 1);
+}
+
+#[test]
+fn test_sst_value_dedup() {
+    // runs of 10 keys share a value, with an empty value (tombstone) in between
+    let value_at = |idx: usize| -> Vec<u8> {
+        if idx % 17 == 16 {
+            Vec::new()
+        } else {
+            format!("default_value_{:04}", idx / 10).into_bytes()
+        }
+    };
+    let build = |dedup: bool| {
+        let mut builder = SsTableBuilder::new(256).with_value_dedup(dedup);
+        for idx in 0..num_of_keys() {
+            builder.add(
+                KeySlice::for_testing_from_slice_no_ts(&key_of(idx)),
+                &value_at(idx),
+            );
+        }
+        Arc::new(builder.build_in_memory(0, None).unwrap())
+    };
+    let plain = build(false);
+    let dedup = build(true);
+    assert!(dedup.file_size() < plain.file_size());
+
+    let mut iter = SsTableIterator::create_and_seek_to_first(dedup.clone()).unwrap();
+    for idx in 0..num_of_keys() {
+        assert!(iter.is_valid());
+        assert_eq!(iter.key().for_testing_key_ref(), key_of(idx));
+        assert_eq!(iter.value(), value_at(idx));
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+
+    // seeking into the middle of a run resolves the value as well
+    for idx in (0..num_of_keys()).rev() {
+        let iter = SsTableIterator::create_and_seek_to_key(
+            dedup.clone(),
+            KeySlice::for_testing_from_slice_no_ts(&key_of(idx)),
+        )
+        .unwrap();
+        assert_eq!(iter.value(), value_at(idx));
+    }
+}
+
+#[test]
+fn test_sst_value_len_limit() {
+    // the longest values are read back as they are, not as back-references
+    for dedup in [false, true] {
+        let mut builder = SsTableBuilder::new(256).with_value_dedup(dedup);
+        builder.add(
+            KeySlice::for_testing_from_slice_no_ts(b"a"),
+            &vec![b'a'; MAX_VALUE_LEN],
+        );
+        builder.add(
+            KeySlice::for_testing_from_slice_no_ts(b"b"),
+            &vec![b'b'; MAX_VALUE_LEN],
+        );
+        let sst = Arc::new(builder.build_in_memory(0, None).unwrap());
+        let mut iter = SsTableIterator::create_and_seek_to_first(sst).unwrap();
+        for key in [b'a', b'b'] {
+            assert_eq!(iter.key().for_testing_key_ref(), [key]);
+            assert_eq!(iter.value(), vec![key; MAX_VALUE_LEN]);
+            iter.next().unwrap();
+        }
+        assert!(!iter.is_valid());
+
+        let mut builder = SsTableBuilder::new(256).with_value_dedup(dedup);
+        builder.add(KeySlice::for_testing_from_slice_no_ts(b"a"), b"value");
+        builder.add(
+            KeySlice::for_testing_from_slice_no_ts(b"b"),
+            &vec![b'b'; MAX_VALUE_LEN + 1],
+        );
+        assert!(builder.build_in_memory(0, None).is_err());
+    }
+    let mut builder = DescendingSsTableBuilder::new(256);
+    assert!(
+        builder
+            .add(
+                KeySlice::for_testing_from_slice_no_ts(b"a"),
+                &vec![b'a'; MAX_VALUE_LEN + 1],
+            )
+            .is_err()
+    );
+}
+
+#[test]
+fn test_sst_stats() {
+    // every block holds 3 entries, except for the last one with the last key
+    let (_dir, sst) = generate_sst();
+    let stats = sst.stats().unwrap();
+    let block_sizes = sst.block_meta.iter().map(|meta| meta.padded_len);
+    assert_eq!(stats.num_blocks, 34);
+    assert_eq!(stats.num_entries, num_of_keys());
+    assert_eq!(stats.min_block_size, block_sizes.clone().min().unwrap());
+    assert_eq!(stats.max_block_size, block_sizes.clone().max().unwrap());
+    assert!(stats.max_block_size <= 128 + 4);
+    assert_eq!(stats.avg_block_size, block_sizes.sum::<usize>() / 34);
+    assert_eq!(stats.data_size, sst.data_size());
+    assert_eq!(stats.first_key.for_testing_key_ref(), key_of(0));
+    assert_eq!(
+        stats.last_key.for_testing_key_ref(),
+        key_of(num_of_keys() - 1)
+    );
+    // 10 bits per key for a 1% false positive rate
+    assert_eq!(stats.bloom_bits, 10 * num_of_keys());
+    assert!(stats.bloom_estimated_fpr.unwrap() < 0.02);
+
+    // the last key makes a block of its own
+    let mut builder = SsTableBuilder::new(128).with_block_alignment(64);
+    for idx in 0..num_of_keys() {
+        builder.add(
+            KeySlice::for_testing_from_slice_no_ts(&key_of(idx)),
+            &value_of(idx),
+        );
+    }
+    let stats = builder.build_in_memory(0, None).unwrap().stats().unwrap();
+    assert_eq!(stats.num_blocks, 34);
+    assert_eq!(stats.num_entries, num_of_keys());
+    assert_eq!(stats.min_block_size, 64);
+    assert_eq!(stats.max_block_size, 128);
+    assert_eq!(stats.avg_block_size, (33 * 128 + 64) / 34);
+}
+
+#[test]
+fn test_sst_bloom_filter() {
+    let (dir, _) = generate_sst();
+    let sst = SsTable::open(
+        1,
+        None,
+        FileObject::open(&dir.path().join("1.sst")).unwrap(),
+    )
+    .unwrap();
+    for idx in 0..num_of_keys() {
+        let key = key_of(idx);
+        assert!(sst.may_contain(KeySlice::for_testing_from_slice_no_ts(&key)));
+    }
+    // keys within the key range but not in the SST are mostly filtered out
+    let false_positives = (0..num_of_keys() * 5)
+        .filter(|idx| idx % 5 != 0)
+        .filter(|idx| {
+            let key = format!("key_{:03}", idx);
+            sst.may_contain(KeySlice::for_testing_from_slice_no_ts(key.as_bytes()))
+        })
+        .count();
+    assert!(
+        false_positives < num_of_keys() * 4 / 20,
+        "{}",
+        false_positives
+    );
+
+    // fewer bits per key make a smaller filter with more false positives
+    let mut builder = SsTableBuilder::new(128).with_bloom_bits_per_key(2);
+    for idx in 0..num_of_keys() {
+        builder.add(
+            KeySlice::for_testing_from_slice_no_ts(&key_of(idx)),
+            &value_of(idx),
+        );
+    }
+    let small = builder.build_in_memory(2, None).unwrap();
+    assert!(small.file_size() < sst.file_size());
+    assert_eq!(small.stats().unwrap().bloom_bits, 2 * num_of_keys());
+}
+
+#[test]
+fn test_sst_compression() {
+    let value_at = |idx: usize| format!("{:0>64}", idx % 7).into_bytes();
+    let build = |compression: CompressionType, compress_meta: bool| {
+        let mut builder = SsTableBuilder::new(4096)
+            .with_compression(compression)
+            .with_meta_compression(compress_meta);
+        for idx in 0..num_of_keys() * 5 {
+            builder.add(
+                KeySlice::for_testing_from_slice_no_ts(format!("key_{:05}", idx).as_bytes()),
+                &value_at(idx),
+            );
+        }
+        let mut buf = Vec::new();
+        builder
+            .build_with_writer(0, Some(Arc::new(BlockCache::new(64))), &mut buf)
+            .unwrap();
+        let sst = SsTable::open(
+            0,
+            Some(Arc::new(BlockCache::new(64))),
+            FileObject::from_bytes(buf),
+        )
+        .unwrap();
+        Arc::new(sst)
+    };
+    let plain = build(CompressionType::None, true);
+    for compression in [CompressionType::Lz4, CompressionType::Zstd] {
+        let compressed = build(compression, true);
+        let meta_uncompressed = build(compression, false);
+        assert!(compressed.data_size() * 2 < plain.data_size());
+        assert_eq!(meta_uncompressed.data_size(), compressed.data_size());
+        assert!(compressed.file_size() < meta_uncompressed.file_size());
+        assert_eq!(compressed.block_meta.len(), plain.block_meta.len());
+
+        // cached blocks are decompressed
+        for block_idx in 0..plain.num_of_blocks() {
+            assert_eq!(
+                compressed.read_block_cached(block_idx).unwrap().encode(),
+                plain.read_block(block_idx).unwrap().encode()
+            );
+        }
+        compressed.verify().unwrap();
+        let mut iter = SsTableIterator::create_and_seek_to_first(compressed.clone()).unwrap();
+        for idx in 0..num_of_keys() * 5 {
+            assert_eq!(
+                iter.key().for_testing_key_ref(),
+                format!("key_{:05}", idx).as_bytes()
+            );
+            assert_eq!(iter.value(), value_at(idx));
+            iter.next().unwrap();
+        }
+        assert!(!iter.is_valid());
+    }
+}
+
+#[test]
+fn test_sst_versions() {
+    let mut builder = SsTableBuilder::new(128);
+    // versions of a key are ordered from the latest to the earliest
+    for (key, ts) in [(b"a", 3), (b"a", 1), (b"b", 7), (b"c", 2), (b"c", 0)] {
+        builder.add(
+            KeySlice::for_testing_from_slice_with_ts(key, ts),
+            format!("{}{}", key[0] as char, ts).as_bytes(),
+        );
+    }
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    let sst = Arc::new(builder.build_for_test(&path).unwrap());
+    assert_eq!(sst.max_ts(), 7);
+    let reopened = SsTable::open_for_test(FileObject::open(&path).unwrap()).unwrap();
+    assert_eq!(reopened.max_ts(), 7);
+    assert_eq!(reopened.first_key().ts(), 3);
+    assert_eq!(reopened.last_key().ts(), 0);
+
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+    let mut versions = Vec::new();
+    while iter.is_valid() {
+        versions.push((iter.key().key_ref().to_vec(), iter.key().ts()));
+        iter.next().unwrap();
+    }
+    let expected = [(b"a", 3), (b"a", 1), (b"b", 7), (b"c", 2), (b"c", 0)];
+    assert_eq!(
+        versions,
+        expected.map(|(key, ts)| (key.to_vec(), ts)).to_vec()
+    );
+
+    // a lookup returns the latest version no later than the timestamp of the key
+    let get = |key: &[u8], ts| sst.get_kind(KeySlice::for_testing_from_slice_with_ts(key, ts));
+    assert_eq!(get(b"a", 5).unwrap(), GetResult::Found(Bytes::from("a3")));
+    assert_eq!(get(b"a", 2).unwrap(), GetResult::Found(Bytes::from("a1")));
+    assert_eq!(get(b"a", 0).unwrap(), GetResult::NotFound);
+    assert_eq!(get(b"b", 6).unwrap(), GetResult::NotFound);
+    assert_eq!(get(b"c", 1).unwrap(), GetResult::Found(Bytes::from("c0")));
+}
+
+#[test]
+fn test_sst_mmap() {
+    let (dir, on_disk) = generate_sst();
+    let path = dir.path().join("1.sst");
+    let file = FileObject::open_mmap(&path).unwrap();
+    assert!(file.is_mmap());
+    assert!(!on_disk.file.is_mmap());
+    assert_eq!(file.size(), on_disk.file.size());
+    assert_eq!(
+        file.read_bytes(0, file.size()).unwrap(),
+        on_disk.file.read_bytes(0, on_disk.file.size()).unwrap()
+    );
+    assert!(file.read(file.size() - 2, 4).is_err());
+
+    let mut builder = SsTableBuilder::new(128);
+    for idx in 0..num_of_keys() {
+        builder.add(
+            KeySlice::for_testing_from_slice_no_ts(&key_of(idx)),
+            &value_of(idx),
+        );
+    }
+    let sst = builder
+        .build_with_writer(2, None, MmapWriter(&dir.path().join("2.sst")))
+        .unwrap();
+    assert!(sst.file.is_mmap());
+    for sst in [
+        Arc::new(sst),
+        Arc::new(SsTable::open(1, None, file).unwrap()),
+    ] {
+        let mut iter = SsTableIterator::create_and_seek_to_first(sst).unwrap();
+        for idx in 0..num_of_keys() {
+            assert_eq!(iter.key().for_testing_key_ref(), key_of(idx));
+            assert_eq!(iter.value(), value_of(idx));
+            iter.next().unwrap();
+        }
+        assert!(!iter.is_valid());
+    }
+}
+
+#[test]
+fn test_storage_use_mmap() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.use_mmap = true;
+    let storage = LsmStorageInner::open(dir.path(), options.clone()).unwrap();
+    storage.put(b"a", b"1").unwrap();
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    storage.force_flush_next_imm_memtable().unwrap();
+    let is_mmap = |storage: &LsmStorageInner| {
+        let state = storage.state.read();
+        assert_eq!(state.sstables.len(), 1);
+        state.sstables.values().all(|sst| sst.file.is_mmap())
+    };
+    assert!(is_mmap(&storage));
+    assert_eq!(storage.get(b"a").unwrap().unwrap(), &b"1"[..]);
+    drop(storage);
+
+    // the SSTs are mapped again after recovery, and only if the option is set
+    let storage = LsmStorageInner::open(dir.path(), options.clone()).unwrap();
+    assert!(is_mmap(&storage));
+    assert_eq!(storage.get(b"a").unwrap().unwrap(), &b"1"[..]);
+    drop(storage);
+    options.use_mmap = false;
+    let storage = LsmStorageInner::open(dir.path(), options).unwrap();
+    assert!(!is_mmap(&storage));
+}
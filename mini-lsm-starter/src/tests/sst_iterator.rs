@@ -0,0 +1,296 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ops::Bound;
+use std::sync::Arc;
+
+use tempfile::{TempDir, tempdir};
+
+use crate::block::RESTART_INTERVAL;
+use crate::iterators::{ReversibleIterator, StorageIterator};
+use crate::key::KeySlice;
+use crate::lsm_storage::BlockCache;
+use crate::table::{SsTable, SsTableBuilder, SsTableIterator};
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:03}", idx * 5).into_bytes()
+}
+
+fn value_of(idx: usize) -> Vec<u8> {
+    format!("value_{:010}", idx).into_bytes()
+}
+
+fn num_of_keys() -> usize {
+    100
+}
+
+fn generate_sst() -> (TempDir, Arc<SsTable>) {
+    let mut builder = SsTableBuilder::new(128);
+    for idx in 0..num_of_keys() {
+        builder.add(
+            KeySlice::for_testing_from_slice_no_ts(&key_of(idx)),
+            &value_of(idx),
+        );
+    }
+    let dir = tempdir().unwrap();
+    let sst = builder.build_for_test(dir.path().join("1.sst")).unwrap();
+    (dir, Arc::new(sst))
+}
+
+fn check_from(iter: &mut SsTableIterator, from: usize) {
+    for idx in from..num_of_keys() {
+        assert!(iter.is_valid());
+        assert_eq!(iter.key().for_testing_key_ref(), key_of(idx));
+        assert_eq!(iter.value(), value_of(idx));
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_sst_seek_to_range() {
+    let (_dir, sst) = generate_sst();
+    let mut iter =
+        SsTableIterator::create_and_seek_to_range(sst.clone(), Bound::Unbounded).unwrap();
+    check_from(&mut iter, 0);
+    let mut iter =
+        SsTableIterator::create_and_seek_to_range(sst.clone(), Bound::Included(&key_of(10)))
+            .unwrap();
+    check_from(&mut iter, 10);
+    let mut iter =
+        SsTableIterator::create_and_seek_to_range(sst.clone(), Bound::Excluded(&key_of(10)))
+            .unwrap();
+    check_from(&mut iter, 11);
+    // keys that are not in the SST
+    let mut iter =
+        SsTableIterator::create_and_seek_to_range(sst.clone(), Bound::Included(b"key_051"))
+            .unwrap();
+    check_from(&mut iter, 11);
+    let mut iter =
+        SsTableIterator::create_and_seek_to_range(sst.clone(), Bound::Excluded(b"key_051"))
+            .unwrap();
+    check_from(&mut iter, 11);
+    let mut iter =
+        SsTableIterator::create_and_seek_to_range(sst.clone(), Bound::Excluded(b"a")).unwrap();
+    check_from(&mut iter, 0);
+    let iter = SsTableIterator::create_and_seek_to_range(
+        sst.clone(),
+        Bound::Excluded(&key_of(num_of_keys() - 1)),
+    )
+    .unwrap();
+    assert!(!iter.is_valid());
+    let iter = SsTableIterator::create_and_seek_to_range(sst, Bound::Included(b"zzz")).unwrap();
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_sst_seek_to_range_block_gap() {
+    let (_dir, sst) = generate_sst();
+    // find a lower bound between the last key of a block and the first key of the next block
+    let last_key = sst.block_meta[0].last_key.for_testing_key_ref().to_vec();
+    let idx = (0..num_of_keys())
+        .position(|x| key_of(x) == last_key)
+        .unwrap();
+    let mut gap_key = last_key.clone();
+    gap_key.push(0);
+    assert_eq!(
+        sst.find_block_idx(KeySlice::for_testing_from_slice_no_ts(&gap_key))
+            .unwrap(),
+        1
+    );
+    let mut iter =
+        SsTableIterator::create_and_seek_to_range(sst.clone(), Bound::Included(&gap_key)).unwrap();
+    check_from(&mut iter, idx + 1);
+    let mut iter =
+        SsTableIterator::create_and_seek_to_range(sst.clone(), Bound::Excluded(&gap_key)).unwrap();
+    check_from(&mut iter, idx + 1);
+    let mut iter =
+        SsTableIterator::create_and_seek_to_range(sst, Bound::Excluded(&last_key)).unwrap();
+    check_from(&mut iter, idx + 1);
+}
+
+#[test]
+fn test_sst_iterator_progress() {
+    let (_dir, sst) = generate_sst();
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+    assert_eq!(iter.progress(), 0.0);
+    assert_eq!(iter.current_block_index(), 0);
+    let mut last_progress = 0.0;
+    let mut block_changes = 0;
+    while iter.is_valid() {
+        let block_idx = iter.current_block_index();
+        iter.next().unwrap();
+        let progress = iter.progress();
+        if iter.current_block_index() != block_idx {
+            assert!(progress > last_progress);
+            block_changes += 1;
+        } else {
+            assert_eq!(progress, last_progress);
+        }
+        last_progress = progress;
+    }
+    assert_eq!(block_changes, sst.num_of_blocks());
+    assert_eq!(iter.current_block_index(), sst.num_of_blocks());
+    assert!((iter.progress() - 1.0).abs() < 1e-9);
+}
+
+#[test]
+fn test_sst_iterator_next_n() {
+    let (_dir, sst) = generate_sst();
+    for step in [0, 1, 3, 7, 40, 99] {
+        let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+        let mut pos = 0;
+        while pos + step < num_of_keys() {
+            assert_eq!(iter.next_n(step).unwrap(), step);
+            pos += step;
+            assert_eq!(iter.key().for_testing_key_ref(), key_of(pos));
+            assert_eq!(iter.value(), value_of(pos));
+            if step == 0 {
+                break;
+            }
+        }
+        if step > 0 {
+            assert_eq!(iter.next_n(step).unwrap(), num_of_keys() - pos);
+            assert!(!iter.is_valid());
+            assert_eq!(iter.next_n(step).unwrap(), 0);
+        }
+    }
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst).unwrap();
+    assert_eq!(iter.next_n(num_of_keys()).unwrap(), num_of_keys());
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_sst_iterator_table_bounds() {
+    let (_dir, sst) = generate_sst();
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+    assert_eq!(iter.table_first_key().for_testing_key_ref(), key_of(0));
+    assert_eq!(
+        iter.table_last_key().for_testing_key_ref(),
+        key_of(num_of_keys() - 1)
+    );
+    let mut last_key = Vec::new();
+    assert_eq!(
+        iter.key().for_testing_key_ref(),
+        iter.table_first_key().for_testing_key_ref()
+    );
+    while iter.is_valid() {
+        last_key = iter.key().for_testing_key_ref().to_vec();
+        iter.next().unwrap();
+    }
+    // the bounds do not depend on the position of the iterator
+    assert_eq!(iter.table_last_key().for_testing_key_ref(), last_key);
+    assert_eq!(iter.table_first_key(), sst.first_key());
+}
+
+#[test]
+fn test_sst_iterator_prev() {
+    let (_dir, sst) = generate_sst();
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+    iter.seek_for_prev(KeySlice::for_testing_from_slice_no_ts(b"key_999"))
+        .unwrap();
+    for idx in (0..num_of_keys()).rev() {
+        assert!(iter.is_valid());
+        assert_eq!(iter.key().for_testing_key_ref(), key_of(idx));
+        assert_eq!(iter.value(), value_of(idx));
+        iter.prev().unwrap();
+    }
+    assert!(!iter.is_valid());
+    assert!(iter.prev().is_err());
+
+    for idx in 0..num_of_keys() {
+        // seek to an existing key and to a key right after it
+        for suffix in ["", "x"] {
+            let key = [key_of(idx), suffix.as_bytes().to_vec()].concat();
+            iter.seek_for_prev(KeySlice::for_testing_from_slice_no_ts(&key))
+                .unwrap();
+            assert_eq!(iter.key().for_testing_key_ref(), key_of(idx));
+        }
+    }
+    iter.seek_for_prev(KeySlice::for_testing_from_slice_no_ts(b"a"))
+        .unwrap();
+    assert!(!iter.is_valid());
+    iter.seek_to_key(KeySlice::for_testing_from_slice_no_ts(b"a"))
+        .unwrap();
+    check_from(&mut iter, 0);
+}
+
+#[test]
+fn test_sst_iterator_into_entries() {
+    let mut builder = SsTableBuilder::new(128);
+    for idx in 0..num_of_keys() {
+        builder.add(
+            KeySlice::for_testing_from_slice_no_ts(&key_of(idx)),
+            &value_of(idx),
+        );
+    }
+    let cache = Arc::new(BlockCache::new(64));
+    let sst = Arc::new(builder.build_in_memory(1, Some(cache)).unwrap());
+    let entries = SsTableIterator::create_and_seek_to_first(sst.clone())
+        .unwrap()
+        .into_entries()
+        .collect::<anyhow::Result<Vec<_>>>()
+        .unwrap();
+    assert_eq!(entries.len(), num_of_keys());
+
+    let mut idx = 0;
+    for block_idx in 0..sst.num_of_blocks() {
+        // the cache hands out the same block the entries were read from
+        let block = sst.read_block_cached(block_idx).unwrap();
+        let range = block.data.as_ptr_range();
+        for entry_idx in 0..block.offsets.len() {
+            let (key, value) = &entries[idx];
+            assert_eq!(key.for_testing_key_ref(), key_of(idx));
+            assert_eq!(value[..], value_of(idx));
+            // only keys stored in full can be shared, others are prefix-compressed
+            if entry_idx % RESTART_INTERVAL == 0 {
+                assert!(range.contains(&key.key_ref().as_ptr()));
+            }
+            assert!(range.contains(&value.as_ptr()));
+            idx += 1;
+        }
+    }
+    assert_eq!(idx, num_of_keys());
+}
+
+#[test]
+fn test_sst_seek_to_key_le() {
+    let (_dir, sst) = generate_sst();
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+    // the key exists
+    iter.seek_to_key_le(KeySlice::for_testing_from_slice_no_ts(&key_of(42)))
+        .unwrap();
+    assert_eq!(iter.key().for_testing_key_ref(), key_of(42));
+    assert_eq!(iter.value(), value_of(42));
+
+    // the key falls in the gap before the first key of a block, landing on the previous block
+    let first_key = sst.block_meta[2].first_key.for_testing_key_ref().to_vec();
+    let idx = (0..num_of_keys())
+        .position(|x| key_of(x) == first_key)
+        .unwrap();
+    let mut gap_key = first_key.clone();
+    gap_key.pop();
+    assert!(key_of(idx - 1) < gap_key);
+    iter.seek_to_key_le(KeySlice::for_testing_from_slice_no_ts(&gap_key))
+        .unwrap();
+    assert_eq!(iter.current_block_index(), 1);
+    assert_eq!(iter.key().for_testing_key_ref(), key_of(idx - 1));
+    iter.next().unwrap();
+    check_from(&mut iter, idx);
+
+    // the key is below everything
+    iter.seek_to_key_le(KeySlice::for_testing_from_slice_no_ts(b"key"))
+        .unwrap();
+    assert!(!iter.is_valid());
+}
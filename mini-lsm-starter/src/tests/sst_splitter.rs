@@ -0,0 +1,105 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use bytes::Bytes;
+
+use super::harness::key_of;
+use crate::iterators::StorageIterator;
+use crate::key::{KeySlice, bytewise_comparator};
+use crate::table::{SsTable, SsTableBuilder, SsTableIterator, SstSplitter};
+
+/// An SST of 600 keys with two versions each.
+fn input() -> Arc<SsTable> {
+    let mut builder = SsTableBuilder::new(128);
+    for idx in 0..600 {
+        for ts in [2, 1] {
+            builder.add(
+                KeySlice::for_testing_from_slice_with_ts(&key_of(idx), ts),
+                format!("value_{:05}_{}", idx, ts).as_bytes(),
+            );
+        }
+    }
+    Arc::new(builder.build_in_memory(0, None).unwrap())
+}
+
+/// Split the entries of `input` by `splitter`, and check that every entry is written once.
+fn split(input: Arc<SsTable>, splitter: &SstSplitter) -> Vec<Arc<SsTable>> {
+    let mut iter = SsTableIterator::create_and_seek_to_first(input).unwrap();
+    let mut ssts = Vec::new();
+    while iter.is_valid() {
+        let mut builder = SsTableBuilder::new(128);
+        builder
+            .add_from_iter_until(&mut iter, true, 0, &[], &[], splitter)
+            .unwrap();
+        ssts.push(Arc::new(
+            builder.build_in_memory(ssts.len() + 1, None).unwrap(),
+        ));
+    }
+    let mut num_entries = 0;
+    for sst in &ssts {
+        let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+        while iter.is_valid() {
+            num_entries += 1;
+            iter.next().unwrap();
+        }
+    }
+    assert_eq!(num_entries, 1200);
+    // the versions of a key are never split
+    for pair in ssts.windows(2) {
+        assert!(pair[0].last_key().key_ref() < pair[1].first_key().key_ref());
+        assert_eq!(pair[1].first_key().ts(), 2);
+    }
+    ssts
+}
+
+#[test]
+fn test_split_by_target_size() {
+    let target_size = 4096;
+    let ssts = split(input(), &SstSplitter::new(target_size));
+    assert!(ssts.len() > 4, "{}", ssts.len());
+    for sst in &ssts[..ssts.len() - 1] {
+        let data_size = sst.block_meta_at(sst.num_of_blocks() - 1).unwrap().offset;
+        // the SST ends at the first user key after the finished blocks reach the target
+        assert!(data_size >= target_size && data_size < target_size + 512);
+    }
+
+    let ssts = split(input(), &SstSplitter::new(usize::MAX));
+    assert_eq!(ssts.len(), 1);
+}
+
+#[test]
+fn test_split_at_grandparent_boundaries() {
+    // the grandparent SSTs start at every 60 keys, which are fewer bytes than the target size but
+    // more than half of it
+    let boundaries = (1..10)
+        .map(|sst| Bytes::from(key_of(sst * 60)))
+        .collect::<Vec<_>>();
+    let splitter =
+        SstSplitter::new(6144).with_boundaries(boundaries.clone(), bytewise_comparator());
+    let ssts = split(input(), &splitter);
+    assert_eq!(ssts.len(), 10);
+    for (sst, boundary) in ssts[1..].iter().zip(&boundaries) {
+        assert_eq!(sst.first_key().key_ref(), &boundary[..]);
+    }
+
+    // a boundary close to the start of an SST is skipped, as the SST is too small to end there
+    let boundaries = vec![Bytes::from(key_of(5)), Bytes::from(key_of(60))];
+    let splitter = SstSplitter::new(6144).with_boundaries(boundaries, bytewise_comparator());
+    let ssts = split(input(), &splitter);
+    assert_eq!(ssts[1].first_key().key_ref(), key_of(60));
+    // past the boundaries, the SSTs end at the target size
+    assert!(ssts.len() > 4, "{}", ssts.len());
+}
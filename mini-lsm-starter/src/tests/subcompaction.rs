@@ -0,0 +1,203 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use super::harness::{key_of, sync};
+use crate::compact::{CompactionOptions, LeveledCompactionOptions, Subcompaction};
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions, MiniLsm};
+use crate::range_tombstone::RangeTombstone;
+use crate::table::SsTableBuilder;
+
+fn scan_all(storage: &LsmStorageInner) -> BTreeMap<Vec<u8>, Vec<u8>> {
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    let mut result = BTreeMap::new();
+    while iter.is_valid() {
+        result.insert(iter.key().to_vec(), iter.value().to_vec());
+        iter.next().unwrap();
+    }
+    result
+}
+
+/// Write rounds of overwrites and deletes, flushing after each round, and return the expected
+/// contents.
+fn write_rounds(storage: &LsmStorageInner) -> BTreeMap<Vec<u8>, Vec<u8>> {
+    let mut expected = BTreeMap::new();
+    for round in 0..4 {
+        for idx in (round..2000).step_by(round + 1) {
+            let value = format!("value_{}_{}", idx, round).into_bytes();
+            storage.put(&key_of(idx), &value).unwrap();
+            expected.insert(key_of(idx), value);
+        }
+        for idx in (0..2000).step_by(7 + round) {
+            storage.delete(&key_of(idx)).unwrap();
+            expected.remove(&key_of(idx));
+        }
+        sync(storage);
+    }
+    storage.delete_range(&key_of(500), &key_of(600)).unwrap();
+    expected.retain(|key, _| !(&key_of(500) <= key && key < &key_of(600)));
+    sync(storage);
+    while !storage.state.read().imm_memtables.is_empty() {
+        storage.force_flush_next_imm_memtable().unwrap();
+    }
+    expected
+}
+
+fn check_sorted_run(storage: &LsmStorageInner, ssts: &[usize]) {
+    let state = storage.state.read();
+    let ssts = ssts
+        .iter()
+        .map(|id| state.sstables[id].clone())
+        .filter(|sst| sst.num_of_blocks() > 0)
+        .collect::<Vec<_>>();
+    for pair in ssts.windows(2) {
+        assert!(pair[0].last_key().key_ref() < pair[1].first_key().key_ref());
+    }
+}
+
+#[test]
+fn test_subcompaction_split() {
+    let ssts = (0..4)
+        .map(|sst| {
+            let mut builder = SsTableBuilder::new(128);
+            for idx in sst * 100..(sst + 1) * 100 {
+                builder.add(
+                    crate::key::KeySlice::for_testing_from_slice_no_ts(&key_of(idx)),
+                    b"value",
+                );
+            }
+            Arc::new(builder.build_in_memory(sst, None).unwrap())
+        })
+        .collect::<Vec<_>>();
+
+    let whole = Subcompaction::split(&ssts, 1).unwrap();
+    assert_eq!(
+        whole,
+        vec![Subcompaction {
+            lower: None,
+            upper: None
+        }]
+    );
+
+    let parts = Subcompaction::split(&ssts, 4).unwrap();
+    assert_eq!(parts.len(), 4);
+    assert_eq!(parts[0].lower, None);
+    assert_eq!(parts[3].upper, None);
+    for pair in parts.windows(2) {
+        assert_eq!(pair[0].upper, pair[1].lower);
+        assert!(pair[1].lower.is_some());
+    }
+    // the parts are about the same size
+    for (part, expected) in parts.iter().skip(1).zip([100, 200, 300]) {
+        let split = std::str::from_utf8(part.lower.as_ref().unwrap()).unwrap();
+        let idx = split["key_".len()..].parse::<usize>().unwrap();
+        assert!(idx.abs_diff(expected) <= 20, "{} {}", idx, expected);
+    }
+    assert!(parts[0].contains(&key_of(0)));
+    assert!(!parts[0].contains(parts[1].lower.as_ref().unwrap()));
+    assert!(parts[3].contains(&key_of(399)));
+
+    let tombstone = RangeTombstone::new(b"a", b"z", 1);
+    let clipped = parts
+        .iter()
+        .map(|part| part.clip(&tombstone).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(clipped[0].start, &b"a"[..]);
+    assert_eq!(clipped[3].end, &b"z"[..]);
+    for (piece, part) in clipped.iter().zip(&parts).skip(1) {
+        assert_eq!(&piece.start, part.lower.as_ref().unwrap());
+    }
+    assert!(
+        parts[0]
+            .clip(&RangeTombstone::new(b"z", b"zz", 1))
+            .is_none()
+    );
+}
+
+#[test]
+fn test_full_compaction_subcompactions() {
+    for (max_subcompactions, compaction_threads) in [(1, 1), (4, 2), (8, 8)] {
+        let dir = tempdir().unwrap();
+        let mut options = LsmStorageOptions::default_for_week1_test();
+        options.block_size = 256;
+        options.target_sst_size = 4096;
+        options.max_subcompactions = max_subcompactions;
+        options.compaction_threads = compaction_threads;
+        let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+        let expected = write_rounds(&storage.inner);
+        let inputs = storage.inner.state.read().l0_sstables.clone();
+        assert_eq!(scan_all(&storage.inner), expected);
+
+        storage.force_full_compaction().unwrap();
+        {
+            let state = storage.inner.state.read();
+            assert!(state.l0_sstables.is_empty());
+            assert!(state.levels[0].1.len() > max_subcompactions);
+            for id in &inputs {
+                assert!(!state.sstables.contains_key(id));
+                assert!(!storage.inner.path_of_sst(*id).exists());
+            }
+        }
+        check_sorted_run(&storage.inner, &storage.inner.state.read().levels[0].1);
+        assert_eq!(scan_all(&storage.inner), expected);
+        storage.close().unwrap();
+        drop(storage);
+
+        let storage = MiniLsm::open(&dir, options).unwrap();
+        assert!(storage.inner.state.read().l0_sstables.is_empty());
+        assert_eq!(scan_all(&storage.inner), expected);
+    }
+}
+
+#[test]
+fn test_background_compaction_subcompactions() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week2_test(CompactionOptions::Leveled(
+        LeveledCompactionOptions {
+            level_size_multiplier: 2,
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 3,
+            base_level_size_mb: 1,
+        },
+    ));
+    options.block_size = 256;
+    options.target_sst_size = 4096;
+    options.max_subcompactions = 4;
+    options.compaction_threads = 4;
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    let expected = write_rounds(&storage.inner);
+
+    let start = Instant::now();
+    while storage.inner.state.read().l0_sstables.len() >= 2 {
+        assert!(start.elapsed() < Duration::from_secs(10));
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    storage.close().unwrap();
+    for (_, ssts) in &storage.inner.state.read().levels {
+        check_sorted_run(&storage.inner, ssts);
+    }
+    assert_eq!(scan_all(&storage.inner), expected);
+    assert_eq!(
+        storage.get(&key_of(1)).unwrap(),
+        expected.get(&key_of(1)).cloned().map(Bytes::from)
+    );
+}
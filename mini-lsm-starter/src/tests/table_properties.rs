@@ -0,0 +1,176 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use crate::key::KeySlice;
+use crate::lsm_storage::{LsmStorageOptions, MiniLsm};
+use crate::range_tombstone::RangeTombstone;
+use crate::table::{
+    CompressionType, FileObject, Footer, SsTable, SsTableBuilder, TableProperties,
+    TablePropertiesCollector, TablePropertiesCollectorFactory,
+};
+
+/// Counts the keys starting with `prefix`.
+#[derive(Debug)]
+struct PrefixCounter {
+    prefix: Vec<u8>,
+    count: u64,
+}
+
+impl TablePropertiesCollector for PrefixCounter {
+    fn add(&mut self, key: KeySlice, _value: &[u8]) {
+        if key.key_ref().starts_with(&self.prefix) {
+            self.count += 1;
+        }
+    }
+
+    fn finish(&mut self) -> BTreeMap<String, Vec<u8>> {
+        let name = format!("prefix.{}", String::from_utf8_lossy(&self.prefix));
+        BTreeMap::from([(name, self.count.to_le_bytes().to_vec())])
+    }
+}
+
+#[derive(Debug)]
+struct PrefixCounterFactory(&'static [u8]);
+
+impl TablePropertiesCollectorFactory for PrefixCounterFactory {
+    fn create(&self) -> Box<dyn TablePropertiesCollector> {
+        Box::new(PrefixCounter {
+            prefix: self.0.to_vec(),
+            count: 0,
+        })
+    }
+}
+
+fn prefix_count(properties: &TableProperties, prefix: &str) -> u64 {
+    let value = &properties.user_properties[&format!("prefix.{}", prefix)];
+    u64::from_le_bytes(value[..].try_into().unwrap())
+}
+
+#[test]
+fn test_sst_properties() {
+    let dir = tempdir().unwrap();
+    let build = |path: &str, compression: CompressionType| {
+        let mut builder = SsTableBuilder::new(256)
+            .with_compression(compression)
+            .with_properties_collector(PrefixCounterFactory(b"a").create())
+            .with_properties_collector(PrefixCounterFactory(b"b").create());
+        for idx in 0..300 {
+            let key = format!("{}{:05}", if idx < 100 { 'a' } else { 'b' }, idx);
+            let value = if idx % 10 == 0 {
+                Vec::new()
+            } else {
+                vec![b'v'; 20]
+            };
+            builder.add(
+                KeySlice::for_testing_from_slice_no_ts(key.as_bytes()),
+                &value,
+            );
+        }
+        builder.add_range_tombstone(RangeTombstone::new(b"a", b"b", 0));
+        let path = dir.path().join(path);
+        let built = builder.build_for_test(&path).unwrap();
+        let sst = SsTable::open_for_test(FileObject::open(&path).unwrap()).unwrap();
+        assert_eq!(sst.properties(), built.properties());
+        sst
+    };
+
+    let sst = build("1.sst", CompressionType::None);
+    let properties = sst.properties().unwrap();
+    assert_eq!(properties.num_entries, 300);
+    assert_eq!(properties.num_deletions, 30);
+    assert_eq!(properties.num_range_tombstones, 1);
+    assert_eq!(properties.raw_key_size, 300 * (6 + 8));
+    assert_eq!(properties.raw_value_size, 270 * 20);
+    assert_eq!(properties.data_size, sst.data_size());
+    assert_eq!(prefix_count(properties, "a"), 100);
+    assert_eq!(prefix_count(properties, "b"), 200);
+    assert_eq!(sst.num_entries().unwrap(), 300);
+
+    let compressed = build("2.sst", CompressionType::Zstd);
+    assert!(
+        compressed.properties().unwrap().compression_ratio() > properties.compression_ratio() * 2.0
+    );
+
+    // an SST in the format before the properties section
+    let data = sst.file.read(0, sst.file.size()).unwrap();
+    let footer = Footer::decode(&data[data.len() - Footer::SIZE..])
+        .unwrap()
+        .unwrap();
+    let mut old = data[..footer.properties_offset.unwrap() as usize].to_vec();
+    Footer {
+        properties_offset: None,
+        version: 2,
+        ..footer
+    }
+    .encode(&mut old);
+    let old = SsTable::open_for_test(FileObject::from_bytes(old)).unwrap();
+    assert_eq!(old.format_version(), 2);
+    assert!(old.properties().is_none());
+    assert_eq!(old.num_entries().unwrap(), 300);
+
+    // a corrupted properties section is rejected
+    let mut corrupted = data.to_vec();
+    corrupted[footer.properties_offset.unwrap() as usize + 3] ^= 1;
+    assert!(SsTable::open_for_test(FileObject::from_bytes(corrupted)).is_err());
+}
+
+#[test]
+fn test_storage_properties_collectors() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.table_properties_collectors = vec![Arc::new(PrefixCounterFactory(b"user"))];
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    for idx in 0..50 {
+        storage
+            .put(format!("user{:03}", idx).as_bytes(), b"1")
+            .unwrap();
+        storage
+            .put(format!("order{:03}", idx).as_bytes(), b"1")
+            .unwrap();
+    }
+    storage.flush().unwrap();
+    for idx in 0..10 {
+        storage
+            .delete(format!("user{:03}", idx).as_bytes())
+            .unwrap();
+    }
+    storage.flush().unwrap();
+    let state = storage.inner.state.read().clone();
+    let flushed = state
+        .l0_sstables
+        .iter()
+        .map(|sst_id| prefix_count(state.sstables[sst_id].properties().unwrap(), "user"))
+        .collect::<Vec<_>>();
+    assert_eq!(flushed, vec![10, 50]);
+
+    // the compacted SSTs are collected as well
+    storage.force_full_compaction().unwrap();
+    let state = storage.inner.state.read().clone();
+    let (num_entries, users) = state.levels[0]
+        .1
+        .iter()
+        .map(|sst_id| state.sstables[sst_id].properties().unwrap())
+        .fold((0, 0), |(num_entries, users), properties| {
+            (
+                num_entries + properties.num_entries,
+                users + prefix_count(properties, "user"),
+            )
+        });
+    assert_eq!((num_entries, users), (90, 40));
+}
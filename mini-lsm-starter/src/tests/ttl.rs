@@ -0,0 +1,174 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ops::Bound;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::iterators::StorageIterator;
+use crate::key::KeySlice;
+use crate::lsm_storage::{CompactionFilter, LsmStorageInner, LsmStorageOptions};
+use crate::mem_table::MemTable;
+use crate::table::{SsTableBuilder, SsTableIterator};
+use crate::ttl::{decode_value, encode_value, is_expired};
+
+const HOUR: Duration = Duration::from_secs(3600);
+
+#[test]
+fn test_ttl_encode_value() {
+    for value in [
+        &b"v"[..],
+        b"v\xff",
+        b"v\xfe",
+        b"\xff",
+        b"\xfe\xfe",
+        b"12345678\xff",
+    ] {
+        let encoded = encode_value(value, None);
+        assert_eq!(decode_value(&encoded), (value, None));
+        let encoded = encode_value(value, Some(42));
+        assert_eq!(decode_value(&encoded), (value, Some(42)));
+        assert!(is_expired(&encoded, 42));
+        assert!(!is_expired(&encoded, 41));
+    }
+    // only the values ending with a tag byte are escaped
+    assert_eq!(&encode_value(b"value", None)[..], b"value");
+    assert_eq!(&encode_value(b"v\xff", None)[..], b"v\xff\xfe");
+    assert!(!is_expired(b"value", u64::MAX));
+}
+
+#[test]
+fn test_storage_put_with_ttl() {
+    let dir = tempdir().unwrap();
+    let storage =
+        LsmStorageInner::open(dir.path(), LsmStorageOptions::default_for_week1_test()).unwrap();
+    storage.put(b"a", b"1\xff").unwrap();
+    storage.put_with_ttl(b"b", b"2", HOUR).unwrap();
+    storage.put(b"c", b"3").unwrap();
+    storage.put_with_ttl(b"c", b"3", Duration::ZERO).unwrap();
+    storage.put_with_ttl(b"d", b"4", Duration::ZERO).unwrap();
+
+    let check = |storage: &LsmStorageInner| {
+        assert_eq!(storage.get(b"a").unwrap().unwrap(), &b"1\xff"[..]);
+        assert_eq!(storage.get(b"b").unwrap().unwrap(), &b"2"[..]);
+        // an expired version hides the older ones
+        assert_eq!(storage.get(b"c").unwrap(), None);
+        assert_eq!(storage.get(b"d").unwrap(), None);
+        assert_eq!(
+            storage.multi_get(&[b"d", b"b", b"a"]).unwrap(),
+            vec![
+                None,
+                Some(Bytes::from_static(b"2")),
+                Some(Bytes::from_static(b"1\xff"))
+            ]
+        );
+        let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+        let mut result = Vec::new();
+        while iter.is_valid() {
+            result.push((iter.key().to_vec(), iter.value().to_vec()));
+            iter.next().unwrap();
+        }
+        assert_eq!(
+            result,
+            vec![
+                (b"a".to_vec(), b"1\xff".to_vec()),
+                (b"b".to_vec(), b"2".to_vec())
+            ]
+        );
+    };
+    check(&storage);
+    storage
+        .force_freeze_memtable(&storage.state_lock.lock())
+        .unwrap();
+    storage.force_flush_next_imm_memtable().unwrap();
+    check(&storage);
+}
+
+#[test]
+fn test_storage_default_ttl() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.default_ttl = Some(Duration::ZERO);
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options).unwrap());
+    storage.put(b"a", b"1").unwrap();
+    storage.put_with_ttl(b"b", b"2", HOUR).unwrap();
+    assert_eq!(storage.get(b"a").unwrap(), None);
+    assert_eq!(storage.get(b"b").unwrap().unwrap(), &b"2"[..]);
+
+    // the default applies to transactions as well
+    let txn = storage.new_txn().unwrap();
+    txn.put(b"c", b"3");
+    txn.commit().unwrap();
+    assert_eq!(storage.get(b"c").unwrap(), None);
+}
+
+#[test]
+fn test_add_from_iter_expired() {
+    let memtable = MemTable::create(0);
+    let put = |key: &[u8], ts: u64, value: &[u8], expire_at: Option<u64>| {
+        memtable
+            .put(
+                KeySlice::for_testing_from_slice_with_ts(key, ts),
+                &encode_value(value, expire_at),
+            )
+            .unwrap();
+    };
+    put(b"a", 1, b"a1", Some(0));
+    put(b"b", 1, b"b1", None);
+    put(b"b", 5, b"b5", Some(0));
+    put(b"c", 1, b"c1", Some(u64::MAX));
+    let filters = [CompactionFilter::Prefix(Bytes::from_static(b"c"))];
+
+    let build = |keep_tombstones: bool, filters: &[CompactionFilter]| {
+        let dir = tempdir().unwrap();
+        let mut iter = memtable.scan(Bound::Unbounded, Bound::Unbounded);
+        let mut builder = SsTableBuilder::new(128);
+        builder
+            .add_from_iter(&mut iter, keep_tombstones, 3, &[], filters)
+            .unwrap();
+        let sst = Arc::new(builder.build_for_test(dir.path().join("1.sst")).unwrap());
+        let mut iter = SsTableIterator::create_and_seek_to_first(sst).unwrap();
+        let mut versions = Vec::new();
+        while iter.is_valid() {
+            versions.push((
+                iter.key().key_ref().to_vec(),
+                iter.key().ts(),
+                decode_value(iter.value()).0.to_vec(),
+            ));
+            iter.next().unwrap();
+        }
+        versions
+    };
+
+    // the expired versions above the watermark are kept for the snapshots
+    assert_eq!(
+        build(true, &[]),
+        vec![
+            (b"a".to_vec(), 1, vec![]),
+            (b"b".to_vec(), 5, b"b5".to_vec()),
+            (b"b".to_vec(), 1, b"b1".to_vec()),
+            (b"c".to_vec(), 1, b"c1".to_vec())
+        ]
+    );
+    assert_eq!(
+        build(false, &filters),
+        vec![
+            (b"b".to_vec(), 5, b"b5".to_vec()),
+            (b"b".to_vec(), 1, b"b1".to_vec())
+        ]
+    );
+}
@@ -0,0 +1,142 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ops::Bound;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use super::harness::{MockIterator, check_iter_result_by_key, generate_sst};
+use crate::error::Error;
+use crate::iterators::two_merge_iterator::TwoMergeIterator;
+use crate::iterators::{ReversibleIterator, StorageIterator};
+use crate::key::KeySlice;
+use crate::mem_table::MemTable;
+use crate::table::SsTableIterator;
+
+fn kv(key: &str, value: &str) -> (Bytes, Bytes) {
+    (
+        Bytes::copy_from_slice(key.as_bytes()),
+        Bytes::copy_from_slice(value.as_bytes()),
+    )
+}
+
+#[test]
+fn test_two_merge_prefer_a() {
+    let a = MockIterator::new(vec![kv("a", "1.1"), kv("b", "1.2"), kv("c", "1.3")]);
+    let b = MockIterator::new(vec![kv("a", "2.1"), kv("b", "2.2"), kv("d", "2.4")]);
+    let mut iter = TwoMergeIterator::create(a, b).unwrap();
+    assert_eq!(iter.num_active_iterators(), 2);
+    check_iter_result_by_key(
+        &mut iter,
+        vec![
+            kv("a", "1.1"),
+            kv("b", "1.2"),
+            kv("c", "1.3"),
+            kv("d", "2.4"),
+        ],
+    );
+
+    // either side may be empty
+    for (a, b) in [
+        (vec![], vec![kv("a", "2.1")]),
+        (vec![kv("a", "1.1")], vec![]),
+    ] {
+        let mut iter =
+            TwoMergeIterator::create(MockIterator::new(a), MockIterator::new(b)).unwrap();
+        assert!(iter.is_valid());
+        iter.next().unwrap();
+        assert!(!iter.is_valid());
+    }
+    let iter =
+        TwoMergeIterator::create(MockIterator::new(vec![]), MockIterator::new(vec![])).unwrap();
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_two_merge_error() {
+    let a = MockIterator::new(vec![kv("a", "1.1"), kv("c", "1.3")]);
+    let b = MockIterator::new_with_error(vec![kv("b", "2.2"), kv("d", "2.4")], 1);
+    let mut iter = TwoMergeIterator::create(a, b).unwrap();
+    iter.next().unwrap();
+    assert_eq!(iter.key().for_testing_key_ref(), b"b");
+    assert!(iter.next().is_err());
+}
+
+#[test]
+fn test_two_merge_memtable_and_sst() {
+    // the memtable shadows the SST on equal keys
+    let memtable = MemTable::create(0);
+    for (key, value) in [("b", "1.2"), ("c", "1.3"), ("f", "1.6")] {
+        memtable
+            .for_testing_put_slice(key.as_bytes(), value.as_bytes())
+            .unwrap();
+    }
+    let dir = tempdir().unwrap();
+    let sst = Arc::new(generate_sst(
+        1,
+        dir.path().join("1.sst"),
+        vec![
+            kv("a", "2.1"),
+            kv("c", "2.3"),
+            kv("d", "2.4"),
+            kv("f", "2.6"),
+        ],
+        None,
+    ));
+    let mut iter = TwoMergeIterator::create(
+        memtable.for_testing_scan_slice(Bound::Unbounded, Bound::Unbounded),
+        SsTableIterator::create_and_seek_to_first(sst).unwrap(),
+    )
+    .unwrap();
+    let expected = vec![
+        kv("a", "2.1"),
+        kv("b", "1.2"),
+        kv("c", "1.3"),
+        kv("d", "2.4"),
+        kv("f", "1.6"),
+    ];
+
+    iter.seek_for_prev(KeySlice::for_testing_from_slice_no_ts(b"z"))
+        .unwrap();
+    let mut backward = Vec::new();
+    while iter.is_valid() {
+        backward.push((
+            Bytes::copy_from_slice(iter.key().for_testing_key_ref()),
+            Bytes::copy_from_slice(iter.value()),
+        ));
+        iter.prev().unwrap();
+    }
+    backward.reverse();
+    assert_eq!(backward, expected);
+    let err = iter.prev().unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<Error>(),
+        Some(Error::IteratorExhausted)
+    ));
+
+    // turn around on a key both sides have, and on a key only one side has
+    iter.seek_for_prev(KeySlice::for_testing_from_slice_no_ts(b"c"))
+        .unwrap();
+    assert_eq!(iter.value(), b"1.3");
+    iter.next().unwrap();
+    assert_eq!(iter.value(), b"2.4");
+    iter.prev().unwrap();
+    assert_eq!(iter.value(), b"1.3");
+    iter.prev().unwrap();
+    assert_eq!(iter.value(), b"1.2");
+    iter.next().unwrap();
+    check_iter_result_by_key(&mut iter, expected[2..].to_vec());
+}
@@ -0,0 +1,148 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ops::Bound;
+use std::sync::Arc;
+
+use tempfile::{TempDir, tempdir};
+
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+
+fn open_storage(serializable: bool) -> (TempDir, Arc<LsmStorageInner>) {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.serializable = serializable;
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options).unwrap());
+    (dir, storage)
+}
+
+fn collect<I>(mut iter: I) -> Vec<(Vec<u8>, Vec<u8>)>
+where
+    I: for<'a> StorageIterator<KeyType<'a> = &'a [u8]>,
+{
+    let mut result = Vec::new();
+    while iter.is_valid() {
+        result.push((iter.key().to_vec(), iter.value().to_vec()));
+        iter.next().unwrap();
+    }
+    result
+}
+
+fn kv(key: &str, value: &str) -> (Vec<u8>, Vec<u8>) {
+    (key.as_bytes().to_vec(), value.as_bytes().to_vec())
+}
+
+#[test]
+fn test_txn_local_writes() {
+    let (_dir, storage) = open_storage(false);
+    storage.put(b"a", b"1").unwrap();
+    storage.put(b"b", b"1").unwrap();
+    storage.put(b"c", b"1").unwrap();
+    let txn = storage.new_txn().unwrap();
+    txn.put(b"a", b"2");
+    txn.delete(b"b");
+    txn.put(b"d", b"2");
+    // writes after the transaction started are not visible to it
+    storage.put(b"c", b"3").unwrap();
+    storage.put(b"e", b"3").unwrap();
+
+    assert_eq!(txn.get(b"a").unwrap().unwrap(), &b"2"[..]);
+    assert_eq!(txn.get(b"b").unwrap(), None);
+    assert_eq!(txn.get(b"c").unwrap().unwrap(), &b"1"[..]);
+    assert_eq!(txn.get(b"e").unwrap(), None);
+    assert_eq!(
+        collect(txn.scan(Bound::Unbounded, Bound::Unbounded).unwrap()),
+        vec![kv("a", "2"), kv("c", "1"), kv("d", "2")]
+    );
+    assert_eq!(
+        collect(
+            txn.scan(Bound::Excluded(b"a"), Bound::Included(b"c"))
+                .unwrap()
+        ),
+        vec![kv("c", "1")]
+    );
+    // nothing is written until commit
+    assert_eq!(storage.get(b"a").unwrap().unwrap(), &b"1"[..]);
+
+    txn.commit().unwrap();
+    assert!(txn.commit().is_err());
+    assert!(txn.get(b"a").is_err());
+    assert_eq!(
+        collect(storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap()),
+        vec![kv("a", "2"), kv("c", "3"), kv("d", "2"), kv("e", "3")]
+    );
+    // the writes of a transaction share one commit timestamp
+    assert_eq!(storage.mvcc().latest_commit_ts(), 6);
+}
+
+#[test]
+fn test_txn_write_conflict() {
+    let (_dir, storage) = open_storage(false);
+    let txn1 = storage.new_txn().unwrap();
+    let txn2 = storage.new_txn().unwrap();
+    let txn3 = storage.new_txn().unwrap();
+    txn1.put(b"a", b"1");
+    txn2.put(b"a", b"2");
+    txn3.put(b"b", b"3");
+    txn1.commit().unwrap();
+    assert!(txn2.commit().is_err());
+    txn3.commit().unwrap();
+    assert_eq!(storage.get(b"a").unwrap().unwrap(), &b"1"[..]);
+    assert_eq!(storage.get(b"b").unwrap().unwrap(), &b"3"[..]);
+
+    // a transaction that starts after the commit does not conflict with it
+    let txn4 = storage.new_txn().unwrap();
+    drop((txn1, txn2, txn3));
+    txn4.put(b"a", b"4");
+    txn4.commit().unwrap();
+    assert_eq!(storage.get(b"a").unwrap().unwrap(), &b"4"[..]);
+    // no live transaction can conflict with the commits up to the watermark any more
+    assert_eq!(storage.mvcc().watermark(), 2);
+    let committed_txns = storage.mvcc().committed_txns.lock();
+    assert_eq!(committed_txns.keys().copied().collect::<Vec<_>>(), vec![3]);
+}
+
+#[test]
+fn test_txn_serializable() {
+    // write skew: each transaction reads the key the other one writes
+    for serializable in [false, true] {
+        let (_dir, storage) = open_storage(serializable);
+        storage.put(b"a", b"1").unwrap();
+        storage.put(b"b", b"2").unwrap();
+        let txn1 = storage.new_txn().unwrap();
+        let txn2 = storage.new_txn().unwrap();
+        let a = txn1.get(b"a").unwrap().unwrap();
+        txn1.put(b"b", &a);
+        let b = txn2.get(b"b").unwrap().unwrap();
+        txn2.put(b"a", &b);
+        txn1.commit().unwrap();
+        assert_eq!(txn2.commit().is_err(), serializable);
+    }
+
+    // keys read by a scan are validated as well
+    let (_dir, storage) = open_storage(true);
+    storage.put(b"c", b"1").unwrap();
+    storage.put(b"d", b"2").unwrap();
+    let txn3 = storage.new_txn().unwrap();
+    let txn4 = storage.new_txn().unwrap();
+    assert_eq!(
+        collect(txn3.scan(Bound::Unbounded, Bound::Unbounded).unwrap()),
+        vec![kv("c", "1"), kv("d", "2")]
+    );
+    txn3.put(b"e", b"3");
+    txn4.put(b"d", b"4");
+    txn4.commit().unwrap();
+    assert!(txn3.commit().is_err());
+}
@@ -0,0 +1,205 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ops::Bound;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use super::harness::sync;
+use crate::iterators::StorageIterator;
+use crate::key::KeySlice;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions};
+use crate::ttl::{decode_value, encode_pointer, encode_value, is_separated};
+use crate::vlog::{ValueLogFile, ValuePointer};
+
+fn large_value(tag: u8) -> Vec<u8> {
+    vec![tag; 32]
+}
+
+fn options() -> LsmStorageOptions {
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.value_threshold = Some(16);
+    options
+}
+
+fn scan(storage: &LsmStorageInner) -> Vec<(Vec<u8>, Vec<u8>)> {
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    let mut result = Vec::new();
+    while iter.is_valid() {
+        result.push((iter.key().to_vec(), iter.value().to_vec()));
+        iter.next().unwrap();
+    }
+    result
+}
+
+#[test]
+fn test_value_pointer_encoding() {
+    let pointer = ValuePointer {
+        file_id: 7,
+        offset: 1 << 40,
+        len: 52,
+    };
+    assert_eq!(ValuePointer::decode(&pointer.encode()).unwrap(), pointer);
+    assert!(ValuePointer::decode(&pointer.encode()[1..]).is_err());
+
+    for expire_at in [None, Some(42)] {
+        let encoded = encode_pointer(&pointer.encode(), expire_at);
+        assert!(is_separated(&encoded));
+        assert_eq!(decode_value(&encoded), (&pointer.encode()[..], expire_at));
+    }
+    // inline values ending with a pointer tag are escaped
+    for value in [&b"v\xfd"[..], b"v\xfc"] {
+        let encoded = encode_value(value, None);
+        assert!(!is_separated(&encoded));
+        assert_eq!(decode_value(&encoded), (value, None));
+    }
+}
+
+#[test]
+fn test_value_separation() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorageInner::open(dir.path(), options()).unwrap();
+    storage.put(b"a", b"small").unwrap();
+    storage.put(b"b", &large_value(b'b')).unwrap();
+    storage.put(b"c", &large_value(b'c')).unwrap();
+    storage
+        .put_with_ttl(b"d", &large_value(b'd'), Duration::ZERO)
+        .unwrap();
+    storage.delete(b"c").unwrap();
+    {
+        // only the large values are in the value log
+        let memtable = storage.state.read().memtable.clone();
+        let version = |key: &[u8]| {
+            memtable
+                .get_version(KeySlice::from_slice(key, u64::MAX))
+                .unwrap()
+                .1
+        };
+        assert!(!is_separated(&version(b"a")));
+        assert!(is_separated(&version(b"b")));
+        assert_eq!(storage.value_logs.files().len(), 1);
+    }
+
+    let check = |storage: &LsmStorageInner| {
+        assert_eq!(storage.get(b"a").unwrap().unwrap(), &b"small"[..]);
+        assert_eq!(storage.get(b"b").unwrap().unwrap(), large_value(b'b'));
+        assert_eq!(storage.get(b"c").unwrap(), None);
+        assert_eq!(storage.get(b"d").unwrap(), None);
+        assert_eq!(
+            storage.multi_get(&[b"b", b"d", b"a"]).unwrap(),
+            vec![
+                Some(Bytes::from(large_value(b'b'))),
+                None,
+                Some(Bytes::from_static(b"small"))
+            ]
+        );
+        assert_eq!(
+            scan(storage),
+            vec![
+                (b"a".to_vec(), b"small".to_vec()),
+                (b"b".to_vec(), large_value(b'b'))
+            ]
+        );
+    };
+    check(&storage);
+    sync(&storage);
+    check(&storage);
+    drop(storage);
+
+    let storage = LsmStorageInner::open(dir.path(), options()).unwrap();
+    check(&storage);
+    // a new file is started after recovery
+    storage.put(b"e", &large_value(b'e')).unwrap();
+    assert_eq!(storage.value_logs.files().len(), 2);
+    assert_eq!(storage.get(b"e").unwrap().unwrap(), large_value(b'e'));
+}
+
+#[test]
+fn test_value_log_gc() {
+    let dir = tempdir().unwrap();
+    let mut options = options();
+    // each file holds two records of 52 bytes
+    options.value_log_file_size = 100;
+    let storage = Arc::new(LsmStorageInner::open(dir.path(), options.clone()).unwrap());
+    for key in b'0'..=b'5' {
+        storage.put(&[key], &large_value(key)).unwrap();
+    }
+    let files = storage
+        .value_logs
+        .files()
+        .iter()
+        .map(|file| file.id())
+        .collect::<Vec<_>>();
+    assert_eq!(files.len(), 3);
+    let snapshot = storage.new_snapshot();
+    storage.put(b"0", &large_value(b'a')).unwrap();
+    storage.put(b"2", &large_value(b'b')).unwrap();
+    storage.delete(b"3").unwrap();
+    sync(&storage);
+    let expected = vec![
+        (b"0".to_vec(), large_value(b'a')),
+        (b"1".to_vec(), large_value(b'1')),
+        (b"2".to_vec(), large_value(b'b')),
+        (b"4".to_vec(), large_value(b'4')),
+        (b"5".to_vec(), large_value(b'5')),
+    ];
+
+    // the snapshot may still read the superseded values
+    assert!(storage.gc_value_log().unwrap().is_empty());
+    assert_eq!(snapshot.get(b"0").unwrap().unwrap(), large_value(b'0'));
+    drop(snapshot);
+
+    // the first two files are half dead, the last one holds only live records
+    assert_eq!(storage.gc_value_log().unwrap(), files[..2].to_vec());
+    for id in &files[..2] {
+        assert!(!storage.path_of_vlog(*id).exists());
+    }
+    assert!(storage.path_of_vlog(files[2]).exists());
+    assert_eq!(scan(&storage), expected);
+    assert_eq!(storage.get(b"1").unwrap().unwrap(), large_value(b'1'));
+    // the moved records are live in their new files
+    assert!(storage.gc_value_log().unwrap().is_empty());
+    drop(storage);
+
+    let storage = LsmStorageInner::open(dir.path(), options).unwrap();
+    assert_eq!(scan(&storage), expected);
+}
+
+#[test]
+fn test_value_log_torn_record() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorageInner::open(dir.path(), options()).unwrap();
+    storage.put(b"a", &large_value(b'a')).unwrap();
+    storage.put(b"b", &large_value(b'b')).unwrap();
+    let id = storage.value_logs.files()[0].id();
+    let path = storage.path_of_vlog(id);
+    drop(storage);
+
+    let data = std::fs::read(&path).unwrap();
+    std::fs::write(&path, &data[..data.len() - 1]).unwrap();
+    let records = ValueLogFile::open(id, &path).unwrap().records().unwrap();
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].key, &b"a"[..]);
+    assert_eq!(records[0].value, large_value(b'a'));
+
+    let mut corrupted = data.clone();
+    corrupted[10] ^= 1;
+    std::fs::write(&path, &corrupted).unwrap();
+    let file = ValueLogFile::open(id, &path).unwrap();
+    assert!(file.records().is_err());
+    assert!(file.read(records[0].pointer).is_err());
+}
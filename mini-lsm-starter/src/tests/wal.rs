@@ -0,0 +1,308 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fs::OpenOptions;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tempfile::tempdir;
+
+use crate::error::Error;
+use crate::key::{KeySlice, TS_RANGE_BEGIN};
+use crate::lsm_storage::{LsmStorageInner, LsmStorageOptions, MiniLsm, SyncPolicy, WriteBatch};
+use crate::mem_table::MemTable;
+use crate::wal::{MAX_KEY_LEN, MAX_VALUE_LEN};
+
+fn batch_of(prefix: &str) -> Vec<(Vec<u8>, Vec<u8>)> {
+    (0..3)
+        .map(|idx| {
+            (
+                format!("{}_key_{}", prefix, idx).into_bytes(),
+                format!("{}_value_{}", prefix, idx).into_bytes(),
+            )
+        })
+        .collect()
+}
+
+fn put_batch(memtable: &MemTable, batch: &[(Vec<u8>, Vec<u8>)]) {
+    let data = batch
+        .iter()
+        .map(|(key, value)| (KeySlice::for_testing_from_slice_no_ts(key), &value[..]))
+        .collect::<Vec<_>>();
+    memtable.put_batch(&data).unwrap();
+}
+
+#[test]
+fn test_wal_recover_batch() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.wal");
+    let memtable = MemTable::create_with_wal(1, &path).unwrap();
+    put_batch(&memtable, &batch_of("a"));
+    memtable
+        .for_testing_put_slice(b"b_key", b"b_value")
+        .unwrap();
+    memtable.sync_wal().unwrap();
+    drop(memtable);
+
+    let memtable = MemTable::recover_from_wal(1, &path).unwrap();
+    for (key, value) in batch_of("a") {
+        assert_eq!(memtable.for_testing_get_slice(&key).unwrap(), value);
+    }
+    assert_eq!(
+        memtable.for_testing_get_slice(b"b_key").unwrap(),
+        &b"b_value"[..]
+    );
+    // the recovered WAL keeps accepting writes
+    memtable
+        .for_testing_put_slice(b"c_key", b"c_value")
+        .unwrap();
+    memtable.sync_wal().unwrap();
+    drop(memtable);
+    let memtable = MemTable::recover_from_wal(1, &path).unwrap();
+    assert_eq!(
+        memtable.for_testing_get_slice(b"c_key").unwrap(),
+        &b"c_value"[..]
+    );
+}
+
+#[test]
+fn test_wal_torn_batch() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.wal");
+    let memtable = MemTable::create_with_wal(1, &path).unwrap();
+    put_batch(&memtable, &batch_of("a"));
+    memtable.sync_wal().unwrap();
+    let committed_len = std::fs::metadata(&path).unwrap().len();
+    put_batch(&memtable, &batch_of("b"));
+    memtable.sync_wal().unwrap();
+    drop(memtable);
+
+    // a crash in the middle of writing the second batch
+    let file = OpenOptions::new().write(true).open(&path).unwrap();
+    let len = file.metadata().unwrap().len();
+    file.set_len(len - 5).unwrap();
+    drop(file);
+
+    let memtable = MemTable::recover_from_wal(1, &path).unwrap();
+    for (key, value) in batch_of("a") {
+        assert_eq!(memtable.for_testing_get_slice(&key).unwrap(), value);
+    }
+    for (key, _) in batch_of("b") {
+        assert!(memtable.for_testing_get_slice(&key).is_none());
+    }
+    // the torn record is cut off so that new records follow the committed ones
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), committed_len);
+}
+
+#[test]
+fn test_wal_checksum_mismatch() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.wal");
+    let memtable = MemTable::create_with_wal(1, &path).unwrap();
+    put_batch(&memtable, &batch_of("a"));
+    memtable.sync_wal().unwrap();
+    drop(memtable);
+
+    let mut data = std::fs::read(&path).unwrap();
+    data[6] ^= 0xff;
+    std::fs::write(&path, data).unwrap();
+    let err = MemTable::recover_from_wal(1, &path).err().unwrap();
+    assert!(matches!(
+        err.downcast_ref::<Error>(),
+        Some(Error::Corruption { .. })
+    ));
+}
+
+#[test]
+fn test_storage_write_batch() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorageInner::open(&dir, LsmStorageOptions::default_for_week1_test()).unwrap();
+    storage.put(b"1", b"233").unwrap();
+    storage.put(b"2", b"2333").unwrap();
+    let mut batch = WriteBatch::new();
+    batch.put(b"3", b"23333").delete(b"1").put(b"2", b"233333");
+    assert_eq!(batch.len(), 3);
+    storage.write_batch(batch.records()).unwrap();
+    assert_eq!(storage.get(b"1").unwrap(), None);
+    assert_eq!(storage.get(b"2").unwrap().unwrap(), &b"233333"[..]);
+    assert_eq!(storage.get(b"3").unwrap().unwrap(), &b"23333"[..]);
+}
+
+#[test]
+fn test_storage_entry_len_limits() {
+    let dir = tempdir().unwrap();
+    let mut options = LsmStorageOptions::default_for_week1_test();
+    options.enable_wal = true;
+    let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+    // a key as long as a marker would be replayed as the record the marker stands for
+    let longest_key = vec![b'k'; MAX_KEY_LEN];
+    let too_long_key = vec![b'k'; MAX_KEY_LEN + 1];
+    storage.put(&longest_key, b"value").unwrap();
+    assert!(storage.put(&too_long_key, b"value").is_err());
+    assert!(storage.delete(&too_long_key).is_err());
+    assert!(storage.delete_range(b"a", &too_long_key).is_err());
+    assert!(storage.put(b"key", &vec![b'v'; MAX_VALUE_LEN + 1]).is_err());
+    // nothing of a rejected batch is written
+    let mut batch = WriteBatch::new();
+    batch.put(b"key", b"value").put(&too_long_key, b"value");
+    assert!(storage.write_batch(batch.records()).is_err());
+    assert_eq!(storage.get(b"key").unwrap(), None);
+    storage.close().unwrap();
+    drop(storage);
+
+    let storage = MiniLsm::open(&dir, options).unwrap();
+    assert_eq!(storage.get(&longest_key).unwrap().unwrap(), &b"value"[..]);
+    assert_eq!(storage.get(b"key").unwrap(), None);
+}
+
+#[test]
+fn test_storage_wal_replay() {
+    for enable_wal in [true, false] {
+        let dir = tempdir().unwrap();
+        let mut options = LsmStorageOptions::default_for_week1_test();
+        options.enable_wal = enable_wal;
+        let storage = MiniLsm::open(&dir, options.clone()).unwrap();
+        storage.put(b"1", b"233").unwrap();
+        storage.put(b"2", b"2333").unwrap();
+        storage
+            .inner
+            .force_freeze_memtable(&storage.inner.state_lock.lock())
+            .unwrap();
+        storage.put(b"3", b"23333").unwrap();
+        storage.delete(b"1").unwrap();
+        storage.close().unwrap();
+        drop(storage);
+
+        let storage = MiniLsm::open(&dir, options).unwrap();
+        {
+            let state = storage.inner.state.read();
+            if enable_wal {
+                // nothing is flushed, the memtables are replayed from their WALs
+                assert!(state.l0_sstables.is_empty());
+                assert_eq!(state.imm_memtables.len(), 2);
+            } else {
+                assert_eq!(state.l0_sstables.len(), 2);
+            }
+        }
+        assert_eq!(storage.get(b"1").unwrap(), None);
+        assert_eq!(storage.get(b"2").unwrap().unwrap(), &b"2333"[..]);
+        assert_eq!(storage.get(b"3").unwrap().unwrap(), &b"23333"[..]);
+    }
+}
+
+/// Recover a copy of the WAL of the current memtable, i.e., what a crash would leave of it.
+fn recover_wal_copy(storage: &LsmStorageInner, dir: &std::path::Path) -> MemTable {
+    let id = storage.state.read().memtable.id();
+    let copy = dir.join("copy.wal");
+    std::fs::copy(storage.path_of_wal(id), &copy).unwrap();
+    let memtable = MemTable::recover_from_wal(id, &copy).unwrap();
+    std::fs::remove_file(copy).unwrap();
+    memtable
+}
+
+#[test]
+fn test_wal_sync_group() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.wal");
+    let memtable = Arc::new(MemTable::create_with_wal(1, &path).unwrap());
+    let threads = (0..8)
+        .map(|thread| {
+            let memtable = memtable.clone();
+            std::thread::spawn(move || {
+                for idx in 0..50 {
+                    let batch = batch_of(&format!("{}_{}", thread, idx));
+                    put_batch(&memtable, &batch);
+                    memtable.sync_wal_group().unwrap();
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    let size = std::fs::metadata(&path).unwrap().len();
+    memtable.sync_wal().unwrap();
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), size);
+    drop(memtable);
+
+    let memtable = MemTable::recover_from_wal(1, &path).unwrap();
+    for thread in 0..8 {
+        for idx in 0..50 {
+            for (key, value) in batch_of(&format!("{}_{}", thread, idx)) {
+                assert_eq!(memtable.for_testing_get_slice(&key).unwrap(), value);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_storage_sync_policy() {
+    for sync_policy in [
+        SyncPolicy::PerWrite,
+        SyncPolicy::Group,
+        SyncPolicy::Interval(Duration::from_millis(10)),
+        SyncPolicy::Never,
+    ] {
+        let dir = tempdir().unwrap();
+        let mut options = LsmStorageOptions::default_for_week1_test();
+        options.enable_wal = true;
+        options.sync_policy = sync_policy;
+        let storage = MiniLsm::open(&dir, options).unwrap();
+        let threads = (0..4)
+            .map(|thread| {
+                let storage = storage.clone();
+                std::thread::spawn(move || {
+                    for idx in 0..25 {
+                        let key = format!("{}_{}", thread, idx);
+                        storage.put(key.as_bytes(), key.as_bytes()).unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        storage.delete_range(b"3", b"4").unwrap();
+
+        let durable = |memtable: &MemTable| {
+            (0..3).all(|thread| {
+                (0..25).all(|idx| {
+                    let key = format!("{}_{}", thread, idx);
+                    memtable
+                        .get(KeySlice::from_slice(key.as_bytes(), TS_RANGE_BEGIN))
+                        .is_some()
+                })
+            }) && memtable.range_tombstones().len() == 1
+        };
+        match sync_policy {
+            SyncPolicy::PerWrite | SyncPolicy::Group => {
+                assert!(durable(&recover_wal_copy(&storage.inner, dir.path())));
+            }
+            SyncPolicy::Interval(_) => {
+                let start = std::time::Instant::now();
+                while !durable(&recover_wal_copy(&storage.inner, dir.path())) {
+                    assert!(start.elapsed() < Duration::from_secs(5));
+                    std::thread::sleep(Duration::from_millis(10));
+                }
+            }
+            SyncPolicy::Never => {
+                // the writes are still buffered
+                assert!(!durable(&recover_wal_copy(&storage.inner, dir.path())));
+                storage.sync().unwrap();
+                assert!(durable(&recover_wal_copy(&storage.inner, dir.path())));
+            }
+        }
+        storage.close().unwrap();
+    }
+}
@@ -0,0 +1,123 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Expiration of values. A value written with a TTL is stored as
+//! `value | expire_at (u64, ms since epoch) | TAG_EXPIRING`. A value without a TTL is stored as is,
+//! unless its last byte is one of the tags, in which case `TAG_ESCAPED` is appended, so that only
+//! the values ending with a tag byte pay for the encoding. Tombstones stay empty.
+//!
+//! A value separated into the value log is stored as its encoded `ValuePointer` followed by
+//! `TAG_POINTER`, or by its expiry and `TAG_POINTER_EXPIRING`, see `encode_pointer`.
+//!
+//! A merge operand is stored as `operand | TAG_MERGE`, inline and without an expiry, see
+//! `encode_merge_operand`.
+
+use std::borrow::Cow;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const TAG_EXPIRING: u8 = 0xff;
+const TAG_ESCAPED: u8 = 0xfe;
+const TAG_POINTER: u8 = 0xfd;
+const TAG_POINTER_EXPIRING: u8 = 0xfc;
+const TAG_MERGE: u8 = 0xfb;
+const SIZEOF_EXPIRE_AT: usize = std::mem::size_of::<u64>();
+
+/// Milliseconds since the epoch.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// The expiry of a value written now with `ttl`.
+pub fn expire_at(ttl: Duration) -> u64 {
+    now().saturating_add(ttl.as_millis() as u64)
+}
+
+/// Encode a non-empty value, which expires at `expire_at` if set.
+pub fn encode_value(value: &[u8], expire_at: Option<u64>) -> Cow<'_, [u8]> {
+    if let Some(expire_at) = expire_at {
+        let mut buf = Vec::with_capacity(value.len() + SIZEOF_EXPIRE_AT + 1);
+        buf.extend_from_slice(value);
+        buf.extend_from_slice(&expire_at.to_be_bytes());
+        buf.push(TAG_EXPIRING);
+        return Cow::Owned(buf);
+    }
+    match value.last() {
+        Some(&(TAG_MERGE..=TAG_EXPIRING)) => {
+            let mut buf = Vec::with_capacity(value.len() + 1);
+            buf.extend_from_slice(value);
+            buf.push(TAG_ESCAPED);
+            Cow::Owned(buf)
+        }
+        _ => Cow::Borrowed(value),
+    }
+}
+
+/// Encode the pointer to a value separated into the value log, which expires at `expire_at` if
+/// set.
+pub fn encode_pointer(pointer: &[u8], expire_at: Option<u64>) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(pointer.len() + SIZEOF_EXPIRE_AT + 1);
+    buf.extend_from_slice(pointer);
+    match expire_at {
+        Some(expire_at) => {
+            buf.extend_from_slice(&expire_at.to_be_bytes());
+            buf.push(TAG_POINTER_EXPIRING);
+        }
+        None => buf.push(TAG_POINTER),
+    }
+    buf
+}
+
+/// Encode a merge operand, see `MergeOperator`.
+pub fn encode_merge_operand(operand: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(operand.len() + 1);
+    buf.extend_from_slice(operand);
+    buf.push(TAG_MERGE);
+    buf
+}
+
+/// Whether the stored value is a merge operand, which `decode_value` returns the operand of.
+pub fn is_merge_operand(raw: &[u8]) -> bool {
+    raw.last() == Some(&TAG_MERGE)
+}
+
+/// Whether the stored value is a pointer to a value in the value log, in which case
+/// `decode_value` returns the encoded pointer instead of the user value.
+pub fn is_separated(raw: &[u8]) -> bool {
+    matches!(raw.last(), Some(&(TAG_POINTER | TAG_POINTER_EXPIRING)))
+}
+
+/// Decode a stored value into the user value, or the encoded pointer if `is_separated`, and its
+/// expiry. Values too short to be encoded are returned as is.
+pub fn decode_value(raw: &[u8]) -> (&[u8], Option<u64>) {
+    match raw.last() {
+        Some(&(TAG_EXPIRING | TAG_POINTER_EXPIRING)) if raw.len() > SIZEOF_EXPIRE_AT => {
+            let (value, expire_at) =
+                raw[..raw.len() - 1].split_at(raw.len() - 1 - SIZEOF_EXPIRE_AT);
+            (
+                value,
+                Some(u64::from_be_bytes(expire_at.try_into().unwrap())),
+            )
+        }
+        Some(&(TAG_ESCAPED | TAG_POINTER | TAG_MERGE)) => (&raw[..raw.len() - 1], None),
+        _ => (raw, None),
+    }
+}
+
+/// Whether the stored value has expired at `now`.
+pub fn is_expired(raw: &[u8], now: u64) -> bool {
+    matches!(decode_value(raw).1, Some(expire_at) if expire_at <= now)
+}
@@ -0,0 +1,474 @@
+// Copyright (c) 2022-2025 Alex Chi Z
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Key-value separation: values of at least `LsmStorageOptions::value_threshold` bytes are appended
+//! to value log files, and the LSM tree stores a `ValuePointer` to them instead, so that flushes
+//! and compactions move small pointers around instead of large values. A value log file is a
+//! sequence of records:
+//!
+//! `| key_len (u16) | key | ts (u64) | value_len (u32) | value | checksum (u32) |`
+//!
+//! where the checksum covers the fields before it. The key and the timestamp tell the garbage
+//! collection which version of a key a record belongs to.
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use bytes::{Buf, BufMut, Bytes};
+use parking_lot::RwLock;
+
+use crate::error::Error;
+use crate::key::KeySlice;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageState};
+use crate::ttl;
+
+/// Where a separated value is: the record at `offset` of `len` bytes in the value log file `file_id`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ValuePointer {
+    pub file_id: usize,
+    pub offset: u64,
+    pub len: u32,
+}
+
+impl ValuePointer {
+    pub const SIZE: usize = 4 + 8 + 4;
+
+    pub fn encode(&self) -> [u8; Self::SIZE] {
+        let mut buf = [0; Self::SIZE];
+        let mut slice = &mut buf[..];
+        slice.put_u32(self.file_id as u32);
+        slice.put_u64(self.offset);
+        slice.put_u32(self.len);
+        buf
+    }
+
+    pub fn decode(mut data: &[u8]) -> Result<Self> {
+        if data.len() != Self::SIZE {
+            bail!(Error::corruption(format!(
+                "value pointer of {} bytes",
+                data.len()
+            )));
+        }
+        Ok(Self {
+            file_id: data.get_u32() as usize,
+            offset: data.get_u64(),
+            len: data.get_u32(),
+        })
+    }
+}
+
+/// A record of a value log file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ValueLogRecord {
+    pub key: Bytes,
+    pub ts: u64,
+    pub value: Bytes,
+    pub pointer: ValuePointer,
+}
+
+/// A value log file, read with positional reads so that it can be appended to at the same time.
+pub struct ValueLogFile {
+    id: usize,
+    file: File,
+}
+
+impl ValueLogFile {
+    pub fn open(id: usize, path: impl AsRef<Path>) -> Result<Self> {
+        let file = File::open(path.as_ref())
+            .with_context(|| format!("failed to open value log {}", id))?;
+        Ok(Self { id, file })
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// Size of the file in bytes, including the records being appended.
+    pub fn size(&self) -> Result<u64> {
+        Ok(self.file.metadata().map_err(Error::Io)?.len())
+    }
+
+    /// Read the value `pointer` points to, verifying the checksum of its record.
+    pub fn read(&self, pointer: ValuePointer) -> Result<Bytes> {
+        use std::os::unix::fs::FileExt;
+        let mut data = vec![0; pointer.len as usize];
+        self.file
+            .read_exact_at(&mut data, pointer.offset)
+            .map_err(Error::Io)?;
+        Ok(Self::decode_record(data.into(), pointer)?.value)
+    }
+
+    /// Read all records of the file in order. A record cut short at the end of the file, i.e., an
+    /// append interrupted by a crash, is ignored, as nothing points to it.
+    pub fn records(&self) -> Result<Vec<ValueLogRecord>> {
+        use std::os::unix::fs::FileExt;
+        let mut data = vec![0; self.size()? as usize];
+        self.file.read_exact_at(&mut data, 0).map_err(Error::Io)?;
+        let data = Bytes::from(data);
+        let mut records = Vec::new();
+        let mut offset = 0;
+        while let Some(len) = Self::record_len(&data[offset..]) {
+            let pointer = ValuePointer {
+                file_id: self.id,
+                offset: offset as u64,
+                len: len as u32,
+            };
+            records.push(Self::decode_record(
+                data.slice(offset..offset + len),
+                pointer,
+            )?);
+            offset += len;
+        }
+        Ok(records)
+    }
+
+    /// The length of the complete record at the front of `data`, if any.
+    fn record_len(mut data: &[u8]) -> Option<usize> {
+        let key_len = data.try_get_u16().ok()? as usize;
+        data = data.get(key_len + 8..)?;
+        let value_len = data.try_get_u32().ok()? as usize;
+        let len = 2 + key_len + 8 + 4 + value_len + 4;
+        (data.len() >= value_len + 4).then_some(len)
+    }
+
+    fn decode_record(data: Bytes, pointer: ValuePointer) -> Result<ValueLogRecord> {
+        let malformed = || {
+            Error::corruption(format!(
+                "value log record at offset {} of file {} malformed",
+                pointer.offset, pointer.file_id
+            ))
+        };
+        if data.len() < 4 {
+            bail!(malformed());
+        }
+        let (body, mut checksum) = data.split_at(data.len() - 4);
+        if checksum.get_u32() != crc32fast::hash(body) {
+            bail!(Error::corruption(format!(
+                "value log record at offset {} of file {} checksum mismatched",
+                pointer.offset, pointer.file_id
+            )));
+        }
+        let mut buf = body;
+        let key_len = buf.try_get_u16().map_err(|_| malformed())? as usize;
+        if buf.remaining() < key_len + 8 + 4 {
+            bail!(malformed());
+        }
+        let key = data.slice(2..2 + key_len);
+        buf.advance(key_len);
+        let ts = buf.get_u64();
+        let value_len = buf.get_u32() as usize;
+        if buf.remaining() != value_len {
+            bail!(malformed());
+        }
+        let value_offset = 2 + key_len + 8 + 4;
+        Ok(ValueLogRecord {
+            key,
+            ts,
+            value: data.slice(value_offset..value_offset + value_len),
+            pointer,
+        })
+    }
+}
+
+type ValueLogFiles = HashMap<usize, Arc<ValueLogFile>>;
+
+/// The value log files of the storage by id, shared by the storage and its readers.
+#[derive(Clone, Default)]
+pub struct ValueLogs(Arc<RwLock<Arc<ValueLogFiles>>>);
+
+impl ValueLogs {
+    /// Take a snapshot of the files to resolve the pointers of a read with. It should be taken
+    /// before the state of the storage that the read goes through, so that a file removed by the
+    /// garbage collection afterwards can still be read.
+    pub fn snapshot(&self) -> ValueLogSnapshot {
+        ValueLogSnapshot {
+            files: self.0.read().clone(),
+            live: self.clone(),
+        }
+    }
+
+    /// The files sorted by id.
+    pub fn files(&self) -> Vec<Arc<ValueLogFile>> {
+        let mut files = self.0.read().values().cloned().collect::<Vec<_>>();
+        files.sort_by_key(|file| file.id());
+        files
+    }
+
+    pub(crate) fn insert(&self, file: Arc<ValueLogFile>) {
+        let mut files = self.0.write();
+        let mut new_files = files.as_ref().clone();
+        new_files.insert(file.id(), file);
+        *files = Arc::new(new_files);
+    }
+
+    pub(crate) fn remove(&self, id: usize) {
+        let mut files = self.0.write();
+        let mut new_files = files.as_ref().clone();
+        new_files.remove(&id);
+        *files = Arc::new(new_files);
+    }
+}
+
+/// The value log files as of a read, see `ValueLogs::snapshot`.
+#[derive(Clone)]
+pub struct ValueLogSnapshot {
+    files: Arc<ValueLogFiles>,
+    /// Files created after the snapshot, which the values rewritten by the garbage collection
+    /// may point to, are looked up here.
+    live: ValueLogs,
+}
+
+impl ValueLogSnapshot {
+    pub fn get(&self, id: usize) -> Option<Arc<ValueLogFile>> {
+        self.files
+            .get(&id)
+            .cloned()
+            .or_else(|| self.live.0.read().get(&id).cloned())
+    }
+
+    /// The user value of a stored value, read from the value log if the value is separated.
+    pub fn resolve(&self, raw: &Bytes) -> Result<Bytes> {
+        let (value, _) = ttl::decode_value(raw);
+        if !ttl::is_separated(raw) {
+            return Ok(raw.slice(..value.len()));
+        }
+        let pointer = ValuePointer::decode(value)?;
+        let Some(file) = self.get(pointer.file_id) else {
+            bail!(Error::corruption(format!(
+                "value log {} not found",
+                pointer.file_id
+            )));
+        };
+        file.read(pointer)
+    }
+}
+
+/// The value log file being appended to.
+pub(crate) struct ValueLogHead {
+    pub(crate) file: Arc<ValueLogFile>,
+    writer: File,
+    size: u64,
+}
+
+impl ValueLogHead {
+    pub(crate) fn create(id: usize, path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let writer = OpenOptions::new()
+            .append(true)
+            .create_new(true)
+            .open(path)
+            .with_context(|| format!("failed to create value log {}", id))?;
+        Ok(Self {
+            file: Arc::new(ValueLogFile::open(id, path)?),
+            writer,
+            size: 0,
+        })
+    }
+
+    pub(crate) fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Append a record. The write is not buffered, so that the value can be read as soon as the
+    /// pointer to it is.
+    pub(crate) fn append(&mut self, key: &[u8], ts: u64, value: &[u8]) -> Result<ValuePointer> {
+        let mut buf = Vec::with_capacity(2 + key.len() + 8 + 4 + value.len() + 4);
+        buf.put_u16(key.len() as u16);
+        buf.put_slice(key);
+        buf.put_u64(ts);
+        buf.put_u32(value.len() as u32);
+        buf.put_slice(value);
+        buf.put_u32(crc32fast::hash(&buf));
+        self.writer.write_all(&buf).map_err(Error::Io)?;
+        let pointer = ValuePointer {
+            file_id: self.file.id(),
+            offset: self.size,
+            len: buf.len() as u32,
+        };
+        self.size += buf.len() as u64;
+        Ok(pointer)
+    }
+
+    pub(crate) fn sync(&self) -> Result<()> {
+        self.writer.sync_all().map_err(Error::Io)?;
+        Ok(())
+    }
+}
+
+/// What the garbage collection of the value log makes of a record.
+enum RecordLiveness {
+    /// No reader can see the version of the record, or the version points elsewhere.
+    Dead,
+    /// The version of the record is the latest version of its key, and expires at the time if set.
+    Latest(Option<u64>),
+    /// A newer version of the key exists, but snapshots may still read the version of the record.
+    Pinned,
+}
+
+impl LsmStorageInner {
+    fn record_liveness(
+        state: &LsmStorageState,
+        record: &ValueLogRecord,
+        watermark: u64,
+        latest_ts: u64,
+    ) -> Result<RecordLiveness> {
+        let Some((ts, raw)) = Self::latest_version(state, &record.key, record.ts)? else {
+            return Ok(RecordLiveness::Dead);
+        };
+        let (pointer, expire_at) = ttl::decode_value(&raw);
+        if ts != record.ts || !ttl::is_separated(&raw) || pointer != record.pointer.encode() {
+            return Ok(RecordLiveness::Dead);
+        }
+        if matches!(Self::latest_version(state, &record.key, latest_ts)?, Some((ts, _)) if ts == record.ts)
+        {
+            return Ok(RecordLiveness::Latest(expire_at));
+        }
+        // a newer version visible at the watermark is visible to every reader, unless it is a
+        // merge operand that the version of the record may still be folded into
+        Ok(match Self::latest_version(state, &record.key, watermark)? {
+            Some((ts, raw)) if ts > record.ts && !ttl::is_merge_operand(&raw) => {
+                RecordLiveness::Dead
+            }
+            _ => RecordLiveness::Pinned,
+        })
+    }
+
+    /// Garbage-collect the value log files whose dead records take at least `value_log_gc_ratio` of
+    /// their size. The records of the latest versions are appended to the current value log file,
+    /// the LSM tree is pointed at their new location with the same timestamps, and the old file is
+    /// removed once the new pointers are persisted. A record is dead once a newer version of its key
+    /// is visible at the watermark. A file with a record that only snapshots can see is left for a
+    /// later run. Returns the ids of the removed files.
+    pub fn gc_value_log(&self) -> Result<Vec<usize>> {
+        self.check_writable()?;
+        let _gc_lock = self.value_log_gc_lock.lock();
+        let head_id = self
+            .value_log_head
+            .lock()
+            .as_ref()
+            .map(|head| head.file.id());
+        let mut removed = Vec::new();
+        for file in self.value_logs.files() {
+            if Some(file.id()) != head_id && self.gc_value_log_file(&file)? {
+                removed.push(file.id());
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Garbage-collect a value log file that is not appended to anymore, returning whether it is
+    /// removed.
+    fn gc_value_log_file(&self, file: &ValueLogFile) -> Result<bool> {
+        let size = file.size()?;
+        let records = file.records()?;
+        // check the ratio without blocking the writes first
+        let state = self.state.read().clone();
+        let (watermark, latest_ts) = (self.mvcc().watermark(), self.mvcc().latest_commit_ts());
+        let mut dead_size = 0;
+        let mut candidates = Vec::new();
+        for record in records {
+            match Self::record_liveness(&state, &record, watermark, latest_ts)? {
+                RecordLiveness::Dead => dead_size += record.pointer.len as u64,
+                RecordLiveness::Latest(_) => candidates.push(record),
+                RecordLiveness::Pinned => return Ok(false),
+            }
+        }
+        if (dead_size as f64) < self.options.value_log_gc_ratio * size as f64 {
+            return Ok(false);
+        }
+
+        let memtable = if candidates.is_empty() {
+            None
+        } else {
+            // no write may supersede the records while they are moved
+            let _write_lock = self.mvcc().write_lock.lock();
+            let state = self.state.read().clone();
+            let (watermark, latest_ts) = (self.mvcc().watermark(), self.mvcc().latest_commit_ts());
+            let mut live = Vec::new();
+            for record in candidates {
+                match Self::record_liveness(&state, &record, watermark, latest_ts)? {
+                    RecordLiveness::Dead => {}
+                    RecordLiveness::Latest(expire_at) => live.push((record, expire_at)),
+                    RecordLiveness::Pinned => return Ok(false),
+                }
+            }
+            let mut values = Vec::with_capacity(live.len());
+            for (record, expire_at) in &live {
+                let pointer = self.append_value(&record.key, record.ts, &record.value)?;
+                values.push(ttl::encode_pointer(&pointer.encode(), *expire_at));
+            }
+            let data = live
+                .iter()
+                .zip(values.iter())
+                .map(|((record, _), value)| {
+                    (
+                        KeySlice::from_slice(&record.key, record.ts),
+                        value.as_slice(),
+                    )
+                })
+                .collect::<Vec<_>>();
+            // the versions keep their timestamps, and shadow the old ones as the memtable is newer
+            let memtable = self.state.read().memtable.clone();
+            memtable.put_batch(&data)?;
+            Some(memtable)
+        };
+
+        // persist the new pointers before the values they replace are gone
+        if let Some(memtable) = memtable {
+            self.sync_value_log()?;
+            if self.options.enable_wal {
+                memtable.sync_wal()?;
+            } else {
+                if !self.state.read().memtable.is_empty() {
+                    self.force_freeze_memtable(&self.state_lock.lock())?;
+                }
+                while !self.state.read().imm_memtables.is_empty() {
+                    self.force_flush_next_imm_memtable()?;
+                }
+            }
+        }
+        self.value_logs.remove(file.id());
+        std::fs::remove_file(self.path_of_vlog(file.id())).map_err(Error::Io)?;
+        self.sync_dir()?;
+        Ok(true)
+    }
+
+    /// Run the garbage collection of the value log periodically if values are separated.
+    pub(crate) fn spawn_value_log_gc_thread(
+        self: &Arc<Self>,
+        rx: crossbeam_channel::Receiver<()>,
+    ) -> Result<Option<std::thread::JoinHandle<()>>> {
+        if self.options.value_threshold.is_none() {
+            return Ok(None);
+        }
+        let this = self.clone();
+        let handle = std::thread::spawn(move || {
+            let ticker = crossbeam_channel::tick(Duration::from_secs(1));
+            loop {
+                crossbeam_channel::select! {
+                    recv(ticker) -> _ => if let Err(e) = this.gc_value_log() {
+                        eprintln!("value log gc failed: {}", e);
+                    },
+                    recv(rx) -> _ => return
+                }
+            }
+        });
+        Ok(Some(handle))
+    }
+}
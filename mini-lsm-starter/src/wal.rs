@@ -1,4 +1,3 @@
-// REMOVE THIS LINE after fully implementing this functionality
 // Copyright (c) 2022-2025 Alex Chi Z
 //
 // Licensed under the Apache License, Version 2.0 (the "License");
@@ -12,45 +11,349 @@
 // WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 // See the License for the specific language governing permissions and
 // limitations under the License.
-#![allow(unused_variables)] // TODO(you): remove this lint after implementing this mod
-#![allow(dead_code)] // TODO(you): remove this lint after implementing this mod
-
-use anyhow::Result;
-use bytes::{Buf, Bytes};
-use crossbeam_skiplist::SkipMap;
-use parking_lot::Mutex;
-use std::fs::File;
-use std::io::BufWriter;
-use std::path::Path;
+
+use anyhow::{Context, Result, bail};
+use bytes::{Buf, BufMut, Bytes};
+use parking_lot::{Condvar, Mutex};
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::block;
+use crate::error::Error;
+use crate::failpoint;
+use crate::key::{KeyBytes, KeySlice};
+use crate::mem_table::SkipList;
+use crate::range_tombstone::RangeTombstone;
+
+/// Takes the place of the key length of an entry to mark a range tombstone, which keys cannot be
+/// as long as.
+const RANGE_TOMBSTONE_MARKER: u16 = u16::MAX;
+/// Takes the place of the key length of an entry to mark a batch written to a column family.
+const COLUMN_FAMILY_MARKER: u16 = u16::MAX - 1;
+
+/// The entries of the column families replayed from a WAL, as `(column family id, key, value)`.
+pub type ColumnFamilyEntries = Vec<(usize, KeyBytes, Bytes)>;
 
-use crate::key::KeySlice;
+/// The longest key a WAL record can hold, as the longer key lengths are taken by the markers.
+pub const MAX_KEY_LEN: usize = COLUMN_FAMILY_MARKER as usize - 1;
+
+/// The longest value a WAL record can hold, which is also the longest value an SST block can hold.
+pub const MAX_VALUE_LEN: usize = block::MAX_VALUE_LEN;
+
+/// The entries of a batch written to a column family, with the id of the column family.
+pub type ColumnFamilyBatch<'a> = (usize, Vec<(KeySlice<'a>, &'a [u8])>);
+
+/// When a write to the WAL is synced to the disk. Only used with `enable_wal`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SyncPolicy {
+    /// Sync the WAL before each write returns, with an `fsync` of its own.
+    PerWrite,
+    /// Sync the WAL before each write returns, sharing one `fsync` among the concurrent writes, see
+    /// `Wal::sync_group`.
+    Group,
+    /// Sync the WAL in the background at this interval, and when the memtable is frozen. A crash
+    /// may lose the writes of the last interval.
+    Interval(Duration),
+    /// Only sync the WAL on `sync` and `close`. A crash may lose any write since then.
+    #[default]
+    Never,
+}
 
+/// A write-ahead log of a memtable. Every write is appended as one record:
+///
+/// `| body_len (u32) | key_len (u16) | key | ts (u64) | value_len (u16) | value | ... | checksum (u32) |`
+///
+/// where the body holds all key-value pairs of a batch and the checksum covers the body. A record
+/// is replayed as a whole on recovery, so a batch is either fully recovered or not at all. A range
+/// delete is a record of `RANGE_TOMBSTONE_MARKER (u16)` followed by the encoded `RangeTombstone`.
+/// The pairs following `COLUMN_FAMILY_MARKER (u16)` and the id of a column family (u64) are
+/// written to the column family, e.g., a batch written to a column family is a record starting
+/// with the marker.
 pub struct Wal {
+    path: PathBuf,
     file: Arc<Mutex<BufWriter<File>>>,
+    /// A handle to the same file to sync it without blocking the appends.
+    sync_file: File,
+    /// Number of bytes appended, updated while holding the lock of `file`.
+    appended: AtomicU64,
+    sync_state: Mutex<SyncState>,
+    /// Notifies the writers waiting in `sync_group` that a sync is done.
+    sync_done: Condvar,
+}
+
+#[derive(Default)]
+struct SyncState {
+    /// Number of bytes known to be on the disk.
+    synced: u64,
+    /// Whether a writer is syncing for the group.
+    syncing: bool,
 }
 
 impl Wal {
     pub fn create(_path: impl AsRef<Path>) -> Result<Self> {
+        let path = _path.as_ref();
+        Self::new(path, File::create_new(path)?, 0)
+    }
+
+    fn new(path: &Path, file: File, len: u64) -> Result<Self> {
         Ok(Wal {
-            file: Arc::new(Mutex::new(BufWriter::new(File::create_new(_path)?))),
+            path: path.to_path_buf(),
+            sync_file: file.try_clone()?,
+            file: Arc::new(Mutex::new(BufWriter::new(file))),
+            appended: AtomicU64::new(len),
+            sync_state: Mutex::new(SyncState {
+                synced: len,
+                syncing: false,
+            }),
+            sync_done: Condvar::new(),
         })
     }
 
-    pub fn recover(_path: impl AsRef<Path>, _skiplist: &SkipMap<Bytes, Bytes>) -> Result<Self> {
-        unimplemented!()
+    /// Replay the WAL at `path` into `skiplist` and `range_tombstones`, and the batches written to
+    /// column families into `column_families`, and reopen it for appending. A record cut short at
+    /// the end of the file, i.e., a write interrupted by a crash, is dropped with all of its
+    /// entries; a record whose checksum does not match is reported as corruption.
+    pub(crate) fn recover(
+        _path: impl AsRef<Path>,
+        _skiplist: &SkipList,
+        range_tombstones: &mut Vec<RangeTombstone>,
+        column_families: &mut ColumnFamilyEntries,
+    ) -> Result<Self> {
+        let path = _path.as_ref();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .open(path)
+            .context("failed to recover from WAL")?;
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf)?;
+        let mut rbuf = &buf[..];
+        let mut valid_len = 0;
+        while rbuf.remaining() >= 4 {
+            let body_len = (&rbuf[..]).get_u32() as usize;
+            if rbuf.remaining() < 4 + body_len + 4 {
+                break;
+            }
+            rbuf.advance(4);
+            let mut body = &rbuf[..body_len];
+            rbuf.advance(body_len);
+            if rbuf.get_u32() != crc32fast::hash(body) {
+                bail!(Error::corruption(format!(
+                    "WAL record at offset {} checksum mismatched",
+                    valid_len
+                )));
+            }
+            if body.len() >= 2 && (&body[..]).get_u16() == RANGE_TOMBSTONE_MARKER {
+                body.advance(2);
+                range_tombstones.push(RangeTombstone::decode(&mut body)?);
+                valid_len += 4 + body_len + 4;
+                continue;
+            }
+            // the entries go to the storage until a marker switches them to a column family
+            let mut column_family = None;
+            let mut entries = Vec::new();
+            while body.has_remaining() {
+                if body.len() >= 2 && (&body[..]).get_u16() == COLUMN_FAMILY_MARKER {
+                    body.advance(2);
+                    column_family = Some(body.try_get_u64()? as usize);
+                    continue;
+                }
+                let key = Self::get_slice(&mut body)?;
+                let ts = body.try_get_u64()?;
+                let value = Self::get_slice(&mut body)?;
+                entries.push((
+                    column_family,
+                    KeySlice::from_slice(key, ts).to_key_vec().into_key_bytes(),
+                    Bytes::copy_from_slice(value),
+                ));
+            }
+            for (column_family, key, value) in entries {
+                match column_family {
+                    Some(id) => column_families.push((id, key, value)),
+                    None => _skiplist.insert(key.as_key_slice(), &value),
+                }
+            }
+            valid_len += 4 + body_len + 4;
+        }
+        // drop the torn record so that new records are appended right after the last complete one
+        file.set_len(valid_len as u64)?;
+        Self::new(path, file, valid_len as u64)
+    }
+
+    /// Check that a key-value pair fits in a WAL record. The lengths are stored as `u16`, and a
+    /// key as long as a marker would be replayed as the record the marker stands for.
+    pub fn check_entry_len(key: &[u8], value: &[u8]) -> Result<()> {
+        if key.len() > MAX_KEY_LEN {
+            bail!(
+                "key of {} bytes is longer than {} bytes",
+                key.len(),
+                MAX_KEY_LEN
+            );
+        }
+        if value.len() > MAX_VALUE_LEN {
+            bail!(
+                "value of {} bytes is longer than {} bytes",
+                value.len(),
+                MAX_VALUE_LEN
+            );
+        }
+        Ok(())
+    }
+
+    /// Read a length-prefixed slice of a record body.
+    fn get_slice<'a>(body: &mut &'a [u8]) -> Result<&'a [u8]> {
+        let len = body.try_get_u16()? as usize;
+        if body.remaining() < len {
+            bail!(Error::corruption("WAL record truncated"));
+        }
+        let (slice, rest) = body.split_at(len);
+        *body = rest;
+        Ok(slice)
     }
 
-    pub fn put(&self, _key: &[u8], _value: &[u8]) -> Result<()> {
-        unimplemented!()
+    pub fn put(&self, _key: KeySlice, _value: &[u8]) -> Result<()> {
+        self.put_batch(&[(_key, _value)])
     }
 
-    /// Implement this in week 3, day 5; if you want to implement this earlier, use `&[u8]` as the key type.
+    /// Append all pairs of `_data` as a single record.
     pub fn put_batch(&self, _data: &[(KeySlice, &[u8])]) -> Result<()> {
-        unimplemented!()
+        self.append(&Self::encode_batch(_data, &[]))
+    }
+
+    /// Append all pairs of `data` written to the column family of `column_family_id` as a single
+    /// record.
+    pub fn put_column_family_batch(
+        &self,
+        column_family_id: usize,
+        data: &[(KeySlice, &[u8])],
+    ) -> Result<()> {
+        self.append(&Self::encode_batch(
+            &[],
+            &[(column_family_id, data.to_vec())],
+        ))
+    }
+
+    /// Append the pairs of `data` and the batches written to `column_families` as a single record,
+    /// so that they are recovered together.
+    pub fn put_batch_with_column_families(
+        &self,
+        data: &[(KeySlice, &[u8])],
+        column_families: &[ColumnFamilyBatch],
+    ) -> Result<()> {
+        self.append(&Self::encode_batch(data, column_families))
+    }
+
+    /// Encode the pairs of `data`, followed by the marker of each column family in
+    /// `column_families` and the pairs written to it, as a record.
+    fn encode_batch(data: &[(KeySlice, &[u8])], column_families: &[ColumnFamilyBatch]) -> Vec<u8> {
+        let data_len = |data: &[(KeySlice, &[u8])]| {
+            data.iter()
+                .map(|(key, value)| 4 + key.raw_len() + value.len())
+                .sum::<usize>()
+        };
+        let body_len = data_len(data)
+            + column_families
+                .iter()
+                .map(|(_, data)| 10 + data_len(data))
+                .sum::<usize>();
+        let mut buf = Vec::with_capacity(4 + body_len + 4);
+        buf.put_u32(body_len as u32);
+        let put_data = |buf: &mut Vec<u8>, data: &[(KeySlice, &[u8])]| {
+            for (key, value) in data {
+                buf.put_u16(key.key_len() as u16);
+                buf.put_slice(key.key_ref());
+                buf.put_u64(key.ts());
+                buf.put_u16(value.len() as u16);
+                buf.put_slice(value);
+            }
+        };
+        put_data(&mut buf, data);
+        for (id, data) in column_families {
+            buf.put_u16(COLUMN_FAMILY_MARKER);
+            buf.put_u64(*id as u64);
+            put_data(&mut buf, data);
+        }
+        buf.put_u32(crc32fast::hash(&buf[4..]));
+        buf
+    }
+
+    /// Append a range tombstone as a record of its own.
+    pub fn put_range_tombstone(&self, tombstone: &RangeTombstone) -> Result<()> {
+        let body_len = 2 + tombstone.raw_len();
+        let mut buf = Vec::with_capacity(4 + body_len + 4);
+        buf.put_u32(body_len as u32);
+        buf.put_u16(RANGE_TOMBSTONE_MARKER);
+        tombstone.encode(&mut buf);
+        buf.put_u32(crc32fast::hash(&buf[4..]));
+        self.append(&buf)
+    }
+
+    fn append(&self, record: &[u8]) -> Result<()> {
+        failpoint::eval(failpoint::WAL_APPEND, &self.path)?;
+        let mut file = self.file.lock();
+        file.write_all(record)?;
+        self.appended
+            .fetch_add(record.len() as u64, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Number of bytes appended to the WAL, including the ones not synced yet.
+    pub fn size(&self) -> u64 {
+        self.appended.load(Ordering::SeqCst)
     }
 
     pub fn sync(&self) -> Result<()> {
-        unimplemented!()
+        let len = {
+            let mut file = self.file.lock();
+            file.flush()?;
+            file.get_mut().sync_all()?;
+            self.appended.load(Ordering::SeqCst)
+        };
+        let mut state = self.sync_state.lock();
+        state.synced = state.synced.max(len);
+        Ok(())
+    }
+
+    /// Make the records appended so far durable, sharing the `fsync` with concurrent callers: the
+    /// first caller becomes the leader and syncs all records appended by the time it starts, while
+    /// the others wait for it and return if their records are covered, or take over as the next
+    /// leader otherwise. The appends go on during the `fsync` and are synced by the next leader.
+    pub fn sync_group(&self) -> Result<()> {
+        let target = self.appended.load(Ordering::SeqCst);
+        let mut state = self.sync_state.lock();
+        loop {
+            if state.synced >= target {
+                return Ok(());
+            }
+            if !state.syncing {
+                break;
+            }
+            self.sync_done.wait(&mut state);
+        }
+        state.syncing = true;
+        drop(state);
+
+        let result = (|| {
+            let len = {
+                let mut file = self.file.lock();
+                file.flush()?;
+                self.appended.load(Ordering::SeqCst)
+            };
+            self.sync_file.sync_data()?;
+            Ok(len)
+        })();
+
+        let mut state = self.sync_state.lock();
+        state.syncing = false;
+        if let Ok(len) = result {
+            state.synced = state.synced.max(len);
+        }
+        // on failure, a waiting writer retries as the leader
+        self.sync_done.notify_all();
+        result.map(|_| ())
     }
 }